@@ -1,12 +1,16 @@
 use crate::native::jvmti_native::jthread;
 
 use super::capabilities::Capabilities;
+use super::context::static_context;
 use super::environment::jvm::{JVMAgent, JVMF};
 use super::environment::jvmti::JVMTI;
 use super::error::*;
 use super::event::*;
 use super::native::JavaVMPtr;
+use super::runtime::ClassFileLoadEvent;
 use super::version::VersionNumber;
+use std::io;
+use std::path::Path;
 
 pub struct Agent {
     pub jvm: Box<dyn JVMF>,
@@ -55,8 +59,13 @@ impl Agent {
         self.environment.get_version_number()
     }
 
+    /// Resume the threads the agent has left suspended and end the jitdump
     pub fn shutdown(&self) {
-        // TODO implement this method
+        static_context().suspended.resume_all(&*self.environment);
+
+        if let Err(err) = static_context().perf.close() {
+            println!("Couldn't close the jitdump: {}", err);
+        }
     }
 
     pub fn destroy(&self) -> Result<(), NativeError> {
@@ -155,6 +164,42 @@ impl Agent {
                             VMEvent::ClassFileLoadHook,
                             self.callbacks.class_file_load_hook.is_some(),
                         );
+                        self.environment.set_event_notification_mode(
+                            VMEvent::DataDumpRequest,
+                            self.callbacks.data_dump_request.is_some(),
+                        );
+                        self.environment.set_event_notification_mode(
+                            VMEvent::ClassLoad,
+                            self.callbacks.class_load.is_some(),
+                        );
+                        self.environment.set_event_notification_mode(
+                            VMEvent::ClassPrepare,
+                            self.callbacks.class_prepare.is_some(),
+                        );
+                        // The perf output is written from the code events without any callbacks
+                        self.environment.set_event_notification_mode(
+                            VMEvent::CompiledMethodLoad,
+                            self.callbacks.compiled_method_load.is_some() || static_context().perf.is_enabled(),
+                        );
+                        self.environment.set_event_notification_mode(
+                            VMEvent::CompiledMethodUnload,
+                            self.callbacks.compiled_method_unload.is_some(),
+                        );
+                        self.environment.set_event_notification_mode(
+                            VMEvent::DynamicCodeGenerated,
+                            self.callbacks.dynamic_code_generated.is_some() || static_context().perf.is_enabled(),
+                        );
+                        self.environment.set_event_notification_mode(
+                            VMEvent::Breakpoint,
+                            self.callbacks.breakpoint.is_some(),
+                        );
+                        // Single step events are enabled per thread while it's stepping, and
+                        // frame pops are only sent for the frames requested through
+                        // `notify_frame_pop`, eg. by steps and `debug::on_frame_return`
+                        self.environment.set_event_notification_mode(
+                            VMEvent::FramePop,
+                            self.capabilities.can_generate_frame_pop_events,
+                        );
                     }
                     Some(error) => {
                         println!("Couldn't register callbacks: {}", translate_error(&error))
@@ -208,13 +253,23 @@ impl Agent {
     pub fn on_exception(&mut self, handler: Option<FnException>) {
         self.callbacks.exception = handler;
         self.capabilities.can_generate_exception_events =
-            handler.or(self.callbacks.exception_catch).is_some();
+            handler.is_some() || self.callbacks.exception_catch.is_some();
+
+        if handler.is_some() {
+            self.capabilities.can_get_line_numbers = true;
+            self.capabilities.can_get_source_file_name = true;
+        }
     }
 
     pub fn on_exception_catch(&mut self, handler: Option<FnExceptionCatch>) {
         self.callbacks.exception_catch = handler;
         self.capabilities.can_generate_exception_events =
-            handler.or(self.callbacks.exception).is_some();
+            handler.is_some() || self.callbacks.exception.is_some();
+
+        if handler.is_some() {
+            self.capabilities.can_get_line_numbers = true;
+            self.capabilities.can_get_source_file_name = true;
+        }
     }
 
     pub fn on_monitor_wait(&mut self, handler: Option<FnMonitorWait>) {
@@ -268,11 +323,19 @@ impl Agent {
     pub fn on_field_access(&mut self, handler: Option<FnFieldAccess>) {
         self.callbacks.field_access = handler;
         self.capabilities.can_generate_field_access_events = handler.is_some();
+
+        if handler.is_some() {
+            self.capabilities.can_get_line_numbers = true;
+        }
     }
 
     pub fn on_field_modification(&mut self, handler: Option<FnFieldModification>) {
         self.callbacks.field_modification = handler;
         self.capabilities.can_generate_field_modification_events = handler.is_some();
+
+        if handler.is_some() {
+            self.capabilities.can_get_line_numbers = true;
+        }
     }
 
     pub fn on_garbage_collection_start(&mut self, handler: Option<FnGarbageCollectionStart>) {
@@ -292,4 +355,112 @@ impl Agent {
     pub fn on_class_file_load(&mut self, handler: Option<FnClassFileLoad>) {
         self.callbacks.class_file_load_hook = handler;
     }
+
+    /// Handle classes once they are loaded, before their methods and fields are available
+    pub fn on_class_load(&mut self, handler: Option<FnClassLoad>) {
+        self.callbacks.class_load = handler;
+    }
+
+    /// Handle classes once they are prepared, eg. to set breakpoints and field watches on them
+    pub fn on_class_prepare(&mut self, handler: Option<FnClassPrepare>) {
+        self.callbacks.class_prepare = handler;
+    }
+
+    /// Handle the breakpoints set through `static_context().breakpoints`
+    pub fn on_breakpoint(&mut self, handler: Option<FnBreakpoint>) {
+        self.callbacks.breakpoint = handler;
+        self.capabilities.can_generate_breakpoint_events = handler.is_some();
+
+        if handler.is_some() {
+            self.capabilities.can_get_line_numbers = true;
+        }
+    }
+
+    /// Handle the steps started with `debug::step`, once they complete
+    pub fn on_single_step(&mut self, handler: Option<FnSingleStep>) {
+        self.callbacks.single_step = handler;
+        self.capabilities.can_generate_single_step_events = handler.is_some();
+        self.capabilities.can_generate_frame_pop_events = handler.is_some() || self.callbacks.frame_pop.is_some();
+
+        if handler.is_some() {
+            self.capabilities.can_get_line_numbers = true;
+        }
+    }
+
+    /// Handle the returns of the frames requested through `notify_frame_pop`, except those
+    /// requested by steps and `debug::on_frame_return`
+    pub fn on_frame_pop(&mut self, handler: Option<FnFramePop>) {
+        self.callbacks.frame_pop = handler;
+        self.capabilities.can_generate_frame_pop_events = handler.is_some() || self.callbacks.single_step.is_some();
+    }
+
+    /// Handle methods compiled to native code
+    pub fn on_compiled_method_load(&mut self, handler: Option<FnCompiledMethodLoad>) {
+        self.callbacks.compiled_method_load = handler;
+        self.capabilities.can_generate_compiled_method_load_events = handler.is_some()
+            || self.callbacks.compiled_method_unload.is_some()
+            || static_context().perf.is_enabled();
+
+        if handler.is_some() {
+            self.capabilities.can_get_line_numbers = true;
+            self.capabilities.can_get_source_file_name = true;
+        }
+    }
+
+    /// Handle compiled code being freed
+    pub fn on_compiled_method_unload(&mut self, handler: Option<FnCompiledMethodUnload>) {
+        self.callbacks.compiled_method_unload = handler;
+        self.capabilities.can_generate_compiled_method_load_events = handler.is_some()
+            || self.callbacks.compiled_method_load.is_some()
+            || static_context().perf.is_enabled();
+    }
+
+    /// Handle native code generated by the VM itself, eg. the interpreter
+    pub fn on_dynamic_code_generated(&mut self, handler: Option<FnDynamicCodeGenerated>) {
+        self.callbacks.dynamic_code_generated = handler;
+    }
+
+    /// Write the compiled methods and the code generated by the VM to `/tmp/perf-<pid>.map`, so
+    /// that perf can symbolize them. See `PerfMap::new` for `unfold_inlined`.
+    pub fn use_perf_map(&mut self, unfold_inlined: bool) -> io::Result<()> {
+        static_context().perf.write_perf_map(unfold_inlined)?;
+        self.capabilities.can_generate_compiled_method_load_events = true;
+        Ok(())
+    }
+
+    /// Write the compiled methods and the code generated by the VM to `jit-<pid>.dump` in the
+    /// directory, with their source lines, for `perf inject --jit`
+    pub fn use_jitdump(&mut self, directory: &Path) -> io::Result<()> {
+        static_context().perf.write_jitdump(directory)?;
+        self.capabilities.can_generate_compiled_method_load_events = true;
+        self.capabilities.can_get_line_numbers = true;
+        self.capabilities.can_get_source_file_name = true;
+        Ok(())
+    }
+
+    ///
+    /// Send the code events for the methods compiled and the code generated before they were
+    /// enabled, eg. when the agent is attached to a running VM. Only available in the live phase,
+    /// after `update`.
+    pub fn generate_events(&self) -> Result<(), NativeError> {
+        if self.capabilities.can_generate_compiled_method_load_events {
+            self.environment.generate_events(VMEvent::CompiledMethodLoad)?;
+        }
+
+        self.environment.generate_events(VMEvent::DynamicCodeGenerated)
+    }
+
+    /// Handle requests to dump the VM's state, eg. with a `ThreadDump`
+    pub fn on_data_dump_request(&mut self, handler: Option<FnDataDumpRequest>) {
+        self.callbacks.data_dump_request = handler;
+    }
+
+    /// Transform loaded classes with the transformers registered in the static agent context
+    pub fn use_transformer_chain(&mut self) {
+        self.on_class_file_load(Some(transform_class_file));
+    }
+}
+
+fn transform_class_file(event: ClassFileLoadEvent) -> Option<Vec<u8>> {
+    static_context().transform_class(&event)
 }
@@ -5,7 +5,7 @@ use std::fmt::Debug;
 /// itself, it doesn't represent every byte in the class definition, though, many information are
 /// encoded in the type system instead. This approach may seem restrictive but it helps achieving
 /// bytecode safety.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Classfile {
     pub version: ClassfileVersion,
     pub constant_pool: ConstantPool,
@@ -43,7 +43,7 @@ impl Default for Classfile {
 
 ///
 /// Describe a classfile version number.
-#[derive(Ord, PartialOrd, Eq, PartialEq, Debug)]
+#[derive(Ord, PartialOrd, Eq, PartialEq, Debug, Clone)]
 pub struct ClassfileVersion {
     pub minor_version: u16,
     pub major_version: u16,
@@ -73,7 +73,7 @@ impl Default for ClassfileVersion {
 ///
 /// A `ConstantPool` is a table of various string and number literal constants that are referred
 /// within the substructures of the `Classfile`.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct ConstantPool {
     pub constants: Vec<Constant>,
 }
@@ -162,7 +162,7 @@ impl Default for ConstantPool {
     }
 }
 
-#[derive(Default, Debug, PartialEq, Clone)]
+#[derive(Default, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct ConstantPoolIndex {
     pub idx: usize,
 }
@@ -173,7 +173,7 @@ impl ConstantPoolIndex {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub enum Constant {
     Utf8(Vec<u8>),
     Integer(u32),
@@ -289,7 +289,7 @@ impl Constant {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum ReferenceKind {
     GetField = 1,
     GetStatic = 2,
@@ -419,7 +419,7 @@ pub enum ParameterAccessFlags {
     Mandated = 0x8000,
 }
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Clone)]
 pub struct Field {
     pub access_flags: AccessFlags,
     pub name_index: ConstantPoolIndex,
@@ -1088,7 +1088,7 @@ impl Instruction {
             &Instruction::LDC_W(_) => 3,
             &Instruction::LDC2_W(_) => 3,
             &Instruction::LLOAD(_) => 2,
-            &Instruction::LOOKUPSWITCH(_, ref pairs) => 5 + pairs.len() * 4,
+            Instruction::LOOKUPSWITCH(_, pairs) => 9 + pairs.len() * 8,
             &Instruction::LSTORE(_) => 2,
             &Instruction::MULTIANEWARRAY(_, _) => 4,
             &Instruction::NEW(_) => 3,
@@ -1098,20 +1098,32 @@ impl Instruction {
             &Instruction::RET(_) => 2,
             &Instruction::SIPUSH(_) => 3,
             &Instruction::TABLESWITCH(_, _, _, ref indices) => 13 + (indices.len() * 4),
-            &Instruction::IINC_W(_, _) => 9,
-            &Instruction::ILOAD_W(_) => 5,
-            &Instruction::FLOAD_W(_) => 5,
-            &Instruction::ALOAD_W(_) => 5,
-            &Instruction::LLOAD_W(_) => 5,
-            &Instruction::DLOAD_W(_) => 5,
-            &Instruction::ISTORE_W(_) => 5,
-            &Instruction::FSTORE_W(_) => 5,
-            &Instruction::ASTORE_W(_) => 5,
-            &Instruction::LSTORE_W(_) => 5,
-            &Instruction::DSTORE_W(_) => 5,
-            &Instruction::RET_W(_) => 5,
+            &Instruction::IINC_W(_, _) => 6,
+            &Instruction::ILOAD_W(_) => 4,
+            &Instruction::FLOAD_W(_) => 4,
+            &Instruction::ALOAD_W(_) => 4,
+            &Instruction::LLOAD_W(_) => 4,
+            &Instruction::DLOAD_W(_) => 4,
+            &Instruction::ISTORE_W(_) => 4,
+            &Instruction::FSTORE_W(_) => 4,
+            &Instruction::ASTORE_W(_) => 4,
+            &Instruction::LSTORE_W(_) => 4,
+            &Instruction::DSTORE_W(_) => 4,
+            &Instruction::RET_W(_) => 4,
             &Instruction::PADDED_INSTRUCTION(padding) => padding,
             _ => 1,
         }
     }
+
+    /// Returns the encoded length of this instruction when it starts at the given code offset.
+    /// This only differs from `len()` for the switch instructions which are padded to a 4-byte
+    /// boundary.
+    pub fn size_at(&self, offset: usize) -> usize {
+        match *self {
+            Instruction::LOOKUPSWITCH(_, _) | Instruction::TABLESWITCH(_, _, _, _) => {
+                self.len() + (4 - ((offset + 1) % 4)) % 4
+            }
+            _ => self.len(),
+        }
+    }
 }
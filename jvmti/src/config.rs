@@ -1,14 +1,186 @@
 extern crate toml;
 
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{ Read };
 use std::path::Path;
+use std::str::FromStr;
+use super::instrumentation::counters::CounterMode;
+use super::instrumentation::pointcut::Pointcut;
+use super::instrumentation::timing::ProbeError;
+use super::instrumentation::transaction;
 
 #[derive(Deserialize)]
 pub struct Config {
     pub agent_name: String,
+    /// Names of the pointcuts whose method entries are recorded and whose classes are transformed
+    /// by the sample agent
     pub entry_points: Vec<String>,
-    pub active_classes: Vec<String>
+    pub active_classes: Vec<String>,
+    /// Named pointcut expressions, eg. `services = "execution(com.acme.*Service.*) && access(public)"`
+    #[serde(default)]
+    pub pointcuts: BTreeMap<String, Pointcut>,
+    /// Names of the pointcuts whose methods are timed by woven probes
+    #[serde(default)]
+    pub timed: Vec<String>,
+    /// Names of the pointcuts whose methods report their allocation sites
+    #[serde(default)]
+    pub allocations: Vec<String>,
+    /// Names of the pointcuts whose tasks passed to executors and `CompletableFuture` stages
+    /// carry the trace context over to the threads that run them
+    #[serde(default)]
+    pub propagated: Vec<String>,
+    /// Names of the built-in pointcut packs recorded as transactions and database calls, eg.
+    /// `[ "servlet", "jdbc" ]`
+    #[serde(default)]
+    pub packs: Vec<String>,
+    /// Classes measured by the coverage probes and where the reports are written
+    #[serde(default)]
+    pub coverage: Option<CoverageConfig>,
+    /// Methods whose arguments and results are captured, and how the values are recorded
+    #[serde(default)]
+    pub capture: Option<CaptureConfig>,
+    /// Methods counted by increments woven into their own bytecode, and how often the counts
+    /// are read
+    #[serde(default)]
+    pub counters: Option<CountersConfig>,
+    /// Methods whose bodies are replaced by stubs, eg. to inject faults
+    #[serde(default)]
+    pub stubs: Vec<StubConfig>
+}
+
+///
+/// The `[coverage]` section, eg.
+///
+/// ```text
+/// [coverage]
+/// pointcuts = [ "services" ]
+/// lcov = "target/coverage.info"
+/// cobertura = "target/coverage.xml"
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CoverageConfig {
+    /// Names of the pointcuts whose methods are measured
+    pub pointcuts: Vec<String>,
+    /// Path of the LCOV tracefile written when the VM exits
+    #[serde(default)]
+    pub lcov: Option<String>,
+    /// Path of the Cobertura XML report written when the VM exits
+    #[serde(default)]
+    pub cobertura: Option<String>
+}
+
+///
+/// The `[capture]` section, eg.
+///
+/// ```text
+/// [capture]
+/// pointcuts = [ "services" ]
+/// max_length = 120
+/// redact = [ "password", "token" ]
+///
+/// [capture.extractors]
+/// "com.acme.User" = "login"
+/// "com.acme.Order" = "id:J"
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CaptureConfig {
+    /// Names of the pointcuts whose methods are captured
+    pub pointcuts: Vec<String>,
+    /// Maximum number of characters recorded for the string representation of an object
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+    /// Maximum number of captured calls kept, older ones are dropped first
+    #[serde(default = "default_max_calls")]
+    pub max_calls: usize,
+    /// Names of the parameters whose values are never read, compared ignoring case
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// Objects of the listed classes are recorded by the value of a field instead of `toString`.
+    /// The field is given by its name, optionally followed by a colon and its descriptor, which
+    /// defaults to `Ljava/lang/String;`.
+    #[serde(default)]
+    pub extractors: BTreeMap<String, String>
+}
+
+///
+/// The `[counters]` section, eg.
+///
+/// ```text
+/// [counters]
+/// pointcuts = [ "hot" ]
+/// mode = "call_site"
+/// interval = 500
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CountersConfig {
+    /// Names of the pointcuts whose methods are counted
+    pub pointcuts: Vec<String>,
+    /// Whether each method gets a single counter, or each call it makes gets one
+    #[serde(default)]
+    pub mode: CounterMode,
+    /// Milliseconds between two reads of the counters
+    #[serde(default = "default_interval")]
+    pub interval: u64
+}
+
+///
+/// An entry of the `[[stubs]]` list, eg.
+///
+/// ```text
+/// [[stubs]]
+/// pointcut = "payments"
+/// action = "throw"
+/// exception = "java.io.IOException"
+/// message = "injected failure"
+///
+/// [[stubs]]
+/// pointcut = "prices"
+/// action = "return"
+/// value = "42"
+///
+/// [[stubs]]
+/// pointcut = "inventory"
+/// action = "delay"
+/// millis = 250
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct StubConfig {
+    /// Name of the pointcut whose methods are stubbed
+    pub pointcut: String,
+    pub action: StubAction,
+    /// The returned constant, parsed according to the return type of the method
+    #[serde(default)]
+    pub value: Option<String>,
+    /// Class name of the thrown exception. It needs a constructor taking a `String` if there is
+    /// a message, otherwise one without parameters.
+    #[serde(default)]
+    pub exception: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Milliseconds to wait before the original body runs
+    #[serde(default)]
+    pub millis: Option<u64>
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StubAction {
+    Return,
+    Throw,
+    Delay
+}
+
+fn default_interval() -> u64 {
+    1000
+}
+
+fn default_max_length() -> usize {
+    256
+}
+
+fn default_max_calls() -> usize {
+    1000
 }
 
 impl Config {
@@ -25,13 +197,36 @@ impl Config {
                 let mut contents = String::new();
                 let _ = file.read_to_string(&mut contents);
 
-                let config: Config = toml::from_str(contents.as_str()).unwrap();
+                let config: Config = contents.parse().unwrap();
 
                 Some(config)
             },
             _ => None
         }
     }
+
+    /// The pointcut with the given name, or the built-in pack of that name if it isn't defined
+    pub fn pointcut(&self, name: &str) -> Option<&Pointcut> {
+        self.pointcuts
+            .get(name)
+            .or_else(|| transaction::pack(name).map(|pack| &pack.pointcut))
+    }
+
+    /// The pointcuts listed in `entry_points`, every name has to refer to a defined pointcut
+    pub fn entry_points(&self) -> Result<Vec<&Pointcut>, ProbeError> {
+        self.entry_points
+            .iter()
+            .map(|name| self.pointcut(name).ok_or_else(|| ProbeError::UnknownPointcut(name.clone())))
+            .collect()
+    }
+}
+
+impl FromStr for Config {
+    type Err = toml::de::Error;
+
+    fn from_str(contents: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(contents)
+    }
 }
 
 impl Default for Config {
@@ -40,7 +235,16 @@ impl Default for Config {
         Config {
             agent_name: String::from("default"),
             entry_points: vec![],
-            active_classes: vec![]
+            active_classes: vec![],
+            pointcuts: BTreeMap::new(),
+            timed: vec![],
+            allocations: vec![],
+            propagated: vec![],
+            packs: vec![],
+            coverage: None,
+            capture: None,
+            counters: None,
+            stubs: vec![]
         }
     }
 }
@@ -1,5 +1,18 @@
 use super::config::Config;
-use super::thread::ThreadId;
+use super::debug::{Breakpoints, FrameReturns};
+use super::exception::ExceptionFilter;
+use super::instrumentation::allocation::AllocationRegistry;
+use super::instrumentation::capture::CaptureRegistry;
+use super::instrumentation::chain::{ClassTransformer, TransformerChain};
+use super::instrumentation::counters::CounterRegistry;
+use super::instrumentation::coverage::CoverageRegistry;
+use super::instrumentation::dynamic::ProbeManager;
+use super::instrumentation::timing::ProbeRegistry;
+use super::instrumentation::trace::{Span, TraceContext, TraceRegistry};
+use super::instrumentation::transaction::TransactionRegistry;
+use super::perf::PerfOutput;
+use super::runtime::ClassFileLoadEvent;
+use super::thread::{SuspendedThreads, ThreadId};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use time::now;
@@ -26,13 +39,57 @@ pub fn static_context() -> &'static AgentContext {
 pub struct AgentContext {
     context: Arc<RwLock<Context>>,
     pub config: Arc<RwLock<Config>>,
+    pub transformers: Arc<RwLock<TransformerChain>>,
+    /// Sites of the woven timing probes and their measurements
+    pub probes: Arc<ProbeRegistry>,
+    /// Probes enabled and disabled at runtime. It has to be registered in the transformer chain
+    /// to take effect.
+    pub dynamic_probes: Arc<ProbeManager>,
+    /// Coverage probes and their hits
+    pub coverage: Arc<CoverageRegistry>,
+    /// Allocation sites and the number of objects they have allocated
+    pub allocations: Arc<AllocationRegistry>,
+    /// Captured arguments and results of the selected methods
+    pub capture: Arc<CaptureRegistry>,
+    /// Current trace context of each thread and the finished spans
+    pub traces: Arc<TraceRegistry>,
+    /// Requests and database calls selected by the pointcut packs
+    pub transactions: Arc<TransactionRegistry>,
+    /// Counter slots woven into the instrumented classes and their last read values
+    pub counters: Arc<CounterRegistry>,
+    /// Threads suspended by the agent, which are resumed when it shuts down or panics
+    pub suspended: Arc<SuspendedThreads>,
+    /// Breakpoints set by the agent
+    pub breakpoints: Arc<Breakpoints>,
+    /// Handlers waiting for frames to return
+    pub frame_returns: Arc<FrameReturns>,
+    /// The exceptions reported to the exception callbacks
+    pub exceptions: Arc<ExceptionFilter>,
+    /// The perf map and jitdump written for the generated code
+    pub perf: Arc<PerfOutput>,
 }
 
 impl AgentContext {
     pub fn new() -> AgentContext {
+        let traces = Arc::new(TraceRegistry::new());
+
         AgentContext {
             context: Arc::new(RwLock::new(Context::new())),
             config: Arc::new(RwLock::new(Config::default())),
+            transformers: Arc::new(RwLock::new(TransformerChain::new())),
+            probes: Arc::new(ProbeRegistry::new()),
+            dynamic_probes: Arc::new(ProbeManager::new()),
+            coverage: Arc::new(CoverageRegistry::new()),
+            allocations: Arc::new(AllocationRegistry::new()),
+            capture: Arc::new(CaptureRegistry::new()),
+            transactions: Arc::new(TransactionRegistry::new(traces.clone())),
+            traces,
+            counters: Arc::new(CounterRegistry::new()),
+            suspended: Arc::new(SuspendedThreads::new()),
+            breakpoints: Arc::new(Breakpoints::new()),
+            frame_returns: Arc::new(FrameReturns::new()),
+            exceptions: Arc::new(ExceptionFilter::new()),
+            perf: Arc::new(PerfOutput::new()),
         }
     }
 
@@ -45,6 +102,38 @@ impl AgentContext {
         }
     }
 
+    pub fn add_transformer<T: ClassTransformer + 'static>(&self, name: &str, priority: i32, transformer: T) {
+        match self.transformers.write() {
+            Ok(mut chain) => (*chain).add(name, priority, transformer),
+            Err(_) => log::warn!("Couldn't add transformer {}, the transformer chain is poisoned", name),
+        }
+    }
+
+    pub fn remove_transformer(&self, name: &str) -> bool {
+        match self.transformers.write() {
+            Ok(mut chain) => (*chain).remove(name),
+            Err(_) => false,
+        }
+    }
+
+    /// Run the registered transformers on a class that is being loaded
+    pub fn transform_class(&self, event: &ClassFileLoadEvent) -> Option<Vec<u8>> {
+        match self.transformers.read() {
+            Ok(chain) => (*chain).transform(&event.class_name, &event.class_data),
+            Err(_) => None,
+        }
+    }
+
+    /// Start a span on the calling thread, as a child of its current trace context
+    pub fn start_span(&self, name: &str) -> TraceContext {
+        self.traces.start_span(name)
+    }
+
+    /// End the innermost span started on the calling thread
+    pub fn end_span(&self) -> Option<Span> {
+        self.traces.end_span()
+    }
+
     pub fn thread_start(&self, thread_id: &ThreadId) {
         match self.context.write() {
             Ok(mut ctx) => {
@@ -111,40 +200,38 @@ impl AgentContext {
     }
 
     pub fn method_enter(&self, thread_id: &ThreadId) {
+        self.method_enter_at(thread_id, now());
+    }
+
+    /// Record a method entry that happened at the given time, eg. one measured by a woven probe
+    pub fn method_enter_at(&self, thread_id: &ThreadId, time: Tm) {
         match self.context.write() {
             Ok(mut ctx) => {
-                let now = now();
-
-                let new_stack = match (*ctx).method_times.remove(thread_id) {
-                    Some(mut thread_stack) => {
-                        thread_stack.push(now);
-                        thread_stack
-                    }
-                    None => {
-                        let new_vec = vec![now];
-                        new_vec
-                    }
-                };
-
-                (*ctx).method_times.insert((*thread_id).clone(), new_stack);
+                ctx
+                    .method_times
+                    .entry((*thread_id).clone())
+                    .or_insert_with(Vec::new)
+                    .push(time);
             }
             Err(_) => { /* TODO: Ignoring for now */ }
         }
     }
 
     pub fn method_exit(&self, thread_id: &ThreadId) -> Option<Duration> {
-        match self.context.write() {
-            Ok(mut ctx) => {
-                let now = now();
+        self.method_exit_at(thread_id, now())
+    }
 
-                match (*ctx).method_times.get_mut(thread_id) {
-                    Some(ref mut thread_stack) => match thread_stack.pop() {
-                        Some(time) => Some(time - now),
-                        None => None,
-                    },
+    /// Record a method exit that happened at the given time and return the time spent in the
+    /// method since the matching entry
+    pub fn method_exit_at(&self, thread_id: &ThreadId, time: Tm) -> Option<Duration> {
+        match self.context.write() {
+            Ok(mut ctx) => match ctx.method_times.get_mut(thread_id) {
+                Some(ref mut thread_stack) => match thread_stack.pop() {
+                    Some(start) => Some(time - start),
                     None => None,
-                }
-            }
+                },
+                None => None,
+            },
             Err(_) => {
                 None /* TODO Ignoring for now */
             }
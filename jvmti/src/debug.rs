@@ -0,0 +1,612 @@
+use super::context::static_context;
+use super::environment::jni::JNI;
+use super::environment::jvmti::JVMTI;
+use super::environment::Environment;
+use super::error::{translate_error, NativeError};
+use super::event::VMEvent;
+use super::instrumentation::dynamic::internal_name;
+use super::native::jvmti_native::{jclass, jfieldID, jint, jlocation, jmethodID, jthread};
+use super::runtime::{FramePopEvent, LocationEvent};
+use super::stack::{line_number, optional};
+use super::thread::{ThreadControlError, ThreadId};
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+
+///
+/// Describes why a breakpoint couldn't be set or cleared
+#[derive(Debug)]
+pub enum BreakpointError {
+    Jvmti(NativeError),
+    /// None of the loaded classes has the given name
+    ClassNotLoaded(String),
+    /// None of the selected methods has code on the line
+    NoCodeAtLine(u32),
+}
+
+impl fmt::Display for BreakpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BreakpointError::Jvmti(ref err) => write!(f, "{}", translate_error(err)),
+            BreakpointError::ClassNotLoaded(ref name) => write!(f, "Class {} is not loaded", name),
+            BreakpointError::NoCodeAtLine(line) => write!(f, "There is no code at line {}", line),
+        }
+    }
+}
+
+impl From<NativeError> for BreakpointError {
+    fn from(err: NativeError) -> Self {
+        BreakpointError::Jvmti(err)
+    }
+}
+
+///
+/// An instruction in a method
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodeLocation {
+    pub method: jmethodID,
+    pub location: jlocation,
+}
+
+/// Marker trait implementation for `Send`
+unsafe impl Send for CodeLocation {}
+
+/// Marker trait implementation for `Sync`
+unsafe impl Sync for CodeLocation {}
+
+///
+/// A line breakpoint and the instructions it was resolved to. A line may start in more than one
+/// method, eg. when a lambda body shares the line with the method declaring it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub line: Option<u32>,
+    pub locations: Vec<CodeLocation>,
+}
+
+///
+/// Breakpoints set by the agent. Breakpoints resolving to the same instruction share the JVMTI
+/// breakpoint, which is only cleared with the last of them.
+///
+pub struct Breakpoints {
+    entries: Mutex<(usize, Vec<Breakpoint>)>,
+}
+
+impl Default for Breakpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            entries: Mutex::new((0, vec![])),
+        }
+    }
+
+    ///
+    /// Set a breakpoint on the first instruction of the line in each method of the class, or
+    /// only in the methods with the given name
+    pub fn set(&self, jvmti: &dyn JVMTI, class: jclass, method_name: Option<&str>, line: u32) -> Result<Breakpoint, BreakpointError> {
+        let locations = resolve_line(jvmti, class, method_name, line)?;
+
+        if locations.is_empty() {
+            return Err(BreakpointError::NoCodeAtLine(line));
+        }
+
+        self.add(jvmti, Some(line), locations)
+    }
+
+    ///
+    /// Set a line breakpoint in a loaded class given by its binary or internal name, eg.
+    /// `com.acme.Orders`
+    pub fn set_by_name(&self, jvmti: &dyn JVMTI, class_name: &str, method_name: Option<&str>, line: u32) -> Result<Breakpoint, BreakpointError> {
+        match loaded_classes_named(jvmti, class_name)?.first() {
+            Some(class) => self.set(jvmti, *class, method_name, line),
+            None => Err(BreakpointError::ClassNotLoaded(class_name.to_string())),
+        }
+    }
+
+    ///
+    /// Set a breakpoint on a single instruction
+    pub fn set_at(&self, jvmti: &dyn JVMTI, method: jmethodID, location: jlocation) -> Result<Breakpoint, BreakpointError> {
+        self.add(jvmti, None, vec![CodeLocation { method, location }])
+    }
+
+    ///
+    /// Clear a breakpoint and return whether it was set
+    pub fn clear(&self, jvmti: &dyn JVMTI, id: usize) -> Result<bool, NativeError> {
+        let mut entries = self.entries();
+
+        match entries.1.iter().position(|breakpoint| breakpoint.id == id) {
+            Some(index) => {
+                let breakpoint = entries.1.remove(index);
+                release(jvmti, &entries.1, &breakpoint.locations)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    ///
+    /// Clear all breakpoints and return how many there were
+    pub fn clear_all(&self, jvmti: &dyn JVMTI) -> Result<usize, NativeError> {
+        let mut entries = self.entries();
+        let cleared: Vec<Breakpoint> = entries.1.drain(..).collect();
+
+        for breakpoint in cleared.iter() {
+            release(jvmti, &[], &breakpoint.locations)?;
+        }
+
+        Ok(cleared.len())
+    }
+
+    pub fn list(&self) -> Vec<Breakpoint> {
+        self.entries().1.clone()
+    }
+
+    ///
+    /// The identifiers of the breakpoints set on the instruction, eg. to tell which of them a
+    /// `Breakpoint` event was sent for
+    pub fn at(&self, method: jmethodID, location: jlocation) -> Vec<usize> {
+        let wanted = CodeLocation { method, location };
+
+        self.entries().1.iter()
+            .filter(|breakpoint| breakpoint.locations.contains(&wanted))
+            .map(|breakpoint| breakpoint.id)
+            .collect()
+    }
+
+    fn add(&self, jvmti: &dyn JVMTI, line: Option<u32>, locations: Vec<CodeLocation>) -> Result<Breakpoint, BreakpointError> {
+        let mut entries = self.entries();
+        let mut set: Vec<CodeLocation> = vec![];
+
+        for location in locations.iter() {
+            if entries.1.iter().any(|breakpoint| breakpoint.locations.contains(location)) {
+                continue;
+            }
+
+            match jvmti.set_breakpoint(location.method, location.location) {
+                Ok(()) => set.push(*location),
+                // Set outside of the registry, it's shared the same way
+                Err(NativeError::Duplicate) => (),
+                Err(err) => {
+                    for location in set.iter() {
+                        let _ = jvmti.clear_breakpoint(location.method, location.location);
+                    }
+                    return Err(BreakpointError::Jvmti(err));
+                }
+            }
+        }
+
+        entries.0 += 1;
+
+        let breakpoint = Breakpoint {
+            id: entries.0,
+            line,
+            locations,
+        };
+
+        entries.1.push(breakpoint.clone());
+        Ok(breakpoint)
+    }
+
+    // The breakpoints are looked up from the `Breakpoint` and `SingleStep` callbacks, which
+    // mustn't fail because another thread panicked
+    fn entries(&self) -> MutexGuard<'_, (usize, Vec<Breakpoint>)> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Clear the JVMTI breakpoints on the locations that none of the remaining breakpoints use
+fn release(jvmti: &dyn JVMTI, remaining: &[Breakpoint], locations: &[CodeLocation]) -> Result<(), NativeError> {
+    for location in locations.iter() {
+        if remaining.iter().any(|breakpoint| breakpoint.locations.contains(location)) {
+            continue;
+        }
+
+        match jvmti.clear_breakpoint(location.method, location.location) {
+            Ok(()) | Err(NativeError::NotFound) => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// The loaded classes with the binary or internal name, one for each class loader that defined
+/// a class with the name
+pub fn loaded_classes_named(jvmti: &dyn JVMTI, class_name: &str) -> Result<Vec<jclass>, NativeError> {
+    let wanted = class_name.replace('.', "/");
+    let mut classes = vec![];
+
+    for class in jvmti.get_loaded_classes()?.iter() {
+        if internal_name(&jvmti.get_class_signature(class)?).as_ref() == Some(&wanted) {
+            classes.push(*class);
+        }
+    }
+
+    Ok(classes)
+}
+
+///
+/// The first instruction of the line in each of the selected methods of the class. Methods
+/// without line numbers, eg. native or abstract ones, are skipped.
+pub fn resolve_line(jvmti: &dyn JVMTI, class: jclass, method_name: Option<&str>, line: u32) -> Result<Vec<CodeLocation>, NativeError> {
+    let mut locations = vec![];
+
+    for method in jvmti.get_class_methods(&class)? {
+        if let Some(name) = method_name {
+            if jvmti.get_method_name(method)?.name != name {
+                continue;
+            }
+        }
+
+        if let Some(table) = optional(jvmti.get_line_number_table(method))? {
+            let start = table.iter()
+                .filter(|entry| entry.line_number as u32 == line)
+                .map(|entry| entry.start_location)
+                .min();
+
+            if let Some(start) = start {
+                locations.push(CodeLocation { method, location: start });
+            }
+        }
+    }
+
+    Ok(locations)
+}
+
+///
+/// The source line of an instruction, `None` if the method has no line numbers
+pub fn line_at(jvmti: &dyn JVMTI, method: jmethodID, location: jlocation) -> Result<Option<u32>, NativeError> {
+    Ok(optional(jvmti.get_line_number_table(method))?
+        .and_then(|table| line_number(&table, location)))
+}
+
+///
+/// Describes why a field watch couldn't be set or cleared
+#[derive(Debug)]
+pub enum WatchError {
+    Jvmti(NativeError),
+    /// None of the loaded classes has the given name
+    ClassNotLoaded(String),
+    /// The class doesn't declare a field with the given name
+    NoSuchField(String),
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WatchError::Jvmti(ref err) => write!(f, "{}", translate_error(err)),
+            WatchError::ClassNotLoaded(ref name) => write!(f, "Class {} is not loaded", name),
+            WatchError::NoSuchField(ref name) => write!(f, "There is no field {}", name),
+        }
+    }
+}
+
+impl From<NativeError> for WatchError {
+    fn from(err: NativeError) -> Self {
+        WatchError::Jvmti(err)
+    }
+}
+
+///
+/// The field events a watch sends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    /// `FieldAccess` events, requires `can_generate_field_access_events`
+    Access,
+    /// `FieldModification` events, requires `can_generate_field_modification_events`
+    Modification,
+}
+
+///
+/// Watch a field declared by a loaded class, eg. `watch_field(jvmti, "com.acme.Orders", "count",
+/// WatchKind::Modification)`. Classes with the same name defined by different class loaders are
+/// all watched, and the number of watched classes is returned.
+pub fn watch_field(jvmti: &dyn JVMTI, class_name: &str, field_name: &str, kind: WatchKind) -> Result<usize, WatchError> {
+    let fields = resolve_field(jvmti, class_name, field_name)?;
+
+    for &(class, field) in fields.iter() {
+        let result = match kind {
+            WatchKind::Access => jvmti.set_field_access_watch(&class, field),
+            WatchKind::Modification => jvmti.set_field_modification_watch(&class, field),
+        };
+
+        match result {
+            Ok(()) | Err(NativeError::Duplicate) => (),
+            Err(err) => return Err(WatchError::Jvmti(err)),
+        }
+    }
+
+    Ok(fields.len())
+}
+
+///
+/// Stop watching a field, returning the number of classes it was cleared in
+pub fn unwatch_field(jvmti: &dyn JVMTI, class_name: &str, field_name: &str, kind: WatchKind) -> Result<usize, WatchError> {
+    let fields = resolve_field(jvmti, class_name, field_name)?;
+
+    for &(class, field) in fields.iter() {
+        let result = match kind {
+            WatchKind::Access => jvmti.clear_field_access_watch(&class, field),
+            WatchKind::Modification => jvmti.clear_field_modification_watch(&class, field),
+        };
+
+        match result {
+            Ok(()) | Err(NativeError::NotFound) => (),
+            Err(err) => return Err(WatchError::Jvmti(err)),
+        }
+    }
+
+    Ok(fields.len())
+}
+
+/// The field with the name in each of the loaded classes with the class name
+fn resolve_field(jvmti: &dyn JVMTI, class_name: &str, field_name: &str) -> Result<Vec<(jclass, jfieldID)>, WatchError> {
+    let classes = loaded_classes_named(jvmti, class_name)?;
+
+    if classes.is_empty() {
+        return Err(WatchError::ClassNotLoaded(class_name.to_string()));
+    }
+
+    let mut fields = vec![];
+
+    for class in classes {
+        for field in jvmti.get_class_fields(&class)? {
+            if jvmti.get_field_name(&class, field)?.0 == field_name {
+                fields.push((class, field));
+            }
+        }
+    }
+
+    if fields.is_empty() {
+        Err(WatchError::NoSuchField(format!("{}.{}", class_name, field_name)))
+    } else {
+        Ok(fields)
+    }
+}
+
+///
+/// How far a thread runs before the step completes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepKind {
+    /// Stop at the next line, including the first line of a called method
+    Into,
+    /// Stop at the next line of the current method, or in its caller once it returns
+    Over,
+    /// Stop once the current method returns to its caller
+    Out,
+}
+
+struct Step {
+    kind: StepKind,
+    method: jmethodID,
+    line: Option<u32>,
+    /// Frame count of the thread when the step started
+    depth: jint,
+    /// Frame count at the `FramePop` event that resumes single stepping
+    pop_depth: Option<jint>,
+}
+
+thread_local! {
+    static STEP: RefCell<Option<Step>> = const { RefCell::new(None) };
+}
+
+///
+/// Start stepping the current thread from its top frame, eg. in a breakpoint or single step
+/// handler. The steps are tracked per thread, so they have to be started on the thread that is
+/// stepped. The step completes with a `SingleStep` event at the location it stopped at; until
+/// then the thread's `SingleStep` events are consumed by the step.
+///
+/// Requires `can_generate_single_step_events`, and `can_generate_frame_pop_events` to step over
+/// calls and out of methods, with `FramePop` events enabled.
+pub fn step(jvmti: &dyn JVMTI, thread: jthread, kind: StepKind) -> Result<(), NativeError> {
+    let (method, location) = jvmti.get_frame_location(thread, 0)?;
+    let depth = jvmti.get_frame_count(thread)?;
+    let line = line_at(jvmti, method, location)?;
+
+    let pop_depth = match kind {
+        StepKind::Out => {
+            jvmti.notify_frame_pop(thread, 0)?;
+            Some(depth)
+        }
+        _ => {
+            jvmti.set_thread_event_notification_mode(VMEvent::SingleStep, thread, true)?;
+            None
+        }
+    };
+
+    STEP.with(|step| {
+        *step.borrow_mut() = Some(Step {
+            kind,
+            method,
+            line,
+            depth,
+            pop_depth,
+        })
+    });
+
+    Ok(())
+}
+
+///
+/// Abandon the step in progress on the current thread, returning whether there was one
+pub fn cancel_step(jvmti: &dyn JVMTI, thread: jthread) -> Result<bool, NativeError> {
+    match STEP.with(|step| step.borrow_mut().take()) {
+        Some(_) => {
+            jvmti.set_thread_event_notification_mode(VMEvent::SingleStep, thread, false)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+pub fn is_stepping() -> bool {
+    STEP.with(|step| step.borrow().is_some())
+}
+
+///
+/// Advance the step in progress on the current thread with a `SingleStep` event. Returns the
+/// event to deliver once the step completes, or right away when the thread isn't stepping.
+pub fn on_single_step(jvmti: &dyn JVMTI, thread: jthread, method: jmethodID, location: jlocation) -> Result<Option<LocationEvent>, NativeError> {
+    let line = line_at(jvmti, method, location)?;
+    let event = LocationEvent {
+        thread,
+        method,
+        location,
+        line,
+    };
+
+    let mut current = match STEP.with(|step| step.borrow_mut().take()) {
+        Some(current) => current,
+        None => return Ok(Some(event)),
+    };
+
+    let depth = jvmti.get_frame_count(thread)?;
+
+    let completed = match current.kind {
+        StepKind::Into => depth != current.depth || method != current.method || line != current.line,
+        StepKind::Over if depth > current.depth => {
+            // Entered a callee, run it at full speed until it returns
+            jvmti.notify_frame_pop(thread, 0)?;
+            jvmti.set_thread_event_notification_mode(VMEvent::SingleStep, thread, false)?;
+            current.pop_depth = Some(depth);
+            false
+        }
+        StepKind::Over => depth < current.depth || method != current.method || line != current.line,
+        StepKind::Out => true,
+    };
+
+    if completed {
+        jvmti.set_thread_event_notification_mode(VMEvent::SingleStep, thread, false)?;
+        Ok(Some(event))
+    } else {
+        STEP.with(|step| *step.borrow_mut() = Some(current));
+        Ok(None)
+    }
+}
+
+///
+/// Resume single stepping the current thread when the frame a step waits for is popped. Returns
+/// whether the event belonged to the step.
+pub fn on_frame_pop(jvmti: &dyn JVMTI, thread: jthread) -> Result<bool, NativeError> {
+    let pop_depth = STEP.with(|step| step.borrow().as_ref().and_then(|current| current.pop_depth));
+
+    match pop_depth {
+        Some(pop_depth) if pop_depth == jvmti.get_frame_count(thread)? => {
+            STEP.with(|step| {
+                if let Some(ref mut current) = *step.borrow_mut() {
+                    current.pop_depth = None;
+                }
+            });
+            jvmti.set_thread_event_notification_mode(VMEvent::SingleStep, thread, true)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Called once with the `FramePop` event of the frame it waits for
+pub type FrameReturnHandler = Box<dyn FnOnce(&Environment, FramePopEvent) + Send>;
+
+struct PendingReturn {
+    /// Global reference of the thread
+    thread: ThreadId,
+    /// Frame count of the thread when the frame is popped, including the popped frame
+    frame_count: jint,
+    handler: FrameReturnHandler,
+}
+
+///
+/// Handlers waiting for a frame to return, or to be popped by an exception. The frames are
+/// requested through `NotifyFramePop`, which doesn't need `MethodExit` events for every method.
+///
+pub struct FrameReturns {
+    pending: Mutex<Vec<PendingReturn>>,
+}
+
+impl Default for FrameReturns {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameReturns {
+    pub fn new() -> FrameReturns {
+        FrameReturns {
+            pending: Mutex::new(vec![]),
+        }
+    }
+
+    ///
+    /// Call the handler once the frame at the depth returns. The thread has to be the current
+    /// thread or suspended. Requires `can_generate_frame_pop_events`, with `FramePop` events
+    /// enabled.
+    pub fn add(&self, jvmti: &dyn JVMTI, jni: &dyn JNI, thread: jthread, depth: jint, handler: FrameReturnHandler) -> Result<(), ThreadControlError> {
+        let frame_count = jvmti.get_frame_count(thread)? - depth;
+
+        match jvmti.notify_frame_pop(thread, depth) {
+            // Already requested, eg. by a step or another handler
+            Ok(()) | Err(NativeError::Duplicate) => (),
+            Err(err) => return Err(ThreadControlError::Jvmti(err)),
+        }
+
+        let global = jni.new_global_ref(&thread)?;
+
+        self.pending().push(PendingReturn {
+            thread: ThreadId::new(global),
+            frame_count,
+            handler,
+        });
+
+        Ok(())
+    }
+
+    ///
+    /// Remove the handlers waiting for the frame of the thread that is being popped
+    pub fn take(&self, jni: &dyn JNI, thread: jthread, frame_count: jint) -> Vec<FrameReturnHandler> {
+        let mut pending = self.pending();
+        let mut handlers = vec![];
+        let mut index = 0;
+
+        while index < pending.len() {
+            if pending[index].frame_count == frame_count && jni.is_same_object(&pending[index].thread.native_id, &thread) {
+                let entry = pending.remove(index);
+                let _ = jni.delete_global_ref(&entry.thread.native_id);
+                handlers.push(entry.handler);
+            } else {
+                index += 1;
+            }
+        }
+
+        handlers
+    }
+
+    /// The number of handlers waiting for their frame to return
+    pub fn len(&self) -> usize {
+        self.pending().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending().is_empty()
+    }
+
+    // The handlers are taken from the `FramePop` callback, which mustn't fail because another
+    // thread panicked
+    fn pending(&self) -> MutexGuard<'_, Vec<PendingReturn>> {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+///
+/// Call the closure once the frame at the depth of the thread returns or is popped by an
+/// exception, eg. to time a single invocation without enabling `MethodExit` events. The agent
+/// needs `can_generate_frame_pop_events`, which enables `FramePop` events on `update`.
+pub fn on_frame_return<F>(env: &Environment, thread: jthread, depth: jint, closure: F) -> Result<(), ThreadControlError>
+    where F: FnOnce(&Environment, FramePopEvent) + Send + 'static {
+    static_context().frame_returns.add(env, env, thread, depth, Box::new(closure))
+}
@@ -0,0 +1,347 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+use std::ops::BitOr;
+
+use super::environment::jni::JNI;
+use super::environment::jvmti::JVMTI;
+use super::error::NativeError;
+use super::native::jvmti_native::*;
+use super::stack::{binary_name, optional, StackFrame, StackInfo, StackTrace};
+use super::util::stringify;
+
+///
+/// The state bits of a thread, as reported by `GetThreadState` and `GetAllStackTraces`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ThreadState(u32);
+
+impl ThreadState {
+    pub const ALIVE: ThreadState = ThreadState(JVMTI_THREAD_STATE_ALIVE);
+    pub const TERMINATED: ThreadState = ThreadState(JVMTI_THREAD_STATE_TERMINATED);
+    pub const RUNNABLE: ThreadState = ThreadState(JVMTI_THREAD_STATE_RUNNABLE);
+    pub const BLOCKED_ON_MONITOR_ENTER: ThreadState = ThreadState(JVMTI_THREAD_STATE_BLOCKED_ON_MONITOR_ENTER);
+    pub const WAITING: ThreadState = ThreadState(JVMTI_THREAD_STATE_WAITING);
+    pub const WAITING_INDEFINITELY: ThreadState = ThreadState(JVMTI_THREAD_STATE_WAITING_INDEFINITELY);
+    pub const WAITING_WITH_TIMEOUT: ThreadState = ThreadState(JVMTI_THREAD_STATE_WAITING_WITH_TIMEOUT);
+    pub const SLEEPING: ThreadState = ThreadState(JVMTI_THREAD_STATE_SLEEPING);
+    pub const IN_OBJECT_WAIT: ThreadState = ThreadState(JVMTI_THREAD_STATE_IN_OBJECT_WAIT);
+    pub const PARKED: ThreadState = ThreadState(JVMTI_THREAD_STATE_PARKED);
+    pub const SUSPENDED: ThreadState = ThreadState(JVMTI_THREAD_STATE_SUSPENDED);
+    pub const INTERRUPTED: ThreadState = ThreadState(JVMTI_THREAD_STATE_INTERRUPTED);
+    pub const IN_NATIVE: ThreadState = ThreadState(JVMTI_THREAD_STATE_IN_NATIVE);
+
+    const NAMES: [(ThreadState, &'static str); 13] = [
+        (ThreadState::ALIVE, "alive"),
+        (ThreadState::TERMINATED, "terminated"),
+        (ThreadState::RUNNABLE, "runnable"),
+        (ThreadState::BLOCKED_ON_MONITOR_ENTER, "blocked_on_monitor_enter"),
+        (ThreadState::WAITING, "waiting"),
+        (ThreadState::WAITING_INDEFINITELY, "waiting_indefinitely"),
+        (ThreadState::WAITING_WITH_TIMEOUT, "waiting_with_timeout"),
+        (ThreadState::SLEEPING, "sleeping"),
+        (ThreadState::IN_OBJECT_WAIT, "in_object_wait"),
+        (ThreadState::PARKED, "parked"),
+        (ThreadState::SUSPENDED, "suspended"),
+        (ThreadState::INTERRUPTED, "interrupted"),
+        (ThreadState::IN_NATIVE, "in_native"),
+    ];
+
+    pub fn from_bits(bits: u32) -> ThreadState {
+        ThreadState(bits)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Whether all bits of `other` are set
+    pub fn contains(&self, other: ThreadState) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The matching `java.lang.Thread.State`, eg. `TIMED_WAITING`
+    pub fn java_state(&self) -> &'static str {
+        if self.contains(ThreadState::TERMINATED) {
+            "TERMINATED"
+        } else if !self.contains(ThreadState::ALIVE) {
+            "NEW"
+        } else if self.contains(ThreadState::BLOCKED_ON_MONITOR_ENTER) {
+            "BLOCKED"
+        } else if self.contains(ThreadState::WAITING_WITH_TIMEOUT) {
+            "TIMED_WAITING"
+        } else if self.contains(ThreadState::WAITING_INDEFINITELY) {
+            "WAITING"
+        } else {
+            "RUNNABLE"
+        }
+    }
+
+    /// What the thread is waiting for, as jstack shows it next to the Java state
+    pub fn detail(&self) -> Option<&'static str> {
+        if self.contains(ThreadState::BLOCKED_ON_MONITOR_ENTER) || self.contains(ThreadState::IN_OBJECT_WAIT) {
+            Some("on object monitor")
+        } else if self.contains(ThreadState::SLEEPING) {
+            Some("sleeping")
+        } else if self.contains(ThreadState::PARKED) {
+            Some("parking")
+        } else {
+            None
+        }
+    }
+
+    /// The names of the bits that are set, eg. `["alive", "waiting", "sleeping"]`
+    pub fn flags(&self) -> Vec<&'static str> {
+        ThreadState::NAMES
+            .iter()
+            .filter(|&&(flag, _)| self.contains(flag))
+            .map(|&(_, name)| name)
+            .collect()
+    }
+}
+
+impl BitOr for ThreadState {
+    type Output = ThreadState;
+
+    fn bitor(self, other: ThreadState) -> ThreadState {
+        ThreadState(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for ThreadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.detail() {
+            Some(detail) => write!(f, "{} ({})", self.java_state(), detail),
+            None => write!(f, "{}", self.java_state()),
+        }
+    }
+}
+
+impl Serialize for ThreadState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ThreadState", 2)?;
+        state.serialize_field("state", self.java_state())?;
+        state.serialize_field("flags", &self.flags())?;
+        state.end()
+    }
+}
+
+///
+/// An object whose monitor is held or awaited
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitorInfo {
+    /// Binary name of the object's class, eg. `java.lang.Object`
+    pub class_name: String,
+    /// The identity hash code, which stands in for the address jstack shows
+    pub hash_code: i32,
+}
+
+impl fmt::Display for MonitorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<0x{:08x}> (a {})", self.hash_code, self.class_name)
+    }
+}
+
+///
+/// A monitor owned by a thread
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LockedMonitor {
+    pub monitor: MonitorInfo,
+    /// The depth of the frame that locked the monitor, -1 if it was locked through JNI
+    pub depth: i32,
+}
+
+///
+/// The state, stack and monitors of a thread at the time of the dump
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ThreadSnapshot {
+    pub name: String,
+    pub priority: u32,
+    pub daemon: bool,
+    pub state: ThreadState,
+    /// The frames of the stack, the most recent frame first
+    pub frames: Vec<StackFrame>,
+    /// Monitors owned by the thread. Empty if the environment lacks
+    /// `can_get_owned_monitor_stack_depth_info`.
+    pub locked: Vec<LockedMonitor>,
+    /// The monitor the thread is waiting to enter or to regain after `Object.wait`. `None` if the
+    /// environment lacks `can_get_current_contended_monitor`.
+    pub waiting_on: Option<MonitorInfo>,
+}
+
+impl fmt::Display for ThreadSnapshot {
+    /// Renders the thread the way jstack does, monitors following the frame that holds them
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\"{}\"{} prio={}", self.name, if self.daemon { " daemon" } else { "" }, self.priority)?;
+        writeln!(f, "   java.lang.Thread.State: {}", self.state)?;
+
+        for (depth, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "\tat {}", frame)?;
+
+            if depth == 0 {
+                if let Some(ref monitor) = self.waiting_on {
+                    if self.state.contains(ThreadState::IN_OBJECT_WAIT) {
+                        writeln!(f, "\t- waiting on {}", monitor)?;
+                    } else {
+                        writeln!(f, "\t- waiting to lock {}", monitor)?;
+                    }
+                }
+            }
+
+            for locked in self.locked.iter().filter(|locked| locked.depth == depth as i32) {
+                writeln!(f, "\t- locked {}", locked.monitor)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// The threads of the VM with their stacks and monitors, like jstack reports them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ThreadDump {
+    /// The maximum number of frames captured per thread
+    pub max_depth: jint,
+    pub threads: Vec<ThreadSnapshot>,
+}
+
+impl ThreadDump {
+    ///
+    /// Dump all live threads with up to `max_depth` frames each
+    pub fn capture(jvmti: &dyn JVMTI, jni: &dyn JNI, max_depth: jint) -> Result<ThreadDump, NativeError> {
+        let stacks = jvmti.get_all_stack_traces(max_depth)?;
+        let dump = ThreadDump::from_stacks(jvmti, &stacks, max_depth, &|object| class_name(jvmti, jni, object), &|object| {
+            let _ = jni.delete_local_ref(&object);
+        });
+
+        for stack in &stacks {
+            let _ = jni.delete_local_ref(&stack.thread);
+        }
+
+        dump
+    }
+
+    ///
+    /// Dump the given threads with up to `max_depth` frames each
+    pub fn capture_threads(jvmti: &dyn JVMTI, jni: &dyn JNI, threads: &[jthread], max_depth: jint) -> Result<ThreadDump, NativeError> {
+        let stacks = jvmti.get_thread_list_stack_traces(threads, max_depth)?;
+
+        ThreadDump::from_stacks(jvmti, &stacks, max_depth, &|object| class_name(jvmti, jni, object), &|object| {
+            let _ = jni.delete_local_ref(&object);
+        })
+    }
+
+    ///
+    /// Build a dump from raw stacks. `describe` returns the class name of a monitor object and
+    /// `release` is called with each local reference once it's no longer needed. Threads that
+    /// terminate while they are being dumped are left out.
+    pub fn from_stacks(
+        jvmti: &dyn JVMTI,
+        stacks: &[StackInfo],
+        max_depth: jint,
+        describe: &dyn Fn(jobject) -> String,
+        release: &dyn Fn(jobject),
+    ) -> Result<ThreadDump, NativeError> {
+        let mut threads = vec![];
+
+        for stack in stacks {
+            match ThreadDump::snapshot(jvmti, stack, describe, release) {
+                Ok(snapshot) => threads.push(snapshot),
+                Err(NativeError::ThreadNotAlive) | Err(NativeError::InvalidThread) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(ThreadDump {
+            max_depth,
+            threads,
+        })
+    }
+
+    fn snapshot(
+        jvmti: &dyn JVMTI,
+        stack: &StackInfo,
+        describe: &dyn Fn(jobject) -> String,
+        release: &dyn Fn(jobject),
+    ) -> Result<ThreadSnapshot, NativeError> {
+        let info = jvmti.get_thread_info(&stack.thread)?;
+        let name = stringify(info.name);
+        jvmti.deallocate(info.name as _)?;
+
+        for reference in [info.thread_group, info.context_class_loader].iter() {
+            if !reference.is_null() {
+                release(*reference);
+            }
+        }
+
+        let monitor = |object: jobject| -> Result<MonitorInfo, NativeError> {
+            let hash_code = jvmti.get_object_hash_code(&object)?;
+            let info = MonitorInfo {
+                class_name: describe(object),
+                hash_code,
+            };
+            release(object);
+            Ok(info)
+        };
+
+        let mut locked = vec![];
+
+        for owned in optional(jvmti.get_owned_monitor_stack_depth_info(stack.thread))?.unwrap_or_default() {
+            locked.push(LockedMonitor {
+                monitor: monitor(owned.monitor)?,
+                depth: owned.stack_depth,
+            });
+        }
+
+        let waiting_on = match optional(jvmti.get_current_contended_monitor(stack.thread))? {
+            Some(Some(object)) => Some(monitor(object)?),
+            _ => None,
+        };
+
+        Ok(ThreadSnapshot {
+            name,
+            priority: info.priority as u32,
+            daemon: info.is_daemon > 0,
+            state: ThreadState::from_bits(stack.state),
+            frames: StackTrace::resolve(jvmti, &stack.frames)?,
+            locked,
+            waiting_on,
+        })
+    }
+
+    pub fn thread(&self, name: &str) -> Option<&ThreadSnapshot> {
+        self.threads.iter().find(|thread| thread.name == name)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl fmt::Display for ThreadDump {
+    /// Renders the dump in the format of jstack
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Full thread dump:")?;
+
+        for thread in &self.threads {
+            writeln!(f)?;
+            write!(f, "{}", thread)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The binary name of the object's class, or `?` if it can't be resolved
+fn class_name(jvmti: &dyn JVMTI, jni: &dyn JNI, object: jobject) -> String {
+    match jni.get_object_class(&object) {
+        Ok(class) => {
+            let name = jvmti
+                .get_class_signature(&class)
+                .map(|signature| binary_name(&signature.native_sig))
+                .unwrap_or_else(|_| "?".to_string());
+            let _ = jni.delete_local_ref(&class);
+            name
+        }
+        Err(_) => "?".to_string(),
+    }
+}
@@ -2,31 +2,89 @@ use crate::{
     environment::{jni::JNI, jvmti::JVMTIError, Environment},
     native::{
         jvmti_native::{
-            jclass, jlong, jmethodID, jobject, jrawMonitorID, jthread, jvmtiFrameInfo,
-            jvmtiThreadInfo,
+            jclass, jdouble, jfieldID, jfloat, jint, jlocation, jlong, jmethodID, jobject, jrawMonitorID, jthread, jvmtiClassDefinition,
+            jvmtiFrameInfo, jvmtiMonitorStackDepthInfo, jvmtiThreadInfo,
         },
-        JavaClass,
+        JavaClass, JavaValue,
     },
 };
 
 use super::capabilities::Capabilities;
-use super::class::{ClassId, ClassSignature};
+use super::class::{ClassId, ClassSignature, JavaType};
 use super::environment::jvm::JVMF;
 use super::environment::jvmti::JVMTI;
 use super::error::NativeError;
 use super::event::{EventCallbacks, VMEvent};
 use super::mem::MemoryAllocation;
-use super::method::MethodSignature;
-use super::native::JavaThread;
+use super::method::{LineNumberEntry, LocalValue, LocalVariableEntry, MethodSignature};
+use super::native::{JVMTIEnvPtr, JavaThread};
 use super::runtime::*;
+use super::stack::StackInfo;
 use super::version::VersionNumber;
 use std::collections::HashMap;
+use std::ffi::CString;
+use std::ptr;
+use std::sync::Mutex;
 
 /// Allows testing of JVM and JVMTI-related functions by emulating (mocking) a JVM agent.
 pub struct JVMEmulator {
     pub capabilities: Capabilities,
     pub callbacks: EventCallbacks,
     pub events: HashMap<VMEvent, bool>,
+    /// Classes reported by `get_loaded_classes`
+    pub loaded_classes: Vec<jclass>,
+    /// JVM type signatures of the classes, eg. `Lcom/acme/Service;`
+    pub class_signatures: HashMap<jclass, String>,
+    /// Classes passed to `retransform_classes`, in the order they were retransformed
+    pub retransformed: Mutex<Vec<jclass>>,
+    /// Values of the local variables by slot, returned by `get_local_*` for any thread and depth
+    pub locals: HashMap<jint, JavaValue>,
+    /// Slots and values of the local variables assigned through `set_local_*`, in order
+    pub assigned_locals: Mutex<Vec<(jint, LocalValue)>>,
+    /// The `this` object returned by `get_local_instance` for any frame, `None` for static frames
+    pub instance: Option<jobject>,
+    /// Local variable tables returned by `get_local_variable_table`
+    pub local_variable_tables: HashMap<jmethodID, Vec<LocalVariableEntry>>,
+    /// Frames returned by `get_stack_trace_range` for any thread, the top frame first
+    pub frames: Vec<jvmtiFrameInfo>,
+    /// Declaring class, name and descriptor of the methods, eg. `(Orders, "count", "()I")`
+    pub methods: HashMap<jmethodID, (jclass, String, String)>,
+    /// Line number tables returned by `get_line_number_table`
+    pub line_number_tables: HashMap<jmethodID, Vec<LineNumberEntry>>,
+    /// Source file names returned by `get_source_file_name`
+    pub source_files: HashMap<jclass, String>,
+    /// Name, priority and daemon status of the threads returned by `get_thread_info`
+    pub threads: HashMap<jthread, (String, jint, bool)>,
+    /// Stacks returned by `get_all_stack_traces` and `get_thread_list_stack_traces`
+    pub stacks: Vec<StackInfo>,
+    /// Monitors owned by the threads, with the depth of the frame that locked them
+    pub owned_monitors: HashMap<jthread, Vec<jvmtiMonitorStackDepthInfo>>,
+    /// Monitors the threads are waiting for
+    pub contended_monitors: HashMap<jthread, jobject>,
+    /// Identity hash codes returned by `get_object_hash_code`
+    pub hash_codes: HashMap<jobject, jint>,
+    /// Threads suspended through `suspend_thread` and `suspend_thread_list`
+    pub suspended: Mutex<Vec<jthread>>,
+    /// Threads passed to `interrupt_thread`, in the order they were interrupted
+    pub interrupted: Mutex<Vec<jthread>>,
+    /// Threads passed to `stop_thread` and the exceptions they were stopped with
+    pub stopped: Mutex<Vec<(jthread, jobject)>>,
+    /// Breakpoints set through `set_breakpoint` and not cleared yet, in the order they were set
+    pub breakpoints: Mutex<Vec<(jmethodID, jlocation)>>,
+    /// Threads and frame depths passed to `notify_frame_pop`, in order
+    pub frame_pop_requests: Mutex<Vec<(jthread, jint)>>,
+    /// Events enabled or disabled for single threads through `set_thread_event_notification_mode`, in order
+    pub thread_events: Mutex<Vec<(VMEvent, jthread, bool)>>,
+    /// Declaring class, name and type signature of the fields, eg. `(Orders, "count", "I")`
+    pub fields: HashMap<jfieldID, (jclass, String, String)>,
+    /// Fields watched through `set_field_access_watch`
+    pub access_watches: Mutex<Vec<(jclass, jfieldID)>>,
+    /// Fields watched through `set_field_modification_watch`
+    pub modification_watches: Mutex<Vec<(jclass, jfieldID)>>,
+    /// Class loaders returned by `get_classloader`, classes without one are bootstrap classes
+    pub class_loaders: HashMap<jclass, jobject>,
+    /// Events requested through `generate_events`, in order
+    pub generated_events: Mutex<Vec<VMEvent>>,
 }
 
 impl JVMEmulator {
@@ -35,6 +93,33 @@ impl JVMEmulator {
             capabilities: Capabilities::new(),
             callbacks: EventCallbacks::new(),
             events: HashMap::new(),
+            loaded_classes: vec![],
+            class_signatures: HashMap::new(),
+            retransformed: Mutex::new(vec![]),
+            locals: HashMap::new(),
+            assigned_locals: Mutex::new(vec![]),
+            instance: None,
+            local_variable_tables: HashMap::new(),
+            frames: vec![],
+            methods: HashMap::new(),
+            line_number_tables: HashMap::new(),
+            source_files: HashMap::new(),
+            threads: HashMap::new(),
+            stacks: vec![],
+            owned_monitors: HashMap::new(),
+            contended_monitors: HashMap::new(),
+            hash_codes: HashMap::new(),
+            suspended: Mutex::new(vec![]),
+            interrupted: Mutex::new(vec![]),
+            stopped: Mutex::new(vec![]),
+            breakpoints: Mutex::new(vec![]),
+            frame_pop_requests: Mutex::new(vec![]),
+            thread_events: Mutex::new(vec![]),
+            fields: HashMap::new(),
+            access_watches: Mutex::new(vec![]),
+            modification_watches: Mutex::new(vec![]),
+            class_loaders: HashMap::new(),
+            generated_events: Mutex::new(vec![]),
         }
     }
 
@@ -61,12 +146,24 @@ impl JVMF for JVMEmulator {
         unimplemented!()
     }
 
+    fn attach_current_thread_as_daemon(&self, thread_name: &str) -> Result<Box<dyn JNI>, NativeError> {
+        unimplemented!()
+    }
+
+    fn detach_current_thread(&self) -> Result<(), NativeError> {
+        Ok(())
+    }
+
     fn get_jni_environment(&self) -> Result<Box<dyn JNI>, NativeError> {
         todo!()
     }
 }
 
 impl JVMTI for JVMEmulator {
+    fn get_env_ptr(&self) -> Option<JVMTIEnvPtr> {
+        None
+    }
+
     fn get_version_number(&self) -> VersionNumber {
         VersionNumber::unknown()
     }
@@ -95,27 +192,42 @@ impl JVMTI for JVMEmulator {
     }
 
     fn get_thread_info(&self, thread_id: &JavaThread) -> Result<jvmtiThreadInfo, NativeError> {
-        match *thread_id as u64 {
-            _ => Err(NativeError::NotImplemented),
+        match self.threads.get(thread_id) {
+            Some(&(ref name, priority, is_daemon)) => Ok(jvmtiThreadInfo {
+                name: CString::new(name.as_str()).unwrap().into_raw(),
+                priority,
+                is_daemon: is_daemon as u8,
+                thread_group: ::std::ptr::null_mut(),
+                context_class_loader: ::std::ptr::null_mut(),
+            }),
+            None => Err(NativeError::NotImplemented),
         }
     }
 
     fn get_method_declaring_class(&self, method_id: &jmethodID) -> Result<ClassId, NativeError> {
-        match *method_id as u64 {
-            _ => Err(NativeError::NotImplemented),
+        match self.methods.get(method_id) {
+            Some(&(class, _, _)) => Ok(ClassId { native_id: class }),
+            None => Err(NativeError::NotImplemented),
         }
     }
 
     fn get_method_name(&self, method_id: jmethodID) -> Result<MethodSignature, NativeError> {
-        match method_id as u64 {
-            0x01 => Ok(MethodSignature::new("".to_string(), "".to_string())),
-            _ => Err(NativeError::NotImplemented),
+        match self.methods.get(&method_id) {
+            Some((_, name, descriptor)) => Ok(MethodSignature::new(name.clone(), descriptor.clone())),
+            None => match method_id as u64 {
+                0x01 => Ok(MethodSignature::new("".to_string(), "".to_string())),
+                _ => Err(NativeError::NotImplemented),
+            },
         }
     }
 
     fn get_class_signature(&self, class_id: &jclass) -> Result<ClassSignature, NativeError> {
-        match *class_id as u64 {
-            _ => Err(NativeError::NotImplemented),
+        match self.class_signatures.get(class_id) {
+            Some(signature) => match JavaType::parse(signature) {
+                Some(java_type) => Ok(ClassSignature::new(java_type, signature.clone())),
+                None => Err(NativeError::InvalidClass),
+            },
+            None => Err(NativeError::NotImplemented),
         }
     }
 
@@ -127,7 +239,7 @@ impl JVMTI for JVMEmulator {
     }
 
     fn deallocate(&self, mem: *mut u8) -> Result<(), NativeError> {
-        unimplemented!()
+        Ok(())
     }
 
     fn get_all_threads(&self) -> Result<&[jthread], NativeError> {
@@ -151,19 +263,355 @@ impl JVMTI for JVMEmulator {
         unimplemented!()
     }
 
+    fn get_stack_trace_range(
+        &self,
+        thread: jthread,
+        start_depth: jint,
+        max_frame_count: jint,
+    ) -> Result<Vec<jvmtiFrameInfo>, NativeError> {
+        let start = if start_depth < 0 { self.frames.len() as jint + start_depth } else { start_depth };
+
+        if start < 0 || start as usize > self.frames.len() {
+            return Err(NativeError::IllegalArgument);
+        }
+
+        Ok(self.frames.iter().skip(start as usize).take(max_frame_count.max(0) as usize).cloned().collect())
+    }
+
+    fn get_line_number_table(&self, method: jmethodID) -> Result<Vec<LineNumberEntry>, NativeError> {
+        match self.line_number_tables.get(&method) {
+            Some(table) => Ok(table.clone()),
+            None => Err(NativeError::AbsentInformation),
+        }
+    }
+
+    fn get_source_file_name(&self, class: &jclass) -> Result<String, NativeError> {
+        match self.source_files.get(class) {
+            Some(name) => Ok(name.clone()),
+            None => Err(NativeError::AbsentInformation),
+        }
+    }
+
+    fn get_all_stack_traces(&self, max_frame_count: jint) -> Result<Vec<StackInfo>, NativeError> {
+        Ok(self
+            .stacks
+            .iter()
+            .map(|stack| StackInfo {
+                thread: stack.thread,
+                state: stack.state,
+                frames: stack.frames.iter().take(max_frame_count.max(0) as usize).cloned().collect(),
+            })
+            .collect())
+    }
+
+    fn get_thread_list_stack_traces(
+        &self,
+        threads: &[jthread],
+        max_frame_count: jint,
+    ) -> Result<Vec<StackInfo>, NativeError> {
+        let all = self.get_all_stack_traces(max_frame_count)?;
+
+        Ok(threads
+            .iter()
+            .map(|&thread| match all.iter().find(|stack| stack.thread == thread) {
+                Some(stack) => stack.clone(),
+                None => StackInfo { thread, state: 0, frames: vec![] },
+            })
+            .collect())
+    }
+
+    fn get_owned_monitor_stack_depth_info(
+        &self,
+        thread: jthread,
+    ) -> Result<Vec<jvmtiMonitorStackDepthInfo>, NativeError> {
+        Ok(self.owned_monitors.get(&thread).cloned().unwrap_or_default())
+    }
+
+    fn get_current_contended_monitor(&self, thread: jthread) -> Result<Option<jobject>, NativeError> {
+        Ok(self.contended_monitors.get(&thread).cloned())
+    }
+
     fn get_local_object(
         &self,
         thread: crate::native::jvmti_native::jthread,
         depth: crate::native::jvmti_native::jint,
         slot: crate::native::jvmti_native::jint,
     ) -> Result<crate::native::jvmti_native::jobject, NativeError> {
-        unimplemented!()
+        match self.locals.get(&slot) {
+            Some(value) => Ok(unsafe { *value.clone().l() }),
+            None => Err(NativeError::InvalidSlot),
+        }
     }
 
     fn get_thread_state(&self, thread: jthread) -> Result<u32, NativeError> {
         unimplemented!()
     }
 
+    fn suspend_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        if !self.capabilities.can_suspend {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        let mut suspended = self.suspended.lock().unwrap();
+
+        if suspended.contains(&thread) {
+            Err(NativeError::ThreadSuspended)
+        } else {
+            suspended.push(thread);
+            Ok(())
+        }
+    }
+
+    fn suspend_thread_list(&self, threads: &[jthread]) -> Result<Vec<Result<(), NativeError>>, NativeError> {
+        if !self.capabilities.can_suspend {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        Ok(threads.iter().map(|thread| self.suspend_thread(*thread)).collect())
+    }
+
+    fn resume_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        if !self.capabilities.can_suspend {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        let mut suspended = self.suspended.lock().unwrap();
+
+        match suspended.iter().position(|t| *t == thread) {
+            Some(index) => {
+                suspended.remove(index);
+                Ok(())
+            }
+            None => Err(NativeError::ThreadNotSuspended),
+        }
+    }
+
+    fn resume_thread_list(&self, threads: &[jthread]) -> Result<Vec<Result<(), NativeError>>, NativeError> {
+        if !self.capabilities.can_suspend {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        Ok(threads.iter().map(|thread| self.resume_thread(*thread)).collect())
+    }
+
+    fn interrupt_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        if !self.capabilities.can_signal_thread {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        self.interrupted.lock().unwrap().push(thread);
+        Ok(())
+    }
+
+    fn stop_thread(&self, thread: jthread, exception: jobject) -> Result<(), NativeError> {
+        if !self.capabilities.can_signal_thread {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        self.stopped.lock().unwrap().push((thread, exception));
+        Ok(())
+    }
+
+    fn get_local_int(&self, thread: jthread, depth: jint, slot: jint) -> Result<jint, NativeError> {
+        match self.locals.get(&slot) {
+            Some(value) => Ok(unsafe { *value.clone().i() }),
+            None => Err(NativeError::InvalidSlot),
+        }
+    }
+
+    fn get_local_long(&self, thread: jthread, depth: jint, slot: jint) -> Result<jlong, NativeError> {
+        match self.locals.get(&slot) {
+            Some(value) => Ok(unsafe { *value.clone().j() }),
+            None => Err(NativeError::InvalidSlot),
+        }
+    }
+
+    fn get_local_float(&self, thread: jthread, depth: jint, slot: jint) -> Result<jfloat, NativeError> {
+        match self.locals.get(&slot) {
+            Some(value) => Ok(unsafe { *value.clone().f() }),
+            None => Err(NativeError::InvalidSlot),
+        }
+    }
+
+    fn get_local_double(&self, thread: jthread, depth: jint, slot: jint) -> Result<jdouble, NativeError> {
+        match self.locals.get(&slot) {
+            Some(value) => Ok(unsafe { *value.clone().d() }),
+            None => Err(NativeError::InvalidSlot),
+        }
+    }
+
+    fn get_local_instance(&self, thread: jthread, depth: jint) -> Result<jobject, NativeError> {
+        self.instance.ok_or(NativeError::InvalidSlot)
+    }
+
+    fn set_local_object(&self, thread: jthread, depth: jint, slot: jint, value: jobject) -> Result<(), NativeError> {
+        self.assigned_locals.lock().unwrap().push((slot, LocalValue::Object(value)));
+        Ok(())
+    }
+
+    fn set_local_int(&self, thread: jthread, depth: jint, slot: jint, value: jint) -> Result<(), NativeError> {
+        self.assigned_locals.lock().unwrap().push((slot, LocalValue::Int(value)));
+        Ok(())
+    }
+
+    fn set_local_long(&self, thread: jthread, depth: jint, slot: jint, value: jlong) -> Result<(), NativeError> {
+        self.assigned_locals.lock().unwrap().push((slot, LocalValue::Long(value)));
+        Ok(())
+    }
+
+    fn set_local_float(&self, thread: jthread, depth: jint, slot: jint, value: jfloat) -> Result<(), NativeError> {
+        self.assigned_locals.lock().unwrap().push((slot, LocalValue::Float(value)));
+        Ok(())
+    }
+
+    fn set_local_double(&self, thread: jthread, depth: jint, slot: jint, value: jdouble) -> Result<(), NativeError> {
+        self.assigned_locals.lock().unwrap().push((slot, LocalValue::Double(value)));
+        Ok(())
+    }
+
+    fn get_frame_location(&self, thread: jthread, depth: jint) -> Result<(jmethodID, jlocation), NativeError> {
+        match self.frames.get(depth as usize) {
+            Some(frame) => Ok((frame.method, frame.location)),
+            None => Err(NativeError::NoMoreFrames),
+        }
+    }
+
+    fn get_frame_count(&self, thread: jthread) -> Result<jint, NativeError> {
+        Ok(self.frames.len() as jint)
+    }
+
+    fn notify_frame_pop(&self, thread: jthread, depth: jint) -> Result<(), NativeError> {
+        if !self.capabilities.can_generate_frame_pop_events {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        if depth < 0 || depth as usize >= self.frames.len() {
+            return Err(NativeError::NoMoreFrames);
+        }
+
+        self.frame_pop_requests.lock().unwrap().push((thread, depth));
+        Ok(())
+    }
+
+    fn set_breakpoint(&self, method: jmethodID, location: jlocation) -> Result<(), NativeError> {
+        if !self.capabilities.can_generate_breakpoint_events {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        let mut breakpoints = self.breakpoints.lock().unwrap();
+
+        if breakpoints.contains(&(method, location)) {
+            Err(NativeError::Duplicate)
+        } else {
+            breakpoints.push((method, location));
+            Ok(())
+        }
+    }
+
+    fn clear_breakpoint(&self, method: jmethodID, location: jlocation) -> Result<(), NativeError> {
+        if !self.capabilities.can_generate_breakpoint_events {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        let mut breakpoints = self.breakpoints.lock().unwrap();
+
+        match breakpoints.iter().position(|breakpoint| *breakpoint == (method, location)) {
+            Some(index) => {
+                breakpoints.remove(index);
+                Ok(())
+            }
+            None => Err(NativeError::NotFound),
+        }
+    }
+
+    fn get_class_methods(&self, class: &jclass) -> Result<Vec<jmethodID>, NativeError> {
+        let mut methods: Vec<jmethodID> = self.methods.iter()
+            .filter(|&(_, &(declaring_class, _, _))| declaring_class == *class)
+            .map(|(method, _)| *method)
+            .collect();
+
+        methods.sort();
+        Ok(methods)
+    }
+
+    fn get_class_fields(&self, class: &jclass) -> Result<Vec<jfieldID>, NativeError> {
+        let mut fields: Vec<jfieldID> = self.fields.iter()
+            .filter(|&(_, &(declaring_class, _, _))| declaring_class == *class)
+            .map(|(field, _)| *field)
+            .collect();
+
+        fields.sort();
+        Ok(fields)
+    }
+
+    fn get_field_name(&self, class: &jclass, field: jfieldID) -> Result<(String, String), NativeError> {
+        match self.fields.get(&field) {
+            Some((_, name, signature)) => Ok((name.clone(), signature.clone())),
+            None => Err(NativeError::InvalidFieldId),
+        }
+    }
+
+    fn set_field_access_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        if !self.capabilities.can_generate_field_access_events {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        set_watch(&self.access_watches, *class, field)
+    }
+
+    fn clear_field_access_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        if !self.capabilities.can_generate_field_access_events {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        clear_watch(&self.access_watches, *class, field)
+    }
+
+    fn set_field_modification_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        if !self.capabilities.can_generate_field_modification_events {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        set_watch(&self.modification_watches, *class, field)
+    }
+
+    fn clear_field_modification_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        if !self.capabilities.can_generate_field_modification_events {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        clear_watch(&self.modification_watches, *class, field)
+    }
+
+    fn set_thread_event_notification_mode(&self, event: VMEvent, thread: jthread, enabled: bool) -> Result<(), NativeError> {
+        self.thread_events.lock().unwrap().push((event, thread, enabled));
+        Ok(())
+    }
+
+    fn generate_events(&self, event: VMEvent) -> Result<(), NativeError> {
+        match event {
+            VMEvent::CompiledMethodLoad if !self.capabilities.can_generate_compiled_method_load_events => {
+                Err(NativeError::MustPossessCapability)
+            }
+            VMEvent::CompiledMethodLoad | VMEvent::DynamicCodeGenerated => {
+                self.generated_events.lock().unwrap().push(event);
+                Ok(())
+            }
+            _ => Err(NativeError::IllegalArgument),
+        }
+    }
+
+    fn get_method_modifiers(&self, method: jmethodID) -> Result<jint, NativeError> {
+        Err(NativeError::NotImplemented)
+    }
+
+    fn get_local_variable_table(&self, method: jmethodID) -> Result<Vec<LocalVariableEntry>, NativeError> {
+        match self.local_variable_tables.get(&method) {
+            Some(table) => Ok(table.clone()),
+            None => Err(NativeError::AbsentInformation),
+        }
+    }
+
     fn add_to_bootstrap_classloader_search(&self, class_path: &str) -> Result<(), NativeError> {
         unimplemented!()
     }
@@ -198,6 +646,22 @@ impl JVMTI for JVMEmulator {
         count: crate::native::jvmti_native::jint,
         class: *const crate::native::jvmti_native::jclass,
     ) -> Result<(), NativeError> {
+        let classes = unsafe { std::slice::from_raw_parts(class, count as usize) };
+
+        self.retransformed.lock().unwrap().extend_from_slice(classes);
+        Ok(())
+    }
+
+    fn redefine_classes(&self, definitions: &[jvmtiClassDefinition]) -> Result<(), NativeError> {
+        Ok(())
+    }
+
+    /// Null class references are reported as unmodifiable, every other class is modifiable
+    fn is_modifiable_class(&self, class: &ClassId) -> Result<bool, NativeError> {
+        Ok(!class.native_id.is_null())
+    }
+
+    fn get_class_status(&self, class: &crate::native::jvmti_native::jclass) -> Result<u32, NativeError> {
         todo!()
     }
 
@@ -229,7 +693,7 @@ impl JVMTI for JVMEmulator {
     }
 
     fn get_classloader(&self, klass: &jclass) -> Result<jobject, NativeError> {
-        todo!()
+        Ok(self.class_loaders.get(klass).cloned().unwrap_or(ptr::null_mut()))
     }
 
     fn get_object_size(&self, object: &jobject) -> Result<jlong, NativeError> {
@@ -237,7 +701,7 @@ impl JVMTI for JVMEmulator {
     }
 
     fn get_loaded_classes(&self) -> Result<&[crate::native::jvmti_native::jclass], NativeError> {
-        todo!()
+        Ok(&self.loaded_classes)
     }
 
     fn get_class_loader_classes(
@@ -268,7 +732,10 @@ impl JVMTI for JVMEmulator {
         &self,
         object: &jobject,
     ) -> Result<crate::native::jvmti_native::jint, NativeError> {
-        todo!()
+        match self.hash_codes.get(object) {
+            Some(&hash_code) => Ok(hash_code),
+            None => Err(NativeError::InvalidObject),
+        }
     }
 
     fn follow_references(
@@ -286,3 +753,26 @@ impl JVMTI for JVMEmulator {
         todo!()
     }
 }
+
+fn set_watch(watches: &Mutex<Vec<(jclass, jfieldID)>>, class: jclass, field: jfieldID) -> Result<(), NativeError> {
+    let mut watches = watches.lock().unwrap();
+
+    if watches.contains(&(class, field)) {
+        Err(NativeError::Duplicate)
+    } else {
+        watches.push((class, field));
+        Ok(())
+    }
+}
+
+fn clear_watch(watches: &Mutex<Vec<(jclass, jfieldID)>>, class: jclass, field: jfieldID) -> Result<(), NativeError> {
+    let mut watches = watches.lock().unwrap();
+
+    match watches.iter().position(|watch| *watch == (class, field)) {
+        Some(index) => {
+            watches.remove(index);
+            Ok(())
+        }
+        None => Err(NativeError::NotFound),
+    }
+}
@@ -1,4 +1,5 @@
 use std::ffi::CString;
+use std::os::raw::c_void;
 
 use crate::{
     method::MethodId,
@@ -21,6 +22,23 @@ pub enum JNIError {
     ClassObjectIsNull,
     MethodIsNull,
     FieldIsNull,
+    /// The class couldn't be defined, eg. because it's malformed or already defined by the loader
+    ClassDefinitionFailed(String),
+    /// At least one of the methods isn't a native method of the class
+    NativesNotRegistered(Vec<String>),
+    /// The region doesn't lie within the bounds of the array
+    IndexOutOfBounds(jsize, jsize),
+}
+
+///
+/// A native method implementation to bind with `register_natives`. The function has to follow
+/// the JNI calling convention of the method, eg. `extern "C" fn(JNIEnvPtr, JavaClass, jint)` for
+/// a `static native void m(int)`.
+#[derive(Debug, Clone)]
+pub struct NativeMethod {
+    pub name: String,
+    pub signature: String,
+    pub function: *mut c_void,
 }
 
 impl From<jint> for JavaValue {
@@ -126,6 +144,11 @@ pub trait JNI {
     /// Return an `ClassId` belonging to the given Java object instance.
     fn get_object_class(&self, object: &JavaObject) -> Result<JavaClass, JNIError>;
     fn find_class(&self, clazz: &str) -> Result<ClassId, JNIError>;
+    /// Define a class from class file data. A null `loader` defines the class in the bootstrap
+    /// class loader.
+    fn define_class(&self, name: &str, loader: &JavaObject, data: &[u8]) -> Result<ClassId, JNIError>;
+    /// Bind the implementations of native methods declared by the class
+    fn register_natives(&self, class: &JavaClass, methods: &[NativeMethod]) -> Result<(), JNIError>;
     fn get_method(&self, class: &JavaClass, name: &str, sig: &str) -> Result<MethodId, JNIError>;
     fn get_static_method(
         &self,
@@ -135,6 +158,10 @@ pub trait JNI {
     ) -> Result<MethodId, JNIError>;
     fn get_field_id(&self, class: &JavaClass, name: &str, sig: &str)
         -> Result<JavaField, JNIError>;
+    /// The id of a static field. Fails with `FieldNotFound` if the class doesn't declare or
+    /// inherit it.
+    fn get_static_field_id(&self, class: &JavaClass, name: &str, sig: &str) -> Result<JavaField, JNIError>;
+    fn get_static_object_field(&self, class: &JavaClass, field: &JavaField) -> Result<JavaObject, JNIError>;
     fn new_string_utf(&self, str: &str) -> Result<JavaString, JNIError>;
     fn get_string_utf_chars(&self, string: &JavaString) -> Result<String, JNIError>;
     fn release_string_utf_chars(&self, str: &JavaString, chars: *const i8) -> Result<(), JNIError>;
@@ -146,8 +173,15 @@ pub trait JNI {
     ) -> Result<JavaObject, JNIError>;
     fn new_global_ref(&self, object: &JavaObject) -> Result<JavaObject, JNIError>;
     fn delete_global_ref(&self, object: &JavaObject) -> Result<(), JNIError>;
+    /// A reference that stays valid across native calls and threads, but doesn't keep the object
+    /// from being garbage collected
+    fn new_weak_global_ref(&self, object: &JavaObject) -> Result<JavaObject, JNIError>;
+    /// Whether both references refer to the same object, eg. a local and a global reference
+    fn is_same_object(&self, first: &JavaObject, second: &JavaObject) -> bool;
     fn is_instance_of(&self, object: &JavaObject, class: &JavaClass) -> Result<bool, JNIError>;
     fn is_assignable_from(&self, sub: &JavaClass, sup: &JavaClass) -> Result<bool, JNIError>;
+    /// The superclass of the class, `None` for `java.lang.Object`, interfaces and primitive types
+    fn get_superclass(&self, class: &JavaClass) -> Result<Option<JavaClass>, JNIError>;
     fn call_static_boolean_method(
         &self,
         class: &JavaClass,
@@ -180,11 +214,18 @@ pub trait JNI {
         field: &JavaField,
     ) -> Result<JavaObject, JNIError>;
     fn get_array_length(&self, array: &JavaArray) -> Result<jsize, JNIError>;
+    /// Copy `len` elements of a `long[]`, starting at index `start`
+    fn get_long_array_region(&self, array: &JavaArray, start: jsize, len: jsize) -> Result<Vec<jlong>, JNIError>;
     fn get_object_array_element(
         &self,
         array: &JavaObjectArray,
         index: jsize,
     ) -> Result<JavaObject, JNIError>;
+    /// Clear the pending exception, if any, and return whether there was one. Calls into Java
+    /// code, eg. `call_object_method`, leave the exceptions they throw pending.
+    fn clear_pending_exception(&self) -> bool;
+    /// The VM this environment belongs to, eg. to attach other threads to it
+    fn get_java_vm(&self) -> Option<JavaVMPtr>;
 }
 
 ///
@@ -198,6 +239,12 @@ impl JNIEnvironment {
     pub fn new(jni: JNIEnvPtr) -> JNIEnvironment {
         JNIEnvironment { jni: jni }
     }
+
+    /// Clear the exception a failed call has left pending, so that it isn't thrown into the
+    /// Java code that triggered the agent
+    fn clear_exception(&self) {
+        self.clear_pending_exception();
+    }
 }
 
 impl JNI for JNIEnvironment {
@@ -222,6 +269,63 @@ impl JNI for JNIEnvironment {
         }
     }
 
+    fn define_class(&self, name: &str, loader: &JavaObject, data: &[u8]) -> Result<ClassId, JNIError> {
+        let class_name = CString::new(name).unwrap();
+        unsafe {
+            let class_id = (**self.jni).DefineClass.unwrap()(
+                self.jni,
+                class_name.as_ptr(),
+                *loader,
+                data.as_ptr() as *const jbyte,
+                data.len() as jsize,
+            );
+            if class_id.is_null() {
+                self.clear_exception();
+                Err(JNIError::ClassDefinitionFailed(name.to_string()))
+            } else {
+                Ok(ClassId {
+                    native_id: class_id,
+                })
+            }
+        }
+    }
+
+    fn register_natives(&self, class: &JavaClass, methods: &[NativeMethod]) -> Result<(), JNIError> {
+        if class.is_null() {
+            return Err(JNIError::ClassObjectIsNull);
+        }
+        let names: Vec<(CString, CString)> = methods
+            .iter()
+            .map(|m| (CString::new(m.name.as_str()).unwrap(), CString::new(m.signature.as_str()).unwrap()))
+            .collect();
+        let natives: Vec<JNINativeMethod> = names
+            .iter()
+            .zip(methods.iter())
+            .map(|((name, signature), method)| JNINativeMethod {
+                name: name.as_ptr() as *mut _,
+                signature: signature.as_ptr() as *mut _,
+                fnPtr: method.function,
+            })
+            .collect();
+
+        unsafe {
+            let result = (**self.jni).RegisterNatives.unwrap()(
+                self.jni,
+                *class,
+                natives.as_ptr(),
+                natives.len() as jint,
+            );
+            if result == 0 {
+                Ok(())
+            } else {
+                self.clear_exception();
+                Err(JNIError::NativesNotRegistered(
+                    methods.iter().map(|m| m.name.clone()).collect(),
+                ))
+            }
+        }
+    }
+
     fn get_method(
         &self,
         class: &JavaClass,
@@ -287,6 +391,36 @@ impl JNI for JNIEnvironment {
         Ok(unsafe { (**self.jni).GetObjectField.unwrap()(self.jni, *object, *field) })
     }
 
+    fn get_static_field_id(&self, class: &JavaClass, name: &str, sig: &str) -> Result<JavaField, JNIError> {
+        if class.is_null() {
+            return Err(JNIError::ClassObjectIsNull);
+        }
+        let field_name = CString::new(name).unwrap();
+        let field_sig = CString::new(sig).unwrap();
+
+        let id = unsafe {
+            (**self.jni).GetStaticFieldID.unwrap()(self.jni, *class, field_name.as_ptr(), field_sig.as_ptr())
+        };
+
+        if id.is_null() {
+            self.clear_exception();
+            Err(JNIError::FieldNotFound(name.to_string()))
+        } else {
+            Ok(id)
+        }
+    }
+
+    fn get_static_object_field(&self, class: &JavaClass, field: &JavaField) -> Result<JavaObject, JNIError> {
+        if class.is_null() {
+            return Err(JNIError::ClassObjectIsNull);
+        }
+
+        if field.is_null() {
+            return Err(JNIError::FieldIsNull);
+        }
+        unsafe { Ok((**self.jni).GetStaticObjectField.unwrap()(self.jni, *class, *field)) }
+    }
+
     fn get_static_method(
         &self,
         class: &JavaClass,
@@ -361,6 +495,20 @@ impl JNI for JNIEnvironment {
         unsafe { Ok((**self.jni).IsAssignableFrom.unwrap()(self.jni, *sub, *sup) == 1) }
     }
 
+    fn get_superclass(&self, class: &JavaClass) -> Result<Option<JavaClass>, JNIError> {
+        if class.is_null() {
+            return Err(JNIError::ClassObjectIsNull);
+        }
+
+        let superclass = unsafe { (**self.jni).GetSuperclass.unwrap()(self.jni, *class) };
+
+        if superclass.is_null() {
+            Ok(None)
+        } else {
+            Ok(Some(superclass))
+        }
+    }
+
     fn call_static_boolean_method(
         &self,
         class: &JavaClass,
@@ -487,6 +635,17 @@ impl JNI for JNIEnvironment {
         unsafe { Ok((**self.jni).DeleteGlobalRef.unwrap()(self.jni, *object)) }
     }
 
+    fn new_weak_global_ref(&self, object: &JavaObject) -> Result<JavaObject, JNIError> {
+        if object.is_null() {
+            return Err(JNIError::ObjectIsNull);
+        }
+        Ok(unsafe { (**self.jni).NewWeakGlobalRef.unwrap()(self.jni, *object) })
+    }
+
+    fn is_same_object(&self, first: &JavaObject, second: &JavaObject) -> bool {
+        unsafe { (**self.jni).IsSameObject.unwrap()(self.jni, *first, *second) == TRUE }
+    }
+
     fn get_array_length(&self, array: &JavaArray) -> Result<jsize, JNIError> {
         if array.is_null() {
             return Err(JNIError::ObjectIsNull);
@@ -494,6 +653,24 @@ impl JNI for JNIEnvironment {
         unsafe { Ok((**self.jni).GetArrayLength.unwrap()(self.jni, *array)) }
     }
 
+    fn get_long_array_region(&self, array: &JavaArray, start: jsize, len: jsize) -> Result<Vec<jlong>, JNIError> {
+        if array.is_null() {
+            return Err(JNIError::ObjectIsNull);
+        }
+
+        let mut values: Vec<jlong> = vec![0; len.max(0) as usize];
+
+        unsafe {
+            (**self.jni).GetLongArrayRegion.unwrap()(self.jni, *array, start, len, values.as_mut_ptr());
+        }
+
+        if self.clear_pending_exception() {
+            Err(JNIError::IndexOutOfBounds(start, len))
+        } else {
+            Ok(values)
+        }
+    }
+
     fn get_object_array_element(
         &self,
         array: &JavaObjectArray,
@@ -501,4 +678,27 @@ impl JNI for JNIEnvironment {
     ) -> Result<JavaObject, JNIError> {
         Ok(unsafe { (**self.jni).GetObjectArrayElement.unwrap()(self.jni, *array, index) })
     }
+
+    fn clear_pending_exception(&self) -> bool {
+        unsafe {
+            if (**self.jni).ExceptionCheck.unwrap()(self.jni) == TRUE {
+                (**self.jni).ExceptionClear.unwrap()(self.jni);
+                true
+            } else {
+                false
+            }
+        }
+    }
+
+    fn get_java_vm(&self) -> Option<JavaVMPtr> {
+        let mut vm: JavaVMPtr = std::ptr::null_mut();
+
+        unsafe {
+            if (**self.jni).GetJavaVM.unwrap()(self.jni, &mut vm) == 0 && !vm.is_null() {
+                Some(vm)
+            } else {
+                None
+            }
+        }
+    }
 }
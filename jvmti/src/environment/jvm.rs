@@ -26,6 +26,9 @@ pub trait JVMF {
     fn get_jni_environment(&self) -> Result<Box<dyn JNI>, NativeError>;
     fn destroy(&self) -> Result<(), NativeError>;
     fn attach_current_thread(&self, thread_name: &str) -> Result<Box<dyn JNI>, NativeError>;
+    /// Attach the current thread as a daemon thread, which doesn't keep the VM from exiting
+    fn attach_current_thread_as_daemon(&self, thread_name: &str) -> Result<Box<dyn JNI>, NativeError>;
+    fn detach_current_thread(&self) -> Result<(), NativeError>;
 }
 ///
 /// `JVMAgent` represents a binding to the JVM.
@@ -98,6 +101,42 @@ impl JVMF for JVMAgent {
         }
     }
 
+    fn attach_current_thread_as_daemon(&self, thread_name: &str) -> Result<Box<dyn JNI>, NativeError> {
+        let thread_name = CString::new(thread_name).unwrap();
+        unsafe {
+            let mut env = ptr::null_mut();
+            let mut args: JavaVMAttachArgs = JavaVMAttachArgs {
+                version: JNI_VERSION_1_6,
+                name: thread_name.as_ptr() as *mut _,
+                group: std::ptr::null_mut(),
+            };
+
+            let error = (**self.vm).AttachCurrentThreadAsDaemon.unwrap()(
+                self.vm,
+                &mut env,
+                &mut args as *const _ as *mut _,
+            ) as u32;
+
+            if error == 0 {
+                Ok(Box::new(JNIEnvironment::new(env as JNIEnvPtr)))
+            } else {
+                Err(wrap_error(error))
+            }
+        }
+    }
+
+    fn detach_current_thread(&self) -> Result<(), NativeError> {
+        unsafe {
+            let error = (**self.vm).DetachCurrentThread.unwrap()(self.vm) as u32;
+
+            if error == 0 {
+                Ok(())
+            } else {
+                Err(wrap_error(error))
+            }
+        }
+    }
+
     fn get_jni_environment(&self) -> Result<Box<dyn JNI>, NativeError> {
         unsafe {
             let mut void_ptr: *mut c_void = ptr::null_mut() as *mut c_void;
@@ -1,10 +1,11 @@
 use crate::native::jvmti_native::*;
 
-use super::super::error::{wrap_error, NativeError};
+use super::super::error::{wrap_error, NativeError, RedefineError};
 use super::super::event::{EventCallbacks, VMEvent};
 use super::super::event_handler::*;
+use super::super::stack::StackInfo;
 use super::super::mem::MemoryAllocation;
-use super::super::method::MethodSignature;
+use super::super::method::{LineNumberEntry, LocalValue, LocalVariable, LocalVariableEntry, MethodSignature};
 use super::super::native::jvmti_native::jvmtiCapabilities;
 use super::super::native::{
     JVMTIEnvPtr, JavaClass, JavaInstance, JavaLong, JavaObject, JavaThread, MutByteArray, MutString,
@@ -34,6 +35,8 @@ pub trait JVMTI {
     /// Return the JVM TI version number, which includes major, minor and micro version numbers.
     ///
     fn get_version_number(&self) -> VersionNumber;
+    /// The native environment, `None` if the environment is emulated
+    fn get_env_ptr(&self) -> Option<JVMTIEnvPtr>;
     /// Set new capabilities by adding the capabilities whose values are set to true in new_caps.
     /// All previous capabilities are retained.
     /// Some virtual machines may allow a limited set of capabilities to be added in the live phase.
@@ -63,6 +66,62 @@ pub trait JVMTI {
         depth: jint,
         slot: jint,
     ) -> Result<jobject, NativeError>;
+    /// The value of a local variable of type `int`, `short`, `char`, `byte` or `boolean` in the
+    /// frame at the given depth. Requires `can_access_local_variables`.
+    fn get_local_int(&self, thread: jthread, depth: jint, slot: jint) -> Result<jint, NativeError>;
+    fn get_local_long(&self, thread: jthread, depth: jint, slot: jint) -> Result<jlong, NativeError>;
+    fn get_local_float(&self, thread: jthread, depth: jint, slot: jint) -> Result<jfloat, NativeError>;
+    fn get_local_double(&self, thread: jthread, depth: jint, slot: jint) -> Result<jdouble, NativeError>;
+    /// The `this` object of the frame at the given depth, which fails with `InvalidSlot` for static
+    /// methods. Requires `can_access_local_variables`.
+    fn get_local_instance(&self, thread: jthread, depth: jint) -> Result<jobject, NativeError>;
+    /// Set a local variable of an object type in the frame at the given depth. Requires
+    /// `can_access_local_variables`.
+    fn set_local_object(&self, thread: jthread, depth: jint, slot: jint, value: jobject) -> Result<(), NativeError>;
+    /// Set a local variable of type `int`, `short`, `char`, `byte` or `boolean` in the frame at the
+    /// given depth. Requires `can_access_local_variables`.
+    fn set_local_int(&self, thread: jthread, depth: jint, slot: jint, value: jint) -> Result<(), NativeError>;
+    fn set_local_long(&self, thread: jthread, depth: jint, slot: jint, value: jlong) -> Result<(), NativeError>;
+    fn set_local_float(&self, thread: jthread, depth: jint, slot: jint, value: jfloat) -> Result<(), NativeError>;
+    fn set_local_double(&self, thread: jthread, depth: jint, slot: jint, value: jdouble) -> Result<(), NativeError>;
+    /// The method of the frame at the given depth and the index of the instruction being executed,
+    /// -1 for native methods
+    fn get_frame_location(&self, thread: jthread, depth: jint) -> Result<(jmethodID, jlocation), NativeError>;
+    /// The number of frames on the thread's stack
+    fn get_frame_count(&self, thread: jthread) -> Result<jint, NativeError>;
+    /// Request a `FramePop` event when the frame at the given depth returns or is popped by an
+    /// exception. Requires `can_generate_frame_pop_events`.
+    fn notify_frame_pop(&self, thread: jthread, depth: jint) -> Result<(), NativeError>;
+    /// Set a breakpoint at the instruction, which fails with `Duplicate` if one is already set.
+    /// Requires `can_generate_breakpoint_events`.
+    fn set_breakpoint(&self, method: jmethodID, location: jlocation) -> Result<(), NativeError>;
+    /// Clear a breakpoint, which fails with `NotFound` if none is set at the instruction. Requires
+    /// `can_generate_breakpoint_events`.
+    fn clear_breakpoint(&self, method: jmethodID, location: jlocation) -> Result<(), NativeError>;
+    /// The methods declared by the class, including constructors and static initialisers
+    fn get_class_methods(&self, class: &jclass) -> Result<Vec<jmethodID>, NativeError>;
+    /// The fields declared by the class
+    fn get_class_fields(&self, class: &jclass) -> Result<Vec<jfieldID>, NativeError>;
+    /// The name and JVM type signature of a field, eg. `("count", "I")`
+    fn get_field_name(&self, class: &jclass, field: jfieldID) -> Result<(String, String), NativeError>;
+    /// Send `FieldAccess` events when the field is read. Requires `can_generate_field_access_events`.
+    fn set_field_access_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError>;
+    fn clear_field_access_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError>;
+    /// Send `FieldModification` events when the field is assigned. Requires
+    /// `can_generate_field_modification_events`.
+    fn set_field_modification_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError>;
+    fn clear_field_modification_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError>;
+    /// Enable or disable an event for a single thread, eg. `SingleStep` while a thread is stepping
+    fn set_thread_event_notification_mode(&self, event: VMEvent, thread: jthread, enabled: bool) -> Result<(), NativeError>;
+    /// Send the current state of the VM as events, ie. a `CompiledMethodLoad` event for each
+    /// compiled method or a `DynamicCodeGenerated` event for each stub. Only available in the live
+    /// phase, `CompiledMethodLoad` requires `can_generate_compiled_method_load_events`.
+    fn generate_events(&self, event: VMEvent) -> Result<(), NativeError>;
+    /// The access flags of the method, eg. `ACC_STATIC`
+    fn get_method_modifiers(&self, method: jmethodID) -> Result<jint, NativeError>;
+    /// The local variable table of the method. Fails with `AbsentInformation` if the class was
+    /// compiled without it. Requires `can_access_local_variables`.
+    fn get_local_variable_table(&self, method: jmethodID) -> Result<Vec<LocalVariableEntry>, NativeError>;
     fn run_agent_thread(
         &self,
         thread: jthread,
@@ -72,13 +131,193 @@ pub trait JVMTI {
     ) -> Result<(), NativeError>;
     fn get_current_thread(&self) -> Result<jthread, NativeError>;
     fn get_stack_trace(&self, thread: jthread) -> Result<&[jvmtiFrameInfo], NativeError>;
+    /// At most `max_frame_count` frames of the thread's stack, starting at `start_depth`. A
+    /// negative start depth counts from the bottom of the stack, eg. -1 is the oldest frame.
+    fn get_stack_trace_range(
+        &self,
+        thread: jthread,
+        start_depth: jint,
+        max_frame_count: jint,
+    ) -> Result<Vec<jvmtiFrameInfo>, NativeError>;
+    /// The line number table of the method. Fails with `AbsentInformation` if the class was
+    /// compiled without it and with `NativeMethod` for native methods. Requires
+    /// `can_get_line_numbers`.
+    fn get_line_number_table(&self, method: jmethodID) -> Result<Vec<LineNumberEntry>, NativeError>;
+    /// The name of the class' source file, eg. `Orders.java`. Requires `can_get_source_file_name`.
+    fn get_source_file_name(&self, class: &jclass) -> Result<String, NativeError>;
+    /// The stacks of all live threads, with at most `max_frame_count` frames each. The threads are
+    /// returned as local references.
+    fn get_all_stack_traces(&self, max_frame_count: jint) -> Result<Vec<StackInfo>, NativeError>;
+    /// The stacks of the given threads, in the same order, with at most `max_frame_count` frames each
+    fn get_thread_list_stack_traces(
+        &self,
+        threads: &[jthread],
+        max_frame_count: jint,
+    ) -> Result<Vec<StackInfo>, NativeError>;
+    /// The monitors owned by the thread and the depth of the frame that locked them, -1 for
+    /// monitors locked through JNI. Requires `can_get_owned_monitor_stack_depth_info`.
+    fn get_owned_monitor_stack_depth_info(
+        &self,
+        thread: jthread,
+    ) -> Result<Vec<jvmtiMonitorStackDepthInfo>, NativeError>;
+    /// The monitor the thread is waiting to enter or, within `Object.wait`, to regain. Requires
+    /// `can_get_current_contended_monitor`.
+    fn get_current_contended_monitor(&self, thread: jthread) -> Result<Option<jobject>, NativeError>;
     fn get_thread_state(&self, thread: jthread) -> Result<u32, NativeError>;
+    /// Suspend the thread, which may be the calling thread. Requires `can_suspend`.
+    fn suspend_thread(&self, thread: jthread) -> Result<(), NativeError>;
+    /// Suspend the threads, returning the result for each of them in the same order. Requires
+    /// `can_suspend`.
+    fn suspend_thread_list(&self, threads: &[jthread]) -> Result<Vec<Result<(), NativeError>>, NativeError>;
+    /// Resume a suspended thread. Requires `can_suspend`.
+    fn resume_thread(&self, thread: jthread) -> Result<(), NativeError>;
+    /// Resume the threads, returning the result for each of them in the same order. Requires
+    /// `can_suspend`.
+    fn resume_thread_list(&self, threads: &[jthread]) -> Result<Vec<Result<(), NativeError>>, NativeError>;
+    /// Interrupt the thread, like `Thread.interrupt`. Requires `can_signal_thread`.
+    fn interrupt_thread(&self, thread: jthread) -> Result<(), NativeError>;
+    /// Asynchronously throw the exception in the thread, like `Thread.stop`. Requires
+    /// `can_signal_thread`.
+    fn stop_thread(&self, thread: jthread, exception: jobject) -> Result<(), NativeError>;
     fn add_to_bootstrap_classloader_search(&self, class_path: &str) -> Result<(), NativeError>;
     fn raw_monitor_enter(&self, monitor: &jrawMonitorID) -> Result<(), NativeError>;
     fn raw_monitor_exit(&self, monitor: jrawMonitorID) -> Result<(), NativeError>;
     fn create_raw_monitor(&self, name: &str) -> Result<jrawMonitorID, NativeError>;
     fn destroy_raw_monitor(&self, monitor: jrawMonitorID) -> Result<(), NativeError>;
     fn retransform_classes(&self, count: jint, class: *const jclass) -> Result<(), NativeError>;
+    fn redefine_classes(&self, definitions: &[jvmtiClassDefinition]) -> Result<(), NativeError>;
+    /// Whether the class can be retransformed or redefined. Primitive and array classes never are,
+    /// other classes may depend on `can_retransform_any_class` and `can_redefine_any_class`.
+    fn is_modifiable_class(&self, class: &ClassId) -> Result<bool, NativeError>;
+    /// The status bits of the class, eg. `JVMTI_CLASS_STATUS_INITIALIZED`
+    fn get_class_status(&self, class: &jclass) -> Result<u32, NativeError>;
+
+    ///
+    /// Retransform the given classes, which runs the class file load hook with their current class
+    /// file. Either all classes are retransformed or none of them.
+    fn retransform(&self, classes: &[ClassId]) -> Result<(), RedefineError> {
+        if !self.get_capabilities().can_retransform_classes {
+            return Err(RedefineError::MissingCapability);
+        }
+
+        check_modifiable(self, classes.iter())?;
+
+        if classes.is_empty() {
+            return Ok(());
+        }
+
+        let natives: Vec<jclass> = classes.iter().map(|class| class.native_id).collect();
+        Ok(self.retransform_classes(natives.len() as jint, natives.as_ptr())?)
+    }
+
+    ///
+    /// Replace the definitions of the given classes. Either all classes are redefined or none of
+    /// them. Changes other than to method bodies fail with a `NativeError` for which
+    /// `is_unsupported_redefinition` is true.
+    fn redefine(&self, definitions: &[(ClassId, Vec<u8>)]) -> Result<(), RedefineError> {
+        if !self.get_capabilities().can_redefine_classes {
+            return Err(RedefineError::MissingCapability);
+        }
+
+        check_modifiable(self, definitions.iter().map(|(class, _)| class))?;
+
+        if definitions.is_empty() {
+            return Ok(());
+        }
+
+        let natives: Vec<jvmtiClassDefinition> = definitions
+            .iter()
+            .map(|(class, data)| jvmtiClassDefinition {
+                klass: class.native_id,
+                class_byte_count: data.len() as jint,
+                class_bytes: data.as_ptr(),
+            })
+            .collect();
+
+        Ok(self.redefine_classes(&natives)?)
+    }
+
+    ///
+    /// Read a local variable of the frame at the given depth with the `GetLocal*` function that
+    /// matches its type. Objects are returned as local references.
+    fn get_local(&self, thread: jthread, depth: jint, slot: jint, java_type: &JavaType) -> Result<LocalValue, NativeError> {
+        if !self.get_capabilities().can_access_local_variables {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        match *java_type {
+            JavaType::Long => self.get_local_long(thread, depth, slot).map(LocalValue::Long),
+            JavaType::Float => self.get_local_float(thread, depth, slot).map(LocalValue::Float),
+            JavaType::Double => self.get_local_double(thread, depth, slot).map(LocalValue::Double),
+            JavaType::Class(_) | JavaType::Array(_) => self.get_local_object(thread, depth, slot).map(LocalValue::Object),
+            JavaType::Void => Err(NativeError::TypeMismatch),
+            _ => self.get_local_int(thread, depth, slot).map(LocalValue::Int),
+        }
+    }
+
+    ///
+    /// Assign a local variable of the frame at the given depth with the `SetLocal*` function that
+    /// matches the value
+    fn set_local(&self, thread: jthread, depth: jint, slot: jint, value: &LocalValue) -> Result<(), NativeError> {
+        if !self.get_capabilities().can_access_local_variables {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        match *value {
+            LocalValue::Int(value) => self.set_local_int(thread, depth, slot, value),
+            LocalValue::Long(value) => self.set_local_long(thread, depth, slot, value),
+            LocalValue::Float(value) => self.set_local_float(thread, depth, slot, value),
+            LocalValue::Double(value) => self.set_local_double(thread, depth, slot, value),
+            LocalValue::Object(value) => self.set_local_object(thread, depth, slot, value),
+        }
+    }
+
+    ///
+    /// The local variables that are live at the current location of the frame at the given depth,
+    /// ordered by slot, with their names and types from the method's local variable table.
+    /// Fails with `AbsentInformation` if the class was compiled without the table, and with
+    /// `OpaqueFrame` for native frames.
+    fn get_frame_locals(&self, thread: jthread, depth: jint) -> Result<Vec<LocalVariable>, NativeError> {
+        if !self.get_capabilities().can_access_local_variables {
+            return Err(NativeError::MustPossessCapability);
+        }
+
+        let (method, location) = self.get_frame_location(thread, depth)?;
+
+        if location < 0 {
+            return Err(NativeError::OpaqueFrame);
+        }
+
+        let mut entries: Vec<LocalVariableEntry> = self
+            .get_local_variable_table(method)?
+            .into_iter()
+            .filter(|entry| entry.is_live_at(location))
+            .collect();
+        entries.sort_by_key(|entry| entry.slot);
+
+        let mut locals = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            let value = match JavaType::parse(&entry.signature) {
+                Some(ref java_type) => match self.get_local(thread, depth, entry.slot, java_type) {
+                    Ok(value) => Some(value),
+                    // The VM may not be able to read a variable, eg. one that's dead in compiled code
+                    Err(NativeError::InvalidSlot) | Err(NativeError::TypeMismatch) => None,
+                    Err(err) => return Err(err),
+                },
+                None => None,
+            };
+
+            locals.push(LocalVariable {
+                name: entry.name,
+                signature: entry.signature,
+                slot: entry.slot,
+                value,
+            });
+        }
+
+        Ok(locals)
+    }
     fn iterate_over_heap(
         &self,
         object_filter: jvmtiHeapObjectFilter,
@@ -120,6 +359,27 @@ pub trait JVMTI {
     );
 }
 
+/// Fails with the positions of the classes that can't be modified
+fn check_modifiable<'a, T, I>(jvmti: &T, classes: I) -> Result<(), RedefineError>
+where
+    T: JVMTI + ?Sized,
+    I: Iterator<Item = &'a ClassId>,
+{
+    let mut unmodifiable = vec![];
+
+    for (position, class) in classes.enumerate() {
+        if !jvmti.is_modifiable_class(class)? {
+            unmodifiable.push(position);
+        }
+    }
+
+    if unmodifiable.is_empty() {
+        Ok(())
+    } else {
+        Err(RedefineError::Unmodifiable(unmodifiable))
+    }
+}
+
 pub struct JVMTIEnvironment {
     jvmti: JVMTIEnvPtr,
 }
@@ -131,6 +391,10 @@ impl JVMTIEnvironment {
 }
 
 impl JVMTI for JVMTIEnvironment {
+    fn get_env_ptr(&self) -> Option<JVMTIEnvPtr> {
+        Some(self.jvmti)
+    }
+
     fn get_version_number(&self) -> VersionNumber {
         unsafe {
             let mut version: i32 = 0;
@@ -190,6 +454,15 @@ impl JVMTI for JVMTIEnvironment {
         register_garbage_collection_start(callbacks.garbage_collection_start);
         register_garbage_collection_finish(callbacks.garbage_collection_finish);
         register_class_file_load_hook(callbacks.class_file_load_hook);
+        register_data_dump_request_callback(callbacks.data_dump_request);
+        register_breakpoint_callback(callbacks.breakpoint);
+        register_single_step_callback(callbacks.single_step);
+        register_frame_pop_callback(callbacks.frame_pop);
+        register_class_load_callback(callbacks.class_load);
+        register_class_prepare_callback(callbacks.class_prepare);
+        register_compiled_method_load_callback(callbacks.compiled_method_load);
+        register_compiled_method_unload_callback(callbacks.compiled_method_unload);
+        register_dynamic_code_generated_callback(callbacks.dynamic_code_generated);
 
         let (native_callbacks, callbacks_size) = registered_callbacks();
 
@@ -249,6 +522,72 @@ impl JVMTI for JVMTIEnvironment {
         }
     }
 
+    fn suspend_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SuspendThread.unwrap()(self.jvmti, thread)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn suspend_thread_list(&self, threads: &[jthread]) -> Result<Vec<Result<(), NativeError>>, NativeError> {
+        let mut results: Vec<jvmtiError> = vec![0; threads.len()];
+        unsafe {
+            match wrap_error((**self.jvmti).SuspendThreadList.unwrap()(
+                self.jvmti,
+                threads.len() as jint,
+                threads.as_ptr(),
+                results.as_mut_ptr(),
+            )) {
+                NativeError::NoError => Ok(results.into_iter().map(thread_result).collect()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn resume_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).ResumeThread.unwrap()(self.jvmti, thread)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn resume_thread_list(&self, threads: &[jthread]) -> Result<Vec<Result<(), NativeError>>, NativeError> {
+        let mut results: Vec<jvmtiError> = vec![0; threads.len()];
+        unsafe {
+            match wrap_error((**self.jvmti).ResumeThreadList.unwrap()(
+                self.jvmti,
+                threads.len() as jint,
+                threads.as_ptr(),
+                results.as_mut_ptr(),
+            )) {
+                NativeError::NoError => Ok(results.into_iter().map(thread_result).collect()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn interrupt_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).InterruptThread.unwrap()(self.jvmti, thread)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn stop_thread(&self, thread: jthread, exception: jobject) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).StopThread.unwrap()(self.jvmti, thread, exception)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
     fn get_method_declaring_class(&self, method: &jmethodID) -> Result<ClassId, NativeError> {
         let mut jstruct: JavaInstance = JavaInstance {
             _hacky_hack_workaround: 0,
@@ -394,6 +733,161 @@ impl JVMTI for JVMTIEnvironment {
         }
     }
 
+    fn get_stack_trace_range(
+        &self,
+        thread: jthread,
+        start_depth: jint,
+        max_frame_count: jint,
+    ) -> Result<Vec<jvmtiFrameInfo>, NativeError> {
+        let mut count: jint = 0;
+        let mut frames = vec![jvmtiFrameInfo::default(); max_frame_count.max(0) as usize];
+
+        unsafe {
+            match wrap_error((**self.jvmti).GetStackTrace.unwrap()(
+                self.jvmti,
+                thread,
+                start_depth,
+                max_frame_count,
+                frames.as_mut_ptr(),
+                &mut count,
+            )) {
+                NativeError::NoError => {
+                    frames.truncate(count as usize);
+                    Ok(frames)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_line_number_table(&self, method: jmethodID) -> Result<Vec<LineNumberEntry>, NativeError> {
+        let mut count: jint = 0;
+        let mut table: *mut jvmtiLineNumberEntry = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetLineNumberTable.unwrap()(
+                self.jvmti,
+                method,
+                &mut count,
+                &mut table,
+            )) {
+                NativeError::NoError if table.is_null() => Ok(vec![]),
+                NativeError::NoError => {
+                    let entries = std::slice::from_raw_parts(table, count as usize)
+                        .iter()
+                        .map(|entry| LineNumberEntry {
+                            start_location: entry.start_location,
+                            line_number: entry.line_number,
+                        })
+                        .collect();
+
+                    (**self.jvmti).Deallocate.unwrap()(self.jvmti, table as _);
+                    Ok(entries)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_source_file_name(&self, class: &jclass) -> Result<String, NativeError> {
+        let mut name: MutString = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetSourceFileName.unwrap()(
+                self.jvmti, *class, &mut name,
+            )) {
+                NativeError::NoError => {
+                    let source_file = stringify(name);
+                    (**self.jvmti).Deallocate.unwrap()(self.jvmti, name as _);
+                    Ok(source_file)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_all_stack_traces(&self, max_frame_count: jint) -> Result<Vec<StackInfo>, NativeError> {
+        let mut count: jint = 0;
+        let mut stacks: *mut jvmtiStackInfo = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetAllStackTraces.unwrap()(
+                self.jvmti,
+                max_frame_count,
+                &mut stacks,
+                &mut count,
+            )) {
+                NativeError::NoError => {
+                    let result = copy_stack_info(stacks, count);
+                    (**self.jvmti).Deallocate.unwrap()(self.jvmti, stacks as _);
+                    Ok(result)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_thread_list_stack_traces(
+        &self,
+        threads: &[jthread],
+        max_frame_count: jint,
+    ) -> Result<Vec<StackInfo>, NativeError> {
+        let mut stacks: *mut jvmtiStackInfo = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetThreadListStackTraces.unwrap()(
+                self.jvmti,
+                threads.len() as jint,
+                threads.as_ptr(),
+                max_frame_count,
+                &mut stacks,
+            )) {
+                NativeError::NoError => {
+                    let result = copy_stack_info(stacks, threads.len() as jint);
+                    (**self.jvmti).Deallocate.unwrap()(self.jvmti, stacks as _);
+                    Ok(result)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_owned_monitor_stack_depth_info(
+        &self,
+        thread: jthread,
+    ) -> Result<Vec<jvmtiMonitorStackDepthInfo>, NativeError> {
+        let mut count: jint = 0;
+        let mut monitors: *mut jvmtiMonitorStackDepthInfo = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetOwnedMonitorStackDepthInfo.unwrap()(
+                self.jvmti,
+                thread,
+                &mut count,
+                &mut monitors,
+            )) {
+                NativeError::NoError => {
+                    if monitors.is_null() {
+                        return Ok(vec![]);
+                    }
+
+                    let result = std::slice::from_raw_parts(monitors, count as usize).to_vec();
+                    (**self.jvmti).Deallocate.unwrap()(self.jvmti, monitors as _);
+                    Ok(result)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_current_contended_monitor(&self, thread: jthread) -> Result<Option<jobject>, NativeError> {
+        let mut monitor: jobject = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetCurrentContendedMonitor.unwrap()(
+                self.jvmti, thread, &mut monitor,
+            )) {
+                NativeError::NoError if monitor.is_null() => Ok(None),
+                NativeError::NoError => Ok(Some(monitor)),
+                err => Err(err),
+            }
+        }
+    }
+
     fn get_local_object(
         &self,
         thread: jthread,
@@ -411,6 +905,366 @@ impl JVMTI for JVMTIEnvironment {
         }
     }
 
+    fn get_local_int(&self, thread: jthread, depth: jint, slot: jint) -> Result<jint, NativeError> {
+        let mut value: jint = Default::default();
+        unsafe {
+            match wrap_error((**self.jvmti).GetLocalInt.unwrap()(
+                self.jvmti, thread, depth, slot, &mut value,
+            )) {
+                NativeError::NoError => Ok(value),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_local_long(&self, thread: jthread, depth: jint, slot: jint) -> Result<jlong, NativeError> {
+        let mut value: jlong = Default::default();
+        unsafe {
+            match wrap_error((**self.jvmti).GetLocalLong.unwrap()(
+                self.jvmti, thread, depth, slot, &mut value,
+            )) {
+                NativeError::NoError => Ok(value),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_local_float(&self, thread: jthread, depth: jint, slot: jint) -> Result<jfloat, NativeError> {
+        let mut value: jfloat = Default::default();
+        unsafe {
+            match wrap_error((**self.jvmti).GetLocalFloat.unwrap()(
+                self.jvmti, thread, depth, slot, &mut value,
+            )) {
+                NativeError::NoError => Ok(value),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_local_double(&self, thread: jthread, depth: jint, slot: jint) -> Result<jdouble, NativeError> {
+        let mut value: jdouble = Default::default();
+        unsafe {
+            match wrap_error((**self.jvmti).GetLocalDouble.unwrap()(
+                self.jvmti, thread, depth, slot, &mut value,
+            )) {
+                NativeError::NoError => Ok(value),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_local_instance(&self, thread: jthread, depth: jint) -> Result<jobject, NativeError> {
+        let mut value: jobject = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetLocalInstance.unwrap()(
+                self.jvmti, thread, depth, &mut value,
+            )) {
+                NativeError::NoError => Ok(value),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_local_object(&self, thread: jthread, depth: jint, slot: jint, value: jobject) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SetLocalObject.unwrap()(
+                self.jvmti, thread, depth, slot, value,
+            )) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_local_int(&self, thread: jthread, depth: jint, slot: jint, value: jint) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SetLocalInt.unwrap()(
+                self.jvmti, thread, depth, slot, value,
+            )) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_local_long(&self, thread: jthread, depth: jint, slot: jint, value: jlong) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SetLocalLong.unwrap()(
+                self.jvmti, thread, depth, slot, value,
+            )) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_local_float(&self, thread: jthread, depth: jint, slot: jint, value: jfloat) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SetLocalFloat.unwrap()(
+                self.jvmti, thread, depth, slot, value,
+            )) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_local_double(&self, thread: jthread, depth: jint, slot: jint, value: jdouble) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SetLocalDouble.unwrap()(
+                self.jvmti, thread, depth, slot, value,
+            )) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_frame_location(&self, thread: jthread, depth: jint) -> Result<(jmethodID, jlocation), NativeError> {
+        let mut method: jmethodID = ptr::null_mut();
+        let mut location: jlocation = 0;
+        unsafe {
+            match wrap_error((**self.jvmti).GetFrameLocation.unwrap()(
+                self.jvmti, thread, depth, &mut method, &mut location,
+            )) {
+                NativeError::NoError => Ok((method, location)),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_frame_count(&self, thread: jthread) -> Result<jint, NativeError> {
+        let mut count: jint = 0;
+        unsafe {
+            match wrap_error((**self.jvmti).GetFrameCount.unwrap()(self.jvmti, thread, &mut count)) {
+                NativeError::NoError => Ok(count),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn notify_frame_pop(&self, thread: jthread, depth: jint) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).NotifyFramePop.unwrap()(self.jvmti, thread, depth)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_breakpoint(&self, method: jmethodID, location: jlocation) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SetBreakpoint.unwrap()(self.jvmti, method, location)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn clear_breakpoint(&self, method: jmethodID, location: jlocation) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).ClearBreakpoint.unwrap()(self.jvmti, method, location)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_class_methods(&self, class: &jclass) -> Result<Vec<jmethodID>, NativeError> {
+        let mut count: jint = 0;
+        let mut methods: *mut jmethodID = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetClassMethods.unwrap()(
+                self.jvmti,
+                *class,
+                &mut count,
+                &mut methods,
+            )) {
+                NativeError::NoError => {
+                    if methods.is_null() {
+                        return Ok(vec![]);
+                    }
+
+                    let result = std::slice::from_raw_parts(methods, count as usize).to_vec();
+                    (**self.jvmti).Deallocate.unwrap()(self.jvmti, methods as _);
+                    Ok(result)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_class_fields(&self, class: &jclass) -> Result<Vec<jfieldID>, NativeError> {
+        let mut count: jint = 0;
+        let mut fields: *mut jfieldID = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetClassFields.unwrap()(
+                self.jvmti,
+                *class,
+                &mut count,
+                &mut fields,
+            )) {
+                NativeError::NoError => {
+                    if fields.is_null() {
+                        return Ok(vec![]);
+                    }
+
+                    let result = std::slice::from_raw_parts(fields, count as usize).to_vec();
+                    (**self.jvmti).Deallocate.unwrap()(self.jvmti, fields as _);
+                    Ok(result)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_field_name(&self, class: &jclass, field: jfieldID) -> Result<(String, String), NativeError> {
+        let mut name: MutString = ptr::null_mut();
+        let mut signature: MutString = ptr::null_mut();
+        let mut generic: MutString = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetFieldName.unwrap()(
+                self.jvmti,
+                *class,
+                field,
+                &mut name,
+                &mut signature,
+                &mut generic,
+            )) {
+                NativeError::NoError => {
+                    let result = (stringify(name), stringify(signature));
+
+                    for ptr in [name, signature, generic].iter() {
+                        if !ptr.is_null() {
+                            (**self.jvmti).Deallocate.unwrap()(self.jvmti, *ptr as _);
+                        }
+                    }
+
+                    Ok(result)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_field_access_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SetFieldAccessWatch.unwrap()(self.jvmti, *class, field)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn clear_field_access_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).ClearFieldAccessWatch.unwrap()(self.jvmti, *class, field)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_field_modification_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).SetFieldModificationWatch.unwrap()(self.jvmti, *class, field)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn clear_field_modification_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).ClearFieldModificationWatch.unwrap()(self.jvmti, *class, field)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn set_thread_event_notification_mode(&self, event: VMEvent, thread: jthread, enabled: bool) -> Result<(), NativeError> {
+        let mode = if enabled { JVMTI_ENABLE } else { JVMTI_DISABLE };
+        unsafe {
+            match wrap_error((**self.jvmti).SetEventNotificationMode.unwrap()(
+                self.jvmti,
+                mode,
+                event as u32,
+                thread,
+            )) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn generate_events(&self, event: VMEvent) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).GenerateEvents.unwrap()(self.jvmti, event as u32)) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_method_modifiers(&self, method: jmethodID) -> Result<jint, NativeError> {
+        let mut modifiers: jint = 0;
+        unsafe {
+            match wrap_error((**self.jvmti).GetMethodModifiers.unwrap()(
+                self.jvmti,
+                method,
+                &mut modifiers,
+            )) {
+                NativeError::NoError => Ok(modifiers),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_local_variable_table(&self, method: jmethodID) -> Result<Vec<LocalVariableEntry>, NativeError> {
+        let mut count: jint = 0;
+        let mut table: *mut jvmtiLocalVariableEntry = ptr::null_mut();
+        unsafe {
+            match wrap_error((**self.jvmti).GetLocalVariableTable.unwrap()(
+                self.jvmti,
+                method,
+                &mut count,
+                &mut table,
+            )) {
+                NativeError::NoError => {
+                    let mut entries = vec![];
+
+                    if table.is_null() {
+                        return Ok(entries);
+                    }
+
+                    for entry in std::slice::from_raw_parts(table, count as usize) {
+                        entries.push(LocalVariableEntry {
+                            start_location: entry.start_location,
+                            length: entry.length,
+                            name: stringify(entry.name),
+                            signature: stringify(entry.signature),
+                            generic_signature: if entry.generic_signature.is_null() {
+                                None
+                            } else {
+                                Some(stringify(entry.generic_signature))
+                            },
+                            slot: entry.slot,
+                        });
+
+                        (**self.jvmti).Deallocate.unwrap()(self.jvmti, entry.name as _);
+                        (**self.jvmti).Deallocate.unwrap()(self.jvmti, entry.signature as _);
+                        (**self.jvmti).Deallocate.unwrap()(self.jvmti, entry.generic_signature as _);
+                    }
+
+                    (**self.jvmti).Deallocate.unwrap()(self.jvmti, table as _);
+                    Ok(entries)
+                }
+                err => Err(err),
+            }
+        }
+    }
+
     fn add_to_bootstrap_classloader_search(&self, class_path: &str) -> Result<(), NativeError> {
         let path = CString::new(class_path).unwrap();
         unsafe {
@@ -482,6 +1336,47 @@ impl JVMTI for JVMTIEnvironment {
         }
     }
 
+    fn redefine_classes(&self, definitions: &[jvmtiClassDefinition]) -> Result<(), NativeError> {
+        unsafe {
+            match wrap_error((**self.jvmti).RedefineClasses.unwrap()(
+                self.jvmti,
+                definitions.len() as jint,
+                definitions.as_ptr(),
+            )) {
+                NativeError::NoError => Ok(()),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn is_modifiable_class(&self, class: &ClassId) -> Result<bool, NativeError> {
+        let mut is_modifiable: jboolean = FALSE;
+        unsafe {
+            match wrap_error((**self.jvmti).IsModifiableClass.unwrap()(
+                self.jvmti,
+                class.native_id,
+                &mut is_modifiable,
+            )) {
+                NativeError::NoError => Ok(is_modifiable == TRUE),
+                err => Err(err),
+            }
+        }
+    }
+
+    fn get_class_status(&self, class: &jclass) -> Result<u32, NativeError> {
+        let mut status: jint = 0;
+        unsafe {
+            match wrap_error((**self.jvmti).GetClassStatus.unwrap()(
+                self.jvmti,
+                *class,
+                &mut status,
+            )) {
+                NativeError::NoError => Ok(status as u32),
+                err => Err(err),
+            }
+        }
+    }
+
     fn iterate_over_heap(
         &self,
         object_filter: jvmtiHeapObjectFilter,
@@ -712,3 +1607,31 @@ impl JVMTI for JVMTIEnvironment {
         }
     }
 }
+
+/// Copy the stacks out of a buffer allocated by `GetAllStackTraces` or `GetThreadListStackTraces`
+unsafe fn copy_stack_info(stacks: *const jvmtiStackInfo, count: jint) -> Vec<StackInfo> {
+    if stacks.is_null() {
+        return vec![];
+    }
+
+    std::slice::from_raw_parts(stacks, count as usize)
+        .iter()
+        .map(|stack| StackInfo {
+            thread: stack.thread,
+            state: stack.state as u32,
+            frames: if stack.frame_buffer.is_null() {
+                vec![]
+            } else {
+                std::slice::from_raw_parts(stack.frame_buffer, stack.frame_count as usize).to_vec()
+            },
+        })
+        .collect()
+}
+
+/// The result of a thread in a `SuspendThreadList` or `ResumeThreadList` call
+fn thread_result(code: jvmtiError) -> Result<(), NativeError> {
+    match wrap_error(code) {
+        NativeError::NoError => Ok(()),
+        err => Err(err),
+    }
+}
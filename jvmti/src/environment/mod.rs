@@ -1,9 +1,9 @@
-use crate::native::{jvmti_native::*, JavaClass, JavaMethod, JavaObjectArray};
+use crate::native::{jvmti_native::*, JVMTIEnvPtr, JavaClass, JavaMethod, JavaObjectArray};
 use std::os::raw::c_void;
 
 use self::jvmti::{JVMTIEnvironment, JVMTI};
 use self::{
-    jni::{JNIEnvironment, JNIError, JNI},
+    jni::{JNIEnvironment, JNIError, NativeMethod, JNI},
     jvmti::JVMTIError,
 };
 use super::capabilities::Capabilities;
@@ -11,8 +11,9 @@ use super::class::{ClassId, ClassSignature};
 use super::error::NativeError;
 use super::event::{EventCallbacks, VMEvent};
 use super::mem::MemoryAllocation;
-use super::method::{MethodId, MethodSignature};
-use super::native::JavaObject;
+use super::method::{LineNumberEntry, LocalVariableEntry, MethodId, MethodSignature};
+use super::native::{JavaObject, JavaVMPtr};
+use super::stack::StackInfo;
 use super::version::VersionNumber;
 
 pub mod jni;
@@ -43,6 +44,10 @@ impl Environment {
 }
 
 impl JVMTI for Environment {
+    fn get_env_ptr(&self) -> Option<JVMTIEnvPtr> {
+        self.jvmti.get_env_ptr()
+    }
+
     fn get_version_number(&self) -> VersionNumber {
         self.jvmti.get_version_number()
     }
@@ -108,6 +113,46 @@ impl JVMTI for Environment {
         self.jvmti.get_stack_trace(thread)
     }
 
+    fn get_stack_trace_range(
+        &self,
+        thread: jthread,
+        start_depth: jint,
+        max_frame_count: jint,
+    ) -> Result<Vec<jvmtiFrameInfo>, NativeError> {
+        self.jvmti.get_stack_trace_range(thread, start_depth, max_frame_count)
+    }
+
+    fn get_line_number_table(&self, method: jmethodID) -> Result<Vec<LineNumberEntry>, NativeError> {
+        self.jvmti.get_line_number_table(method)
+    }
+
+    fn get_source_file_name(&self, class: &jclass) -> Result<String, NativeError> {
+        self.jvmti.get_source_file_name(class)
+    }
+
+    fn get_all_stack_traces(&self, max_frame_count: jint) -> Result<Vec<StackInfo>, NativeError> {
+        self.jvmti.get_all_stack_traces(max_frame_count)
+    }
+
+    fn get_thread_list_stack_traces(
+        &self,
+        threads: &[jthread],
+        max_frame_count: jint,
+    ) -> Result<Vec<StackInfo>, NativeError> {
+        self.jvmti.get_thread_list_stack_traces(threads, max_frame_count)
+    }
+
+    fn get_owned_monitor_stack_depth_info(
+        &self,
+        thread: jthread,
+    ) -> Result<Vec<jvmtiMonitorStackDepthInfo>, NativeError> {
+        self.jvmti.get_owned_monitor_stack_depth_info(thread)
+    }
+
+    fn get_current_contended_monitor(&self, thread: jthread) -> Result<Option<jobject>, NativeError> {
+        self.jvmti.get_current_contended_monitor(thread)
+    }
+
     fn get_local_object(
         &self,
         thread: jthread,
@@ -121,6 +166,134 @@ impl JVMTI for Environment {
         self.jvmti.get_thread_state(thread)
     }
 
+    fn suspend_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        self.jvmti.suspend_thread(thread)
+    }
+
+    fn suspend_thread_list(&self, threads: &[jthread]) -> Result<Vec<Result<(), NativeError>>, NativeError> {
+        self.jvmti.suspend_thread_list(threads)
+    }
+
+    fn resume_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        self.jvmti.resume_thread(thread)
+    }
+
+    fn resume_thread_list(&self, threads: &[jthread]) -> Result<Vec<Result<(), NativeError>>, NativeError> {
+        self.jvmti.resume_thread_list(threads)
+    }
+
+    fn interrupt_thread(&self, thread: jthread) -> Result<(), NativeError> {
+        self.jvmti.interrupt_thread(thread)
+    }
+
+    fn stop_thread(&self, thread: jthread, exception: jobject) -> Result<(), NativeError> {
+        self.jvmti.stop_thread(thread, exception)
+    }
+
+    fn get_local_int(&self, thread: jthread, depth: jint, slot: jint) -> Result<jint, NativeError> {
+        self.jvmti.get_local_int(thread, depth, slot)
+    }
+
+    fn get_local_long(&self, thread: jthread, depth: jint, slot: jint) -> Result<jlong, NativeError> {
+        self.jvmti.get_local_long(thread, depth, slot)
+    }
+
+    fn get_local_float(&self, thread: jthread, depth: jint, slot: jint) -> Result<jfloat, NativeError> {
+        self.jvmti.get_local_float(thread, depth, slot)
+    }
+
+    fn get_local_double(&self, thread: jthread, depth: jint, slot: jint) -> Result<jdouble, NativeError> {
+        self.jvmti.get_local_double(thread, depth, slot)
+    }
+
+    fn get_local_instance(&self, thread: jthread, depth: jint) -> Result<jobject, NativeError> {
+        self.jvmti.get_local_instance(thread, depth)
+    }
+
+    fn set_local_object(&self, thread: jthread, depth: jint, slot: jint, value: jobject) -> Result<(), NativeError> {
+        self.jvmti.set_local_object(thread, depth, slot, value)
+    }
+
+    fn set_local_int(&self, thread: jthread, depth: jint, slot: jint, value: jint) -> Result<(), NativeError> {
+        self.jvmti.set_local_int(thread, depth, slot, value)
+    }
+
+    fn set_local_long(&self, thread: jthread, depth: jint, slot: jint, value: jlong) -> Result<(), NativeError> {
+        self.jvmti.set_local_long(thread, depth, slot, value)
+    }
+
+    fn set_local_float(&self, thread: jthread, depth: jint, slot: jint, value: jfloat) -> Result<(), NativeError> {
+        self.jvmti.set_local_float(thread, depth, slot, value)
+    }
+
+    fn set_local_double(&self, thread: jthread, depth: jint, slot: jint, value: jdouble) -> Result<(), NativeError> {
+        self.jvmti.set_local_double(thread, depth, slot, value)
+    }
+
+    fn get_frame_location(&self, thread: jthread, depth: jint) -> Result<(jmethodID, jlocation), NativeError> {
+        self.jvmti.get_frame_location(thread, depth)
+    }
+
+    fn get_frame_count(&self, thread: jthread) -> Result<jint, NativeError> {
+        self.jvmti.get_frame_count(thread)
+    }
+
+    fn notify_frame_pop(&self, thread: jthread, depth: jint) -> Result<(), NativeError> {
+        self.jvmti.notify_frame_pop(thread, depth)
+    }
+
+    fn set_breakpoint(&self, method: jmethodID, location: jlocation) -> Result<(), NativeError> {
+        self.jvmti.set_breakpoint(method, location)
+    }
+
+    fn clear_breakpoint(&self, method: jmethodID, location: jlocation) -> Result<(), NativeError> {
+        self.jvmti.clear_breakpoint(method, location)
+    }
+
+    fn get_class_methods(&self, class: &jclass) -> Result<Vec<jmethodID>, NativeError> {
+        self.jvmti.get_class_methods(class)
+    }
+
+    fn get_class_fields(&self, class: &jclass) -> Result<Vec<jfieldID>, NativeError> {
+        self.jvmti.get_class_fields(class)
+    }
+
+    fn get_field_name(&self, class: &jclass, field: jfieldID) -> Result<(String, String), NativeError> {
+        self.jvmti.get_field_name(class, field)
+    }
+
+    fn set_field_access_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        self.jvmti.set_field_access_watch(class, field)
+    }
+
+    fn clear_field_access_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        self.jvmti.clear_field_access_watch(class, field)
+    }
+
+    fn set_field_modification_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        self.jvmti.set_field_modification_watch(class, field)
+    }
+
+    fn clear_field_modification_watch(&self, class: &jclass, field: jfieldID) -> Result<(), NativeError> {
+        self.jvmti.clear_field_modification_watch(class, field)
+    }
+
+    fn set_thread_event_notification_mode(&self, event: VMEvent, thread: jthread, enabled: bool) -> Result<(), NativeError> {
+        self.jvmti.set_thread_event_notification_mode(event, thread, enabled)
+    }
+
+    fn generate_events(&self, event: VMEvent) -> Result<(), NativeError> {
+        self.jvmti.generate_events(event)
+    }
+
+    fn get_method_modifiers(&self, method: jmethodID) -> Result<jint, NativeError> {
+        self.jvmti.get_method_modifiers(method)
+    }
+
+    fn get_local_variable_table(&self, method: jmethodID) -> Result<Vec<LocalVariableEntry>, NativeError> {
+        self.jvmti.get_local_variable_table(method)
+    }
+
     fn add_to_bootstrap_classloader_search(&self, class_path: &str) -> Result<(), NativeError> {
         self.jvmti.add_to_bootstrap_classloader_search(class_path)
     }
@@ -145,6 +318,18 @@ impl JVMTI for Environment {
         self.jvmti.retransform_classes(count, class)
     }
 
+    fn redefine_classes(&self, definitions: &[jvmtiClassDefinition]) -> Result<(), NativeError> {
+        self.jvmti.redefine_classes(definitions)
+    }
+
+    fn is_modifiable_class(&self, class: &ClassId) -> Result<bool, NativeError> {
+        self.jvmti.is_modifiable_class(class)
+    }
+
+    fn get_class_status(&self, class: &jclass) -> Result<u32, NativeError> {
+        self.jvmti.get_class_status(class)
+    }
+
     fn iterate_over_instances_of_class(
         &self,
         klass: &jclass,
@@ -237,6 +422,14 @@ impl JVMTI for Environment {
 }
 
 impl JNI for Environment {
+    fn define_class(&self, name: &str, loader: &JavaObject, data: &[u8]) -> Result<ClassId, JNIError> {
+        self.jni.define_class(name, loader, data)
+    }
+
+    fn register_natives(&self, class: &JavaClass, methods: &[NativeMethod]) -> Result<(), JNIError> {
+        self.jni.register_natives(class, methods)
+    }
+
     fn get_object_class(&self, object_id: &JavaObject) -> Result<JavaClass, JNIError> {
         self.jni.get_object_class(object_id)
     }
@@ -280,6 +473,14 @@ impl JNI for Environment {
         self.jni.get_static_method(class, name, sig)
     }
 
+    fn get_static_field_id(&self, class: &jclass, name: &str, sig: &str) -> Result<jfieldID, JNIError> {
+        self.jni.get_static_field_id(class, name, sig)
+    }
+
+    fn get_static_object_field(&self, class: &jclass, field: &jfieldID) -> Result<JavaObject, JNIError> {
+        self.jni.get_static_object_field(class, field)
+    }
+
     fn new_string_utf(&self, str: &str) -> Result<jstring, JNIError> {
         self.jni.new_string_utf(str)
     }
@@ -288,6 +489,10 @@ impl JNI for Environment {
         self.jni.is_assignable_from(sub, sup)
     }
 
+    fn get_superclass(&self, class: &jclass) -> Result<Option<jclass>, JNIError> {
+        self.jni.get_superclass(class)
+    }
+
     fn call_static_object_method(
         &self,
         class: &jclass,
@@ -347,10 +552,22 @@ impl JNI for Environment {
         self.jni.delete_global_ref(object)
     }
 
+    fn new_weak_global_ref(&self, object: &JavaObject) -> Result<JavaObject, JNIError> {
+        self.jni.new_weak_global_ref(object)
+    }
+
+    fn is_same_object(&self, first: &JavaObject, second: &JavaObject) -> bool {
+        self.jni.is_same_object(first, second)
+    }
+
     fn get_array_length(&self, array: &jarray) -> Result<jsize, JNIError> {
         self.jni.get_array_length(array)
     }
 
+    fn get_long_array_region(&self, array: &jarray, start: jsize, len: jsize) -> Result<Vec<jlong>, JNIError> {
+        self.jni.get_long_array_region(array, start, len)
+    }
+
     fn get_object_array_element(
         &self,
         array: &JavaObjectArray,
@@ -358,4 +575,12 @@ impl JNI for Environment {
     ) -> Result<JavaObject, JNIError> {
         self.jni.get_object_array_element(array, index)
     }
+
+    fn clear_pending_exception(&self) -> bool {
+        self.jni.clear_pending_exception()
+    }
+
+    fn get_java_vm(&self) -> Option<JavaVMPtr> {
+        self.jni.get_java_vm()
+    }
 }
@@ -1,6 +1,36 @@
 const JVMTI_ERROR_INVALID_MONITOR: u32 = 50;
 const JVMTI_ERROR_NOT_MONITOR_OWNER: u32 = 51;
 const JVMTI_ERROR_ILLEGAL_ARGUMENT: u32 = 103;
+const JVMTI_ERROR_INVALID_THREAD: u32 = 10;
+const JVMTI_ERROR_INVALID_OBJECT: u32 = 20;
+const JVMTI_ERROR_THREAD_NOT_SUSPENDED: u32 = 13;
+const JVMTI_ERROR_THREAD_SUSPENDED: u32 = 14;
+const JVMTI_ERROR_INVALID_CLASS: u32 = 21;
+const JVMTI_ERROR_INVALID_CLASS_FORMAT: u32 = 60;
+const JVMTI_ERROR_CIRCULAR_CLASS_DEFINITION: u32 = 61;
+const JVMTI_ERROR_FAILS_VERIFICATION: u32 = 62;
+const JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_ADDED: u32 = 63;
+const JVMTI_ERROR_UNSUPPORTED_REDEFINITION_SCHEMA_CHANGED: u32 = 64;
+const JVMTI_ERROR_INVALID_TYPESTATE: u32 = 65;
+const JVMTI_ERROR_UNSUPPORTED_REDEFINITION_HIERARCHY_CHANGED: u32 = 66;
+const JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_DELETED: u32 = 67;
+const JVMTI_ERROR_UNSUPPORTED_VERSION: u32 = 68;
+const JVMTI_ERROR_NAMES_DONT_MATCH: u32 = 69;
+const JVMTI_ERROR_UNSUPPORTED_REDEFINITION_CLASS_MODIFIERS_CHANGED: u32 = 70;
+const JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_MODIFIERS_CHANGED: u32 = 71;
+const JVMTI_ERROR_UNSUPPORTED_REDEFINITION_CLASS_ATTRIBUTE_CHANGED: u32 = 72;
+const JVMTI_ERROR_UNMODIFIABLE_CLASS: u32 = 79;
+const JVMTI_ERROR_INVALID_METHODID: u32 = 23;
+const JVMTI_ERROR_NATIVE_METHOD: u32 = 104;
+const JVMTI_ERROR_INVALID_LOCATION: u32 = 24;
+const JVMTI_ERROR_INVALID_FIELDID: u32 = 25;
+const JVMTI_ERROR_DUPLICATE: u32 = 40;
+const JVMTI_ERROR_NOT_FOUND: u32 = 41;
+const JVMTI_ERROR_NO_MORE_FRAMES: u32 = 31;
+const JVMTI_ERROR_OPAQUE_FRAME: u32 = 32;
+const JVMTI_ERROR_TYPE_MISMATCH: u32 = 34;
+const JVMTI_ERROR_INVALID_SLOT: u32 = 35;
+const JVMTI_ERROR_ABSENT_INFORMATION: u32 = 101;
 
 /// A type-safe representation of possible errors
 #[derive(Debug)]
@@ -20,6 +50,82 @@ pub enum NativeError {
     InvalidMonitor = JVMTI_ERROR_INVALID_MONITOR as isize,
     NotMonitorOwner = JVMTI_ERROR_NOT_MONITOR_OWNER as isize,
     IllegalArgument = JVMTI_ERROR_ILLEGAL_ARGUMENT as isize,
+    InvalidThread = JVMTI_ERROR_INVALID_THREAD as isize,
+    InvalidObject = JVMTI_ERROR_INVALID_OBJECT as isize,
+    ThreadNotSuspended = JVMTI_ERROR_THREAD_NOT_SUSPENDED as isize,
+    ThreadSuspended = JVMTI_ERROR_THREAD_SUSPENDED as isize,
+    InvalidClass = JVMTI_ERROR_INVALID_CLASS as isize,
+    InvalidClassFormat = JVMTI_ERROR_INVALID_CLASS_FORMAT as isize,
+    CircularClassDefinition = JVMTI_ERROR_CIRCULAR_CLASS_DEFINITION as isize,
+    FailsVerification = JVMTI_ERROR_FAILS_VERIFICATION as isize,
+    MethodAdded = JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_ADDED as isize,
+    SchemaChanged = JVMTI_ERROR_UNSUPPORTED_REDEFINITION_SCHEMA_CHANGED as isize,
+    InvalidTypestate = JVMTI_ERROR_INVALID_TYPESTATE as isize,
+    HierarchyChanged = JVMTI_ERROR_UNSUPPORTED_REDEFINITION_HIERARCHY_CHANGED as isize,
+    MethodDeleted = JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_DELETED as isize,
+    UnsupportedVersion = JVMTI_ERROR_UNSUPPORTED_VERSION as isize,
+    NamesDontMatch = JVMTI_ERROR_NAMES_DONT_MATCH as isize,
+    ClassModifiersChanged = JVMTI_ERROR_UNSUPPORTED_REDEFINITION_CLASS_MODIFIERS_CHANGED as isize,
+    MethodModifiersChanged = JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_MODIFIERS_CHANGED as isize,
+    ClassAttributeChanged = JVMTI_ERROR_UNSUPPORTED_REDEFINITION_CLASS_ATTRIBUTE_CHANGED as isize,
+    UnmodifiableClass = JVMTI_ERROR_UNMODIFIABLE_CLASS as isize,
+    InvalidMethodId = JVMTI_ERROR_INVALID_METHODID as isize,
+    NativeMethod = JVMTI_ERROR_NATIVE_METHOD as isize,
+    InvalidLocation = JVMTI_ERROR_INVALID_LOCATION as isize,
+    InvalidFieldId = JVMTI_ERROR_INVALID_FIELDID as isize,
+    Duplicate = JVMTI_ERROR_DUPLICATE as isize,
+    NotFound = JVMTI_ERROR_NOT_FOUND as isize,
+    NoMoreFrames = JVMTI_ERROR_NO_MORE_FRAMES as isize,
+    OpaqueFrame = JVMTI_ERROR_OPAQUE_FRAME as isize,
+    TypeMismatch = JVMTI_ERROR_TYPE_MISMATCH as isize,
+    InvalidSlot = JVMTI_ERROR_INVALID_SLOT as isize,
+    AbsentInformation = JVMTI_ERROR_ABSENT_INFORMATION as isize,
+}
+
+impl NativeError {
+    /// Returns true if the error rejects a redefinition or retransformation because the new class
+    /// file changes more than method bodies
+    pub fn is_unsupported_redefinition(&self) -> bool {
+        matches!(
+            *self,
+            NativeError::MethodAdded
+                | NativeError::SchemaChanged
+                | NativeError::HierarchyChanged
+                | NativeError::MethodDeleted
+                | NativeError::ClassModifiersChanged
+                | NativeError::MethodModifiersChanged
+                | NativeError::ClassAttributeChanged
+        )
+    }
+}
+
+///
+/// Describes why classes couldn't be retransformed or redefined
+#[derive(Debug)]
+pub enum RedefineError {
+    /// The environment doesn't possess `can_retransform_classes` or `can_redefine_classes`
+    MissingCapability,
+    /// Positions of the classes that `IsModifiableClass` reported as unmodifiable. None of the
+    /// classes were changed.
+    Unmodifiable(Vec<usize>),
+    /// The VM has rejected the request, eg. with `NativeError::SchemaChanged`
+    Failed(NativeError),
+}
+
+impl From<NativeError> for RedefineError {
+    fn from(err: NativeError) -> RedefineError {
+        RedefineError::Failed(err)
+    }
+}
+
+impl std::fmt::Display for RedefineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            RedefineError::MissingCapability => write!(f, "{}", translate_error(&NativeError::MustPossessCapability)),
+            RedefineError::Unmodifiable(ref classes) => write!(f, "Unmodifiable classes at positions {:?}", classes),
+            RedefineError::Failed(ref err) => write!(f, "{}", translate_error(err)),
+        }
+    }
 }
 
 /// Turn a native error code into a type-safe error
@@ -39,6 +145,36 @@ pub fn wrap_error(code: u32) -> NativeError {
         JVMTI_ERROR_INVALID_MONITOR => NativeError::InvalidMonitor,
         JVMTI_ERROR_NOT_MONITOR_OWNER => NativeError::NotMonitorOwner,
         JVMTI_ERROR_ILLEGAL_ARGUMENT => NativeError::IllegalArgument,
+        JVMTI_ERROR_INVALID_THREAD => NativeError::InvalidThread,
+        JVMTI_ERROR_INVALID_OBJECT => NativeError::InvalidObject,
+        JVMTI_ERROR_THREAD_NOT_SUSPENDED => NativeError::ThreadNotSuspended,
+        JVMTI_ERROR_THREAD_SUSPENDED => NativeError::ThreadSuspended,
+        JVMTI_ERROR_INVALID_CLASS => NativeError::InvalidClass,
+        JVMTI_ERROR_INVALID_CLASS_FORMAT => NativeError::InvalidClassFormat,
+        JVMTI_ERROR_CIRCULAR_CLASS_DEFINITION => NativeError::CircularClassDefinition,
+        JVMTI_ERROR_FAILS_VERIFICATION => NativeError::FailsVerification,
+        JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_ADDED => NativeError::MethodAdded,
+        JVMTI_ERROR_UNSUPPORTED_REDEFINITION_SCHEMA_CHANGED => NativeError::SchemaChanged,
+        JVMTI_ERROR_INVALID_TYPESTATE => NativeError::InvalidTypestate,
+        JVMTI_ERROR_UNSUPPORTED_REDEFINITION_HIERARCHY_CHANGED => NativeError::HierarchyChanged,
+        JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_DELETED => NativeError::MethodDeleted,
+        JVMTI_ERROR_UNSUPPORTED_VERSION => NativeError::UnsupportedVersion,
+        JVMTI_ERROR_NAMES_DONT_MATCH => NativeError::NamesDontMatch,
+        JVMTI_ERROR_UNSUPPORTED_REDEFINITION_CLASS_MODIFIERS_CHANGED => NativeError::ClassModifiersChanged,
+        JVMTI_ERROR_UNSUPPORTED_REDEFINITION_METHOD_MODIFIERS_CHANGED => NativeError::MethodModifiersChanged,
+        JVMTI_ERROR_UNSUPPORTED_REDEFINITION_CLASS_ATTRIBUTE_CHANGED => NativeError::ClassAttributeChanged,
+        JVMTI_ERROR_UNMODIFIABLE_CLASS => NativeError::UnmodifiableClass,
+        JVMTI_ERROR_INVALID_METHODID => NativeError::InvalidMethodId,
+        JVMTI_ERROR_NATIVE_METHOD => NativeError::NativeMethod,
+        JVMTI_ERROR_INVALID_LOCATION => NativeError::InvalidLocation,
+        JVMTI_ERROR_INVALID_FIELDID => NativeError::InvalidFieldId,
+        JVMTI_ERROR_DUPLICATE => NativeError::Duplicate,
+        JVMTI_ERROR_NOT_FOUND => NativeError::NotFound,
+        JVMTI_ERROR_NO_MORE_FRAMES => NativeError::NoMoreFrames,
+        JVMTI_ERROR_OPAQUE_FRAME => NativeError::OpaqueFrame,
+        JVMTI_ERROR_TYPE_MISMATCH => NativeError::TypeMismatch,
+        JVMTI_ERROR_INVALID_SLOT => NativeError::InvalidSlot,
+        JVMTI_ERROR_ABSENT_INFORMATION => NativeError::AbsentInformation,
         _ => {
             eprintln!("Unknown error code was detected: {}", code);
             NativeError::UnknownError
@@ -70,6 +206,36 @@ pub fn translate_error(code: &NativeError) -> String {
         &NativeError::InvalidMonitor => "Invalid raw monitor.",
         &NativeError::NotMonitorOwner => "This thread doesn't own the raw monitor.",
         &NativeError::IllegalArgument => "Illegal argument.",
+        &NativeError::InvalidThread => "Invalid thread.",
+        &NativeError::InvalidObject => "Invalid object.",
+        &NativeError::ThreadNotSuspended => "The thread was not suspended.",
+        &NativeError::ThreadSuspended => "The thread is already suspended.",
+        &NativeError::InvalidClass => "Invalid class.",
+        &NativeError::InvalidClassFormat => "A new class file is malformed.",
+        &NativeError::CircularClassDefinition => "The new class file definitions would lead to a circular definition.",
+        &NativeError::FailsVerification => "The class bytes fail verification.",
+        &NativeError::MethodAdded => "A new class file would require adding a method.",
+        &NativeError::SchemaChanged => "A new class version changes fields.",
+        &NativeError::InvalidTypestate => "The state of the thread has been modified, and is now inconsistent.",
+        &NativeError::HierarchyChanged => "A direct superclass is different for the new class version, or the set of directly implemented interfaces is different.",
+        &NativeError::MethodDeleted => "A new class version does not declare a method declared in the old class version.",
+        &NativeError::UnsupportedVersion => "A new class file has a version number not supported by this VM.",
+        &NativeError::NamesDontMatch => "The class name defined in the new class file is different from the name in the old class object.",
+        &NativeError::ClassModifiersChanged => "A new class version has different modifiers.",
+        &NativeError::MethodModifiersChanged => "A method in the new class version has different modifiers than its counterpart in the old class version.",
+        &NativeError::ClassAttributeChanged => "A new class version has unsupported differences in class attributes.",
+        &NativeError::UnmodifiableClass => "The class cannot be modified.",
+        &NativeError::InvalidMethodId => "Invalid method.",
+        &NativeError::NativeMethod => "The method is a native method.",
+        &NativeError::InvalidLocation => "Invalid location.",
+        &NativeError::InvalidFieldId => "Invalid field.",
+        &NativeError::Duplicate => "Item already set.",
+        &NativeError::NotFound => "Desired element (e.g. field or breakpoint) not found.",
+        &NativeError::NoMoreFrames => "There are no Java programming language or JNI stack frames at the specified depth.",
+        &NativeError::OpaqueFrame => "Information about the frame is not available (e.g. for native frames).",
+        &NativeError::TypeMismatch => "The variable is not an appropriate type for the function used.",
+        &NativeError::InvalidSlot => "Invalid slot.",
+        &NativeError::AbsentInformation => "The class does not have the requested debug information, eg. a local variable table.",
 
     }.to_string()
 }
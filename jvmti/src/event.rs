@@ -1,5 +1,5 @@
 use crate::{
-    environment::Environment,
+    environment::{jvmti::JVMTIEnvironment, Environment},
     native::{JNIEnvPtr, JVMTIEnvPtr},
 };
 
@@ -8,6 +8,7 @@ use super::runtime::*;
 use super::thread::Thread;
 
 pub type FnMethodEntry = fn(env: Environment, event: MethodInvocationEvent) -> ();
+/// The value is the returned value, or null if the frame was popped by an exception
 pub type FnMethodExit =
     fn(env: Environment, thread: jthread, event: MethodInvocationEvent, value: *const jvalue) -> ();
 pub type FnVMInit = fn(jvmti: JVMTIEnvPtr, jni: JNIEnvPtr, jthread) -> ();
@@ -17,35 +18,36 @@ pub type FnVMObjectAlloc = fn(event: ObjectAllocationEvent) -> ();
 pub type FnVMObjectFree = fn() -> ();
 pub type FnThreadStart = fn(thread: Thread) -> ();
 pub type FnThreadEnd = fn(thread: Thread) -> ();
-pub type FnException = fn() -> ();
-pub type FnExceptionCatch = fn() -> ();
+pub type FnException = fn(env: Environment, event: ExceptionEvent) -> ();
+pub type FnExceptionCatch = fn(env: Environment, event: ExceptionCatchEvent) -> ();
 pub type FnMonitorWait = fn(thread: Thread) -> ();
 pub type FnMonitorWaited = fn(thread: Thread) -> ();
 pub type FnMonitorContendedEnter = fn(thread: Thread) -> ();
 pub type FnMonitorContendedEntered = fn(thread: Thread) -> ();
-pub type FnFieldAccess = fn() -> ();
-pub type FnFieldModification = fn() -> ();
+pub type FnFieldAccess = fn(env: Environment, event: FieldAccessEvent) -> ();
+pub type FnFieldModification = fn(env: Environment, event: FieldModificationEvent) -> ();
 pub type FnGarbageCollectionStart = fn() -> ();
 pub type FnGarbageCollectionFinish = fn() -> ();
 pub type FnClassFileLoad = fn(event: ClassFileLoadEvent) -> Option<Vec<u8>>;
-pub type FnClassLoad = fn() -> ();
-pub type FnClassPrepare = fn() -> ();
-pub type FnSingleStep = fn() -> ();
-pub type FnFramePop = fn() -> ();
-pub type FnBreakpoint = fn() -> ();
+pub type FnClassLoad = fn(env: Environment, event: ClassLoadEvent) -> ();
+pub type FnClassPrepare = fn(env: Environment, event: ClassPrepareEvent) -> ();
+pub type FnSingleStep = fn(env: Environment, event: LocationEvent) -> ();
+pub type FnFramePop = fn(env: Environment, event: FramePopEvent) -> ();
+pub type FnBreakpoint = fn(env: Environment, event: LocationEvent) -> ();
 pub type FnNativeMethodBind = fn() -> ();
-pub type FnCompiledMethodLoad = fn() -> ();
-pub type FnCompiledMethodUnload = fn() -> ();
-pub type FnDynamicCodeGenerated = fn() -> ();
+pub type FnCompiledMethodLoad = fn(jvmti: JVMTIEnvironment, event: CompiledMethodLoadEvent) -> ();
+pub type FnCompiledMethodUnload = fn(jvmti: JVMTIEnvironment, event: CompiledMethodUnloadEvent) -> ();
+pub type FnDynamicCodeGenerated = fn(jvmti: JVMTIEnvironment, event: DynamicCodeGeneratedEvent) -> ();
 pub type FnResourceExhausted = fn() -> ();
-pub type FnDataDumpRequest = fn() -> ();
+/// Sent when the VM is asked to dump its state, eg. on `SIGQUIT`
+pub type FnDataDumpRequest = fn(jvmti: JVMTIEnvPtr) -> ();
 
 ///
 /// `VMEvent` represents events that can occur in JVM applications. These events can be handled
 /// using event handlers. For each event a corresponding handler will be called.
 ///
 #[allow(dead_code)]
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub enum VMEvent {
     VMInit = JVMTI_EVENT_VM_INIT as isize,
     VMDeath = JVMTI_EVENT_VM_DEATH as isize,
@@ -1,13 +1,18 @@
 use super::bytecode::*;
+use super::context::static_context;
+use super::debug::{self, line_at, CodeLocation};
 use super::environment::jni::{JNIEnvironment, JNI};
 use super::environment::jvmti::{JVMTIEnvironment, JVMTI};
 use super::environment::Environment;
 use super::error::{translate_error, NativeError};
 use super::event::*;
+use super::exception::{exception_catch_event, exception_event};
 use super::method::MethodId;
+use super::perf::{compiled_method_load_event, inlined_frames};
 use super::native::jvmti_native::*;
 use super::native::*;
 use super::runtime::*;
+use super::stack::binary_name;
 use super::util::stringify;
 use libc::{c_char, c_uchar, c_void};
 use std::io::Cursor;
@@ -168,6 +173,60 @@ pub fn register_class_file_load_hook(callback: Option<FnClassFileLoad>) {
     }
 }
 
+pub fn register_breakpoint_callback(callback: Option<FnBreakpoint>) {
+    unsafe {
+        CALLBACK_TABLE.breakpoint = callback;
+    }
+}
+
+pub fn register_single_step_callback(callback: Option<FnSingleStep>) {
+    unsafe {
+        CALLBACK_TABLE.single_step = callback;
+    }
+}
+
+pub fn register_frame_pop_callback(callback: Option<FnFramePop>) {
+    unsafe {
+        CALLBACK_TABLE.frame_pop = callback;
+    }
+}
+
+pub fn register_class_load_callback(callback: Option<FnClassLoad>) {
+    unsafe {
+        CALLBACK_TABLE.class_load = callback;
+    }
+}
+
+pub fn register_class_prepare_callback(callback: Option<FnClassPrepare>) {
+    unsafe {
+        CALLBACK_TABLE.class_prepare = callback;
+    }
+}
+
+pub fn register_compiled_method_load_callback(callback: Option<FnCompiledMethodLoad>) {
+    unsafe {
+        CALLBACK_TABLE.compiled_method_load = callback;
+    }
+}
+
+pub fn register_compiled_method_unload_callback(callback: Option<FnCompiledMethodUnload>) {
+    unsafe {
+        CALLBACK_TABLE.compiled_method_unload = callback;
+    }
+}
+
+pub fn register_dynamic_code_generated_callback(callback: Option<FnDynamicCodeGenerated>) {
+    unsafe {
+        CALLBACK_TABLE.dynamic_code_generated = callback;
+    }
+}
+
+pub fn register_data_dump_request_callback(callback: Option<FnDataDumpRequest>) {
+    unsafe {
+        CALLBACK_TABLE.data_dump_request = callback;
+    }
+}
+
 pub fn registered_callbacks() -> (jvmtiEventCallbacks, i32) {
     (
         local_event_callbacks(),
@@ -331,7 +390,11 @@ unsafe extern "C" fn local_cb_method_exit(
                             class_sig: class_sig,
                             thread: new_thread,
                         },
-                        &return_value,
+                        if was_popped_by_exception == 0 {
+                            &return_value
+                        } else {
+                            ptr::null()
+                        },
                     )
                 }
                 Err(err) => {
@@ -360,14 +423,28 @@ unsafe extern "C" fn local_cb_exception(
 ) -> () {
     match CALLBACK_TABLE.exception {
         Some(function) => {
-            function();
             let env = Environment::new(
                 JVMTIEnvironment::new(jvmti_env),
                 JNIEnvironment::new(jni_env),
             );
-            let exception_class = env.get_object_class(&exception);
-
-            function()
+            let catch = if catch_method.is_null() {
+                None
+            } else {
+                Some(CodeLocation { method: catch_method, location: catch_location })
+            };
+            match exception_event(
+                &env,
+                &env,
+                &static_context().exceptions,
+                thread,
+                exception,
+                CodeLocation { method, location },
+                catch,
+            ) {
+                Ok(Some(event)) => function(env, event),
+                Ok(None) => (),
+                Err(err) => println!("Couldn't resolve exception: {}", translate_error(&err)),
+            }
         }
         None => println!("No dynamic callback method was found for exception"),
     }
@@ -384,13 +461,23 @@ unsafe extern "C" fn local_cb_exception_catch(
 ) -> () {
     match CALLBACK_TABLE.exception_catch {
         Some(function) => {
-            function();
-            /*
-            let env = Environment::new(JVMTIEnvironment::new(jvmti_env), JNIEnvironment::new(jni_env));
-            let current_thread = env.get_thread_info(&thread).ok().unwrap();
-
-            function()
-            */
+            let env = Environment::new(
+                JVMTIEnvironment::new(jvmti_env),
+                JNIEnvironment::new(jni_env),
+            );
+            match exception_catch_event(
+                &env,
+                &env,
+                &static_context().exceptions,
+                thread,
+                method,
+                location,
+                exception,
+            ) {
+                Ok(Some(event)) => function(env, event),
+                Ok(None) => (),
+                Err(err) => println!("Couldn't resolve caught exception: {}", translate_error(&err)),
+            }
         }
         None => println!("No dynamic callback method was found for exception catch"),
     }
@@ -595,6 +682,27 @@ unsafe extern "C" fn local_cb_breakpoint(
     method: jmethodID,
     location: jlocation,
 ) -> () {
+    match CALLBACK_TABLE.breakpoint {
+        Some(function) => {
+            let env = Environment::new(
+                JVMTIEnvironment::new(jvmti_env),
+                JNIEnvironment::new(jni_env),
+            );
+            match line_at(&env, method, location) {
+                Ok(line) => function(
+                    env,
+                    LocationEvent {
+                        thread,
+                        method,
+                        location,
+                        line,
+                    },
+                ),
+                Err(err) => println!("Couldn't resolve breakpoint line: {}", translate_error(&err)),
+            }
+        }
+        None => println!("No dynamic callback method was found for breakpoint events"),
+    }
 }
 
 #[allow(unused_variables)]
@@ -684,6 +792,19 @@ unsafe extern "C" fn local_cb_class_load(
     thread: jthread,
     klass: jclass,
 ) -> () {
+    match CALLBACK_TABLE.class_load {
+        Some(function) => {
+            let env = Environment::new(
+                JVMTIEnvironment::new(jvmti_env),
+                JNIEnvironment::new(jni_env),
+            );
+            match ClassLoadEvent::resolve(&env, thread, klass) {
+                Ok(event) => function(env, event),
+                Err(err) => println!("Couldn't resolve loaded class: {}", translate_error(&err)),
+            }
+        }
+        None => println!("No dynamic callback method was found for class load events"),
+    }
 }
 
 #[allow(unused_variables)]
@@ -693,9 +814,21 @@ unsafe extern "C" fn local_cb_class_prepare(
     thread: jthread,
     klass: jclass,
 ) -> () {
+    match CALLBACK_TABLE.class_prepare {
+        Some(function) => {
+            let env = Environment::new(
+                JVMTIEnvironment::new(jvmti_env),
+                JNIEnvironment::new(jni_env),
+            );
+            match ClassPrepareEvent::resolve(&env, thread, klass) {
+                Ok(event) => function(env, event),
+                Err(err) => println!("Couldn't resolve prepared class: {}", translate_error(&err)),
+            }
+        }
+        None => println!("No dynamic callback method was found for class prepare events"),
+    }
 }
 
-#[allow(unused_variables)]
 unsafe extern "C" fn local_cb_compiled_method_load(
     jvmti_env: *mut jvmtiEnv,
     method: jmethodID,
@@ -705,26 +838,81 @@ unsafe extern "C" fn local_cb_compiled_method_load(
     map: *const jvmtiAddrLocationMap,
     compile_info: *const c_void,
 ) -> () {
+    let jvmti = JVMTIEnvironment::new(jvmti_env);
+    let map = if map.is_null() || map_length <= 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(map, map_length as usize)
+    };
+
+    let code = if code_addr.is_null() || code_size <= 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(code_addr as *const u8, code_size as usize)
+    };
+
+    match compiled_method_load_event(&jvmti, method, code_addr as usize, code.len(), map, &inlined_frames(compile_info)) {
+        Ok(event) => {
+
+            if let Err(err) = static_context().perf.compiled_method(&event, code) {
+                println!("Couldn't write compiled method: {}", err);
+            }
+
+            if let Some(function) = CALLBACK_TABLE.compiled_method_load {
+                function(jvmti, event);
+            }
+        }
+        Err(err) => println!("Couldn't resolve compiled method: {}", translate_error(&err)),
+    }
 }
 
-#[allow(unused_variables)]
 unsafe extern "C" fn local_cb_compiled_method_unload(
     jvmti_env: *mut jvmtiEnv,
     method: jmethodID,
     code_addr: *const c_void,
 ) -> () {
+    match CALLBACK_TABLE.compiled_method_unload {
+        Some(function) => function(
+            JVMTIEnvironment::new(jvmti_env),
+            CompiledMethodUnloadEvent { method, code_address: code_addr as usize },
+        ),
+        None => println!("No dynamic callback method was found for compiled method unload events"),
+    }
 }
 
-#[allow(unused_variables)]
-unsafe extern "C" fn local_cb_data_dump_request(jvmti_env: *mut jvmtiEnv) -> () {}
+unsafe extern "C" fn local_cb_data_dump_request(jvmti_env: *mut jvmtiEnv) {
+    match CALLBACK_TABLE.data_dump_request {
+        Some(function) => {
+            function(jvmti_env);
+        }
+        None => println!("No dynamic callback method was found for data dump requests"),
+    }
+}
 
-#[allow(unused_variables)]
 unsafe extern "C" fn local_cb_dynamic_code_generated(
     jvmti_env: *mut jvmtiEnv,
     name: *const c_char,
     address: *const c_void,
     length: jint,
 ) -> () {
+    let code = if address.is_null() || length <= 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(address as *const u8, length as usize)
+    };
+    let event = DynamicCodeGeneratedEvent {
+        name: stringify(name),
+        code_address: address as usize,
+        code_size: code.len(),
+    };
+
+    if let Err(err) = static_context().perf.dynamic_code(&event, code) {
+        println!("Couldn't write generated code: {}", err);
+    }
+
+    if let Some(function) = CALLBACK_TABLE.dynamic_code_generated {
+        function(JVMTIEnvironment::new(jvmti_env), event);
+    }
 }
 
 #[allow(unused_variables)]
@@ -740,7 +928,14 @@ unsafe extern "C" fn local_cb_field_access(
 ) -> () {
     match CALLBACK_TABLE.field_access {
         Some(function) => {
-            function();
+            let env = Environment::new(
+                JVMTIEnvironment::new(jvmti_env),
+                JNIEnvironment::new(jni_env),
+            );
+            match field_access(&env, thread, method, location, field_klass, object, field) {
+                Ok(event) => function(env, event),
+                Err(err) => println!("Couldn't resolve accessed field: {}", translate_error(&err)),
+            }
         }
         None => println!("No dynamic callback method was found for field access events"),
     }
@@ -761,12 +956,63 @@ unsafe extern "C" fn local_cb_field_modification(
 ) -> () {
     match CALLBACK_TABLE.field_modification {
         Some(function) => {
-            function();
+            let env = Environment::new(
+                JVMTIEnvironment::new(jvmti_env),
+                JNIEnvironment::new(jni_env),
+            );
+            let access = match field_access(&env, thread, method, location, field_klass, object, field) {
+                Ok(access) => access,
+                Err(err) => {
+                    println!("Couldn't resolve modified field: {}", translate_error(&err));
+                    return;
+                }
+            };
+            match FieldValue::decode(signature_type as u8, new_value) {
+                Some(value) => function(
+                    env,
+                    FieldModificationEvent {
+                        thread: access.thread,
+                        method: access.method,
+                        location: access.location,
+                        line: access.line,
+                        class_name: access.class_name,
+                        field_name: access.field_name,
+                        field_signature: access.field_signature,
+                        object: access.object,
+                        new_value: value,
+                    },
+                ),
+                None => println!("Unknown field type {}", signature_type as u8 as char),
+            }
         }
         None => println!("No dynamic callback method was found for field modification events"),
     }
 }
 
+/// Resolve the field and the line of the instruction that accesses it
+fn field_access(
+    env: &Environment,
+    thread: jthread,
+    method: jmethodID,
+    location: jlocation,
+    class: jclass,
+    object: jobject,
+    field: jfieldID,
+) -> Result<FieldAccessEvent, NativeError> {
+    let (field_name, field_signature) = env.get_field_name(&class, field)?;
+
+    Ok(FieldAccessEvent {
+        thread,
+        method,
+        location,
+        line: line_at(env, method, location)?,
+        class_name: binary_name(&env.get_class_signature(&class)?.native_sig),
+        field_name,
+        field_signature,
+        object,
+    })
+}
+
 #[allow(unused_variables)]
 unsafe extern "C" fn local_cb_frame_pop(
     jvmti_env: *mut jvmtiEnv,
@@ -775,6 +1021,42 @@ unsafe extern "C" fn local_cb_frame_pop(
     method: jmethodID,
     was_popped_by_exception: jboolean,
 ) -> () {
+    let env = Environment::new(
+        JVMTIEnvironment::new(jvmti_env),
+        JNIEnvironment::new(jni_env),
+    );
+    let stepping = match debug::on_frame_pop(&env, thread) {
+        Ok(stepping) => stepping,
+        Err(err) => {
+            println!("Couldn't resume stepping: {}", translate_error(&err));
+            false
+        }
+    };
+    let event = FramePopEvent {
+        thread,
+        method,
+        was_popped_by_exception: was_popped_by_exception != 0,
+    };
+    let handlers = match env.get_frame_count(thread) {
+        Ok(frame_count) => static_context().frame_returns.take(&env, thread, frame_count),
+        Err(err) => {
+            println!("Couldn't get frame count: {}", translate_error(&err));
+            vec![]
+        }
+    };
+
+    // Frames popped for steps and frame return handlers aren't reported to the callback
+    if stepping || !handlers.is_empty() {
+        for handler in handlers {
+            handler(&env, event);
+        }
+        return;
+    }
+
+    match CALLBACK_TABLE.frame_pop {
+        Some(function) => function(env, event),
+        None => println!("No dynamic callback method was found for frame pop events"),
+    }
 }
 
 #[allow(unused_variables)]
@@ -809,6 +1091,18 @@ unsafe extern "C" fn local_cb_single_step(
     method: jmethodID,
     location: jlocation,
 ) -> () {
+    let env = Environment::new(
+        JVMTIEnvironment::new(jvmti_env),
+        JNIEnvironment::new(jni_env),
+    );
+    match debug::on_single_step(&env, thread, method, location) {
+        Ok(Some(event)) => match CALLBACK_TABLE.single_step {
+            Some(function) => function(env, event),
+            None => println!("No dynamic callback method was found for single step events"),
+        },
+        Ok(None) => (),
+        Err(err) => println!("Couldn't advance step: {}", translate_error(&err)),
+    }
 }
 
 #[allow(unused_variables)]
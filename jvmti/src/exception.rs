@@ -0,0 +1,209 @@
+use super::debug::CodeLocation;
+use super::environment::jni::JNI;
+use super::environment::jvmti::JVMTI;
+use super::error::NativeError;
+use super::instrumentation::capture::call_string;
+use super::native::jvmti_native::{jclass, jint, jlocation, jmethodID, jobject, jthread, jvmtiFrameInfo};
+use super::runtime::{ExceptionCatchEvent, ExceptionEvent};
+use super::stack::{binary_name, StackFrame, StackTrace};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+
+///
+/// Selects the exceptions that are reported to the `Exception` and `ExceptionCatch` callbacks by
+/// their class hierarchy, and whether their stack traces are captured. Without any classes every
+/// exception is reported.
+///
+/// Whether a class is reported is cached by its name, so only the first exception of each class
+/// walks its superclasses.
+pub struct ExceptionFilter {
+    /// Binary names of the reported classes, their subclasses are reported too
+    classes: RwLock<Vec<String>>,
+    /// Whether exceptions are reported by the binary name of their class
+    reported: Mutex<HashMap<String, bool>>,
+    /// The number of frames captured for each reported exception
+    stack_depth: RwLock<Option<jint>>,
+}
+
+impl Default for ExceptionFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExceptionFilter {
+    pub fn new() -> ExceptionFilter {
+        ExceptionFilter {
+            classes: RwLock::new(vec![]),
+            reported: Mutex::new(HashMap::new()),
+            stack_depth: RwLock::new(None),
+        }
+    }
+
+    ///
+    /// Report the exceptions of the class and its subclasses, eg. `java.sql.SQLException`
+    pub fn include(&self, class_name: &str) {
+        self.classes.write().unwrap().push(class_name.replace('/', "."));
+        self.reported.lock().unwrap().clear();
+    }
+
+    ///
+    /// Report every exception again
+    pub fn include_all(&self) {
+        self.classes.write().unwrap().clear();
+        self.reported.lock().unwrap().clear();
+    }
+
+    ///
+    /// Capture up to `max_depth` frames of the thread's stack with each reported exception, or
+    /// none with `None`
+    pub fn capture_stack_traces(&self, max_depth: Option<jint>) {
+        *self.stack_depth.write().unwrap() = max_depth;
+    }
+
+    pub fn stack_depth(&self) -> Option<jint> {
+        *self.stack_depth.read().unwrap()
+    }
+
+    ///
+    /// Whether exceptions of the class are reported. The binary names of the class and its
+    /// superclasses are only requested if the class hasn't been seen before.
+    pub fn accepts<F>(&self, class_name: &str, hierarchy: F) -> bool
+        where F: FnOnce() -> Vec<String> {
+        let classes = self.classes.read().unwrap();
+
+        if classes.is_empty() {
+            return true;
+        }
+
+        if let Some(reported) = self.reported.lock().unwrap().get(class_name) {
+            return *reported;
+        }
+
+        let reported = hierarchy().iter().any(|name| classes.contains(name));
+        self.reported.lock().unwrap().insert(class_name.to_string(), reported);
+        reported
+    }
+}
+
+thread_local! {
+    /// Set while an exception event is built, as `getMessage` may throw exceptions of its own
+    static BUILDING: Cell<bool> = const { Cell::new(false) };
+}
+
+///
+/// Build the event of a thrown exception, or `None` if the filter doesn't report it. The catch
+/// location is `None` when no method up the stack catches the exception.
+pub fn exception_event(
+    jvmti: &dyn JVMTI,
+    jni: &dyn JNI,
+    filter: &ExceptionFilter,
+    thread: jthread,
+    exception: jobject,
+    throw_location: CodeLocation,
+    catch_location: Option<CodeLocation>,
+) -> Result<Option<ExceptionEvent>, NativeError> {
+    guarded(|| {
+        let (class_name, message) = match describe(jvmti, jni, filter, exception)? {
+            Some(description) => description,
+            None => return Ok(None),
+        };
+
+        let catch_frame = match catch_location {
+            Some(catch) => Some(frame(jvmti, catch.method, catch.location)?),
+            None => None,
+        };
+
+        Ok(Some(ExceptionEvent {
+            thread,
+            exception,
+            class_name,
+            message,
+            throw_frame: frame(jvmti, throw_location.method, throw_location.location)?,
+            catch_frame,
+            stack_trace: stack_trace(jvmti, filter, thread)?,
+        }))
+    })
+}
+
+///
+/// Build the event of a caught exception, or `None` if the filter doesn't report it
+pub fn exception_catch_event(
+    jvmti: &dyn JVMTI,
+    jni: &dyn JNI,
+    filter: &ExceptionFilter,
+    thread: jthread,
+    method: jmethodID,
+    location: jlocation,
+    exception: jobject,
+) -> Result<Option<ExceptionCatchEvent>, NativeError> {
+    guarded(|| {
+        let (class_name, message) = match describe(jvmti, jni, filter, exception)? {
+            Some(description) => description,
+            None => return Ok(None),
+        };
+
+        Ok(Some(ExceptionCatchEvent {
+            thread,
+            exception,
+            class_name,
+            message,
+            catch_frame: frame(jvmti, method, location)?,
+            stack_trace: stack_trace(jvmti, filter, thread)?,
+        }))
+    })
+}
+
+/// Skip the events of exceptions thrown while another event of the thread is built
+fn guarded<T, F>(build: F) -> Result<Option<T>, NativeError>
+    where F: FnOnce() -> Result<Option<T>, NativeError> {
+    if BUILDING.with(|building| building.replace(true)) {
+        return Ok(None);
+    }
+
+    let result = build();
+    BUILDING.with(|building| building.set(false));
+    result
+}
+
+/// The binary name of the exception's class and its message, `None` if the filter doesn't
+/// report the class
+fn describe(jvmti: &dyn JVMTI, jni: &dyn JNI, filter: &ExceptionFilter, exception: jobject) -> Result<Option<(String, Option<String>)>, NativeError> {
+    let class = jni.get_object_class(&exception).map_err(|_| NativeError::InvalidObject)?;
+    let class_name = binary_name(&jvmti.get_class_signature(&class)?.native_sig);
+
+    if !filter.accepts(&class_name, || hierarchy(jvmti, jni, class)) {
+        return Ok(None);
+    }
+
+    Ok(Some((class_name, call_string(jni, exception, "getMessage"))))
+}
+
+///
+/// The binary names of the class and its superclasses, the class first
+pub fn hierarchy(jvmti: &dyn JVMTI, jni: &dyn JNI, class: jclass) -> Vec<String> {
+    let mut names = vec![];
+    let mut current = Some(class);
+
+    while let Some(class) = current {
+        if let Ok(signature) = jvmti.get_class_signature(&class) {
+            names.push(binary_name(&signature.native_sig));
+        }
+
+        current = jni.get_superclass(&class).unwrap_or(None);
+    }
+
+    names
+}
+
+fn frame(jvmti: &dyn JVMTI, method: jmethodID, location: jlocation) -> Result<StackFrame, NativeError> {
+    StackFrame::resolve(jvmti, &jvmtiFrameInfo { method, location })
+}
+
+fn stack_trace(jvmti: &dyn JVMTI, filter: &ExceptionFilter, thread: jthread) -> Result<Option<StackTrace>, NativeError> {
+    match filter.stack_depth() {
+        Some(max_depth) => Ok(Some(StackTrace::capture(jvmti, thread, 0, max_depth)?)),
+        None => Ok(None),
+    }
+}
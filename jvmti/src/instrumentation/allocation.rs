@@ -0,0 +1,464 @@
+//!
+//! Allocation sites through probes woven after `new`, `newarray`, `anewarray` and
+//! `multianewarray` instructions. `VMObjectAlloc` events are only sent for objects the VM
+//! allocates itself, eg. through reflection or JNI, so they miss almost every allocation made by
+//! bytecode.
+//!
+//! Each site gets a numeric id that the woven code passes to a native helper method, arrays
+//! pass their length as well. The helper counts the allocations and their estimated size per
+//! site in the `AllocationRegistry` of the `AgentContext`.
+//!
+//! ```ignore
+//! let probes = AllocationProbes::from_config(&config, static_context().allocations.clone())?;
+//! static_context().add_transformer("allocations", 0, probes);
+//! agent.use_transformer_chain();
+//! agent.on_vm_init(Some(on_vm_init));
+//!
+//! fn on_vm_init(_jvmti: JVMTIEnvPtr, jni: JNIEnvPtr, _thread: jthread) {
+//!     allocation::install(jni).unwrap();
+//! }
+//! ```
+//!
+//! Sizes are estimated for a 64-bit VM with compressed references. The size of an object
+//! doesn't depend on the allocation site, but on fields the weaving doesn't know about, so
+//! objects are assumed to take `DEFAULT_INSTANCE_SIZE` bytes unless the agent sets the size of
+//! their class with `AllocationRegistry::set_instance_size`, eg. from `GetObjectSize`. Only the
+//! outermost array of a `multianewarray` is counted.
+
+use super::super::bytecode::classfile::{Attribute, Instruction};
+use super::super::config::Config;
+use super::super::context::static_context;
+use super::super::environment::jni::JNIEnvironment;
+use super::super::native::jvmti_native::jint;
+use super::super::native::{JNIEnvPtr, JavaClass as JavaClassPtr};
+use super::analysis;
+use super::bridge::{BridgeError, NativeBridge};
+use super::chain::{ClassTransformer, TransformError};
+use super::code::{Insn, Invoke, MemberRef, TypeOp};
+use super::pointcut::{ClassInfo, MethodInfo, Pointcut};
+use super::timing::{push_int, ProbeError};
+use super::{JavaClass, ModelError};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Internal name of the generated helper class the probes call
+pub const HELPER_CLASS: &str = "jvmti/probe/Allocation";
+/// `static native void object(int site)`
+pub const OBJECT_DESCRIPTOR: &str = "(I)V";
+/// `static native void array(int length, int site)`
+pub const ARRAY_DESCRIPTOR: &str = "(II)V";
+
+/// Estimated size of an object whose class size is unknown: a 12 byte header and one field
+pub const DEFAULT_INSTANCE_SIZE: u64 = 16;
+/// Size of an array header, including the length
+pub const ARRAY_HEADER_SIZE: u64 = 16;
+/// Size of a compressed reference
+pub const REFERENCE_SIZE: u64 = 4;
+
+static HELPER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+///
+/// An instruction that allocates an object or array
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllocationSite {
+    /// Internal name of the class that contains the instruction
+    pub class_name: String,
+    pub method_name: String,
+    pub descriptor: String,
+    /// Offset of the instruction in the method's code before the probes were woven
+    pub pc: u32,
+    pub line: Option<u16>,
+    /// Internal name of the allocated class, or the descriptor of the allocated array type, eg.
+    /// `java/lang/String` or `[[I`
+    pub allocated_type: String,
+}
+
+impl AllocationSite {
+    pub fn is_array(&self) -> bool {
+        self.allocated_type.starts_with('[')
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AllocationStats {
+    pub count: u64,
+    /// Estimated number of bytes allocated
+    pub bytes: u64,
+}
+
+struct Counters {
+    count: AtomicU64,
+    bytes: AtomicU64,
+}
+
+///
+/// Assigns the numeric ids the woven code passes to the helper and aggregates the allocations
+/// of each site.
+pub struct AllocationRegistry {
+    sites: RwLock<Vec<(AllocationSite, Counters)>>,
+    instance_sizes: RwLock<HashMap<String, u64>>,
+}
+
+impl AllocationRegistry {
+    pub fn new() -> AllocationRegistry {
+        AllocationRegistry {
+            sites: RwLock::new(vec![]),
+            instance_sizes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the id of the site, registering it first if it's new. Classes with the same name
+    /// loaded by different class loaders share their sites.
+    pub fn register(&self, site: AllocationSite) -> u32 {
+        let mut sites = self.sites.write().unwrap();
+
+        match sites.iter().position(|(existing, _)| *existing == site) {
+            Some(id) => id as u32,
+            None => {
+                sites.push((
+                    site,
+                    Counters {
+                        count: AtomicU64::new(0),
+                        bytes: AtomicU64::new(0),
+                    },
+                ));
+                (sites.len() - 1) as u32
+            }
+        }
+    }
+
+    pub fn site(&self, id: u32) -> Option<AllocationSite> {
+        self.sites
+            .read()
+            .ok()
+            .and_then(|sites| sites.get(id as usize).map(|(site, _)| site.clone()))
+    }
+
+    ///
+    /// Set the size of the instances of a class, given by its internal name. Allocations that
+    /// have already been recorded aren't updated.
+    pub fn set_instance_size(&self, class_name: &str, bytes: u64) {
+        if let Ok(mut sizes) = self.instance_sizes.write() {
+            sizes.insert(class_name.to_string(), bytes);
+        }
+    }
+
+    pub fn instance_size(&self, class_name: &str) -> u64 {
+        self.instance_sizes
+            .read()
+            .ok()
+            .and_then(|sizes| sizes.get(class_name).cloned())
+            .unwrap_or(DEFAULT_INSTANCE_SIZE)
+    }
+
+    /// Add an allocation of the site. `length` is the length of an allocated array.
+    pub fn record(&self, id: u32, length: Option<u32>) {
+        if let Ok(sites) = self.sites.read() {
+            if let Some((site, counters)) = sites.get(id as usize) {
+                let bytes = match length {
+                    Some(length) => array_size(&site.allocated_type, length),
+                    None => self.instance_size(&site.allocated_type),
+                };
+
+                counters.count.fetch_add(1, Ordering::Relaxed);
+                counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn stats(&self, id: u32) -> Option<AllocationStats> {
+        self.sites
+            .read()
+            .ok()
+            .and_then(|sites| sites.get(id as usize).map(|(_, counters)| stats(counters)))
+    }
+
+    /// All registered sites with their current statistics, ordered by id
+    pub fn snapshot(&self) -> Vec<(AllocationSite, AllocationStats)> {
+        match self.sites.read() {
+            Ok(sites) => sites
+                .iter()
+                .map(|(site, counters)| (site.clone(), stats(counters)))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sites.read().map(|sites| sites.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for AllocationRegistry {
+    fn default() -> Self {
+        AllocationRegistry::new()
+    }
+}
+
+fn stats(counters: &Counters) -> AllocationStats {
+    AllocationStats {
+        count: counters.count.load(Ordering::Relaxed),
+        bytes: counters.bytes.load(Ordering::Relaxed),
+    }
+}
+
+///
+/// Estimated size of an array of the given type, aligned to 8 bytes
+pub fn array_size(array_type: &str, length: u32) -> u64 {
+    let element_size = match array_type.as_bytes().get(1) {
+        Some(&b'Z') | Some(&b'B') => 1,
+        Some(&b'C') | Some(&b'S') => 2,
+        Some(&b'I') | Some(&b'F') => 4,
+        Some(&b'J') | Some(&b'D') => 8,
+        _ => REFERENCE_SIZE,
+    };
+
+    (ARRAY_HEADER_SIZE + element_size * length as u64).div_ceil(8) * 8
+}
+
+///
+/// A `ClassTransformer` that weaves allocation probes into the methods selected by any of its
+/// pointcuts
+pub struct AllocationProbes {
+    pointcuts: Vec<Pointcut>,
+    registry: Arc<AllocationRegistry>,
+}
+
+impl AllocationProbes {
+    pub fn new(pointcuts: Vec<Pointcut>, registry: Arc<AllocationRegistry>) -> AllocationProbes {
+        AllocationProbes {
+            pointcuts,
+            registry,
+        }
+    }
+
+    /// Instrument the methods selected by the pointcuts listed in `allocations`
+    pub fn from_config(config: &Config, registry: Arc<AllocationRegistry>) -> Result<AllocationProbes, ProbeError> {
+        let pointcuts = config
+            .allocations
+            .iter()
+            .map(|name| {
+                config
+                    .pointcut(name)
+                    .cloned()
+                    .ok_or_else(|| ProbeError::UnknownPointcut(name.clone()))
+            })
+            .collect::<Result<Vec<Pointcut>, ProbeError>>()?;
+
+        Ok(AllocationProbes::new(pointcuts, registry))
+    }
+}
+
+impl ClassTransformer for AllocationProbes {
+    fn accepts(&self, class_name: &str) -> bool {
+        let class = ClassInfo::new(class_name);
+
+        HELPER_INSTALLED.load(Ordering::SeqCst)
+            && class_name != HELPER_CLASS
+            && self.pointcuts.iter().any(|p| p.matches_class(&class))
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        let class_info = ClassInfo::from_class(class);
+
+        let selected: Vec<usize> = class
+            .methods
+            .iter()
+            .enumerate()
+            .filter(|&(_, method)| {
+                method.code.is_some()
+                    && self
+                        .pointcuts
+                        .iter()
+                        .any(|p| p.matches_method(&class_info, &MethodInfo::from_method(class, method)))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        if selected.is_empty() {
+            return Ok(false);
+        }
+
+        let offsets = instruction_offsets(class)?;
+        let mut modified = false;
+
+        for index in selected {
+            modified |= weave(class, index, &offsets[index], &self.registry)? > 0;
+        }
+
+        Ok(modified)
+    }
+}
+
+///
+/// Weave a probe after every allocation in a method of the class and register the sites, whose
+/// pc is taken from the given offsets of the method's original instructions. Returns the number
+/// of sites. The probes don't change the control flow or the types of local variables, so the
+/// existing stack map frames remain valid.
+pub fn weave(
+    class: &mut JavaClass,
+    method_index: usize,
+    offsets: &[usize],
+    registry: &AllocationRegistry,
+) -> Result<usize, ModelError> {
+    let class_name = class.name.clone();
+    let method = &mut class.methods[method_index];
+    let method_name = method.name.clone();
+    let descriptor = method.descriptor.descriptor();
+    let mut sites = 0;
+
+    {
+        let code = match method.code.as_mut() {
+            Some(code) => code,
+            None => return Ok(0),
+        };
+
+        let original = code.instructions.split_off(0);
+        let mut line = None;
+        let mut position = 0;
+
+        for insn in original {
+            if let Insn::LineNumber(number) = insn {
+                line = Some(number);
+            }
+
+            let allocated = allocated_type(&insn);
+            let is_instruction = insn.is_instruction();
+
+            code.instructions.push(insn);
+
+            if let Some((allocated_type, is_array)) = allocated {
+                let site = registry.register(AllocationSite {
+                    class_name: class_name.clone(),
+                    method_name: method_name.clone(),
+                    descriptor: descriptor.clone(),
+                    pc: offsets.get(position).cloned().unwrap_or(0) as u32,
+                    line,
+                    allocated_type,
+                });
+
+                if is_array {
+                    code.instructions.push(Insn::Op(Instruction::DUP));
+                    code.instructions.push(Insn::Op(Instruction::ARRAYLENGTH));
+                    code.instructions.push(push_int(site as i32));
+                    code.instructions.push(Insn::Invoke(Invoke::Static, MemberRef::new(HELPER_CLASS, "array", ARRAY_DESCRIPTOR)));
+                } else {
+                    code.instructions.push(push_int(site as i32));
+                    code.instructions.push(Insn::Invoke(Invoke::Static, MemberRef::new(HELPER_CLASS, "object", OBJECT_DESCRIPTOR)));
+                }
+
+                sites += 1;
+            }
+
+            if is_instruction {
+                position += 1;
+            }
+        }
+    }
+
+    if sites > 0 {
+        analysis::compute_maxs(&class_name, method)?;
+    }
+
+    Ok(sites)
+}
+
+/// The allocated type of an allocation instruction and whether it's an array
+fn allocated_type(insn: &Insn) -> Option<(String, bool)> {
+    match *insn {
+        Insn::Type(TypeOp::New, ref class_name) => Some((class_name.clone(), false)),
+        Insn::Type(TypeOp::ANewArray, ref element) => {
+            if element.starts_with('[') {
+                Some((format!("[{}", element), true))
+            } else {
+                Some((format!("[L{};", element), true))
+            }
+        }
+        Insn::MultiANewArray(ref array_type, _) => Some((array_type.clone(), true)),
+        Insn::Op(Instruction::NEWARRAY(element)) => {
+            let element = match element {
+                4 => "Z",
+                5 => "C",
+                6 => "F",
+                7 => "D",
+                8 => "B",
+                9 => "S",
+                10 => "I",
+                _ => "J",
+            };
+
+            Some((format!("[{}", element), true))
+        }
+        _ => None,
+    }
+}
+
+///
+/// Code offsets of the instructions of every method, as the class would be written now. Has to be
+/// called before any method is woven, so that the offsets are those of the original code.
+pub fn instruction_offsets(class: &JavaClass) -> Result<Vec<Vec<usize>>, ModelError> {
+    let classfile = class.to_classfile()?;
+
+    Ok(classfile
+        .methods
+        .iter()
+        .map(|method| {
+            let mut offsets = vec![];
+
+            for attribute in method.attributes.iter() {
+                if let Attribute::Code { code, .. } = attribute {
+                    let mut offset = 0;
+
+                    for instruction in code.iter() {
+                        offsets.push(offset);
+                        offset += instruction.size_at(offset);
+                    }
+                }
+            }
+
+            offsets
+        })
+        .collect())
+}
+
+///
+/// The bridge that defines the helper class and binds its `object` and `array` methods
+pub fn bridge() -> NativeBridge {
+    NativeBridge::new(HELPER_CLASS)
+        .method("object", OBJECT_DESCRIPTOR, allocated_object as *mut c_void)
+        .method("array", ARRAY_DESCRIPTOR, allocated_array as *mut c_void)
+}
+
+///
+/// Define the helper class in the bootstrap class loader, bind its native methods and start
+/// weaving the classes loaded from now on. Has to be called in the live phase, eg. from the
+/// `VMInit` handler.
+pub fn install(jni: JNIEnvPtr) -> Result<(), BridgeError> {
+    if HELPER_INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    bridge().define(&JNIEnvironment::new(jni))?;
+    HELPER_INSTALLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Returns true once the helper class is defined and classes are being woven
+pub fn is_installed() -> bool {
+    HELPER_INSTALLED.load(Ordering::SeqCst)
+}
+
+extern "C" fn allocated_object(_jni: JNIEnvPtr, _class: JavaClassPtr, site: jint) {
+    static_context().allocations.record(site as u32, None);
+}
+
+extern "C" fn allocated_array(_jni: JNIEnvPtr, _class: JavaClassPtr, length: jint, site: jint) {
+    static_context().allocations.record(site as u32, Some(length as u32));
+}
@@ -0,0 +1,1119 @@
+use super::super::bytecode::classfile::Instruction;
+use super::code::*;
+use super::descriptor::{JavaType, MethodDescriptor};
+use super::{Method, ModelError};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+const OBJECT: &str = "java/lang/Object";
+const THROWABLE: &str = "java/lang/Throwable";
+
+///
+/// Provides the class hierarchy information needed to merge two reference types where control
+/// flow paths meet.
+pub trait TypeHierarchy {
+    /// Returns the most specific common super class of the given internal class names
+    fn common_super_class(&self, left: &str, right: &str) -> String;
+}
+
+///
+/// A `TypeHierarchy` that doesn't know about any classes and merges every pair of different
+/// classes into `java/lang/Object`. Code that relies on a more specific common super type after
+/// a join needs an explicit `checkcast` when frames are computed with this.
+pub struct ObjectHierarchy;
+
+impl TypeHierarchy for ObjectHierarchy {
+    fn common_super_class(&self, _: &str, _: &str) -> String {
+        OBJECT.to_string()
+    }
+}
+
+///
+/// A `TypeHierarchy` backed by a function that returns the super class of a class, or `None`
+/// if it's unknown.
+pub struct SuperClassHierarchy<F: Fn(&str) -> Option<String>> {
+    super_class: F,
+}
+
+impl<F: Fn(&str) -> Option<String>> SuperClassHierarchy<F> {
+    pub fn new(super_class: F) -> SuperClassHierarchy<F> {
+        SuperClassHierarchy {
+            super_class,
+        }
+    }
+
+    fn ancestors(&self, name: &str) -> Vec<String> {
+        let mut ancestors = vec![name.to_string()];
+
+        while let Some(parent) = (self.super_class)(&ancestors[ancestors.len() - 1]) {
+            // Guard against broken (cyclic) hierarchies
+            if ancestors.contains(&parent) {
+                break;
+            }
+
+            ancestors.push(parent);
+        }
+
+        ancestors
+    }
+}
+
+impl<F: Fn(&str) -> Option<String>> TypeHierarchy for SuperClassHierarchy<F> {
+    fn common_super_class(&self, left: &str, right: &str) -> String {
+        let right = self.ancestors(right);
+
+        self.ancestors(left)
+            .into_iter()
+            .find(|class| right.contains(class))
+            .unwrap_or(OBJECT.to_string())
+    }
+}
+
+///
+/// Recalculates `max_stack`, `max_locals` and all stack map frames of a method body. Existing
+/// frames are discarded and unreachable instructions are removed, since the verifier would
+/// require a frame for them that can't be inferred.
+///
+/// Different reference types meeting at a branch target are merged into `java/lang/Object`,
+/// use `compute_frames_with` if a more precise merge is needed.
+pub fn compute_frames(class_name: &str, method: &mut Method) -> Result<(), ModelError> {
+    compute_frames_with(class_name, method, &ObjectHierarchy)
+}
+
+///
+/// Same as `compute_frames`, but uses the given class hierarchy to merge reference types.
+pub fn compute_frames_with(
+    class_name: &str,
+    method: &mut Method,
+    hierarchy: &dyn TypeHierarchy,
+) -> Result<(), ModelError> {
+    let initial = Frame::initial(
+        class_name,
+        method.is_static(),
+        &method.name,
+        &method.descriptor,
+    );
+
+    let code = match method.code {
+        Some(ref mut code) => code,
+        None => return Ok(()),
+    };
+
+    code.instructions.retain(|insn| !matches!(*insn, Insn::Frame(_)));
+
+    label_allocations(code);
+
+    let analysis = Analyzer::new(class_name, code, hierarchy)?.run(&initial)?;
+    let frame_points = frame_points(code, &analysis);
+    let live = |idx: usize| analysis.states[idx].is_some();
+
+    {
+        let instructions = &code.instructions;
+
+        code.try_catch_blocks.retain(|block| {
+            let start = analysis.labels[&block.start];
+            let end = analysis.labels[&block.end];
+
+            (start..end).any(|idx| instructions[idx].is_instruction() && live(idx))
+        });
+    }
+
+    let mut instructions = Vec::with_capacity(code.instructions.len());
+
+    for (idx, insn) in code.instructions.iter().enumerate() {
+        match *insn {
+            Insn::Label(_) => instructions.push(insn.clone()),
+            Insn::LineNumber(_) => {
+                if next_instruction(&code.instructions, idx).map(&live).unwrap_or(false) {
+                    instructions.push(insn.clone());
+                }
+            }
+            Insn::Frame(_) => (),
+            _ => {
+                if let Some(ref state) = analysis.states[idx] {
+                    if frame_points.contains(&idx) {
+                        instructions.push(Insn::Frame(state.to_frame()));
+                    }
+
+                    instructions.push(insn.clone());
+                }
+            }
+        }
+    }
+
+    code.instructions = instructions;
+    code.max_stack = analysis.max_stack as u16;
+    code.max_locals = analysis.max_locals as u16;
+
+    Ok(())
+}
+
+///
+/// Recalculates `max_stack` and `max_locals` of a method body, leaving the instructions and
+/// the stack map frames untouched.
+pub fn compute_maxs(class_name: &str, method: &mut Method) -> Result<(), ModelError> {
+    let initial = Frame::initial(
+        class_name,
+        method.is_static(),
+        &method.name,
+        &method.descriptor,
+    );
+
+    if let Some(ref mut code) = method.code {
+        label_allocations(code);
+
+        let analysis = Analyzer::new(class_name, code, &ObjectHierarchy)?.run(&initial)?;
+
+        code.max_stack = analysis.max_stack as u16;
+        code.max_locals = analysis.max_locals as u16;
+    }
+
+    Ok(())
+}
+
+///
+/// Checks the stack map frames of a method body against the types inferred by the data flow
+/// analysis. Every reachable branch target, exception handler and instruction following an
+/// unconditional jump needs a frame, and the values reaching a frame must be assignable to the
+/// types it declares. Since the class hierarchy isn't known, reference types are only checked
+/// to be references.
+pub fn verify_frames(class_name: &str, method: &Method) -> Result<(), ModelError> {
+    let initial = Frame::initial(
+        class_name,
+        method.is_static(),
+        &method.name,
+        &method.descriptor,
+    );
+
+    let mut code = match method.code {
+        Some(ref code) => code.clone(),
+        None => return Ok(()),
+    };
+
+    label_allocations(&mut code);
+
+    let analysis = Analyzer::new(class_name, &code, &ObjectHierarchy)?.run(&initial)?;
+    let frame_points = frame_points(&code, &analysis);
+
+    for (idx, insn) in code.instructions.iter().enumerate() {
+        let state = match analysis.states[idx] {
+            Some(ref state) if insn.is_instruction() => state,
+            _ => continue,
+        };
+
+        let declared = match preceding_frame(&code.instructions, idx) {
+            Some(frame) => State::from_frame(frame),
+            None if frame_points.contains(&idx) => return Err(ModelError::InvalidFrame(idx)),
+            None => continue,
+        };
+
+        let site = |label: &Label| {
+            analysis
+                .labels
+                .get(label)
+                .and_then(|&position| next_instruction(&code.instructions, position))
+        };
+
+        let assignable = |inferred: &FrameItem, declared: &FrameItem| match (inferred, declared) {
+            _ if inferred == declared => true,
+            (_, FrameItem::Top) => true,
+            (FrameItem::Null, FrameItem::Object(_)) => true,
+            (FrameItem::Object(_), FrameItem::Object(_)) => true,
+            (FrameItem::Uninitialized(left), FrameItem::Uninitialized(right)) => {
+                site(left).is_some() && site(left) == site(right)
+            }
+            _ => false,
+        };
+
+        let locals = (0..declared.locals.len())
+            .all(|slot| assignable(&state.load(slot), &declared.locals[slot]));
+        let stack = state.stack.len() == declared.stack.len()
+            && state
+                .stack
+                .iter()
+                .zip(declared.stack.iter())
+                .all(|(inferred, declared)| assignable(inferred, declared));
+
+        if !locals || !stack {
+            return Err(ModelError::InvalidFrame(idx));
+        }
+    }
+
+    Ok(())
+}
+
+/// Positions of the instructions that need a stack map frame: branch targets, exception
+/// handlers and instructions following an unconditional jump
+fn frame_points(code: &Code, analysis: &Analysis) -> HashSet<usize> {
+    let mut frame_points = HashSet::new();
+
+    for (idx, insn) in code.instructions.iter().enumerate() {
+        match *insn {
+            Insn::Jump(jump, ref target) => {
+                frame_points.insert(analysis.labels[target]);
+
+                if !jump.is_conditional() {
+                    frame_points.insert(idx + 1);
+                }
+            }
+            Insn::TableSwitch {
+                ref default,
+                ref targets,
+                ..
+            } => {
+                frame_points.insert(analysis.labels[default]);
+                frame_points.extend(targets.iter().map(|t| analysis.labels[t]));
+                frame_points.insert(idx + 1);
+            }
+            Insn::LookupSwitch {
+                ref default,
+                ref pairs,
+            } => {
+                frame_points.insert(analysis.labels[default]);
+                frame_points.extend(pairs.iter().map(|(_, t)| analysis.labels[t]));
+                frame_points.insert(idx + 1);
+            }
+            Insn::Op(ref instruction) if ends_flow(instruction) => {
+                frame_points.insert(idx + 1);
+            }
+            _ => (),
+        }
+    }
+
+    for block in &code.try_catch_blocks {
+        frame_points.insert(analysis.labels[&block.handler]);
+    }
+
+    // Frames are attached to the next real instruction after a label
+    frame_points
+        .into_iter()
+        .filter_map(|idx| next_instruction(&code.instructions, idx))
+        .collect()
+}
+
+/// Uninitialised values are identified by the label of their `new` instruction, so make sure
+/// every `new` has one
+fn label_allocations(code: &mut Code) {
+    let mut idx = 0;
+
+    while idx < code.instructions.len() {
+        if let Insn::Type(TypeOp::New, _) = code.instructions[idx] {
+            if preceding_label(&code.instructions, idx).is_none() {
+                let label = code.new_label();
+                code.instructions.insert(idx, Insn::Label(label));
+                idx += 1;
+            }
+        }
+
+        idx += 1;
+    }
+}
+
+/// Returns the label placed right before the instruction at `idx`, if any
+fn preceding_label(instructions: &[Insn], idx: usize) -> Option<Label> {
+    instructions[..idx]
+        .iter()
+        .rev()
+        .take_while(|insn| !insn.is_instruction())
+        .filter_map(|insn| match *insn {
+            Insn::Label(label) => Some(label),
+            _ => None,
+        })
+        .next()
+}
+
+/// Returns the frame placed right before the instruction at `idx`, if any
+fn preceding_frame(instructions: &[Insn], idx: usize) -> Option<&Frame> {
+    instructions[..idx]
+        .iter()
+        .rev()
+        .take_while(|insn| !insn.is_instruction())
+        .filter_map(|insn| match insn {
+            Insn::Frame(frame) => Some(frame),
+            _ => None,
+        })
+        .next()
+}
+
+fn next_instruction(instructions: &[Insn], idx: usize) -> Option<usize> {
+    (idx..instructions.len()).find(|&i| instructions[i].is_instruction())
+}
+
+/// Returns true for instructions after which execution doesn't continue with the next one
+fn ends_flow(instruction: &Instruction) -> bool {
+    matches!(
+        *instruction,
+        Instruction::IRETURN
+            | Instruction::LRETURN
+            | Instruction::FRETURN
+            | Instruction::DRETURN
+            | Instruction::ARETURN
+            | Instruction::RETURN
+            | Instruction::ATHROW
+    )
+}
+
+///
+/// Operand stack and local variables before an instruction. Unlike in `Frame`, `long` and
+/// `double` values take up two entries here, the second of which is `Top`.
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    locals: Vec<FrameItem>,
+    stack: Vec<FrameItem>,
+}
+
+impl State {
+    fn from_frame(frame: &Frame) -> State {
+        let mut state = State {
+            locals: vec![],
+            stack: vec![],
+        };
+
+        for item in &frame.locals {
+            state.locals.push(item.clone());
+
+            if item.is_wide() {
+                state.locals.push(FrameItem::Top);
+            }
+        }
+
+        for item in &frame.stack {
+            state.push(item.clone());
+        }
+
+        state
+    }
+
+    fn to_frame(&self) -> Frame {
+        let mut locals = compress(&self.locals);
+
+        while locals.last() == Some(&FrameItem::Top) {
+            locals.pop();
+        }
+
+        Frame::new(locals, compress(&self.stack))
+    }
+
+    fn push(&mut self, item: FrameItem) {
+        let wide = item.is_wide();
+
+        self.stack.push(item);
+
+        if wide {
+            self.stack.push(FrameItem::Top);
+        }
+    }
+
+    fn push_type(&mut self, java_type: &JavaType) {
+        if let Some(item) = FrameItem::from_type(java_type) {
+            self.push(item);
+        }
+    }
+
+    /// Remove a single stack slot, which may be half of a `long` or `double`
+    fn pop_slot(&mut self, position: usize) -> Result<FrameItem, ModelError> {
+        self.stack.pop().ok_or(ModelError::StackUnderflow(position))
+    }
+
+    fn pop_slots(&mut self, slots: usize, position: usize) -> Result<(), ModelError> {
+        if self.stack.len() < slots {
+            return Err(ModelError::StackUnderflow(position));
+        }
+
+        let len = self.stack.len();
+        self.stack.truncate(len - slots);
+
+        Ok(())
+    }
+
+    /// Remove a whole value from the top of the stack
+    fn pop(&mut self, position: usize) -> Result<FrameItem, ModelError> {
+        match self.pop_slot(position)? {
+            FrameItem::Top => match self.pop_slot(position)? {
+                ref wide if wide.is_wide() => Ok(wide.clone()),
+                _ => Err(ModelError::StackMismatch(position)),
+            },
+            item => Ok(item),
+        }
+    }
+
+    fn load(&self, index: usize) -> FrameItem {
+        self.locals.get(index).cloned().unwrap_or(FrameItem::Top)
+    }
+
+    fn store(&mut self, index: usize, item: FrameItem) {
+        let wide = item.is_wide();
+        let len = index + if wide { 2 } else { 1 };
+
+        if self.locals.len() < len {
+            self.locals.resize(len, FrameItem::Top);
+        }
+
+        // Overwriting the second half of a long or double invalidates the first half
+        if index > 0 && self.locals[index - 1].is_wide() {
+            self.locals[index - 1] = FrameItem::Top;
+        }
+
+        self.locals[index] = item;
+
+        if wide {
+            self.locals[index + 1] = FrameItem::Top;
+        }
+    }
+
+    /// Replace every occurrence of an uninitialised value once its constructor has been called
+    fn initialize(&mut self, from: &FrameItem, to: &FrameItem) {
+        for item in self.locals.iter_mut().chain(self.stack.iter_mut()) {
+            if item == from {
+                *item = to.clone();
+            }
+        }
+    }
+
+    /// Merge another state flowing into the same instruction. Returns true if this state has
+    /// changed as a result.
+    fn merge(
+        &mut self,
+        other: &State,
+        position: usize,
+        hierarchy: &dyn TypeHierarchy,
+    ) -> Result<bool, ModelError> {
+        if self.stack.len() != other.stack.len() {
+            return Err(ModelError::StackMismatch(position));
+        }
+
+        let mut changed = false;
+
+        for (item, other) in self.stack.iter_mut().zip(other.stack.iter()) {
+            let merged = merge_items(item, other, hierarchy);
+
+            if merged == FrameItem::Top && *item != FrameItem::Top {
+                return Err(ModelError::StackMismatch(position));
+            }
+
+            if merged != *item {
+                *item = merged;
+                changed = true;
+            }
+        }
+
+        for idx in 0..self.locals.len() {
+            let merged = merge_items(&self.locals[idx], &other.load(idx), hierarchy);
+
+            if merged != self.locals[idx] {
+                self.locals[idx] = merged;
+                changed = true;
+            }
+        }
+
+        Ok(changed)
+    }
+}
+
+fn merge_items(item: &FrameItem, other: &FrameItem, hierarchy: &dyn TypeHierarchy) -> FrameItem {
+    match (item, other) {
+        _ if item == other => item.clone(),
+        (&FrameItem::Null, &FrameItem::Object(_)) => other.clone(),
+        (&FrameItem::Object(_), &FrameItem::Null) => item.clone(),
+        (FrameItem::Object(left), FrameItem::Object(right)) => {
+            FrameItem::Object(merge_classes(left, right, hierarchy))
+        }
+        _ => FrameItem::Top,
+    }
+}
+
+fn merge_classes(left: &str, right: &str, hierarchy: &dyn TypeHierarchy) -> String {
+    match (JavaType::from_internal_name(left), JavaType::from_internal_name(right)) {
+        (JavaType::Array(left), JavaType::Array(right)) => {
+            if left.is_reference() && right.is_reference() {
+                format!(
+                    "[{}",
+                    JavaType::from_internal_name(&merge_classes(
+                        &left.internal_name(),
+                        &right.internal_name(),
+                        hierarchy
+                    ))
+                    .descriptor()
+                )
+            } else {
+                OBJECT.to_string()
+            }
+        }
+        (JavaType::Class(ref left), JavaType::Class(ref right)) => {
+            hierarchy.common_super_class(left, right)
+        }
+        _ => OBJECT.to_string(),
+    }
+}
+
+/// Turn a list of slots into frame items, where `long` and `double` take up a single item
+fn compress(slots: &[FrameItem]) -> Vec<FrameItem> {
+    let mut items = vec![];
+    let mut idx = 0;
+
+    while idx < slots.len() {
+        items.push(slots[idx].clone());
+        idx += if slots[idx].is_wide() { 2 } else { 1 };
+    }
+
+    items
+}
+
+struct Analysis {
+    /// State before each element of the instruction list, `None` for unreachable ones
+    states: Vec<Option<State>>,
+    /// Position of each label in the instruction list
+    labels: HashMap<Label, usize>,
+    max_stack: usize,
+    max_locals: usize,
+}
+
+struct Handler {
+    start: usize,
+    end: usize,
+    handler: usize,
+    exception: FrameItem,
+}
+
+///
+/// Data flow analysis of a method body that infers the type of every local variable and stack
+/// entry before each instruction.
+struct Analyzer<'a> {
+    class_name: &'a str,
+    hierarchy: &'a dyn TypeHierarchy,
+    code: &'a Code,
+    labels: HashMap<Label, usize>,
+    /// Class created by the `new` instruction at each label
+    allocations: HashMap<Label, String>,
+    handlers: Vec<Handler>,
+    states: Vec<Option<State>>,
+    max_stack: usize,
+    max_locals: usize,
+}
+
+impl<'a> Analyzer<'a> {
+    fn new(
+        class_name: &'a str,
+        code: &'a Code,
+        hierarchy: &'a dyn TypeHierarchy,
+    ) -> Result<Analyzer<'a>, ModelError> {
+        let mut labels = HashMap::new();
+        let mut allocations = HashMap::new();
+
+        for (idx, insn) in code.instructions.iter().enumerate() {
+            match *insn {
+                Insn::Label(label) if labels.insert(label, idx).is_some() => {
+                    return Err(ModelError::DuplicateLabel(label));
+                }
+                Insn::Type(TypeOp::New, ref class) => {
+                    if let Some(label) = preceding_label(&code.instructions, idx) {
+                        allocations.insert(label, class.clone());
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut analyzer = Analyzer {
+            class_name,
+            hierarchy,
+            code,
+            labels,
+            allocations,
+            handlers: vec![],
+            states: vec![None; code.instructions.len()],
+            max_stack: 0,
+            max_locals: 0,
+        };
+
+        for block in &code.try_catch_blocks {
+            let handler = Handler {
+                start: analyzer.label(&block.start)?,
+                end: analyzer.label(&block.end)?,
+                handler: analyzer.label(&block.handler)?,
+                exception: FrameItem::Object(
+                    block.catch_type.clone().unwrap_or(THROWABLE.to_string()),
+                ),
+            };
+
+            analyzer.handlers.push(handler);
+        }
+
+        Ok(analyzer)
+    }
+
+    fn label(&self, label: &Label) -> Result<usize, ModelError> {
+        self.labels
+            .get(label)
+            .cloned()
+            .ok_or(ModelError::UnplacedLabel(*label))
+    }
+
+    fn run(mut self, initial: &Frame) -> Result<Analysis, ModelError> {
+        let mut queue = VecDeque::new();
+        let mut queued = HashSet::new();
+
+        if self.code.instructions.is_empty() {
+            return Err(ModelError::FallOffEnd);
+        }
+
+        let initial = State::from_frame(initial);
+        self.max_locals = initial.locals.len();
+        self.states[0] = Some(initial);
+        queue.push_back(0);
+        queued.insert(0);
+
+        while let Some(idx) = queue.pop_front() {
+            queued.remove(&idx);
+
+            let state = match self.states[idx] {
+                Some(ref state) => state.clone(),
+                None => continue,
+            };
+
+            let insn = &self.code.instructions[idx];
+            let (after, successors) = self.execute(idx, insn, state.clone())?;
+
+            self.max_stack = self.max_stack.max(state.stack.len());
+            self.max_locals = self.max_locals.max(state.locals.len());
+
+            let mut targets: Vec<(usize, State)> = vec![];
+
+            if insn.is_instruction() {
+                // The handler may be entered before or after the instruction has executed
+                for handler in &self.handlers {
+                    if handler.start <= idx && idx < handler.end {
+                        let mut entries = vec![&state.locals];
+
+                        if let Some(ref after) = after {
+                            entries.push(&after.locals);
+                        }
+
+                        for locals in entries {
+                            let mut entry = State {
+                                locals: locals.clone(),
+                                stack: vec![],
+                            };
+
+                            entry.push(handler.exception.clone());
+                            targets.push((handler.handler, entry));
+                        }
+                    }
+                }
+            }
+
+            if let Some(ref after) = after {
+                self.max_stack = self.max_stack.max(after.stack.len());
+                self.max_locals = self.max_locals.max(after.locals.len());
+
+                for successor in successors {
+                    if successor >= self.code.instructions.len() {
+                        return Err(ModelError::FallOffEnd);
+                    }
+
+                    targets.push((successor, after.clone()));
+                }
+            }
+
+            for (target, state) in targets {
+                let changed = match self.states[target] {
+                    Some(ref mut existing) => existing.merge(&state, target, self.hierarchy)?,
+                    None => true,
+                };
+
+                if changed {
+                    if self.states[target].is_none() {
+                        self.states[target] = Some(state);
+                    }
+
+                    if queued.insert(target) {
+                        queue.push_back(target);
+                    }
+                }
+            }
+        }
+
+        Ok(Analysis {
+            states: self.states,
+            labels: self.labels,
+            max_stack: self.max_stack,
+            max_locals: self.max_locals,
+        })
+    }
+
+    /// Simulate a single instruction. Returns the state after the instruction (`None` if it
+    /// doesn't complete normally) and the positions execution may continue at.
+    fn execute(
+        &self,
+        idx: usize,
+        insn: &Insn,
+        mut state: State,
+    ) -> Result<(Option<State>, Vec<usize>), ModelError> {
+        let next = vec![idx + 1];
+
+        match *insn {
+            Insn::Label(_) | Insn::LineNumber(_) | Insn::Frame(_) => {
+                // A label at the very end of the code doesn't lead anywhere
+                if idx + 1 == self.code.instructions.len() {
+                    return Ok((Some(state), vec![]));
+                }
+            }
+            Insn::Op(ref instruction) => {
+                if ends_flow(instruction) {
+                    return Ok((None, vec![]));
+                }
+
+                self.execute_op(idx, instruction, &mut state)?;
+            }
+            Insn::Jump(jump, ref target) => {
+                if jump == Jump::Jsr {
+                    return Err(ModelError::InvalidInstruction(Instruction::JSR(0)));
+                }
+
+                for _ in 0..jump.operands() {
+                    state.pop(idx)?;
+                }
+
+                let target = self.label(target)?;
+
+                if jump.is_conditional() {
+                    return Ok((Some(state), vec![idx + 1, target]));
+                } else {
+                    return Ok((Some(state), vec![target]));
+                }
+            }
+            Insn::TableSwitch {
+                ref default,
+                ref targets,
+                ..
+            } => {
+                state.pop(idx)?;
+
+                let mut successors = vec![self.label(default)?];
+
+                for target in targets {
+                    successors.push(self.label(target)?);
+                }
+
+                return Ok((Some(state), successors));
+            }
+            Insn::LookupSwitch {
+                ref default,
+                ref pairs,
+            } => {
+                state.pop(idx)?;
+
+                let mut successors = vec![self.label(default)?];
+
+                for (_, target) in pairs {
+                    successors.push(self.label(target)?);
+                }
+
+                return Ok((Some(state), successors));
+            }
+            Insn::Ldc(ref value) => state.push(match *value {
+                Value::Int(_) => FrameItem::Integer,
+                Value::Float(_) => FrameItem::Float,
+                Value::Long(_) => FrameItem::Long,
+                Value::Double(_) => FrameItem::Double,
+                Value::String(_) => FrameItem::Object("java/lang/String".to_string()),
+                Value::Class(_) => FrameItem::Object("java/lang/Class".to_string()),
+                Value::MethodType(_) => {
+                    FrameItem::Object("java/lang/invoke/MethodType".to_string())
+                }
+                Value::MethodHandle(_) => {
+                    FrameItem::Object("java/lang/invoke/MethodHandle".to_string())
+                }
+            }),
+            Insn::Field(op, ref member) => {
+                let field_type = JavaType::parse(&member.descriptor)
+                    .ok_or(ModelError::InvalidDescriptor(member.descriptor.clone()))?;
+
+                match op {
+                    FieldOp::GetStatic => state.push_type(&field_type),
+                    FieldOp::PutStatic => state.pop_slots(field_type.size() as usize, idx)?,
+                    FieldOp::GetField => {
+                        state.pop(idx)?;
+                        state.push_type(&field_type);
+                    }
+                    FieldOp::PutField => {
+                        state.pop_slots(field_type.size() as usize, idx)?;
+                        state.pop(idx)?;
+                    }
+                }
+            }
+            Insn::Invoke(kind, ref member) => {
+                let descriptor = parse_method_descriptor(&member.descriptor)?;
+
+                state.pop_slots(descriptor.parameter_size() as usize, idx)?;
+
+                if kind != Invoke::Static {
+                    let receiver = state.pop(idx)?;
+
+                    if kind == Invoke::Special && member.name == "<init>" {
+                        let initialized = match receiver {
+                            FrameItem::UninitializedThis => {
+                                FrameItem::Object(self.class_name.to_string())
+                            }
+                            FrameItem::Uninitialized(ref label) => FrameItem::Object(
+                                self.allocations
+                                    .get(label)
+                                    .cloned()
+                                    .unwrap_or(member.owner.clone()),
+                            ),
+                            ref other => other.clone(),
+                        };
+
+                        state.initialize(&receiver, &initialized);
+                    }
+                }
+
+                state.push_type(&descriptor.return_type);
+            }
+            Insn::InvokeDynamic(ref call_site) => {
+                let descriptor = parse_method_descriptor(&call_site.descriptor)?;
+
+                state.pop_slots(descriptor.parameter_size() as usize, idx)?;
+                state.push_type(&descriptor.return_type);
+            }
+            Insn::Type(op, ref class) => match op {
+                TypeOp::New => {
+                    let label = preceding_label(&self.code.instructions, idx)
+                        .ok_or(ModelError::InvalidOffset(idx))?;
+
+                    state.push(FrameItem::Uninitialized(label));
+                }
+                TypeOp::ANewArray => {
+                    state.pop(idx)?;
+                    state.push(FrameItem::Object(
+                        JavaType::Array(Box::new(JavaType::from_internal_name(class)))
+                            .internal_name(),
+                    ));
+                }
+                TypeOp::CheckCast => {
+                    state.pop(idx)?;
+                    state.push(FrameItem::Object(class.clone()));
+                }
+                TypeOp::InstanceOf => {
+                    state.pop(idx)?;
+                    state.push(FrameItem::Integer);
+                }
+            },
+            Insn::MultiANewArray(ref class, dimensions) => {
+                state.pop_slots(dimensions as usize, idx)?;
+                state.push(FrameItem::Object(class.clone()));
+            }
+        }
+
+        Ok((Some(state), next))
+    }
+
+    fn execute_op(
+        &self,
+        idx: usize,
+        instruction: &Instruction,
+        state: &mut State,
+    ) -> Result<(), ModelError> {
+        use self::FrameItem::*;
+        use super::super::bytecode::classfile::Instruction::*;
+
+        match *instruction {
+            NOP | IINC(_, _) | IINC_W(_, _) => (),
+
+            ACONST_NULL => state.push(Null),
+            ICONST_M1 | ICONST_0 | ICONST_1 | ICONST_2 | ICONST_3 | ICONST_4 | ICONST_5
+            | BIPUSH(_) | SIPUSH(_) => state.push(Integer),
+            LCONST_0 | LCONST_1 => state.push(Long),
+            FCONST_0 | FCONST_1 | FCONST_2 => state.push(Float),
+            DCONST_0 | DCONST_1 => state.push(Double),
+
+            ILOAD(_) | ILOAD_0 | ILOAD_1 | ILOAD_2 | ILOAD_3 | ILOAD_W(_) => {
+                state.push(Integer)
+            }
+            LLOAD(_) | LLOAD_0 | LLOAD_1 | LLOAD_2 | LLOAD_3 | LLOAD_W(_) => {
+                state.push(Long)
+            }
+            FLOAD(_) | FLOAD_0 | FLOAD_1 | FLOAD_2 | FLOAD_3 | FLOAD_W(_) => {
+                state.push(Float)
+            }
+            DLOAD(_) | DLOAD_0 | DLOAD_1 | DLOAD_2 | DLOAD_3 | DLOAD_W(_) => {
+                state.push(Double)
+            }
+            ALOAD(_) | ALOAD_0 | ALOAD_1 | ALOAD_2 | ALOAD_3 | ALOAD_W(_) => {
+                let index = local_index(instruction);
+
+                state.push(match state.load(index) {
+                    Top => Object(OBJECT.to_string()),
+                    other => other,
+                });
+            }
+
+            ISTORE(_) | ISTORE_0 | ISTORE_1 | ISTORE_2 | ISTORE_3 | ISTORE_W(_)
+            | LSTORE(_) | LSTORE_0 | LSTORE_1 | LSTORE_2 | LSTORE_3 | LSTORE_W(_)
+            | FSTORE(_) | FSTORE_0 | FSTORE_1 | FSTORE_2 | FSTORE_3 | FSTORE_W(_)
+            | DSTORE(_) | DSTORE_0 | DSTORE_1 | DSTORE_2 | DSTORE_3 | DSTORE_W(_)
+            | ASTORE(_) | ASTORE_0 | ASTORE_1 | ASTORE_2 | ASTORE_3 | ASTORE_W(_) => {
+                let value = state.pop(idx)?;
+                state.store(local_index(instruction), value);
+            }
+
+            IALOAD | BALOAD | CALOAD | SALOAD => binary(state, idx, 2, Integer)?,
+            LALOAD => binary(state, idx, 2, Long)?,
+            FALOAD => binary(state, idx, 2, Float)?,
+            DALOAD => binary(state, idx, 2, Double)?,
+            AALOAD => {
+                state.pop(idx)?;
+
+                let component = match state.pop(idx)? {
+                    Null => Null,
+                    Object(ref array) if array.starts_with('[') => {
+                        match JavaType::parse(array) {
+                            Some(JavaType::Array(component)) => Object(component.internal_name()),
+                            _ => Object(OBJECT.to_string()),
+                        }
+                    }
+                    _ => Object(OBJECT.to_string()),
+                };
+
+                state.push(component);
+            }
+
+            IASTORE | BASTORE | CASTORE | SASTORE | FASTORE | AASTORE => {
+                state.pop_slots(3, idx)?
+            }
+            LASTORE | DASTORE => state.pop_slots(4, idx)?,
+
+            POP => state.pop_slots(1, idx)?,
+            POP2 => state.pop_slots(2, idx)?,
+            DUP => {
+                let a = state.pop_slot(idx)?;
+                state.stack.extend(vec![a.clone(), a]);
+            }
+            DUP_X1 => {
+                let a = state.pop_slot(idx)?;
+                let b = state.pop_slot(idx)?;
+                state.stack.extend(vec![a.clone(), b, a]);
+            }
+            DUP_X2 => {
+                let a = state.pop_slot(idx)?;
+                let b = state.pop_slot(idx)?;
+                let c = state.pop_slot(idx)?;
+                state.stack.extend(vec![a.clone(), c, b, a]);
+            }
+            DUP2 => {
+                let a = state.pop_slot(idx)?;
+                let b = state.pop_slot(idx)?;
+                state.stack.extend(vec![b.clone(), a.clone(), b, a]);
+            }
+            DUP2_X1 => {
+                let a = state.pop_slot(idx)?;
+                let b = state.pop_slot(idx)?;
+                let c = state.pop_slot(idx)?;
+                state.stack.extend(vec![b.clone(), a.clone(), c, b, a]);
+            }
+            DUP2_X2 => {
+                let a = state.pop_slot(idx)?;
+                let b = state.pop_slot(idx)?;
+                let c = state.pop_slot(idx)?;
+                let d = state.pop_slot(idx)?;
+                state.stack.extend(vec![b.clone(), a.clone(), d, c, b, a]);
+            }
+            SWAP => {
+                let a = state.pop_slot(idx)?;
+                let b = state.pop_slot(idx)?;
+                state.stack.extend(vec![a, b]);
+            }
+
+            IADD | ISUB | IMUL | IDIV | IREM | ISHL | ISHR | IUSHR | IAND | IOR
+            | IXOR => binary(state, idx, 2, Integer)?,
+            LADD | LSUB | LMUL | LDIV | LREM | LAND | LOR | LXOR => {
+                binary(state, idx, 4, Long)?
+            }
+            LSHL | LSHR | LUSHR => binary(state, idx, 3, Long)?,
+            FADD | FSUB | FMUL | FDIV | FREM => binary(state, idx, 2, Float)?,
+            DADD | DSUB | DMUL | DDIV | DREM => binary(state, idx, 4, Double)?,
+            INEG | I2B | I2C | I2S => binary(state, idx, 1, Integer)?,
+            LNEG => binary(state, idx, 2, Long)?,
+            FNEG => binary(state, idx, 1, Float)?,
+            DNEG => binary(state, idx, 2, Double)?,
+
+            I2L => binary(state, idx, 1, Long)?,
+            I2F => binary(state, idx, 1, Float)?,
+            I2D => binary(state, idx, 1, Double)?,
+            L2I => binary(state, idx, 2, Integer)?,
+            L2F => binary(state, idx, 2, Float)?,
+            L2D => binary(state, idx, 2, Double)?,
+            F2I => binary(state, idx, 1, Integer)?,
+            F2L => binary(state, idx, 1, Long)?,
+            F2D => binary(state, idx, 1, Double)?,
+            D2I => binary(state, idx, 2, Integer)?,
+            D2L => binary(state, idx, 2, Long)?,
+            D2F => binary(state, idx, 2, Float)?,
+
+            LCMP | DCMPL | DCMPG => binary(state, idx, 4, Integer)?,
+            FCMPL | FCMPG => binary(state, idx, 2, Integer)?,
+
+            ARRAYLENGTH => binary(state, idx, 1, Integer)?,
+            MONITORENTER | MONITOREXIT => state.pop_slots(1, idx)?,
+            NEWARRAY(atype) => {
+                state.pop(idx)?;
+                state.push(Object(
+                    match atype {
+                        4 => "[Z",
+                        5 => "[C",
+                        6 => "[F",
+                        7 => "[D",
+                        8 => "[B",
+                        9 => "[S",
+                        10 => "[I",
+                        11 => "[J",
+                        _ => return Err(ModelError::InvalidInstruction(instruction.clone())),
+                    }
+                    .to_string(),
+                ));
+            }
+
+            ref other => return Err(ModelError::InvalidInstruction(other.clone())),
+        }
+
+        Ok(())
+    }
+}
+
+/// Pop the given number of stack slots and push a single result
+fn binary(
+    state: &mut State,
+    idx: usize,
+    slots: usize,
+    result: FrameItem,
+) -> Result<(), ModelError> {
+    state.pop_slots(slots, idx)?;
+    state.push(result);
+    Ok(())
+}
+
+fn parse_method_descriptor(descriptor: &str) -> Result<MethodDescriptor, ModelError> {
+    MethodDescriptor::parse(descriptor).ok_or(ModelError::InvalidDescriptor(descriptor.to_string()))
+}
+
+/// Local variable index of a load or store instruction
+fn local_index(instruction: &Instruction) -> usize {
+    use super::super::bytecode::classfile::Instruction::*;
+
+    match *instruction {
+        ILOAD_0 | LLOAD_0 | FLOAD_0 | DLOAD_0 | ALOAD_0 | ISTORE_0 | LSTORE_0
+        | FSTORE_0 | DSTORE_0 | ASTORE_0 => 0,
+        ILOAD_1 | LLOAD_1 | FLOAD_1 | DLOAD_1 | ALOAD_1 | ISTORE_1 | LSTORE_1
+        | FSTORE_1 | DSTORE_1 | ASTORE_1 => 1,
+        ILOAD_2 | LLOAD_2 | FLOAD_2 | DLOAD_2 | ALOAD_2 | ISTORE_2 | LSTORE_2
+        | FSTORE_2 | DSTORE_2 | ASTORE_2 => 2,
+        ILOAD_3 | LLOAD_3 | FLOAD_3 | DLOAD_3 | ALOAD_3 | ISTORE_3 | LSTORE_3
+        | FSTORE_3 | DSTORE_3 | ASTORE_3 => 3,
+        ILOAD(index) | LLOAD(index) | FLOAD(index) | DLOAD(index) | ALOAD(index)
+        | ISTORE(index) | LSTORE(index) | FSTORE(index) | DSTORE(index)
+        | ASTORE(index) => index as usize,
+        ILOAD_W(index) | LLOAD_W(index) | FLOAD_W(index) | DLOAD_W(index)
+        | ALOAD_W(index) | ISTORE_W(index) | LSTORE_W(index) | FSTORE_W(index)
+        | DSTORE_W(index) | ASTORE_W(index) => index as usize,
+        _ => 0,
+    }
+}
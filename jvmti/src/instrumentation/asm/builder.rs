@@ -0,0 +1,785 @@
+use super::super::super::bytecode::classfile::{
+    AccessFlags, ClassAccessFlags, Classfile, FieldAccessFlags, Instruction, MethodAccessFlags,
+};
+use super::super::analysis;
+use super::super::code::*;
+use super::super::descriptor::{JavaType, MethodDescriptor};
+use super::super::{Field, JavaClass, Method, ModelError};
+use super::ClassfileVersion;
+
+///
+/// Fluent builder for generating classes from scratch, eg.
+///
+/// ```ignore
+/// ClassBuilder::new("com/acme/Probe")
+///     .public()
+///     .method("enter", "(J)V", |m| m.public().static_().lload(0).invokestatic("com/acme/Agent", "enter", "(J)V").ret())
+///     .to_classfile()
+/// ```
+///
+/// `max_stack`, `max_locals` and stack map frames are computed when the class is built.
+pub struct ClassBuilder {
+    class: JavaClass,
+    version: ClassfileVersion,
+    error: Option<ModelError>,
+}
+
+impl ClassBuilder {
+    pub fn new(name: &str) -> ClassBuilder {
+        let mut class = JavaClass::new();
+
+        class.name = name.to_string();
+        class.access_flags = AccessFlags::of(ClassAccessFlags::Super as u16);
+
+        ClassBuilder {
+            class,
+            version: ClassfileVersion::Java1_8,
+            error: None,
+        }
+    }
+
+    pub fn version(mut self, version: ClassfileVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn access(mut self, flags: u16) -> Self {
+        self.class.access_flags.set_flag(flags);
+        self
+    }
+
+    pub fn public(self) -> Self {
+        self.access(ClassAccessFlags::Public as u16)
+    }
+
+    pub fn final_(self) -> Self {
+        self.access(ClassAccessFlags::Final as u16)
+    }
+
+    pub fn abstract_(self) -> Self {
+        self.access(ClassAccessFlags::Abstract as u16)
+    }
+
+    pub fn synthetic(self) -> Self {
+        self.access(ClassAccessFlags::Synthetic as u16)
+    }
+
+    pub fn interface(mut self) -> Self {
+        self.class
+            .access_flags
+            .clear_flag(ClassAccessFlags::Super as u16);
+        self.access(ClassAccessFlags::Interface as u16 | ClassAccessFlags::Abstract as u16)
+    }
+
+    pub fn extends(mut self, super_name: &str) -> Self {
+        self.class.super_name = Some(super_name.to_string());
+        self
+    }
+
+    pub fn implements(mut self, interface: &str) -> Self {
+        self.class.add_interface(interface);
+        self
+    }
+
+    pub fn source_file(mut self, source_file: &str) -> Self {
+        self.class.source_file = Some(source_file.to_string());
+        self
+    }
+
+    pub fn field<F>(mut self, name: &str, descriptor: &str, build: F) -> Self
+    where
+        F: FnOnce(FieldBuilder) -> FieldBuilder,
+    {
+        match JavaType::parse(descriptor) {
+            Some(field_type) => {
+                let builder = build(FieldBuilder::new(name, field_type));
+                self.class.add_field(builder.field);
+            }
+            None => self.fail(ModelError::InvalidDescriptor(descriptor.to_string())),
+        }
+
+        self
+    }
+
+    pub fn method<F>(mut self, name: &str, descriptor: &str, build: F) -> Self
+    where
+        F: FnOnce(MethodBuilder) -> MethodBuilder,
+    {
+        match MethodDescriptor::parse(descriptor) {
+            Some(descriptor) => match build(MethodBuilder::new(name, descriptor)).build() {
+                Ok(method) => self.class.add_method(method),
+                Err(err) => self.fail(err),
+            },
+            None => self.fail(ModelError::InvalidDescriptor(descriptor.to_string())),
+        }
+
+        self
+    }
+
+    /// Add a public no-argument constructor that calls the super class constructor
+    pub fn default_constructor(self) -> Self {
+        let super_name = self
+            .class
+            .super_name
+            .clone()
+            .unwrap_or("java/lang/Object".to_string());
+
+        self.method("<init>", "()V", |m| {
+            m.public()
+                .aload(0)
+                .invokespecial(&super_name, "<init>", "()V")
+                .ret()
+        })
+    }
+
+    fn fail(&mut self, error: ModelError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Finish the class, computing the stack sizes and frames of every method
+    pub fn build(mut self) -> Result<JavaClass, ModelError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        self.class.version = self.version.to_version();
+
+        let name = self.class.name.clone();
+
+        for method in self.class.methods.iter_mut() {
+            if self.version.has_frames() {
+                analysis::compute_frames(&name, method)?;
+            } else {
+                analysis::compute_maxs(&name, method)?;
+            }
+        }
+
+        Ok(self.class)
+    }
+
+    pub fn to_classfile(self) -> Result<Classfile, ModelError> {
+        self.build()?.to_classfile()
+    }
+}
+
+pub struct FieldBuilder {
+    field: Field,
+}
+
+impl FieldBuilder {
+    fn new(name: &str, field_type: JavaType) -> FieldBuilder {
+        FieldBuilder {
+            field: Field::new(name.to_string(), field_type),
+        }
+    }
+
+    pub fn access(mut self, flags: u16) -> Self {
+        self.field.access_flags.set_flag(flags);
+        self
+    }
+
+    pub fn public(self) -> Self {
+        self.access(FieldAccessFlags::Public as u16)
+    }
+
+    pub fn private(self) -> Self {
+        self.access(FieldAccessFlags::Private as u16)
+    }
+
+    pub fn protected(self) -> Self {
+        self.access(FieldAccessFlags::Protected as u16)
+    }
+
+    pub fn static_(self) -> Self {
+        self.access(FieldAccessFlags::Static as u16)
+    }
+
+    pub fn final_(self) -> Self {
+        self.access(FieldAccessFlags::Final as u16)
+    }
+
+    pub fn volatile(self) -> Self {
+        self.access(FieldAccessFlags::Volatile as u16)
+    }
+
+    pub fn transient(self) -> Self {
+        self.access(FieldAccessFlags::Transient as u16)
+    }
+
+    pub fn synthetic(self) -> Self {
+        self.access(FieldAccessFlags::Synthetic as u16)
+    }
+
+    /// Initial value of a static final field
+    pub fn constant(mut self, value: Value) -> Self {
+        self.field.constant_value = Some(value);
+        self
+    }
+}
+
+///
+/// Fluent builder for a single method. Instructions are appended in order, branch targets are
+/// `Label`s that are allocated with `new_label` and placed with `mark`.
+pub struct MethodBuilder {
+    method: Method,
+    code: Code,
+    error: Option<ModelError>,
+}
+
+impl MethodBuilder {
+    pub fn new(name: &str, descriptor: MethodDescriptor) -> MethodBuilder {
+        MethodBuilder {
+            method: Method::new(name.to_string(), descriptor),
+            code: Code::new(),
+            error: None,
+        }
+    }
+
+    pub fn build(mut self) -> Result<Method, ModelError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        if !self.method.is_abstract() && !self.method.is_native() {
+            self.method.code = Some(self.code);
+        }
+
+        Ok(self.method)
+    }
+
+    pub fn access(mut self, flags: u16) -> Self {
+        self.method.access_flags.set_flag(flags);
+        self
+    }
+
+    pub fn public(self) -> Self {
+        self.access(MethodAccessFlags::Public as u16)
+    }
+
+    pub fn private(self) -> Self {
+        self.access(MethodAccessFlags::Private as u16)
+    }
+
+    pub fn protected(self) -> Self {
+        self.access(MethodAccessFlags::Protected as u16)
+    }
+
+    pub fn static_(self) -> Self {
+        self.access(MethodAccessFlags::Static as u16)
+    }
+
+    pub fn final_(self) -> Self {
+        self.access(MethodAccessFlags::Final as u16)
+    }
+
+    pub fn synchronized(self) -> Self {
+        self.access(MethodAccessFlags::Synchronized as u16)
+    }
+
+    pub fn native(self) -> Self {
+        self.access(MethodAccessFlags::Native as u16)
+    }
+
+    pub fn abstract_(self) -> Self {
+        self.access(MethodAccessFlags::Abstract as u16)
+    }
+
+    pub fn synthetic(self) -> Self {
+        self.access(MethodAccessFlags::Synthetic as u16)
+    }
+
+    pub fn varargs(self) -> Self {
+        self.access(MethodAccessFlags::Varargs as u16)
+    }
+
+    /// Declare a checked exception thrown by the method
+    pub fn throws(mut self, exception: &str) -> Self {
+        self.method.exceptions.push(exception.to_string());
+        self
+    }
+
+    /// Allocate a new label. It has to be placed with `mark` before the method is built.
+    pub fn new_label(&mut self) -> Label {
+        self.code.new_label()
+    }
+
+    pub fn mark(self, label: Label) -> Self {
+        self.insn(Insn::Label(label))
+    }
+
+    /// Attribute the following instructions to the given source line
+    pub fn line(self, line: u16) -> Self {
+        self.insn(Insn::LineNumber(line))
+    }
+
+    pub fn insn(mut self, insn: Insn) -> Self {
+        self.code.instructions.push(insn);
+        self
+    }
+
+    /// Append an instruction that has no constant pool or branch operands
+    pub fn op(mut self, instruction: Instruction) -> Self {
+        if is_plain_instruction(&instruction) {
+            self.insn(Insn::Op(instruction))
+        } else {
+            if self.error.is_none() {
+                self.error = Some(ModelError::InvalidInstruction(instruction));
+            }
+
+            self
+        }
+    }
+
+    pub fn try_catch(mut self, start: Label, end: Label, handler: Label, exception: Option<&str>) -> Self {
+        self.code.try_catch_blocks.push(TryCatchBlock {
+            start,
+            end,
+            handler,
+            catch_type: exception.map(|e| e.to_string()),
+        });
+        self
+    }
+
+    pub fn local_variable(
+        mut self,
+        name: &str,
+        descriptor: &str,
+        start: Label,
+        end: Label,
+        index: u16,
+    ) -> Self {
+        self.code.local_variables.push(LocalVariable {
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+            signature: None,
+            start,
+            end,
+            index,
+        });
+        self
+    }
+
+    // Constants
+
+    pub fn aconst_null(self) -> Self {
+        self.op(Instruction::ACONST_NULL)
+    }
+
+    pub fn iconst(self, value: i32) -> Self {
+        match value {
+            -1 => self.op(Instruction::ICONST_M1),
+            0 => self.op(Instruction::ICONST_0),
+            1 => self.op(Instruction::ICONST_1),
+            2 => self.op(Instruction::ICONST_2),
+            3 => self.op(Instruction::ICONST_3),
+            4 => self.op(Instruction::ICONST_4),
+            5 => self.op(Instruction::ICONST_5),
+            -128..=127 => self.op(Instruction::BIPUSH(value as i8 as u8)),
+            -32768..=32767 => self.op(Instruction::SIPUSH(value as i16 as u16)),
+            _ => self.ldc(Value::Int(value)),
+        }
+    }
+
+    pub fn lconst(self, value: i64) -> Self {
+        match value {
+            0 => self.op(Instruction::LCONST_0),
+            1 => self.op(Instruction::LCONST_1),
+            _ => self.ldc(Value::Long(value)),
+        }
+    }
+
+    pub fn fconst(self, value: f32) -> Self {
+        // Compare bit patterns so that -0.0 isn't mistaken for 0.0
+        if value.to_bits() == 0.0f32.to_bits() {
+            self.op(Instruction::FCONST_0)
+        } else if value == 1.0 {
+            self.op(Instruction::FCONST_1)
+        } else if value == 2.0 {
+            self.op(Instruction::FCONST_2)
+        } else {
+            self.ldc(Value::Float(value))
+        }
+    }
+
+    pub fn dconst(self, value: f64) -> Self {
+        if value.to_bits() == 0.0f64.to_bits() {
+            self.op(Instruction::DCONST_0)
+        } else if value == 1.0 {
+            self.op(Instruction::DCONST_1)
+        } else {
+            self.ldc(Value::Double(value))
+        }
+    }
+
+    pub fn ldc(self, value: Value) -> Self {
+        self.insn(Insn::Ldc(value))
+    }
+
+    pub fn ldc_string(self, value: &str) -> Self {
+        self.ldc(Value::String(value.to_string()))
+    }
+
+    // Local variables
+
+    fn local(
+        self,
+        index: u16,
+        short: [Instruction; 4],
+        byte: fn(u8) -> Instruction,
+        wide: fn(u16) -> Instruction,
+    ) -> Self {
+        if index < 4 {
+            self.op(short[index as usize].clone())
+        } else if index < 256 {
+            self.op(byte(index as u8))
+        } else {
+            self.op(wide(index))
+        }
+    }
+
+    pub fn iload(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [ILOAD_0, ILOAD_1, ILOAD_2, ILOAD_3], ILOAD, ILOAD_W)
+    }
+
+    pub fn lload(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [LLOAD_0, LLOAD_1, LLOAD_2, LLOAD_3], LLOAD, LLOAD_W)
+    }
+
+    pub fn fload(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [FLOAD_0, FLOAD_1, FLOAD_2, FLOAD_3], FLOAD, FLOAD_W)
+    }
+
+    pub fn dload(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [DLOAD_0, DLOAD_1, DLOAD_2, DLOAD_3], DLOAD, DLOAD_W)
+    }
+
+    pub fn aload(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [ALOAD_0, ALOAD_1, ALOAD_2, ALOAD_3], ALOAD, ALOAD_W)
+    }
+
+    pub fn istore(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [ISTORE_0, ISTORE_1, ISTORE_2, ISTORE_3], ISTORE, ISTORE_W)
+    }
+
+    pub fn lstore(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [LSTORE_0, LSTORE_1, LSTORE_2, LSTORE_3], LSTORE, LSTORE_W)
+    }
+
+    pub fn fstore(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [FSTORE_0, FSTORE_1, FSTORE_2, FSTORE_3], FSTORE, FSTORE_W)
+    }
+
+    pub fn dstore(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [DSTORE_0, DSTORE_1, DSTORE_2, DSTORE_3], DSTORE, DSTORE_W)
+    }
+
+    pub fn astore(self, index: u16) -> Self {
+        use super::super::super::bytecode::classfile::Instruction::*;
+        self.local(index, [ASTORE_0, ASTORE_1, ASTORE_2, ASTORE_3], ASTORE, ASTORE_W)
+    }
+
+    /// Load a value of the given type from a local variable
+    pub fn load(self, java_type: &JavaType, index: u16) -> Self {
+        match *java_type {
+            JavaType::Long => self.lload(index),
+            JavaType::Float => self.fload(index),
+            JavaType::Double => self.dload(index),
+            JavaType::Class(_) | JavaType::Array(_) => self.aload(index),
+            _ => self.iload(index),
+        }
+    }
+
+    /// Store a value of the given type into a local variable
+    pub fn store(self, java_type: &JavaType, index: u16) -> Self {
+        match *java_type {
+            JavaType::Long => self.lstore(index),
+            JavaType::Float => self.fstore(index),
+            JavaType::Double => self.dstore(index),
+            JavaType::Class(_) | JavaType::Array(_) => self.astore(index),
+            _ => self.istore(index),
+        }
+    }
+
+    pub fn iinc(self, index: u16, delta: i16) -> Self {
+        if index < 256 && delta >= i8::MIN as i16 && delta <= i8::MAX as i16 {
+            self.op(Instruction::IINC(index as u8, delta as i8))
+        } else {
+            self.op(Instruction::IINC_W(index, delta))
+        }
+    }
+
+    // Stack and arithmetic
+
+    pub fn pop(self) -> Self {
+        self.op(Instruction::POP)
+    }
+
+    pub fn pop2(self) -> Self {
+        self.op(Instruction::POP2)
+    }
+
+    pub fn dup(self) -> Self {
+        self.op(Instruction::DUP)
+    }
+
+    pub fn dup_x1(self) -> Self {
+        self.op(Instruction::DUP_X1)
+    }
+
+    pub fn dup2(self) -> Self {
+        self.op(Instruction::DUP2)
+    }
+
+    pub fn swap(self) -> Self {
+        self.op(Instruction::SWAP)
+    }
+
+    pub fn iadd(self) -> Self {
+        self.op(Instruction::IADD)
+    }
+
+    pub fn isub(self) -> Self {
+        self.op(Instruction::ISUB)
+    }
+
+    pub fn ladd(self) -> Self {
+        self.op(Instruction::LADD)
+    }
+
+    pub fn lsub(self) -> Self {
+        self.op(Instruction::LSUB)
+    }
+
+    pub fn i2l(self) -> Self {
+        self.op(Instruction::I2L)
+    }
+
+    pub fn l2i(self) -> Self {
+        self.op(Instruction::L2I)
+    }
+
+    pub fn arraylength(self) -> Self {
+        self.op(Instruction::ARRAYLENGTH)
+    }
+
+    pub fn aaload(self) -> Self {
+        self.op(Instruction::AALOAD)
+    }
+
+    pub fn aastore(self) -> Self {
+        self.op(Instruction::AASTORE)
+    }
+
+    pub fn monitorenter(self) -> Self {
+        self.op(Instruction::MONITORENTER)
+    }
+
+    pub fn monitorexit(self) -> Self {
+        self.op(Instruction::MONITOREXIT)
+    }
+
+    pub fn athrow(self) -> Self {
+        self.op(Instruction::ATHROW)
+    }
+
+    /// Return from the method with the return instruction matching its descriptor
+    pub fn ret(self) -> Self {
+        let instruction = match self.method.descriptor.return_type {
+            JavaType::Void => Instruction::RETURN,
+            JavaType::Long => Instruction::LRETURN,
+            JavaType::Float => Instruction::FRETURN,
+            JavaType::Double => Instruction::DRETURN,
+            JavaType::Class(_) | JavaType::Array(_) => Instruction::ARETURN,
+            _ => Instruction::IRETURN,
+        };
+
+        self.op(instruction)
+    }
+
+    // Fields and methods
+
+    pub fn getstatic(self, owner: &str, name: &str, descriptor: &str) -> Self {
+        self.insn(Insn::Field(FieldOp::GetStatic, MemberRef::new(owner, name, descriptor)))
+    }
+
+    pub fn putstatic(self, owner: &str, name: &str, descriptor: &str) -> Self {
+        self.insn(Insn::Field(FieldOp::PutStatic, MemberRef::new(owner, name, descriptor)))
+    }
+
+    pub fn getfield(self, owner: &str, name: &str, descriptor: &str) -> Self {
+        self.insn(Insn::Field(FieldOp::GetField, MemberRef::new(owner, name, descriptor)))
+    }
+
+    pub fn putfield(self, owner: &str, name: &str, descriptor: &str) -> Self {
+        self.insn(Insn::Field(FieldOp::PutField, MemberRef::new(owner, name, descriptor)))
+    }
+
+    pub fn invokevirtual(self, owner: &str, name: &str, descriptor: &str) -> Self {
+        self.insn(Insn::Invoke(Invoke::Virtual, MemberRef::new(owner, name, descriptor)))
+    }
+
+    pub fn invokespecial(self, owner: &str, name: &str, descriptor: &str) -> Self {
+        self.insn(Insn::Invoke(Invoke::Special, MemberRef::new(owner, name, descriptor)))
+    }
+
+    pub fn invokestatic(self, owner: &str, name: &str, descriptor: &str) -> Self {
+        self.insn(Insn::Invoke(Invoke::Static, MemberRef::new(owner, name, descriptor)))
+    }
+
+    pub fn invokeinterface(self, owner: &str, name: &str, descriptor: &str) -> Self {
+        self.insn(Insn::Invoke(
+            Invoke::Interface,
+            MemberRef::interface(owner, name, descriptor),
+        ))
+    }
+
+    pub fn invokedynamic(self, call_site: CallSite) -> Self {
+        self.insn(Insn::InvokeDynamic(call_site))
+    }
+
+    // Objects and arrays
+
+    pub fn new_(self, class: &str) -> Self {
+        self.insn(Insn::Type(TypeOp::New, class.to_string()))
+    }
+
+    pub fn anewarray(self, class: &str) -> Self {
+        self.insn(Insn::Type(TypeOp::ANewArray, class.to_string()))
+    }
+
+    pub fn checkcast(self, class: &str) -> Self {
+        self.insn(Insn::Type(TypeOp::CheckCast, class.to_string()))
+    }
+
+    pub fn instanceof(self, class: &str) -> Self {
+        self.insn(Insn::Type(TypeOp::InstanceOf, class.to_string()))
+    }
+
+    /// Create a new array of the given primitive type, or of references for other types
+    pub fn newarray(self, component: &JavaType) -> Self {
+        let atype = match *component {
+            JavaType::Boolean => 4,
+            JavaType::Char => 5,
+            JavaType::Float => 6,
+            JavaType::Double => 7,
+            JavaType::Byte => 8,
+            JavaType::Short => 9,
+            JavaType::Int => 10,
+            JavaType::Long => 11,
+            ref other => return self.anewarray(&other.internal_name()),
+        };
+
+        self.op(Instruction::NEWARRAY(atype))
+    }
+
+    pub fn multianewarray(self, descriptor: &str, dimensions: u8) -> Self {
+        self.insn(Insn::MultiANewArray(descriptor.to_string(), dimensions))
+    }
+
+    // Control flow
+
+    pub fn jump(self, jump: Jump, target: Label) -> Self {
+        self.insn(Insn::Jump(jump, target))
+    }
+
+    pub fn goto(self, target: Label) -> Self {
+        self.jump(Jump::Goto, target)
+    }
+
+    pub fn ifeq(self, target: Label) -> Self {
+        self.jump(Jump::IfEq, target)
+    }
+
+    pub fn ifne(self, target: Label) -> Self {
+        self.jump(Jump::IfNe, target)
+    }
+
+    pub fn iflt(self, target: Label) -> Self {
+        self.jump(Jump::IfLt, target)
+    }
+
+    pub fn ifge(self, target: Label) -> Self {
+        self.jump(Jump::IfGe, target)
+    }
+
+    pub fn ifgt(self, target: Label) -> Self {
+        self.jump(Jump::IfGt, target)
+    }
+
+    pub fn ifle(self, target: Label) -> Self {
+        self.jump(Jump::IfLe, target)
+    }
+
+    pub fn if_icmpeq(self, target: Label) -> Self {
+        self.jump(Jump::IfICmpEq, target)
+    }
+
+    pub fn if_icmpne(self, target: Label) -> Self {
+        self.jump(Jump::IfICmpNe, target)
+    }
+
+    pub fn if_icmplt(self, target: Label) -> Self {
+        self.jump(Jump::IfICmpLt, target)
+    }
+
+    pub fn if_icmpge(self, target: Label) -> Self {
+        self.jump(Jump::IfICmpGe, target)
+    }
+
+    pub fn if_icmpgt(self, target: Label) -> Self {
+        self.jump(Jump::IfICmpGt, target)
+    }
+
+    pub fn if_icmple(self, target: Label) -> Self {
+        self.jump(Jump::IfICmpLe, target)
+    }
+
+    pub fn if_acmpeq(self, target: Label) -> Self {
+        self.jump(Jump::IfACmpEq, target)
+    }
+
+    pub fn if_acmpne(self, target: Label) -> Self {
+        self.jump(Jump::IfACmpNe, target)
+    }
+
+    pub fn ifnull(self, target: Label) -> Self {
+        self.jump(Jump::IfNull, target)
+    }
+
+    pub fn ifnonnull(self, target: Label) -> Self {
+        self.jump(Jump::IfNonNull, target)
+    }
+
+    /// Jump to `targets[value - low]`, or to `default` if the value is out of range
+    pub fn tableswitch(self, low: i32, default: Label, targets: Vec<Label>) -> Self {
+        let high = low + targets.len() as i32 - 1;
+
+        self.insn(Insn::TableSwitch {
+            default,
+            low,
+            high,
+            targets,
+        })
+    }
+
+    pub fn lookupswitch(self, default: Label, mut pairs: Vec<(i32, Label)>) -> Self {
+        // The JVM requires the keys in ascending order
+        pairs.sort_by_key(|&(key, _)| key);
+
+        self.insn(Insn::LookupSwitch {
+            default,
+            pairs,
+        })
+    }
+}
@@ -1,13 +1,89 @@
 use super::super::bytecode::classfile::Classfile as ClassfileImpl;
+use super::super::bytecode::classfile::ClassfileVersion as VersionImpl;
+use super::super::bytecode::classfile::ConstantPoolIndex;
+use super::constants::ConstantPoolBuilder;
 
+mod builder;
 pub mod transformer;
 
+pub use self::builder::{ClassBuilder, FieldBuilder, MethodBuilder};
+pub use super::Method;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ClassfileVersion {
     Java1_5,
     Java1_6,
     Java1_7,
     Java1_8,
     Java1_9,
+    Java10,
+    Java11,
+    Java12,
+    Java13,
+    Java14,
+    Java15,
+    Java16,
+    Java17,
+    Java18,
+    Java19,
+    Java20,
+    Java21,
+}
+
+impl ClassfileVersion {
+    pub fn major_version(&self) -> u16 {
+        match *self {
+            ClassfileVersion::Java1_5 => 49,
+            ClassfileVersion::Java1_6 => 50,
+            ClassfileVersion::Java1_7 => 51,
+            ClassfileVersion::Java1_8 => 52,
+            ClassfileVersion::Java1_9 => 53,
+            ClassfileVersion::Java10 => 54,
+            ClassfileVersion::Java11 => 55,
+            ClassfileVersion::Java12 => 56,
+            ClassfileVersion::Java13 => 57,
+            ClassfileVersion::Java14 => 58,
+            ClassfileVersion::Java15 => 59,
+            ClassfileVersion::Java16 => 60,
+            ClassfileVersion::Java17 => 61,
+            ClassfileVersion::Java18 => 62,
+            ClassfileVersion::Java19 => 63,
+            ClassfileVersion::Java20 => 64,
+            ClassfileVersion::Java21 => 65,
+        }
+    }
+
+    pub fn from_major_version(major_version: u16) -> Option<ClassfileVersion> {
+        match major_version {
+            49 => Some(ClassfileVersion::Java1_5),
+            50 => Some(ClassfileVersion::Java1_6),
+            51 => Some(ClassfileVersion::Java1_7),
+            52 => Some(ClassfileVersion::Java1_8),
+            53 => Some(ClassfileVersion::Java1_9),
+            54 => Some(ClassfileVersion::Java10),
+            55 => Some(ClassfileVersion::Java11),
+            56 => Some(ClassfileVersion::Java12),
+            57 => Some(ClassfileVersion::Java13),
+            58 => Some(ClassfileVersion::Java14),
+            59 => Some(ClassfileVersion::Java15),
+            60 => Some(ClassfileVersion::Java16),
+            61 => Some(ClassfileVersion::Java17),
+            62 => Some(ClassfileVersion::Java18),
+            63 => Some(ClassfileVersion::Java19),
+            64 => Some(ClassfileVersion::Java20),
+            65 => Some(ClassfileVersion::Java21),
+            _ => None,
+        }
+    }
+
+    /// Stack map frames are required from Java 7 onwards and understood from Java 6
+    pub fn has_frames(&self) -> bool {
+        *self >= ClassfileVersion::Java1_6
+    }
+
+    pub fn to_version(&self) -> VersionImpl {
+        VersionImpl::new(self.major_version(), 0)
+    }
 }
 
 pub struct Class {
@@ -32,15 +108,8 @@ impl Class {
     pub fn to_classfile(&self) -> ClassfileImpl {
         let mut cf = ClassfileImpl::new();
 
-        cf.version.major_version = match &self.version {
-            ClassfileVersion::Java1_5 => 49,
-            ClassfileVersion::Java1_6 => 50,
-            ClassfileVersion::Java1_7 => 51,
-            ClassfileVersion::Java1_8 => 52,
-            ClassfileVersion::Java1_9 => 53,
-        };
-
-        cf.version.minor_version = 0;
+        cf.version = self.version.to_version();
+        cf.constant_pool = self.constant_pool.builder.clone().into_parts().0;
 
         cf
     }
@@ -51,22 +120,33 @@ impl Class {
     }
 }
 
-pub struct ConstantPool {}
+pub struct ConstantPool {
+    builder: ConstantPoolBuilder,
+}
 
 impl ConstantPool {
     pub fn new() -> ConstantPool {
-        ConstantPool {}
+        ConstantPool {
+            builder: ConstantPoolBuilder::new(),
+        }
     }
 
-    pub fn add_utf8_constant(&mut self, content: String) {}
+    /// Add a `CONSTANT_Utf8` entry unless it's already present and return its index
+    pub fn add_utf8_constant(&mut self, content: String) -> ConstantPoolIndex {
+        self.builder.utf8(&content)
+    }
 
-    pub fn add_string_constant(&mut self, content: String) {}
-}
+    /// Add a `CONSTANT_String` entry (along with its `CONSTANT_Utf8`) and return its index
+    pub fn add_string_constant(&mut self, content: String) -> ConstantPoolIndex {
+        self.builder.string(&content)
+    }
 
-pub struct Method {}
+    /// Number of slots used in the pool, including the unused 0th entry
+    pub fn len(&self) -> usize {
+        self.builder.len()
+    }
 
-impl Method {
-    pub fn new() -> Method {
-        Method {}
+    pub fn is_empty(&self) -> bool {
+        self.builder.is_empty()
     }
 }
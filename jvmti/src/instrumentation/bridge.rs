@@ -0,0 +1,280 @@
+//!
+//! Lets instrumented bytecode call back into the agent. A `NativeBridge` generates a helper class
+//! with `public static native` methods, gets it loaded in the bootstrap class loader, so that
+//! classes of any class loader can see it, and binds its methods to Rust functions.
+//!
+//! There are two ways to load the helper:
+//!
+//! * `define` uses JNI `DefineClass` and binds the natives right away. It needs a `JNIEnv`, so it
+//!   can only be used in the live phase, eg. from the `VMInit` handler.
+//! * `append_to_bootstrap` writes the class into a temporary JAR and adds it to the bootstrap
+//!   class loader search path. This already works in `Agent_OnLoad`, so classes loaded early can
+//!   refer to the helper as well. The natives are bound later with `bind`, and calls made before
+//!   that fail with an `UnsatisfiedLinkError`.
+//!
+//! ```ignore
+//! extern "C" fn hit(_jni: JNIEnvPtr, _class: JavaClass, id: jint) { ... }
+//!
+//! let bridge = NativeBridge::new("com/acme/agent/Bridge").method("hit", "(I)V", hit as *mut c_void);
+//! let class = bridge.define(&JNIEnvironment::new(jni))?;
+//! ```
+
+use super::super::bytecode::io::ClassWriter;
+use super::super::class::ClassId;
+use super::super::environment::jni::{JNIError, NativeMethod, JNI};
+use super::super::environment::jvmti::JVMTI;
+use super::super::error::NativeError;
+use super::super::native::JavaObject;
+use super::asm::{ClassBuilder, ClassfileVersion};
+use super::{JavaClass, ModelError};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+
+///
+/// Describes why a helper class couldn't be loaded or bound
+#[derive(Debug)]
+pub enum BridgeError {
+    /// The helper class couldn't be generated, eg. because of an invalid method descriptor
+    Model(ModelError),
+    /// The temporary JAR couldn't be written
+    Io(String),
+    Jni(JNIError),
+    /// The JAR couldn't be added to the bootstrap class loader search path. This is only
+    /// possible in the `OnLoad` and live phases.
+    Jvmti(NativeError),
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BridgeError::Model(ref err) => write!(f, "Couldn't generate the helper class: {:?}", err),
+            BridgeError::Io(ref err) => write!(f, "Couldn't write the helper JAR: {}", err),
+            BridgeError::Jni(ref err) => write!(f, "JNI error: {:?}", err),
+            BridgeError::Jvmti(ref err) => write!(f, "JVMTI error: {:?}", err),
+        }
+    }
+}
+
+impl From<ModelError> for BridgeError {
+    fn from(err: ModelError) -> BridgeError {
+        BridgeError::Model(err)
+    }
+}
+
+impl From<io::Error> for BridgeError {
+    fn from(err: io::Error) -> BridgeError {
+        BridgeError::Io(err.to_string())
+    }
+}
+
+impl From<JNIError> for BridgeError {
+    fn from(err: JNIError) -> BridgeError {
+        BridgeError::Jni(err)
+    }
+}
+
+///
+/// A helper class whose native methods are implemented by Rust functions
+#[derive(Debug, Clone)]
+pub struct NativeBridge {
+    class_name: String,
+    methods: Vec<NativeMethod>,
+}
+
+impl NativeBridge {
+    /// Create a bridge for the helper class with the given internal name. The class is defined in
+    /// the bootstrap class loader, so its package must not belong to a named module.
+    pub fn new(class_name: &str) -> NativeBridge {
+        NativeBridge {
+            class_name: class_name.to_string(),
+            methods: vec![],
+        }
+    }
+
+    ///
+    /// Declare a `public static native` method. The function has to follow the JNI calling
+    /// convention of the descriptor, eg. `extern "C" fn(JNIEnvPtr, JavaClass, jint, jlong)` for
+    /// `(IJ)V`.
+    pub fn method(mut self, name: &str, descriptor: &str, function: *mut c_void) -> Self {
+        self.methods.push(NativeMethod {
+            name: name.to_string(),
+            signature: descriptor.to_string(),
+            function,
+        });
+        self
+    }
+
+    pub fn class_name(&self) -> &str {
+        &self.class_name
+    }
+
+    pub fn methods(&self) -> &[NativeMethod] {
+        &self.methods
+    }
+
+    /// Generate the helper class
+    pub fn helper_class(&self) -> Result<JavaClass, ModelError> {
+        self.methods
+            .iter()
+            .fold(
+                ClassBuilder::new(&self.class_name)
+                    .version(ClassfileVersion::Java1_6)
+                    .public()
+                    .final_()
+                    .synthetic(),
+                |class, method| class.method(&method.name, &method.signature, |m| m.public().static_().native()),
+            )
+            .build()
+    }
+
+    /// The class file of the helper class
+    pub fn class_data(&self) -> Result<Vec<u8>, BridgeError> {
+        let classfile = self.helper_class()?.to_classfile()?;
+        let mut data = vec![];
+
+        ClassWriter::new(&mut data).write_class(&classfile)?;
+
+        Ok(data)
+    }
+
+    /// Define the helper class in the bootstrap class loader and bind its native methods
+    pub fn define(&self, jni: &dyn JNI) -> Result<ClassId, BridgeError> {
+        let bootstrap_loader: JavaObject = ptr::null_mut();
+        let class = jni.define_class(&self.class_name, &bootstrap_loader, &self.class_data()?)?;
+
+        jni.register_natives(&class.native_id, &self.methods)?;
+
+        Ok(class)
+    }
+
+    /// Bind the native methods of a helper class that was loaded from the bootstrap class path
+    pub fn bind(&self, jni: &dyn JNI) -> Result<ClassId, BridgeError> {
+        let class = jni.find_class(&self.class_name)?;
+
+        jni.register_natives(&class.native_id, &self.methods)?;
+
+        Ok(class)
+    }
+
+    ///
+    /// Write the helper class into a JAR in the temporary directory and add it to the bootstrap
+    /// class loader search path. Returns the path of the JAR, which has to stay in place for as
+    /// long as the VM runs.
+    pub fn append_to_bootstrap(&self, jvmti: &dyn JVMTI) -> Result<PathBuf, BridgeError> {
+        let path = env::temp_dir().join(format!(
+            "jvmti-{}-{}.jar",
+            process::id(),
+            self.class_name.replace('/', ".")
+        ));
+
+        self.write_jar(&path)?;
+
+        jvmti
+            .add_to_bootstrap_classloader_search(&path.to_string_lossy())
+            .map_err(BridgeError::Jvmti)?;
+
+        Ok(path)
+    }
+
+    /// Write a JAR that contains only the helper class
+    pub fn write_jar(&self, path: &Path) -> Result<(), BridgeError> {
+        let entry = format!("{}.class", self.class_name);
+        let jar = write_zip(&[(&entry, &self.class_data()?)]);
+
+        fs::write(path, jar)?;
+
+        Ok(())
+    }
+}
+
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Version 1.0 of the ZIP specification is enough for stored entries
+const ZIP_VERSION: u16 = 10;
+/// 1980-01-01, the earliest date a ZIP entry can have
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+/// Write an uncompressed ZIP archive with the given entries
+fn write_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = vec![];
+    let mut central_directory = vec![];
+
+    for &(name, data) in entries {
+        let offset = zip.len() as u32;
+        let crc = crc32(data);
+
+        put_u32(&mut zip, LOCAL_FILE_HEADER);
+        put_entry_header(&mut zip, name, data, crc);
+        zip.extend_from_slice(name.as_bytes());
+        zip.extend_from_slice(data);
+
+        put_u32(&mut central_directory, CENTRAL_DIRECTORY_HEADER);
+        put_u16(&mut central_directory, ZIP_VERSION);
+        put_entry_header(&mut central_directory, name, data, crc);
+        // Comment length, disk number, internal and external attributes
+        put_u16(&mut central_directory, 0);
+        put_u16(&mut central_directory, 0);
+        put_u16(&mut central_directory, 0);
+        put_u32(&mut central_directory, 0);
+        put_u32(&mut central_directory, offset);
+        central_directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = zip.len() as u32;
+    zip.extend_from_slice(&central_directory);
+
+    put_u32(&mut zip, END_OF_CENTRAL_DIRECTORY);
+    put_u16(&mut zip, 0);
+    put_u16(&mut zip, 0);
+    put_u16(&mut zip, entries.len() as u16);
+    put_u16(&mut zip, entries.len() as u16);
+    put_u32(&mut zip, central_directory.len() as u32);
+    put_u32(&mut zip, directory_offset);
+    put_u16(&mut zip, 0);
+
+    zip
+}
+
+/// The part of the header that local and central directory entries have in common
+fn put_entry_header(target: &mut Vec<u8>, name: &str, data: &[u8], crc: u32) {
+    put_u16(target, ZIP_VERSION);
+    // Flags, compression method (stored) and time
+    put_u16(target, 0);
+    put_u16(target, 0);
+    put_u16(target, 0);
+    put_u16(target, DOS_DATE);
+    put_u32(target, crc);
+    put_u32(target, data.len() as u32);
+    put_u32(target, data.len() as u32);
+    put_u16(target, name.len() as u16);
+    // Extra field length
+    put_u16(target, 0);
+}
+
+fn put_u16(target: &mut Vec<u8>, value: u16) {
+    target.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(target: &mut Vec<u8>, value: u32) {
+    target.extend_from_slice(&value.to_le_bytes());
+}
+
+/// CRC-32 as used by ZIP (reflected, polynomial 0xEDB88320)
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
@@ -0,0 +1,1101 @@
+//!
+//! Argument and result capture for the methods selected by pointcuts. Every captured call records
+//! the values of the arguments when the method was entered, and either the returned value or the
+//! thrown exception.
+//!
+//! There are two ways to capture calls, which record into the same `CaptureRegistry`:
+//!
+//! * `CaptureProbes` weaves calls of a native helper class into the selected methods, like the
+//!   timing probes. The arguments are passed to the helper on entry, and the result before every
+//!   return or exception thrown out of the method.
+//! * `EventCapture` reads the arguments with `GetLocal*` from `MethodEntry` events and the result
+//!   from `MethodExit` events. It doesn't change any class, but needs
+//!   `can_access_local_variables` and keeps every thread interpreted while the events are
+//!   enabled. JVMTI doesn't pass the exception to `MethodExit`, so it is recorded as unavailable.
+//!
+//! ```ignore
+//! let probes = CaptureProbes::from_config(&config, static_context().capture.clone())?;
+//! static_context().add_transformer("capture", 0, probes);
+//! agent.use_transformer_chain();
+//! agent.on_vm_init(Some(on_vm_init));
+//!
+//! fn on_vm_init(_jvmti: JVMTIEnvPtr, jni: JNIEnvPtr, _thread: jthread) {
+//!     capture::install(jni).unwrap();
+//! }
+//! ```
+//!
+//! Primitive values are recorded as they are, objects by their `toString`, or by the value of a
+//! field if an extractor is configured for their class. The string representations are cut to
+//! `max_length` characters. The values of parameters whose names are redacted are never read,
+//! parameter names come from the local variable table, so classes compiled without it only have
+//! `arg0`, `arg1`, etc.
+//!
+//! Calls into `toString` made while a value is recorded aren't captured themselves. Constructors
+//! aren't woven, because the exception handler of the exit probe can't cover the call of the
+//! super constructor.
+
+use super::super::bytecode::classfile::Instruction;
+use super::super::config::{CaptureConfig, Config};
+use super::super::context::static_context;
+use super::super::environment::jni::{JNIEnvironment, JNI};
+use super::super::environment::jvmti::JVMTI;
+use super::super::environment::Environment;
+use super::super::error::NativeError;
+use super::super::native::jvmti_native::{jdouble, jfloat, jint, jlong, jmethodID, jthread};
+use super::super::native::{JNIEnvPtr, JavaClass as JavaClassPtr, JavaObject, JavaValue};
+use super::analysis;
+use super::bridge::{BridgeError, NativeBridge};
+use super::chain::{ClassTransformer, TransformError};
+use super::code::{Insn, Invoke, MemberRef};
+use super::descriptor::{JavaType, MethodDescriptor};
+use super::pointcut::{ClassInfo, MethodDeclaration, MethodInfo, Pointcut};
+use super::timing::{push_int, weave_exits, ProbeError};
+use super::{JavaClass, ModelError};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Internal name of the generated helper class the probes call
+pub const HELPER_CLASS: &str = "jvmti/probe/Capture";
+/// `static native void enter(int site)`
+pub const ENTER_DESCRIPTOR: &str = "(I)V";
+/// `static native void intArgument(int index, int value)`, also used for `boolean`, `byte`,
+/// `char` and `short` arguments
+pub const INT_ARGUMENT_DESCRIPTOR: &str = "(II)V";
+pub const LONG_ARGUMENT_DESCRIPTOR: &str = "(IJ)V";
+pub const FLOAT_ARGUMENT_DESCRIPTOR: &str = "(IF)V";
+pub const DOUBLE_ARGUMENT_DESCRIPTOR: &str = "(ID)V";
+pub const OBJECT_ARGUMENT_DESCRIPTOR: &str = "(ILjava/lang/Object;)V";
+/// `static native void returnInt(int value, int site)`
+pub const INT_RETURN_DESCRIPTOR: &str = "(II)V";
+pub const LONG_RETURN_DESCRIPTOR: &str = "(JI)V";
+pub const FLOAT_RETURN_DESCRIPTOR: &str = "(FI)V";
+pub const DOUBLE_RETURN_DESCRIPTOR: &str = "(DI)V";
+pub const OBJECT_RETURN_DESCRIPTOR: &str = "(Ljava/lang/Object;I)V";
+/// `static native void returnVoid(int site)`
+pub const VOID_RETURN_DESCRIPTOR: &str = "(I)V";
+/// `static native void thrown(Throwable exception, int site)`
+pub const THROWN_DESCRIPTOR: &str = "(Ljava/lang/Throwable;I)V";
+
+const ACC_STATIC: jint = 0x0008;
+
+static HELPER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+///
+/// A recorded argument, return value or exception
+#[derive(Debug, Clone, PartialEq)]
+pub enum CapturedValue {
+    Null,
+    Boolean(bool),
+    Byte(i8),
+    Char(char),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    /// The string representation of an object, or the value of its extracted field
+    Object {
+        /// Binary name of the class of the object, eg. `java.lang.String`
+        class_name: String,
+        text: String,
+        /// Whether the text was cut to the maximum length
+        truncated: bool,
+    },
+    /// The parameter is redacted, so its value wasn't read
+    Redacted,
+    /// The value couldn't be read, eg. because `toString` threw an exception
+    Unavailable,
+}
+
+impl CapturedValue {
+    /// The string representation of an object, cut to `max_length` characters
+    pub fn text(class_name: &str, text: &str, max_length: usize) -> CapturedValue {
+        let truncated = text.chars().count() > max_length;
+
+        CapturedValue::Object {
+            class_name: class_name.to_string(),
+            text: if truncated { text.chars().take(max_length).collect() } else { text.to_string() },
+            truncated,
+        }
+    }
+
+    /// Interpret an `int` value passed for a parameter or result of the given type. The JVM
+    /// passes `boolean`, `byte`, `char` and `short` values as `int`.
+    pub fn from_int(java_type: &JavaType, value: i32) -> CapturedValue {
+        match *java_type {
+            JavaType::Boolean => CapturedValue::Boolean(value != 0),
+            JavaType::Byte => CapturedValue::Byte(value as i8),
+            JavaType::Char => CapturedValue::Char(std::char::from_u32(value as u16 as u32).unwrap_or('\u{fffd}')),
+            JavaType::Short => CapturedValue::Short(value as i16),
+            _ => CapturedValue::Int(value),
+        }
+    }
+
+    /// Narrow an `Int` recorded for a value of the given type
+    fn narrow(self, java_type: &JavaType) -> CapturedValue {
+        match self {
+            CapturedValue::Int(value) => CapturedValue::from_int(java_type, value),
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for CapturedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CapturedValue::Null => write!(f, "null"),
+            CapturedValue::Boolean(value) => write!(f, "{}", value),
+            CapturedValue::Byte(value) => write!(f, "{}", value),
+            CapturedValue::Char(value) => write!(f, "'{}'", value),
+            CapturedValue::Short(value) => write!(f, "{}", value),
+            CapturedValue::Int(value) => write!(f, "{}", value),
+            CapturedValue::Long(value) => write!(f, "{}", value),
+            CapturedValue::Float(value) => write!(f, "{}", value),
+            CapturedValue::Double(value) => write!(f, "{}", value),
+            CapturedValue::Object { ref class_name, ref text, truncated } => {
+                let ellipsis = if truncated { "..." } else { "" };
+
+                if class_name == "java.lang.String" {
+                    write!(f, "\"{}{}\"", text, ellipsis)
+                } else {
+                    write!(f, "{}{}", text, ellipsis)
+                }
+            }
+            CapturedValue::Redacted => write!(f, "<redacted>"),
+            CapturedValue::Unavailable => write!(f, "<unavailable>"),
+        }
+    }
+}
+
+///
+/// How a captured call has ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallOutcome {
+    /// The returned value, `None` for `void` methods
+    Returned(Option<CapturedValue>),
+    Threw(CapturedValue),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedCall {
+    /// Id of the site in the `CaptureRegistry`
+    pub site: u32,
+    /// The values of the parameters on entry, in declaration order
+    pub arguments: Vec<CapturedValue>,
+    pub outcome: CallOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    /// Name from the local variable table, or `arg0`, `arg1`, etc. if there's none
+    pub name: String,
+    pub java_type: JavaType,
+    /// The local variable slot of the parameter
+    pub slot: u16,
+    pub redacted: bool,
+}
+
+///
+/// A method whose calls are captured
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureSite {
+    /// Internal name of the declaring class
+    pub class_name: String,
+    pub method_name: String,
+    pub descriptor: String,
+    pub parameters: Vec<Parameter>,
+    pub return_type: JavaType,
+}
+
+impl CaptureSite {
+    ///
+    /// Describe a method. `names` are the local variable names by slot, eg. from the local
+    /// variable table. Parameters are redacted according to the settings.
+    pub fn new(
+        class_name: &str,
+        method_name: &str,
+        descriptor: &MethodDescriptor,
+        is_static: bool,
+        names: &[(u16, String)],
+        settings: &CaptureSettings,
+    ) -> CaptureSite {
+        let mut slot = if is_static { 0 } else { 1 };
+        let mut parameters = vec![];
+
+        for (index, java_type) in descriptor.parameters.iter().enumerate() {
+            let name = names
+                .iter()
+                .find(|&&(variable, _)| variable == slot)
+                .map(|(_, name)| name.clone())
+                .unwrap_or_else(|| format!("arg{}", index));
+
+            parameters.push(Parameter {
+                redacted: settings.is_redacted(&name),
+                name,
+                java_type: java_type.clone(),
+                slot,
+            });
+
+            slot += java_type.size();
+        }
+
+        CaptureSite {
+            class_name: class_name.to_string(),
+            method_name: method_name.to_string(),
+            descriptor: descriptor.descriptor(),
+            parameters,
+            return_type: descriptor.return_type.clone(),
+        }
+    }
+
+    /// Describe a method of the class, naming the parameters from its local variable table
+    pub fn from_method(class: &JavaClass, method_index: usize, settings: &CaptureSettings) -> CaptureSite {
+        let method = &class.methods[method_index];
+        let names: Vec<(u16, String)> = match method.code {
+            Some(ref code) => code
+                .local_variables
+                .iter()
+                .map(|variable| (variable.index, variable.name.clone()))
+                .collect(),
+            None => vec![],
+        };
+
+        CaptureSite::new(&class.name, &method.name, &method.descriptor, method.is_static(), &names, settings)
+    }
+}
+
+///
+/// Reads a field of objects instead of calling their `toString`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldExtractor {
+    pub field: String,
+    /// Descriptor of the field. `int` and reference fields are supported, the `toString` of the
+    /// latter is recorded.
+    pub descriptor: String,
+}
+
+impl FieldExtractor {
+    /// Parse `name` or `name:descriptor`. The descriptor defaults to `Ljava/lang/String;`.
+    pub fn parse(spec: &str) -> FieldExtractor {
+        match spec.find(':') {
+            Some(index) => FieldExtractor {
+                field: spec[..index].trim().to_string(),
+                descriptor: spec[index + 1..].trim().to_string(),
+            },
+            None => FieldExtractor {
+                field: spec.trim().to_string(),
+                descriptor: "Ljava/lang/String;".to_string(),
+            },
+        }
+    }
+}
+
+///
+/// How values are recorded, see `CaptureConfig`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureSettings {
+    pub max_length: usize,
+    pub max_calls: usize,
+    /// Redacted parameter names in lower case
+    pub redact: Vec<String>,
+    /// Extractors by the binary name of the class, eg. `com.acme.User`
+    pub extractors: HashMap<String, FieldExtractor>,
+}
+
+impl CaptureSettings {
+    pub fn from_config(config: &CaptureConfig) -> CaptureSettings {
+        CaptureSettings {
+            max_length: config.max_length,
+            max_calls: config.max_calls,
+            redact: config.redact.iter().map(|name| name.to_lowercase()).collect(),
+            extractors: config
+                .extractors
+                .iter()
+                .map(|(class_name, spec)| (class_name.replace('/', "."), FieldExtractor::parse(spec)))
+                .collect(),
+        }
+    }
+
+    pub fn is_redacted(&self, parameter: &str) -> bool {
+        let parameter = parameter.to_lowercase();
+
+        self.redact.contains(&parameter)
+    }
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        CaptureSettings {
+            max_length: 256,
+            max_calls: 1000,
+            redact: vec![],
+            extractors: HashMap::new(),
+        }
+    }
+}
+
+/// A call that has been entered but hasn't completed yet
+struct Invocation {
+    site: u32,
+    arguments: Vec<CapturedValue>,
+    /// Calls made while a value is recorded, eg. by `toString`, are tracked but not captured
+    suppressed: bool,
+}
+
+thread_local! {
+    static INVOCATIONS: RefCell<Vec<Invocation>> = const { RefCell::new(vec![]) };
+    static RECORDING: Cell<bool> = const { Cell::new(false) };
+}
+
+///
+/// Assigns the numeric ids the woven code passes to the helper, tracks the calls in progress on
+/// each thread and keeps the most recent completed calls.
+pub struct CaptureRegistry {
+    sites: RwLock<Vec<CaptureSite>>,
+    settings: RwLock<Arc<CaptureSettings>>,
+    calls: Mutex<VecDeque<CapturedCall>>,
+}
+
+impl CaptureRegistry {
+    pub fn new() -> CaptureRegistry {
+        CaptureRegistry {
+            sites: RwLock::new(vec![]),
+            settings: RwLock::new(Arc::new(CaptureSettings::default())),
+            calls: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Returns the id of the site, registering it first if it's new
+    pub fn register(&self, site: CaptureSite) -> u32 {
+        let mut sites = self.sites.write().unwrap();
+
+        match sites.iter().position(|existing| *existing == site) {
+            Some(id) => id as u32,
+            None => {
+                sites.push(site);
+                (sites.len() - 1) as u32
+            }
+        }
+    }
+
+    pub fn site(&self, id: u32) -> Option<CaptureSite> {
+        self.sites.read().ok().and_then(|sites| sites.get(id as usize).cloned())
+    }
+
+    ///
+    /// Replace the settings. Redaction applies to the sites registered from now on, the other
+    /// settings to the values recorded from now on.
+    pub fn configure(&self, settings: CaptureSettings) {
+        if let Ok(mut current) = self.settings.write() {
+            *current = Arc::new(settings);
+        }
+    }
+
+    pub fn settings(&self) -> Arc<CaptureSettings> {
+        match self.settings.read() {
+            Ok(settings) => settings.clone(),
+            Err(_) => Arc::new(CaptureSettings::default()),
+        }
+    }
+
+    ///
+    /// Start a call of the site on the current thread. Redacted arguments are recorded as such,
+    /// the others as unavailable until they are passed to `argument`.
+    pub fn enter(&self, site: u32) {
+        let arguments = self.site(site).map(|site| {
+            site.parameters
+                .iter()
+                .map(|parameter| {
+                    if parameter.redacted {
+                        CapturedValue::Redacted
+                    } else {
+                        CapturedValue::Unavailable
+                    }
+                })
+                .collect()
+        });
+        let suppressed = arguments.is_none() || RECORDING.with(|recording| recording.get());
+
+        INVOCATIONS.with(|invocations| {
+            invocations.borrow_mut().push(Invocation {
+                site,
+                arguments: arguments.unwrap_or_default(),
+                suppressed,
+            })
+        });
+    }
+
+    /// Record an argument of the innermost call on the current thread. `Int` values are
+    /// narrowed to the type of the parameter.
+    pub fn argument(&self, index: usize, value: CapturedValue) {
+        let site = INVOCATIONS.with(|invocations| invocations.borrow().last().map(|invocation| invocation.site));
+        let java_type = match site.and_then(|site| self.site(site)) {
+            Some(site) => match site.parameters.get(index) {
+                Some(parameter) if !parameter.redacted => parameter.java_type.clone(),
+                _ => return,
+            },
+            None => return,
+        };
+
+        INVOCATIONS.with(|invocations| {
+            if let Some(invocation) = invocations.borrow_mut().last_mut() {
+                if !invocation.suppressed {
+                    invocation.arguments[index] = value.narrow(&java_type);
+                }
+            }
+        });
+    }
+
+    ///
+    /// Complete the innermost call of the site on the current thread. Calls entered after it
+    /// that never completed are discarded. Returns the captured call, unless it was made while a
+    /// value was recorded.
+    pub fn exit(&self, site: u32, outcome: CallOutcome) -> Option<CapturedCall> {
+        let invocation = INVOCATIONS.with(|invocations| {
+            let mut invocations = invocations.borrow_mut();
+
+            match invocations.iter().rposition(|invocation| invocation.site == site) {
+                Some(position) => invocations.drain(position..).next(),
+                None => None,
+            }
+        })?;
+
+        if invocation.suppressed {
+            return None;
+        }
+
+        let outcome = match outcome {
+            CallOutcome::Returned(Some(value)) => {
+                let return_type = self.site(site).map(|site| site.return_type).unwrap_or(JavaType::Int);
+                CallOutcome::Returned(Some(value.narrow(&return_type)))
+            }
+            other => other,
+        };
+        let call = CapturedCall {
+            site,
+            arguments: invocation.arguments,
+            outcome,
+        };
+
+        if let Ok(mut calls) = self.calls.lock() {
+            let max_calls = self.settings().max_calls;
+
+            calls.push_back(call.clone());
+            while calls.len() > max_calls {
+                calls.pop_front();
+            }
+        }
+
+        Some(call)
+    }
+
+    /// The kept calls, oldest first
+    pub fn calls(&self) -> Vec<CapturedCall> {
+        match self.calls.lock() {
+            Ok(calls) => calls.iter().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Remove and return the kept calls, oldest first
+    pub fn drain(&self) -> Vec<CapturedCall> {
+        match self.calls.lock() {
+            Ok(mut calls) => calls.drain(..).collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    ///
+    /// A line describing the call, eg.
+    /// `com.acme.Users.login(name="alice", password=<redacted>) returned true`
+    pub fn describe(&self, call: &CapturedCall) -> String {
+        let site = match self.site(call.site) {
+            Some(site) => site,
+            None => return format!("<unknown site {}>", call.site),
+        };
+        let arguments: Vec<String> = site
+            .parameters
+            .iter()
+            .zip(call.arguments.iter())
+            .map(|(parameter, value)| format!("{}={}", parameter.name, value))
+            .collect();
+        let outcome = match call.outcome {
+            CallOutcome::Returned(Some(ref value)) => format!("returned {}", value),
+            CallOutcome::Returned(None) => "returned".to_string(),
+            CallOutcome::Threw(ref exception) => format!("threw {}", exception),
+        };
+
+        format!(
+            "{}.{}({}) {}",
+            site.class_name.replace('/', "."),
+            site.method_name,
+            arguments.join(", "),
+            outcome
+        )
+    }
+
+    /// Number of registered sites
+    pub fn len(&self) -> usize {
+        self.sites.read().map(|sites| sites.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for CaptureRegistry {
+    fn default() -> Self {
+        CaptureRegistry::new()
+    }
+}
+
+/// The pointcuts listed in the `[capture]` section, which also configures the registry
+fn configured_pointcuts(config: &Config, registry: &CaptureRegistry) -> Result<Vec<Pointcut>, ProbeError> {
+    let capture = match config.capture {
+        Some(ref capture) => capture,
+        None => return Ok(vec![]),
+    };
+
+    let pointcuts = capture
+        .pointcuts
+        .iter()
+        .map(|name| {
+            config
+                .pointcut(name)
+                .cloned()
+                .ok_or_else(|| ProbeError::UnknownPointcut(name.clone()))
+        })
+        .collect::<Result<Vec<Pointcut>, ProbeError>>()?;
+
+    registry.configure(CaptureSettings::from_config(capture));
+
+    Ok(pointcuts)
+}
+
+///
+/// A `ClassTransformer` that weaves capture probes into the methods selected by any of its
+/// pointcuts
+pub struct CaptureProbes {
+    pointcuts: Vec<Pointcut>,
+    registry: Arc<CaptureRegistry>,
+}
+
+impl CaptureProbes {
+    pub fn new(pointcuts: Vec<Pointcut>, registry: Arc<CaptureRegistry>) -> CaptureProbes {
+        CaptureProbes {
+            pointcuts,
+            registry,
+        }
+    }
+
+    /// Instrument the methods selected by the `[capture]` section and apply its settings
+    pub fn from_config(config: &Config, registry: Arc<CaptureRegistry>) -> Result<CaptureProbes, ProbeError> {
+        let pointcuts = configured_pointcuts(config, &registry)?;
+
+        Ok(CaptureProbes::new(pointcuts, registry))
+    }
+}
+
+impl ClassTransformer for CaptureProbes {
+    fn accepts(&self, class_name: &str) -> bool {
+        let class = ClassInfo::new(class_name);
+
+        HELPER_INSTALLED.load(Ordering::SeqCst)
+            && class_name != HELPER_CLASS
+            && self.pointcuts.iter().any(|p| p.matches_class(&class))
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        let class_info = ClassInfo::from_class(class);
+        let settings = self.registry.settings();
+
+        let selected: Vec<usize> = class
+            .methods
+            .iter()
+            .enumerate()
+            .filter(|&(_, method)| {
+                method.code.is_some()
+                    && method.name != "<init>"
+                    && self
+                        .pointcuts
+                        .iter()
+                        .any(|p| p.matches_method(&class_info, &MethodInfo::from_method(class, method)))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        for &index in selected.iter() {
+            let site = CaptureSite::from_method(class, index, &settings);
+            let id = self.registry.register(site.clone());
+
+            weave(class, index, id, &site)?;
+        }
+
+        Ok(!selected.is_empty())
+    }
+}
+
+///
+/// Weave the capture probes of the site into a method of the class. The entry probe passes the
+/// arguments that aren't redacted, the exit probes the returned value or the thrown exception.
+/// The code doesn't need any new local variables, so the existing stack map frames remain
+/// valid, only the exception handler of the exit probe gets a new frame.
+pub fn weave(class: &mut JavaClass, method_index: usize, id: u32, site: &CaptureSite) -> Result<(), ModelError> {
+    let class_name = class.name.clone();
+    let has_frames = class.version.major_version >= 50;
+    let method = &mut class.methods[method_index];
+
+    {
+        let code = match method.code.as_mut() {
+            Some(code) => code,
+            None => return Ok(()),
+        };
+
+        let mut instructions = vec![push_int(id as i32), helper_call("enter", ENTER_DESCRIPTOR)];
+
+        for (index, parameter) in site.parameters.iter().enumerate() {
+            if parameter.redacted {
+                continue;
+            }
+
+            let (name, descriptor) = match parameter.java_type {
+                JavaType::Long => ("longArgument", LONG_ARGUMENT_DESCRIPTOR),
+                JavaType::Float => ("floatArgument", FLOAT_ARGUMENT_DESCRIPTOR),
+                JavaType::Double => ("doubleArgument", DOUBLE_ARGUMENT_DESCRIPTOR),
+                JavaType::Class(_) | JavaType::Array(_) => ("objectArgument", OBJECT_ARGUMENT_DESCRIPTOR),
+                _ => ("intArgument", INT_ARGUMENT_DESCRIPTOR),
+            };
+
+            instructions.push(push_int(index as i32));
+            instructions.push(load(&parameter.java_type, parameter.slot));
+            instructions.push(helper_call(name, descriptor));
+        }
+
+        weave_exits(
+            code,
+            instructions,
+            has_frames,
+            |insn| return_probe(insn, id),
+            vec![
+                Insn::Op(Instruction::DUP),
+                push_int(id as i32),
+                helper_call("thrown", THROWN_DESCRIPTOR),
+            ],
+        );
+    }
+
+    analysis::compute_maxs(&class_name, method)
+}
+
+/// The probe in front of a return instruction, which passes a copy of the returned value
+fn return_probe(insn: &Insn, id: u32) -> Option<Vec<Insn>> {
+    let (copy, name, descriptor) = match *insn {
+        Insn::Op(Instruction::IRETURN) => (Some(Instruction::DUP), "returnInt", INT_RETURN_DESCRIPTOR),
+        Insn::Op(Instruction::LRETURN) => (Some(Instruction::DUP2), "returnLong", LONG_RETURN_DESCRIPTOR),
+        Insn::Op(Instruction::FRETURN) => (Some(Instruction::DUP), "returnFloat", FLOAT_RETURN_DESCRIPTOR),
+        Insn::Op(Instruction::DRETURN) => (Some(Instruction::DUP2), "returnDouble", DOUBLE_RETURN_DESCRIPTOR),
+        Insn::Op(Instruction::ARETURN) => (Some(Instruction::DUP), "returnObject", OBJECT_RETURN_DESCRIPTOR),
+        Insn::Op(Instruction::RETURN) => (None, "returnVoid", VOID_RETURN_DESCRIPTOR),
+        _ => return None,
+    };
+
+    let mut probe = vec![];
+
+    if let Some(copy) = copy {
+        probe.push(Insn::Op(copy));
+    }
+    probe.push(push_int(id as i32));
+    probe.push(helper_call(name, descriptor));
+
+    Some(probe)
+}
+
+fn helper_call(name: &str, descriptor: &str) -> Insn {
+    Insn::Invoke(Invoke::Static, MemberRef::new(HELPER_CLASS, name, descriptor))
+}
+
+/// Load a local variable of the given type
+pub(crate) fn load(java_type: &JavaType, slot: u16) -> Insn {
+    use super::super::bytecode::classfile::Instruction::*;
+
+    let short = match *java_type {
+        JavaType::Long => [LLOAD_0, LLOAD_1, LLOAD_2, LLOAD_3],
+        JavaType::Float => [FLOAD_0, FLOAD_1, FLOAD_2, FLOAD_3],
+        JavaType::Double => [DLOAD_0, DLOAD_1, DLOAD_2, DLOAD_3],
+        JavaType::Class(_) | JavaType::Array(_) => [ALOAD_0, ALOAD_1, ALOAD_2, ALOAD_3],
+        _ => [ILOAD_0, ILOAD_1, ILOAD_2, ILOAD_3],
+    };
+
+    Insn::Op(match (java_type, slot) {
+        (_, 0..=3) => short[slot as usize].clone(),
+        (&JavaType::Long, 4..=255) => LLOAD(slot as u8),
+        (&JavaType::Float, 4..=255) => FLOAD(slot as u8),
+        (&JavaType::Double, 4..=255) => DLOAD(slot as u8),
+        (&JavaType::Class(_), 4..=255) | (&JavaType::Array(_), 4..=255) => ALOAD(slot as u8),
+        (_, 4..=255) => ILOAD(slot as u8),
+        (&JavaType::Long, _) => LLOAD_W(slot),
+        (&JavaType::Float, _) => FLOAD_W(slot),
+        (&JavaType::Double, _) => DLOAD_W(slot),
+        (&JavaType::Class(_), _) | (&JavaType::Array(_), _) => ALOAD_W(slot),
+        _ => ILOAD_W(slot),
+    })
+}
+
+///
+/// Record an object with JNI, by the extractor configured for its class or by `toString`.
+/// Exceptions thrown meanwhile are cleared and the value is recorded as unavailable.
+pub fn record_object(jni: &dyn JNI, object: JavaObject, settings: &CaptureSettings) -> CapturedValue {
+    if object.is_null() {
+        return CapturedValue::Null;
+    }
+
+    let recording = RECORDING.with(|recording| recording.replace(true));
+    let value = describe_object(jni, object, settings);
+
+    RECORDING.with(|current| current.set(recording));
+
+    if jni.clear_pending_exception() {
+        CapturedValue::Unavailable
+    } else {
+        value.unwrap_or(CapturedValue::Unavailable)
+    }
+}
+
+fn describe_object(jni: &dyn JNI, object: JavaObject, settings: &CaptureSettings) -> Option<CapturedValue> {
+    let class = jni.get_object_class(&object).ok()?;
+    let class_name = call_string(jni, class, "getName")?;
+
+    match settings.extractors.get(&class_name) {
+        Some(extractor) => {
+            let field = jni.get_field_id(&class, &extractor.field, &extractor.descriptor).ok()?;
+
+            if jni.clear_pending_exception() || field.is_null() {
+                return None;
+            }
+
+            match extractor.descriptor.as_bytes().first() {
+                Some(&b'I') => {
+                    let value = jni.get_int_field(&object, &field).ok()?;
+                    Some(CapturedValue::text(&class_name, &value.to_string(), settings.max_length))
+                }
+                Some(&b'L') | Some(&b'[') => {
+                    let value = jni.get_object_field(&object, &field).ok()?;
+
+                    if value.is_null() {
+                        Some(CapturedValue::Null)
+                    } else {
+                        let text = call_string(jni, value, "toString")?;
+                        Some(CapturedValue::text(&class_name, &text, settings.max_length))
+                    }
+                }
+                _ => None,
+            }
+        }
+        None => {
+            let text = call_string(jni, object, "toString")?;
+            Some(CapturedValue::text(&class_name, &text, settings.max_length))
+        }
+    }
+}
+
+/// Call a `String` method without arguments, eg. `toString`
+pub(crate) fn call_string(jni: &dyn JNI, object: JavaObject, name: &str) -> Option<String> {
+    let class = jni.get_object_class(&object).ok()?;
+    let method = jni.get_method(&class, name, "()Ljava/lang/String;").ok()?;
+    let string = jni.call_object_method(&object, &method.native_id, &[]).ok()?;
+
+    if jni.clear_pending_exception() || string.is_null() {
+        return None;
+    }
+
+    jni.get_string_utf_chars(&string).ok()
+}
+
+///
+/// Read the arguments of a site from the frame at the given depth with `GetLocal*`, which
+/// requires `can_access_local_variables`. This only works at the start of the method, before
+/// the parameters are reassigned. Objects are recorded with the given function, arguments that
+/// can't be read are recorded as unavailable.
+pub fn read_arguments(
+    jvmti: &dyn JVMTI,
+    thread: jthread,
+    depth: jint,
+    site: &CaptureSite,
+    object: &dyn Fn(JavaObject) -> CapturedValue,
+) -> Vec<CapturedValue> {
+    site.parameters
+        .iter()
+        .map(|parameter| {
+            if parameter.redacted {
+                return CapturedValue::Redacted;
+            }
+
+            let slot = parameter.slot as jint;
+            let value = match parameter.java_type {
+                JavaType::Long => jvmti.get_local_long(thread, depth, slot).map(CapturedValue::Long),
+                JavaType::Float => jvmti.get_local_float(thread, depth, slot).map(CapturedValue::Float),
+                JavaType::Double => jvmti.get_local_double(thread, depth, slot).map(CapturedValue::Double),
+                JavaType::Class(_) | JavaType::Array(_) => jvmti.get_local_object(thread, depth, slot).map(object),
+                ref java_type => jvmti
+                    .get_local_int(thread, depth, slot)
+                    .map(|value| CapturedValue::from_int(java_type, value)),
+            };
+
+            value.unwrap_or(CapturedValue::Unavailable)
+        })
+        .collect()
+}
+
+///
+/// Record a value passed to a `MethodExit` event, which is `None` if the frame was popped by an
+/// exception
+pub fn read_return_value(
+    site: &CaptureSite,
+    value: Option<&JavaValue>,
+    object: &dyn Fn(JavaObject) -> CapturedValue,
+) -> CallOutcome {
+    let mut value = match value {
+        Some(value) => *value,
+        None => return CallOutcome::Threw(CapturedValue::Unavailable),
+    };
+
+    let captured = unsafe {
+        match site.return_type {
+            JavaType::Void => return CallOutcome::Returned(None),
+            JavaType::Long => CapturedValue::Long(*value.j()),
+            JavaType::Float => CapturedValue::Float(*value.f()),
+            JavaType::Double => CapturedValue::Double(*value.d()),
+            JavaType::Class(_) | JavaType::Array(_) => object(*value.l()),
+            ref java_type => CapturedValue::from_int(java_type, *value.i()),
+        }
+    };
+
+    CallOutcome::Returned(Some(captured))
+}
+
+///
+/// Captures the calls of the methods selected by its pointcuts from `MethodEntry` and
+/// `MethodExit` events, without weaving any class
+pub struct EventCapture {
+    pointcuts: Vec<Pointcut>,
+    registry: Arc<CaptureRegistry>,
+    /// The site ids of the methods seen so far, `None` for those that aren't selected
+    methods: RwLock<HashMap<usize, Option<u32>>>,
+}
+
+impl EventCapture {
+    pub fn new(pointcuts: Vec<Pointcut>, registry: Arc<CaptureRegistry>) -> EventCapture {
+        EventCapture {
+            pointcuts,
+            registry,
+            methods: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Capture the methods selected by the `[capture]` section and apply its settings
+    pub fn from_config(config: &Config, registry: Arc<CaptureRegistry>) -> Result<EventCapture, ProbeError> {
+        let pointcuts = configured_pointcuts(config, &registry)?;
+
+        Ok(EventCapture::new(pointcuts, registry))
+    }
+
+    ///
+    /// Start capturing a call if the method is selected, to be called from the `MethodEntry`
+    /// handler. Returns whether the call is captured.
+    pub fn method_entry(&self, env: &Environment, thread: jthread, method: jmethodID) -> Result<bool, NativeError> {
+        let id = match self.site_id(env, method)? {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        let site = match self.registry.site(id) {
+            Some(site) => site,
+            None => return Ok(false),
+        };
+        let settings = self.registry.settings();
+
+        self.registry.enter(id);
+
+        let arguments = read_arguments(env, thread, 0, &site, &|object| record_object(env, object, &settings));
+
+        for (index, value) in arguments.into_iter().enumerate() {
+            self.registry.argument(index, value);
+        }
+
+        Ok(true)
+    }
+
+    ///
+    /// Complete a captured call, to be called from the `MethodExit` handler with the value it
+    /// receives, which is `None` if the frame was popped by an exception
+    pub fn method_exit(
+        &self,
+        env: &Environment,
+        method: jmethodID,
+        value: Option<&JavaValue>,
+    ) -> Result<Option<CapturedCall>, NativeError> {
+        let id = match self.site_id(env, method)? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let site = match self.registry.site(id) {
+            Some(site) => site,
+            None => return Ok(None),
+        };
+        let settings = self.registry.settings();
+        let outcome = read_return_value(&site, value, &|object| record_object(env, object, &settings));
+
+        Ok(self.registry.exit(id, outcome))
+    }
+
+    /// The site id of a selected method, registering the site when the method is first seen
+    fn site_id(&self, env: &Environment, method: jmethodID) -> Result<Option<u32>, NativeError> {
+        if let Some(id) = self.methods.read().ok().and_then(|methods| methods.get(&(method as usize)).cloned()) {
+            return Ok(id);
+        }
+
+        let class = env.get_method_declaring_class(&method)?;
+        let class_info = ClassInfo::from_signature(&env.get_class_signature(&class.native_id)?);
+        let signature = env.get_method_name(method)?;
+        let modifiers = env.get_method_modifiers(method)?;
+        let method_info = MethodInfo {
+            name: signature.name.clone(),
+            descriptor: signature.signature.clone(),
+            declaration: Some(MethodDeclaration {
+                access_flags: modifiers as u16,
+                annotations: vec![],
+            }),
+        };
+
+        let id = match MethodDescriptor::parse(&signature.signature) {
+            Some(ref descriptor) if self.pointcuts.iter().any(|p| p.matches_method(&class_info, &method_info)) => {
+                let names: Vec<(u16, String)> = env
+                    .get_local_variable_table(method)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|entry| (entry.slot as u16, entry.name))
+                    .collect();
+                let site = CaptureSite::new(
+                    &class_info.name.replace('.', "/"),
+                    &signature.name,
+                    descriptor,
+                    modifiers & ACC_STATIC != 0,
+                    &names,
+                    &self.registry.settings(),
+                );
+
+                Some(self.registry.register(site))
+            }
+            _ => None,
+        };
+
+        if let Ok(mut methods) = self.methods.write() {
+            methods.insert(method as usize, id);
+        }
+
+        Ok(id)
+    }
+}
+
+///
+/// The bridge that defines the helper class and binds its methods
+pub fn bridge() -> NativeBridge {
+    NativeBridge::new(HELPER_CLASS)
+        .method("enter", ENTER_DESCRIPTOR, entered as *mut c_void)
+        .method("intArgument", INT_ARGUMENT_DESCRIPTOR, int_argument as *mut c_void)
+        .method("longArgument", LONG_ARGUMENT_DESCRIPTOR, long_argument as *mut c_void)
+        .method("floatArgument", FLOAT_ARGUMENT_DESCRIPTOR, float_argument as *mut c_void)
+        .method("doubleArgument", DOUBLE_ARGUMENT_DESCRIPTOR, double_argument as *mut c_void)
+        .method("objectArgument", OBJECT_ARGUMENT_DESCRIPTOR, object_argument as *mut c_void)
+        .method("returnInt", INT_RETURN_DESCRIPTOR, returned_int as *mut c_void)
+        .method("returnLong", LONG_RETURN_DESCRIPTOR, returned_long as *mut c_void)
+        .method("returnFloat", FLOAT_RETURN_DESCRIPTOR, returned_float as *mut c_void)
+        .method("returnDouble", DOUBLE_RETURN_DESCRIPTOR, returned_double as *mut c_void)
+        .method("returnObject", OBJECT_RETURN_DESCRIPTOR, returned_object as *mut c_void)
+        .method("returnVoid", VOID_RETURN_DESCRIPTOR, returned_void as *mut c_void)
+        .method("thrown", THROWN_DESCRIPTOR, thrown as *mut c_void)
+}
+
+///
+/// Define the helper class in the bootstrap class loader, bind its native methods and start
+/// weaving the classes loaded from now on. Has to be called in the live phase, eg. from the
+/// `VMInit` handler.
+pub fn install(jni: JNIEnvPtr) -> Result<(), BridgeError> {
+    if HELPER_INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    bridge().define(&JNIEnvironment::new(jni))?;
+    HELPER_INSTALLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Returns true once the helper class is defined and classes are being woven
+pub fn is_installed() -> bool {
+    HELPER_INSTALLED.load(Ordering::SeqCst)
+}
+
+fn object_value(jni: JNIEnvPtr, object: JavaObject) -> CapturedValue {
+    record_object(&JNIEnvironment::new(jni), object, &static_context().capture.settings())
+}
+
+extern "C" fn entered(_jni: JNIEnvPtr, _class: JavaClassPtr, site: jint) {
+    static_context().capture.enter(site as u32);
+}
+
+extern "C" fn int_argument(_jni: JNIEnvPtr, _class: JavaClassPtr, index: jint, value: jint) {
+    static_context().capture.argument(index as usize, CapturedValue::Int(value));
+}
+
+extern "C" fn long_argument(_jni: JNIEnvPtr, _class: JavaClassPtr, index: jint, value: jlong) {
+    static_context().capture.argument(index as usize, CapturedValue::Long(value));
+}
+
+extern "C" fn float_argument(_jni: JNIEnvPtr, _class: JavaClassPtr, index: jint, value: jfloat) {
+    static_context().capture.argument(index as usize, CapturedValue::Float(value));
+}
+
+extern "C" fn double_argument(_jni: JNIEnvPtr, _class: JavaClassPtr, index: jint, value: jdouble) {
+    static_context().capture.argument(index as usize, CapturedValue::Double(value));
+}
+
+extern "C" fn object_argument(jni: JNIEnvPtr, _class: JavaClassPtr, index: jint, value: JavaObject) {
+    static_context().capture.argument(index as usize, object_value(jni, value));
+}
+
+extern "C" fn returned_int(_jni: JNIEnvPtr, _class: JavaClassPtr, value: jint, site: jint) {
+    static_context().capture.exit(site as u32, CallOutcome::Returned(Some(CapturedValue::Int(value))));
+}
+
+extern "C" fn returned_long(_jni: JNIEnvPtr, _class: JavaClassPtr, value: jlong, site: jint) {
+    static_context().capture.exit(site as u32, CallOutcome::Returned(Some(CapturedValue::Long(value))));
+}
+
+extern "C" fn returned_float(_jni: JNIEnvPtr, _class: JavaClassPtr, value: jfloat, site: jint) {
+    static_context().capture.exit(site as u32, CallOutcome::Returned(Some(CapturedValue::Float(value))));
+}
+
+extern "C" fn returned_double(_jni: JNIEnvPtr, _class: JavaClassPtr, value: jdouble, site: jint) {
+    static_context().capture.exit(site as u32, CallOutcome::Returned(Some(CapturedValue::Double(value))));
+}
+
+extern "C" fn returned_object(jni: JNIEnvPtr, _class: JavaClassPtr, value: JavaObject, site: jint) {
+    let value = object_value(jni, value);
+
+    static_context().capture.exit(site as u32, CallOutcome::Returned(Some(value)));
+}
+
+extern "C" fn returned_void(_jni: JNIEnvPtr, _class: JavaClassPtr, site: jint) {
+    static_context().capture.exit(site as u32, CallOutcome::Returned(None));
+}
+
+extern "C" fn thrown(jni: JNIEnvPtr, _class: JavaClassPtr, exception: JavaObject, site: jint) {
+    let exception = object_value(jni, exception);
+
+    static_context().capture.exit(site as u32, CallOutcome::Threw(exception));
+}
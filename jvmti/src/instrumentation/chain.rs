@@ -0,0 +1,335 @@
+use super::super::bytecode::io::{ClassReader, ClassWriter};
+use super::super::thread::catch_panic;
+use super::analysis;
+use super::{Code, JavaClass, Method, ModelError};
+use std::any::Any;
+use std::fmt;
+use std::io::Cursor;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+///
+/// Describes why a transformer couldn't transform a class
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransformError {
+    /// The class model couldn't be edited or the edited code doesn't pass the verification
+    Model(ModelError),
+    /// The transformer panicked with the given message
+    Panicked(String),
+    /// Any other reason reported by the transformer
+    Failed(String),
+}
+
+impl From<ModelError> for TransformError {
+    fn from(err: ModelError) -> TransformError {
+        TransformError::Model(err)
+    }
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransformError::Model(ref err) => write!(f, "{:?}", err),
+            TransformError::Panicked(ref message) => write!(f, "panicked: {}", message),
+            TransformError::Failed(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+///
+/// A class file transformation that can be registered in a `TransformerChain`. Closures taking
+/// a `&mut JavaClass` implement this trait too.
+pub trait ClassTransformer: Send + Sync {
+    /// Whether the transformer is interested in the class with the given internal name. Classes
+    /// no transformer is interested in aren't parsed at all.
+    fn accepts(&self, _class_name: &str) -> bool {
+        true
+    }
+
+    /// Transform the class in place. Returns true if the class was modified.
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError>;
+}
+
+impl<T: ClassTransformer + ?Sized> ClassTransformer for Arc<T> {
+    fn accepts(&self, class_name: &str) -> bool {
+        (**self).accepts(class_name)
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        (**self).transform(class)
+    }
+}
+
+impl<F> ClassTransformer for F
+where
+    F: Fn(&mut JavaClass) -> Result<bool, TransformError> + Send + Sync,
+{
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        self(class)
+    }
+}
+
+///
+/// Counters of a single transformer in a chain
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransformerStats {
+    /// Number of classes the transformer was run on
+    pub invocations: u64,
+    /// Number of classes the transformer has modified successfully
+    pub modified: u64,
+    /// Number of errors and panics, including modifications that failed the verification
+    pub failures: u64,
+    pub panics: u64,
+    pub total_time: Duration,
+    pub max_time: Duration,
+    /// Name of the class and the error of the most recent failure
+    pub last_failure: Option<(String, TransformError)>,
+}
+
+///
+/// Counters of the chain itself
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChainStats {
+    /// Classes that at least one transformer was interested in
+    pub classes_parsed: u64,
+    /// Classes that were returned modified
+    pub classes_transformed: u64,
+    pub read_failures: u64,
+    pub write_failures: u64,
+}
+
+struct Entry {
+    name: String,
+    priority: i32,
+    transformer: Box<dyn ClassTransformer>,
+    stats: Mutex<TransformerStats>,
+}
+
+///
+/// Runs named transformers over a class in priority order. The class is parsed once and
+/// serialised once, however many transformers modify it.
+///
+/// Transformers are isolated from each other: if one of them returns an error, panics or leaves
+/// a method that fails the verification, its changes are discarded and the chain continues
+/// with the next one. If the class can't be parsed or serialised, the original bytes are kept.
+///
+/// Only methods whose code has changed are verified: their `max_stack` and `max_locals` are
+/// recomputed and their stack map frames are checked with `analysis::verify_frames`. Frames
+/// aren't recomputed, so transformers that change the control flow or the types of local
+/// variables are expected to do that themselves using `analysis::compute_frames`.
+pub struct TransformerChain {
+    entries: Vec<Entry>,
+    stats: Mutex<ChainStats>,
+}
+
+impl TransformerChain {
+    pub fn new() -> TransformerChain {
+        TransformerChain {
+            entries: vec![],
+            stats: Mutex::new(ChainStats::default()),
+        }
+    }
+
+    ///
+    /// Register a transformer. Transformers with a higher priority run first, those with equal
+    /// priority run in the order they were added. A transformer registered with a name that is
+    /// already in use replaces the existing one.
+    pub fn add<T: ClassTransformer + 'static>(&mut self, name: &str, priority: i32, transformer: T) {
+        self.remove(name);
+
+        let position = self
+            .entries
+            .iter()
+            .position(|entry| entry.priority < priority)
+            .unwrap_or(self.entries.len());
+
+        self.entries.insert(
+            position,
+            Entry {
+                name: name.to_string(),
+                priority,
+                transformer: Box::new(transformer),
+                stats: Mutex::new(TransformerStats::default()),
+            },
+        );
+    }
+
+    /// Remove a transformer, returns false if there's no transformer with the given name
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.name != name);
+        self.entries.len() != before
+    }
+
+    /// Names of the registered transformers, in the order they run
+    pub fn names(&self) -> Vec<String> {
+        self.entries.iter().map(|entry| entry.name.clone()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self, name: &str) -> Option<TransformerStats> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .and_then(|entry| entry.stats.lock().ok().map(|stats| stats.clone()))
+    }
+
+    pub fn chain_stats(&self) -> ChainStats {
+        self.stats.lock().map(|stats| stats.clone()).unwrap_or_default()
+    }
+
+    ///
+    /// Transform the class file with the given internal name. Returns the new class file, or
+    /// `None` if the class should be loaded as it is.
+    pub fn transform(&self, class_name: &str, class_data: &[u8]) -> Option<Vec<u8>> {
+        let entries: Vec<&Entry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.transformer.accepts(class_name))
+            .collect();
+
+        if entries.is_empty() {
+            return None;
+        }
+
+        self.count(|stats| stats.classes_parsed += 1);
+
+        let mut class = match read(class_data) {
+            Ok(class) => class,
+            Err(err) => {
+                log::warn!("Couldn't parse class {}: {}", class_name, err);
+                self.count(|stats| stats.read_failures += 1);
+                return None;
+            }
+        };
+
+        let mut modified = false;
+
+        for entry in entries {
+            let snapshot = class.clone();
+            let started = Instant::now();
+
+            let result = match catch_panic(AssertUnwindSafe(|| entry.transformer.transform(&mut class))) {
+                Ok(Ok(true)) => verify(&snapshot, &mut class).map(|_| true),
+                Ok(result) => result,
+                Err(payload) => Err(TransformError::Panicked(panic_message(payload))),
+            };
+
+            let elapsed = started.elapsed();
+
+            if let Ok(mut stats) = entry.stats.lock() {
+                stats.invocations += 1;
+                stats.total_time += elapsed;
+                stats.max_time = stats.max_time.max(elapsed);
+
+                match result {
+                    Ok(true) => stats.modified += 1,
+                    Ok(false) => (),
+                    Err(ref err) => {
+                        stats.failures += 1;
+
+                        if let &TransformError::Panicked(_) = err {
+                            stats.panics += 1;
+                        }
+
+                        stats.last_failure = Some((class_name.to_string(), err.clone()));
+                    }
+                }
+            }
+
+            match result {
+                Ok(changed) => modified |= changed,
+                Err(err) => {
+                    log::warn!("Transformer {} failed on {}: {}", entry.name, class_name, err);
+                    class = snapshot;
+                }
+            }
+        }
+
+        if !modified {
+            return None;
+        }
+
+        match write(&class) {
+            Ok(data) => {
+                self.count(|stats| stats.classes_transformed += 1);
+                Some(data)
+            }
+            Err(err) => {
+                log::warn!("Couldn't write transformed class {}: {}", class_name, err);
+                self.count(|stats| stats.write_failures += 1);
+                None
+            }
+        }
+    }
+
+    fn count<F: FnOnce(&mut ChainStats)>(&self, update: F) {
+        if let Ok(mut stats) = self.stats.lock() {
+            update(&mut stats);
+        }
+    }
+}
+
+impl Default for TransformerChain {
+    fn default() -> Self {
+        TransformerChain::new()
+    }
+}
+
+pub(crate) fn read(class_data: &[u8]) -> Result<JavaClass, String> {
+    let classfile = ClassReader::read_class(&mut Cursor::new(class_data)).map_err(|err| err.to_string())?;
+
+    JavaClass::from_classfile(&classfile).map_err(|err| format!("{:?}", err))
+}
+
+pub(crate) fn write(class: &JavaClass) -> Result<Vec<u8>, String> {
+    let classfile = class.to_classfile().map_err(|err| format!("{:?}", err))?;
+    let mut data = vec![];
+
+    ClassWriter::new(&mut data)
+        .write_class(&classfile)
+        .map_err(|err| err.to_string())?;
+
+    Ok(data)
+}
+
+/// Run the analysis on every method whose code differs from the original class, and check its
+/// frames if the class version requires them
+fn verify(original: &JavaClass, class: &mut JavaClass) -> Result<(), TransformError> {
+    let name = class.name.clone();
+    let has_frames = class.version.major_version >= 50;
+
+    for method in class.methods.iter_mut() {
+        if method.code.is_some() && original_code(original, method) != method.code.as_ref() {
+            analysis::compute_maxs(&name, method)?;
+
+            if has_frames {
+                analysis::verify_frames(&name, method)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn original_code<'a>(original: &'a JavaClass, method: &Method) -> Option<&'a Code> {
+    original
+        .methods
+        .iter()
+        .find(|m| m.name == method.name && m.descriptor == method.descriptor)
+        .and_then(|m| m.code.as_ref())
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
@@ -0,0 +1,441 @@
+use super::super::bytecode::classfile::{Attribute, Instruction, ReferenceKind};
+use super::descriptor::{JavaType, MethodDescriptor};
+
+///
+/// A `Label` marks a position in a method body. Branches, exception handlers, local variable
+/// ranges and stack map frames refer to labels instead of byte offsets, so instructions can be
+/// inserted or removed without recalculating any of them.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label {
+    id: usize,
+}
+
+impl Label {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+///
+/// A loadable constant, as used by `ldc`, field initialisers and bootstrap method arguments.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f32),
+    Long(i64),
+    Double(f64),
+    String(String),
+    /// Internal class name or array descriptor
+    Class(String),
+    /// Method descriptor
+    MethodType(String),
+    MethodHandle(Handle),
+}
+
+impl Value {
+    /// Long and double constants take up two constant pool slots and need `ldc2_w`
+    pub fn is_wide(&self) -> bool {
+        matches!(*self, Value::Long(_) | Value::Double(_))
+    }
+}
+
+///
+/// Symbolic reference to a field or a method of a class.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MemberRef {
+    pub owner: String,
+    pub name: String,
+    pub descriptor: String,
+    /// Whether the owner is an interface, ie. the reference is an `InterfaceMethodref`
+    pub interface: bool,
+}
+
+impl MemberRef {
+    pub fn new(owner: &str, name: &str, descriptor: &str) -> MemberRef {
+        MemberRef {
+            owner: owner.to_string(),
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+            interface: false,
+        }
+    }
+
+    pub fn interface(owner: &str, name: &str, descriptor: &str) -> MemberRef {
+        MemberRef {
+            interface: true,
+            ..MemberRef::new(owner, name, descriptor)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub kind: ReferenceKind,
+    pub member: MemberRef,
+}
+
+///
+/// Everything an `invokedynamic` instruction refers to: the call site's name and type and its
+/// bootstrap method along with the static arguments.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallSite {
+    pub name: String,
+    pub descriptor: String,
+    pub bootstrap: Handle,
+    pub arguments: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Jump {
+    IfEq,
+    IfNe,
+    IfLt,
+    IfGe,
+    IfGt,
+    IfLe,
+    IfICmpEq,
+    IfICmpNe,
+    IfICmpLt,
+    IfICmpGe,
+    IfICmpGt,
+    IfICmpLe,
+    IfACmpEq,
+    IfACmpNe,
+    IfNull,
+    IfNonNull,
+    Goto,
+    Jsr,
+}
+
+impl Jump {
+    /// Split a branch instruction into its kind and relative offset
+    pub fn from_instruction(instruction: &Instruction) -> Option<(Jump, i32)> {
+        match *instruction {
+            Instruction::IFEQ(o) => Some((Jump::IfEq, o as i32)),
+            Instruction::IFNE(o) => Some((Jump::IfNe, o as i32)),
+            Instruction::IFLT(o) => Some((Jump::IfLt, o as i32)),
+            Instruction::IFGE(o) => Some((Jump::IfGe, o as i32)),
+            Instruction::IFGT(o) => Some((Jump::IfGt, o as i32)),
+            Instruction::IFLE(o) => Some((Jump::IfLe, o as i32)),
+            Instruction::IF_ICMPEQ(o) => Some((Jump::IfICmpEq, o as i32)),
+            Instruction::IF_ICMPNE(o) => Some((Jump::IfICmpNe, o as i32)),
+            Instruction::IF_ICMPLT(o) => Some((Jump::IfICmpLt, o as i32)),
+            Instruction::IF_ICMPGE(o) => Some((Jump::IfICmpGe, o as i32)),
+            Instruction::IF_ICMPGT(o) => Some((Jump::IfICmpGt, o as i32)),
+            Instruction::IF_ICMPLE(o) => Some((Jump::IfICmpLe, o as i32)),
+            Instruction::IF_ACMPEQ(o) => Some((Jump::IfACmpEq, o as i32)),
+            Instruction::IF_ACMPNE(o) => Some((Jump::IfACmpNe, o as i32)),
+            Instruction::IFNULL(o) => Some((Jump::IfNull, o as i32)),
+            Instruction::IFNONNULL(o) => Some((Jump::IfNonNull, o as i32)),
+            Instruction::GOTO(o) => Some((Jump::Goto, o as i32)),
+            Instruction::GOTO_W(o) => Some((Jump::Goto, o)),
+            Instruction::JSR(o) => Some((Jump::Jsr, o as i32)),
+            Instruction::JSR_W(o) => Some((Jump::Jsr, o)),
+            _ => None,
+        }
+    }
+
+    /// Create the short (16-bit offset) form of this branch
+    pub fn to_instruction(&self, offset: i16) -> Instruction {
+        match *self {
+            Jump::IfEq => Instruction::IFEQ(offset),
+            Jump::IfNe => Instruction::IFNE(offset),
+            Jump::IfLt => Instruction::IFLT(offset),
+            Jump::IfGe => Instruction::IFGE(offset),
+            Jump::IfGt => Instruction::IFGT(offset),
+            Jump::IfLe => Instruction::IFLE(offset),
+            Jump::IfICmpEq => Instruction::IF_ICMPEQ(offset),
+            Jump::IfICmpNe => Instruction::IF_ICMPNE(offset),
+            Jump::IfICmpLt => Instruction::IF_ICMPLT(offset),
+            Jump::IfICmpGe => Instruction::IF_ICMPGE(offset),
+            Jump::IfICmpGt => Instruction::IF_ICMPGT(offset),
+            Jump::IfICmpLe => Instruction::IF_ICMPLE(offset),
+            Jump::IfACmpEq => Instruction::IF_ACMPEQ(offset),
+            Jump::IfACmpNe => Instruction::IF_ACMPNE(offset),
+            Jump::IfNull => Instruction::IFNULL(offset),
+            Jump::IfNonNull => Instruction::IFNONNULL(offset),
+            Jump::Goto => Instruction::GOTO(offset),
+            Jump::Jsr => Instruction::JSR(offset),
+        }
+    }
+
+    /// Create the wide (32-bit offset) form of this branch. Only `goto` and `jsr` have one.
+    pub fn to_wide_instruction(&self, offset: i32) -> Option<Instruction> {
+        match *self {
+            Jump::Goto => Some(Instruction::GOTO_W(offset)),
+            Jump::Jsr => Some(Instruction::JSR_W(offset)),
+            _ => None,
+        }
+    }
+
+    pub fn is_conditional(&self) -> bool {
+        !matches!(*self, Jump::Goto | Jump::Jsr)
+    }
+
+    /// Number of operand stack entries the branch consumes
+    pub fn operands(&self) -> usize {
+        match *self {
+            Jump::Goto | Jump::Jsr => 0,
+            Jump::IfEq
+            | Jump::IfNe
+            | Jump::IfLt
+            | Jump::IfGe
+            | Jump::IfGt
+            | Jump::IfLe
+            | Jump::IfNull
+            | Jump::IfNonNull => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldOp {
+    GetStatic,
+    PutStatic,
+    GetField,
+    PutField,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Invoke {
+    Virtual,
+    Special,
+    Static,
+    Interface,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TypeOp {
+    New,
+    ANewArray,
+    CheckCast,
+    InstanceOf,
+}
+
+///
+/// Verification type of a single local variable or operand stack entry in a stack map frame.
+/// Just like in the class file, `long` and `double` values are represented by a single item.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FrameItem {
+    Top,
+    Integer,
+    Float,
+    Long,
+    Double,
+    Null,
+    UninitializedThis,
+    /// Internal class name or array descriptor
+    Object(String),
+    /// Object created by the `new` instruction at the given label and not yet initialised
+    Uninitialized(Label),
+}
+
+impl FrameItem {
+    /// Verification type of a local variable or stack entry of the given type
+    pub fn from_type(java_type: &JavaType) -> Option<FrameItem> {
+        match *java_type {
+            JavaType::Void => None,
+            JavaType::Boolean
+            | JavaType::Byte
+            | JavaType::Char
+            | JavaType::Short
+            | JavaType::Int => Some(FrameItem::Integer),
+            JavaType::Float => Some(FrameItem::Float),
+            JavaType::Long => Some(FrameItem::Long),
+            JavaType::Double => Some(FrameItem::Double),
+            ref other => Some(FrameItem::Object(other.internal_name())),
+        }
+    }
+
+    pub fn is_wide(&self) -> bool {
+        matches!(*self, FrameItem::Long | FrameItem::Double)
+    }
+}
+
+///
+/// The expected state of local variables and the operand stack at a given point of the code.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Frame {
+    pub locals: Vec<FrameItem>,
+    pub stack: Vec<FrameItem>,
+}
+
+impl Frame {
+    pub fn new(locals: Vec<FrameItem>, stack: Vec<FrameItem>) -> Frame {
+        Frame {
+            locals,
+            stack,
+        }
+    }
+
+    /// The implicit frame at the start of a method: `this` (unless static) and the parameters
+    pub fn initial(
+        class_name: &str,
+        is_static: bool,
+        method_name: &str,
+        descriptor: &MethodDescriptor,
+    ) -> Frame {
+        let mut locals = vec![];
+
+        if !is_static {
+            if method_name == "<init>" && class_name != "java/lang/Object" {
+                locals.push(FrameItem::UninitializedThis);
+            } else {
+                locals.push(FrameItem::Object(class_name.to_string()));
+            }
+        }
+
+        locals.extend(descriptor.parameters.iter().filter_map(FrameItem::from_type));
+
+        Frame::new(locals, vec![])
+    }
+}
+
+///
+/// A single element of a method body. Apart from real instructions, the list contains
+/// pseudo-instructions that mark positions (`Label`), source lines (`LineNumber`) and stack map
+/// frames (`Frame`). These don't take up any space in the generated bytecode.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Insn {
+    Label(Label),
+    /// The next instruction is the first one generated from the given source line
+    LineNumber(u16),
+    /// Stack map frame that holds at the next instruction
+    Frame(Frame),
+    /// An instruction that refers to neither the constant pool nor code offsets (eg. `iadd`,
+    /// `aload_0`, `bipush` or `iinc`)
+    Op(Instruction),
+    Jump(Jump, Label),
+    TableSwitch {
+        default: Label,
+        low: i32,
+        high: i32,
+        targets: Vec<Label>,
+    },
+    LookupSwitch {
+        default: Label,
+        pairs: Vec<(i32, Label)>,
+    },
+    Ldc(Value),
+    Field(FieldOp, MemberRef),
+    Invoke(Invoke, MemberRef),
+    InvokeDynamic(CallSite),
+    Type(TypeOp, String),
+    MultiANewArray(String, u8),
+}
+
+impl Insn {
+    /// Returns true if this element generates actual bytecode
+    pub fn is_instruction(&self) -> bool {
+        !matches!(*self, Insn::Label(_) | Insn::LineNumber(_) | Insn::Frame(_))
+    }
+}
+
+///
+/// Returns true if the given low-level instruction can be stored as `Insn::Op`, ie. it has no
+/// constant pool or branch offset operands.
+pub fn is_plain_instruction(instruction: &Instruction) -> bool {
+    match *instruction {
+        Instruction::ANEWARRAY(_)
+        | Instruction::CHECKCAST(_)
+        | Instruction::GETFIELD(_)
+        | Instruction::GETSTATIC(_)
+        | Instruction::PUTFIELD(_)
+        | Instruction::PUTSTATIC(_)
+        | Instruction::INSTANCEOF(_)
+        | Instruction::INVOKEDYNAMIC(_)
+        | Instruction::INVOKEINTERFACE(_, _)
+        | Instruction::INVOKESPECIAL(_)
+        | Instruction::INVOKESTATIC(_)
+        | Instruction::INVOKEVIRTUAL(_)
+        | Instruction::LDC(_)
+        | Instruction::LDC_W(_)
+        | Instruction::LDC2_W(_)
+        | Instruction::LOOKUPSWITCH(_, _)
+        | Instruction::TABLESWITCH(_, _, _, _)
+        | Instruction::MULTIANEWARRAY(_, _)
+        | Instruction::NEW(_)
+        | Instruction::PADDED_INSTRUCTION(_)
+        | Instruction::WTF(_) => false,
+        ref other => Jump::from_instruction(other).is_none(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryCatchBlock {
+    pub start: Label,
+    pub end: Label,
+    pub handler: Label,
+    /// Internal name of the caught exception class, `None` catches everything (`finally`)
+    pub catch_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVariable {
+    pub name: String,
+    pub descriptor: String,
+    /// Generic signature from the `LocalVariableTypeTable`, if any
+    pub signature: Option<String>,
+    pub start: Label,
+    pub end: Label,
+    pub index: u16,
+}
+
+///
+/// The body of a method.
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Code {
+    pub max_stack: u16,
+    pub max_locals: u16,
+    pub instructions: Vec<Insn>,
+    pub try_catch_blocks: Vec<TryCatchBlock>,
+    pub local_variables: Vec<LocalVariable>,
+    /// Attributes of the `Code` attribute that aren't modelled explicitly. Constant pool
+    /// references in these refer to the pool of the class the code was read from.
+    pub attributes: Vec<Attribute>,
+    next_label: usize,
+}
+
+impl Code {
+    pub fn new() -> Code {
+        Code::default()
+    }
+
+    /// Allocate a new label that is unique within this method body. The label has to be placed
+    /// into the instruction list with `Insn::Label` before the code can be converted to bytecode.
+    pub fn new_label(&mut self) -> Label {
+        let label = Label {
+            id: self.next_label,
+        };
+        self.next_label += 1;
+        label
+    }
+
+    /// Returns true if the code contains any stack map frames
+    pub fn has_frames(&self) -> bool {
+        self.instructions.iter().any(|insn| matches!(*insn, Insn::Frame(_)))
+    }
+
+    /// Returns the source line of the instruction at `position` in the instruction list
+    pub fn line_at(&self, position: usize) -> Option<u16> {
+        let end = (position + 1).min(self.instructions.len());
+
+        self.instructions[..end]
+            .iter()
+            .rev()
+            .filter_map(|insn| match *insn {
+                Insn::LineNumber(line) => Some(line),
+                _ => None,
+            })
+            .next()
+    }
+}
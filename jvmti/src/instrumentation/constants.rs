@@ -0,0 +1,425 @@
+use super::super::bytecode::classfile::*;
+use super::code::{CallSite, Handle, MemberRef, Value};
+use super::ModelError;
+use std::collections::HashMap;
+
+///
+/// Decode a "modified UTF-8" string as stored in `CONSTANT_Utf8` entries. Unpaired surrogates are
+/// replaced by U+FFFD since they can't be represented in a Rust string.
+pub fn decode_utf8(bytes: &[u8]) -> Result<String, ModelError> {
+    let mut units: Vec<u16> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    let continuation = |idx: usize| -> Result<u16, ModelError> {
+        match bytes.get(idx) {
+            Some(&b) if b & 0xC0 == 0x80 => Ok((b & 0x3F) as u16),
+            _ => Err(ModelError::InvalidUtf8(bytes.to_vec())),
+        }
+    };
+
+    while i < bytes.len() {
+        let b = bytes[i];
+
+        if b & 0x80 == 0 {
+            units.push(b as u16);
+            i += 1;
+        } else if b & 0xE0 == 0xC0 {
+            units.push(((b & 0x1F) as u16) << 6 | continuation(i + 1)?);
+            i += 2;
+        } else if b & 0xF0 == 0xE0 {
+            units.push(((b & 0x0F) as u16) << 12 | continuation(i + 1)? << 6 | continuation(i + 2)?);
+            i += 3;
+        } else if b & 0xF8 == 0xF0 {
+            // Not valid in modified UTF-8 but some class file generators emit standard 4-byte
+            // sequences for supplementary characters anyway.
+            let c = ((b & 0x07) as u32) << 18
+                | (continuation(i + 1)? as u32) << 12
+                | (continuation(i + 2)? as u32) << 6
+                | continuation(i + 3)? as u32;
+            let c = c - 0x10000;
+            units.push(0xD800 | (c >> 10) as u16);
+            units.push(0xDC00 | (c & 0x3FF) as u16);
+            i += 4;
+        } else {
+            return Err(ModelError::InvalidUtf8(bytes.to_vec()));
+        }
+    }
+
+    Ok(String::from_utf16_lossy(&units))
+}
+
+/// Encode a string in the "modified UTF-8" format used by the class file format
+pub fn encode_utf8(string: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(string.len());
+
+    for unit in string.encode_utf16() {
+        match unit {
+            0x0001..=0x007F => bytes.push(unit as u8),
+            0x0000..=0x07FF => {
+                bytes.push(0xC0 | (unit >> 6) as u8);
+                bytes.push(0x80 | (unit & 0x3F) as u8);
+            }
+            _ => {
+                bytes.push(0xE0 | (unit >> 12) as u8);
+                bytes.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                bytes.push(0x80 | (unit & 0x3F) as u8);
+            }
+        }
+    }
+
+    bytes
+}
+
+///
+/// Resolves constant pool indices into symbolic values.
+///
+pub struct ConstantResolver<'a> {
+    pool: &'a ConstantPool,
+    bootstrap_methods: &'a [BootstrapMethod],
+}
+
+impl<'a> ConstantResolver<'a> {
+    pub fn new(pool: &'a ConstantPool, bootstrap_methods: &'a [BootstrapMethod]) -> Self {
+        ConstantResolver {
+            pool,
+            bootstrap_methods,
+        }
+    }
+
+    pub fn constant(&self, idx: usize) -> Result<&'a Constant, ModelError> {
+        match self.pool.constants.get(idx) {
+            Some(&Constant::Placeholder) | None => Err(ModelError::MissingConstant(idx)),
+            Some(constant) => Ok(constant),
+        }
+    }
+
+    pub fn utf8(&self, idx: usize) -> Result<String, ModelError> {
+        match *self.constant(idx)? {
+            Constant::Utf8(ref bytes) => decode_utf8(bytes),
+            _ => Err(ModelError::UnexpectedConstant(idx)),
+        }
+    }
+
+    pub fn class_name(&self, idx: usize) -> Result<String, ModelError> {
+        match *self.constant(idx)? {
+            Constant::Class(ref name) => self.utf8(name.idx),
+            _ => Err(ModelError::UnexpectedConstant(idx)),
+        }
+    }
+
+    /// Same as `class_name` except that index 0 is interpreted as "no class"
+    pub fn optional_class_name(&self, idx: usize) -> Result<Option<String>, ModelError> {
+        match idx {
+            0 => Ok(None),
+            _ => self.class_name(idx).map(Some),
+        }
+    }
+
+    pub fn name_and_type(&self, idx: usize) -> Result<(String, String), ModelError> {
+        match *self.constant(idx)? {
+            Constant::NameAndType {
+                ref name_index,
+                ref descriptor_index,
+            } => Ok((self.utf8(name_index.idx)?, self.utf8(descriptor_index.idx)?)),
+            _ => Err(ModelError::UnexpectedConstant(idx)),
+        }
+    }
+
+    pub fn member_ref(&self, idx: usize) -> Result<MemberRef, ModelError> {
+        let (class_index, name_and_type_index, interface) = match *self.constant(idx)? {
+            Constant::FieldRef {
+                ref class_index,
+                ref name_and_type_index,
+            }
+            | Constant::MethodRef {
+                ref class_index,
+                ref name_and_type_index,
+            } => (class_index, name_and_type_index, false),
+            Constant::InterfaceMethodRef {
+                ref class_index,
+                ref name_and_type_index,
+            } => (class_index, name_and_type_index, true),
+            _ => return Err(ModelError::UnexpectedConstant(idx)),
+        };
+
+        let (name, descriptor) = self.name_and_type(name_and_type_index.idx)?;
+
+        Ok(MemberRef {
+            owner: self.class_name(class_index.idx)?,
+            name,
+            descriptor,
+            interface,
+        })
+    }
+
+    pub fn handle(&self, idx: usize) -> Result<Handle, ModelError> {
+        match *self.constant(idx)? {
+            Constant::MethodHandle {
+                ref reference_kind,
+                ref reference_index,
+            } => Ok(Handle {
+                kind: reference_kind.clone(),
+                member: self.member_ref(reference_index.idx)?,
+            }),
+            _ => Err(ModelError::UnexpectedConstant(idx)),
+        }
+    }
+
+    /// Resolve a loadable constant
+    pub fn value(&self, idx: usize) -> Result<Value, ModelError> {
+        match *self.constant(idx)? {
+            Constant::Integer(value) => Ok(Value::Int(value as i32)),
+            Constant::Float(value) => Ok(Value::Float(f32::from_bits(value))),
+            Constant::Long(value) => Ok(Value::Long(value as i64)),
+            Constant::Double(value) => Ok(Value::Double(f64::from_bits(value))),
+            Constant::String(ref string) => self.utf8(string.idx).map(Value::String),
+            Constant::Class(ref name) => self.utf8(name.idx).map(Value::Class),
+            Constant::MethodType(ref descriptor) => {
+                self.utf8(descriptor.idx).map(Value::MethodType)
+            }
+            Constant::MethodHandle { .. } => self.handle(idx).map(Value::MethodHandle),
+            _ => Err(ModelError::UnexpectedConstant(idx)),
+        }
+    }
+
+    pub fn call_site(&self, idx: usize) -> Result<CallSite, ModelError> {
+        match *self.constant(idx)? {
+            Constant::InvokeDynamic {
+                ref bootstrap_method_attr_index,
+                ref name_and_type_index,
+            } => {
+                let (name, descriptor) = self.name_and_type(name_and_type_index.idx)?;
+
+                match self.bootstrap_methods.get(bootstrap_method_attr_index.idx) {
+                    Some(method) => Ok(CallSite {
+                        name,
+                        descriptor,
+                        bootstrap: self.handle(method.bootstrap_method_ref.idx)?,
+                        arguments: method
+                            .bootstrap_arguments
+                            .iter()
+                            .map(|arg| self.value(arg.idx))
+                            .collect::<Result<Vec<Value>, ModelError>>()?,
+                    }),
+                    None => Err(ModelError::MissingBootstrapMethod(
+                        bootstrap_method_attr_index.idx,
+                    )),
+                }
+            }
+            _ => Err(ModelError::UnexpectedConstant(idx)),
+        }
+    }
+}
+
+///
+/// Builds a constant pool by interning symbolic values. Each distinct constant is added only
+/// once. When seeded with an existing pool, all existing entries keep their indices so attributes
+/// that still refer to the old pool remain valid.
+///
+#[derive(Debug, Clone)]
+pub struct ConstantPoolBuilder {
+    constants: Vec<Constant>,
+    index: HashMap<Constant, usize>,
+    bootstrap_methods: Vec<BootstrapMethod>,
+}
+
+impl Default for ConstantPoolBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConstantPoolBuilder {
+    pub fn new() -> ConstantPoolBuilder {
+        ConstantPoolBuilder {
+            constants: vec![Constant::Placeholder],
+            index: HashMap::new(),
+            bootstrap_methods: vec![],
+        }
+    }
+
+    /// Start from an existing constant pool and bootstrap method table
+    pub fn seeded(pool: &ConstantPool, bootstrap_methods: &[BootstrapMethod]) -> ConstantPoolBuilder {
+        let mut builder = ConstantPoolBuilder::new();
+
+        if !pool.constants.is_empty() {
+            builder.constants = pool.constants.clone();
+        }
+
+        for (idx, constant) in builder.constants.iter().enumerate() {
+            match *constant {
+                Constant::Placeholder | Constant::Unknown(_) => (),
+                ref other => {
+                    builder.index.entry(other.clone()).or_insert(idx);
+                }
+            }
+        }
+
+        builder.bootstrap_methods = bootstrap_methods.to_vec();
+        builder
+    }
+
+    /// Number of slots used in the pool, including the unused 0th entry
+    pub fn len(&self) -> usize {
+        self.constants.len()
+    }
+
+    /// Returns true if nothing but the unused 0th entry has been added yet
+    pub fn is_empty(&self) -> bool {
+        self.constants.len() <= 1
+    }
+
+    pub fn add(&mut self, constant: Constant) -> ConstantPoolIndex {
+        if let Some(&idx) = self.index.get(&constant) {
+            return ConstantPoolIndex::new(idx);
+        }
+
+        let idx = self.constants.len();
+        let size = constant.cp_size();
+
+        self.index.insert(constant.clone(), idx);
+        self.constants.push(constant);
+
+        // Long and double constants are followed by an unusable slot
+        for _ in 1..size {
+            self.constants.push(Constant::Placeholder);
+        }
+
+        ConstantPoolIndex::new(idx)
+    }
+
+    pub fn utf8(&mut self, value: &str) -> ConstantPoolIndex {
+        self.add(Constant::Utf8(encode_utf8(value)))
+    }
+
+    pub fn class(&mut self, name: &str) -> ConstantPoolIndex {
+        let name_index = self.utf8(name);
+        self.add(Constant::Class(name_index))
+    }
+
+    pub fn string(&mut self, value: &str) -> ConstantPoolIndex {
+        let string_index = self.utf8(value);
+        self.add(Constant::String(string_index))
+    }
+
+    pub fn integer(&mut self, value: i32) -> ConstantPoolIndex {
+        self.add(Constant::Integer(value as u32))
+    }
+
+    pub fn float(&mut self, value: f32) -> ConstantPoolIndex {
+        self.add(Constant::Float(value.to_bits()))
+    }
+
+    pub fn long(&mut self, value: i64) -> ConstantPoolIndex {
+        self.add(Constant::Long(value as u64))
+    }
+
+    pub fn double(&mut self, value: f64) -> ConstantPoolIndex {
+        self.add(Constant::Double(value.to_bits()))
+    }
+
+    pub fn name_and_type(&mut self, name: &str, descriptor: &str) -> ConstantPoolIndex {
+        let name_index = self.utf8(name);
+        let descriptor_index = self.utf8(descriptor);
+
+        self.add(Constant::NameAndType {
+            name_index,
+            descriptor_index,
+        })
+    }
+
+    pub fn field_ref(&mut self, member: &MemberRef) -> ConstantPoolIndex {
+        let class_index = self.class(&member.owner);
+        let name_and_type_index = self.name_and_type(&member.name, &member.descriptor);
+
+        self.add(Constant::FieldRef {
+            class_index,
+            name_and_type_index,
+        })
+    }
+
+    pub fn method_ref(&mut self, member: &MemberRef) -> ConstantPoolIndex {
+        let class_index = self.class(&member.owner);
+        let name_and_type_index = self.name_and_type(&member.name, &member.descriptor);
+
+        if member.interface {
+            self.add(Constant::InterfaceMethodRef {
+                class_index,
+                name_and_type_index,
+            })
+        } else {
+            self.add(Constant::MethodRef {
+                class_index,
+                name_and_type_index,
+            })
+        }
+    }
+
+    pub fn handle(&mut self, handle: &Handle) -> ConstantPoolIndex {
+        let reference_index = match handle.kind {
+            ReferenceKind::GetField
+            | ReferenceKind::GetStatic
+            | ReferenceKind::PutField
+            | ReferenceKind::PutStatic => self.field_ref(&handle.member),
+            _ => self.method_ref(&handle.member),
+        };
+
+        self.add(Constant::MethodHandle {
+            reference_kind: handle.kind.clone(),
+            reference_index,
+        })
+    }
+
+    pub fn method_type(&mut self, descriptor: &str) -> ConstantPoolIndex {
+        let descriptor_index = self.utf8(descriptor);
+        self.add(Constant::MethodType(descriptor_index))
+    }
+
+    pub fn value(&mut self, value: &Value) -> ConstantPoolIndex {
+        match *value {
+            Value::Int(v) => self.integer(v),
+            Value::Float(v) => self.float(v),
+            Value::Long(v) => self.long(v),
+            Value::Double(v) => self.double(v),
+            Value::String(ref v) => self.string(v),
+            Value::Class(ref v) => self.class(v),
+            Value::MethodType(ref v) => self.method_type(v),
+            Value::MethodHandle(ref v) => self.handle(v),
+        }
+    }
+
+    /// Add an `InvokeDynamic` constant along with its bootstrap method table entry
+    pub fn call_site(&mut self, call_site: &CallSite) -> ConstantPoolIndex {
+        let bootstrap = BootstrapMethod {
+            bootstrap_method_ref: self.handle(&call_site.bootstrap),
+            bootstrap_arguments: call_site
+                .arguments
+                .iter()
+                .map(|arg| self.value(arg))
+                .collect(),
+        };
+
+        let bootstrap_index = match self.bootstrap_methods.iter().position(|m| *m == bootstrap) {
+            Some(idx) => idx,
+            None => {
+                self.bootstrap_methods.push(bootstrap);
+                self.bootstrap_methods.len() - 1
+            }
+        };
+
+        let name_and_type_index = self.name_and_type(&call_site.name, &call_site.descriptor);
+
+        self.add(Constant::InvokeDynamic {
+            bootstrap_method_attr_index: ConstantPoolIndex::new(bootstrap_index),
+            name_and_type_index,
+        })
+    }
+
+    pub fn bootstrap_methods(&self) -> &[BootstrapMethod] {
+        &self.bootstrap_methods
+    }
+
+    pub fn into_parts(self) -> (ConstantPool, Vec<BootstrapMethod>) {
+        (ConstantPool::new(self.constants), self.bootstrap_methods)
+    }
+}
@@ -0,0 +1,610 @@
+//!
+//! Invocation counters kept by the instrumented classes themselves. Timing and coverage probes
+//! call a native method for every hit, which is too expensive for very hot code, so instead each
+//! instrumented class gets a synthetic `long[]` field whose slots the woven bytecode increments
+//! directly. Depending on the `CounterMode` a slot counts the invocations of a method, or the
+//! calls made by one of its invoke instructions.
+//!
+//! A daemon thread started by `start_reader` reads the arrays through JNI at a fixed interval
+//! and sums them per class name into the `CounterRegistry` of the `AgentContext`.
+//!
+//! ```ignore
+//! let probes = CounterProbes::from_config(&config, static_context().counters.clone())?;
+//! static_context().add_transformer("counters", 0, probes);
+//! agent.use_transformer_chain();
+//! agent.on_vm_init(Some(on_vm_init));
+//!
+//! fn on_vm_init(_jvmti: JVMTIEnvPtr, jni: JNIEnvPtr, _thread: jthread) {
+//!     counters::start_reader(jni, static_context().counters.clone(), Duration::from_millis(500)).unwrap();
+//! }
+//! ```
+//!
+//! The woven code doesn't call anything, so unlike the other probes the weaving can start as
+//! soon as the transformer is registered. The field can't be added by a retransformation though,
+//! so classes that are already loaded when the transformer is registered in the live phase have
+//! to be excluded with `CounterProbes::exclude_loaded`. Interfaces aren't counted.
+//!
+//! The increments aren't atomic, so a site that runs on several threads at once loses some of
+//! its increments, for a tight loop on a few threads as much as half of them. The counts suit
+//! finding the hot spots rather than exact call counts. The counts of a class are gone once the
+//! class is unloaded.
+
+use super::super::bytecode::classfile::{FieldAccessFlags, Instruction, MethodAccessFlags};
+use super::super::config::Config;
+use super::super::environment::jni::{JNIEnvironment, JNIError, JNI};
+use super::super::environment::jvm::{JVMAgent, JVMF};
+use super::super::environment::jvmti::JVMTI;
+use super::super::error::NativeError;
+use super::super::native::jvmti_native::JVMTI_CLASS_STATUS_INITIALIZED;
+use super::super::native::{JNIEnvPtr, JavaClass as JavaClassPtr, JavaVMPtr};
+use super::analysis;
+use super::chain::{ClassTransformer, TransformError};
+use super::code::{Code, FieldOp, Insn, MemberRef};
+use super::dynamic::internal_name;
+use super::pointcut::{ClassInfo, MethodInfo, Pointcut};
+use super::timing::{push_int, ProbeError};
+use super::trace::HELPER_PACKAGE;
+use super::{AccessFlags, Field, JavaClass, JavaType, Method, MethodDescriptor, ModelError};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// Name of the synthetic field that holds the counters of a class
+pub const COUNTER_FIELD: &str = "$jvmti$counters";
+/// `long[]`
+pub const COUNTER_DESCRIPTOR: &str = "[J";
+/// Name of the thread that reads the counters
+pub const READER_THREAD: &str = "jvmti counter reader";
+
+/// Array type operand of `newarray` for `long`
+const T_LONG: u8 = 11;
+
+static READER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+///
+/// What a slot of the counter array counts
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CounterMode {
+    /// The invocations of each selected method
+    #[default]
+    Method,
+    /// The calls made by each invoke instruction of the selected methods
+    CallSite,
+}
+
+///
+/// A counted method, or a counted call made by it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterSite {
+    /// Internal name of the class that contains the method
+    pub class_name: String,
+    pub method_name: String,
+    pub descriptor: String,
+    /// The called method of a call site, eg. `java/util/List.add(Ljava/lang/Object;)Z`.
+    /// `invokedynamic` call sites only have a name and descriptor.
+    pub callee: Option<String>,
+    /// Source line of the call site
+    pub line: Option<u16>,
+}
+
+struct ClassCounters {
+    sites: Vec<CounterSite>,
+    counts: Vec<u64>,
+}
+
+///
+/// The counter slots of each instrumented class and their values as of the last read
+pub struct CounterRegistry {
+    classes: RwLock<BTreeMap<String, ClassCounters>>,
+}
+
+impl CounterRegistry {
+    pub fn new() -> CounterRegistry {
+        CounterRegistry {
+            classes: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Register the slots of a class, in slot order. Classes with the same name loaded by
+    /// different class loaders share their slots, the counts are reset if the slots change.
+    pub fn register(&self, class_name: &str, sites: Vec<CounterSite>) {
+        if let Ok(mut classes) = self.classes.write() {
+            let unchanged = classes
+                .get(class_name)
+                .map(|existing| existing.sites == sites)
+                .unwrap_or(false);
+
+            if !unchanged {
+                let counts = vec![0; sites.len()];
+
+                classes.insert(class_name.to_string(), ClassCounters { sites, counts });
+            }
+        }
+    }
+
+    /// Number of slots of the class, `None` if it isn't instrumented
+    pub fn slots(&self, class_name: &str) -> Option<usize> {
+        self.classes
+            .read()
+            .ok()
+            .and_then(|classes| classes.get(class_name).map(|class| class.sites.len()))
+    }
+
+    pub fn sites(&self, class_name: &str) -> Vec<CounterSite> {
+        self.classes
+            .read()
+            .ok()
+            .and_then(|classes| classes.get(class_name).map(|class| class.sites.clone()))
+            .unwrap_or_default()
+    }
+
+    /// Replace the counts of a class with the values read from its counter arrays. Missing
+    /// values are taken as zero, extra ones are ignored.
+    pub fn update(&self, class_name: &str, counts: &[u64]) {
+        if let Ok(mut classes) = self.classes.write() {
+            if let Some(class) = classes.get_mut(class_name) {
+                for (slot, count) in class.counts.iter_mut().enumerate() {
+                    *count = counts.get(slot).cloned().unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    pub fn count(&self, class_name: &str, slot: usize) -> Option<u64> {
+        self.classes
+            .read()
+            .ok()
+            .and_then(|classes| classes.get(class_name).and_then(|class| class.counts.get(slot).cloned()))
+    }
+
+    /// All sites with their counts as of the last read, ordered by class name and slot
+    pub fn snapshot(&self) -> Vec<(CounterSite, u64)> {
+        match self.classes.read() {
+            Ok(classes) => classes
+                .values()
+                .flat_map(|class| class.sites.iter().cloned().zip(class.counts.iter().cloned()))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Number of registered sites
+    pub fn len(&self) -> usize {
+        self.classes
+            .read()
+            .map(|classes| classes.values().map(|class| class.sites.len()).sum())
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for CounterRegistry {
+    fn default() -> Self {
+        CounterRegistry::new()
+    }
+}
+
+///
+/// Describes why the counters couldn't be read
+#[derive(Debug)]
+pub enum CounterError {
+    Jvmti(NativeError),
+    Jni(JNIError),
+}
+
+impl fmt::Display for CounterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CounterError::Jvmti(ref err) => write!(f, "JVMTI error: {}", err),
+            CounterError::Jni(ref err) => write!(f, "JNI error: {:?}", err),
+        }
+    }
+}
+
+impl From<NativeError> for CounterError {
+    fn from(err: NativeError) -> CounterError {
+        CounterError::Jvmti(err)
+    }
+}
+
+impl From<JNIError> for CounterError {
+    fn from(err: JNIError) -> CounterError {
+        CounterError::Jni(err)
+    }
+}
+
+///
+/// A `ClassTransformer` that weaves counters into the methods selected by any of its pointcuts.
+/// Abstract and native methods and static initialisers are skipped.
+pub struct CounterProbes {
+    pointcuts: Vec<Pointcut>,
+    mode: CounterMode,
+    registry: Arc<CounterRegistry>,
+    excluded: RwLock<HashSet<String>>,
+}
+
+impl CounterProbes {
+    pub fn new(pointcuts: Vec<Pointcut>, mode: CounterMode, registry: Arc<CounterRegistry>) -> CounterProbes {
+        CounterProbes {
+            pointcuts,
+            mode,
+            registry,
+            excluded: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Count the methods selected by the pointcuts listed in the `[counters]` section
+    pub fn from_config(config: &Config, registry: Arc<CounterRegistry>) -> Result<CounterProbes, ProbeError> {
+        let (names, mode) = match config.counters {
+            Some(ref counters) => (counters.pointcuts.clone(), counters.mode),
+            None => (vec![], CounterMode::default()),
+        };
+
+        let pointcuts = names
+            .iter()
+            .map(|name| {
+                config
+                    .pointcut(name)
+                    .cloned()
+                    .ok_or_else(|| ProbeError::UnknownPointcut(name.clone()))
+            })
+            .collect::<Result<Vec<Pointcut>, ProbeError>>()?;
+
+        Ok(CounterProbes::new(pointcuts, mode, registry))
+    }
+
+    pub fn registry(&self) -> Arc<CounterRegistry> {
+        self.registry.clone()
+    }
+
+    ///
+    /// Never weave the classes that are currently loaded, because retransforming them would
+    /// fail once the field is added. Returns the number of excluded classes.
+    pub fn exclude_loaded(&self, jvmti: &dyn JVMTI) -> Result<usize, NativeError> {
+        let mut names = vec![];
+
+        for class in jvmti.get_loaded_classes()?.iter() {
+            if let Some(name) = internal_name(&jvmti.get_class_signature(class)?) {
+                names.push(name);
+            }
+        }
+
+        let count = names.len();
+
+        if let Ok(mut excluded) = self.excluded.write() {
+            excluded.extend(names);
+        }
+
+        Ok(count)
+    }
+
+    fn is_excluded(&self, class_name: &str) -> bool {
+        self.excluded
+            .read()
+            .map(|excluded| excluded.contains(class_name))
+            .unwrap_or(false)
+    }
+}
+
+impl ClassTransformer for CounterProbes {
+    fn accepts(&self, class_name: &str) -> bool {
+        let class = ClassInfo::new(class_name);
+
+        !class_name.starts_with(HELPER_PACKAGE)
+            && self.pointcuts.iter().any(|p| p.matches_class(&class))
+            && !self.is_excluded(class_name)
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        if class.is_interface() || class.field(COUNTER_FIELD).is_some() {
+            return Ok(false);
+        }
+
+        let class_info = ClassInfo::from_class(class);
+
+        let selected: Vec<usize> = class
+            .methods
+            .iter()
+            .enumerate()
+            .filter(|&(_, method)| {
+                method.code.is_some()
+                    && method.name != "<clinit>"
+                    && self
+                        .pointcuts
+                        .iter()
+                        .any(|p| p.matches_method(&class_info, &MethodInfo::from_method(class, method)))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        let sites = weave(class, &selected, self.mode)?;
+
+        if sites.is_empty() {
+            return Ok(false);
+        }
+
+        self.registry.register(&class.name, sites);
+
+        Ok(true)
+    }
+}
+
+///
+/// Weave counters into the methods of the class and add the counter field, which the static
+/// initialiser creates before anything else. Returns the sites in slot order, the class is left
+/// unchanged if there are none. The increments neither branch nor need local variables, so the
+/// existing stack map frames remain valid.
+pub fn weave(class: &mut JavaClass, method_indices: &[usize], mode: CounterMode) -> Result<Vec<CounterSite>, ModelError> {
+    let class_name = class.name.clone();
+    let mut sites = vec![];
+
+    for &index in method_indices {
+        let method = &mut class.methods[index];
+
+        if method.name == "<clinit>" {
+            continue;
+        }
+
+        let method_name = method.name.clone();
+        let descriptor = method.descriptor.descriptor();
+
+        let woven = {
+            let code = match method.code.as_mut() {
+                Some(code) => code,
+                None => continue,
+            };
+
+            let site = |callee: Option<String>, line: Option<u16>| CounterSite {
+                class_name: class_name.clone(),
+                method_name: method_name.clone(),
+                descriptor: descriptor.clone(),
+                callee,
+                line,
+            };
+
+            match mode {
+                CounterMode::Method => {
+                    let mut instructions = increment(&class_name, sites.len());
+                    instructions.append(&mut code.instructions);
+                    code.instructions = instructions;
+
+                    sites.push(site(None, None));
+                    true
+                }
+                CounterMode::CallSite => {
+                    let mut instructions = vec![];
+                    let mut line = None;
+                    let count = sites.len();
+
+                    for insn in code.instructions.drain(..) {
+                        if let Insn::LineNumber(number) = insn {
+                            line = Some(number);
+                        }
+
+                        if let Some(callee) = callee(&insn) {
+                            instructions.extend(increment(&class_name, sites.len()));
+                            sites.push(site(Some(callee), line));
+                        }
+
+                        instructions.push(insn);
+                    }
+
+                    code.instructions = instructions;
+                    sites.len() > count
+                }
+            }
+        };
+
+        if woven {
+            analysis::compute_maxs(&class_name, method)?;
+        }
+    }
+
+    if !sites.is_empty() {
+        add_counter_field(class, sites.len())?;
+    }
+
+    Ok(sites)
+}
+
+/// `$jvmti$counters[slot]++`
+fn increment(class_name: &str, slot: usize) -> Vec<Insn> {
+    vec![
+        Insn::Field(FieldOp::GetStatic, MemberRef::new(class_name, COUNTER_FIELD, COUNTER_DESCRIPTOR)),
+        push_int(slot as i32),
+        Insn::Op(Instruction::DUP2),
+        Insn::Op(Instruction::LALOAD),
+        Insn::Op(Instruction::LCONST_1),
+        Insn::Op(Instruction::LADD),
+        Insn::Op(Instruction::LASTORE),
+    ]
+}
+
+/// The method called by an invoke instruction
+fn callee(insn: &Insn) -> Option<String> {
+    match *insn {
+        Insn::Invoke(_, ref member) => Some(format!("{}.{}{}", member.owner, member.name, member.descriptor)),
+        Insn::InvokeDynamic(ref call_site) => Some(format!("{}{}", call_site.name, call_site.descriptor)),
+        _ => None,
+    }
+}
+
+/// Add the counter field and create the array at the start of the static initialiser
+fn add_counter_field(class: &mut JavaClass, slots: usize) -> Result<(), ModelError> {
+    let class_name = class.name.clone();
+
+    let mut field = Field::new(COUNTER_FIELD.to_string(), JavaType::Array(Box::new(JavaType::Long)));
+    field.access_flags = AccessFlags::of(
+        FieldAccessFlags::Private as u16
+            | FieldAccessFlags::Static as u16
+            | FieldAccessFlags::Final as u16
+            | FieldAccessFlags::Synthetic as u16,
+    );
+    class.add_field(field);
+
+    let mut instructions = vec![
+        push_int(slots as i32),
+        Insn::Op(Instruction::NEWARRAY(T_LONG)),
+        Insn::Field(FieldOp::PutStatic, MemberRef::new(&class_name, COUNTER_FIELD, COUNTER_DESCRIPTOR)),
+    ];
+
+    if class.method("<clinit>", "()V").is_none() {
+        let mut method = Method::new("<clinit>".to_string(), MethodDescriptor::new(vec![], JavaType::Void));
+        method.access_flags = AccessFlags::of(MethodAccessFlags::Static as u16);
+        method.code = Some(Code::new());
+        class.add_method(method);
+
+        instructions.push(Insn::Op(Instruction::RETURN));
+    }
+
+    let initialiser = class.method_mut("<clinit>", "()V").unwrap();
+
+    if let Some(code) = initialiser.code.as_mut() {
+        instructions.append(&mut code.instructions);
+        code.instructions = instructions;
+    }
+
+    analysis::compute_maxs(&class_name, initialiser)
+}
+
+///
+/// Read the counter arrays of the initialised instrumented classes and update the registry with
+/// their sums per class name. Returns the number of arrays read.
+pub fn read(jvmti: &dyn JVMTI, jni: &dyn JNI, registry: &CounterRegistry) -> Result<usize, CounterError> {
+    let mut totals: HashMap<String, Vec<u64>> = HashMap::new();
+    let mut arrays = 0;
+
+    for class in jvmti.get_loaded_classes()?.iter() {
+        let slots = internal_name(&jvmti.get_class_signature(class)?)
+            .and_then(|name| registry.slots(&name).map(|slots| (name, slots)));
+
+        // Looking up a static field initialises the class, so only initialised ones are read
+        if let Some((name, slots)) = slots {
+            if jvmti.get_class_status(class)? & JVMTI_CLASS_STATUS_INITIALIZED != 0 {
+                if let Some(values) = read_class(jni, class, slots)? {
+                    let total = totals.entry(name).or_insert_with(|| vec![0; slots]);
+
+                    for (sum, value) in total.iter_mut().zip(values) {
+                        *sum += value.max(0) as u64;
+                    }
+
+                    arrays += 1;
+                }
+            }
+        }
+
+        jni.delete_local_ref(class)?;
+    }
+
+    for (name, counts) in totals.iter() {
+        registry.update(name, counts);
+    }
+
+    Ok(arrays)
+}
+
+/// The counter values of a class, `None` if it wasn't woven
+fn read_class(jni: &dyn JNI, class: &JavaClassPtr, slots: usize) -> Result<Option<Vec<i64>>, JNIError> {
+    let field = match jni.get_static_field_id(class, COUNTER_FIELD, COUNTER_DESCRIPTOR) {
+        Ok(field) => field,
+        // Loaded before the transformer was registered
+        Err(JNIError::FieldNotFound(_)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let array = jni.get_static_object_field(class, &field)?;
+
+    if array.is_null() {
+        return Ok(None);
+    }
+
+    let length = (jni.get_array_length(&array)? as usize).min(slots);
+    let values = jni.get_long_array_region(&array, 0, length as i32);
+
+    jni.delete_local_ref(&array)?;
+
+    values.map(Some)
+}
+
+struct VmPtr(JavaVMPtr);
+
+// The invocation interface may be used from any thread
+unsafe impl Send for VmPtr {}
+
+///
+/// Start a daemon thread that reads the counters into the registry at the given interval. Has to
+/// be called in the live phase, eg. from the `VMInit` handler. Does nothing if the reader is
+/// already running.
+pub fn start_reader(jni: JNIEnvPtr, registry: Arc<CounterRegistry>, interval: Duration) -> io::Result<()> {
+    let vm = match JNIEnvironment::new(jni).get_java_vm() {
+        Some(vm) => VmPtr(vm),
+        None => return Err(io::Error::other("the JNI environment has no VM")),
+    };
+
+    if READER_RUNNING.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let started = thread::Builder::new()
+        .name(READER_THREAD.to_string())
+        .spawn(move || {
+            run_reader(vm.0, &registry, interval);
+            READER_RUNNING.store(false, Ordering::SeqCst);
+        });
+
+    if started.is_err() {
+        READER_RUNNING.store(false, Ordering::SeqCst);
+    }
+
+    started.map(|_| ())
+}
+
+/// Stop the reader thread after its current wait. The counts of the last read remain in the
+/// registry.
+pub fn stop_reader() {
+    READER_RUNNING.store(false, Ordering::SeqCst);
+}
+
+pub fn is_reader_running() -> bool {
+    READER_RUNNING.load(Ordering::SeqCst)
+}
+
+fn run_reader(vm: JavaVMPtr, registry: &CounterRegistry, interval: Duration) {
+    let jvm = JVMAgent::new(vm);
+
+    let jni = match jvm.attach_current_thread_as_daemon(READER_THREAD) {
+        Ok(jni) => jni,
+        Err(err) => {
+            log::warn!("Couldn't attach the counter reader: {}", err);
+            return;
+        }
+    };
+
+    // Only an attached thread can get an environment
+    let jvmti = match jvm.get_environment() {
+        Ok(jvmti) => jvmti,
+        Err(err) => {
+            log::warn!("Couldn't start the counter reader: {}", err);
+            let _ = jvm.detach_current_thread();
+            return;
+        }
+    };
+
+    while READER_RUNNING.load(Ordering::SeqCst) {
+        thread::sleep(interval);
+
+        if let Err(err) = read(&*jvmti, &*jni, registry) {
+            log::warn!("Couldn't read the counters: {}", err);
+        }
+    }
+
+    let _ = jvm.detach_current_thread();
+}
@@ -0,0 +1,930 @@
+//!
+//! Line and branch coverage through probes woven into the selected methods.
+//!
+//! A probe is a call of a native helper method that counts how often it has been reached. Every
+//! basic block and every source line of a method gets a probe at its first instruction, so a line
+//! counts as executed as soon as execution reaches its first instruction. Both outcomes of a
+//! conditional jump and every target of a switch get a probe of their own: the fall through
+//! probe is placed right after the jump, while jumps are redirected through small trampolines at
+//! the end of the method that count the hit and continue at the original target. The probes
+//! are mapped to source lines using the `LineNumberTable` of the method.
+//!
+//! The counters are kept in the `CoverageRegistry` of the `AgentContext` and written as LCOV
+//! and Cobertura reports by `write_reports`, usually from the `VMDeath` handler:
+//!
+//! ```ignore
+//! let probes = CoverageProbes::from_config(&config, static_context().coverage.clone())?;
+//! static_context().add_transformer("coverage", 0, probes);
+//! agent.use_transformer_chain();
+//! agent.on_vm_init(Some(on_vm_init));
+//! agent.on_vm_death(Some(on_vm_death));
+//!
+//! fn on_vm_init(_jvmti: JVMTIEnvPtr, jni: JNIEnvPtr, _thread: jthread) {
+//!     coverage::install(jni).unwrap();
+//! }
+//!
+//! fn on_vm_death() {
+//!     if let Some(ref config) = static_context().config.read().unwrap().coverage {
+//!         coverage::write_reports(config, &static_context().coverage).unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! As with the timing probes, classes are woven only after the helper class is installed and
+//! classes with the same name share their counters.
+
+use super::super::bytecode::classfile::Instruction;
+use super::super::config::{Config, CoverageConfig};
+use super::super::context::static_context;
+use super::super::environment::jni::JNIEnvironment;
+use super::super::native::jvmti_native::jint;
+use super::super::native::{JNIEnvPtr, JavaClass as JavaClassPtr};
+use super::analysis;
+use super::bridge::{BridgeError, NativeBridge};
+use super::chain::{ClassTransformer, TransformError};
+use super::code::{Frame, FrameItem, Insn, Invoke, Jump, Label, MemberRef, TypeOp};
+use super::pointcut::{ClassInfo, MethodInfo, Pointcut};
+use super::timing::{push_int, ProbeError};
+use super::{JavaClass, ModelError};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Internal name of the generated helper class the probes call
+pub const HELPER_CLASS: &str = "jvmti/probe/Coverage";
+/// `static native void hit(int class, int probe)`
+pub const HIT_DESCRIPTOR: &str = "(II)V";
+
+static HELPER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+///
+/// The first probe of a source line
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineProbe {
+    pub line: u16,
+    pub probe: u32,
+}
+
+///
+/// One outcome of a conditional jump or a switch
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchProbe {
+    /// Source line of the jump or switch
+    pub line: u16,
+    /// Number of the jump or switch within the method
+    pub block: u32,
+    /// Number of the outcome, the fall through of a jump is 0 and the jump itself is 1
+    pub branch: u32,
+    pub probe: u32,
+}
+
+///
+/// The probes woven into a method
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodCoverage {
+    pub name: String,
+    pub descriptor: String,
+    /// The probe at the first instruction of the method, which counts its invocations
+    pub entry: u32,
+    pub lines: Vec<LineProbe>,
+    pub branches: Vec<BranchProbe>,
+}
+
+impl MethodCoverage {
+    pub fn first_line(&self) -> Option<u16> {
+        self.lines.iter().map(|line| line.line).min()
+    }
+}
+
+///
+/// The probes woven into a class. Probe numbers are local to the class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassCoverage {
+    /// Internal name of the class
+    pub class_name: String,
+    /// Name of the source file from the `SourceFile` attribute
+    pub source_file: Option<String>,
+    pub methods: Vec<MethodCoverage>,
+    /// Number of probes in the class
+    pub probes: u32,
+}
+
+impl ClassCoverage {
+    pub fn new(class_name: &str) -> ClassCoverage {
+        ClassCoverage {
+            class_name: class_name.to_string(),
+            source_file: None,
+            methods: vec![],
+            probes: 0,
+        }
+    }
+
+    /// Path of the source file relative to the source root, eg. `com/acme/Service.java`
+    pub fn source_path(&self) -> String {
+        let outer = self.class_name.split('$').next().unwrap_or(&self.class_name);
+
+        match self.source_file {
+            Some(ref file) => match outer.rfind('/') {
+                Some(idx) => format!("{}/{}", &outer[..idx], file),
+                None => file.clone(),
+            },
+            None => format!("{}.java", outer),
+        }
+    }
+}
+
+///
+/// Assigns the numeric ids the woven code passes to the helper and counts the hits of every probe
+pub struct CoverageRegistry {
+    classes: RwLock<Vec<(ClassCoverage, Vec<AtomicU64>)>>,
+}
+
+impl CoverageRegistry {
+    pub fn new() -> CoverageRegistry {
+        CoverageRegistry {
+            classes: RwLock::new(vec![]),
+        }
+    }
+
+    /// Returns the id of the class with the given internal name, registering it first if it's new
+    pub fn register(&self, class_name: &str) -> u32 {
+        let mut classes = self.classes.write().unwrap();
+
+        match classes.iter().position(|(class, _)| class.class_name == class_name) {
+            Some(id) => id as u32,
+            None => {
+                classes.push((ClassCoverage::new(class_name), vec![]));
+                (classes.len() - 1) as u32
+            }
+        }
+    }
+
+    ///
+    /// Set the probes of a registered class. The hits counted so far are kept if the probes are
+    /// the same as before, eg. because the class has been retransformed, and reset otherwise.
+    pub fn update(&self, id: u32, coverage: ClassCoverage) {
+        if let Ok(mut classes) = self.classes.write() {
+            if let Some(entry) = classes.get_mut(id as usize) {
+                if entry.0 != coverage {
+                    entry.1 = (0..coverage.probes).map(|_| AtomicU64::new(0)).collect();
+                    entry.0 = coverage;
+                }
+            }
+        }
+    }
+
+    /// Count a hit of a probe
+    pub fn hit(&self, id: u32, probe: u32) {
+        if let Ok(classes) = self.classes.read() {
+            if let Some(counter) = classes.get(id as usize).and_then(|(_, hits)| hits.get(probe as usize)) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The probes of every class with the current number of hits of each probe, ordered by id
+    pub fn snapshot(&self) -> Vec<(ClassCoverage, Vec<u64>)> {
+        match self.classes.read() {
+            Ok(classes) => classes
+                .iter()
+                .map(|(class, hits)| {
+                    (class.clone(), hits.iter().map(|hit| hit.load(Ordering::Relaxed)).collect())
+                })
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.read().map(|classes| classes.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for CoverageRegistry {
+    fn default() -> Self {
+        CoverageRegistry::new()
+    }
+}
+
+///
+/// A `ClassTransformer` that weaves coverage probes into the methods selected by any of its
+/// pointcuts. Abstract and native methods are skipped.
+pub struct CoverageProbes {
+    pointcuts: Vec<Pointcut>,
+    registry: Arc<CoverageRegistry>,
+}
+
+impl CoverageProbes {
+    pub fn new(pointcuts: Vec<Pointcut>, registry: Arc<CoverageRegistry>) -> CoverageProbes {
+        CoverageProbes {
+            pointcuts,
+            registry,
+        }
+    }
+
+    /// Measure the methods selected by the pointcuts listed in the `[coverage]` section
+    pub fn from_config(config: &Config, registry: Arc<CoverageRegistry>) -> Result<CoverageProbes, ProbeError> {
+        let names = match config.coverage {
+            Some(ref coverage) => coverage.pointcuts.clone(),
+            None => vec![],
+        };
+
+        let pointcuts = names
+            .iter()
+            .map(|name| {
+                config
+                    .pointcut(name)
+                    .cloned()
+                    .ok_or_else(|| ProbeError::UnknownPointcut(name.clone()))
+            })
+            .collect::<Result<Vec<Pointcut>, ProbeError>>()?;
+
+        Ok(CoverageProbes::new(pointcuts, registry))
+    }
+}
+
+impl ClassTransformer for CoverageProbes {
+    fn accepts(&self, class_name: &str) -> bool {
+        let class = ClassInfo::new(class_name);
+
+        HELPER_INSTALLED.load(Ordering::SeqCst)
+            && class_name != HELPER_CLASS
+            && self.pointcuts.iter().any(|p| p.matches_class(&class))
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        let class_info = ClassInfo::from_class(class);
+
+        let selected: Vec<usize> = class
+            .methods
+            .iter()
+            .enumerate()
+            .filter(|&(_, method)| {
+                method.code.is_some()
+                    && self
+                        .pointcuts
+                        .iter()
+                        .any(|p| p.matches_method(&class_info, &MethodInfo::from_method(class, method)))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        if selected.is_empty() {
+            return Ok(false);
+        }
+
+        let id = self.registry.register(&class.name);
+        let coverage = weave(class, &selected, id)?;
+
+        self.registry.update(id, coverage);
+
+        Ok(true)
+    }
+}
+
+///
+/// Weave coverage probes with the given class id into the methods of the class. Returns the
+/// probes, which have to be registered under the same id.
+pub fn weave(class: &mut JavaClass, method_indices: &[usize], class_id: u32) -> Result<ClassCoverage, ModelError> {
+    let mut coverage = ClassCoverage::new(&class.name);
+    coverage.source_file = class.source_file.clone();
+
+    for &index in method_indices {
+        let method = weave_method(class, index, class_id, &mut coverage.probes)?;
+        coverage.methods.push(method);
+    }
+
+    Ok(coverage)
+}
+
+///
+/// Weave probes into a single method, numbering them from `next_probe` on. Only the trampolines
+/// need stack map frames, which are copied from the original jump targets, so the existing frames
+/// remain valid.
+fn weave_method(
+    class: &mut JavaClass,
+    method_index: usize,
+    class_id: u32,
+    next_probe: &mut u32,
+) -> Result<MethodCoverage, ModelError> {
+    let class_name = class.name.clone();
+    let has_frames = class.version.major_version >= 50;
+    let method = &mut class.methods[method_index];
+
+    let mut coverage = MethodCoverage {
+        name: method.name.clone(),
+        descriptor: method.descriptor.descriptor(),
+        entry: *next_probe,
+        lines: vec![],
+        branches: vec![],
+    };
+
+    {
+        let code = match method.code.as_mut() {
+            Some(code) => code,
+            None => return Ok(coverage),
+        };
+
+        let mut targets: HashSet<Label> = code.try_catch_blocks.iter().map(|block| block.handler).collect();
+
+        for insn in code.instructions.iter() {
+            targets.extend(jump_targets(insn));
+        }
+
+        let original = mem::take(&mut code.instructions);
+        let mut instructions = vec![];
+        let mut trampolines = vec![];
+        let mut line = None;
+        let mut block = 0;
+        let mut pending = true;
+        // Labels placed since the last instruction, and the fresh labels given to allocations
+        let mut labels = vec![];
+        let mut allocations = HashMap::new();
+
+        let mut new_probe = || {
+            *next_probe += 1;
+            *next_probe - 1
+        };
+
+        for insn in original.iter() {
+            match *insn {
+                Insn::Label(ref label) => {
+                    pending |= targets.contains(label);
+                    labels.push(*label);
+                    instructions.push(insn.clone());
+                    continue;
+                }
+                Insn::LineNumber(number) => {
+                    pending |= line != Some(number);
+                    line = Some(number);
+                    instructions.push(insn.clone());
+                    continue;
+                }
+                Insn::Frame(_) => {
+                    instructions.push(insn.clone());
+                    continue;
+                }
+                _ => (),
+            }
+
+            if pending {
+                let probe = new_probe();
+
+                if let Some(number) = line {
+                    coverage.lines.push(LineProbe {
+                        line: number,
+                        probe,
+                    });
+                }
+
+                instructions.extend(hit(class_id, probe));
+                pending = false;
+
+                // Uninitialised values are identified by the label of their `new`, which now
+                // marks the probe instead
+                if let Insn::Type(TypeOp::New, _) = insn {
+                    if !labels.is_empty() {
+                        let allocation = code.new_label();
+
+                        instructions.push(Insn::Label(allocation));
+                        allocations.extend(labels.iter().map(|&label| (label, allocation)));
+                    }
+                }
+            }
+
+            labels.clear();
+
+            // Outcomes of the jump or switch, in the order of their branch numbers
+            let mut outcomes = vec![];
+
+            match *insn {
+                Insn::Jump(Jump::Goto, _) | Insn::Jump(Jump::Jsr, _) => {
+                    instructions.push(insn.clone());
+                    pending = true;
+                }
+                Insn::Jump(kind, target) => {
+                    let fall_through = new_probe();
+                    let trampoline = code.new_label();
+                    let taken = new_probe();
+
+                    instructions.push(Insn::Jump(kind, trampoline));
+                    instructions.extend(hit(class_id, fall_through));
+                    trampolines.extend(trampoline_code(&original, trampoline, target, has_frames, class_id, taken)?);
+
+                    outcomes.push(fall_through);
+                    outcomes.push(taken);
+                    pending = true;
+                }
+                Insn::TableSwitch {
+                    default,
+                    low,
+                    high,
+                    ref targets,
+                } => {
+                    let mut redirect = HashMap::new();
+
+                    for &target in Some(&default).into_iter().chain(targets.iter()) {
+                        if let Entry::Vacant(entry) = redirect.entry(target) {
+                            let trampoline = code.new_label();
+                            let probe = new_probe();
+
+                            trampolines.extend(trampoline_code(&original, trampoline, target, has_frames, class_id, probe)?);
+                            entry.insert(trampoline);
+                            outcomes.push(probe);
+                        }
+                    }
+
+                    instructions.push(Insn::TableSwitch {
+                        default: redirect[&default],
+                        low,
+                        high,
+                        targets: targets.iter().map(|target| redirect[target]).collect(),
+                    });
+                    pending = true;
+                }
+                Insn::LookupSwitch { default, ref pairs } => {
+                    let mut redirect = HashMap::new();
+
+                    for &target in Some(&default).into_iter().chain(pairs.iter().map(|(_, target)| target)) {
+                        if let Entry::Vacant(entry) = redirect.entry(target) {
+                            let trampoline = code.new_label();
+                            let probe = new_probe();
+
+                            trampolines.extend(trampoline_code(&original, trampoline, target, has_frames, class_id, probe)?);
+                            entry.insert(trampoline);
+                            outcomes.push(probe);
+                        }
+                    }
+
+                    instructions.push(Insn::LookupSwitch {
+                        default: redirect[&default],
+                        pairs: pairs.iter().map(|&(key, ref target)| (key, redirect[target])).collect(),
+                    });
+                    pending = true;
+                }
+                Insn::Op(ref instruction) if ends_flow(instruction) => {
+                    instructions.push(insn.clone());
+                    pending = true;
+                }
+                _ => instructions.push(insn.clone()),
+            }
+
+            if !outcomes.is_empty() {
+                if let Some(number) = line {
+                    for (branch, &probe) in outcomes.iter().enumerate() {
+                        coverage.branches.push(BranchProbe {
+                            line: number,
+                            block,
+                            branch: branch as u32,
+                            probe,
+                        });
+                    }
+                }
+
+                block += 1;
+            }
+        }
+
+        instructions.extend(trampolines);
+
+        if !allocations.is_empty() {
+            for insn in instructions.iter_mut() {
+                if let Insn::Frame(frame) = insn {
+                    for item in frame.locals.iter_mut().chain(frame.stack.iter_mut()) {
+                        if let FrameItem::Uninitialized(label) = item {
+                            if let Some(&allocation) = allocations.get(label) {
+                                *label = allocation;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        code.instructions = instructions;
+    }
+
+    analysis::compute_maxs(&class_name, method)?;
+
+    Ok(coverage)
+}
+
+/// `Coverage.hit(class, probe)`
+fn hit(class_id: u32, probe: u32) -> Vec<Insn> {
+    vec![
+        push_int(class_id as i32),
+        push_int(probe as i32),
+        Insn::Invoke(Invoke::Static, MemberRef::new(HELPER_CLASS, "hit", HIT_DESCRIPTOR)),
+    ]
+}
+
+/// Counts a hit of the probe and continues at the target, with the frame of the target
+fn trampoline_code(
+    instructions: &[Insn],
+    trampoline: Label,
+    target: Label,
+    has_frames: bool,
+    class_id: u32,
+    probe: u32,
+) -> Result<Vec<Insn>, ModelError> {
+    let mut code = vec![Insn::Label(trampoline)];
+
+    if has_frames {
+        code.push(Insn::Frame(frame_at(instructions, target)?));
+    }
+
+    code.extend(hit(class_id, probe));
+    code.push(Insn::Jump(Jump::Goto, target));
+
+    Ok(code)
+}
+
+/// The stack map frame placed at a label
+fn frame_at(instructions: &[Insn], label: Label) -> Result<Frame, ModelError> {
+    let position = instructions
+        .iter()
+        .position(|insn| insn == &Insn::Label(label))
+        .ok_or(ModelError::UnplacedLabel(label))?;
+
+    for insn in instructions[position..].iter() {
+        match *insn {
+            Insn::Frame(ref frame) => return Ok(frame.clone()),
+            ref insn if insn.is_instruction() => break,
+            _ => (),
+        }
+    }
+
+    Err(ModelError::InvalidFrame(position))
+}
+
+fn jump_targets(insn: &Insn) -> Vec<Label> {
+    match *insn {
+        Insn::Jump(_, target) => vec![target],
+        Insn::TableSwitch {
+            default, ref targets, ..
+        } => Some(default).into_iter().chain(targets.iter().cloned()).collect(),
+        Insn::LookupSwitch { default, ref pairs } => Some(default)
+            .into_iter()
+            .chain(pairs.iter().map(|&(_, target)| target))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn ends_flow(instruction: &Instruction) -> bool {
+    matches!(
+        *instruction,
+        Instruction::IRETURN
+            | Instruction::LRETURN
+            | Instruction::FRETURN
+            | Instruction::DRETURN
+            | Instruction::ARETURN
+            | Instruction::RETURN
+            | Instruction::ATHROW
+            | Instruction::RET(_)
+            | Instruction::RET_W(_)
+    )
+}
+
+///
+/// The bridge that defines the helper class and binds its `hit` method
+pub fn bridge() -> NativeBridge {
+    NativeBridge::new(HELPER_CLASS).method("hit", HIT_DESCRIPTOR, coverage_hit as *mut c_void)
+}
+
+///
+/// Define the helper class in the bootstrap class loader, bind its native method and start
+/// weaving the classes loaded from now on. Has to be called in the live phase, eg. from the
+/// `VMInit` handler.
+pub fn install(jni: JNIEnvPtr) -> Result<(), BridgeError> {
+    if HELPER_INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    bridge().define(&JNIEnvironment::new(jni))?;
+    HELPER_INSTALLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Returns true once the helper class is defined and classes are being woven
+pub fn is_installed() -> bool {
+    HELPER_INSTALLED.load(Ordering::SeqCst)
+}
+
+extern "C" fn coverage_hit(_jni: JNIEnvPtr, _class: JavaClassPtr, class_id: jint, probe: jint) {
+    static_context().coverage.hit(class_id as u32, probe as u32);
+}
+
+///
+/// Write the reports configured in the `[coverage]` section
+pub fn write_reports(config: &CoverageConfig, registry: &CoverageRegistry) -> io::Result<()> {
+    let classes = registry.snapshot();
+
+    if let Some(ref path) = config.lcov {
+        fs::write(path, lcov(&classes))?;
+    }
+
+    if let Some(ref path) = config.cobertura {
+        fs::write(path, cobertura(&classes, time::get_time().sec * 1000))?;
+    }
+
+    Ok(())
+}
+
+/// Line hits, the highest hit count of the probes on each line
+fn line_hits(method: &MethodCoverage, hits: &[u64], lines: &mut BTreeMap<u16, u64>) {
+    for probe in method.lines.iter() {
+        let count = hits.get(probe.probe as usize).cloned().unwrap_or(0);
+        let entry = lines.entry(probe.line).or_insert(0);
+
+        *entry = (*entry).max(count);
+    }
+}
+
+fn probe_hits(hits: &[u64], probe: u32) -> u64 {
+    hits.get(probe as usize).cloned().unwrap_or(0)
+}
+
+/// Function name used in reports, eg. `com.acme.Service.pick(I)I`
+fn function_name(class: &ClassCoverage, method: &MethodCoverage) -> String {
+    format!("{}.{}{}", class.class_name.replace('/', "."), method.name, method.descriptor)
+}
+
+///
+/// Render an LCOV tracefile with one record per source file
+pub fn lcov(classes: &[(ClassCoverage, Vec<u64>)]) -> String {
+    let mut files: BTreeMap<String, Vec<&(ClassCoverage, Vec<u64>)>> = BTreeMap::new();
+
+    for entry in classes.iter() {
+        files.entry(entry.0.source_path()).or_default().push(entry);
+    }
+
+    let mut out = String::new();
+
+    for (path, entries) in files.iter() {
+        let mut lines = BTreeMap::new();
+        let mut functions = vec![];
+        let mut branches = vec![];
+        let mut block_offset = 0;
+
+        for &(class, hits) in entries.iter() {
+            for method in class.methods.iter() {
+                line_hits(method, hits, &mut lines);
+
+                if let Some(line) = method.first_line() {
+                    functions.push((line, function_name(class, method), probe_hits(hits, method.entry)));
+                }
+
+                for branch in method.branches.iter() {
+                    branches.push((branch.line, block_offset + branch.block, branch.branch, probe_hits(hits, branch.probe)));
+                }
+
+                block_offset += method.branches.iter().map(|branch| branch.block + 1).max().unwrap_or(0);
+            }
+        }
+
+        let _ = writeln!(out, "TN:");
+        let _ = writeln!(out, "SF:{}", path);
+
+        for &(line, ref name, _) in functions.iter() {
+            let _ = writeln!(out, "FN:{},{}", line, name);
+        }
+
+        for &(_, ref name, count) in functions.iter() {
+            let _ = writeln!(out, "FNDA:{},{}", count, name);
+        }
+
+        let _ = writeln!(out, "FNF:{}", functions.len());
+        let _ = writeln!(out, "FNH:{}", functions.iter().filter(|&&(_, _, count)| count > 0).count());
+
+        for &(line, block, branch, count) in branches.iter() {
+            // Branches of lines that were never reached are reported as `-`
+            if lines.get(&line).cloned().unwrap_or(0) == 0 {
+                let _ = writeln!(out, "BRDA:{},{},{},-", line, block, branch);
+            } else {
+                let _ = writeln!(out, "BRDA:{},{},{},{}", line, block, branch, count);
+            }
+        }
+
+        let _ = writeln!(out, "BRF:{}", branches.len());
+        let _ = writeln!(out, "BRH:{}", branches.iter().filter(|&&(_, _, _, count)| count > 0).count());
+
+        for (line, count) in lines.iter() {
+            let _ = writeln!(out, "DA:{},{}", line, count);
+        }
+
+        let _ = writeln!(out, "LF:{}", lines.len());
+        let _ = writeln!(out, "LH:{}", lines.values().filter(|&&count| count > 0).count());
+        let _ = writeln!(out, "end_of_record");
+    }
+
+    out
+}
+
+/// Covered and valid lines and branches
+#[derive(Default, Clone, Copy)]
+struct Counts {
+    lines_covered: usize,
+    lines_valid: usize,
+    branches_covered: usize,
+    branches_valid: usize,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.lines_covered += other.lines_covered;
+        self.lines_valid += other.lines_valid;
+        self.branches_covered += other.branches_covered;
+        self.branches_valid += other.branches_valid;
+    }
+
+    fn rates(&self) -> String {
+        format!(
+            "line-rate=\"{}\" branch-rate=\"{}\"",
+            rate(self.lines_covered, self.lines_valid),
+            rate(self.branches_covered, self.branches_valid)
+        )
+    }
+}
+
+fn rate(covered: usize, valid: usize) -> String {
+    if valid == 0 {
+        "1".to_string()
+    } else {
+        format!("{:.4}", covered as f64 / valid as f64)
+    }
+}
+
+/// Render the `<line>` elements of the given methods and count their lines and branches
+fn cobertura_lines(methods: &[&MethodCoverage], hits: &[u64], indent: &str, out: &mut String) -> Counts {
+    let mut lines = BTreeMap::new();
+    let mut branches: BTreeMap<u16, (usize, usize)> = BTreeMap::new();
+
+    for method in methods.iter() {
+        line_hits(method, hits, &mut lines);
+
+        for branch in method.branches.iter() {
+            let entry = branches.entry(branch.line).or_insert((0, 0));
+
+            entry.1 += 1;
+            if probe_hits(hits, branch.probe) > 0 {
+                entry.0 += 1;
+            }
+        }
+    }
+
+    let mut counts = Counts::default();
+
+    for (line, count) in lines.iter() {
+        counts.lines_valid += 1;
+        if *count > 0 {
+            counts.lines_covered += 1;
+        }
+
+        match branches.get(line) {
+            Some(&(covered, valid)) => {
+                counts.branches_covered += covered;
+                counts.branches_valid += valid;
+
+                let _ = writeln!(
+                    out,
+                    "{}<line number=\"{}\" hits=\"{}\" branch=\"true\" condition-coverage=\"{}% ({}/{})\"/>",
+                    indent,
+                    line,
+                    count,
+                    covered * 100 / valid,
+                    covered,
+                    valid
+                );
+            }
+            None => {
+                let _ = writeln!(out, "{}<line number=\"{}\" hits=\"{}\" branch=\"false\"/>", indent, line, count);
+            }
+        }
+    }
+
+    counts
+}
+
+///
+/// Render a Cobertura XML report. The timestamp is in milliseconds since the epoch.
+pub fn cobertura(classes: &[(ClassCoverage, Vec<u64>)], timestamp: i64) -> String {
+    let mut packages: BTreeMap<String, Vec<&(ClassCoverage, Vec<u64>)>> = BTreeMap::new();
+
+    for entry in classes.iter() {
+        let package = match entry.0.class_name.rfind('/') {
+            Some(idx) => entry.0.class_name[..idx].replace('/', "."),
+            None => String::new(),
+        };
+
+        packages.entry(package).or_default().push(entry);
+    }
+
+    let mut body = String::new();
+    let mut total = Counts::default();
+
+    for (package, entries) in packages.iter() {
+        let mut package_body = String::new();
+        let mut package_counts = Counts::default();
+
+        for &(class, hits) in entries.iter() {
+            let mut methods_body = String::new();
+
+            for method in class.methods.iter() {
+                let mut lines = String::new();
+                let counts = cobertura_lines(&[method], hits, "              ", &mut lines);
+
+                let _ = writeln!(
+                    methods_body,
+                    "          <method name=\"{}\" signature=\"{}\" {} complexity=\"0\">",
+                    escape(&method.name),
+                    escape(&method.descriptor),
+                    counts.rates()
+                );
+                let _ = writeln!(methods_body, "            <lines>");
+                methods_body.push_str(&lines);
+                let _ = writeln!(methods_body, "            </lines>");
+                let _ = writeln!(methods_body, "          </method>");
+            }
+
+            let mut lines = String::new();
+            let methods: Vec<&MethodCoverage> = class.methods.iter().collect();
+            let counts = cobertura_lines(&methods, hits, "          ", &mut lines);
+
+            package_counts.add(counts);
+
+            let _ = writeln!(
+                package_body,
+                "      <class name=\"{}\" filename=\"{}\" {} complexity=\"0\">",
+                escape(&class.class_name.replace('/', ".")),
+                escape(&class.source_path()),
+                counts.rates()
+            );
+            let _ = writeln!(package_body, "        <methods>");
+            package_body.push_str(&methods_body);
+            let _ = writeln!(package_body, "        </methods>");
+            let _ = writeln!(package_body, "        <lines>");
+            package_body.push_str(&lines);
+            let _ = writeln!(package_body, "        </lines>");
+            let _ = writeln!(package_body, "      </class>");
+        }
+
+        total.add(package_counts);
+
+        let _ = writeln!(
+            body,
+            "  <package name=\"{}\" {} complexity=\"0\">",
+            escape(package),
+            package_counts.rates()
+        );
+        let _ = writeln!(body, "    <classes>");
+        body.push_str(&package_body);
+        let _ = writeln!(body, "    </classes>");
+        let _ = writeln!(body, "  </package>");
+    }
+
+    let mut out = String::new();
+
+    let _ = writeln!(out, "<?xml version=\"1.0\" ?>");
+    let _ = writeln!(
+        out,
+        "<!DOCTYPE coverage SYSTEM \"http://cobertura.sourceforge.net/xml/coverage-04.dtd\">"
+    );
+    let _ = writeln!(
+        out,
+        "<coverage {} lines-covered=\"{}\" lines-valid=\"{}\" branches-covered=\"{}\" branches-valid=\"{}\" complexity=\"0\" version=\"0\" timestamp=\"{}\">",
+        total.rates(),
+        total.lines_covered,
+        total.lines_valid,
+        total.branches_covered,
+        total.branches_valid,
+        timestamp
+    );
+    let _ = writeln!(out, "<sources>");
+    let _ = writeln!(out, "  <source>.</source>");
+    let _ = writeln!(out, "</sources>");
+    let _ = writeln!(out, "<packages>");
+    out.push_str(&body);
+    let _ = writeln!(out, "</packages>");
+    let _ = writeln!(out, "</coverage>");
+
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
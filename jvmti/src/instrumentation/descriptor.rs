@@ -0,0 +1,188 @@
+use std::fmt;
+
+///
+/// A field or parameter type as it appears in a JVM descriptor. Unlike `class::JavaType` it owns
+/// its class names so it can be stored in the editable class model.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum JavaType {
+    Boolean,
+    Byte,
+    Char,
+    Double,
+    Float,
+    Int,
+    Long,
+    Short,
+    Void,
+    /// Reference type, identified by its internal name (eg. `java/lang/String`)
+    Class(String),
+    Array(Box<JavaType>),
+}
+
+impl JavaType {
+    /// Parse a single field descriptor, eg. `I`, `Ljava/lang/String;` or `[[J`
+    pub fn parse(descriptor: &str) -> Option<JavaType> {
+        match JavaType::parse_prefix(descriptor) {
+            Some((java_type, "")) => Some(java_type),
+            _ => None,
+        }
+    }
+
+    /// Parse a type from the beginning of the given descriptor and return the unparsed remainder
+    fn parse_prefix(descriptor: &str) -> Option<(JavaType, &str)> {
+        let mut chars = descriptor.chars();
+
+        match chars.next() {
+            Some('Z') => Some((JavaType::Boolean, &descriptor[1..])),
+            Some('B') => Some((JavaType::Byte, &descriptor[1..])),
+            Some('C') => Some((JavaType::Char, &descriptor[1..])),
+            Some('D') => Some((JavaType::Double, &descriptor[1..])),
+            Some('F') => Some((JavaType::Float, &descriptor[1..])),
+            Some('I') => Some((JavaType::Int, &descriptor[1..])),
+            Some('J') => Some((JavaType::Long, &descriptor[1..])),
+            Some('S') => Some((JavaType::Short, &descriptor[1..])),
+            Some('V') => Some((JavaType::Void, &descriptor[1..])),
+            Some('L') => match descriptor.find(';') {
+                Some(end) if end > 1 => Some((
+                    JavaType::Class(descriptor[1..end].to_string()),
+                    &descriptor[end + 1..],
+                )),
+                _ => None,
+            },
+            Some('[') => match JavaType::parse_prefix(&descriptor[1..]) {
+                Some((JavaType::Void, _)) => None,
+                Some((component, rest)) => Some((JavaType::Array(Box::new(component)), rest)),
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Construct a type from a `CONSTANT_Class` name, which is either an internal class name or
+    /// an array descriptor
+    pub fn from_internal_name(name: &str) -> JavaType {
+        if name.starts_with('[') {
+            JavaType::parse(name).unwrap_or(JavaType::Class(name.to_string()))
+        } else {
+            JavaType::Class(name.to_string())
+        }
+    }
+
+    /// Returns the name that identifies this type in a `CONSTANT_Class` entry
+    pub fn internal_name(&self) -> String {
+        match *self {
+            JavaType::Class(ref name) => name.clone(),
+            ref other => other.descriptor(),
+        }
+    }
+
+    pub fn descriptor(&self) -> String {
+        match *self {
+            JavaType::Boolean => "Z".to_string(),
+            JavaType::Byte => "B".to_string(),
+            JavaType::Char => "C".to_string(),
+            JavaType::Double => "D".to_string(),
+            JavaType::Float => "F".to_string(),
+            JavaType::Int => "I".to_string(),
+            JavaType::Long => "J".to_string(),
+            JavaType::Short => "S".to_string(),
+            JavaType::Void => "V".to_string(),
+            JavaType::Class(ref name) => format!("L{};", name),
+            JavaType::Array(ref component) => format!("[{}", component.descriptor()),
+        }
+    }
+
+    /// Number of local variable slots (and operand stack entries) a value of this type occupies
+    pub fn size(&self) -> u16 {
+        match *self {
+            JavaType::Void => 0,
+            JavaType::Long | JavaType::Double => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn is_reference(&self) -> bool {
+        matches!(*self, JavaType::Class(_) | JavaType::Array(_))
+    }
+}
+
+impl fmt::Display for JavaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JavaType::Boolean => write!(f, "boolean"),
+            JavaType::Byte => write!(f, "byte"),
+            JavaType::Char => write!(f, "char"),
+            JavaType::Double => write!(f, "double"),
+            JavaType::Float => write!(f, "float"),
+            JavaType::Int => write!(f, "int"),
+            JavaType::Long => write!(f, "long"),
+            JavaType::Short => write!(f, "short"),
+            JavaType::Void => write!(f, "void"),
+            JavaType::Class(ref name) => write!(f, "{}", name.replace("/", ".")),
+            JavaType::Array(ref component) => write!(f, "{}[]", component),
+        }
+    }
+}
+
+///
+/// A parsed method descriptor, eg. `(ILjava/lang/String;)V`
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MethodDescriptor {
+    pub parameters: Vec<JavaType>,
+    pub return_type: JavaType,
+}
+
+impl MethodDescriptor {
+    pub fn new(parameters: Vec<JavaType>, return_type: JavaType) -> MethodDescriptor {
+        MethodDescriptor {
+            parameters,
+            return_type,
+        }
+    }
+
+    pub fn parse(descriptor: &str) -> Option<MethodDescriptor> {
+        if !descriptor.starts_with('(') {
+            return None;
+        }
+
+        let mut rest = &descriptor[1..];
+        let mut parameters = vec![];
+
+        while !rest.starts_with(')') {
+            match JavaType::parse_prefix(rest) {
+                Some((JavaType::Void, _)) | None => return None,
+                Some((param, remainder)) => {
+                    parameters.push(param);
+                    rest = remainder;
+                }
+            }
+        }
+
+        JavaType::parse(&rest[1..]).map(|return_type| MethodDescriptor::new(parameters, return_type))
+    }
+
+    pub fn descriptor(&self) -> String {
+        format!(
+            "({}){}",
+            self.parameters
+                .iter()
+                .map(|p| p.descriptor())
+                .collect::<Vec<String>>()
+                .join(""),
+            self.return_type.descriptor()
+        )
+    }
+
+    /// Number of local variable slots taken up by the parameters, not counting `this`
+    pub fn parameter_size(&self) -> u16 {
+        self.parameters.iter().fold(0, |acc, p| acc + p.size())
+    }
+}
+
+impl fmt::Display for MethodDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.descriptor())
+    }
+}
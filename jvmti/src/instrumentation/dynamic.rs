@@ -0,0 +1,324 @@
+//!
+//! Probes that can be added and removed while the VM is running. A `ProbeManager` is registered
+//! in the transformer chain like any other transformer, and weaves the probes that are enabled
+//! at the time a class is loaded or retransformed.
+//!
+//! Enabling or disabling a probe retransforms only the loaded classes it applies to. Probes are
+//! woven into the class as it reaches the manager, so the changes of the transformers that run
+//! before it are kept. The manager caches every class it has woven as it was before and after
+//! weaving: if a class still contains the probes when it's transformed again, the methods they
+//! were woven into get their cached original code back before the current probes are woven. Once
+//! the last probe of a class is removed, the cache entry is dropped.
+//!
+//! ```ignore
+//! let manager = static_context().dynamic_probes.clone();
+//! static_context().add_transformer("probes", -100, manager.clone());
+//!
+//! // Later, eg. from a command handler
+//! let weaver = TimingWeaver::new(static_context().probes.clone());
+//! manager.enable("checkout", Pointcut::parse("execution(com.acme.Cart.checkout)")?, weaver, jvmti)?;
+//! manager.disable("checkout", jvmti)?;
+//! ```
+//!
+//! Classes are identified by their name, so classes with the same name defined by different class
+//! loaders share their probes.
+
+use super::super::class::{ClassId, ClassSignature};
+use super::super::environment::jvmti::JVMTI;
+use super::super::error::RedefineError;
+use super::chain::{self, ClassTransformer, TransformError};
+use super::pointcut::{ClassInfo, MethodInfo, Pointcut};
+use super::JavaClass;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+///
+/// Weaves a probe into a single method of a class. Closures taking the class and the index of the
+/// method implement this trait too.
+pub trait MethodWeaver: Send + Sync {
+    fn weave(&self, class: &mut JavaClass, method_index: usize) -> Result<(), TransformError>;
+}
+
+impl<F> MethodWeaver for F
+where
+    F: Fn(&mut JavaClass, usize) -> Result<(), TransformError> + Send + Sync,
+{
+    fn weave(&self, class: &mut JavaClass, method_index: usize) -> Result<(), TransformError> {
+        self(class, method_index)
+    }
+}
+
+struct Probe {
+    pointcut: Pointcut,
+    weaver: Box<dyn MethodWeaver>,
+}
+
+struct WovenClass {
+    /// The class file as it was before the probes were woven into it
+    original: Vec<u8>,
+    /// The class file with the probes woven into it
+    woven: Vec<u8>,
+    /// Names of the probes woven into the class
+    probes: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct State {
+    probes: BTreeMap<String, Probe>,
+    woven: HashMap<String, WovenClass>,
+}
+
+///
+/// Keeps track of the enabled probes and the classes they are woven into
+pub struct ProbeManager {
+    state: RwLock<State>,
+}
+
+impl ProbeManager {
+    pub fn new() -> ProbeManager {
+        ProbeManager {
+            state: RwLock::new(State::default()),
+        }
+    }
+
+    ///
+    /// Register a probe without retransforming any class, so it only applies to classes loaded
+    /// from now on. A probe registered with a name that is already in use replaces the existing
+    /// one.
+    pub fn add<W: MethodWeaver + 'static>(&self, name: &str, pointcut: Pointcut, weaver: W) {
+        self.state_mut().probes.insert(
+            name.to_string(),
+            Probe {
+                pointcut,
+                weaver: Box::new(weaver),
+            },
+        );
+    }
+
+    ///
+    /// Unregister a probe without retransforming any class. Returns the internal names of the
+    /// classes it is still woven into, or `None` if there's no probe with the given name.
+    pub fn remove(&self, name: &str) -> Option<Vec<String>> {
+        let mut state = self.state_mut();
+
+        state.probes.remove(name).map(|_| woven_classes(&state, name))
+    }
+
+    /// Names of the registered probes, in the order they are woven
+    pub fn probes(&self) -> Vec<String> {
+        self.state().probes.keys().cloned().collect()
+    }
+
+    /// Internal names of the classes the probe is currently woven into, in no particular order
+    pub fn woven_classes(&self, name: &str) -> Vec<String> {
+        woven_classes(&self.state(), name)
+    }
+
+    /// The class file of a woven class as it was before the probes were woven into it
+    pub fn original(&self, class_name: &str) -> Option<Vec<u8>> {
+        self.state().woven.get(class_name).map(|class| class.original.clone())
+    }
+
+    ///
+    /// Register a probe and retransform the loaded classes it applies to, as well as those an
+    /// existing probe with the same name is woven into. Returns the internal names of the
+    /// retransformed classes, or `RedefineError::Unmodifiable` if one of them can't be
+    /// retransformed.
+    pub fn enable<W: MethodWeaver + 'static>(
+        &self,
+        name: &str,
+        pointcut: Pointcut,
+        weaver: W,
+        jvmti: &dyn JVMTI,
+    ) -> Result<Vec<String>, RedefineError> {
+        let replaced: BTreeSet<String> = self.woven_classes(name).into_iter().collect();
+        let selected = loaded_classes(jvmti)?
+            .into_iter()
+            .filter(|(_, class_name)| {
+                replaced.contains(class_name) || pointcut.matches_class(&ClassInfo::new(class_name))
+            })
+            .collect();
+
+        self.add(name, pointcut, weaver);
+
+        retransform(jvmti, selected)
+    }
+
+    ///
+    /// Unregister a probe and retransform the classes it is woven into. Returns the internal
+    /// names of the retransformed classes, which is empty if there's no such probe.
+    pub fn disable(&self, name: &str, jvmti: &dyn JVMTI) -> Result<Vec<String>, RedefineError> {
+        let affected: BTreeSet<String> = match self.remove(name) {
+            Some(classes) => classes.into_iter().collect(),
+            None => return Ok(vec![]),
+        };
+
+        if affected.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let selected = loaded_classes(jvmti)?
+            .into_iter()
+            .filter(|(_, class_name)| affected.contains(class_name))
+            .collect();
+
+        retransform(jvmti, selected)
+    }
+
+    // The state is used from the class file load hook, which mustn't fail because another thread
+    // panicked
+    fn state(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn state_mut(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for ProbeManager {
+    fn default() -> Self {
+        ProbeManager::new()
+    }
+}
+
+impl ClassTransformer for ProbeManager {
+    fn accepts(&self, class_name: &str) -> bool {
+        let state = self.state();
+        let class = ClassInfo::new(class_name);
+
+        state.woven.contains_key(class_name) || state.probes.values().any(|p| p.pointcut.matches_class(&class))
+    }
+
+    ///
+    /// Weave the enabled probes into the class, after removing those that are still woven into
+    /// it from an earlier transformation. Probes are woven in the order of their names.
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        let mut state = self.state_mut();
+        let cached = state.woven.contains_key(&class.name);
+
+        let original = match state.woven.get(&class.name) {
+            Some(woven) => strip(class, woven)?,
+            None => class.clone(),
+        };
+
+        let mut woven = original.clone();
+        let mut applied = BTreeSet::new();
+        let class_info = ClassInfo::from_class(&original);
+
+        for (name, probe) in state.probes.iter() {
+            if !probe.pointcut.matches_class(&class_info) {
+                continue;
+            }
+
+            let selected: Vec<usize> = woven
+                .methods
+                .iter()
+                .enumerate()
+                .filter(|&(_, method)| {
+                    method.code.is_some()
+                        && probe
+                            .pointcut
+                            .matches_method(&class_info, &MethodInfo::from_method(&original, method))
+                })
+                .map(|(index, _)| index)
+                .collect();
+
+            for &index in selected.iter() {
+                probe.weaver.weave(&mut woven, index)?;
+            }
+
+            if !selected.is_empty() {
+                applied.insert(name.clone());
+            }
+        }
+
+        // A class that was woven before is replaced even if nothing is woven into it anymore
+        let modified = cached || !applied.is_empty();
+
+        if applied.is_empty() {
+            state.woven.remove(&class.name);
+        } else {
+            state.woven.insert(
+                class.name.clone(),
+                WovenClass {
+                    original: chain::write(&original).map_err(TransformError::Failed)?,
+                    woven: chain::write(&woven).map_err(TransformError::Failed)?,
+                    probes: applied,
+                },
+            );
+        }
+
+        *class = woven;
+
+        Ok(modified)
+    }
+}
+
+/// The class with the methods that still contain the probes of the cached class reverted to their
+/// original code. Methods that have been changed since, eg. by a redefinition, are kept.
+fn strip(class: &JavaClass, cached: &WovenClass) -> Result<JavaClass, TransformError> {
+    let original = chain::read(&cached.original).map_err(TransformError::Failed)?;
+    let woven = chain::read(&cached.woven).map_err(TransformError::Failed)?;
+
+    // Compare the class in the form it has once written, like the cached ones
+    let written = chain::write(class).map_err(TransformError::Failed)?;
+    let mut stripped = chain::read(&written).map_err(TransformError::Failed)?;
+
+    for method in stripped.methods.iter_mut() {
+        let descriptor = method.descriptor.descriptor();
+        let woven_code = woven.method(&method.name, &descriptor).map(|m| &m.code);
+
+        if woven_code != Some(&method.code) {
+            continue;
+        }
+
+        if let Some(original) = original.method(&method.name, &descriptor) {
+            method.code = original.code.clone();
+        }
+    }
+
+    Ok(stripped)
+}
+
+fn woven_classes(state: &State, name: &str) -> Vec<String> {
+    state
+        .woven
+        .iter()
+        .filter(|&(_, class)| class.probes.contains(name))
+        .map(|(class_name, _)| class_name.clone())
+        .collect()
+}
+
+/// The loaded classes with their internal names, array and primitive classes are left out
+fn loaded_classes(jvmti: &dyn JVMTI) -> Result<Vec<(ClassId, String)>, RedefineError> {
+    let mut classes = vec![];
+
+    for class in jvmti.get_loaded_classes()?.iter() {
+        if let Some(name) = internal_name(&jvmti.get_class_signature(class)?) {
+            classes.push((ClassId { native_id: *class }, name));
+        }
+    }
+
+    Ok(classes)
+}
+
+/// `Lcom/acme/Service;` becomes `com/acme/Service`
+pub(crate) fn internal_name(signature: &ClassSignature) -> Option<String> {
+    let sig = &signature.native_sig;
+
+    if sig.starts_with('L') && sig.ends_with(';') {
+        Some(sig[1..sig.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+/// Retransform the given classes and return their names
+fn retransform(jvmti: &dyn JVMTI, classes: Vec<(ClassId, String)>) -> Result<Vec<String>, RedefineError> {
+    let (ids, names): (Vec<ClassId>, Vec<String>) = classes.into_iter().unzip();
+
+    jvmti.retransform(&ids)?;
+
+    Ok(names)
+}
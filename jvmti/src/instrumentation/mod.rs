@@ -1,74 +1,219 @@
 use super::bytecode::classfile::*;
 
+pub mod allocation;
+pub mod analysis;
 pub mod asm;
+pub mod bridge;
+pub mod capture;
+pub mod chain;
+pub mod code;
+pub mod constants;
+pub mod counters;
+pub mod coverage;
+pub mod descriptor;
+pub mod dynamic;
+pub mod pattern;
+pub mod pointcut;
+mod reader;
+pub mod replacement;
+pub mod timing;
+pub mod trace;
+pub mod transaction;
+mod writer;
 
-#[derive(PartialEq)]
-pub enum JavaType {
-    Boolean,
-    String,
-    Integer,
-    Long,
-    Float,
-    Double,
-    Array,
-    Reference,
-    Void
+pub use self::chain::{ClassTransformer, TransformError, TransformerChain};
+pub use self::code::{Code, Frame, FrameItem, Insn, Label, MemberRef, Value};
+pub use self::descriptor::{JavaType, MethodDescriptor};
+pub use self::dynamic::{MethodWeaver, ProbeManager};
+pub use self::pointcut::{ClassInfo, MethodInfo, Pointcut};
+pub use self::timing::{ProbeRegistry, TimingProbes};
+pub use self::writer::attribute_name;
+use self::writer::AttributeOrder;
+
+///
+/// Describes why a `Classfile` couldn't be turned into a `JavaClass` or vice versa
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelError {
+    /// A constant pool index points to an unused or non-existent slot
+    MissingConstant(usize),
+    /// A constant pool index points to a constant of the wrong kind
+    UnexpectedConstant(usize),
+    MissingBootstrapMethod(usize),
+    InvalidUtf8(Vec<u8>),
+    InvalidDescriptor(String),
+    /// A branch, handler or debug table refers to an offset that isn't an instruction boundary
+    InvalidOffset(usize),
+    InvalidInstruction(Instruction),
+    InvalidFrame(usize),
+    /// A label is referenced but was never placed in the instruction list
+    UnplacedLabel(Label),
+    DuplicateLabel(Label),
+    /// A conditional branch target is further away than a 16-bit offset can reach
+    BranchOutOfRange(Label),
+    CodeTooLarge(usize),
+    ConstantPoolOverflow(usize),
+    /// An instruction at the given position pops more values than there are on the stack
+    StackUnderflow(usize),
+    /// Incompatible operand stacks meet at the given position
+    StackMismatch(usize),
+    /// Execution can continue past the last instruction
+    FallOffEnd,
 }
 
-#[derive(PartialEq)]
+///
+/// An editable representation of a class, where constant pool references are replaced by names,
+/// descriptors are parsed and method bodies use labels instead of offsets. The constant pool is
+/// rebuilt when the class is converted back into a `Classfile`.
+#[derive(Debug, Clone, PartialEq)]
 pub struct JavaClass {
-    methods: Vec<Method>,
-    fields: Vec<Field>
+    pub version: ClassfileVersion,
+    pub access_flags: AccessFlags,
+    /// Internal name of the class, eg. `java/lang/String`
+    pub name: String,
+    pub super_name: Option<String>,
+    pub interfaces: Vec<String>,
+    pub source_file: Option<String>,
+    pub signature: Option<String>,
+    pub fields: Vec<Field>,
+    pub methods: Vec<Method>,
+    /// Attributes that aren't modelled explicitly. They are written back as they are.
+    pub attributes: Vec<Attribute>,
+    /// The constant pool the class was read from. Retained so that indices inside unmodelled
+    /// attributes remain valid after conversion.
+    constant_pool: ConstantPool,
+    bootstrap_methods: Vec<BootstrapMethod>,
+    attribute_order: AttributeOrder,
 }
 
 impl JavaClass {
     pub fn new() -> JavaClass {
         JavaClass {
+            version: ClassfileVersion::default(),
+            access_flags: AccessFlags::of(ClassAccessFlags::Public as u16 | ClassAccessFlags::Super as u16),
+            name: String::new(),
+            super_name: Some("java/lang/Object".to_string()),
+            interfaces: vec![],
+            source_file: None,
+            signature: None,
+            fields: vec![],
             methods: vec![],
-            fields: vec![]
+            attributes: vec![],
+            constant_pool: ConstantPool::default(),
+            bootstrap_methods: vec![],
+            attribute_order: AttributeOrder::default(),
         }
     }
 
-    pub fn to_classfile(&self) -> Classfile {
-        Classfile::new()
+    pub fn to_classfile(&self) -> Result<Classfile, ModelError> {
+        writer::write_class(self)
+    }
+
+    pub fn from_classfile(classfile: &Classfile) -> Result<JavaClass, ModelError> {
+        reader::read_class(classfile)
     }
 
-    // TODO: this function should report errors better, instead of just returning nothing on error
-    pub fn from_classfile(classfile: &Classfile) -> Option<JavaClass> {
-        None
+    pub fn is_interface(&self) -> bool {
+        self.access_flags.has_flag(ClassAccessFlags::Interface as u16)
     }
 
-    pub fn add_method(method: Method) {
+    pub fn add_method(&mut self, method: Method) {
+        self.methods.push(method);
+    }
+
+    pub fn add_field(&mut self, field: Field) {
+        self.fields.push(field);
+    }
+
+    pub fn add_interface(&mut self, name: &str) {
+        if !self.interfaces.iter().any(|i| i == name) {
+            self.interfaces.push(name.to_string());
+        }
+    }
+
+    pub fn method(&self, name: &str, descriptor: &str) -> Option<&Method> {
+        self.methods
+            .iter()
+            .find(|m| m.name == name && m.descriptor.descriptor() == descriptor)
+    }
 
+    pub fn method_mut(&mut self, name: &str, descriptor: &str) -> Option<&mut Method> {
+        self.methods
+            .iter_mut()
+            .find(|m| m.name == name && m.descriptor.descriptor() == descriptor)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Field {
-    name: String,
-    field_type: JavaType
+    pub access_flags: AccessFlags,
+    pub name: String,
+    pub field_type: JavaType,
+    pub signature: Option<String>,
+    /// Initial value of a static final field
+    pub constant_value: Option<Value>,
+    pub attributes: Vec<Attribute>,
+    attribute_order: AttributeOrder,
 }
 
 impl Field {
     pub fn new(name: String, field_type: JavaType) -> Field {
         Field {
-            name: name,
-            field_type: field_type
+            access_flags: AccessFlags::new(),
+            name,
+            field_type,
+            signature: None,
+            constant_value: None,
+            attributes: vec![],
+            attribute_order: AttributeOrder::default(),
         }
     }
+
+    pub fn is_static(&self) -> bool {
+        self.access_flags.has_flag(FieldAccessFlags::Static as u16)
+    }
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Method {
-    name: String,
-    return_type: JavaType
+    pub access_flags: AccessFlags,
+    pub name: String,
+    pub descriptor: MethodDescriptor,
+    pub signature: Option<String>,
+    /// Internal names of the checked exceptions declared by the method
+    pub exceptions: Vec<String>,
+    /// The method body, `None` for abstract and native methods
+    pub code: Option<Code>,
+    pub attributes: Vec<Attribute>,
+    attribute_order: AttributeOrder,
 }
 
 impl Method {
-    pub fn new(name: String) -> Method {
+    pub fn new(name: String, descriptor: MethodDescriptor) -> Method {
         Method {
-            name: name,
-            return_type: JavaType::Void
+            access_flags: AccessFlags::new(),
+            name,
+            descriptor,
+            signature: None,
+            exceptions: vec![],
+            code: None,
+            attributes: vec![],
+            attribute_order: AttributeOrder::default(),
         }
     }
+
+    pub fn is_static(&self) -> bool {
+        self.access_flags.has_flag(MethodAccessFlags::Static as u16)
+    }
+
+    pub fn is_abstract(&self) -> bool {
+        self.access_flags.has_flag(MethodAccessFlags::Abstract as u16)
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.access_flags.has_flag(MethodAccessFlags::Native as u16)
+    }
 }
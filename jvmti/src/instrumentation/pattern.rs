@@ -0,0 +1,583 @@
+use std::fmt;
+
+///
+/// A name pattern used by pointcuts. Patterns enclosed in slashes (`/Abstract.*Service/`) are
+/// regular expressions, everything else is a glob.
+///
+/// Both kinds have to match the whole text, not just a part of it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Glob(Glob),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Parse a glob or a regular expression. For globs, `*` and `?` won't match the separator.
+    pub fn parse(source: &str, separator: Option<char>) -> Result<Pattern, PatternError> {
+        if source.len() >= 2 && source.starts_with('/') && source.ends_with('/') {
+            Regex::parse(&source[1..source.len() - 1]).map(Pattern::Regex)
+        } else {
+            Glob::parse(source, separator).map(Pattern::Glob)
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match *self {
+            Pattern::Glob(ref glob) => glob.matches(text),
+            Pattern::Regex(ref regex) => regex.matches(text),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pattern::Glob(ref glob) => write!(f, "{}", glob.source),
+            Pattern::Regex(ref regex) => write!(f, "/{}/", regex.source),
+        }
+    }
+}
+
+///
+/// Describes why a pattern couldn't be parsed. Positions are character offsets into the pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternError {
+    UnbalancedParenthesis(usize),
+    UnbalancedBracket(usize),
+    /// A repetition operator doesn't follow anything it could repeat
+    MissingOperand(usize),
+    InvalidRepetition(usize),
+    InvalidEscape(usize),
+    /// A character class range ends before it starts, eg. `[z-a]`
+    InvalidRange(usize),
+    TrailingBackslash,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatternError::UnbalancedParenthesis(at) => write!(f, "unbalanced parenthesis at {}", at),
+            PatternError::UnbalancedBracket(at) => write!(f, "unbalanced bracket at {}", at),
+            PatternError::MissingOperand(at) => write!(f, "nothing to repeat at {}", at),
+            PatternError::InvalidRepetition(at) => write!(f, "invalid repetition at {}", at),
+            PatternError::InvalidEscape(at) => write!(f, "invalid escape sequence at {}", at),
+            PatternError::InvalidRange(at) => write!(f, "invalid character range at {}", at),
+            PatternError::TrailingBackslash => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum GlobToken {
+    Char(char),
+    /// `?`, a single character other than the separator
+    AnyChar,
+    /// `*`, any number of characters other than the separator
+    Star,
+    /// `**`, any number of characters
+    DoubleStar,
+}
+
+///
+/// A shell-like pattern, eg. `com.acme.**.*Service`. Special characters can be escaped with `\`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    source: String,
+    tokens: Vec<GlobToken>,
+    separator: Option<char>,
+}
+
+impl Glob {
+    pub fn parse(source: &str, separator: Option<char>) -> Result<Glob, PatternError> {
+        let mut tokens = vec![];
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => {
+                    if chars.peek() == Some(&'*') {
+                        chars.next();
+                        tokens.push(GlobToken::DoubleStar);
+                    } else {
+                        tokens.push(GlobToken::Star);
+                    }
+                }
+                '?' => tokens.push(GlobToken::AnyChar),
+                '\\' => match chars.next() {
+                    Some(escaped) => tokens.push(GlobToken::Char(escaped)),
+                    None => return Err(PatternError::TrailingBackslash),
+                },
+                _ => tokens.push(GlobToken::Char(c)),
+            }
+        }
+
+        Ok(Glob {
+            source: source.to_string(),
+            tokens,
+            separator,
+        })
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let is_separator = |c: char| self.separator == Some(c);
+
+        // matched[j] is set when the tokens seen so far match the first j characters
+        let mut matched = vec![false; chars.len() + 1];
+        matched[0] = true;
+
+        for token in self.tokens.iter() {
+            let mut next = vec![false; chars.len() + 1];
+
+            for j in 0..chars.len() + 1 {
+                next[j] = match *token {
+                    GlobToken::Star => matched[j] || (j > 0 && next[j - 1] && !is_separator(chars[j - 1])),
+                    GlobToken::DoubleStar => matched[j] || (j > 0 && next[j - 1]),
+                    GlobToken::AnyChar => j > 0 && matched[j - 1] && !is_separator(chars[j - 1]),
+                    GlobToken::Char(c) => j > 0 && matched[j - 1] && chars[j - 1] == c,
+                };
+            }
+
+            matched = next;
+        }
+
+        matched[chars.len()]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, u32, Option<u32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Inst {
+    Char(char),
+    Any,
+    /// Inclusive character ranges, negated when the flag is set
+    Class(Vec<(char, char)>, bool),
+    Split(usize, usize),
+    Jump(usize),
+    Start,
+    End,
+    Match,
+}
+
+/// Upper bound of counted repetitions, to keep compiled programs small
+const MAX_REPETITION: u32 = 1000;
+
+///
+/// A regular expression supporting the commonly used part of the Java syntax: alternation,
+/// groups, character classes, `\d`, `\w`, `\s` and their negations, anchors and the `*`, `+`,
+/// `?` and `{n,m}` repetitions. Matching runs in linear time, there's no backtracking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regex {
+    source: String,
+    program: Vec<Inst>,
+}
+
+impl Regex {
+    pub fn parse(source: &str) -> Result<Regex, PatternError> {
+        let mut parser = RegexParser {
+            chars: source.chars().collect(),
+            position: 0,
+        };
+
+        let node = parser.alternation()?;
+
+        if parser.position < parser.chars.len() {
+            // The only way to stop early is an unmatched closing parenthesis
+            return Err(PatternError::UnbalancedParenthesis(parser.position));
+        }
+
+        let mut program = vec![];
+        compile(&node, &mut program);
+        program.push(Inst::Match);
+
+        Ok(Regex {
+            source: source.to_string(),
+            program,
+        })
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut current = vec![];
+        let mut seen = vec![false; self.program.len()];
+
+        self.follow(0, 0, chars.len(), &mut current, &mut seen);
+
+        for (position, c) in chars.iter().enumerate() {
+            let mut next = vec![];
+            let mut seen = vec![false; self.program.len()];
+
+            for &pc in current.iter() {
+                let accepted = match self.program[pc] {
+                    Inst::Char(expected) => expected == *c,
+                    Inst::Any => true,
+                    Inst::Class(ref ranges, negated) => {
+                        ranges.iter().any(|&(low, high)| low <= *c && *c <= high) != negated
+                    }
+                    _ => false,
+                };
+
+                if accepted {
+                    self.follow(pc + 1, position + 1, chars.len(), &mut next, &mut seen);
+                }
+            }
+
+            if next.is_empty() {
+                return false;
+            }
+
+            current = next;
+        }
+
+        current.iter().any(|&pc| self.program[pc] == Inst::Match)
+    }
+
+    /// Add the instructions reachable from `pc` without consuming input to the thread list
+    fn follow(&self, pc: usize, position: usize, length: usize, threads: &mut Vec<usize>, seen: &mut Vec<bool>) {
+        if seen[pc] {
+            return;
+        }
+
+        seen[pc] = true;
+
+        match self.program[pc] {
+            Inst::Jump(target) => self.follow(target, position, length, threads, seen),
+            Inst::Split(first, second) => {
+                self.follow(first, position, length, threads, seen);
+                self.follow(second, position, length, threads, seen);
+            }
+            Inst::Start => {
+                if position == 0 {
+                    self.follow(pc + 1, position, length, threads, seen);
+                }
+            }
+            Inst::End => {
+                if position == length {
+                    self.follow(pc + 1, position, length, threads, seen);
+                }
+            }
+            _ => threads.push(pc),
+        }
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match *node {
+        Node::Empty => (),
+        Node::Char(c) => program.push(Inst::Char(c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(ref ranges, negated) => program.push(Inst::Class(ranges.clone(), negated)),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(ref nodes) => {
+            for node in nodes.iter() {
+                compile(node, program);
+            }
+        }
+        Node::Alternate(ref nodes) => {
+            let mut jumps = vec![];
+
+            for (i, node) in nodes.iter().enumerate() {
+                if i + 1 < nodes.len() {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    let next = program.len();
+                    program[split] = Inst::Split(split + 1, next);
+                } else {
+                    compile(node, program);
+                }
+            }
+
+            let end = program.len();
+
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat(ref node, min, max) => {
+            for _ in 0..min {
+                compile(node, program);
+            }
+
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(split));
+                    let end = program.len();
+                    program[split] = Inst::Split(split + 1, end);
+                }
+                Some(max) => {
+                    let mut splits = vec![];
+
+                    for _ in min..max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile(node, program);
+                    }
+
+                    let end = program.len();
+
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct RegexParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl RegexParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn alternation(&mut self) -> Result<Node, PatternError> {
+        let mut branches = vec![self.concatenation()?];
+
+        while self.peek() == Some('|') {
+            self.position += 1;
+            branches.push(self.concatenation()?);
+        }
+
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn concatenation(&mut self) -> Result<Node, PatternError> {
+        let mut nodes = vec![];
+
+        loop {
+            match self.peek() {
+                None | Some('|') | Some(')') => break,
+                Some('*') | Some('+') | Some('?') | Some('{') => {
+                    let operand = match nodes.pop() {
+                        Some(Node::Start) | Some(Node::End) | None => {
+                            return Err(PatternError::MissingOperand(self.position))
+                        }
+                        Some(node) => node,
+                    };
+
+                    let (min, max) = self.repetition()?;
+
+                    // Lazy quantifiers behave the same when the whole text has to match
+                    if self.peek() == Some('?') {
+                        self.position += 1;
+                    }
+
+                    nodes.push(Node::Repeat(Box::new(operand), min, max));
+                }
+                Some(_) => {
+                    let atom = self.atom()?;
+                    nodes.push(atom);
+                }
+            }
+        }
+
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn repetition(&mut self) -> Result<(u32, Option<u32>), PatternError> {
+        let start = self.position;
+        let c = self.chars[self.position];
+        self.position += 1;
+
+        match c {
+            '*' => Ok((0, None)),
+            '+' => Ok((1, None)),
+            '?' => Ok((0, Some(1))),
+            _ => {
+                let min = self.number().ok_or(PatternError::InvalidRepetition(start))?;
+
+                let max = match self.peek() {
+                    Some(',') => {
+                        self.position += 1;
+
+                        if self.peek() == Some('}') {
+                            None
+                        } else {
+                            Some(self.number().ok_or(PatternError::InvalidRepetition(start))?)
+                        }
+                    }
+                    _ => Some(min),
+                };
+
+                if self.peek() != Some('}') || max.map(|max| max < min).unwrap_or(false)
+                    || min.max(max.unwrap_or(0)) > MAX_REPETITION
+                {
+                    return Err(PatternError::InvalidRepetition(start));
+                }
+
+                self.position += 1;
+
+                Ok((min, max))
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.position;
+
+        while self.peek().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            self.position += 1;
+        }
+
+        self.chars[start..self.position]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn atom(&mut self) -> Result<Node, PatternError> {
+        let start = self.position;
+        let c = self.chars[self.position];
+        self.position += 1;
+
+        match c {
+            '(' => {
+                // Groups don't capture anyway, so non-capturing groups are the same thing
+                if self.chars[self.position..].starts_with(&['?', ':']) {
+                    self.position += 2;
+                }
+
+                let node = self.alternation()?;
+
+                if self.peek() != Some(')') {
+                    return Err(PatternError::UnbalancedParenthesis(start));
+                }
+
+                self.position += 1;
+
+                Ok(node)
+            }
+            '[' => self.class(start),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '\\' => match self.escape()? {
+                (ranges, true) if ranges.len() == 1 && ranges[0].0 == ranges[0].1 => Ok(Node::Char(ranges[0].0)),
+                (ranges, positive) => Ok(Node::Class(ranges, !positive)),
+            },
+            _ => Ok(Node::Char(c)),
+        }
+    }
+
+    /// Parse the escape sequence after a backslash into character ranges and whether they're
+    /// to be matched (`\d`) or excluded (`\D`)
+    fn escape(&mut self) -> Result<(Vec<(char, char)>, bool), PatternError> {
+        let start = self.position - 1;
+
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(PatternError::TrailingBackslash),
+        };
+
+        self.position += 1;
+
+        let digits = vec![('0', '9')];
+        let word = vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+        let space = vec![('\t', '\r'), (' ', ' ')];
+
+        match c {
+            'd' => Ok((digits, true)),
+            'D' => Ok((digits, false)),
+            'w' => Ok((word, true)),
+            'W' => Ok((word, false)),
+            's' => Ok((space, true)),
+            'S' => Ok((space, false)),
+            't' => Ok((vec![('\t', '\t')], true)),
+            'n' => Ok((vec![('\n', '\n')], true)),
+            'r' => Ok((vec![('\r', '\r')], true)),
+            'f' => Ok((vec![('\x0C', '\x0C')], true)),
+            c if c.is_alphanumeric() => Err(PatternError::InvalidEscape(start)),
+            c => Ok((vec![(c, c)], true)),
+        }
+    }
+
+    fn class(&mut self, start: usize) -> Result<Node, PatternError> {
+        let mut ranges = vec![];
+        let negated = self.peek() == Some('^');
+
+        if negated {
+            self.position += 1;
+        }
+
+        let mut first = true;
+
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(PatternError::UnbalancedBracket(start)),
+            };
+
+            self.position += 1;
+
+            // A closing bracket right after the opening one is taken literally
+            if c == ']' && !first {
+                break;
+            }
+
+            first = false;
+
+            let low = if c == '\\' {
+                match self.escape()? {
+                    (escaped, true) if escaped.len() == 1 && escaped[0].0 == escaped[0].1 => escaped[0].0,
+                    (escaped, true) => {
+                        ranges.extend(escaped);
+                        continue;
+                    }
+                    (_, false) => return Err(PatternError::InvalidEscape(self.position - 2)),
+                }
+            } else {
+                c
+            };
+
+            let is_range = self.peek() == Some('-')
+                && self.chars.get(self.position + 1).map(|&c| c != ']').unwrap_or(false);
+
+            if is_range {
+                let high = self.chars[self.position + 1];
+                self.position += 2;
+
+                if high < low {
+                    return Err(PatternError::InvalidRange(self.position - 3));
+                }
+
+                ranges.push((low, high));
+            } else {
+                ranges.push((low, low));
+            }
+        }
+
+        Ok(Node::Class(ranges, negated))
+    }
+}
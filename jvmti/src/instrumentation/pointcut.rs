@@ -0,0 +1,555 @@
+use super::super::bytecode::classfile::{Attribute, ClassAccessFlags, ConstantPool, MethodAccessFlags};
+use super::super::class::ClassSignature;
+use super::super::method::MethodSignature;
+use super::constants::decode_utf8;
+use super::descriptor::JavaType;
+use super::pattern::{Pattern, PatternError};
+use super::{JavaClass, Method};
+use serde::de::{self, Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+
+///
+/// Selects classes and methods by their names and declarations. Pointcuts are usually parsed from
+/// expressions like
+///
+/// ```text
+/// execution(com.acme.**.*Service.handle*) && access(public) && !annotated(com.acme.Internal)
+/// ```
+///
+/// The available predicates are
+///
+/// * `class(P)`, `package(P)`: the class name with dots, eg. `com.acme.Service$Worker`, and its package
+/// * `extends(P)`, `implements(P)`: the direct super class or any of the directly implemented interfaces
+/// * `annotated(P)`: an annotation type of the class
+/// * `class_access(M...)`: class modifiers, eg. `class_access(public final)`
+/// * `method(P)`, `descriptor(P)`: the method name and its descriptor, eg. `descriptor((J)*)`
+/// * `access(M...)`: method modifiers, all of them have to be present
+/// * `method_annotated(P)`: an annotation type of the method
+/// * `execution(C.M)` or `execution(C.M(D))`: shorthand for the class, method and descriptor predicates
+///
+/// Predicates are combined with `&&`, `||`, `!` and parentheses. Patterns are either globs where
+/// `*` doesn't cross package boundaries but `**` does, or regular expressions between slashes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pointcut {
+    Class(Pattern),
+    Package(Pattern),
+    Extends(Pattern),
+    Implements(Pattern),
+    Annotated(Pattern),
+    ClassAccess(u16),
+    Method(Pattern),
+    Descriptor(Pattern),
+    Access(u16),
+    MethodAnnotated(Pattern),
+    And(Box<Pointcut>, Box<Pointcut>),
+    Or(Box<Pointcut>, Box<Pointcut>),
+    Not(Box<Pointcut>),
+}
+
+///
+/// Describes why a pointcut expression couldn't be parsed. Positions are character offsets.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PointcutError {
+    UnexpectedEnd,
+    UnexpectedCharacter(usize, char),
+    UnknownPredicate(String),
+    UnknownModifier(String),
+    InvalidPattern(String, PatternError),
+    /// An `execution` argument that doesn't have a `.` between the class and the method name
+    InvalidExecution(String),
+}
+
+impl fmt::Display for PointcutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PointcutError::UnexpectedEnd => write!(f, "unexpected end of pointcut"),
+            PointcutError::UnexpectedCharacter(at, c) => write!(f, "unexpected '{}' at {}", c, at),
+            PointcutError::UnknownPredicate(ref name) => write!(f, "unknown predicate '{}'", name),
+            PointcutError::UnknownModifier(ref name) => write!(f, "unknown modifier '{}'", name),
+            PointcutError::InvalidPattern(ref pattern, ref err) => {
+                write!(f, "invalid pattern '{}': {}", pattern, err)
+            }
+            PointcutError::InvalidExecution(ref argument) => {
+                write!(f, "expected Class.method in execution({})", argument)
+            }
+        }
+    }
+}
+
+const CLASS_MODIFIERS: &[(&str, u16)] = &[
+    ("public", ClassAccessFlags::Public as u16),
+    ("final", ClassAccessFlags::Final as u16),
+    ("interface", ClassAccessFlags::Interface as u16),
+    ("abstract", ClassAccessFlags::Abstract as u16),
+    ("synthetic", ClassAccessFlags::Synthetic as u16),
+    ("annotation", ClassAccessFlags::Annotation as u16),
+    ("enum", ClassAccessFlags::Enum as u16),
+];
+
+const METHOD_MODIFIERS: &[(&str, u16)] = &[
+    ("public", MethodAccessFlags::Public as u16),
+    ("private", MethodAccessFlags::Private as u16),
+    ("protected", MethodAccessFlags::Protected as u16),
+    ("static", MethodAccessFlags::Static as u16),
+    ("final", MethodAccessFlags::Final as u16),
+    ("synchronized", MethodAccessFlags::Synchronized as u16),
+    ("bridge", MethodAccessFlags::Bridge as u16),
+    ("varargs", MethodAccessFlags::Varargs as u16),
+    ("native", MethodAccessFlags::Native as u16),
+    ("abstract", MethodAccessFlags::Abstract as u16),
+    ("strictfp", MethodAccessFlags::Strict as u16),
+    ("synthetic", MethodAccessFlags::Synthetic as u16),
+];
+
+impl Pointcut {
+    pub fn parse(expression: &str) -> Result<Pointcut, PointcutError> {
+        let mut parser = Parser {
+            chars: expression.chars().collect(),
+            position: 0,
+        };
+
+        let pointcut = parser.disjunction()?;
+
+        parser.skip_whitespace();
+
+        match parser.peek() {
+            None => Ok(pointcut),
+            Some(c) => Err(PointcutError::UnexpectedCharacter(parser.position, c)),
+        }
+    }
+
+    pub fn and(self, other: Pointcut) -> Pointcut {
+        Pointcut::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Pointcut) -> Pointcut {
+        Pointcut::Or(Box::new(self), Box::new(other))
+    }
+
+    ///
+    /// Whether the pointcut may select methods of the given class. Method predicates and
+    /// predicates on declarations that aren't known are undecided, which makes this a cheap
+    /// filter for the class file load hook before looking at the methods themselves.
+    pub fn matches_class(&self, class: &ClassInfo) -> bool {
+        self.evaluate(class, None) != Some(false)
+    }
+
+    ///
+    /// Whether the pointcut selects the given method. Predicates on declarations that aren't known,
+    /// eg. annotations of a method reported by a method entry event, don't match.
+    pub fn matches_method(&self, class: &ClassInfo, method: &MethodInfo) -> bool {
+        self.evaluate(class, Some(method)) == Some(true)
+    }
+
+    /// Three-valued evaluation, where `None` stands for facts that aren't known
+    fn evaluate(&self, class: &ClassInfo, method: Option<&MethodInfo>) -> Option<bool> {
+        let class_declaration = class.declaration.as_ref();
+        let method_declaration = method.and_then(|m| m.declaration.as_ref());
+
+        match *self {
+            Pointcut::Class(ref pattern) => Some(pattern.matches(&class.name)),
+            Pointcut::Package(ref pattern) => Some(pattern.matches(class.package())),
+            Pointcut::Extends(ref pattern) => class_declaration.map(|d| match d.super_name {
+                Some(ref name) => pattern.matches(name),
+                None => false,
+            }),
+            Pointcut::Implements(ref pattern) => {
+                class_declaration.map(|d| d.interfaces.iter().any(|i| pattern.matches(i)))
+            }
+            Pointcut::Annotated(ref pattern) => {
+                class_declaration.map(|d| d.annotations.iter().any(|a| pattern.matches(a)))
+            }
+            Pointcut::ClassAccess(flags) => class_declaration.map(|d| d.access_flags & flags == flags),
+            Pointcut::Method(ref pattern) => method.map(|m| pattern.matches(&m.name)),
+            Pointcut::Descriptor(ref pattern) => method.map(|m| pattern.matches(&m.descriptor)),
+            Pointcut::Access(flags) => method_declaration.map(|d| d.access_flags & flags == flags),
+            Pointcut::MethodAnnotated(ref pattern) => {
+                method_declaration.map(|d| d.annotations.iter().any(|a| pattern.matches(a)))
+            }
+            Pointcut::And(ref left, ref right) => {
+                match (left.evaluate(class, method), right.evaluate(class, method)) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            Pointcut::Or(ref left, ref right) => {
+                match (left.evaluate(class, method), right.evaluate(class, method)) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }
+            }
+            Pointcut::Not(ref inner) => inner.evaluate(class, method).map(|result| !result),
+        }
+    }
+}
+
+impl FromStr for Pointcut {
+    type Err = PointcutError;
+
+    fn from_str(expression: &str) -> Result<Pointcut, PointcutError> {
+        Pointcut::parse(expression)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pointcut {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Pointcut, D::Error> {
+        let expression = String::deserialize(deserializer)?;
+
+        Pointcut::parse(&expression).map_err(de::Error::custom)
+    }
+}
+
+impl fmt::Display for Pointcut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pointcut::Class(ref pattern) => write!(f, "class({})", pattern),
+            Pointcut::Package(ref pattern) => write!(f, "package({})", pattern),
+            Pointcut::Extends(ref pattern) => write!(f, "extends({})", pattern),
+            Pointcut::Implements(ref pattern) => write!(f, "implements({})", pattern),
+            Pointcut::Annotated(ref pattern) => write!(f, "annotated({})", pattern),
+            Pointcut::ClassAccess(flags) => write!(f, "class_access({})", modifier_names(CLASS_MODIFIERS, flags)),
+            Pointcut::Method(ref pattern) => write!(f, "method({})", pattern),
+            Pointcut::Descriptor(ref pattern) => write!(f, "descriptor({})", pattern),
+            Pointcut::Access(flags) => write!(f, "access({})", modifier_names(METHOD_MODIFIERS, flags)),
+            Pointcut::MethodAnnotated(ref pattern) => write!(f, "method_annotated({})", pattern),
+            Pointcut::And(ref left, ref right) => {
+                write_operand(f, left, 2)?;
+                write!(f, " && ")?;
+                write_operand(f, right, 2)
+            }
+            Pointcut::Or(ref left, ref right) => {
+                write_operand(f, left, 1)?;
+                write!(f, " || ")?;
+                write_operand(f, right, 1)
+            }
+            Pointcut::Not(ref inner) => {
+                write!(f, "!")?;
+                write_operand(f, inner, 3)
+            }
+        }
+    }
+}
+
+/// Write a nested pointcut, adding parentheses if it binds looser than the given precedence
+fn write_operand(f: &mut fmt::Formatter, pointcut: &Pointcut, precedence: u8) -> fmt::Result {
+    let own_precedence = match *pointcut {
+        Pointcut::Or(_, _) => 1,
+        Pointcut::And(_, _) => 2,
+        _ => 3,
+    };
+
+    if own_precedence < precedence {
+        write!(f, "({})", pointcut)
+    } else {
+        write!(f, "{}", pointcut)
+    }
+}
+
+fn modifier_names(modifiers: &[(&str, u16)], flags: u16) -> String {
+    modifiers
+        .iter()
+        .filter(|&&(_, flag)| flags & flag == flag)
+        .map(|&(name, _)| name)
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.position += 1;
+        }
+    }
+
+    fn consume(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+
+        let matches = token
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.position + i) == Some(&c));
+
+        if matches {
+            self.position += token.chars().count();
+        }
+
+        matches
+    }
+
+    fn expect(&mut self, token: char) -> Result<(), PointcutError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(c) if c == token => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(c) => Err(PointcutError::UnexpectedCharacter(self.position, c)),
+            None => Err(PointcutError::UnexpectedEnd),
+        }
+    }
+
+    fn disjunction(&mut self) -> Result<Pointcut, PointcutError> {
+        let mut pointcut = self.conjunction()?;
+
+        while self.consume("||") {
+            pointcut = pointcut.or(self.conjunction()?);
+        }
+
+        Ok(pointcut)
+    }
+
+    fn conjunction(&mut self) -> Result<Pointcut, PointcutError> {
+        let mut pointcut = self.unary()?;
+
+        while self.consume("&&") {
+            pointcut = pointcut.and(self.unary()?);
+        }
+
+        Ok(pointcut)
+    }
+
+    fn unary(&mut self) -> Result<Pointcut, PointcutError> {
+        if self.consume("!") {
+            Ok(Pointcut::Not(Box::new(self.unary()?)))
+        } else if self.consume("(") {
+            let pointcut = self.disjunction()?;
+            self.expect(')')?;
+            Ok(pointcut)
+        } else {
+            self.predicate()
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Pointcut, PointcutError> {
+        self.skip_whitespace();
+
+        let start = self.position;
+
+        while self.peek().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false) {
+            self.position += 1;
+        }
+
+        if start == self.position {
+            return match self.peek() {
+                Some(c) => Err(PointcutError::UnexpectedCharacter(self.position, c)),
+                None => Err(PointcutError::UnexpectedEnd),
+            };
+        }
+
+        let name: String = self.chars[start..self.position].iter().collect();
+
+        self.expect('(')?;
+        let argument = self.argument()?;
+        self.expect(')')?;
+
+        let names = |argument: &str| pattern(argument, Some('.'));
+
+        match name.as_str() {
+            "class" => Ok(Pointcut::Class(names(&argument)?)),
+            "package" => Ok(Pointcut::Package(names(&argument)?)),
+            "extends" => Ok(Pointcut::Extends(names(&argument)?)),
+            "implements" => Ok(Pointcut::Implements(names(&argument)?)),
+            "annotated" => Ok(Pointcut::Annotated(names(&argument)?)),
+            "class_access" => Ok(Pointcut::ClassAccess(modifiers(CLASS_MODIFIERS, &argument)?)),
+            "method" => Ok(Pointcut::Method(pattern(&argument, None)?)),
+            "descriptor" => Ok(Pointcut::Descriptor(pattern(&argument, None)?)),
+            "access" => Ok(Pointcut::Access(modifiers(METHOD_MODIFIERS, &argument)?)),
+            "method_annotated" => Ok(Pointcut::MethodAnnotated(names(&argument)?)),
+            "execution" => execution(&argument),
+            _ => Err(PointcutError::UnknownPredicate(name)),
+        }
+    }
+
+    /// Read everything up to the closing parenthesis of a predicate. Parentheses inside the
+    /// argument have to be balanced, except in regular expressions.
+    fn argument(&mut self) -> Result<String, PointcutError> {
+        self.skip_whitespace();
+
+        let start = self.position;
+        let mut depth = 0;
+        let mut in_regex = self.peek() == Some('/');
+
+        if in_regex {
+            self.position += 1;
+        }
+
+        loop {
+            match self.peek() {
+                None => return Err(PointcutError::UnexpectedEnd),
+                Some('\\') if in_regex => self.position += 1,
+                Some('/') if in_regex => in_regex = false,
+                Some('(') if !in_regex => depth += 1,
+                Some(')') if !in_regex && depth == 0 => break,
+                Some(')') if !in_regex => depth -= 1,
+                _ => (),
+            }
+
+            self.position += 1;
+        }
+
+        Ok(self.chars[start..self.position].iter().collect::<String>().trim().to_string())
+    }
+}
+
+fn pattern(argument: &str, separator: Option<char>) -> Result<Pattern, PointcutError> {
+    Pattern::parse(argument, separator).map_err(|err| PointcutError::InvalidPattern(argument.to_string(), err))
+}
+
+fn modifiers(modifiers: &[(&str, u16)], argument: &str) -> Result<u16, PointcutError> {
+    argument
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .try_fold(0, |flags, word| match modifiers.iter().find(|&&(name, _)| name == word) {
+            Some(&(_, flag)) => Ok(flags | flag),
+            None => Err(PointcutError::UnknownModifier(word.to_string())),
+        })
+}
+
+/// Expand `execution(com.acme.Service.handle(J)V)` into class, method and descriptor predicates
+fn execution(argument: &str) -> Result<Pointcut, PointcutError> {
+    let (name, descriptor) = match argument.find('(') {
+        Some(index) => argument.split_at(index),
+        None => (argument, ""),
+    };
+
+    let (class_name, method_name) = match name.rfind('.') {
+        Some(index) => (&name[..index], &name[index + 1..]),
+        None => return Err(PointcutError::InvalidExecution(argument.to_string())),
+    };
+
+    let pointcut = Pointcut::Class(pattern(class_name, Some('.'))?).and(Pointcut::Method(pattern(method_name, None)?));
+
+    if descriptor.is_empty() {
+        Ok(pointcut)
+    } else {
+        Ok(pointcut.and(Pointcut::Descriptor(pattern(descriptor, None)?)))
+    }
+}
+
+///
+/// What is known about a class when evaluating a pointcut. Class file load hooks can provide the
+/// full declaration, while method events only know the name of the class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassInfo {
+    /// Binary name of the class with dots, eg. `com.acme.Service$Worker`
+    pub name: String,
+    pub declaration: Option<ClassDeclaration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDeclaration {
+    pub access_flags: u16,
+    pub super_name: Option<String>,
+    pub interfaces: Vec<String>,
+    /// Types of the visible and invisible annotations
+    pub annotations: Vec<String>,
+}
+
+impl ClassInfo {
+    /// Create a class without a declaration, from its internal (`com/acme/Service`) or binary name
+    pub fn new(name: &str) -> ClassInfo {
+        ClassInfo {
+            name: name.replace('/', "."),
+            declaration: None,
+        }
+    }
+
+    pub fn from_class(class: &JavaClass) -> ClassInfo {
+        ClassInfo {
+            name: class.name.replace('/', "."),
+            declaration: Some(ClassDeclaration {
+                access_flags: class.access_flags.flags,
+                super_name: class.super_name.as_ref().map(|name| name.replace('/', ".")),
+                interfaces: class.interfaces.iter().map(|name| name.replace('/', ".")).collect(),
+                annotations: annotations(&class.constant_pool, &class.attributes),
+            }),
+        }
+    }
+
+    pub fn from_signature(signature: &ClassSignature) -> ClassInfo {
+        if signature.package.is_empty() {
+            ClassInfo::new(&signature.name)
+        } else {
+            ClassInfo::new(&signature.to_string())
+        }
+    }
+
+    pub fn package(&self) -> &str {
+        match self.name.rfind('.') {
+            Some(index) => &self.name[..index],
+            None => "",
+        }
+    }
+}
+
+///
+/// What is known about a method when evaluating a pointcut
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodInfo {
+    pub name: String,
+    pub descriptor: String,
+    pub declaration: Option<MethodDeclaration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodDeclaration {
+    pub access_flags: u16,
+    pub annotations: Vec<String>,
+}
+
+impl MethodInfo {
+    pub fn new(name: &str, descriptor: &str) -> MethodInfo {
+        MethodInfo {
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+            declaration: None,
+        }
+    }
+
+    /// Describe a method of the given class, which has to be the class it was read from
+    pub fn from_method(class: &JavaClass, method: &Method) -> MethodInfo {
+        MethodInfo {
+            name: method.name.clone(),
+            descriptor: method.descriptor.descriptor(),
+            declaration: Some(MethodDeclaration {
+                access_flags: method.access_flags.flags,
+                annotations: annotations(&class.constant_pool, &method.attributes),
+            }),
+        }
+    }
+
+    pub fn from_signature(signature: &MethodSignature) -> MethodInfo {
+        MethodInfo::new(&signature.name, &signature.signature)
+    }
+}
+
+/// Collect the binary names of annotation types from runtime annotation attributes
+fn annotations(constant_pool: &ConstantPool, attributes: &[Attribute]) -> Vec<String> {
+    attributes
+        .iter()
+        .flat_map(|attribute| match *attribute {
+            Attribute::RuntimeVisibleAnnotations(ref annotations)
+            | Attribute::RuntimeInvisibleAnnotations(ref annotations) => annotations.iter().collect(),
+            _ => vec![],
+        })
+        .filter_map(|annotation| constant_pool.get_utf8(annotation.type_index.idx as u16))
+        .filter_map(|bytes| decode_utf8(bytes).ok())
+        .filter_map(|descriptor| match JavaType::parse(&descriptor) {
+            Some(JavaType::Class(name)) => Some(name.replace('/', ".")),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,585 @@
+use super::super::bytecode::classfile as bc;
+use super::super::bytecode::classfile::{Attribute, Instruction, StackMapFrame, VerificationType};
+use super::code::*;
+use super::constants::ConstantResolver;
+use super::descriptor::{JavaType, MethodDescriptor};
+use super::writer::AttributeOrder;
+use super::{Field, JavaClass, Method, ModelError};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+///
+/// Converts a low-level `Classfile` into the symbolic `JavaClass` model.
+pub fn read_class(classfile: &bc::Classfile) -> Result<JavaClass, ModelError> {
+    let bootstrap_methods: &[bc::BootstrapMethod] = classfile
+        .attributes
+        .iter()
+        .filter_map(|attr| match *attr {
+            Attribute::BootstrapMethods(ref methods) => Some(methods.as_slice()),
+            _ => None,
+        })
+        .next()
+        .unwrap_or(&[]);
+
+    let resolver = ConstantResolver::new(&classfile.constant_pool, bootstrap_methods);
+
+    let name = resolver.class_name(classfile.this_class.idx)?;
+
+    let mut class = JavaClass::new();
+
+    class.version = classfile.version.clone();
+    class.access_flags = classfile.access_flags.clone();
+    class.super_name = resolver.optional_class_name(classfile.super_class.idx)?;
+    class.interfaces = classfile
+        .interfaces
+        .iter()
+        .map(|idx| resolver.class_name(idx.idx))
+        .collect::<Result<Vec<String>, ModelError>>()?;
+
+    for attribute in &classfile.attributes {
+        match *attribute {
+            Attribute::SourceFile(ref idx) => class.source_file = Some(resolver.utf8(idx.idx)?),
+            Attribute::Signature(ref idx) => class.signature = Some(resolver.utf8(idx.idx)?),
+            // Call sites are resolved symbolically, the table is regenerated on write
+            Attribute::BootstrapMethods(_) => (),
+            ref other => class.attributes.push(other.clone()),
+        }
+    }
+
+    for field in &classfile.fields {
+        class.fields.push(read_field(&resolver, field)?);
+    }
+
+    for method in &classfile.methods {
+        class.methods.push(read_method(&resolver, &name, method)?);
+    }
+
+    class.name = name;
+    class.constant_pool = classfile.constant_pool.clone();
+    class.bootstrap_methods = bootstrap_methods.to_vec();
+    class.attribute_order = AttributeOrder::record(
+        &classfile.attributes,
+        &["SourceFile", "Signature", "BootstrapMethods"],
+    );
+
+    Ok(class)
+}
+
+fn read_field(resolver: &ConstantResolver, field: &bc::Field) -> Result<Field, ModelError> {
+    let descriptor = resolver.utf8(field.descriptor_index.idx)?;
+
+    let mut result = Field::new(
+        resolver.utf8(field.name_index.idx)?,
+        JavaType::parse(&descriptor).ok_or(ModelError::InvalidDescriptor(descriptor.clone()))?,
+    );
+
+    result.access_flags = field.access_flags.clone();
+
+    for attribute in &field.attributes {
+        match *attribute {
+            Attribute::ConstantValue(ref idx) => {
+                result.constant_value = Some(resolver.value(idx.idx)?)
+            }
+            Attribute::Signature(ref idx) => result.signature = Some(resolver.utf8(idx.idx)?),
+            ref other => result.attributes.push(other.clone()),
+        }
+    }
+
+    result.attribute_order =
+        AttributeOrder::record(&field.attributes, &["ConstantValue", "Signature"]);
+
+    Ok(result)
+}
+
+fn read_method(
+    resolver: &ConstantResolver,
+    class_name: &str,
+    method: &bc::Method,
+) -> Result<Method, ModelError> {
+    let descriptor = resolver.utf8(method.descriptor_index.idx)?;
+
+    let mut result = Method::new(
+        resolver.utf8(method.name_index.idx)?,
+        MethodDescriptor::parse(&descriptor).ok_or(ModelError::InvalidDescriptor(descriptor.clone()))?,
+    );
+
+    result.access_flags = method.access_flags.clone();
+
+    for attribute in &method.attributes {
+        match *attribute {
+            Attribute::Code {
+                max_stack,
+                max_locals,
+                ref code,
+                ref exception_table,
+                ref attributes,
+            } => {
+                let initial = Frame::initial(
+                    class_name,
+                    result.is_static(),
+                    &result.name,
+                    &result.descriptor,
+                );
+
+                let mut body = CodeReader::new(resolver, code).read(
+                    initial,
+                    exception_table,
+                    attributes,
+                )?;
+
+                body.max_stack = max_stack;
+                body.max_locals = max_locals;
+
+                result.code = Some(body);
+            }
+            Attribute::Exceptions(ref table) => {
+                result.exceptions = table
+                    .iter()
+                    .map(|idx| resolver.class_name(idx.idx))
+                    .collect::<Result<Vec<String>, ModelError>>()?
+            }
+            Attribute::Signature(ref idx) => result.signature = Some(resolver.utf8(idx.idx)?),
+            ref other => result.attributes.push(other.clone()),
+        }
+    }
+
+    result.attribute_order =
+        AttributeOrder::record(&method.attributes, &["Code", "Exceptions", "Signature"]);
+
+    Ok(result)
+}
+
+///
+/// Lifts the instructions of a single `Code` attribute, replacing offsets with labels.
+struct CodeReader<'a, 'b: 'a> {
+    resolver: &'a ConstantResolver<'b>,
+    instructions: &'a [Instruction],
+    offsets: Vec<usize>,
+    code_length: usize,
+    labels: HashMap<usize, Label>,
+}
+
+impl<'a, 'b> CodeReader<'a, 'b> {
+    fn new(resolver: &'a ConstantResolver<'b>, instructions: &'a [Instruction]) -> Self {
+        let mut offsets = Vec::with_capacity(instructions.len());
+        let mut offset = 0;
+
+        for instruction in instructions {
+            offsets.push(offset);
+            offset += instruction.size_at(offset);
+        }
+
+        CodeReader {
+            resolver,
+            instructions,
+            offsets,
+            code_length: offset,
+            labels: HashMap::new(),
+        }
+    }
+
+    fn read(
+        mut self,
+        initial: Frame,
+        exception_table: &[bc::ExceptionHandler],
+        attributes: &[Attribute],
+    ) -> Result<Code, ModelError> {
+        let mut code = Code::new();
+
+        let stack_map: &[StackMapFrame] = attributes
+            .iter()
+            .filter_map(|attr| match *attr {
+                Attribute::StackMapTable(ref frames) => Some(frames.as_slice()),
+                _ => None,
+            })
+            .next()
+            .unwrap_or(&[]);
+
+        // Labels are allocated in offset order so that reading the same code twice results in
+        // the same model
+        let targets = self.collect_targets(exception_table, attributes, stack_map)?;
+
+        for offset in targets {
+            if offset != self.code_length && self.offsets.binary_search(&offset).is_err() {
+                return Err(ModelError::InvalidOffset(offset));
+            }
+
+            self.labels.insert(offset, code.new_label());
+        }
+
+        let frames = self.read_frames(initial, stack_map)?;
+
+        let mut lines: BTreeMap<usize, Vec<u16>> = BTreeMap::new();
+
+        for attribute in attributes {
+            match *attribute {
+                Attribute::LineNumberTable(ref table) => {
+                    for entry in table {
+                        if self.offsets.binary_search(&(entry.start_pc as usize)).is_err() {
+                            return Err(ModelError::InvalidOffset(entry.start_pc as usize));
+                        }
+
+                        lines
+                            .entry(entry.start_pc as usize)
+                            .or_insert(vec![])
+                            .push(entry.line_number);
+                    }
+                }
+                Attribute::LocalVariableTable(_)
+                | Attribute::LocalVariableTypeTable(_)
+                | Attribute::StackMapTable(_) => (),
+                ref other => code.attributes.push(other.clone()),
+            }
+        }
+
+        for (idx, instruction) in self.instructions.iter().enumerate() {
+            let offset = self.offsets[idx];
+
+            if let Some(label) = self.labels.get(&offset) {
+                code.instructions.push(Insn::Label(*label));
+            }
+
+            if let Some(line_numbers) = lines.get(&offset) {
+                for line in line_numbers {
+                    code.instructions.push(Insn::LineNumber(*line));
+                }
+            }
+
+            if let Some(frame) = frames.get(&offset) {
+                code.instructions.push(Insn::Frame(frame.clone()));
+            }
+
+            code.instructions.push(self.read_instruction(instruction, offset)?);
+        }
+
+        if let Some(label) = self.labels.get(&self.code_length) {
+            code.instructions.push(Insn::Label(*label));
+        }
+
+        for handler in exception_table {
+            code.try_catch_blocks.push(TryCatchBlock {
+                start: self.label(handler.start_pc as i64)?,
+                end: self.label(handler.end_pc as i64)?,
+                handler: self.label(handler.handler_pc as i64)?,
+                catch_type: self.resolver.optional_class_name(handler.catch_type.idx)?,
+            });
+        }
+
+        code.local_variables = self.read_local_variables(attributes)?;
+
+        Ok(code)
+    }
+
+    /// Collect every code offset that has to be represented by a label
+    fn collect_targets(
+        &self,
+        exception_table: &[bc::ExceptionHandler],
+        attributes: &[Attribute],
+        stack_map: &[StackMapFrame],
+    ) -> Result<BTreeSet<usize>, ModelError> {
+        let mut targets: BTreeSet<i64> = BTreeSet::new();
+
+        for (idx, instruction) in self.instructions.iter().enumerate() {
+            let offset = self.offsets[idx] as i64;
+
+            match *instruction {
+                Instruction::TABLESWITCH(default, _, _, ref table) => {
+                    targets.insert(offset + default as i64);
+                    targets.extend(table.iter().map(|t| offset + *t as i64));
+                }
+                Instruction::LOOKUPSWITCH(default, ref pairs) => {
+                    targets.insert(offset + default as i64);
+                    targets.extend(pairs.iter().map(|&(_, t)| offset + t as i64));
+                }
+                ref other => {
+                    if let Some((_, relative)) = Jump::from_instruction(other) {
+                        targets.insert(offset + relative as i64);
+                    }
+                }
+            }
+        }
+
+        for handler in exception_table {
+            targets.insert(handler.start_pc as i64);
+            targets.insert(handler.end_pc as i64);
+            targets.insert(handler.handler_pc as i64);
+        }
+
+        for attribute in attributes {
+            match *attribute {
+                Attribute::LocalVariableTable(ref table) => {
+                    for entry in table {
+                        targets.insert(entry.start_pc as i64);
+                        targets.insert(entry.start_pc as i64 + entry.length as i64);
+                    }
+                }
+                Attribute::LocalVariableTypeTable(ref table) => {
+                    for entry in table {
+                        targets.insert(entry.start_pc as i64);
+                        targets.insert(entry.start_pc as i64 + entry.length as i64);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut frame_offset: i64 = -1;
+
+        for frame in stack_map {
+            frame_offset += frame_offset_delta(frame)? as i64 + 1;
+            targets.insert(frame_offset);
+
+            for item in frame_items(frame) {
+                if let &VerificationType::Uninitialized { offset } = item {
+                    targets.insert(offset as i64);
+                }
+            }
+        }
+
+        targets
+            .into_iter()
+            .map(|offset| {
+                if offset < 0 || offset > self.code_length as i64 {
+                    Err(ModelError::InvalidOffset(offset.max(0) as usize))
+                } else {
+                    Ok(offset as usize)
+                }
+            })
+            .collect()
+    }
+
+    fn label(&self, offset: i64) -> Result<Label, ModelError> {
+        if offset < 0 {
+            return Err(ModelError::InvalidOffset(0));
+        }
+
+        self.labels
+            .get(&(offset as usize))
+            .cloned()
+            .ok_or(ModelError::InvalidOffset(offset as usize))
+    }
+
+    /// Expand the compressed stack map table into full frames keyed by code offset
+    fn read_frames(
+        &self,
+        initial: Frame,
+        stack_map: &[StackMapFrame],
+    ) -> Result<BTreeMap<usize, Frame>, ModelError> {
+        let mut frames = BTreeMap::new();
+        let mut locals = initial.locals;
+        let mut offset: i64 = -1;
+
+        for frame in stack_map {
+            offset += frame_offset_delta(frame)? as i64 + 1;
+
+            let stack = match *frame {
+                StackMapFrame::SameFrame { .. } | StackMapFrame::SameFrameExtended { .. } => {
+                    vec![]
+                }
+                StackMapFrame::SameLocals1StackItemFrame { ref stack, .. }
+                | StackMapFrame::SameLocals1StackItemFrameExtended { ref stack, .. } => {
+                    vec![self.frame_item(stack)?]
+                }
+                StackMapFrame::ChopFrame { tag, .. } => {
+                    let chopped = (251 - tag) as usize;
+
+                    if chopped > locals.len() {
+                        return Err(ModelError::InvalidFrame(offset as usize));
+                    }
+
+                    let remaining = locals.len() - chopped;
+                    locals.truncate(remaining);
+                    vec![]
+                }
+                StackMapFrame::AppendFrame {
+                    locals: ref appended,
+                    ..
+                } => {
+                    for item in appended {
+                        locals.push(self.frame_item(item)?);
+                    }
+                    vec![]
+                }
+                StackMapFrame::FullFrame {
+                    locals: ref full_locals,
+                    ref stack,
+                    ..
+                } => {
+                    locals = full_locals
+                        .iter()
+                        .map(|item| self.frame_item(item))
+                        .collect::<Result<Vec<FrameItem>, ModelError>>()?;
+
+                    stack
+                        .iter()
+                        .map(|item| self.frame_item(item))
+                        .collect::<Result<Vec<FrameItem>, ModelError>>()?
+                }
+                StackMapFrame::FutureUse { .. } => {
+                    return Err(ModelError::InvalidFrame(offset as usize))
+                }
+            };
+
+            frames.insert(offset as usize, Frame::new(locals.clone(), stack));
+        }
+
+        Ok(frames)
+    }
+
+    fn frame_item(&self, item: &VerificationType) -> Result<FrameItem, ModelError> {
+        Ok(match *item {
+            VerificationType::Top => FrameItem::Top,
+            VerificationType::Integer => FrameItem::Integer,
+            VerificationType::Float => FrameItem::Float,
+            VerificationType::Long => FrameItem::Long,
+            VerificationType::Double => FrameItem::Double,
+            VerificationType::Null => FrameItem::Null,
+            VerificationType::UninitializedThis => FrameItem::UninitializedThis,
+            VerificationType::Object { ref cpool_index } => {
+                FrameItem::Object(self.resolver.class_name(cpool_index.idx)?)
+            }
+            VerificationType::Uninitialized { offset } => {
+                FrameItem::Uninitialized(self.label(offset as i64)?)
+            }
+        })
+    }
+
+    fn read_local_variables(
+        &self,
+        attributes: &[Attribute],
+    ) -> Result<Vec<LocalVariable>, ModelError> {
+        let mut variables = vec![];
+
+        for attribute in attributes {
+            if let Attribute::LocalVariableTable(table) = attribute {
+                for entry in table {
+                    variables.push(LocalVariable {
+                        name: self.resolver.utf8(entry.name_index.idx)?,
+                        descriptor: self.resolver.utf8(entry.descriptor_index.idx)?,
+                        signature: None,
+                        start: self.label(entry.start_pc as i64)?,
+                        end: self.label(entry.start_pc as i64 + entry.length as i64)?,
+                        index: entry.index,
+                    });
+                }
+            }
+        }
+
+        for attribute in attributes {
+            if let Attribute::LocalVariableTypeTable(table) = attribute {
+                for entry in table {
+                    let start = self.label(entry.start_pc as i64)?;
+                    let end = self.label(entry.start_pc as i64 + entry.length as i64)?;
+                    let name = self.resolver.utf8(entry.name_index.idx)?;
+
+                    if let Some(variable) = variables.iter_mut().find(|v| {
+                        v.start == start && v.end == end && v.index == entry.index && v.name == name
+                    }) {
+                        variable.signature = Some(self.resolver.utf8(entry.signature_index.idx)?);
+                    }
+                }
+            }
+        }
+
+        Ok(variables)
+    }
+
+    fn read_instruction(&self, instruction: &Instruction, offset: usize) -> Result<Insn, ModelError> {
+        let resolver = self.resolver;
+        let at = |relative: i32| self.label(offset as i64 + relative as i64);
+
+        Ok(match *instruction {
+            Instruction::TABLESWITCH(default, low, high, ref table) => Insn::TableSwitch {
+                default: at(default)?,
+                low,
+                high,
+                targets: table
+                    .iter()
+                    .map(|t| at(*t))
+                    .collect::<Result<Vec<Label>, ModelError>>()?,
+            },
+            Instruction::LOOKUPSWITCH(default, ref pairs) => Insn::LookupSwitch {
+                default: at(default)?,
+                pairs: pairs
+                    .iter()
+                    .map(|&(key, t)| at(t).map(|label| (key, label)))
+                    .collect::<Result<Vec<(i32, Label)>, ModelError>>()?,
+            },
+            Instruction::LDC(idx) => Insn::Ldc(resolver.value(idx as usize)?),
+            Instruction::LDC_W(idx) | Instruction::LDC2_W(idx) => {
+                Insn::Ldc(resolver.value(idx as usize)?)
+            }
+            Instruction::GETSTATIC(idx) => {
+                Insn::Field(FieldOp::GetStatic, resolver.member_ref(idx as usize)?)
+            }
+            Instruction::PUTSTATIC(idx) => {
+                Insn::Field(FieldOp::PutStatic, resolver.member_ref(idx as usize)?)
+            }
+            Instruction::GETFIELD(idx) => {
+                Insn::Field(FieldOp::GetField, resolver.member_ref(idx as usize)?)
+            }
+            Instruction::PUTFIELD(idx) => {
+                Insn::Field(FieldOp::PutField, resolver.member_ref(idx as usize)?)
+            }
+            Instruction::INVOKEVIRTUAL(idx) => {
+                Insn::Invoke(Invoke::Virtual, resolver.member_ref(idx as usize)?)
+            }
+            Instruction::INVOKESPECIAL(idx) => {
+                Insn::Invoke(Invoke::Special, resolver.member_ref(idx as usize)?)
+            }
+            Instruction::INVOKESTATIC(idx) => {
+                Insn::Invoke(Invoke::Static, resolver.member_ref(idx as usize)?)
+            }
+            Instruction::INVOKEINTERFACE(idx, _) => {
+                Insn::Invoke(Invoke::Interface, resolver.member_ref(idx as usize)?)
+            }
+            Instruction::INVOKEDYNAMIC(idx) => Insn::InvokeDynamic(resolver.call_site(idx as usize)?),
+            Instruction::NEW(idx) => Insn::Type(TypeOp::New, resolver.class_name(idx as usize)?),
+            Instruction::ANEWARRAY(idx) => {
+                Insn::Type(TypeOp::ANewArray, resolver.class_name(idx as usize)?)
+            }
+            Instruction::CHECKCAST(idx) => {
+                Insn::Type(TypeOp::CheckCast, resolver.class_name(idx as usize)?)
+            }
+            Instruction::INSTANCEOF(idx) => {
+                Insn::Type(TypeOp::InstanceOf, resolver.class_name(idx as usize)?)
+            }
+            Instruction::MULTIANEWARRAY(idx, dimensions) => {
+                Insn::MultiANewArray(resolver.class_name(idx as usize)?, dimensions)
+            }
+            Instruction::PADDED_INSTRUCTION(_) | Instruction::WTF(_) => {
+                return Err(ModelError::InvalidInstruction(instruction.clone()))
+            }
+            ref other => match Jump::from_instruction(other) {
+                Some((jump, relative)) => Insn::Jump(jump, at(relative)?),
+                None => Insn::Op(other.clone()),
+            },
+        })
+    }
+}
+
+fn frame_offset_delta(frame: &StackMapFrame) -> Result<u16, ModelError> {
+    match *frame {
+        StackMapFrame::SameFrame { tag } => Ok(tag as u16),
+        StackMapFrame::SameLocals1StackItemFrame { tag, .. } => Ok(tag as u16 - 64),
+        StackMapFrame::SameLocals1StackItemFrameExtended { offset_delta, .. }
+        | StackMapFrame::ChopFrame { offset_delta, .. }
+        | StackMapFrame::SameFrameExtended { offset_delta }
+        | StackMapFrame::AppendFrame { offset_delta, .. }
+        | StackMapFrame::FullFrame { offset_delta, .. } => Ok(offset_delta),
+        StackMapFrame::FutureUse { tag } => Err(ModelError::InvalidFrame(tag as usize)),
+    }
+}
+
+fn frame_items(frame: &StackMapFrame) -> Vec<&VerificationType> {
+    match *frame {
+        StackMapFrame::SameLocals1StackItemFrame { ref stack, .. }
+        | StackMapFrame::SameLocals1StackItemFrameExtended { ref stack, .. } => vec![stack],
+        StackMapFrame::AppendFrame { ref locals, .. } => locals.iter().collect(),
+        StackMapFrame::FullFrame {
+            ref locals,
+            ref stack,
+            ..
+        } => locals.iter().chain(stack.iter()).collect(),
+        _ => vec![],
+    }
+}
@@ -0,0 +1,541 @@
+//!
+//! Replacement of method bodies at load time, eg. to inject faults in a staging environment by
+//! making `PaymentClient.charge` throw or return a canned value.
+//!
+//! A body can come from a method of a donor class, which is usually read from a class file
+//! compiled for the purpose, from an assembled `Method`, or from one of the `Stub`s that return
+//! a constant, throw an exception or delay the original body.
+//!
+//! ```ignore
+//! let donor = JavaClass::from_classfile(&ClassReader::read_class(&mut File::open("Faults.class")?)?)?;
+//! let replacer = MethodReplacer::from_config(&config)?
+//!     .donor(Pointcut::parse("execution(com.acme.PaymentClient.charge)")?, donor, "charge");
+//!
+//! static_context().add_transformer("replacement", 0, replacer);
+//! agent.use_transformer_chain();
+//! ```
+//!
+//! The donor code is moved into the target class, so its references to the donor class are
+//! changed to refer to the target class. The members of the donor class it uses must be
+//! declared by the target class as well, and the donor method must have the same descriptor and
+//! be static if and only if the target method is. Constructors and static initialisers can't be
+//! replaced.
+
+use super::super::config::{Config, StubAction, StubConfig};
+use super::analysis;
+use super::asm::MethodBuilder;
+use super::chain::{ClassTransformer, TransformError};
+use super::code::{CallSite, Code, Frame, FrameItem, Handle, Insn, Invoke, MemberRef, Value};
+use super::pointcut::{ClassInfo, MethodInfo, Pointcut};
+use super::timing::ProbeError;
+use super::trace::HELPER_PACKAGE;
+use super::{JavaClass, JavaType, Method, ModelError};
+use std::fmt;
+
+///
+/// Describes why a method body couldn't be replaced
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplaceError {
+    /// The donor class doesn't declare a method of the given name
+    MethodNotFound(String),
+    /// The donor method doesn't match the target method, given as name and descriptor, in its
+    /// descriptor or in being static
+    Incompatible(String, String),
+    /// The donor method is abstract or native
+    NoCode(String),
+    /// Constructors and static initialisers can't be replaced
+    Unsupported(String),
+    /// The donor code uses a member of the donor class the target class doesn't declare
+    MissingMember(MemberRef),
+    /// The stub constant, given first, can't be returned as the return type, given second
+    InvalidConstant(String, String),
+    Model(ModelError),
+}
+
+impl fmt::Display for ReplaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplaceError::MethodNotFound(ref name) => write!(f, "The donor class has no method {}", name),
+            ReplaceError::Incompatible(ref name, ref descriptor) => {
+                write!(f, "The donor method isn't compatible with {}{}", name, descriptor)
+            }
+            ReplaceError::NoCode(ref name) => write!(f, "The donor method {} has no code", name),
+            ReplaceError::Unsupported(ref name) => write!(f, "The body of {} can't be replaced", name),
+            ReplaceError::MissingMember(ref member) => write!(
+                f,
+                "The target class doesn't declare {}{}, which the donor code uses",
+                member.name, member.descriptor
+            ),
+            ReplaceError::InvalidConstant(ref value, ref return_type) => {
+                write!(f, "{} can't be returned as {}", value, return_type)
+            }
+            ReplaceError::Model(ref err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
+impl From<ModelError> for ReplaceError {
+    fn from(err: ModelError) -> ReplaceError {
+        ReplaceError::Model(err)
+    }
+}
+
+impl From<ReplaceError> for TransformError {
+    fn from(err: ReplaceError) -> TransformError {
+        match err {
+            ReplaceError::Model(err) => TransformError::Model(err),
+            other => TransformError::Failed(other.to_string()),
+        }
+    }
+}
+
+///
+/// A generated method body
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stub {
+    /// Return the constant, which is parsed according to the return type of the method: `true`
+    /// or `false`, a number, a single character for `char`, a string for methods returning a
+    /// `String`, `CharSequence` or `Object`, or `null` for any reference type. Void methods
+    /// return right away.
+    Return(String),
+    /// Throw a new instance of the exception class, given by its internal name, created with the
+    /// message if there is one
+    Throw {
+        exception: String,
+        message: Option<String>,
+    },
+    /// Run the original body after waiting for the given number of milliseconds. The wait uses
+    /// `LockSupport.parkNanos`, so it ends early if the thread is unparked or interrupted.
+    Delay(u64),
+}
+
+impl Stub {
+    /// The stub described by an entry of the `[[stubs]]` list
+    pub fn from_config(config: &StubConfig) -> Result<Stub, ProbeError> {
+        let missing = |field: &str| ProbeError::InvalidStub(config.pointcut.clone(), format!("missing {}", field));
+
+        match config.action {
+            StubAction::Return => Ok(Stub::Return(config.value.clone().unwrap_or_default())),
+            StubAction::Throw => match config.exception {
+                Some(ref exception) => Ok(Stub::Throw {
+                    exception: exception.replace('.', "/"),
+                    message: config.message.clone(),
+                }),
+                None => Err(missing("exception")),
+            },
+            StubAction::Delay => config.millis.map(Stub::Delay).ok_or_else(|| missing("millis")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Replacement {
+    /// The method of the given name declared by the donor class
+    Donor(JavaClass, String),
+    /// An assembled method
+    Method(Method),
+    Stub(Stub),
+}
+
+///
+/// A `ClassTransformer` that replaces the bodies of the methods selected by its pointcuts. If
+/// more than one pointcut selects a method, the replacement added first is used. Abstract and
+/// native methods are skipped.
+pub struct MethodReplacer {
+    replacements: Vec<(Pointcut, Replacement)>,
+}
+
+impl MethodReplacer {
+    pub fn new() -> MethodReplacer {
+        MethodReplacer { replacements: vec![] }
+    }
+
+    /// Use the stubs listed in the `[[stubs]]` section of the configuration
+    pub fn from_config(config: &Config) -> Result<MethodReplacer, ProbeError> {
+        let mut replacer = MethodReplacer::new();
+
+        for stub in config.stubs.iter() {
+            let pointcut = config
+                .pointcut(&stub.pointcut)
+                .cloned()
+                .ok_or_else(|| ProbeError::UnknownPointcut(stub.pointcut.clone()))?;
+
+            replacer = replacer.stub(pointcut, Stub::from_config(stub)?);
+        }
+
+        Ok(replacer)
+    }
+
+    /// Replace the selected methods with the body of the donor method of the given name that has
+    /// the same descriptor
+    pub fn donor(mut self, pointcut: Pointcut, class: JavaClass, method_name: &str) -> Self {
+        self.replacements.push((pointcut, Replacement::Donor(class, method_name.to_string())));
+        self
+    }
+
+    /// Replace the selected methods with the body of an assembled method, eg. one built with a
+    /// `MethodBuilder`. The code must not depend on the class it was assembled for.
+    pub fn method(mut self, pointcut: Pointcut, method: Method) -> Self {
+        self.replacements.push((pointcut, Replacement::Method(method)));
+        self
+    }
+
+    pub fn stub(mut self, pointcut: Pointcut, stub: Stub) -> Self {
+        self.replacements.push((pointcut, Replacement::Stub(stub)));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.replacements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replacements.is_empty()
+    }
+}
+
+impl Default for MethodReplacer {
+    fn default() -> Self {
+        MethodReplacer::new()
+    }
+}
+
+impl ClassTransformer for MethodReplacer {
+    fn accepts(&self, class_name: &str) -> bool {
+        let class = ClassInfo::new(class_name);
+
+        !class_name.starts_with(HELPER_PACKAGE) && self.replacements.iter().any(|(p, _)| p.matches_class(&class))
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        let class_info = ClassInfo::from_class(class);
+        let mut selected = vec![];
+
+        for (index, method) in class.methods.iter().enumerate() {
+            if method.code.is_none() || method.name == "<init>" || method.name == "<clinit>" {
+                continue;
+            }
+
+            let method_info = MethodInfo::from_method(class, method);
+
+            if let Some((_, replacement)) = self
+                .replacements
+                .iter()
+                .find(|&(p, _)| p.matches_method(&class_info, &method_info))
+            {
+                selected.push((index, replacement));
+            }
+        }
+
+        for &(index, replacement) in selected.iter() {
+            match *replacement {
+                Replacement::Donor(ref donor, ref name) => {
+                    let descriptor = class.methods[index].descriptor.descriptor();
+                    let method = donor_method(donor, name, &descriptor)?;
+
+                    replace_body(class, index, donor, method)?
+                }
+                Replacement::Method(ref method) => replace_with(class, index, method)?,
+                Replacement::Stub(ref stub) => apply_stub(class, index, stub)?,
+            }
+        }
+
+        Ok(!selected.is_empty())
+    }
+}
+
+/// The method of the donor class with the given name and descriptor
+fn donor_method<'a>(donor: &'a JavaClass, name: &str, descriptor: &str) -> Result<&'a Method, ReplaceError> {
+    if let Some(method) = donor.method(name, descriptor) {
+        return Ok(method);
+    }
+
+    if donor.methods.iter().any(|m| m.name == name) {
+        Err(ReplaceError::Incompatible(name.to_string(), descriptor.to_string()))
+    } else {
+        Err(ReplaceError::MethodNotFound(name.to_string()))
+    }
+}
+
+///
+/// Replace the body of a method of the class with the body of a method of the donor class. The
+/// references of the donor code to the donor class are changed to refer to the target class.
+pub fn replace_body(class: &mut JavaClass, method_index: usize, donor: &JavaClass, donor_method: &Method) -> Result<(), ReplaceError> {
+    check_compatible(&class.methods[method_index], donor_method)?;
+
+    let mut code = match donor_method.code {
+        Some(ref code) => code.clone(),
+        None => return Err(ReplaceError::NoCode(donor_method.name.clone())),
+    };
+
+    rename_class(&mut code, &donor.name, &class.name);
+    check_members(&code, donor, class)?;
+
+    // Without frames of the donor, they have to be computed for the target
+    let has_frames = donor.version.major_version >= 50;
+    install_code(class, method_index, code, has_frames)
+}
+
+///
+/// Replace the body of a method of the class with the body of an assembled method
+pub fn replace_with(class: &mut JavaClass, method_index: usize, method: &Method) -> Result<(), ReplaceError> {
+    check_compatible(&class.methods[method_index], method)?;
+
+    match method.code {
+        Some(ref code) => {
+            let has_frames = code.instructions.iter().any(|insn| matches!(*insn, Insn::Frame(_)));
+
+            install_code(class, method_index, code.clone(), has_frames)
+        }
+        None => Err(ReplaceError::NoCode(method.name.clone())),
+    }
+}
+
+///
+/// Replace the body of a method of the class with a stub, or prepend the delay of a `Delay`
+pub fn apply_stub(class: &mut JavaClass, method_index: usize, stub: &Stub) -> Result<(), ReplaceError> {
+    let class_name = class.name.clone();
+    let method = &mut class.methods[method_index];
+
+    if method.name == "<init>" || method.name == "<clinit>" {
+        return Err(ReplaceError::Unsupported(method.name.clone()));
+    }
+
+    let code = match *stub {
+        Stub::Delay(millis) => {
+            if let Some(code) = method.code.as_mut() {
+                let mut instructions = vec![
+                    Insn::Ldc(Value::Long((millis as i64).saturating_mul(1_000_000))),
+                    Insn::Invoke(
+                        Invoke::Static,
+                        MemberRef::new("java/util/concurrent/locks/LockSupport", "parkNanos", "(J)V"),
+                    ),
+                ];
+                instructions.append(&mut code.instructions);
+                code.instructions = instructions;
+            }
+
+            return Ok(analysis::compute_maxs(&class_name, method)?);
+        }
+        Stub::Return(ref value) => {
+            let builder = MethodBuilder::new(&method.name, method.descriptor.clone());
+            push_constant(builder, &method.descriptor.return_type, value)?.ret().build()?.code
+        }
+        Stub::Throw { ref exception, ref message } => {
+            let builder = MethodBuilder::new(&method.name, method.descriptor.clone()).new_(exception).dup();
+
+            let builder = match *message {
+                Some(ref message) => builder
+                    .ldc_string(message)
+                    .invokespecial(exception, "<init>", "(Ljava/lang/String;)V"),
+                None => builder.invokespecial(exception, "<init>", "()V"),
+            };
+
+            builder.athrow().build()?.code
+        }
+    };
+
+    method.code = code;
+    analysis::compute_maxs(&class_name, method)?;
+
+    Ok(())
+}
+
+/// Push the constant as a value of the given type
+fn push_constant(builder: MethodBuilder, return_type: &JavaType, value: &str) -> Result<MethodBuilder, ReplaceError> {
+    let invalid = || ReplaceError::InvalidConstant(value.to_string(), return_type.descriptor());
+    let text = value.trim();
+
+    match *return_type {
+        JavaType::Void => Ok(builder),
+        JavaType::Boolean => match text {
+            "true" => Ok(builder.iconst(1)),
+            "false" => Ok(builder.iconst(0)),
+            _ => Err(invalid()),
+        },
+        JavaType::Char => match text.parse::<u16>() {
+            Ok(number) => Ok(builder.iconst(number as i32)),
+            Err(_) if text.chars().count() == 1 => match text.chars().next().map(|c| c as u32) {
+                Some(c) if c <= 0xffff => Ok(builder.iconst(c as i32)),
+                _ => Err(invalid()),
+            },
+            Err(_) => Err(invalid()),
+        },
+        JavaType::Byte => text.parse::<i8>().map(|v| builder.iconst(v as i32)).map_err(|_| invalid()),
+        JavaType::Short => text.parse::<i16>().map(|v| builder.iconst(v as i32)).map_err(|_| invalid()),
+        JavaType::Int => text.parse::<i32>().map(|v| builder.iconst(v)).map_err(|_| invalid()),
+        JavaType::Long => text.parse::<i64>().map(|v| builder.lconst(v)).map_err(|_| invalid()),
+        JavaType::Float => text.parse::<f32>().map(|v| builder.fconst(v)).map_err(|_| invalid()),
+        JavaType::Double => text.parse::<f64>().map(|v| builder.dconst(v)).map_err(|_| invalid()),
+        _ if text == "null" => Ok(builder.aconst_null()),
+        JavaType::Class(ref name)
+            if name == "java/lang/String" || name == "java/lang/CharSequence" || name == "java/lang/Object" =>
+        {
+            Ok(builder.ldc_string(value))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn check_compatible(target: &Method, donor: &Method) -> Result<(), ReplaceError> {
+    if target.name == "<init>" || target.name == "<clinit>" {
+        return Err(ReplaceError::Unsupported(target.name.clone()));
+    }
+
+    if target.descriptor != donor.descriptor || target.is_static() != donor.is_static() {
+        return Err(ReplaceError::Incompatible(target.name.clone(), target.descriptor.descriptor()));
+    }
+
+    Ok(())
+}
+
+/// Make sure that the members of the donor class the renamed code uses are declared by the
+/// target class
+fn check_members(code: &Code, donor: &JavaClass, target: &JavaClass) -> Result<(), ReplaceError> {
+    let renamed = |descriptor: String| rename_descriptor(&descriptor, &donor.name, &target.name);
+
+    let donor_members: Vec<(&str, String)> = donor
+        .fields
+        .iter()
+        .map(|f| (f.name.as_str(), renamed(f.field_type.descriptor())))
+        .chain(donor.methods.iter().map(|m| (m.name.as_str(), renamed(m.descriptor.descriptor()))))
+        .collect();
+
+    let target_members: Vec<(&str, String)> = target
+        .fields
+        .iter()
+        .map(|f| (f.name.as_str(), f.field_type.descriptor()))
+        .chain(target.methods.iter().map(|m| (m.name.as_str(), m.descriptor.descriptor())))
+        .collect();
+
+    for member in code.instructions.iter().flat_map(members) {
+        let key = (member.name.as_str(), member.descriptor.clone());
+
+        if member.owner == target.name && donor_members.contains(&key) && !target_members.contains(&key) {
+            return Err(ReplaceError::MissingMember(member.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// The fields and methods an instruction refers to
+fn members(insn: &Insn) -> Vec<&MemberRef> {
+    match *insn {
+        Insn::Field(_, ref member) | Insn::Invoke(_, ref member) => vec![member],
+        Insn::Ldc(Value::MethodHandle(ref handle)) => vec![&handle.member],
+        Insn::InvokeDynamic(ref call_site) => {
+            let mut members = vec![&call_site.bootstrap.member];
+
+            for argument in call_site.arguments.iter() {
+                if let Value::MethodHandle(handle) = argument {
+                    members.push(&handle.member);
+                }
+            }
+
+            members
+        }
+        _ => vec![],
+    }
+}
+
+/// Install the code as the body of the method, computing frames if the code has none but the
+/// class needs them
+fn install_code(class: &mut JavaClass, method_index: usize, mut code: Code, has_frames: bool) -> Result<(), ReplaceError> {
+    let class_name = class.name.clone();
+    let needs_frames = class.version.major_version >= 50;
+    let method = &mut class.methods[method_index];
+
+    // Constant pool references of unknown attributes refer to the pool of the donor
+    code.attributes.clear();
+
+    if !needs_frames {
+        code.instructions.retain(|insn| !matches!(*insn, Insn::Frame(_)));
+    }
+
+    method.code = Some(code);
+
+    if needs_frames && !has_frames {
+        analysis::compute_frames(&class_name, method)?;
+    } else {
+        analysis::compute_maxs(&class_name, method)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Change the references of the code to the class `from` into references to the class `to`
+pub fn rename_class(code: &mut Code, from: &str, to: &str) {
+    for insn in code.instructions.iter_mut() {
+        match *insn {
+            Insn::Field(_, ref mut member) | Insn::Invoke(_, ref mut member) => rename_member(member, from, to),
+            Insn::Type(_, ref mut name) => rename_type(name, from, to),
+            Insn::MultiANewArray(ref mut descriptor, _) => *descriptor = rename_descriptor(descriptor, from, to),
+            Insn::Ldc(ref mut value) => rename_value(value, from, to),
+            Insn::InvokeDynamic(ref mut call_site) => rename_call_site(call_site, from, to),
+            Insn::Frame(ref mut frame) => rename_frame(frame, from, to),
+            _ => (),
+        }
+    }
+
+    for block in code.try_catch_blocks.iter_mut() {
+        if let Some(ref mut catch_type) = block.catch_type {
+            rename_type(catch_type, from, to);
+        }
+    }
+
+    for variable in code.local_variables.iter_mut() {
+        variable.descriptor = rename_descriptor(&variable.descriptor, from, to);
+        variable.signature = variable.signature.as_ref().map(|s| rename_descriptor(s, from, to));
+    }
+}
+
+fn rename_member(member: &mut MemberRef, from: &str, to: &str) {
+    rename_type(&mut member.owner, from, to);
+    member.descriptor = rename_descriptor(&member.descriptor, from, to);
+}
+
+/// Rename an internal name or array descriptor
+fn rename_type(name: &mut String, from: &str, to: &str) {
+    if name == from {
+        *name = to.to_string();
+    } else if name.starts_with('[') {
+        *name = rename_descriptor(name, from, to);
+    }
+}
+
+fn rename_descriptor(descriptor: &str, from: &str, to: &str) -> String {
+    descriptor.replace(&format!("L{};", from), &format!("L{};", to))
+}
+
+fn rename_value(value: &mut Value, from: &str, to: &str) {
+    match *value {
+        Value::Class(ref mut name) => rename_type(name, from, to),
+        Value::MethodType(ref mut descriptor) => *descriptor = rename_descriptor(descriptor, from, to),
+        Value::MethodHandle(ref mut handle) => rename_handle(handle, from, to),
+        _ => (),
+    }
+}
+
+fn rename_handle(handle: &mut Handle, from: &str, to: &str) {
+    rename_member(&mut handle.member, from, to);
+}
+
+fn rename_call_site(call_site: &mut CallSite, from: &str, to: &str) {
+    call_site.descriptor = rename_descriptor(&call_site.descriptor, from, to);
+    rename_handle(&mut call_site.bootstrap, from, to);
+
+    for argument in call_site.arguments.iter_mut() {
+        rename_value(argument, from, to);
+    }
+}
+
+fn rename_frame(frame: &mut Frame, from: &str, to: &str) {
+    for item in frame.locals.iter_mut().chain(frame.stack.iter_mut()) {
+        if let FrameItem::Object(name) = item {
+            rename_type(name, from, to);
+        }
+    }
+}
+
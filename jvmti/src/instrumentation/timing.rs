@@ -0,0 +1,537 @@
+//!
+//! Method timing through woven probes instead of `MethodEntry`/`MethodExit` events, which keep
+//! every thread in interpreted mode while they are enabled.
+//!
+//! The selected methods call `System.nanoTime()` on entry and before every return or exception
+//! thrown out of the method, and pass the result to the native methods of a small helper class.
+//! These are bound to Rust functions that feed the same per-thread method timing of the
+//! `AgentContext` as `method_enter` and `method_exit`.
+//!
+//! The helper class can only be defined once the VM is initialised, so the weaving starts when
+//! `install` is called from the `VMInit` handler. Classes loaded before that are left as they are.
+//!
+//! ```ignore
+//! let probes = TimingProbes::from_config(&config, static_context().probes.clone())?;
+//! static_context().add_transformer("timing", 0, probes);
+//! agent.use_transformer_chain();
+//! agent.on_vm_init(Some(on_vm_init));
+//!
+//! fn on_vm_init(jvmti: JVMTIEnvPtr, jni: JNIEnvPtr, _thread: jthread) {
+//!     timing::install(jvmti, jni).unwrap();
+//! }
+//! ```
+//!
+//! Constructors aren't woven, because the exception handler of the exit probe can't cover the
+//! call of the super constructor.
+
+use super::super::bytecode::classfile::Instruction;
+use super::super::config::Config;
+use super::super::context::static_context;
+use super::super::environment::jni::{JNIEnvironment, JNI};
+use super::super::environment::jvmti::{JVMTIEnvironment, JVMTI};
+use super::super::native::jvmti_native::{jint, jlong, jvmtiEnv};
+use super::super::native::{JNIEnvPtr, JVMTIEnvPtr, JavaClass as JavaClassPtr};
+use super::super::thread::ThreadId;
+use super::analysis;
+use super::bridge::{BridgeError, NativeBridge};
+use super::chain::{ClassTransformer, TransformError};
+use super::code::{Code, Frame, FrameItem, Insn, Invoke, MemberRef, TryCatchBlock, Value};
+use super::dynamic::MethodWeaver;
+use super::pointcut::{ClassInfo, MethodInfo, Pointcut};
+use super::{JavaClass, ModelError};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use time::{at_utc, Timespec, Tm};
+
+/// Internal name of the generated helper class the probes call
+pub const HELPER_CLASS: &str = "jvmti/probe/Timing";
+/// `static native void enter(int probe, long nanoTime)`
+pub const ENTER_DESCRIPTOR: &str = "(IJ)V";
+/// `static native void exit(int probe, long nanoTime)`
+pub const EXIT_DESCRIPTOR: &str = "(IJ)V";
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+static HELPER_INSTALLED: AtomicBool = AtomicBool::new(false);
+static JVMTI_ENV: AtomicPtr<jvmtiEnv> = AtomicPtr::new(ptr::null_mut());
+
+///
+/// Describes why the timing probes couldn't be configured
+#[derive(Debug)]
+pub enum ProbeError {
+    /// The configuration refers to a pointcut that isn't defined
+    UnknownPointcut(String),
+    /// The configuration refers to a built-in pointcut pack that doesn't exist
+    UnknownPack(String),
+    /// A stub for the pointcut, given first, is missing a setting
+    InvalidStub(String, String),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeError::UnknownPointcut(name) => write!(f, "Unknown pointcut: {}", name),
+            ProbeError::UnknownPack(name) => write!(f, "Unknown pointcut pack: {}", name),
+            ProbeError::InvalidStub(name, reason) => write!(f, "Invalid stub for {}: {}", name, reason),
+        }
+    }
+}
+
+///
+/// A woven method
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProbeSite {
+    /// Internal name of the declaring class
+    pub class_name: String,
+    pub method_name: String,
+    pub descriptor: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProbeStats {
+    /// Number of completed invocations, including those that threw an exception
+    pub calls: u64,
+    pub total_time: Duration,
+    pub max_time: Duration,
+}
+
+///
+/// Assigns the numeric ids the woven code passes to the helper and aggregates the measured times
+/// of each site.
+pub struct ProbeRegistry {
+    sites: RwLock<Sites>,
+}
+
+/// The registered sites, indexed by their id, and the id of each site
+#[derive(Default)]
+struct Sites {
+    sites: Vec<(ProbeSite, Mutex<ProbeStats>)>,
+    ids: HashMap<ProbeSite, u32>,
+}
+
+impl ProbeRegistry {
+    pub fn new() -> ProbeRegistry {
+        ProbeRegistry {
+            sites: RwLock::new(Sites::default()),
+        }
+    }
+
+    /// Returns the id of the site, registering it first if it's new. Classes with the same name
+    /// loaded by different class loaders share their sites.
+    pub fn register(&self, site: ProbeSite) -> u32 {
+        let mut sites = self.sites.write().unwrap();
+
+        if let Some(&id) = sites.ids.get(&site) {
+            return id;
+        }
+
+        let id = sites.sites.len() as u32;
+        sites.ids.insert(site.clone(), id);
+        sites.sites.push((site, Mutex::new(ProbeStats::default())));
+        id
+    }
+
+    pub fn site(&self, id: u32) -> Option<ProbeSite> {
+        self.sites
+            .read()
+            .ok()
+            .and_then(|sites| sites.sites.get(id as usize).map(|(site, _)| site.clone()))
+    }
+
+    pub fn stats(&self, id: u32) -> Option<ProbeStats> {
+        self.sites.read().ok().and_then(|sites| {
+            sites
+                .sites
+                .get(id as usize)
+                .and_then(|(_, stats)| stats.lock().ok().map(|stats| stats.clone()))
+        })
+    }
+
+    /// Add a measured invocation of the site
+    pub fn record(&self, id: u32, elapsed: Duration) {
+        if let Ok(sites) = self.sites.read() {
+            if let Some((_, stats)) = sites.sites.get(id as usize) {
+                if let Ok(mut stats) = stats.lock() {
+                    stats.calls += 1;
+                    stats.total_time += elapsed;
+                    stats.max_time = stats.max_time.max(elapsed);
+                }
+            }
+        }
+    }
+
+    /// All registered sites with their current statistics, ordered by id
+    pub fn snapshot(&self) -> Vec<(ProbeSite, ProbeStats)> {
+        match self.sites.read() {
+            Ok(sites) => sites
+                .sites
+                .iter()
+                .map(|(site, stats)| (site.clone(), stats.lock().map(|s| s.clone()).unwrap_or_default()))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sites.read().map(|sites| sites.sites.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for ProbeRegistry {
+    fn default() -> Self {
+        ProbeRegistry::new()
+    }
+}
+
+///
+/// A `ClassTransformer` that weaves timing probes into the methods selected by any of its
+/// pointcuts. Abstract and native methods and constructors are skipped.
+pub struct TimingProbes {
+    pointcuts: Vec<Pointcut>,
+    registry: Arc<ProbeRegistry>,
+}
+
+impl TimingProbes {
+    pub fn new(pointcuts: Vec<Pointcut>, registry: Arc<ProbeRegistry>) -> TimingProbes {
+        TimingProbes {
+            pointcuts,
+            registry,
+        }
+    }
+
+    /// Weave the methods selected by the pointcuts listed in `timed`
+    pub fn from_config(config: &Config, registry: Arc<ProbeRegistry>) -> Result<TimingProbes, ProbeError> {
+        let pointcuts = config
+            .timed
+            .iter()
+            .map(|name| {
+                config
+                    .pointcut(name)
+                    .cloned()
+                    .ok_or_else(|| ProbeError::UnknownPointcut(name.clone()))
+            })
+            .collect::<Result<Vec<Pointcut>, ProbeError>>()?;
+
+        Ok(TimingProbes::new(pointcuts, registry))
+    }
+
+    fn selects(&self, class: &ClassInfo, method: &MethodInfo) -> bool {
+        self.pointcuts.iter().any(|p| p.matches_method(class, method))
+    }
+}
+
+impl ClassTransformer for TimingProbes {
+    fn accepts(&self, class_name: &str) -> bool {
+        let class = ClassInfo::new(class_name);
+
+        HELPER_INSTALLED.load(Ordering::SeqCst)
+            && class_name != HELPER_CLASS
+            && self.pointcuts.iter().any(|p| p.matches_class(&class))
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        let class_info = ClassInfo::from_class(class);
+        let mut selected = vec![];
+
+        for (index, method) in class.methods.iter().enumerate() {
+            if method.code.is_none() || method.name == "<init>" {
+                continue;
+            }
+
+            if self.selects(&class_info, &MethodInfo::from_method(class, method)) {
+                let id = self.registry.register(ProbeSite {
+                    class_name: class.name.clone(),
+                    method_name: method.name.clone(),
+                    descriptor: method.descriptor.descriptor(),
+                });
+
+                selected.push((index, id));
+            }
+        }
+
+        for &(index, id) in selected.iter() {
+            weave(class, index, id)?;
+        }
+
+        Ok(!selected.is_empty())
+    }
+}
+
+///
+/// A `MethodWeaver` for the `ProbeManager` that weaves timing probes into the selected methods.
+/// Constructors are skipped, and weaving fails until the helper class is installed.
+pub struct TimingWeaver {
+    registry: Arc<ProbeRegistry>,
+}
+
+impl TimingWeaver {
+    pub fn new(registry: Arc<ProbeRegistry>) -> TimingWeaver {
+        TimingWeaver { registry }
+    }
+}
+
+impl MethodWeaver for TimingWeaver {
+    fn weave(&self, class: &mut JavaClass, method_index: usize) -> Result<(), TransformError> {
+        if !is_installed() {
+            return Err(TransformError::Failed("the timing helper class isn't installed".to_string()));
+        }
+
+        if class.methods[method_index].name == "<init>" {
+            return Ok(());
+        }
+
+        let id = {
+            let method = &class.methods[method_index];
+
+            self.registry.register(ProbeSite {
+                class_name: class.name.clone(),
+                method_name: method.name.clone(),
+                descriptor: method.descriptor.descriptor(),
+            })
+        };
+
+        Ok(weave(class, method_index, id)?)
+    }
+}
+
+///
+/// Weave the entry and exit probes with the given id into a method of the class. The code
+/// doesn't need any new local variables, so the existing stack map frames remain valid, only the
+/// exception handler of the exit probe gets a new frame.
+pub fn weave(class: &mut JavaClass, method_index: usize, probe: u32) -> Result<(), ModelError> {
+    let class_name = class.name.clone();
+    let has_frames = class.version.major_version >= 50;
+    let method = &mut class.methods[method_index];
+
+    {
+        let code = match method.code.as_mut() {
+            Some(code) => code,
+            None => return Ok(()),
+        };
+
+        weave_exits(
+            code,
+            probe_call(probe, "enter", ENTER_DESCRIPTOR),
+            has_frames,
+            |insn| if is_return(insn) { Some(probe_call(probe, "exit", EXIT_DESCRIPTOR)) } else { None },
+            probe_call(probe, "exit", EXIT_DESCRIPTOR),
+        );
+    }
+
+    analysis::compute_maxs(&class_name, method)
+}
+
+///
+/// Append the code to `instructions`, which hold the entry probe, and insert the exit probe that
+/// `exit_probe` returns for an instruction right before it. A catch-all handler runs
+/// `handler_probe` and rethrows the exception. The handler only covers the code between the
+/// probes, as an exception thrown by an exit probe or by the return itself, eg. the
+/// `IllegalMonitorStateException` of a synchronized method, mustn't run an exit probe twice.
+pub(crate) fn weave_exits<F>(
+    code: &mut Code,
+    mut instructions: Vec<Insn>,
+    has_frames: bool,
+    exit_probe: F,
+    handler_probe: Vec<Insn>,
+) where
+    F: Fn(&Insn) -> Option<Vec<Insn>>,
+{
+    let mut ranges = vec![];
+    let handler = code.new_label();
+    let mut start = code.new_label();
+    // Whether the range since `start` holds any instructions, the class file has no empty ranges
+    let mut covered = false;
+
+    instructions.push(Insn::Label(start));
+
+    for insn in mem::take(&mut code.instructions) {
+        match exit_probe(&insn) {
+            Some(probe) => {
+                if covered {
+                    let end = code.new_label();
+                    instructions.push(Insn::Label(end));
+                    ranges.push((start, end));
+                }
+
+                instructions.extend(probe);
+                instructions.push(insn);
+
+                start = code.new_label();
+                instructions.push(Insn::Label(start));
+                covered = false;
+            }
+            None => {
+                covered |= insn.is_instruction();
+                instructions.push(insn);
+            }
+        }
+    }
+
+    if covered {
+        let end = code.new_label();
+        instructions.push(Insn::Label(end));
+        ranges.push((start, end));
+    }
+
+    if !ranges.is_empty() {
+        instructions.push(Insn::Label(handler));
+
+        if has_frames {
+            // None of the locals are used by the handler, so they can all be left unspecified
+            instructions.push(Insn::Frame(Frame::new(
+                vec![],
+                vec![FrameItem::Object("java/lang/Throwable".to_string())],
+            )));
+        }
+
+        instructions.extend(handler_probe);
+        instructions.push(Insn::Op(Instruction::ATHROW));
+    }
+
+    code.instructions = instructions;
+
+    // Appended last, so that the handlers of the method itself take precedence
+    for (start, end) in ranges {
+        code.try_catch_blocks.push(TryCatchBlock {
+            start,
+            end,
+            handler,
+            catch_type: None,
+        });
+    }
+}
+
+/// `helper(probe, System.nanoTime())`
+fn probe_call(probe: u32, helper: &str, descriptor: &str) -> Vec<Insn> {
+    vec![
+        push_int(probe as i32),
+        Insn::Invoke(Invoke::Static, MemberRef::new("java/lang/System", "nanoTime", "()J")),
+        Insn::Invoke(Invoke::Static, MemberRef::new(HELPER_CLASS, helper, descriptor)),
+    ]
+}
+
+pub(crate) fn push_int(value: i32) -> Insn {
+    match value {
+        0 => Insn::Op(Instruction::ICONST_0),
+        1 => Insn::Op(Instruction::ICONST_1),
+        2 => Insn::Op(Instruction::ICONST_2),
+        3 => Insn::Op(Instruction::ICONST_3),
+        4 => Insn::Op(Instruction::ICONST_4),
+        5 => Insn::Op(Instruction::ICONST_5),
+        -128..=127 => Insn::Op(Instruction::BIPUSH(value as i8 as u8)),
+        -32768..=32767 => Insn::Op(Instruction::SIPUSH(value as i16 as u16)),
+        _ => Insn::Ldc(Value::Int(value)),
+    }
+}
+
+fn is_return(insn: &Insn) -> bool {
+    matches!(
+        *insn,
+        Insn::Op(Instruction::IRETURN)
+            | Insn::Op(Instruction::LRETURN)
+            | Insn::Op(Instruction::FRETURN)
+            | Insn::Op(Instruction::DRETURN)
+            | Insn::Op(Instruction::ARETURN)
+            | Insn::Op(Instruction::RETURN)
+    )
+}
+
+///
+/// The bridge that defines the helper class and binds its `enter` and `exit` methods
+pub fn bridge() -> NativeBridge {
+    NativeBridge::new(HELPER_CLASS)
+        .method("enter", ENTER_DESCRIPTOR, probe_enter as *mut c_void)
+        .method("exit", EXIT_DESCRIPTOR, probe_exit as *mut c_void)
+}
+
+/// Generate the helper class with the native `enter` and `exit` methods
+pub fn helper_class() -> Result<JavaClass, ModelError> {
+    bridge().helper_class()
+}
+
+///
+/// Define the helper class in the bootstrap class loader, bind its native methods and start
+/// weaving the classes loaded from now on. Has to be called in the live phase, eg. from the
+/// `VMInit` handler.
+pub fn install(jvmti: JVMTIEnvPtr, jni: JNIEnvPtr) -> Result<(), BridgeError> {
+    if HELPER_INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    bridge().define(&JNIEnvironment::new(jni))?;
+
+    JVMTI_ENV.store(jvmti, Ordering::SeqCst);
+    HELPER_INSTALLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Returns true once the helper class is defined and classes are being woven
+pub fn is_installed() -> bool {
+    HELPER_INSTALLED.load(Ordering::SeqCst)
+}
+
+///
+/// Convert a `System.nanoTime()` value into a `Tm`. The origin of `nanoTime` is arbitrary, so the
+/// result is only meaningful for computing durations.
+pub fn nano_time(nanos: i64) -> Tm {
+    at_utc(Timespec::new(
+        nanos.div_euclid(NANOS_PER_SECOND),
+        nanos.rem_euclid(NANOS_PER_SECOND) as i32,
+    ))
+}
+
+thread_local! {
+    /// Weak global reference to the Java thread running on this native thread. Unlike the local
+    /// reference returned by `GetCurrentThread` it stays the same for every probe call, so the
+    /// per-thread timing can be keyed on it.
+    static CURRENT_THREAD: RefCell<Option<ThreadId>> = const { RefCell::new(None) };
+}
+
+fn current_thread(jni: JNIEnvPtr) -> Option<ThreadId> {
+    CURRENT_THREAD.with(|current| {
+        if current.borrow().is_none() {
+            let env = JVMTI_ENV.load(Ordering::SeqCst);
+
+            if env.is_null() {
+                return None;
+            }
+
+            let thread = JVMTIEnvironment::new(env).get_current_thread().ok()?;
+            let weak = JNIEnvironment::new(jni).new_weak_global_ref(&thread).ok()?;
+
+            *current.borrow_mut() = Some(ThreadId::new(weak));
+        }
+
+        current.borrow().clone()
+    })
+}
+
+extern "C" fn probe_enter(jni: JNIEnvPtr, _class: JavaClassPtr, _probe: jint, nanos: jlong) {
+    if let Some(thread) = current_thread(jni) {
+        static_context().method_enter_at(&thread, nano_time(nanos));
+    }
+}
+
+extern "C" fn probe_exit(jni: JNIEnvPtr, _class: JavaClassPtr, probe: jint, nanos: jlong) {
+    if let Some(thread) = current_thread(jni) {
+        let context = static_context();
+
+        if let Some(elapsed) = context.method_exit_at(&thread, nano_time(nanos)) {
+            if let Ok(elapsed) = elapsed.to_std() {
+                context.probes.record(probe as u32, elapsed);
+            }
+        }
+    }
+}
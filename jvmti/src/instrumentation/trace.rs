@@ -0,0 +1,670 @@
+//!
+//! Spans and trace context propagation across threads. Each thread has a current trace context,
+//! the innermost span started on it with `AgentContext::start_span`, and new spans become its
+//! children. Work handed over to another thread would start a new trace there, so
+//! `ContextPropagation` wraps the tasks passed to executors and `CompletableFuture` stages:
+//!
+//! * The call sites of `execute`, `submit`, `schedule`, `scheduleAtFixedRate` and
+//!   `scheduleWithFixedDelay` of the `java.util.concurrent` executors, and of every
+//!   `CompletableFuture` and `CompletionStage` method, get their `Runnable`, `Callable`,
+//!   `Supplier`, `Function`, `Consumer`, `BiFunction` and `BiConsumer` arguments wrapped.
+//! * The wrappers are generated classes, eg. `jvmti/probe/TracedRunnable`, that capture the
+//!   current context when they are created, attach it to the worker thread while the task runs
+//!   and detach it afterwards, even if the task throws.
+//!
+//! ```ignore
+//! let propagation = ContextPropagation::from_config(&config)?;
+//! static_context().add_transformer("propagation", 0, propagation);
+//! agent.use_transformer_chain();
+//! agent.on_vm_init(Some(on_vm_init));
+//!
+//! fn on_vm_init(_jvmti: JVMTIEnvPtr, jni: JNIEnvPtr, _thread: jthread) {
+//!     trace::install(jni).unwrap();
+//! }
+//! ```
+//!
+//! Only the call sites in the woven classes are wrapped, so tasks submitted by libraries
+//! outside of the pointcuts, or through the executors' own subclasses, don't carry the context.
+//! Tasks submitted without a current context are passed on unchanged. The context is held in
+//! native thread local storage, which matches Java threads as long as they are platform threads.
+
+use super::super::bytecode::classfile::Instruction;
+use super::super::bytecode::io::ClassWriter;
+use super::super::config::Config;
+use super::super::context::static_context;
+use super::super::environment::jni::{JNIEnvironment, JNI};
+use super::super::native::jvmti_native::jlong;
+use super::super::native::{JNIEnvPtr, JavaClass as JavaClassPtr, JavaObject};
+use super::analysis;
+use super::asm::{ClassBuilder, ClassfileVersion};
+use super::bridge::{BridgeError, NativeBridge};
+use super::capture::load;
+use super::chain::{ClassTransformer, TransformError};
+use super::code::{Insn, Invoke, MemberRef};
+use super::descriptor::{JavaType, MethodDescriptor};
+use super::pointcut::{ClassInfo, MethodInfo, Pointcut};
+use super::timing::ProbeError;
+use super::{JavaClass, ModelError};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use time::{now, Duration, Tm};
+
+/// Internal name of the generated helper class the wrappers call
+pub const HELPER_CLASS: &str = "jvmti/probe/Trace";
+/// Package of the helper and the wrapper classes, which are never woven
+pub const HELPER_PACKAGE: &str = "jvmti/probe/";
+/// `static native long trace()`, the trace id of the current context or 0
+pub const TRACE_DESCRIPTOR: &str = "()J";
+/// `static native long span()`, the span id of the current context or 0
+pub const SPAN_DESCRIPTOR: &str = "()J";
+/// `static native void attach(long trace, long span)`
+pub const ATTACH_DESCRIPTOR: &str = "(JJ)V";
+/// `static native void detach()`
+pub const DETACH_DESCRIPTOR: &str = "()V";
+
+/// Maximum number of finished spans kept, older ones are dropped first
+pub const MAX_SPANS: usize = 10000;
+
+static HELPER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+///
+/// A functional interface whose instances are wrapped when they are handed over to another
+/// thread
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskType {
+    /// Internal name of the interface
+    pub interface: &'static str,
+    /// Name and descriptor of its abstract method
+    pub method: &'static str,
+    pub descriptor: &'static str,
+}
+
+impl TaskType {
+    /// Internal name of the generated wrapper class, eg. `jvmti/probe/TracedRunnable`
+    pub fn wrapper(&self) -> String {
+        let simple_name = self.interface.rsplit('/').next().unwrap_or(self.interface);
+
+        format!("{}Traced{}", HELPER_PACKAGE, simple_name)
+    }
+
+    /// Descriptor of the wrapper's `wrap` method, which takes and returns the interface
+    pub fn wrap_descriptor(&self) -> String {
+        format!("(L{};)L{};", self.interface, self.interface)
+    }
+}
+
+/// The interfaces of the tasks passed to executors and `CompletableFuture` stages
+pub const TASK_TYPES: &[TaskType] = &[
+    TaskType { interface: "java/lang/Runnable", method: "run", descriptor: "()V" },
+    TaskType { interface: "java/util/concurrent/Callable", method: "call", descriptor: "()Ljava/lang/Object;" },
+    TaskType { interface: "java/util/function/Supplier", method: "get", descriptor: "()Ljava/lang/Object;" },
+    TaskType { interface: "java/util/function/Function", method: "apply", descriptor: "(Ljava/lang/Object;)Ljava/lang/Object;" },
+    TaskType { interface: "java/util/function/Consumer", method: "accept", descriptor: "(Ljava/lang/Object;)V" },
+    TaskType {
+        interface: "java/util/function/BiFunction",
+        method: "apply",
+        descriptor: "(Ljava/lang/Object;Ljava/lang/Object;)Ljava/lang/Object;",
+    },
+    TaskType { interface: "java/util/function/BiConsumer", method: "accept", descriptor: "(Ljava/lang/Object;Ljava/lang/Object;)V" },
+];
+
+/// Names of the executor methods that submit a task
+pub const SUBMIT_METHODS: &[&str] = &["execute", "submit", "schedule", "scheduleAtFixedRate", "scheduleWithFixedDelay"];
+
+///
+/// Identifies a span and the trace it belongs to. Ids are unique within the VM and never 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    pub trace_id: u64,
+    pub span_id: u64,
+}
+
+///
+/// A finished span
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub trace_id: u64,
+    pub span_id: u64,
+    /// The span that was current when this one started, possibly on another thread
+    pub parent_id: Option<u64>,
+    pub name: String,
+    pub start: Tm,
+    pub duration: Duration,
+}
+
+impl Span {
+    pub fn context(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: self.span_id,
+        }
+    }
+}
+
+/// An entry of the per-thread context stack
+enum Scope {
+    Span {
+        context: TraceContext,
+        parent_id: Option<u64>,
+        name: String,
+        start: Tm,
+    },
+    /// A context captured on another thread
+    Attached(TraceContext),
+}
+
+impl Scope {
+    fn context(&self) -> TraceContext {
+        match *self {
+            Scope::Span { context, .. } => context,
+            Scope::Attached(context) => context,
+        }
+    }
+}
+
+thread_local! {
+    static SCOPES: RefCell<Vec<Scope>> = const { RefCell::new(vec![]) };
+}
+
+///
+/// Tracks the current trace context of each thread and keeps the most recent finished spans
+pub struct TraceRegistry {
+    next_id: AtomicU64,
+    spans: Mutex<VecDeque<Span>>,
+}
+
+impl TraceRegistry {
+    pub fn new() -> TraceRegistry {
+        TraceRegistry {
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The context of the calling thread, if it's inside a span or runs a propagated task
+    pub fn current(&self) -> Option<TraceContext> {
+        SCOPES.with(|scopes| scopes.borrow().last().map(|scope| scope.context()))
+    }
+
+    ///
+    /// Start a span on the calling thread. It's a child of the current context, or the root of a
+    /// new trace if there is none, and becomes the current context until it ends.
+    pub fn start_span(&self, name: &str) -> TraceContext {
+        let parent = self.current();
+        let context = TraceContext {
+            trace_id: parent.map(|p| p.trace_id).unwrap_or_else(|| self.next_id()),
+            span_id: self.next_id(),
+        };
+
+        SCOPES.with(|scopes| {
+            scopes.borrow_mut().push(Scope::Span {
+                context,
+                parent_id: parent.map(|p| p.span_id),
+                name: name.to_string(),
+                start: now(),
+            })
+        });
+
+        context
+    }
+
+    ///
+    /// End the innermost span started on the calling thread and record it. Returns `None` if the
+    /// current context isn't a span of this thread, eg. because it was attached by a wrapper.
+    pub fn end_span(&self) -> Option<Span> {
+        let scope = SCOPES.with(|scopes| {
+            let mut scopes = scopes.borrow_mut();
+
+            match scopes.last() {
+                Some(&Scope::Span { .. }) => scopes.pop(),
+                _ => None,
+            }
+        });
+
+        match scope {
+            Some(Scope::Span {
+                context,
+                parent_id,
+                name,
+                start,
+            }) => {
+                let span = Span {
+                    trace_id: context.trace_id,
+                    span_id: context.span_id,
+                    parent_id,
+                    name,
+                    start,
+                    duration: now() - start,
+                };
+
+                if let Ok(mut spans) = self.spans.lock() {
+                    if spans.len() >= MAX_SPANS {
+                        spans.pop_front();
+                    }
+                    spans.push_back(span.clone());
+                }
+
+                Some(span)
+            }
+            _ => None,
+        }
+    }
+
+    /// Make a context captured on another thread the current context of the calling thread
+    pub fn attach(&self, context: TraceContext) {
+        SCOPES.with(|scopes| scopes.borrow_mut().push(Scope::Attached(context)));
+    }
+
+    ///
+    /// Restore the context the calling thread had before the latest `attach`. Spans started
+    /// since then and never ended are discarded. Returns false if nothing was attached.
+    pub fn detach(&self) -> bool {
+        SCOPES.with(|scopes| {
+            let mut scopes = scopes.borrow_mut();
+            let attached = scopes.iter().rposition(|scope| matches!(*scope, Scope::Attached(_)));
+
+            match attached {
+                Some(position) => {
+                    scopes.truncate(position);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// The finished spans, oldest first
+    pub fn spans(&self) -> Vec<Span> {
+        match self.spans.lock() {
+            Ok(spans) => spans.iter().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Remove and return the finished spans, oldest first
+    pub fn drain(&self) -> Vec<Span> {
+        match self.spans.lock() {
+            Ok(mut spans) => spans.drain(..).collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+impl Default for TraceRegistry {
+    fn default() -> Self {
+        TraceRegistry::new()
+    }
+}
+
+///
+/// A `ClassTransformer` that wraps the tasks passed to executors and `CompletableFuture` in the
+/// methods selected by any of its pointcuts
+pub struct ContextPropagation {
+    pointcuts: Vec<Pointcut>,
+}
+
+impl ContextPropagation {
+    pub fn new(pointcuts: Vec<Pointcut>) -> ContextPropagation {
+        ContextPropagation { pointcuts }
+    }
+
+    /// Wrap the tasks in the methods selected by the pointcuts listed in `propagated`
+    pub fn from_config(config: &Config) -> Result<ContextPropagation, ProbeError> {
+        let pointcuts = config
+            .propagated
+            .iter()
+            .map(|name| {
+                config
+                    .pointcut(name)
+                    .cloned()
+                    .ok_or_else(|| ProbeError::UnknownPointcut(name.clone()))
+            })
+            .collect::<Result<Vec<Pointcut>, ProbeError>>()?;
+
+        Ok(ContextPropagation::new(pointcuts))
+    }
+}
+
+impl ClassTransformer for ContextPropagation {
+    fn accepts(&self, class_name: &str) -> bool {
+        let class = ClassInfo::new(class_name);
+
+        HELPER_INSTALLED.load(Ordering::SeqCst)
+            && !class_name.starts_with(HELPER_PACKAGE)
+            && self.pointcuts.iter().any(|p| p.matches_class(&class))
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        let class_info = ClassInfo::from_class(class);
+
+        let selected: Vec<usize> = class
+            .methods
+            .iter()
+            .enumerate()
+            .filter(|&(_, method)| {
+                method.code.is_some()
+                    && self
+                        .pointcuts
+                        .iter()
+                        .any(|p| p.matches_method(&class_info, &MethodInfo::from_method(class, method)))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        let mut modified = false;
+
+        for index in selected {
+            modified |= weave(class, index)? > 0;
+        }
+
+        Ok(modified)
+    }
+}
+
+///
+/// Returns true if the tasks passed to the method are run on another thread, ie. it's a
+/// submission method of a `java.util.concurrent` executor or a `CompletableFuture` stage
+pub fn is_handover(member: &MemberRef) -> bool {
+    let owner = &member.owner;
+
+    match owner.rsplit('/').next() {
+        Some("CompletableFuture") | Some("CompletionStage") => owner.starts_with("java/util/concurrent/"),
+        Some(simple_name) => {
+            owner.starts_with("java/util/concurrent/")
+                && (simple_name.contains("Executor") || simple_name == "ForkJoinPool")
+                && SUBMIT_METHODS.contains(&member.name.as_str())
+        }
+        None => false,
+    }
+}
+
+/// The task type of a parameter, if its instances get wrapped
+pub fn task_type(parameter: &JavaType) -> Option<&'static TaskType> {
+    match *parameter {
+        JavaType::Class(ref name) => TASK_TYPES.iter().find(|task| task.interface == name.as_str()),
+        _ => None,
+    }
+}
+
+///
+/// Wrap the task arguments of every executor and `CompletableFuture` call in a method of the
+/// class. Returns the number of wrapped call sites. A task that is the last argument is wrapped
+/// in place, otherwise the arguments are spilled into new local variables, which no branch
+/// target sees, so the existing stack map frames remain valid.
+pub fn weave(class: &mut JavaClass, method_index: usize) -> Result<usize, ModelError> {
+    let class_name = class.name.clone();
+    let method = &mut class.methods[method_index];
+    let mut sites = 0;
+
+    {
+        let code = match method.code.as_mut() {
+            Some(code) => code,
+            None => return Ok(0),
+        };
+
+        let spill_base = code.max_locals;
+        let original = code.instructions.split_off(0);
+
+        for insn in original {
+            let wrapping = match insn {
+                Insn::Invoke(_, ref member) if is_handover(member) => {
+                    MethodDescriptor::parse(&member.descriptor).and_then(|descriptor| wrap_arguments(&descriptor, spill_base))
+                }
+                _ => None,
+            };
+
+            if let Some(wrapping) = wrapping {
+                code.instructions.extend(wrapping);
+                sites += 1;
+            }
+
+            code.instructions.push(insn);
+        }
+    }
+
+    if sites > 0 {
+        analysis::compute_maxs(&class_name, method)?;
+    }
+
+    Ok(sites)
+}
+
+/// The instructions that wrap the task arguments of a call, which are on top of the stack
+fn wrap_arguments(descriptor: &MethodDescriptor, spill_base: u16) -> Option<Vec<Insn>> {
+    let parameters = &descriptor.parameters;
+    let tasks: Vec<Option<&TaskType>> = parameters.iter().map(task_type).collect();
+
+    match tasks.iter().rposition(|task| task.is_some()) {
+        None => None,
+        Some(last) if last == parameters.len() - 1 && tasks[..last].iter().all(|task| task.is_none()) => {
+            tasks[last].map(|task| vec![wrap_call(task)])
+        }
+        Some(_) => {
+            let mut slots = vec![];
+            let mut slot = spill_base;
+
+            for parameter in parameters.iter() {
+                slots.push(slot);
+                slot += parameter.size();
+            }
+
+            let mut instructions: Vec<Insn> = parameters
+                .iter()
+                .zip(slots.iter())
+                .rev()
+                .map(|(parameter, &slot)| store(parameter, slot))
+                .collect();
+
+            for ((parameter, &slot), task) in parameters.iter().zip(slots.iter()).zip(tasks.iter()) {
+                instructions.push(load(parameter, slot));
+
+                if let &Some(task) = task {
+                    instructions.push(wrap_call(task));
+                }
+            }
+
+            Some(instructions)
+        }
+    }
+}
+
+fn wrap_call(task: &TaskType) -> Insn {
+    Insn::Invoke(
+        Invoke::Static,
+        MemberRef::new(&task.wrapper(), "wrap", &task.wrap_descriptor()),
+    )
+}
+
+/// Store a value of the given type into a local variable
+fn store(java_type: &JavaType, slot: u16) -> Insn {
+    use super::super::bytecode::classfile::Instruction::*;
+
+    let short = match *java_type {
+        JavaType::Long => [LSTORE_0, LSTORE_1, LSTORE_2, LSTORE_3],
+        JavaType::Float => [FSTORE_0, FSTORE_1, FSTORE_2, FSTORE_3],
+        JavaType::Double => [DSTORE_0, DSTORE_1, DSTORE_2, DSTORE_3],
+        JavaType::Class(_) | JavaType::Array(_) => [ASTORE_0, ASTORE_1, ASTORE_2, ASTORE_3],
+        _ => [ISTORE_0, ISTORE_1, ISTORE_2, ISTORE_3],
+    };
+
+    Insn::Op(match (java_type, slot) {
+        (_, 0..=3) => short[slot as usize].clone(),
+        (&JavaType::Long, 4..=255) => LSTORE(slot as u8),
+        (&JavaType::Float, 4..=255) => FSTORE(slot as u8),
+        (&JavaType::Double, 4..=255) => DSTORE(slot as u8),
+        (&JavaType::Class(_), 4..=255) | (&JavaType::Array(_), 4..=255) => ASTORE(slot as u8),
+        (_, 4..=255) => ISTORE(slot as u8),
+        (&JavaType::Long, _) => LSTORE_W(slot),
+        (&JavaType::Float, _) => FSTORE_W(slot),
+        (&JavaType::Double, _) => DSTORE_W(slot),
+        (&JavaType::Class(_), _) | (&JavaType::Array(_), _) => ASTORE_W(slot),
+        _ => ISTORE_W(slot),
+    })
+}
+
+///
+/// Generate the wrapper class of a task type. Its static `wrap` method returns the task as it
+/// is if it's null, already wrapped or there is no current context, otherwise a wrapper that
+/// holds the current context. The wrapper's task method attaches the context, delegates to the
+/// task and detaches the context again.
+pub fn wrapper_class(task: &TaskType) -> Result<JavaClass, ModelError> {
+    let wrapper = task.wrapper();
+    let task_descriptor = format!("L{};", task.interface);
+    let constructor = format!("({}JJ)V", task_descriptor);
+    let method = match MethodDescriptor::parse(task.descriptor) {
+        Some(method) => method,
+        None => return Err(ModelError::InvalidDescriptor(task.descriptor.to_string())),
+    };
+
+    ClassBuilder::new(&wrapper)
+        .version(ClassfileVersion::Java1_8)
+        .public()
+        .final_()
+        .synthetic()
+        .implements(task.interface)
+        .field("task", &task_descriptor, |f| f.private().final_())
+        .field("trace", "J", |f| f.private().final_())
+        .field("span", "J", |f| f.private().final_())
+        .method("<init>", &constructor, |m| {
+            m.private()
+                .aload(0)
+                .invokespecial("java/lang/Object", "<init>", "()V")
+                .aload(0)
+                .aload(1)
+                .putfield(&wrapper, "task", &task_descriptor)
+                .aload(0)
+                .lload(2)
+                .putfield(&wrapper, "trace", "J")
+                .aload(0)
+                .lload(4)
+                .putfield(&wrapper, "span", "J")
+                .ret()
+        })
+        .method("wrap", &task.wrap_descriptor(), |mut m| {
+            let unchanged = m.new_label();
+
+            m.public()
+                .static_()
+                .aload(0)
+                .ifnull(unchanged)
+                .aload(0)
+                .instanceof(&wrapper)
+                .ifne(unchanged)
+                .invokestatic(HELPER_CLASS, "span", SPAN_DESCRIPTOR)
+                .lstore(1)
+                .lload(1)
+                .lconst(0)
+                .op(Instruction::LCMP)
+                .ifeq(unchanged)
+                .new_(&wrapper)
+                .dup()
+                .aload(0)
+                .invokestatic(HELPER_CLASS, "trace", TRACE_DESCRIPTOR)
+                .lload(1)
+                .invokespecial(&wrapper, "<init>", &constructor)
+                .ret()
+                .mark(unchanged)
+                .aload(0)
+                .ret()
+        })
+        .method(task.method, task.descriptor, |mut m| {
+            let start = m.new_label();
+            let end = m.new_label();
+            let handler = m.new_label();
+
+            let mut m = m
+                .public()
+                .aload(0)
+                .getfield(&wrapper, "trace", "J")
+                .aload(0)
+                .getfield(&wrapper, "span", "J")
+                .invokestatic(HELPER_CLASS, "attach", ATTACH_DESCRIPTOR)
+                .mark(start)
+                .aload(0)
+                .getfield(&wrapper, "task", &task_descriptor);
+
+            for index in 0..method.parameters.len() {
+                m = m.aload(index as u16 + 1);
+            }
+
+            m.invokeinterface(task.interface, task.method, task.descriptor)
+                .mark(end)
+                .invokestatic(HELPER_CLASS, "detach", DETACH_DESCRIPTOR)
+                .ret()
+                .mark(handler)
+                .invokestatic(HELPER_CLASS, "detach", DETACH_DESCRIPTOR)
+                .athrow()
+                .try_catch(start, end, handler, None)
+        })
+        .build()
+}
+
+///
+/// The bridge that defines the helper class and binds its `trace`, `span`, `attach` and `detach`
+/// methods
+pub fn bridge() -> NativeBridge {
+    NativeBridge::new(HELPER_CLASS)
+        .method("trace", TRACE_DESCRIPTOR, current_trace as *mut c_void)
+        .method("span", SPAN_DESCRIPTOR, current_span as *mut c_void)
+        .method("attach", ATTACH_DESCRIPTOR, attach as *mut c_void)
+        .method("detach", DETACH_DESCRIPTOR, detach as *mut c_void)
+}
+
+///
+/// Define the helper and the wrapper classes in the bootstrap class loader, bind the native
+/// methods and start weaving the classes loaded from now on. Has to be called in the live phase,
+/// eg. from the `VMInit` handler.
+pub fn install(jni: JNIEnvPtr) -> Result<(), BridgeError> {
+    if HELPER_INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let jni = JNIEnvironment::new(jni);
+    let bootstrap_loader: JavaObject = ptr::null_mut();
+
+    bridge().define(&jni)?;
+
+    for task in TASK_TYPES.iter() {
+        let classfile = wrapper_class(task)?.to_classfile()?;
+        let mut data = vec![];
+
+        ClassWriter::new(&mut data).write_class(&classfile)?;
+        jni.define_class(&task.wrapper(), &bootstrap_loader, &data)?;
+    }
+
+    HELPER_INSTALLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Returns true once the helper classes are defined and classes are being woven
+pub fn is_installed() -> bool {
+    HELPER_INSTALLED.load(Ordering::SeqCst)
+}
+
+extern "C" fn current_trace(_jni: JNIEnvPtr, _class: JavaClassPtr) -> jlong {
+    static_context().traces.current().map(|context| context.trace_id as jlong).unwrap_or(0)
+}
+
+extern "C" fn current_span(_jni: JNIEnvPtr, _class: JavaClassPtr) -> jlong {
+    static_context().traces.current().map(|context| context.span_id as jlong).unwrap_or(0)
+}
+
+extern "C" fn attach(_jni: JNIEnvPtr, _class: JavaClassPtr, trace: jlong, span: jlong) {
+    static_context().traces.attach(TraceContext {
+        trace_id: trace as u64,
+        span_id: span as u64,
+    });
+}
+
+extern "C" fn detach(_jni: JNIEnvPtr, _class: JavaClassPtr) {
+    static_context().traces.detach();
+}
@@ -0,0 +1,534 @@
+//!
+//! Transactions and database calls through built-in pointcut packs. A pack selects the methods
+//! of a framework that have a known meaning:
+//!
+//! * `servlet`: `HttpServlet.service(HttpServletRequest, HttpServletResponse)` of `javax.servlet`
+//!   and `jakarta.servlet`, and its direct overrides, are request entry points. They are recorded
+//!   as transactions, with the HTTP method and the URL of the request.
+//! * `jdbc`: the public `execute*` methods of classes that directly implement `java.sql.Statement`,
+//!   `PreparedStatement` or `CallableStatement` are database calls. They are recorded with the SQL
+//!   passed to them, or the `toString` of the statement if it was prepared, which includes the
+//!   SQL with most drivers.
+//!
+//! Every transaction and database call is a span of the `TraceRegistry`, so database calls nest
+//! under the active request, even on other threads if the trace context is propagated to them.
+//! Requests and database calls made inside another one of the same kind, eg. an overriding
+//! `service` calling `super.service` or `executeQuery` calling `execute`, aren't recorded again.
+//!
+//! ```ignore
+//! let probes = TransactionProbes::from_config(&config, static_context().transactions.clone())?;
+//! static_context().add_transformer("transactions", 0, probes);
+//! agent.use_transformer_chain();
+//! agent.on_vm_init(Some(on_vm_init));
+//!
+//! fn on_vm_init(_jvmti: JVMTIEnvPtr, jni: JNIEnvPtr, _thread: jthread) {
+//!     transaction::install(jni).unwrap();
+//! }
+//! ```
+//!
+//! The packs are available as named pointcuts in the configuration as well, eg.
+//! `timed = [ "jdbc" ]`, unless a pointcut of the same name is defined there.
+
+use super::super::bytecode::classfile::Instruction;
+use super::super::config::Config;
+use super::super::context::static_context;
+use super::super::environment::jni::{JNIEnvironment, JNI};
+use super::super::native::jvmti_native::jboolean;
+use super::super::native::{JNIEnvPtr, JavaClass as JavaClassPtr, JavaObject, JavaString};
+use super::analysis;
+use super::bridge::{BridgeError, NativeBridge};
+use super::capture::call_string;
+use super::chain::{ClassTransformer, TransformError};
+use super::code::{Insn, Invoke, MemberRef};
+use super::descriptor::JavaType;
+use super::pointcut::{ClassInfo, MethodInfo, Pointcut};
+use super::timing::{weave_exits, ProbeError};
+use super::trace::{TraceContext, TraceRegistry};
+use super::{JavaClass, ModelError};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use time::{Duration, Tm};
+
+/// Internal name of the generated helper class the probes call
+pub const HELPER_CLASS: &str = "jvmti/probe/Transaction";
+/// `static native void request(Object request)`
+pub const REQUEST_DESCRIPTOR: &str = "(Ljava/lang/Object;)V";
+/// `static native void database(Object statement, String sql)`
+pub const DATABASE_DESCRIPTOR: &str = "(Ljava/lang/Object;Ljava/lang/String;)V";
+/// `static native void exit(boolean failed)`
+pub const EXIT_DESCRIPTOR: &str = "(Z)V";
+
+/// Maximum number of finished transactions, and of database calls outside of any, that are kept
+pub const MAX_RECORDS: usize = 1000;
+
+static HELPER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// What the methods selected by a pack stand for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    Request,
+    Database,
+}
+
+///
+/// A built-in pointcut that selects the methods of a framework with a known meaning
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointcutPack {
+    pub name: &'static str,
+    pub kind: EntryKind,
+    pub pointcut: Pointcut,
+}
+
+const SERVLET_POINTCUT: &str = "(class(javax.servlet.http.HttpServlet) || extends(javax.servlet.http.HttpServlet)) \
+     && method(service) && descriptor((Ljavax/servlet/http/HttpServletRequest;Ljavax/servlet/http/HttpServletResponse;)V) \
+     || (class(jakarta.servlet.http.HttpServlet) || extends(jakarta.servlet.http.HttpServlet)) \
+     && method(service) && descriptor((Ljakarta/servlet/http/HttpServletRequest;Ljakarta/servlet/http/HttpServletResponse;)V)";
+
+const JDBC_POINTCUT: &str = "(implements(java.sql.Statement) || implements(java.sql.PreparedStatement) \
+     || implements(java.sql.CallableStatement)) && method(execute*) && access(public)";
+
+lazy_static! {
+    static ref PACKS: Vec<PointcutPack> = vec![
+        PointcutPack {
+            name: "servlet",
+            kind: EntryKind::Request,
+            pointcut: Pointcut::parse(SERVLET_POINTCUT).unwrap(),
+        },
+        PointcutPack {
+            name: "jdbc",
+            kind: EntryKind::Database,
+            pointcut: Pointcut::parse(JDBC_POINTCUT).unwrap(),
+        },
+    ];
+}
+
+/// The built-in pointcut packs
+pub fn packs() -> &'static [PointcutPack] {
+    &PACKS
+}
+
+/// The built-in pointcut pack with the given name
+pub fn pack(name: &str) -> Option<&'static PointcutPack> {
+    PACKS.iter().find(|pack| pack.name == name)
+}
+
+///
+/// A finished request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub context: TraceContext,
+    /// The HTTP method, eg. `GET`
+    pub method: String,
+    pub url: String,
+    pub start: Tm,
+    pub duration: Duration,
+    /// Whether the request ended with an exception
+    pub failed: bool,
+    /// The database calls made while the request was active, in the order they finished
+    pub database_calls: Vec<DatabaseCall>,
+}
+
+///
+/// A finished database call
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseCall {
+    pub context: TraceContext,
+    /// The span that was active when the call started, eg. the one of the request
+    pub parent_id: Option<u64>,
+    pub sql: String,
+    pub start: Tm,
+    pub duration: Duration,
+    /// Whether the call ended with an exception
+    pub failed: bool,
+}
+
+/// A transaction or database call that has just finished
+#[derive(Debug, Clone, PartialEq)]
+pub enum Completed {
+    Transaction(Transaction),
+    DatabaseCall(DatabaseCall),
+}
+
+/// An entry point in progress on the current thread
+struct Entry {
+    kind: EntryKind,
+    /// Method and URL of a recorded request, or the SQL of a recorded database call. Entries
+    /// nested in one of the same kind aren't recorded.
+    recorded: Option<(String, String)>,
+}
+
+thread_local! {
+    static ENTRIES: RefCell<Vec<Entry>> = const { RefCell::new(vec![]) };
+}
+
+///
+/// Tracks the entry points in progress on each thread and keeps the most recent finished
+/// transactions and the database calls made outside of them
+pub struct TransactionRegistry {
+    traces: Arc<TraceRegistry>,
+    /// Database calls of the transactions in progress, by trace id
+    pending: Mutex<HashMap<u64, Vec<DatabaseCall>>>,
+    transactions: Mutex<VecDeque<Transaction>>,
+    database_calls: Mutex<VecDeque<DatabaseCall>>,
+}
+
+impl TransactionRegistry {
+    /// Create a registry that records its spans in the given trace registry
+    pub fn new(traces: Arc<TraceRegistry>) -> TransactionRegistry {
+        TransactionRegistry {
+            traces,
+            pending: Mutex::new(HashMap::new()),
+            transactions: Mutex::new(VecDeque::new()),
+            database_calls: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    ///
+    /// Enter a request on the calling thread. The method and URL are only asked for if the
+    /// request isn't nested in another one. Returns true if the request is recorded.
+    pub fn enter_request<F: FnOnce() -> (String, String)>(&self, describe: F) -> bool {
+        self.enter(EntryKind::Request, || {
+            let (method, url) = describe();
+            let context = self.traces.start_span(&format!("{} {}", method, url));
+
+            if let Ok(mut pending) = self.pending.lock() {
+                pending.insert(context.trace_id, vec![]);
+            }
+
+            (method, url)
+        })
+    }
+
+    ///
+    /// Enter a database call on the calling thread. The SQL is only asked for if the call isn't
+    /// nested in another one. Returns true if the call is recorded.
+    pub fn enter_database<F: FnOnce() -> String>(&self, describe: F) -> bool {
+        self.enter(EntryKind::Database, || {
+            let sql = describe();
+            self.traces.start_span(&sql);
+
+            (sql, String::new())
+        })
+    }
+
+    fn enter<F: FnOnce() -> (String, String)>(&self, kind: EntryKind, start: F) -> bool {
+        let nested = ENTRIES.with(|entries| entries.borrow().iter().any(|entry| entry.kind == kind));
+        // The span is started outside of the borrow, the description may run Java code
+        let recorded = if nested { None } else { Some(start()) };
+        let is_recorded = recorded.is_some();
+
+        ENTRIES.with(|entries| {
+            entries.borrow_mut().push(Entry {
+                kind,
+                recorded,
+            })
+        });
+
+        is_recorded
+    }
+
+    ///
+    /// Leave the innermost entry point of the calling thread. Returns the transaction or
+    /// database call if it was recorded.
+    pub fn exit(&self, failed: bool) -> Option<Completed> {
+        let entry = ENTRIES.with(|entries| entries.borrow_mut().pop())?;
+        let (first, second) = entry.recorded?;
+        let span = self.traces.end_span()?;
+
+        match entry.kind {
+            EntryKind::Request => {
+                let database_calls = self
+                    .pending
+                    .lock()
+                    .ok()
+                    .and_then(|mut pending| pending.remove(&span.trace_id))
+                    .unwrap_or_default();
+                let transaction = Transaction {
+                    context: span.context(),
+                    method: first,
+                    url: second,
+                    start: span.start,
+                    duration: span.duration,
+                    failed,
+                    database_calls,
+                };
+
+                if let Ok(mut transactions) = self.transactions.lock() {
+                    push_bounded(&mut transactions, transaction.clone());
+                }
+
+                Some(Completed::Transaction(transaction))
+            }
+            EntryKind::Database => {
+                let call = DatabaseCall {
+                    context: span.context(),
+                    parent_id: span.parent_id,
+                    sql: first,
+                    start: span.start,
+                    duration: span.duration,
+                    failed,
+                };
+
+                let in_transaction = match self.pending.lock() {
+                    Ok(mut pending) => match pending.get_mut(&call.context.trace_id) {
+                        Some(calls) => {
+                            calls.push(call.clone());
+                            true
+                        }
+                        None => false,
+                    },
+                    Err(_) => false,
+                };
+
+                if !in_transaction {
+                    if let Ok(mut calls) = self.database_calls.lock() {
+                        push_bounded(&mut calls, call.clone());
+                    }
+                }
+
+                Some(Completed::DatabaseCall(call))
+            }
+        }
+    }
+
+    /// The finished transactions, oldest first
+    pub fn transactions(&self) -> Vec<Transaction> {
+        match self.transactions.lock() {
+            Ok(transactions) => transactions.iter().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Remove and return the finished transactions, oldest first
+    pub fn drain(&self) -> Vec<Transaction> {
+        match self.transactions.lock() {
+            Ok(mut transactions) => transactions.drain(..).collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// The database calls made outside of any transaction, oldest first
+    pub fn database_calls(&self) -> Vec<DatabaseCall> {
+        match self.database_calls.lock() {
+            Ok(calls) => calls.iter().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+fn push_bounded<T>(records: &mut VecDeque<T>, record: T) {
+    if records.len() >= MAX_RECORDS {
+        records.pop_front();
+    }
+    records.push_back(record);
+}
+
+///
+/// A `ClassTransformer` that weaves the probes of its pointcut packs
+pub struct TransactionProbes {
+    packs: Vec<&'static PointcutPack>,
+    registry: Arc<TransactionRegistry>,
+}
+
+impl TransactionProbes {
+    pub fn new(packs: Vec<&'static PointcutPack>, registry: Arc<TransactionRegistry>) -> TransactionProbes {
+        TransactionProbes {
+            packs,
+            registry,
+        }
+    }
+
+    /// Weave the packs listed in `packs`
+    pub fn from_config(config: &Config, registry: Arc<TransactionRegistry>) -> Result<TransactionProbes, ProbeError> {
+        let packs = config
+            .packs
+            .iter()
+            .map(|name| pack(name).ok_or_else(|| ProbeError::UnknownPack(name.clone())))
+            .collect::<Result<Vec<&'static PointcutPack>, ProbeError>>()?;
+
+        Ok(TransactionProbes::new(packs, registry))
+    }
+
+    /// The registry the woven classes report to
+    pub fn registry(&self) -> Arc<TransactionRegistry> {
+        self.registry.clone()
+    }
+}
+
+impl ClassTransformer for TransactionProbes {
+    fn accepts(&self, class_name: &str) -> bool {
+        let class = ClassInfo::new(class_name);
+
+        HELPER_INSTALLED.load(Ordering::SeqCst)
+            && class_name != HELPER_CLASS
+            && self.packs.iter().any(|pack| pack.pointcut.matches_class(&class))
+    }
+
+    fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+        let class_info = ClassInfo::from_class(class);
+
+        let selected: Vec<(usize, EntryKind)> = class
+            .methods
+            .iter()
+            .enumerate()
+            .filter(|&(_, method)| method.code.is_some())
+            .filter_map(|(index, method)| {
+                let method_info = MethodInfo::from_method(class, method);
+
+                self.packs
+                    .iter()
+                    .find(|pack| pack.pointcut.matches_method(&class_info, &method_info))
+                    .map(|pack| (index, pack.kind))
+            })
+            .collect();
+
+        for &(index, kind) in selected.iter() {
+            weave(class, index, kind)?;
+        }
+
+        Ok(!selected.is_empty())
+    }
+}
+
+///
+/// Weave the entry and exit probes of an entry point into a method of the class. Requests pass
+/// their first argument, database calls the statement and their first argument if it's a
+/// `String`. The code doesn't need any new local variables, so the existing stack map frames
+/// remain valid, only the exception handler of the exit probe gets a new frame.
+pub fn weave(class: &mut JavaClass, method_index: usize, kind: EntryKind) -> Result<(), ModelError> {
+    let class_name = class.name.clone();
+    let has_frames = class.version.major_version >= 50;
+    let method = &mut class.methods[method_index];
+    let passes_sql = method.descriptor.parameters.first() == Some(&JavaType::Class("java/lang/String".to_string()));
+
+    {
+        let code = match method.code.as_mut() {
+            Some(code) => code,
+            None => return Ok(()),
+        };
+
+        let instructions = match kind {
+            EntryKind::Request => vec![
+                Insn::Op(Instruction::ALOAD_1),
+                helper_call("request", REQUEST_DESCRIPTOR),
+            ],
+            EntryKind::Database => vec![
+                Insn::Op(Instruction::ALOAD_0),
+                Insn::Op(if passes_sql { Instruction::ALOAD_1 } else { Instruction::ACONST_NULL }),
+                helper_call("database", DATABASE_DESCRIPTOR),
+            ],
+        };
+        weave_exits(
+            code,
+            instructions,
+            has_frames,
+            |insn| {
+                if is_return(insn) {
+                    Some(vec![Insn::Op(Instruction::ICONST_0), helper_call("exit", EXIT_DESCRIPTOR)])
+                } else {
+                    None
+                }
+            },
+            vec![Insn::Op(Instruction::ICONST_1), helper_call("exit", EXIT_DESCRIPTOR)],
+        );
+    }
+
+    analysis::compute_maxs(&class_name, method)
+}
+
+fn helper_call(name: &str, descriptor: &str) -> Insn {
+    Insn::Invoke(Invoke::Static, MemberRef::new(HELPER_CLASS, name, descriptor))
+}
+
+fn is_return(insn: &Insn) -> bool {
+    matches!(
+        *insn,
+        Insn::Op(Instruction::IRETURN)
+            | Insn::Op(Instruction::LRETURN)
+            | Insn::Op(Instruction::FRETURN)
+            | Insn::Op(Instruction::DRETURN)
+            | Insn::Op(Instruction::ARETURN)
+            | Insn::Op(Instruction::RETURN)
+    )
+}
+
+///
+/// The URL of a servlet request, with the query string if there is one
+fn request_url(jni: &dyn JNI, request: JavaObject) -> Option<String> {
+    let class = jni.get_object_class(&request).ok()?;
+    let method = jni.get_method(&class, "getRequestURL", "()Ljava/lang/StringBuffer;").ok()?;
+    let buffer = jni.call_object_method(&request, &method.native_id, &[]).ok()?;
+
+    if jni.clear_pending_exception() || buffer.is_null() {
+        return None;
+    }
+
+    let url = call_string(jni, buffer, "toString")?;
+
+    match call_string(jni, request, "getQueryString") {
+        Some(query) => Some(format!("{}?{}", url, query)),
+        None => Some(url),
+    }
+}
+
+///
+/// The bridge that defines the helper class and binds its `request`, `database` and `exit`
+/// methods
+pub fn bridge() -> NativeBridge {
+    NativeBridge::new(HELPER_CLASS)
+        .method("request", REQUEST_DESCRIPTOR, request_entered as *mut c_void)
+        .method("database", DATABASE_DESCRIPTOR, database_entered as *mut c_void)
+        .method("exit", EXIT_DESCRIPTOR, exited as *mut c_void)
+}
+
+///
+/// Define the helper class in the bootstrap class loader, bind its native methods and start
+/// weaving the classes loaded from now on. Has to be called in the live phase, eg. from the
+/// `VMInit` handler.
+pub fn install(jni: JNIEnvPtr) -> Result<(), BridgeError> {
+    if HELPER_INSTALLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    bridge().define(&JNIEnvironment::new(jni))?;
+    HELPER_INSTALLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Returns true once the helper class is defined and classes are being woven
+pub fn is_installed() -> bool {
+    HELPER_INSTALLED.load(Ordering::SeqCst)
+}
+
+extern "C" fn request_entered(jni: JNIEnvPtr, _class: JavaClassPtr, request: JavaObject) {
+    let jni = JNIEnvironment::new(jni);
+
+    static_context().transactions.enter_request(|| {
+        let method = call_string(&jni, request, "getMethod").unwrap_or_default();
+        let url = request_url(&jni, request).unwrap_or_default();
+
+        (method, url)
+    });
+}
+
+extern "C" fn database_entered(jni: JNIEnvPtr, _class: JavaClassPtr, statement: JavaObject, sql: JavaString) {
+    let jni = JNIEnvironment::new(jni);
+
+    static_context().transactions.enter_database(|| {
+        let sql = if sql.is_null() {
+            call_string(&jni, statement, "toString")
+        } else {
+            jni.get_string_utf_chars(&sql).ok()
+        };
+
+        sql.unwrap_or_default()
+    });
+}
+
+extern "C" fn exited(_jni: JNIEnvPtr, _class: JavaClassPtr, failed: jboolean) {
+    static_context().transactions.exit(failed != 0);
+}
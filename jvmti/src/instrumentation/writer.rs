@@ -0,0 +1,659 @@
+use super::super::bytecode::classfile as bc;
+use super::super::bytecode::classfile::{
+    Attribute, ConstantPoolIndex, Instruction, StackMapFrame, VerificationType,
+};
+use super::code::*;
+use super::constants::ConstantPoolBuilder;
+use super::descriptor::MethodDescriptor;
+use super::{Field, JavaClass, Method, ModelError};
+use std::collections::{HashMap, HashSet};
+
+/// The constant pool and code offsets are limited to 16 bits by the class file format
+const MAX_CONSTANT_POOL_SIZE: usize = 65535;
+const MAX_CODE_LENGTH: usize = 65535;
+
+///
+/// Converts a `JavaClass` back into a `Classfile`, rebuilding the constant pool. The original
+/// constant pool (if any) is kept as a prefix of the new one so the indices in attributes that
+/// are carried over untouched stay valid.
+pub fn write_class(class: &JavaClass) -> Result<bc::Classfile, ModelError> {
+    let mut cp = ConstantPoolBuilder::seeded(&class.constant_pool, &class.bootstrap_methods);
+
+    let mut classfile = bc::Classfile::new();
+
+    classfile.version = class.version.clone();
+    classfile.access_flags = class.access_flags.clone();
+    classfile.this_class = cp.class(&class.name);
+    classfile.super_class = match class.super_name {
+        Some(ref name) => cp.class(name),
+        None => ConstantPoolIndex::new(0),
+    };
+    classfile.interfaces = class.interfaces.iter().map(|name| cp.class(name)).collect();
+
+    for field in &class.fields {
+        classfile.fields.push(write_field(&mut cp, field));
+    }
+
+    for method in &class.methods {
+        classfile.methods.push(write_method(&mut cp, &class.name, method)?);
+    }
+
+    let mut modelled = vec![];
+
+    if let Some(ref source_file) = class.source_file {
+        modelled.push(Attribute::SourceFile(cp.utf8(source_file)));
+    }
+
+    if let Some(ref signature) = class.signature {
+        modelled.push(Attribute::Signature(cp.utf8(signature)));
+    }
+
+    if !cp.bootstrap_methods().is_empty() {
+        modelled.push(Attribute::BootstrapMethods(cp.bootstrap_methods().to_vec()));
+    }
+
+    classfile.attributes = class.attribute_order.arrange(modelled, &class.attributes);
+
+    // The class writer looks up attribute names in the constant pool
+    register_attribute_names(&mut cp, &classfile.attributes);
+
+    for field in &classfile.fields {
+        register_attribute_names(&mut cp, &field.attributes);
+    }
+
+    for method in &classfile.methods {
+        register_attribute_names(&mut cp, &method.attributes);
+    }
+
+    if cp.len() > MAX_CONSTANT_POOL_SIZE {
+        return Err(ModelError::ConstantPoolOverflow(cp.len()));
+    }
+
+    classfile.constant_pool = cp.into_parts().0;
+
+    Ok(classfile)
+}
+
+fn write_field(cp: &mut ConstantPoolBuilder, field: &Field) -> bc::Field {
+    let mut attributes = vec![];
+
+    if let Some(ref value) = field.constant_value {
+        attributes.push(Attribute::ConstantValue(cp.value(value)));
+    }
+
+    if let Some(ref signature) = field.signature {
+        attributes.push(Attribute::Signature(cp.utf8(signature)));
+    }
+
+    bc::Field {
+        access_flags: field.access_flags.clone(),
+        name_index: cp.utf8(&field.name),
+        descriptor_index: cp.utf8(&field.field_type.descriptor()),
+        attributes: field.attribute_order.arrange(attributes, &field.attributes),
+    }
+}
+
+fn write_method(
+    cp: &mut ConstantPoolBuilder,
+    class_name: &str,
+    method: &Method,
+) -> Result<bc::Method, ModelError> {
+    let mut attributes = vec![];
+
+    if let Some(ref code) = method.code {
+        let initial = Frame::initial(
+            class_name,
+            method.is_static(),
+            &method.name,
+            &method.descriptor,
+        );
+
+        attributes.push(CodeWriter::new(cp, code).write(&initial)?);
+    }
+
+    if !method.exceptions.is_empty() {
+        attributes.push(Attribute::Exceptions(
+            method.exceptions.iter().map(|name| cp.class(name)).collect(),
+        ));
+    }
+
+    if let Some(ref signature) = method.signature {
+        attributes.push(Attribute::Signature(cp.utf8(signature)));
+    }
+
+    Ok(bc::Method {
+        access_flags: method.access_flags.clone(),
+        name_index: cp.utf8(&method.name),
+        descriptor_index: cp.utf8(&method.descriptor.descriptor()),
+        attributes: method.attribute_order.arrange(attributes, &method.attributes),
+    })
+}
+
+fn register_attribute_names(cp: &mut ConstantPoolBuilder, attributes: &[Attribute]) {
+    for attribute in attributes {
+        if let Some(name) = attribute_name(attribute) {
+            cp.utf8(name);
+        }
+
+        if let Attribute::Code { attributes, .. } = attribute {
+            register_attribute_names(cp, attributes);
+        }
+    }
+}
+
+/// Returns the name an attribute is stored under, or `None` for raw attributes which carry their
+/// own name index
+pub fn attribute_name(attribute: &Attribute) -> Option<&'static str> {
+    match *attribute {
+        Attribute::ConstantValue(_) => Some("ConstantValue"),
+        Attribute::Code { .. } => Some("Code"),
+        Attribute::StackMapTable(_) => Some("StackMapTable"),
+        Attribute::Exceptions(_) => Some("Exceptions"),
+        Attribute::InnerClasses(_) => Some("InnerClasses"),
+        Attribute::EnclosingMethod { .. } => Some("EnclosingMethod"),
+        Attribute::Synthetic => Some("Synthetic"),
+        Attribute::Signature(_) => Some("Signature"),
+        Attribute::SourceFile(_) => Some("SourceFile"),
+        Attribute::SourceDebugExtension(_) => Some("SourceDebugExtension"),
+        Attribute::LineNumberTable(_) => Some("LineNumberTable"),
+        Attribute::LocalVariableTable(_) => Some("LocalVariableTable"),
+        Attribute::LocalVariableTypeTable(_) => Some("LocalVariableTypeTable"),
+        Attribute::Deprecated => Some("Deprecated"),
+        Attribute::RuntimeVisibleAnnotations(_) => Some("RuntimeVisibleAnnotations"),
+        Attribute::RuntimeInvisibleAnnotations(_) => Some("RuntimeInvisibleAnnotations"),
+        Attribute::RuntimeVisibleParameterAnnotations(_) => {
+            Some("RuntimeVisibleParameterAnnotations")
+        }
+        Attribute::RuntimeInvisibleParameterAnnotations(_) => {
+            Some("RuntimeInvisibleParameterAnnotations")
+        }
+        Attribute::RuntimeVisibleTypeAnnotations(_) => Some("RuntimeVisibleTypeAnnotations"),
+        Attribute::RuntimeInvisibleTypeAnnotations(_) => Some("RuntimeInvisibleTypeAnnotations"),
+        Attribute::AnnotationDefault(_) => Some("AnnotationDefault"),
+        Attribute::BootstrapMethods(_) => Some("BootstrapMethods"),
+        Attribute::MethodParameters(_) => Some("MethodParameters"),
+        Attribute::RawAttribute { .. } => None,
+    }
+}
+
+///
+/// Remembers the order in which the attributes of a class, field or method were read. The model
+/// keeps some attributes as fields and the rest in a list, this allows putting them back in their
+/// original order. The order has no meaning to the JVM so it is ignored when comparing.
+#[derive(Debug, Clone, Default)]
+pub struct AttributeOrder {
+    /// Name of each attribute that was turned into a model field, or `None` for unmodelled ones
+    names: Vec<Option<&'static str>>,
+}
+
+impl AttributeOrder {
+    pub fn record(attributes: &[Attribute], modelled: &[&'static str]) -> AttributeOrder {
+        AttributeOrder {
+            names: attributes
+                .iter()
+                .map(|attribute| {
+                    attribute_name(attribute).and_then(|name| modelled.iter().find(|m| **m == name).cloned())
+                })
+                .collect(),
+        }
+    }
+
+    /// Interleave the attributes generated from model fields with the unmodelled ones. Anything
+    /// that wasn't present when the order was recorded goes to the end, modelled ones first.
+    pub fn arrange(&self, modelled: Vec<Attribute>, unmodelled: &[Attribute]) -> Vec<Attribute> {
+        let mut modelled: Vec<Option<Attribute>> = modelled.into_iter().map(Some).collect();
+        let mut unmodelled = unmodelled.iter();
+        let mut result = vec![];
+
+        for name in &self.names {
+            match *name {
+                Some(name) => {
+                    let slot = modelled.iter_mut().find(|attribute| match **attribute {
+                        Some(ref attribute) => attribute_name(attribute) == Some(name),
+                        None => false,
+                    });
+
+                    if let Some(attribute) = slot.and_then(|slot| slot.take()) {
+                        result.push(attribute);
+                    }
+                }
+                None => result.extend(unmodelled.next().cloned()),
+            }
+        }
+
+        result.extend(modelled.into_iter().flatten());
+        result.extend(unmodelled.cloned());
+        result
+    }
+}
+
+impl PartialEq for AttributeOrder {
+    fn eq(&self, _: &AttributeOrder) -> bool {
+        true
+    }
+}
+
+///
+/// Lowers a method body into a `Code` attribute: lays out the instructions, resolves labels into
+/// offsets and compresses the stack map frames.
+struct CodeWriter<'a> {
+    cp: &'a mut ConstantPoolBuilder,
+    code: &'a Code,
+    /// Instruction list positions of `goto` and `jsr` instructions that need the wide form
+    wide_jumps: HashSet<usize>,
+    offsets: Vec<usize>,
+    labels: HashMap<Label, usize>,
+    code_length: usize,
+}
+
+impl<'a> CodeWriter<'a> {
+    fn new(cp: &'a mut ConstantPoolBuilder, code: &'a Code) -> Self {
+        CodeWriter {
+            cp,
+            code,
+            wide_jumps: HashSet::new(),
+            offsets: vec![],
+            labels: HashMap::new(),
+            code_length: 0,
+        }
+    }
+
+    fn write(mut self, initial: &Frame) -> Result<Attribute, ModelError> {
+        self.layout()?;
+
+        let mut instructions = vec![];
+        let mut line_numbers = vec![];
+        let mut frames: Vec<(usize, &Frame)> = vec![];
+
+        for (idx, insn) in self.code.instructions.iter().enumerate() {
+            let offset = self.offsets[idx];
+
+            match *insn {
+                Insn::Label(_) => (),
+                Insn::LineNumber(line) => line_numbers.push(bc::LineNumberTable {
+                    start_pc: offset as u16,
+                    line_number: line,
+                }),
+                Insn::Frame(ref frame) => {
+                    // A later frame at the same position replaces the previous one
+                    if frames.last().map(|&(o, _)| o == offset).unwrap_or(false) {
+                        frames.pop();
+                    }
+
+                    frames.push((offset, frame));
+                }
+                ref other => instructions.push(self.write_instruction(idx, other)?),
+            }
+        }
+
+        let mut exception_table = vec![];
+
+        for block in &self.code.try_catch_blocks {
+            exception_table.push(bc::ExceptionHandler {
+                start_pc: self.offset(&block.start)? as u16,
+                end_pc: self.offset(&block.end)? as u16,
+                handler_pc: self.offset(&block.handler)? as u16,
+                catch_type: match block.catch_type {
+                    Some(ref name) => self.cp.class(name),
+                    None => ConstantPoolIndex::new(0),
+                },
+            });
+        }
+
+        let mut attributes = vec![];
+
+        if !line_numbers.is_empty() {
+            attributes.push(Attribute::LineNumberTable(line_numbers));
+        }
+
+        if !self.code.local_variables.is_empty() {
+            let mut variables = vec![];
+            let mut variable_types = vec![];
+
+            for variable in &self.code.local_variables {
+                let start = self.offset(&variable.start)?;
+                let length = self.offset(&variable.end)?.saturating_sub(start);
+
+                variables.push(bc::LocalVariableTable {
+                    start_pc: start as u16,
+                    length: length as u16,
+                    name_index: self.cp.utf8(&variable.name),
+                    descriptor_index: self.cp.utf8(&variable.descriptor),
+                    index: variable.index,
+                });
+
+                if let Some(ref signature) = variable.signature {
+                    variable_types.push(bc::LocalVariableTypeTable {
+                        start_pc: start as u16,
+                        length: length as u16,
+                        name_index: self.cp.utf8(&variable.name),
+                        signature_index: self.cp.utf8(signature),
+                        index: variable.index,
+                    });
+                }
+            }
+
+            attributes.push(Attribute::LocalVariableTable(variables));
+
+            if !variable_types.is_empty() {
+                attributes.push(Attribute::LocalVariableTypeTable(variable_types));
+            }
+        }
+
+        if !frames.is_empty() {
+            attributes.push(Attribute::StackMapTable(self.write_frames(initial, &frames)?));
+        }
+
+        attributes.extend(self.code.attributes.iter().cloned());
+
+        Ok(Attribute::Code {
+            max_stack: self.code.max_stack,
+            max_locals: self.code.max_locals,
+            code: instructions,
+            exception_table,
+            attributes,
+        })
+    }
+
+    /// Assign offsets to every element of the instruction list. Jumps that can't reach their
+    /// target with a 16-bit offset are widened, which may push other jumps out of range, so this
+    /// is repeated until the layout is stable.
+    fn layout(&mut self) -> Result<(), ModelError> {
+        let mut placed = HashSet::new();
+
+        for insn in &self.code.instructions {
+            if let &Insn::Label(label) = insn {
+                if !placed.insert(label) {
+                    return Err(ModelError::DuplicateLabel(label));
+                }
+            }
+        }
+
+        loop {
+            self.offsets.clear();
+            self.labels.clear();
+
+            let mut offset = 0;
+
+            for (idx, insn) in self.code.instructions.iter().enumerate() {
+                self.offsets.push(offset);
+
+                if let &Insn::Label(label) = insn {
+                    self.labels.insert(label, offset);
+                }
+
+                offset += self.size_of(idx, insn, offset);
+            }
+
+            self.code_length = offset;
+
+            if self.code_length > MAX_CODE_LENGTH {
+                return Err(ModelError::CodeTooLarge(self.code_length));
+            }
+
+            let mut widened = false;
+
+            for (idx, insn) in self.code.instructions.iter().enumerate() {
+                if let &Insn::Jump(jump, ref target) = insn {
+                    let relative = self.offset(target)? as i64 - self.offsets[idx] as i64;
+
+                    if self.wide_jumps.contains(&idx)
+                        || (relative >= i16::MIN as i64 && relative <= i16::MAX as i64)
+                    {
+                        continue;
+                    }
+
+                    if jump.is_conditional() {
+                        return Err(ModelError::BranchOutOfRange(*target));
+                    }
+
+                    self.wide_jumps.insert(idx);
+                    widened = true;
+                }
+            }
+
+            if !widened {
+                return Ok(());
+            }
+        }
+    }
+
+    fn size_of(&mut self, idx: usize, insn: &Insn, offset: usize) -> usize {
+        let padding = (4 - ((offset + 1) % 4)) % 4;
+
+        match *insn {
+            Insn::Label(_) | Insn::LineNumber(_) | Insn::Frame(_) => 0,
+            Insn::Op(ref instruction) => instruction.size_at(offset),
+            Insn::Jump(_, _) if self.wide_jumps.contains(&idx) => 5,
+            Insn::Jump(_, _) => 3,
+            Insn::TableSwitch { ref targets, .. } => 1 + padding + 12 + targets.len() * 4,
+            Insn::LookupSwitch { ref pairs, .. } => 1 + padding + 8 + pairs.len() * 8,
+            Insn::Ldc(ref value) => {
+                if value.is_wide() || self.cp.value(value).idx > 255 {
+                    3
+                } else {
+                    2
+                }
+            }
+            Insn::Field(_, _) | Insn::Type(_, _) => 3,
+            Insn::Invoke(Invoke::Interface, _) => 5,
+            Insn::Invoke(_, _) => 3,
+            Insn::InvokeDynamic(_) => 5,
+            Insn::MultiANewArray(_, _) => 4,
+        }
+    }
+
+    fn offset(&self, label: &Label) -> Result<usize, ModelError> {
+        self.labels
+            .get(label)
+            .cloned()
+            .ok_or(ModelError::UnplacedLabel(*label))
+    }
+
+    fn relative(&self, idx: usize, label: &Label) -> Result<i32, ModelError> {
+        Ok((self.offset(label)? as i64 - self.offsets[idx] as i64) as i32)
+    }
+
+    fn write_instruction(&mut self, idx: usize, insn: &Insn) -> Result<Instruction, ModelError> {
+        Ok(match *insn {
+            Insn::Op(ref instruction) => {
+                if !is_plain_instruction(instruction) {
+                    return Err(ModelError::InvalidInstruction(instruction.clone()));
+                }
+
+                instruction.clone()
+            }
+            Insn::Jump(jump, ref target) => {
+                let relative = self.relative(idx, target)?;
+
+                if self.wide_jumps.contains(&idx) {
+                    jump.to_wide_instruction(relative)
+                        .ok_or(ModelError::BranchOutOfRange(*target))?
+                } else {
+                    jump.to_instruction(relative as i16)
+                }
+            }
+            Insn::TableSwitch {
+                ref default,
+                low,
+                high,
+                ref targets,
+            } => Instruction::TABLESWITCH(
+                self.relative(idx, default)?,
+                low,
+                high,
+                targets
+                    .iter()
+                    .map(|t| self.relative(idx, t))
+                    .collect::<Result<Vec<i32>, ModelError>>()?,
+            ),
+            Insn::LookupSwitch {
+                ref default,
+                ref pairs,
+            } => Instruction::LOOKUPSWITCH(
+                self.relative(idx, default)?,
+                pairs
+                    .iter()
+                    .map(|&(key, ref t)| self.relative(idx, t).map(|r| (key, r)))
+                    .collect::<Result<Vec<(i32, i32)>, ModelError>>()?,
+            ),
+            Insn::Ldc(ref value) => {
+                let cp_idx = self.cp.value(value).idx;
+
+                if value.is_wide() {
+                    Instruction::LDC2_W(cp_idx as u16)
+                } else if cp_idx > 255 {
+                    Instruction::LDC_W(cp_idx as u16)
+                } else {
+                    Instruction::LDC(cp_idx as u8)
+                }
+            }
+            Insn::Field(op, ref member) => {
+                let cp_idx = self.cp.field_ref(member).idx as u16;
+
+                match op {
+                    FieldOp::GetStatic => Instruction::GETSTATIC(cp_idx),
+                    FieldOp::PutStatic => Instruction::PUTSTATIC(cp_idx),
+                    FieldOp::GetField => Instruction::GETFIELD(cp_idx),
+                    FieldOp::PutField => Instruction::PUTFIELD(cp_idx),
+                }
+            }
+            Insn::Invoke(kind, ref member) => {
+                let cp_idx = self.cp.method_ref(member).idx as u16;
+
+                match kind {
+                    Invoke::Virtual => Instruction::INVOKEVIRTUAL(cp_idx),
+                    Invoke::Special => Instruction::INVOKESPECIAL(cp_idx),
+                    Invoke::Static => Instruction::INVOKESTATIC(cp_idx),
+                    Invoke::Interface => {
+                        let descriptor = MethodDescriptor::parse(&member.descriptor)
+                            .ok_or(ModelError::InvalidDescriptor(member.descriptor.clone()))?;
+
+                        Instruction::INVOKEINTERFACE(cp_idx, 1 + descriptor.parameter_size() as u8)
+                    }
+                }
+            }
+            Insn::InvokeDynamic(ref call_site) => {
+                Instruction::INVOKEDYNAMIC(self.cp.call_site(call_site).idx as u16)
+            }
+            Insn::Type(op, ref name) => {
+                let cp_idx = self.cp.class(name).idx as u16;
+
+                match op {
+                    TypeOp::New => Instruction::NEW(cp_idx),
+                    TypeOp::ANewArray => Instruction::ANEWARRAY(cp_idx),
+                    TypeOp::CheckCast => Instruction::CHECKCAST(cp_idx),
+                    TypeOp::InstanceOf => Instruction::INSTANCEOF(cp_idx),
+                }
+            }
+            Insn::MultiANewArray(ref name, dimensions) => {
+                Instruction::MULTIANEWARRAY(self.cp.class(name).idx as u16, dimensions)
+            }
+            Insn::Label(_) | Insn::LineNumber(_) | Insn::Frame(_) => unreachable!(),
+        })
+    }
+
+    /// Encode frames with the most compact frame type that describes them
+    fn write_frames(
+        &mut self,
+        initial: &Frame,
+        frames: &[(usize, &Frame)],
+    ) -> Result<Vec<StackMapFrame>, ModelError> {
+        let mut result = vec![];
+        let mut previous_locals = &initial.locals;
+        let mut previous_offset: i64 = -1;
+
+        for &(offset, frame) in frames {
+            if offset >= self.code_length {
+                return Err(ModelError::InvalidFrame(offset));
+            }
+
+            let delta = (offset as i64 - previous_offset - 1) as u16;
+            let same_locals = frame.locals == *previous_locals;
+
+            let encoded = if same_locals && frame.stack.is_empty() {
+                if delta < 64 {
+                    StackMapFrame::SameFrame { tag: delta as u8 }
+                } else {
+                    StackMapFrame::SameFrameExtended {
+                        offset_delta: delta,
+                    }
+                }
+            } else if same_locals && frame.stack.len() == 1 {
+                let stack = self.verification_type(&frame.stack[0])?;
+
+                if delta < 64 {
+                    StackMapFrame::SameLocals1StackItemFrame {
+                        tag: 64 + delta as u8,
+                        stack,
+                    }
+                } else {
+                    StackMapFrame::SameLocals1StackItemFrameExtended {
+                        offset_delta: delta,
+                        stack,
+                    }
+                }
+            } else if frame.stack.is_empty()
+                && frame.locals.len() < previous_locals.len()
+                && previous_locals.len() - frame.locals.len() <= 3
+                && previous_locals.starts_with(&frame.locals)
+            {
+                StackMapFrame::ChopFrame {
+                    tag: (251 - (previous_locals.len() - frame.locals.len())) as u8,
+                    offset_delta: delta,
+                }
+            } else if frame.stack.is_empty()
+                && frame.locals.len() > previous_locals.len()
+                && frame.locals.len() - previous_locals.len() <= 3
+                && frame.locals.starts_with(previous_locals)
+            {
+                StackMapFrame::AppendFrame {
+                    tag: (251 + (frame.locals.len() - previous_locals.len())) as u8,
+                    offset_delta: delta,
+                    locals: frame.locals[previous_locals.len()..]
+                        .iter()
+                        .map(|item| self.verification_type(item))
+                        .collect::<Result<Vec<VerificationType>, ModelError>>()?,
+                }
+            } else {
+                StackMapFrame::FullFrame {
+                    offset_delta: delta,
+                    locals: frame
+                        .locals
+                        .iter()
+                        .map(|item| self.verification_type(item))
+                        .collect::<Result<Vec<VerificationType>, ModelError>>()?,
+                    stack: frame
+                        .stack
+                        .iter()
+                        .map(|item| self.verification_type(item))
+                        .collect::<Result<Vec<VerificationType>, ModelError>>()?,
+                }
+            };
+
+            result.push(encoded);
+            previous_locals = &frame.locals;
+            previous_offset = offset as i64;
+        }
+
+        Ok(result)
+    }
+
+    fn verification_type(&mut self, item: &FrameItem) -> Result<VerificationType, ModelError> {
+        Ok(match *item {
+            FrameItem::Top => VerificationType::Top,
+            FrameItem::Integer => VerificationType::Integer,
+            FrameItem::Float => VerificationType::Float,
+            FrameItem::Long => VerificationType::Long,
+            FrameItem::Double => VerificationType::Double,
+            FrameItem::Null => VerificationType::Null,
+            FrameItem::UninitializedThis => VerificationType::UninitializedThis,
+            FrameItem::Object(ref name) => VerificationType::Object {
+                cpool_index: self.cp.class(name),
+            },
+            FrameItem::Uninitialized(ref label) => VerificationType::Uninitialized {
+                offset: self.offset(label)? as u16,
+            },
+        })
+    }
+}
@@ -6,6 +6,8 @@ extern crate libc;
 #[macro_use]
 extern crate lazy_static;
 extern crate time;
+extern crate serde;
+extern crate serde_json;
 extern crate toml;
 #[macro_use]
 extern crate serde_derive;
@@ -16,17 +18,22 @@ pub mod capabilities;
 pub mod class;
 pub mod config;
 pub mod context;
+pub mod debug;
+pub mod dump;
 pub mod emulator;
 pub mod environment;
 pub mod error;
 pub mod event;
 pub mod event_handler;
+pub mod exception;
 pub mod instrumentation;
 pub mod mem;
 pub mod method;
 pub mod native;
 pub mod options;
+pub mod perf;
 pub mod runtime;
+pub mod stack;
 pub mod thread;
 pub mod util;
 pub mod version;
@@ -1,6 +1,6 @@
 use std::ops::Deref;
 
-use crate::native::jvmti_native::jmethodID;
+use crate::native::jvmti_native::{jdouble, jfloat, jint, jlocation, jlong, jmethodID, jobject};
 
 use super::native::JavaMethod;
 
@@ -40,3 +40,59 @@ impl MethodSignature {
         }
     }
 }
+
+///
+/// An entry of a method's local variable table, as reported by `GetLocalVariableTable`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVariableEntry {
+    /// The code index where the variable is first valid
+    pub start_location: i64,
+    /// Length of the code range in which the variable is valid
+    pub length: i32,
+    pub name: String,
+    /// The type of the variable as a field descriptor, eg. `Ljava/lang/String;`
+    pub signature: String,
+    pub generic_signature: Option<String>,
+    pub slot: i32,
+}
+
+impl LocalVariableEntry {
+    /// Whether the variable is valid at the code index
+    pub fn is_live_at(&self, location: jlocation) -> bool {
+        location >= self.start_location && location < self.start_location + self.length as i64
+    }
+}
+
+///
+/// The value of a local variable, by the `GetLocal*` and `SetLocal*` function that accesses it.
+/// `Int` also holds `short`, `char`, `byte` and `boolean` variables.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LocalValue {
+    Int(jint),
+    Long(jlong),
+    Float(jfloat),
+    Double(jdouble),
+    /// A local reference to the object, or null
+    Object(jobject),
+}
+
+///
+/// A local variable of a frame, as listed by `get_frame_locals`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVariable {
+    pub name: String,
+    /// The type of the variable as a field descriptor, eg. `Ljava/lang/String;`
+    pub signature: String,
+    pub slot: i32,
+    /// The value of the variable, `None` if the VM couldn't read it
+    pub value: Option<LocalValue>,
+}
+
+///
+/// An entry of a method's line number table, as reported by `GetLineNumberTable`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineNumberEntry {
+    /// The code index where the line begins
+    pub start_location: i64,
+    pub line_number: i32,
+}
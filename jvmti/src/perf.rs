@@ -0,0 +1,543 @@
+use super::environment::jvmti::JVMTI;
+use super::error::NativeError;
+use super::method::LineNumberEntry;
+use super::native::jvmti_native::{jint, jlocation, jmethodID, jvmtiAddrLocationMap, jvmtiFrameInfo};
+use super::runtime::{AddressLocation, CompiledMethodLoadEvent, DynamicCodeGeneratedEvent, InlinedFrames};
+use super::stack::{line_number, optional, StackFrame};
+use libc::c_void;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process;
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+/// Kind of the `compile_info` records listing the methods inlined at each native address
+const JVMTI_CMLR_INLINE_INFO: jint = 2;
+const JVMTI_CMLR_MAJOR_VERSION_1: jint = 1;
+
+/// `jvmtiCompiledMethodLoadRecordHeader` of `jvmticmlr.h`
+#[repr(C)]
+struct RecordHeader {
+    kind: jint,
+    major_version: jint,
+    minor_version: jint,
+    next: *const RecordHeader,
+}
+
+/// `PCStackInfo` of `jvmticmlr.h`
+#[repr(C)]
+struct PcStackInfo {
+    pc: *const c_void,
+    frame_count: jint,
+    methods: *const jmethodID,
+    bcis: *const jint,
+}
+
+/// `jvmtiCompiledMethodLoadInlineRecord` of `jvmticmlr.h`
+#[repr(C)]
+struct InlineRecord {
+    header: RecordHeader,
+    pc_count: jint,
+    pc_info: *const PcStackInfo,
+}
+
+///
+/// The methods inlined at each native address listed by the `compile_info` records of a
+/// `CompiledMethodLoad` event, the innermost method first. Records of other kinds and versions
+/// are skipped.
+pub(crate) unsafe fn inlined_frames(compile_info: *const c_void) -> Vec<(usize, Vec<jvmtiFrameInfo>)> {
+    let mut pcs = vec![];
+    let mut record = compile_info as *const RecordHeader;
+
+    while !record.is_null() {
+        if (*record).kind == JVMTI_CMLR_INLINE_INFO && (*record).major_version == JVMTI_CMLR_MAJOR_VERSION_1 {
+            let inline = &*(record as *const InlineRecord);
+
+            for pc in raw_slice(inline.pc_info, inline.pc_count) {
+                let frames = raw_slice(pc.methods, pc.frame_count)
+                    .iter()
+                    .zip(raw_slice(pc.bcis, pc.frame_count))
+                    .map(|(&method, &bci)| jvmtiFrameInfo { method, location: bci as jlocation })
+                    .collect();
+
+                pcs.push((pc.pc as usize, frames));
+            }
+        }
+
+        record = (*record).next;
+    }
+
+    pcs
+}
+
+unsafe fn raw_slice<'a, T>(items: *const T, count: jint) -> &'a [T] {
+    if items.is_null() || count <= 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(items, count as usize)
+    }
+}
+
+///
+/// Build the event of a compiled method from its address location map and inlined frames, see
+/// `inlined_frames`
+pub fn compiled_method_load_event(
+    jvmti: &dyn JVMTI,
+    method: jmethodID,
+    code_address: usize,
+    code_size: usize,
+    map: &[jvmtiAddrLocationMap],
+    inlining: &[(usize, Vec<jvmtiFrameInfo>)],
+) -> Result<CompiledMethodLoadEvent, NativeError> {
+    let mut methods = Methods::new(jvmti);
+    let compiled = methods.frame(method, -1)?;
+    let mut locations = vec![];
+    let mut inlined = vec![];
+
+    for entry in map {
+        locations.push(AddressLocation {
+            address: entry.start_address as usize,
+            location: entry.location,
+            line: methods.frame(method, entry.location)?.line,
+        });
+    }
+
+    for &(address, ref frames) in inlining {
+        let mut resolved = vec![];
+
+        for frame in frames {
+            resolved.push(methods.frame(frame.method, frame.location)?);
+        }
+
+        inlined.push(InlinedFrames { address, frames: resolved });
+    }
+
+    locations.sort_by_key(|location| location.address);
+    inlined.sort_by_key(|frames| frames.address);
+
+    Ok(CompiledMethodLoadEvent {
+        method,
+        class_name: compiled.class_name,
+        method_name: compiled.method_name,
+        signature: compiled.signature,
+        source_file: compiled.source_file,
+        code_address,
+        code_size,
+        locations,
+        inlining: inlined,
+    })
+}
+
+/// Methods resolved while building an event, as the same methods are inlined at many addresses
+struct Methods<'a> {
+    jvmti: &'a dyn JVMTI,
+    resolved: HashMap<jmethodID, (StackFrame, Option<Vec<LineNumberEntry>>)>,
+}
+
+impl<'a> Methods<'a> {
+    fn new(jvmti: &'a dyn JVMTI) -> Methods<'a> {
+        Methods { jvmti, resolved: HashMap::new() }
+    }
+
+    fn frame(&mut self, method: jmethodID, location: jlocation) -> Result<StackFrame, NativeError> {
+        if !self.resolved.contains_key(&method) {
+            let frame = StackFrame::resolve(self.jvmti, &jvmtiFrameInfo { method, location: -1 })?;
+            let table = optional(self.jvmti.get_line_number_table(method))?;
+            self.resolved.insert(method, (frame, table));
+        }
+
+        let (frame, table) = &self.resolved[&method];
+
+        Ok(StackFrame {
+            location,
+            line: table.as_ref().and_then(|table| if location < 0 { None } else { line_number(table, location) }),
+            ..frame.clone()
+        })
+    }
+}
+
+///
+/// A perf map, which lists the symbols of the code generated by the process with a `START SIZE
+/// name` line each, in hexadecimal
+pub struct PerfMap<W: Write> {
+    out: W,
+    unfold_inlined: bool,
+}
+
+impl PerfMap<File> {
+    ///
+    /// Create `/tmp/perf-<pid>.map`, where perf looks for the symbols of the process
+    pub fn create(unfold_inlined: bool) -> io::Result<PerfMap<File>> {
+        let file = File::create(format!("/tmp/perf-{}.map", process::id()))?;
+        Ok(PerfMap::new(file, unfold_inlined))
+    }
+}
+
+impl<W: Write> PerfMap<W> {
+    ///
+    /// With `unfold_inlined` the code of a compiled method is split by the methods inlined into
+    /// it, and each range is named by the chain of inlined methods, eg. `A.run()V->B.size()I`
+    pub fn new(out: W, unfold_inlined: bool) -> PerfMap<W> {
+        PerfMap { out, unfold_inlined }
+    }
+
+    pub fn compiled_method(&mut self, event: &CompiledMethodLoadEvent) -> io::Result<()> {
+        if self.unfold_inlined && !event.inlining.is_empty() {
+            for (start, end, name) in unfolded(event) {
+                self.entry(start, end - start, &name)?;
+            }
+
+            Ok(())
+        } else {
+            self.entry(event.code_address, event.code_size, &event.symbol())
+        }
+    }
+
+    pub fn dynamic_code(&mut self, event: &DynamicCodeGeneratedEvent) -> io::Result<()> {
+        self.entry(event.code_address, event.code_size, &event.name)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    fn entry(&mut self, address: usize, size: usize, name: &str) -> io::Result<()> {
+        // A single write, so that perf never reads half a line
+        self.out.write_all(format!("{:x} {:x} {}\n", address, size, name).as_bytes())
+    }
+}
+
+/// The code of the compiled method split by the methods inlined into it. The compiler records
+/// the inlined methods after the instructions they describe, so each range ends at an address.
+fn unfolded(event: &CompiledMethodLoadEvent) -> Vec<(usize, usize, String)> {
+    let mut ranges: Vec<(usize, usize, String)> = vec![];
+    let end_of_code = event.code_address + event.code_size;
+    let mut start = event.code_address;
+
+    let extend = |ranges: &mut Vec<(usize, usize, String)>, start: usize, end: usize, name: String| match ranges.last_mut() {
+        Some(last) if last.1 == start && last.2 == name => last.1 = end,
+        _ => ranges.push((start, end, name)),
+    };
+
+    for inlined in &event.inlining {
+        if inlined.address <= start || inlined.address > end_of_code {
+            continue;
+        }
+
+        let name = if inlined.frames.is_empty() {
+            event.symbol()
+        } else {
+            inlined.frames.iter().rev().map(frame_symbol).collect::<Vec<String>>().join("->")
+        };
+
+        extend(&mut ranges, start, inlined.address, name);
+        start = inlined.address;
+    }
+
+    if start < end_of_code {
+        extend(&mut ranges, start, end_of_code, event.symbol());
+    }
+
+    ranges
+}
+
+fn frame_symbol(frame: &StackFrame) -> String {
+    format!("{}.{}{}", frame.class_name, frame.method_name, frame.signature)
+}
+
+const JITDUMP_MAGIC: u32 = 0x4A69_5444;
+const JITDUMP_VERSION: u32 = 1;
+const JITDUMP_HEADER_SIZE: u32 = 40;
+const JIT_CODE_LOAD: u32 = 0;
+const JIT_CODE_DEBUG_INFO: u32 = 2;
+const JIT_CODE_CLOSE: u32 = 3;
+
+///
+/// A jitdump, which records the code generated by the process with its symbols and source lines
+/// for `perf inject --jit`. Its timestamps are read from `CLOCK_MONOTONIC`, so the profile has to
+/// be recorded with `perf record -k mono`.
+pub struct JitDump<W: Write> {
+    out: W,
+    pid: u32,
+    code_index: u64,
+    /// Address and length of the mapping that makes perf find the dump, unmapped on close
+    mapping: Option<(usize, usize)>,
+}
+
+impl JitDump<File> {
+    ///
+    /// Create `jit-<pid>.dump` in the directory and map it into the process, as perf finds the
+    /// dump by its mapping
+    pub fn create(directory: &Path) -> io::Result<JitDump<File>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(directory.join(format!("jit-{}.dump", process::id())))?;
+
+        let length = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let address = unsafe {
+            libc::mmap(ptr::null_mut(), length, libc::PROT_READ | libc::PROT_EXEC, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut dump = JitDump::new(file, process::id())?;
+        dump.mapping = Some((address as usize, length));
+        Ok(dump)
+    }
+}
+
+impl<W: Write> JitDump<W> {
+    ///
+    /// Start the dump of the process by writing its header
+    pub fn new(mut out: W, pid: u32) -> io::Result<JitDump<W>> {
+        let mut header = Vec::with_capacity(JITDUMP_HEADER_SIZE as usize);
+        header.extend_from_slice(&JITDUMP_MAGIC.to_ne_bytes());
+        header.extend_from_slice(&JITDUMP_VERSION.to_ne_bytes());
+        header.extend_from_slice(&JITDUMP_HEADER_SIZE.to_ne_bytes());
+        header.extend_from_slice(&elf_machine().to_ne_bytes());
+        header.extend_from_slice(&0u32.to_ne_bytes());
+        header.extend_from_slice(&pid.to_ne_bytes());
+        header.extend_from_slice(&timestamp().to_ne_bytes());
+        header.extend_from_slice(&0u64.to_ne_bytes());
+        out.write_all(&header)?;
+
+        Ok(JitDump { out, pid, code_index: 0, mapping: None })
+    }
+
+    ///
+    /// Record the compiled code of the method, preceded by its source lines. The lines of the
+    /// innermost inlined methods are used where the compiler recorded them.
+    pub fn compiled_method(&mut self, event: &CompiledMethodLoadEvent, code: &[u8]) -> io::Result<()> {
+        let lines: Vec<(usize, u32, String)> = if event.inlining.is_empty() {
+            let path = source_path(&event.class_name, &event.source_file);
+
+            event
+                .locations
+                .iter()
+                .filter_map(|location| match (location.line, &path) {
+                    (Some(line), Some(path)) => Some((location.address, line, path.clone())),
+                    _ => None,
+                })
+                .collect()
+        } else {
+            event
+                .inlining
+                .iter()
+                .filter_map(|inlined| {
+                    let frame = inlined.frames.first()?;
+                    Some((inlined.address, frame.line?, source_path(&frame.class_name, &frame.source_file)?))
+                })
+                .collect()
+        };
+
+        if !lines.is_empty() {
+            self.debug_info(event.code_address, &lines)?;
+        }
+
+        self.code_load(&event.symbol(), event.code_address, code)
+    }
+
+    pub fn dynamic_code(&mut self, event: &DynamicCodeGeneratedEvent, code: &[u8]) -> io::Result<()> {
+        self.code_load(&event.name, event.code_address, code)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    ///
+    /// End the dump and unmap it
+    pub fn close(mut self) -> io::Result<()> {
+        self.record(JIT_CODE_CLOSE, &[])?;
+
+        if let Some((address, length)) = self.mapping.take() {
+            unsafe { libc::munmap(address as *mut c_void, length) };
+        }
+
+        Ok(())
+    }
+
+    fn code_load(&mut self, name: &str, address: usize, code: &[u8]) -> io::Result<()> {
+        let mut body = Vec::with_capacity(40 + name.len() + 1 + code.len());
+        body.extend_from_slice(&self.pid.to_ne_bytes());
+        body.extend_from_slice(&thread_id().to_ne_bytes());
+        body.extend_from_slice(&(address as u64).to_ne_bytes());
+        body.extend_from_slice(&(address as u64).to_ne_bytes());
+        body.extend_from_slice(&(code.len() as u64).to_ne_bytes());
+        body.extend_from_slice(&self.code_index.to_ne_bytes());
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        body.extend_from_slice(code);
+
+        self.code_index += 1;
+        self.record(JIT_CODE_LOAD, &body)
+    }
+
+    fn debug_info(&mut self, address: usize, lines: &[(usize, u32, String)]) -> io::Result<()> {
+        let mut body = vec![];
+        body.extend_from_slice(&(address as u64).to_ne_bytes());
+        body.extend_from_slice(&(lines.len() as u64).to_ne_bytes());
+
+        for &(address, line, ref path) in lines {
+            body.extend_from_slice(&(address as u64).to_ne_bytes());
+            body.extend_from_slice(&(line as i32).to_ne_bytes());
+            // Discriminator
+            body.extend_from_slice(&0i32.to_ne_bytes());
+            body.extend_from_slice(path.as_bytes());
+            body.push(0);
+        }
+
+        // perf expects debug information records to be aligned to 8 bytes
+        while (body.len() + 16) % 8 != 0 {
+            body.push(0);
+        }
+
+        self.record(JIT_CODE_DEBUG_INFO, &body)
+    }
+
+    fn record(&mut self, id: u32, body: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(16 + body.len());
+        record.extend_from_slice(&id.to_ne_bytes());
+        record.extend_from_slice(&((16 + body.len()) as u32).to_ne_bytes());
+        record.extend_from_slice(&timestamp().to_ne_bytes());
+        record.extend_from_slice(body);
+
+        // A single write, so that the records stay whole if the process dies
+        self.out.write_all(&record)
+    }
+}
+
+/// The path of the source file relative to the source root, eg. `com/acme/Orders.java`
+fn source_path(class_name: &str, source_file: &Option<String>) -> Option<String> {
+    let source_file = source_file.as_ref()?;
+
+    match class_name.rfind('.') {
+        Some(end) => Some(format!("{}/{}", class_name[..end].replace('.', "/"), source_file)),
+        None => Some(source_file.clone()),
+    }
+}
+
+fn timestamp() -> u64 {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+#[cfg(target_os = "linux")]
+fn thread_id() -> u32 {
+    unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn thread_id() -> u32 {
+    process::id()
+}
+
+/// The ELF machine of the process, `EM_NONE` for unknown architectures
+fn elf_machine() -> u32 {
+    if cfg!(target_arch = "x86_64") {
+        62
+    } else if cfg!(target_arch = "aarch64") {
+        183
+    } else if cfg!(target_arch = "x86") {
+        3
+    } else if cfg!(target_arch = "arm") {
+        40
+    } else if cfg!(target_arch = "riscv64") {
+        243
+    } else {
+        0
+    }
+}
+
+///
+/// The perf map and jitdump written from the `CompiledMethodLoad` and `DynamicCodeGenerated`
+/// events. Unloaded code stays in them, as neither has a record for it.
+pub struct PerfOutput {
+    perf_map: Mutex<Option<PerfMap<File>>>,
+    jitdump: Mutex<Option<JitDump<File>>>,
+}
+
+impl Default for PerfOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PerfOutput {
+    pub fn new() -> PerfOutput {
+        PerfOutput {
+            perf_map: Mutex::new(None),
+            jitdump: Mutex::new(None),
+        }
+    }
+
+    ///
+    /// Write the generated code to `/tmp/perf-<pid>.map`, see `PerfMap::new`
+    pub fn write_perf_map(&self, unfold_inlined: bool) -> io::Result<()> {
+        *self.perf_map() = Some(PerfMap::create(unfold_inlined)?);
+        Ok(())
+    }
+
+    ///
+    /// Write the generated code to `jit-<pid>.dump` in the directory
+    pub fn write_jitdump(&self, directory: &Path) -> io::Result<()> {
+        *self.jitdump() = Some(JitDump::create(directory)?);
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.perf_map().is_some() || self.jitdump().is_some()
+    }
+
+    pub fn compiled_method(&self, event: &CompiledMethodLoadEvent, code: &[u8]) -> io::Result<()> {
+        if let Some(ref mut perf_map) = *self.perf_map() {
+            perf_map.compiled_method(event)?;
+        }
+
+        if let Some(ref mut jitdump) = *self.jitdump() {
+            jitdump.compiled_method(event, code)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn dynamic_code(&self, event: &DynamicCodeGeneratedEvent, code: &[u8]) -> io::Result<()> {
+        if let Some(ref mut perf_map) = *self.perf_map() {
+            perf_map.dynamic_code(event)?;
+        }
+
+        if let Some(ref mut jitdump) = *self.jitdump() {
+            jitdump.dynamic_code(event, code)?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// End the jitdump, the perf map needs no ending
+    pub fn close(&self) -> io::Result<()> {
+        match self.jitdump().take() {
+            Some(jitdump) => jitdump.close(),
+            None => Ok(()),
+        }
+    }
+
+    // The outputs are written from VM callbacks, which mustn't fail because another thread panicked
+    fn perf_map(&self) -> MutexGuard<'_, Option<PerfMap<File>>> {
+        self.perf_map.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn jitdump(&self) -> MutexGuard<'_, Option<JitDump<File>>> {
+        self.jitdump.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -1,7 +1,13 @@
+use crate::native::jvmti_native::{
+    jboolean, jclass, jfieldID, jbyte, jchar, jdouble, jfloat, jint, jlocation, jlong, jmethodID, jobject, jshort, jthread, jvalue,
+};
 use crate::native::JavaClass;
 
 use super::class::ClassSignature;
+use super::environment::jvmti::JVMTI;
+use super::error::NativeError;
 use super::method::{MethodId, MethodSignature};
+use super::stack::{binary_name, StackFrame, StackTrace};
 use super::thread::Thread;
 
 pub trait RuntimeEvent {}
@@ -30,3 +36,334 @@ pub struct ClassFileLoadEvent {
 }
 
 impl RuntimeEvent for ClassFileLoadEvent {}
+
+///
+/// A thread reaching an instruction, sent for breakpoints and completed steps
+pub struct LocationEvent {
+    pub thread: jthread,
+    pub method: jmethodID,
+    pub location: jlocation,
+    /// Source line of the instruction, `None` if the method has no line numbers
+    pub line: Option<u32>,
+}
+
+impl RuntimeEvent for LocationEvent {}
+
+///
+/// A frame requested through `NotifyFramePop` returning, or being popped by an exception
+#[derive(Clone, Copy)]
+pub struct FramePopEvent {
+    pub thread: jthread,
+    pub method: jmethodID,
+    pub was_popped_by_exception: bool,
+}
+
+impl RuntimeEvent for FramePopEvent {}
+
+///
+/// A thread reading a watched field
+pub struct FieldAccessEvent {
+    pub thread: jthread,
+    /// The accessing method and instruction
+    pub method: jmethodID,
+    pub location: jlocation,
+    /// Source line of the instruction, `None` if the method has no line numbers
+    pub line: Option<u32>,
+    /// Binary name of the class owning the field, eg. `com.acme.Orders`
+    pub class_name: String,
+    pub field_name: String,
+    /// JVM type signature of the field, eg. `I`
+    pub field_signature: String,
+    /// The object whose field is read, null for static fields
+    pub object: jobject,
+}
+
+impl RuntimeEvent for FieldAccessEvent {}
+
+///
+/// A thread assigning a watched field
+pub struct FieldModificationEvent {
+    pub thread: jthread,
+    /// The modifying method and instruction
+    pub method: jmethodID,
+    pub location: jlocation,
+    /// Source line of the instruction, `None` if the method has no line numbers
+    pub line: Option<u32>,
+    /// Binary name of the class owning the field, eg. `com.acme.Orders`
+    pub class_name: String,
+    pub field_name: String,
+    /// JVM type signature of the field, eg. `I`
+    pub field_signature: String,
+    /// The object whose field is assigned, null for static fields
+    pub object: jobject,
+    pub new_value: FieldValue,
+}
+
+impl RuntimeEvent for FieldModificationEvent {}
+
+///
+/// The value assigned to a field, by the field's type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldValue {
+    Boolean(bool),
+    Byte(jbyte),
+    Char(jchar),
+    Short(jshort),
+    Int(jint),
+    Long(jlong),
+    Float(jfloat),
+    Double(jdouble),
+    /// A local reference to the object or array, or null
+    Object(jobject),
+}
+
+impl FieldValue {
+    ///
+    /// Read the value by the first character of the field's type signature, `None` if the
+    /// character isn't a field type
+    pub fn decode(signature_type: u8, value: jvalue) -> Option<FieldValue> {
+        let mut value = value;
+
+        unsafe {
+            match signature_type {
+                b'Z' => Some(FieldValue::Boolean(*(value.z() as *const jboolean) != 0)),
+                b'B' => Some(FieldValue::Byte(*value.b())),
+                b'C' => Some(FieldValue::Char(*value.c())),
+                b'S' => Some(FieldValue::Short(*value.s())),
+                b'I' => Some(FieldValue::Int(*value.i())),
+                b'J' => Some(FieldValue::Long(*value.j())),
+                b'F' => Some(FieldValue::Float(*value.f())),
+                b'D' => Some(FieldValue::Double(*value.d())),
+                b'L' | b'[' => Some(FieldValue::Object(*value.l())),
+                _ => None,
+            }
+        }
+    }
+}
+
+///
+/// An exception thrown by Java or native code
+pub struct ExceptionEvent {
+    pub thread: jthread,
+    pub exception: jobject,
+    /// Binary name of the exception's class, eg. `java.sql.SQLException`
+    pub class_name: String,
+    /// The exception's `getMessage()`, `None` if it's null or the call failed
+    pub message: Option<String>,
+    /// The frame that threw the exception
+    pub throw_frame: StackFrame,
+    /// The frame that will catch the exception, `None` if it's uncaught
+    pub catch_frame: Option<StackFrame>,
+    /// The thread's stack, if `ExceptionFilter` captures stack traces
+    pub stack_trace: Option<StackTrace>,
+}
+
+impl ExceptionEvent {
+    pub fn is_uncaught(&self) -> bool {
+        self.catch_frame.is_none()
+    }
+}
+
+impl RuntimeEvent for ExceptionEvent {}
+
+///
+/// An exception being caught, sent when the catching frame is reached
+pub struct ExceptionCatchEvent {
+    pub thread: jthread,
+    pub exception: jobject,
+    /// Binary name of the exception's class, eg. `java.sql.SQLException`
+    pub class_name: String,
+    /// The exception's `getMessage()`, `None` if it's null or the call failed
+    pub message: Option<String>,
+    /// The frame catching the exception, at the start of the handler
+    pub catch_frame: StackFrame,
+    /// The thread's stack, if `ExceptionFilter` captures stack traces
+    pub stack_trace: Option<StackTrace>,
+}
+
+impl RuntimeEvent for ExceptionCatchEvent {}
+
+///
+/// A class being loaded, before it's linked. Its methods and fields aren't available yet.
+pub struct ClassLoadEvent {
+    pub thread: jthread,
+    pub class: jclass,
+    /// Binary name of the class, eg. `com.acme.Orders`
+    pub class_name: String,
+    /// JVM type signature of the class, eg. `Lcom/acme/Orders;`
+    pub signature: String,
+    /// The defining class loader, null for the bootstrap class loader
+    pub loader: jobject,
+}
+
+impl ClassLoadEvent {
+    pub fn resolve(jvmti: &dyn JVMTI, thread: jthread, class: jclass) -> Result<ClassLoadEvent, NativeError> {
+        let signature = jvmti.get_class_signature(&class)?.native_sig;
+
+        Ok(ClassLoadEvent {
+            thread,
+            class,
+            class_name: binary_name(&signature),
+            signature,
+            loader: jvmti.get_classloader(&class)?,
+        })
+    }
+}
+
+impl RuntimeEvent for ClassLoadEvent {}
+
+///
+/// A method declared by a class
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclaredMethod {
+    pub id: jmethodID,
+    pub name: String,
+    /// Descriptor of the method, eg. `(Ljava/util/List;)I`
+    pub signature: String,
+}
+
+///
+/// A field declared by a class
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclaredField {
+    pub id: jfieldID,
+    pub name: String,
+    /// JVM type signature of the field, eg. `I`
+    pub signature: String,
+}
+
+///
+/// A class that has been linked and is about to be initialised. Breakpoints and field watches
+/// can be set on it from this point on.
+pub struct ClassPrepareEvent {
+    pub thread: jthread,
+    pub class: jclass,
+    /// Binary name of the class, eg. `com.acme.Orders`
+    pub class_name: String,
+    /// JVM type signature of the class, eg. `Lcom/acme/Orders;`
+    pub signature: String,
+    /// The defining class loader, null for the bootstrap class loader
+    pub loader: jobject,
+    /// Methods declared by the class, including constructors and the static initialiser
+    pub methods: Vec<DeclaredMethod>,
+    /// Fields declared by the class
+    pub fields: Vec<DeclaredField>,
+}
+
+impl ClassPrepareEvent {
+    pub fn resolve(jvmti: &dyn JVMTI, thread: jthread, class: jclass) -> Result<ClassPrepareEvent, NativeError> {
+        let loaded = ClassLoadEvent::resolve(jvmti, thread, class)?;
+        let mut methods = vec![];
+        let mut fields = vec![];
+
+        for method in jvmti.get_class_methods(&class)? {
+            let name = jvmti.get_method_name(method)?;
+
+            methods.push(DeclaredMethod {
+                id: method,
+                name: name.name,
+                signature: name.signature,
+            });
+        }
+
+        for field in jvmti.get_class_fields(&class)? {
+            let (name, signature) = jvmti.get_field_name(&class, field)?;
+
+            fields.push(DeclaredField {
+                id: field,
+                name,
+                signature,
+            });
+        }
+
+        Ok(ClassPrepareEvent {
+            thread: loaded.thread,
+            class: loaded.class,
+            class_name: loaded.class_name,
+            signature: loaded.signature,
+            loader: loaded.loader,
+            methods,
+            fields,
+        })
+    }
+
+    /// The declared method with the name, the first of them if the method is overloaded
+    pub fn method(&self, name: &str) -> Option<&DeclaredMethod> {
+        self.methods.iter().find(|method| method.name == name)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&DeclaredField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+impl RuntimeEvent for ClassPrepareEvent {}
+
+///
+/// A native address of compiled code and the instruction of the method it was compiled from
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressLocation {
+    pub address: usize,
+    pub location: jlocation,
+    /// Source line of the instruction, `None` if the method has no line numbers
+    pub line: Option<u32>,
+}
+
+///
+/// The methods executed at a native address of compiled code, as recorded by the compiler
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlinedFrames {
+    pub address: usize,
+    /// The innermost inlined method first and the compiled method last, their locations are
+    /// bytecode indices
+    pub frames: Vec<StackFrame>,
+}
+
+///
+/// A method compiled to native code, sent as it's compiled and, for the methods compiled before,
+/// on `GenerateEvents`
+pub struct CompiledMethodLoadEvent {
+    pub method: jmethodID,
+    /// Binary name of the declaring class, eg. `com.acme.Orders`
+    pub class_name: String,
+    pub method_name: String,
+    /// Descriptor of the method, eg. `(Ljava/util/List;)I`
+    pub signature: String,
+    /// Name of the declaring class' source file, `None` if the class was compiled without it
+    pub source_file: Option<String>,
+    pub code_address: usize,
+    pub code_size: usize,
+    /// Native addresses mapped to the instructions of the method, empty if the VM doesn't map them
+    pub locations: Vec<AddressLocation>,
+    /// The methods inlined at native addresses, from the `compile_info` records, empty if the VM
+    /// doesn't record them
+    pub inlining: Vec<InlinedFrames>,
+}
+
+impl CompiledMethodLoadEvent {
+    /// The name of the compiled code, eg. `com.acme.Orders.count(Ljava/util/List;)I`
+    pub fn symbol(&self) -> String {
+        format!("{}.{}{}", self.class_name, self.method_name, self.signature)
+    }
+}
+
+impl RuntimeEvent for CompiledMethodLoadEvent {}
+
+///
+/// Compiled code of a method being freed, eg. when the method is deoptimised or its class unloaded
+pub struct CompiledMethodUnloadEvent {
+    pub method: jmethodID,
+    pub code_address: usize,
+}
+
+impl RuntimeEvent for CompiledMethodUnloadEvent {}
+
+///
+/// Native code generated by the VM itself, eg. the interpreter and its stubs
+pub struct DynamicCodeGeneratedEvent {
+    pub name: String,
+    pub code_address: usize,
+    pub code_size: usize,
+}
+
+impl RuntimeEvent for DynamicCodeGeneratedEvent {}
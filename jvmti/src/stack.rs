@@ -0,0 +1,191 @@
+use std::fmt;
+
+use super::class::JavaType;
+use super::environment::jvmti::JVMTI;
+use super::error::NativeError;
+use super::method::LineNumberEntry;
+use super::native::jvmti_native::{jint, jlocation, jmethodID, jthread, jvmtiFrameInfo};
+
+///
+/// The raw stack of a thread, as reported by `GetAllStackTraces` and `GetThreadListStackTraces`
+#[derive(Clone)]
+pub struct StackInfo {
+    pub thread: jthread,
+    /// The thread's state bits, see `ThreadState`
+    pub state: u32,
+    /// The frames of the stack, the most recent frame first
+    pub frames: Vec<jvmtiFrameInfo>,
+}
+
+/// The number of frames captured when no maximum depth is given
+pub const DEFAULT_MAX_DEPTH: jint = 64;
+
+///
+/// A resolved frame of a thread's stack
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StackFrame {
+    #[serde(skip)]
+    pub method: jmethodID,
+    /// The index of the instruction being executed, -1 for native methods
+    pub location: jlocation,
+    /// Binary name of the declaring class, eg. `com.acme.Orders`
+    pub class_name: String,
+    pub method_name: String,
+    /// Descriptor of the method, eg. `(Ljava/util/List;)I`
+    pub signature: String,
+    /// Name of the declaring class' source file, `None` if the class was compiled without it or
+    /// the environment lacks `can_get_source_file_name`
+    pub source_file: Option<String>,
+    /// The source line being executed, `None` if the class was compiled without line numbers or
+    /// the environment lacks `can_get_line_numbers`
+    pub line: Option<u32>,
+}
+
+/// Marker trait implementation for `Send`, method ids are valid on any thread
+unsafe impl Send for StackFrame {}
+
+/// Marker trait implementation for `Sync`
+unsafe impl Sync for StackFrame {}
+
+impl StackFrame {
+    ///
+    /// Resolve the declaring class, name, source file and line of a raw frame
+    pub fn resolve(jvmti: &dyn JVMTI, frame: &jvmtiFrameInfo) -> Result<StackFrame, NativeError> {
+        let class = jvmti.get_method_declaring_class(&frame.method)?;
+        let class_signature = jvmti.get_class_signature(&class.native_id)?;
+        let method = jvmti.get_method_name(frame.method)?;
+
+        let line = if frame.location < 0 {
+            None
+        } else {
+            optional(jvmti.get_line_number_table(frame.method))?.and_then(|table| line_number(&table, frame.location))
+        };
+
+        Ok(StackFrame {
+            method: frame.method,
+            location: frame.location,
+            class_name: binary_name(&class_signature.native_sig),
+            method_name: method.name,
+            signature: method.signature,
+            source_file: optional(jvmti.get_source_file_name(&class.native_id))?,
+            line,
+        })
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.location == -1
+    }
+}
+
+impl fmt::Display for StackFrame {
+    /// Renders the frame the way `StackTraceElement` does, eg. `com.acme.Orders.count(Orders.java:10)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}(", self.class_name, self.method_name)?;
+
+        match (self.is_native(), &self.source_file, self.line) {
+            (true, _, _) => write!(f, "Native Method)"),
+            (false, Some(source_file), Some(line)) => write!(f, "{}:{})", source_file, line),
+            (false, Some(source_file), None) => write!(f, "{})", source_file),
+            (false, &None, _) => write!(f, "Unknown Source)"),
+        }
+    }
+}
+
+///
+/// The resolved frames of a thread's stack, the most recent frame first
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackTrace {
+    /// The depth of the first frame, as requested
+    pub start_depth: jint,
+    pub frames: Vec<StackFrame>,
+    /// Whether the stack has more frames than the requested maximum depth
+    pub truncated: bool,
+}
+
+impl StackTrace {
+    ///
+    /// Capture up to `max_depth` frames of the thread's stack, skipping `start_depth` frames from
+    /// the top. A negative start depth counts from the bottom of the stack instead.
+    pub fn capture(jvmti: &dyn JVMTI, thread: jthread, start_depth: jint, max_depth: jint) -> Result<StackTrace, NativeError> {
+        // One more frame is requested to tell whether the stack is truncated
+        let mut raw = jvmti.get_stack_trace_range(thread, start_depth, max_depth.saturating_add(1))?;
+        let truncated = raw.len() > max_depth.max(0) as usize;
+        raw.truncate(max_depth.max(0) as usize);
+
+        Ok(StackTrace {
+            start_depth,
+            frames: StackTrace::resolve(jvmti, &raw)?,
+            truncated,
+        })
+    }
+
+    ///
+    /// Capture the top `DEFAULT_MAX_DEPTH` frames of the thread's stack
+    pub fn of(jvmti: &dyn JVMTI, thread: jthread) -> Result<StackTrace, NativeError> {
+        StackTrace::capture(jvmti, thread, 0, DEFAULT_MAX_DEPTH)
+    }
+
+    ///
+    /// Resolve raw frames, eg. the ones reported by `GetStackTrace` or `GetAllStackTraces`
+    pub fn resolve(jvmti: &dyn JVMTI, frames: &[jvmtiFrameInfo]) -> Result<Vec<StackFrame>, NativeError> {
+        frames.iter().map(|frame| StackFrame::resolve(jvmti, frame)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The most recent frame
+    pub fn top(&self) -> Option<&StackFrame> {
+        self.frames.first()
+    }
+}
+
+impl fmt::Display for StackTrace {
+    /// Renders the frames the way `Throwable.printStackTrace` does, one `\tat` line per frame
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for frame in &self.frames {
+            writeln!(f, "\tat {}", frame)?;
+        }
+
+        if self.truncated {
+            writeln!(f, "\t...")?;
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// The line of the code index, ie. the line of the entry with the greatest start location at or
+/// before it. Tables aren't necessarily ordered.
+pub fn line_number(table: &[LineNumberEntry], location: jlocation) -> Option<u32> {
+    table
+        .iter()
+        .filter(|entry| entry.start_location <= location)
+        .max_by_key(|entry| entry.start_location)
+        .map(|entry| entry.line_number as u32)
+}
+
+/// The binary name of a class signature, eg. `com.acme.Orders` for `Lcom/acme/Orders;`
+pub(crate) fn binary_name(signature: &str) -> String {
+    match JavaType::parse(signature) {
+        Some(ref java_type) => JavaType::to_string(java_type),
+        None => signature.to_string(),
+    }
+}
+
+/// Debug information that the class or the environment doesn't provide is reported as missing
+pub(crate) fn optional<T>(result: Result<T, NativeError>) -> Result<Option<T>, NativeError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(NativeError::AbsentInformation)
+        | Err(NativeError::MustPossessCapability)
+        | Err(NativeError::NativeMethod) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
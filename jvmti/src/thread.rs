@@ -1,4 +1,19 @@
-use super::native::JavaThread;
+use super::environment::jni::{JNIError, JNI};
+use super::environment::jvm::{JVMAgent, JVMF};
+use super::environment::jvmti::{JVMTIEnvironment, JVMTI};
+use super::error::NativeError;
+use super::native::jvmti_native::jthread;
+use super::native::{JVMTIEnvPtr, JavaThread, JavaVMPtr};
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, UnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, Once, TryLockError};
+use std::thread;
+
+thread_local! {
+    /// Whether the thread runs code whose panics are caught, see `catch_panic`
+    static ISOLATED: Cell<bool> = const { Cell::new(false) };
+}
 
 ///
 /// Represents a link between a JVM thread and the Rust code calling the JVMTI API.
@@ -26,3 +41,255 @@ pub struct Thread {
     pub priority: u32,
     pub is_daemon: bool,
 }
+
+///
+/// Describes why a thread couldn't be suspended or resumed
+#[derive(Debug)]
+pub enum ThreadControlError {
+    Jvmti(NativeError),
+    Jni(JNIError),
+}
+
+impl fmt::Display for ThreadControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ThreadControlError::Jvmti(ref err) => write!(f, "JVMTI error: {}", err),
+            ThreadControlError::Jni(ref err) => write!(f, "JNI error: {:?}", err),
+        }
+    }
+}
+
+impl From<NativeError> for ThreadControlError {
+    fn from(err: NativeError) -> ThreadControlError {
+        ThreadControlError::Jvmti(err)
+    }
+}
+
+impl From<JNIError> for ThreadControlError {
+    fn from(err: JNIError) -> ThreadControlError {
+        ThreadControlError::Jni(err)
+    }
+}
+
+///
+/// The threads the agent has suspended and not resumed yet. Each of them is held through a global
+/// reference, so that `resume_all` can resume whatever the agent leaves suspended when it unloads.
+/// Once a thread has been suspended, a panic hook is installed that resumes them as well, chained
+/// to the previously installed hook. Panics caught by `catch_panic` leave them suspended.
+pub struct SuspendedThreads {
+    /// Global references of the suspended threads
+    threads: Arc<Mutex<Vec<ThreadId>>>,
+    panic_hook: Once,
+}
+
+impl Default for SuspendedThreads {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SuspendedThreads {
+    pub fn new() -> SuspendedThreads {
+        SuspendedThreads {
+            threads: Arc::new(Mutex::new(vec![])),
+            panic_hook: Once::new(),
+        }
+    }
+
+    ///
+    /// Suspend the thread, which may be the calling thread. In that case the call returns once
+    /// another thread resumes it.
+    pub fn suspend(&self, jvmti: &dyn JVMTI, jni: &dyn JNI, thread: jthread) -> Result<(), ThreadControlError> {
+        // The thread is tracked first, as suspending the calling thread only returns on resume
+        let tracked = self.track(jvmti, jni, thread)?;
+
+        match jvmti.suspend_thread(thread) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                if tracked {
+                    self.untrack(jni, thread);
+                }
+                Err(ThreadControlError::Jvmti(err))
+            }
+        }
+    }
+
+    ///
+    /// Suspend the threads, returning the result for each of them in the same order. Threads that
+    /// couldn't be suspended, eg. because they are dead, aren't tracked.
+    pub fn suspend_list(
+        &self,
+        jvmti: &dyn JVMTI,
+        jni: &dyn JNI,
+        threads: &[jthread],
+    ) -> Result<Vec<Result<(), NativeError>>, ThreadControlError> {
+        let mut tracked = Vec::with_capacity(threads.len());
+
+        for thread in threads {
+            match self.track(jvmti, jni, *thread) {
+                Ok(newly_tracked) => tracked.push(newly_tracked),
+                Err(err) => {
+                    self.untrack_all(jni, &threads[..tracked.len()], &tracked);
+                    return Err(err);
+                }
+            }
+        }
+
+        match jvmti.suspend_thread_list(threads) {
+            Ok(results) => {
+                let failed: Vec<bool> = results.iter().zip(tracked).map(|(result, t)| t && result.is_err()).collect();
+                self.untrack_all(jni, threads, &failed);
+                Ok(results)
+            }
+            Err(err) => {
+                self.untrack_all(jni, threads, &tracked);
+                Err(ThreadControlError::Jvmti(err))
+            }
+        }
+    }
+
+    ///
+    /// Resume a suspended thread. Threads that were suspended by someone else can be resumed too.
+    pub fn resume(&self, jvmti: &dyn JVMTI, jni: &dyn JNI, thread: jthread) -> Result<(), ThreadControlError> {
+        let result = jvmti.resume_thread(thread);
+
+        match result {
+            Ok(()) | Err(NativeError::ThreadNotSuspended) => self.untrack(jni, thread),
+            Err(_) => (),
+        }
+
+        result.map_err(ThreadControlError::Jvmti)
+    }
+
+    ///
+    /// Resume the threads, returning the result for each of them in the same order
+    pub fn resume_list(
+        &self,
+        jvmti: &dyn JVMTI,
+        jni: &dyn JNI,
+        threads: &[jthread],
+    ) -> Result<Vec<Result<(), NativeError>>, ThreadControlError> {
+        let results = jvmti.resume_thread_list(threads)?;
+
+        for (thread, result) in threads.iter().zip(results.iter()) {
+            match *result {
+                Ok(()) | Err(NativeError::ThreadNotSuspended) => self.untrack(jni, *thread),
+                Err(_) => (),
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Whether the thread has been suspended through this registry and not resumed since
+    pub fn is_suspended(&self, jni: &dyn JNI, thread: jthread) -> bool {
+        self.threads().iter().any(|t| jni.is_same_object(&t.native_id, &thread))
+    }
+
+    /// The number of threads suspended through this registry
+    pub fn len(&self) -> usize {
+        self.threads().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.threads().is_empty()
+    }
+
+    ///
+    /// Resume every thread suspended through this registry and return the number of threads that
+    /// were resumed. The global references are left to the VM, as there may be no JNI environment
+    /// at this point, eg. when the agent unloads.
+    pub fn resume_all(&self, jvmti: &dyn JVMTI) -> usize {
+        let threads: Vec<ThreadId> = self.threads().drain(..).collect();
+        resume_threads(jvmti, &threads)
+    }
+
+    /// Track the thread unless it's already tracked, and return whether it's newly tracked
+    fn track(&self, jvmti: &dyn JVMTI, jni: &dyn JNI, thread: jthread) -> Result<bool, ThreadControlError> {
+        if self.is_suspended(jni, thread) {
+            return Ok(false);
+        }
+
+        let global = jni.new_global_ref(&thread)?;
+        self.threads().push(ThreadId::new(global));
+        self.install_panic_hook(jvmti, jni);
+        Ok(true)
+    }
+
+    fn untrack(&self, jni: &dyn JNI, thread: jthread) {
+        let mut threads = self.threads();
+
+        if let Some(index) = threads.iter().position(|t| jni.is_same_object(&t.native_id, &thread)) {
+            let _ = jni.delete_global_ref(&threads.remove(index).native_id);
+        }
+    }
+
+    /// Untrack the threads whose flag is set
+    fn untrack_all(&self, jni: &dyn JNI, threads: &[jthread], flags: &[bool]) {
+        for (thread, flag) in threads.iter().zip(flags) {
+            if *flag {
+                self.untrack(jni, *thread);
+            }
+        }
+    }
+
+    fn threads(&self) -> MutexGuard<'_, Vec<ThreadId>> {
+        // The threads have to be resumed even if another thread panicked while holding the lock
+        self.threads.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn install_panic_hook(&self, jvmti: &dyn JVMTI, jni: &dyn JNI) {
+        let (env, vm) = match (jvmti.get_env_ptr(), jni.get_java_vm()) {
+            (Some(env), Some(vm)) => (env as usize, vm as usize),
+            _ => return,
+        };
+
+        self.panic_hook.call_once(|| {
+            let threads = self.threads.clone();
+            let previous = panic::take_hook();
+
+            panic::set_hook(Box::new(move |info| {
+                if !ISOLATED.with(|isolated| isolated.get()) {
+                    resume_after_panic(env as JVMTIEnvPtr, vm as JavaVMPtr, &threads);
+                }
+                previous(info);
+            }));
+        });
+    }
+}
+
+/// Resume the threads and return the number of threads that were resumed
+fn resume_threads(jvmti: &dyn JVMTI, threads: &[ThreadId]) -> usize {
+    threads
+        .iter()
+        .filter(|thread| jvmti.resume_thread(thread.native_id).is_ok())
+        .count()
+}
+
+/// Resume the threads from the panic hook. The panicking thread may hold the lock of the threads,
+/// or may not be attached to the VM. The environment has to be the one that suspended them, as
+/// `can_suspend` can't be added to a new environment once the VM is running.
+fn resume_after_panic(env: JVMTIEnvPtr, vm: JavaVMPtr, threads: &Mutex<Vec<ThreadId>>) {
+    let suspended: Vec<ThreadId> = match threads.try_lock() {
+        Ok(mut threads) => threads.drain(..).collect(),
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().drain(..).collect(),
+        Err(TryLockError::WouldBlock) => {
+            log::error!("Couldn't resume the suspended threads after a panic, their list is locked");
+            return;
+        }
+    };
+
+    if !suspended.is_empty() && JVMAgent::new(vm).attach_current_thread_as_daemon("jvmti-resume").is_ok() {
+        resume_threads(&JVMTIEnvironment::new(env), &suspended);
+    }
+}
+
+///
+/// Run the closure like `panic::catch_unwind`. As its panic is caught, the panic hook leaves the
+/// suspended threads suspended.
+pub fn catch_panic<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> thread::Result<R> {
+    let outer = ISOLATED.with(|isolated| isolated.replace(true));
+    let result = panic::catch_unwind(f);
+    ISOLATED.with(|isolated| isolated.set(outer));
+    result
+}
@@ -2,9 +2,26 @@ extern crate jvmti;
 
 #[cfg(test)]
 mod tests {
+    use jvmti::context::AgentContext;
+    use jvmti::instrumentation::timing::nano_time;
+    use jvmti::thread::ThreadId;
+    use std::ptr;
 
     #[test]
     fn test() {
         
     }
+
+    #[test]
+    fn nested_method_times_are_measured_from_their_entry() {
+        let context = AgentContext::new();
+        let thread = ThreadId::new(ptr::null_mut());
+
+        context.method_enter_at(&thread, nano_time(1_000));
+        context.method_enter_at(&thread, nano_time(1_500));
+
+        assert_eq!(Some(200), context.method_exit_at(&thread, nano_time(1_700)).and_then(|d| d.num_nanoseconds()));
+        assert_eq!(Some(1_000), context.method_exit_at(&thread, nano_time(2_000)).and_then(|d| d.num_nanoseconds()));
+        assert!(context.method_exit_at(&thread, nano_time(2_500)).is_none());
+    }
 }
@@ -0,0 +1,309 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+
+    use jvmti::debug::*;
+    use jvmti::emulator::JVMEmulator;
+    use jvmti::environment::jni::JNIEnvironment;
+    use jvmti::environment::jvmti::JVMTI;
+    use jvmti::error::{ wrap_error, NativeError };
+    use jvmti::event::VMEvent;
+    use jvmti::method::LineNumberEntry;
+    use jvmti::native::JavaValue;
+    use jvmti::native::jvmti_native::{ jclass, jfieldID, jlocation, jmethodID, jobject, jthread, jvmtiFrameInfo };
+    use jvmti::runtime::FieldValue;
+    use jvmti::thread::ThreadControlError;
+    use std::ptr;
+
+    fn method(id: usize) -> jmethodID {
+        id as jmethodID
+    }
+
+    fn class(id: usize) -> jclass {
+        id as jclass
+    }
+
+    fn field(id: usize) -> jfieldID {
+        id as jfieldID
+    }
+
+    fn thread() -> jthread {
+        5 as jthread
+    }
+
+    fn lines(entries: &[(jlocation, i32)]) -> Vec<LineNumberEntry> {
+        entries.iter().map(|&(start, line)| LineNumberEntry { start_location: start, line_number: line }).collect()
+    }
+
+    fn frames(frames: &[(usize, jlocation)]) -> Vec<jvmtiFrameInfo> {
+        frames.iter().map(|&(id, location)| jvmtiFrameInfo { method: method(id), location }).collect()
+    }
+
+    /// `Orders.count` loops back to line 11, its lambda is declared on the same line
+    fn emulator() -> JVMEmulator {
+        let mut emu = JVMEmulator::new();
+
+        emu.capabilities.can_generate_breakpoint_events = true;
+        emu.capabilities.can_generate_frame_pop_events = true;
+        emu.capabilities.can_generate_single_step_events = true;
+
+        emu.loaded_classes = vec![ class(1), class(2) ];
+        emu.class_signatures.insert(class(1), "Lcom/acme/Orders;".to_string());
+        emu.class_signatures.insert(class(2), "Lcom/acme/Item;".to_string());
+
+        emu.methods.insert(method(10), (class(1), "count".to_string(), "()I".to_string()));
+        emu.methods.insert(method(11), (class(1), "lambda$count$0".to_string(), "(I)Z".to_string()));
+        emu.methods.insert(method(12), (class(1), "hash".to_string(), "()I".to_string()));
+        emu.methods.insert(method(20), (class(2), "price".to_string(), "()J".to_string()));
+
+        emu.line_number_tables.insert(method(10), lines(&[ (0, 10), (4, 11), (9, 12), (15, 11), (20, 13) ]));
+        emu.line_number_tables.insert(method(11), lines(&[ (0, 11) ]));
+        emu.line_number_tables.insert(method(20), lines(&[ (0, 30), (3, 31) ]));
+        emu
+    }
+
+    #[test]
+    fn lines_resolve_to_their_first_instruction_in_each_method() {
+        let emu = emulator();
+
+        assert_eq!(vec![ CodeLocation { method: method(10), location: 4 }, CodeLocation { method: method(11), location: 0 } ],
+                   resolve_line(&emu, class(1), None, 11).unwrap());
+        assert_eq!(vec![ CodeLocation { method: method(10), location: 4 } ],
+                   resolve_line(&emu, class(1), Some("count"), 11).unwrap());
+        assert!(resolve_line(&emu, class(1), None, 99).unwrap().is_empty());
+
+        assert_eq!(Some(11), line_at(&emu, method(10), 17).unwrap());
+        assert_eq!(None, line_at(&emu, method(12), 0).unwrap());
+    }
+
+    #[test]
+    fn breakpoints_sharing_an_instruction_are_cleared_with_the_last_of_them() {
+        let emu = emulator();
+        let breakpoints = Breakpoints::new();
+
+        let line = breakpoints.set(&emu, class(1), None, 11).unwrap();
+        let count = breakpoints.set_by_name(&emu, "com.acme.Orders", Some("count"), 11).unwrap();
+
+        assert_eq!(Some(11), line.line);
+        assert_eq!(2, line.locations.len());
+        assert_eq!(vec![ (method(10), 4), (method(11), 0) ], *emu.breakpoints.lock().unwrap());
+        assert_eq!(vec![ line.id, count.id ], breakpoints.at(method(10), 4));
+        assert_eq!(vec![ line.id ], breakpoints.at(method(11), 0));
+
+        assert!(breakpoints.clear(&emu, line.id).unwrap());
+        assert!(!breakpoints.clear(&emu, line.id).unwrap());
+        assert_eq!(vec![ (method(10), 4) ], *emu.breakpoints.lock().unwrap());
+
+        breakpoints.set_at(&emu, method(20), 3).unwrap();
+        assert_eq!(2, breakpoints.clear_all(&emu).unwrap());
+        assert!(emu.breakpoints.lock().unwrap().is_empty());
+        assert!(breakpoints.list().is_empty());
+    }
+
+    #[test]
+    fn breakpoints_need_code_on_the_line_and_the_capability() {
+        let mut emu = emulator();
+        let breakpoints = Breakpoints::new();
+
+        match breakpoints.set(&emu, class(1), None, 99) {
+            Err(BreakpointError::NoCodeAtLine(99)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match breakpoints.set_by_name(&emu, "com/acme/Missing", None, 1) {
+            Err(BreakpointError::ClassNotLoaded(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        emu.capabilities.can_generate_breakpoint_events = false;
+
+        match breakpoints.set(&emu, class(2), None, 31) {
+            Err(BreakpointError::Jvmti(NativeError::MustPossessCapability)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(breakpoints.list().is_empty());
+    }
+
+    #[test]
+    fn stepping_over_runs_calls_at_full_speed() {
+        let mut emu = emulator();
+        emu.frames = frames(&[ (10, 4), (30, 7) ]);
+
+        step(&emu, thread(), StepKind::Over).unwrap();
+        assert!(is_stepping());
+
+        // Same line
+        assert!(on_single_step(&emu, thread(), method(10), 6).unwrap().is_none());
+
+        // Called `price`, which is left to run until it returns
+        emu.frames = frames(&[ (20, 0), (10, 7), (30, 7) ]);
+        assert!(on_single_step(&emu, thread(), method(20), 0).unwrap().is_none());
+        assert_eq!(vec![ (thread(), 0) ], *emu.frame_pop_requests.lock().unwrap());
+        assert!(on_frame_pop(&emu, thread()).unwrap());
+
+        emu.frames = frames(&[ (10, 9), (30, 7) ]);
+        let event = on_single_step(&emu, thread(), method(10), 9).unwrap().unwrap();
+
+        assert_eq!(method(10), event.method);
+        assert_eq!(Some(12), event.line);
+        assert!(!is_stepping());
+        assert_eq!(vec![ (VMEvent::SingleStep, thread(), true),
+                         (VMEvent::SingleStep, thread(), false),
+                         (VMEvent::SingleStep, thread(), true),
+                         (VMEvent::SingleStep, thread(), false) ], *emu.thread_events.lock().unwrap());
+    }
+
+    #[test]
+    fn stepping_into_stops_in_the_called_method() {
+        let mut emu = emulator();
+        emu.frames = frames(&[ (10, 4), (30, 7) ]);
+
+        step(&emu, thread(), StepKind::Into).unwrap();
+
+        emu.frames = frames(&[ (20, 0), (10, 7), (30, 7) ]);
+        let event = on_single_step(&emu, thread(), method(20), 0).unwrap().unwrap();
+
+        assert_eq!(Some(30), event.line);
+        assert!(emu.frame_pop_requests.lock().unwrap().is_empty());
+        assert!(!is_stepping());
+    }
+
+    #[test]
+    fn stepping_out_stops_in_the_caller() {
+        let mut emu = emulator();
+        emu.frames = frames(&[ (20, 3), (10, 7), (30, 7) ]);
+
+        step(&emu, thread(), StepKind::Out).unwrap();
+        assert_eq!(vec![ (thread(), 0) ], *emu.frame_pop_requests.lock().unwrap());
+        assert!(emu.thread_events.lock().unwrap().is_empty());
+
+        // Pops of frames the step doesn't wait for are ignored
+        emu.frames = frames(&[ (12, 0), (20, 3), (10, 7), (30, 7) ]);
+        assert!(!on_frame_pop(&emu, thread()).unwrap());
+
+        emu.frames = frames(&[ (20, 3), (10, 7), (30, 7) ]);
+        assert!(on_frame_pop(&emu, thread()).unwrap());
+
+        emu.frames = frames(&[ (10, 8), (30, 7) ]);
+        let event = on_single_step(&emu, thread(), method(10), 8).unwrap().unwrap();
+
+        assert_eq!(Some(11), event.line);
+        assert!(!is_stepping());
+    }
+
+    #[test]
+    fn single_steps_are_delivered_when_no_step_is_in_progress() {
+        let mut emu = emulator();
+        emu.frames = frames(&[ (10, 4) ]);
+
+        assert!(on_single_step(&emu, thread(), method(10), 4).unwrap().is_some());
+
+        step(&emu, thread(), StepKind::Into).unwrap();
+        assert!(cancel_step(&emu, thread()).unwrap());
+        assert!(!cancel_step(&emu, thread()).unwrap());
+        assert!(!is_stepping());
+    }
+
+    #[test]
+    fn frame_returns_are_requested_for_existing_frames() {
+        let mut emu = emulator();
+        let returns = FrameReturns::new();
+        // Requests that fail don't get as far as taking a global reference of the thread
+        let jni = JNIEnvironment::new(ptr::null_mut());
+
+        emu.frames = frames(&[ (20, 3), (10, 7) ]);
+
+        match returns.add(&emu, &jni, thread(), 2, Box::new(|_, _| ())) {
+            Err(ThreadControlError::Jvmti(NativeError::NoMoreFrames)) => (),
+            other => panic!("unexpected result {:?}", other.err()),
+        }
+
+        emu.capabilities.can_generate_frame_pop_events = false;
+
+        match returns.add(&emu, &jni, thread(), 1, Box::new(|_, _| ())) {
+            Err(ThreadControlError::Jvmti(NativeError::MustPossessCapability)) => (),
+            other => panic!("unexpected result {:?}", other.err()),
+        }
+        assert!(returns.is_empty());
+        assert!(emu.frame_pop_requests.lock().unwrap().is_empty());
+
+        emu.capabilities.can_generate_frame_pop_events = true;
+        emu.notify_frame_pop(thread(), 1).unwrap();
+        assert_eq!(vec![ (thread(), 1) ], *emu.frame_pop_requests.lock().unwrap());
+    }
+
+    /// `Orders` is defined by two class loaders
+    fn watched() -> JVMEmulator {
+        let mut emu = emulator();
+
+        emu.capabilities.can_generate_field_access_events = true;
+        emu.capabilities.can_generate_field_modification_events = true;
+
+        emu.loaded_classes.push(class(3));
+        emu.class_signatures.insert(class(3), "Lcom/acme/Orders;".to_string());
+        emu.fields.insert(field(40), (class(1), "count".to_string(), "I".to_string()));
+        emu.fields.insert(field(41), (class(1), "items".to_string(), "Ljava/util/List;".to_string()));
+        emu.fields.insert(field(42), (class(3), "count".to_string(), "I".to_string()));
+        emu.fields.insert(field(50), (class(2), "count".to_string(), "J".to_string()));
+        emu
+    }
+
+    #[test]
+    fn fields_are_watched_by_class_and_field_name() {
+        let emu = watched();
+
+        assert_eq!(2, watch_field(&emu, "com.acme.Orders", "count", WatchKind::Modification).unwrap());
+        assert_eq!(1, watch_field(&emu, "com/acme/Orders", "items", WatchKind::Access).unwrap());
+        // Watching twice is harmless
+        assert_eq!(1, watch_field(&emu, "com.acme.Orders", "items", WatchKind::Access).unwrap());
+
+        assert_eq!(vec![ (class(1), field(40)), (class(3), field(42)) ], *emu.modification_watches.lock().unwrap());
+        assert_eq!(vec![ (class(1), field(41)) ], *emu.access_watches.lock().unwrap());
+
+        assert_eq!(2, unwatch_field(&emu, "com.acme.Orders", "count", WatchKind::Modification).unwrap());
+        assert_eq!(2, unwatch_field(&emu, "com.acme.Orders", "count", WatchKind::Access).unwrap());
+        assert!(emu.modification_watches.lock().unwrap().is_empty());
+        assert_eq!(1, emu.access_watches.lock().unwrap().len());
+    }
+
+    #[test]
+    fn watches_need_a_loaded_field_and_the_capability() {
+        let mut emu = watched();
+
+        match watch_field(&emu, "com.acme.Orders", "total", WatchKind::Access) {
+            Err(WatchError::NoSuchField(ref name)) if name == "com.acme.Orders.total" => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match watch_field(&emu, "com.acme.Missing", "count", WatchKind::Access) {
+            Err(WatchError::ClassNotLoaded(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        emu.capabilities.can_generate_field_modification_events = false;
+
+        match watch_field(&emu, "com.acme.Item", "count", WatchKind::Modification) {
+            Err(WatchError::Jvmti(NativeError::MustPossessCapability)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(emu.modification_watches.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn field_values_are_decoded_by_type() {
+        assert_eq!(Some(FieldValue::Boolean(true)), FieldValue::decode(b'Z', JavaValue::from(1u8)));
+        assert_eq!(Some(FieldValue::Char('x' as u16)), FieldValue::decode(b'C', JavaValue::from('x' as u16)));
+        assert_eq!(Some(FieldValue::Int(-3)), FieldValue::decode(b'I', JavaValue::from(-3)));
+        assert_eq!(Some(FieldValue::Long(1 << 40)), FieldValue::decode(b'J', JavaValue::from(1i64 << 40)));
+        assert_eq!(Some(FieldValue::Double(0.25)), FieldValue::decode(b'D', JavaValue::from(0.25f64)));
+        assert_eq!(Some(FieldValue::Object(7 as jobject)), FieldValue::decode(b'[', JavaValue::from(7 as jobject)));
+        assert_eq!(None, FieldValue::decode(b'V', JavaValue::from(0)));
+    }
+
+    #[test]
+    fn breakpoint_errors_are_decoded() {
+        match (wrap_error(24), wrap_error(25), wrap_error(40), wrap_error(41), wrap_error(104)) {
+            (NativeError::InvalidLocation, NativeError::InvalidFieldId, NativeError::Duplicate, NativeError::NotFound, NativeError::NativeMethod) => (),
+            other => panic!("unexpected errors {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+extern crate jvmti;
+extern crate serde_json;
+
+#[cfg(test)]
+mod tests {
+
+    use jvmti::dump::*;
+    use jvmti::emulator::JVMEmulator;
+    use jvmti::environment::jvmti::JVMTI;
+    use jvmti::method::LineNumberEntry;
+    use jvmti::native::jvmti_native::{ jclass, jobject, jthread, jvmtiFrameInfo, jvmtiMonitorStackDepthInfo };
+    use jvmti::stack::StackInfo;
+    use std::cell::RefCell;
+
+    fn id<T>(value: usize) -> *mut T {
+        value as *mut T
+    }
+
+    fn frame(method: usize, location: i64) -> jvmtiFrameInfo {
+        jvmtiFrameInfo { method: id(method), location }
+    }
+
+    /// "main" waits in Object.wait() on a lock it took in Orders.main, "worker" is blocked on it
+    fn emulator() -> JVMEmulator {
+        let mut emu = JVMEmulator::new();
+        let (main, worker): (jthread, jthread) = (id(1), id(2));
+        let (orders, object): (jclass, jclass) = (id(10), id(11));
+        let (lock, other): (jobject, jobject) = (id(100), id(101));
+
+        emu.class_signatures.insert(orders, "Lcom/acme/Orders;".to_string());
+        emu.class_signatures.insert(object, "Ljava/lang/Object;".to_string());
+        emu.source_files.insert(orders, "Orders.java".to_string());
+
+        emu.methods.insert(id(20), (object, "wait".to_string(), "(J)V".to_string()));
+        emu.methods.insert(id(21), (orders, "main".to_string(), "([Ljava/lang/String;)V".to_string()));
+        emu.methods.insert(id(22), (orders, "process".to_string(), "()V".to_string()));
+        emu.line_number_tables.insert(id(21), vec![ LineNumberEntry { start_location: 0, line_number: 7 } ]);
+        emu.line_number_tables.insert(id(22), vec![ LineNumberEntry { start_location: 0, line_number: 12 } ]);
+
+        emu.threads.insert(main, ("main".to_string(), 5, false));
+        emu.threads.insert(worker, ("worker".to_string(), 4, true));
+
+        emu.stacks = vec![
+            StackInfo {
+                thread: main,
+                state: (ThreadState::ALIVE | ThreadState::WAITING | ThreadState::WAITING_INDEFINITELY | ThreadState::IN_OBJECT_WAIT).bits(),
+                frames: vec![ frame(20, -1), frame(21, 3) ],
+            },
+            StackInfo {
+                thread: worker,
+                state: (ThreadState::ALIVE | ThreadState::BLOCKED_ON_MONITOR_ENTER).bits(),
+                frames: vec![ frame(22, 1), frame(21, 9) ],
+            },
+        ];
+
+        emu.owned_monitors.insert(main, vec![ jvmtiMonitorStackDepthInfo { monitor: other, stack_depth: 1 } ]);
+        emu.owned_monitors.insert(worker, vec![ jvmtiMonitorStackDepthInfo { monitor: other, stack_depth: -1 } ]);
+        emu.contended_monitors.insert(main, lock);
+        emu.contended_monitors.insert(worker, lock);
+        emu.hash_codes.insert(lock, 0x1b6d3586);
+        emu.hash_codes.insert(other, 0x4554617c);
+        emu
+    }
+
+    fn describe(object: jobject) -> String {
+        if object as usize == 100 { "java.lang.Object".to_string() } else { "com.acme.Orders".to_string() }
+    }
+
+    #[test]
+    fn thread_states_decode_the_jvmti_bits() {
+        let sleeping = ThreadState::from_bits(0x1 | 0x80 | 0x20 | 0x40);
+
+        assert_eq!("TIMED_WAITING", sleeping.java_state());
+        assert_eq!("TIMED_WAITING (sleeping)", sleeping.to_string());
+        assert_eq!(vec![ "alive", "waiting", "waiting_with_timeout", "sleeping" ], sleeping.flags());
+        assert!(sleeping.contains(ThreadState::ALIVE | ThreadState::SLEEPING));
+        assert!(!sleeping.contains(ThreadState::SUSPENDED));
+
+        assert_eq!("NEW", ThreadState::from_bits(0).to_string());
+        assert_eq!("TERMINATED", ThreadState::TERMINATED.to_string());
+        assert_eq!("RUNNABLE", (ThreadState::ALIVE | ThreadState::RUNNABLE | ThreadState::IN_NATIVE).to_string());
+        assert_eq!("BLOCKED (on object monitor)", (ThreadState::ALIVE | ThreadState::BLOCKED_ON_MONITOR_ENTER).to_string());
+        assert_eq!("WAITING (parking)", (ThreadState::ALIVE | ThreadState::WAITING_INDEFINITELY | ThreadState::PARKED).to_string());
+    }
+
+    #[test]
+    fn dumps_are_rendered_like_jstack() {
+        let emu = emulator();
+        let released = RefCell::new(vec![]);
+        let stacks = emu.stacks.clone();
+
+        let dump = ThreadDump::from_stacks(&emu, &stacks, 10, &describe, &|object| released.borrow_mut().push(object as usize)).unwrap();
+
+        assert_eq!("Full thread dump:\n\
+                    \n\
+                    \"main\" prio=5\n   java.lang.Thread.State: WAITING (on object monitor)\n\
+                    \tat java.lang.Object.wait(Native Method)\n\
+                    \t- waiting on <0x1b6d3586> (a java.lang.Object)\n\
+                    \tat com.acme.Orders.main(Orders.java:7)\n\
+                    \t- locked <0x4554617c> (a com.acme.Orders)\n\
+                    \n\
+                    \"worker\" daemon prio=4\n   java.lang.Thread.State: BLOCKED (on object monitor)\n\
+                    \tat com.acme.Orders.process(Orders.java:12)\n\
+                    \t- waiting to lock <0x1b6d3586> (a java.lang.Object)\n\
+                    \tat com.acme.Orders.main(Orders.java:7)\n",
+                   dump.to_string());
+
+        // Monitors locked through JNI are only listed in the structured dump
+        let worker = dump.thread("worker").unwrap();
+        assert_eq!(-1, worker.locked[0].depth);
+
+        // Every monitor reference is released
+        let mut released = released.into_inner();
+        released.sort();
+        assert_eq!(vec![ 100, 100, 101, 101 ], released);
+    }
+
+    #[test]
+    fn dumps_are_rendered_as_json() {
+        let emu = emulator();
+        let stacks = emu.stacks.clone();
+        let dump = ThreadDump::from_stacks(&emu, &stacks[1..], 10, &describe, &|_| ()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&dump.to_json()).unwrap();
+        let worker = &json["threads"][0];
+
+        assert_eq!(10, json["max_depth"]);
+        assert_eq!("worker", worker["name"]);
+        assert_eq!(true, worker["daemon"]);
+        assert_eq!("BLOCKED", worker["state"]["state"]);
+        assert_eq!(serde_json::json!([ "alive", "blocked_on_monitor_enter" ]), worker["state"]["flags"]);
+        assert_eq!("com.acme.Orders", worker["frames"][0]["class_name"]);
+        assert_eq!(12, worker["frames"][0]["line"]);
+        assert!(worker["frames"][0].get("method").is_none());
+        assert_eq!(0x1b6d3586, worker["waiting_on"]["hash_code"]);
+        assert_eq!("com.acme.Orders", worker["locked"][0]["monitor"]["class_name"]);
+    }
+
+    #[test]
+    fn selected_threads_are_dumped_to_the_given_depth() {
+        let emu = emulator();
+        let stacks = emu.get_thread_list_stack_traces(&[ id(1) ], 1).unwrap();
+        let dump = ThreadDump::from_stacks(&emu, &stacks, 1, &describe, &|_| ()).unwrap();
+
+        assert_eq!(vec![ "main" ], dump.threads.iter().map(|t| t.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!(1, dump.threads[0].frames.len());
+
+        // Monitors locked by frames that weren't captured are kept, but not rendered
+        assert_eq!(1, dump.threads[0].locked.len());
+        assert!(!dump.to_string().contains("- locked"));
+    }
+}
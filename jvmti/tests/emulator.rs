@@ -9,6 +9,11 @@ mod tests {
     use jvmti::environment::jvm::JVMF;
     use jvmti::environment::jvmti::JVMTI;
     use jvmti::version::VersionNumber;
+    use jvmti::class::ClassId;
+    use jvmti::error::{ wrap_error, NativeError, RedefineError };
+    use jvmti::native::JavaClass;
+    use jvmti::native::jvmti_native::{ jobject, jthread };
+    use std::ptr;
 
     #[test]
     fn get_environment_returns_a_valid_environment() {
@@ -47,4 +52,96 @@ mod tests {
         assert_eq!(true, emu.capabilities.can_suspend);
         assert_eq!(true, emu.capabilities.can_get_bytecodes);
     }
+
+    #[test]
+    fn retransform_requires_the_capability() {
+        let mut emu = JVMEmulator::new();
+        emu.capabilities.can_retransform_classes = false;
+        let classes = vec![ ClassId { native_id: 8 as JavaClass } ];
+
+        match emu.retransform(&classes) {
+            Err(RedefineError::MissingCapability) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        emu.capabilities.can_retransform_classes = true;
+        assert!(emu.retransform(&classes).is_ok());
+        assert!(emu.retransform(&[]).is_ok());
+    }
+
+    #[test]
+    fn unmodifiable_classes_are_reported_by_position() {
+        let emu = JVMEmulator::new();
+
+        let definitions = vec![ (ClassId { native_id: 8 as JavaClass }, vec![ 0xCA, 0xFE ]),
+                                (ClassId { native_id: ptr::null_mut() }, vec![ 0xCA, 0xFE ]),
+                                (ClassId { native_id: 16 as JavaClass }, vec![ 0xCA, 0xFE ]) ];
+
+        match emu.redefine(&definitions) {
+            Err(RedefineError::Unmodifiable(ref positions)) => assert_eq!(&vec![ 1 ], positions),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        assert!(emu.redefine(&definitions[2..]).is_ok());
+    }
+
+    #[test]
+    fn redefinition_errors_are_typed() {
+        assert!(wrap_error(64).is_unsupported_redefinition());
+        assert!(wrap_error(63).is_unsupported_redefinition());
+        assert!(!wrap_error(62).is_unsupported_redefinition());
+
+        match RedefineError::from(wrap_error(79)) {
+            RedefineError::Failed(NativeError::UnmodifiableClass) => (),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn thread_control_requires_the_capabilities() {
+        let mut emu = JVMEmulator::new();
+        let thread = 1 as jthread;
+
+        match emu.suspend_thread(thread) {
+            Err(NativeError::MustPossessCapability) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(emu.interrupt_thread(thread).is_err());
+        assert!(emu.stop_thread(thread, 2 as jobject).is_err());
+
+        emu.capabilities.can_suspend = true;
+        emu.capabilities.can_signal_thread = true;
+
+        assert!(emu.suspend_thread(thread).is_ok());
+        assert!(emu.interrupt_thread(thread).is_ok());
+        assert!(emu.stop_thread(thread, 2 as jobject).is_ok());
+        assert_eq!(vec![ (thread, 2 as jobject) ], *emu.stopped.lock().unwrap());
+    }
+
+    #[test]
+    fn thread_lists_report_the_result_of_each_thread() {
+        let mut emu = JVMEmulator::new();
+        emu.capabilities.can_suspend = true;
+        let threads = vec![ 1 as jthread, 2 as jthread ];
+
+        assert!(emu.suspend_thread(threads[1]).is_ok());
+
+        let suspended = emu.suspend_thread_list(&threads).unwrap();
+        assert!(suspended[0].is_ok());
+        match suspended[1] {
+            Err(NativeError::ThreadSuspended) => (),
+            ref other => panic!("unexpected result {:?}", other),
+        }
+
+        assert!(emu.resume_thread(threads[0]).is_ok());
+
+        let resumed = emu.resume_thread_list(&threads).unwrap();
+        match resumed[0] {
+            Err(NativeError::ThreadNotSuspended) => (),
+            ref other => panic!("unexpected result {:?}", other),
+        }
+        assert!(resumed[1].is_ok());
+        assert!(emu.suspended.lock().unwrap().is_empty());
+        assert_eq!("The thread was not suspended.", wrap_error(13).to_string());
+    }
 }
@@ -3,11 +3,71 @@ extern crate jvmti;
 #[cfg(test)]
 mod tests {
 
+    use jvmti::emulator::JVMEmulator;
+    use jvmti::error::NativeError;
     use jvmti::event::EventCallbacks;
+    use jvmti::native::jvmti_native::{ jclass, jfieldID, jmethodID, jobject, jthread };
+    use jvmti::runtime::{ ClassLoadEvent, ClassPrepareEvent };
 
     #[test]
     fn empty_event_callbacks_are_instantiatable_using_new() {
         let ec = EventCallbacks::new();
         assert_eq!(None, ec.method_entry);
     }
+
+    fn orders() -> jclass {
+        1 as jclass
+    }
+
+    /// `Orders` defined by an application class loader, `Object` by the bootstrap class loader
+    fn emulator() -> JVMEmulator {
+        let mut emu = JVMEmulator::new();
+
+        emu.class_signatures.insert(orders(), "Lcom/acme/Orders;".to_string());
+        emu.class_signatures.insert(2 as jclass, "Ljava/lang/Object;".to_string());
+        emu.class_loaders.insert(orders(), 9 as jobject);
+
+        emu.methods.insert(10 as jmethodID, (orders(), "<init>".to_string(), "()V".to_string()));
+        emu.methods.insert(11 as jmethodID, (orders(), "count".to_string(), "(Ljava/util/List;)I".to_string()));
+        emu.methods.insert(12 as jmethodID, (orders(), "count".to_string(), "()I".to_string()));
+        emu.fields.insert(20 as jfieldID, (orders(), "total".to_string(), "J".to_string()));
+        emu
+    }
+
+    #[test]
+    fn loaded_classes_are_named_by_binary_name_and_signature() {
+        let emu = emulator();
+        let event = ClassLoadEvent::resolve(&emu, 5 as jthread, orders()).unwrap();
+
+        assert_eq!("com.acme.Orders", event.class_name);
+        assert_eq!("Lcom/acme/Orders;", event.signature);
+        assert_eq!(9 as jobject, event.loader);
+
+        let bootstrap = ClassLoadEvent::resolve(&emu, 5 as jthread, 2 as jclass).unwrap();
+        assert!(bootstrap.loader.is_null());
+    }
+
+    #[test]
+    fn prepared_classes_list_their_methods_and_fields() {
+        let emu = emulator();
+        let event = ClassPrepareEvent::resolve(&emu, 5 as jthread, orders()).unwrap();
+
+        assert_eq!(vec![ "<init>", "count", "count" ], event.methods.iter().map(|m| m.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!(11 as jmethodID, event.method("count").unwrap().id);
+        assert_eq!("(Ljava/util/List;)I", event.method("count").unwrap().signature);
+        assert_eq!(1, event.fields.len());
+        assert_eq!("J", event.field("total").unwrap().signature);
+        assert!(event.field("count").is_none());
+    }
+
+    #[test]
+    fn unknown_classes_are_not_resolved() {
+        let emu = emulator();
+
+        match ClassPrepareEvent::resolve(&emu, 5 as jthread, 3 as jclass) {
+            Err(NativeError::NotImplemented) => (),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("resolved an unknown class"),
+        }
+    }
 }
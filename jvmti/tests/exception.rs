@@ -0,0 +1,67 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+
+    use jvmti::exception::ExceptionFilter;
+    use std::cell::Cell;
+
+    fn hierarchy(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn every_exception_is_reported_without_classes() {
+        let filter = ExceptionFilter::new();
+
+        assert!(filter.accepts("java.lang.IllegalStateException", || panic!("the hierarchy isn't needed")));
+        assert_eq!(None, filter.stack_depth());
+    }
+
+    #[test]
+    fn exceptions_are_reported_by_class_hierarchy() {
+        let filter = ExceptionFilter::new();
+        filter.include("java.sql.SQLException");
+        filter.include("com/acme/OrderException");
+
+        assert!(filter.accepts("java.sql.SQLTimeoutException",
+                               || hierarchy(&[ "java.sql.SQLTimeoutException", "java.sql.SQLTransientException", "java.sql.SQLException", "java.lang.Exception" ])));
+        assert!(filter.accepts("com.acme.OrderException", || hierarchy(&[ "com.acme.OrderException", "java.lang.RuntimeException" ])));
+        assert!(!filter.accepts("java.io.IOException", || hierarchy(&[ "java.io.IOException", "java.lang.Exception" ])));
+    }
+
+    #[test]
+    fn the_hierarchy_of_each_class_is_walked_once() {
+        let filter = ExceptionFilter::new();
+        let walks = Cell::new(0);
+        let walk = || {
+            walks.set(walks.get() + 1);
+            hierarchy(&[ "java.io.IOException", "java.lang.Exception" ])
+        };
+
+        filter.include("java.sql.SQLException");
+
+        assert!(!filter.accepts("java.io.IOException", walk));
+        assert!(!filter.accepts("java.io.IOException", walk));
+        assert_eq!(1, walks.get());
+
+        // Changing the classes forgets the cached decisions
+        filter.include("java.io.IOException");
+        assert!(filter.accepts("java.io.IOException", walk));
+        assert_eq!(2, walks.get());
+
+        filter.include_all();
+        assert!(filter.accepts("java.lang.Error", || panic!("the hierarchy isn't needed")));
+    }
+
+    #[test]
+    fn stack_traces_are_captured_on_request() {
+        let filter = ExceptionFilter::new();
+
+        filter.capture_stack_traces(Some(16));
+        assert_eq!(Some(16), filter.stack_depth());
+
+        filter.capture_stack_traces(None);
+        assert_eq!(None, filter.stack_depth());
+    }
+}
@@ -0,0 +1,161 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ AccessFlags, Instruction, MethodAccessFlags };
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::allocation::*;
+    use jvmti::instrumentation::code::*;
+    use std::sync::Arc;
+    use super::super::{ assert_unknown_pointcut, config, helper_call, round_trip };
+
+    /// static Object make() {
+    ///     new StringBuilder();                    // line 5
+    ///     int[] a = new int[3]; String[] b = new String[2]; return new long[2][3];    // line 6
+    /// }
+    fn factory_class() -> JavaClass {
+        let mut class = JavaClass::new();
+        class.name = "com/acme/Factory".to_string();
+
+        let mut code = Code::new();
+        code.instructions = vec![
+            Insn::LineNumber(5),
+            Insn::Type(TypeOp::New, "java/lang/StringBuilder".to_string()),
+            Insn::Op(Instruction::DUP),
+            Insn::Invoke(Invoke::Special, MemberRef::new("java/lang/StringBuilder", "<init>", "()V")),
+            Insn::Op(Instruction::POP),
+            Insn::LineNumber(6),
+            Insn::Op(Instruction::ICONST_3),
+            Insn::Op(Instruction::NEWARRAY(10)),
+            Insn::Op(Instruction::POP),
+            Insn::Op(Instruction::ICONST_2),
+            Insn::Type(TypeOp::ANewArray, "java/lang/String".to_string()),
+            Insn::Op(Instruction::POP),
+            Insn::Op(Instruction::ICONST_2),
+            Insn::Op(Instruction::ICONST_3),
+            Insn::MultiANewArray("[[J".to_string(), 2),
+            Insn::Op(Instruction::ARETURN),
+        ];
+
+        let mut method = Method::new("make".to_string(), MethodDescriptor::parse("()Ljava/lang/Object;").unwrap());
+        method.access_flags = AccessFlags::of(MethodAccessFlags::Static as u16);
+        method.code = Some(code);
+        class.add_method(method);
+
+        class
+    }
+
+    fn site(pc: u32, line: u16, allocated_type: &str) -> AllocationSite {
+        AllocationSite {
+            class_name: "com/acme/Factory".to_string(),
+            method_name: "make".to_string(),
+            descriptor: "()Ljava/lang/Object;".to_string(),
+            pc,
+            line: Some(line),
+            allocated_type: allocated_type.to_string(),
+        }
+    }
+
+    fn offsets(class: &JavaClass) -> Vec<usize> {
+        instruction_offsets(class).unwrap().remove(0)
+    }
+
+    #[test]
+    fn probes_follow_every_allocation() {
+        let registry = AllocationRegistry::new();
+        let mut class = factory_class();
+        let offsets = offsets(&class);
+
+        assert_eq!(4, weave(&mut class, 0, &offsets, &registry).unwrap());
+        assert_eq!(vec![ site(0, 5, "java/lang/StringBuilder"), site(9, 6, "[I"), site(13, 6, "[Ljava/lang/String;"),
+                         site(19, 6, "[[J") ],
+                   registry.snapshot().into_iter().map(|(site, _)| site).collect::<Vec<AllocationSite>>());
+
+        let code = class.methods[0].code.as_ref().unwrap();
+
+        // The probe of `new` comes after it, so that the uninitialised object keeps its label, which
+        // the analysis has placed in front of it
+        assert!(matches!(code.instructions[1], Insn::Label(_)));
+        assert_eq!(vec![ Insn::Type(TypeOp::New, "java/lang/StringBuilder".to_string()),
+                         Insn::Op(Instruction::ICONST_0),
+                         helper_call(HELPER_CLASS, "object", OBJECT_DESCRIPTOR),
+                         Insn::Op(Instruction::DUP) ],
+                   code.instructions[2..6].to_vec());
+        assert_eq!(vec![ Insn::Op(Instruction::NEWARRAY(10)),
+                         Insn::Op(Instruction::DUP),
+                         Insn::Op(Instruction::ARRAYLENGTH),
+                         Insn::Op(Instruction::ICONST_1),
+                         helper_call(HELPER_CLASS, "array", ARRAY_DESCRIPTOR) ],
+                   code.instructions[10..15].to_vec());
+        assert_eq!(3, code.max_stack);
+
+        round_trip(&class);
+
+        // Weaving the same sites again reuses their ids
+        weave(&mut factory_class(), 0, &offsets, &registry).unwrap();
+        assert_eq!(4, registry.len());
+    }
+
+    #[test]
+    fn sites_have_the_pc_of_the_original_code() {
+        let mut class = factory_class();
+        let mut copy = class.methods[0].clone();
+        copy.name = "copy".to_string();
+        class.add_method(copy);
+
+        let registry = Arc::new(AllocationRegistry::new());
+        let probes = AllocationProbes::new(vec![ Pointcut::parse("class(com.acme.*)").unwrap() ], registry.clone());
+        assert!(probes.transform(&mut class).unwrap());
+
+        let pcs: Vec<(String, u32)> = registry.snapshot().into_iter().map(|(site, _)| (site.method_name, site.pc)).collect();
+        assert_eq!(vec![ ("make".to_string(), 0), ("make".to_string(), 9), ("make".to_string(), 13), ("make".to_string(), 19),
+                         ("copy".to_string(), 0), ("copy".to_string(), 9), ("copy".to_string(), 13), ("copy".to_string(), 19) ],
+                   pcs);
+    }
+
+    #[test]
+    fn sizes_are_estimated() {
+        let registry = AllocationRegistry::new();
+        let object = registry.register(site(0, 5, "java/lang/StringBuilder"));
+        let ints = registry.register(site(9, 6, "[I"));
+        let strings = registry.register(site(13, 6, "[Ljava/lang/String;"));
+
+        registry.record(object, None);
+        registry.set_instance_size("java/lang/StringBuilder", 24);
+        registry.record(object, None);
+        registry.record(ints, Some(3));
+        registry.record(ints, Some(0));
+        registry.record(strings, Some(2));
+        registry.record(9, None);
+
+        assert_eq!(Some(AllocationStats { count: 2, bytes: 40 }), registry.stats(object));
+        assert_eq!(Some(AllocationStats { count: 2, bytes: 48 }), registry.stats(ints));
+        assert_eq!(Some(AllocationStats { count: 1, bytes: 24 }), registry.stats(strings));
+        assert_eq!(None, registry.stats(9));
+        assert!(registry.site(ints).unwrap().is_array());
+
+        assert_eq!(40, array_size("[J", 3));
+        assert_eq!(24, array_size("[[J", 2));
+        assert_eq!(24, array_size("[Z", 5));
+    }
+
+    #[test]
+    fn allocations_are_read_from_config() {
+        let config = config(r#"
+            allocations = [ "services" ]
+
+            [pointcuts]
+            services = "class(com.acme.*)"
+        "#);
+
+        let probes = AllocationProbes::from_config(&config, Arc::new(AllocationRegistry::new())).unwrap();
+
+        // Nothing is woven before the helper class is installed
+        assert!(!is_installed());
+        assert!(!probes.accepts("com/acme/Factory"));
+
+        let mut missing = config;
+        missing.allocations.push("missing".to_string());
+        assert_unknown_pointcut(AllocationProbes::from_config(&missing, Arc::new(AllocationRegistry::new())), "missing");
+    }
+}
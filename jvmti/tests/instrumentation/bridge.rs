@@ -0,0 +1,71 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ ClassReader, MethodAccessFlags };
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::bridge::*;
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::os::raw::c_void;
+    use std::ptr;
+
+    fn bridge() -> NativeBridge {
+        NativeBridge::new("com/acme/agent/Bridge")
+            .method("hit", "(I)V", ptr::null_mut::<c_void>())
+            .method("now", "()J", ptr::null_mut::<c_void>())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from(data[offset]) | u32::from(data[offset + 1]) << 8 | u32::from(data[offset + 2]) << 16 | u32::from(data[offset + 3]) << 24
+    }
+
+    #[test]
+    fn helper_class_declares_static_natives() {
+        let class = bridge().helper_class().unwrap();
+        let flags = MethodAccessFlags::Public as u16 | MethodAccessFlags::Static as u16 | MethodAccessFlags::Native as u16;
+
+        assert_eq!("com/acme/agent/Bridge", class.name);
+        assert_eq!(vec![ "hit", "now" ], class.methods.iter().map(|m| m.name.as_str()).collect::<Vec<&str>>());
+        assert!(class.methods.iter().all(|m| m.access_flags.has_flag(flags) && m.code.is_none()));
+
+        let data = bridge().class_data().unwrap();
+        let classfile = ClassReader::read_class(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(50, classfile.version.major_version);
+    }
+
+    #[test]
+    fn invalid_descriptors_are_reported() {
+        let bridge = NativeBridge::new("a/B").method("hit", "(Q)V", ptr::null_mut::<c_void>());
+
+        match bridge.class_data() {
+            Err(BridgeError::Model(ModelError::InvalidDescriptor(_))) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn helper_jar_stores_the_class() {
+        let path = env::temp_dir().join(format!("bridge-test-{}.jar", std::process::id()));
+        bridge().write_jar(&path).unwrap();
+
+        let jar = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let name = b"com/acme/agent/Bridge.class";
+        let data = bridge().class_data().unwrap();
+
+        // Local header, name and the stored class data
+        assert_eq!(0x0403_4b50, u32_at(&jar, 0));
+        assert_eq!(data.len() as u32, u32_at(&jar, 18));
+        assert_eq!(&name[..], &jar[30..30 + name.len()]);
+        assert_eq!(&data[..], &jar[30 + name.len()..30 + name.len() + data.len()]);
+
+        // The end of central directory record points to the only entry
+        let end = jar.len() - 22;
+        assert_eq!(0x0605_4b50, u32_at(&jar, end));
+        assert_eq!(1, jar[end + 10]);
+        assert_eq!(0x0201_4b50, u32_at(&jar, u32_at(&jar, end + 16) as usize));
+    }
+}
@@ -0,0 +1,256 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ AccessFlags, Instruction, MethodAccessFlags };
+    use jvmti::emulator::JVMEmulator;
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::capture::*;
+    use jvmti::instrumentation::code::*;
+    use jvmti::native::JavaValue;
+    use std::ptr;
+    use std::sync::Arc;
+    use super::super::{ assert_unknown_pointcut, config, helper_call, is_protected, round_trip };
+
+    /// static boolean login(String user, String password, long attempts) {
+    ///     return attempts < 3;
+    /// }
+    fn users_class() -> JavaClass {
+        let mut class = JavaClass::new();
+        class.version.major_version = 61;
+        class.name = "com/acme/Users".to_string();
+
+        let mut code = Code::new();
+        let start = code.new_label();
+        let end = code.new_label();
+        let denied = code.new_label();
+
+        code.instructions = vec![
+            Insn::Label(start),
+            Insn::Op(Instruction::LLOAD_2),
+            Insn::Ldc(Value::Long(3)),
+            Insn::Op(Instruction::LCMP),
+            Insn::Jump(Jump::IfGe, denied),
+            Insn::Op(Instruction::ICONST_1),
+            Insn::Op(Instruction::IRETURN),
+            Insn::Label(denied),
+            Insn::Frame(Frame::new(vec![ FrameItem::Object("java/lang/String".to_string()),
+                                         FrameItem::Object("java/lang/String".to_string()),
+                                         FrameItem::Long ], vec![])),
+            Insn::Op(Instruction::ICONST_0),
+            Insn::Op(Instruction::IRETURN),
+            Insn::Label(end),
+        ];
+        code.local_variables = [ ("user", "Ljava/lang/String;", 0), ("password", "Ljava/lang/String;", 1), ("attempts", "J", 2) ]
+            .iter()
+            .map(|&(name, descriptor, index)| LocalVariable {
+                name: name.to_string(),
+                descriptor: descriptor.to_string(),
+                signature: None,
+                start,
+                end,
+                index,
+            })
+            .collect();
+        code.max_stack = 4;
+        code.max_locals = 4;
+
+        let descriptor = MethodDescriptor::parse("(Ljava/lang/String;Ljava/lang/String;J)Z").unwrap();
+        let mut method = Method::new("login".to_string(), descriptor);
+        method.access_flags = AccessFlags::of(MethodAccessFlags::Static as u16);
+        method.code = Some(code);
+        class.add_method(method);
+
+        class
+    }
+
+    fn settings() -> CaptureSettings {
+        CaptureSettings { redact: vec![ "password".to_string() ], ..CaptureSettings::default() }
+    }
+
+    fn text(class_name: &str, text: &str) -> CapturedValue {
+        CapturedValue::Object { class_name: class_name.to_string(), text: text.to_string(), truncated: false }
+    }
+
+    #[test]
+    fn sites_are_described_from_the_local_variable_table() {
+        let site = CaptureSite::from_method(&users_class(), 0, &settings());
+
+        assert_eq!(vec![ ("user", 0, false), ("password", 1, true), ("attempts", 2, false) ],
+                   site.parameters.iter().map(|p| (p.name.as_str(), p.slot, p.redacted)).collect::<Vec<(&str, u16, bool)>>());
+        assert_eq!(JavaType::Boolean, site.return_type);
+
+        // Without a local variable table the parameters are numbered, `this` takes the first slot
+        let descriptor = MethodDescriptor::parse("(JI)V").unwrap();
+        let site = CaptureSite::new("com/acme/Users", "touch", &descriptor, false, &[], &settings());
+        assert_eq!(vec![ ("arg0", 1), ("arg1", 3) ],
+                   site.parameters.iter().map(|p| (p.name.as_str(), p.slot)).collect::<Vec<(&str, u16)>>());
+    }
+
+    #[test]
+    fn probes_pass_arguments_and_results() {
+        let mut class = users_class();
+        let site = CaptureSite::from_method(&class, 0, &settings());
+
+        weave(&mut class, 0, 7, &site).unwrap();
+
+        let code = class.methods[0].code.as_ref().unwrap();
+
+        // The redacted password isn't passed to the helper
+        assert_eq!(vec![ Insn::Op(Instruction::BIPUSH(7)), helper_call(HELPER_CLASS, "enter", ENTER_DESCRIPTOR),
+                         Insn::Op(Instruction::ICONST_0), Insn::Op(Instruction::ALOAD_0),
+                         helper_call(HELPER_CLASS, "objectArgument", OBJECT_ARGUMENT_DESCRIPTOR),
+                         Insn::Op(Instruction::ICONST_2), Insn::Op(Instruction::LLOAD_2),
+                         helper_call(HELPER_CLASS, "longArgument", LONG_ARGUMENT_DESCRIPTOR) ],
+                   code.instructions[..8].to_vec());
+
+        let returns: Vec<usize> = code.instructions.iter().enumerate()
+            .filter(|&(_, insn)| *insn == Insn::Op(Instruction::IRETURN))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(2, returns.len());
+        for &index in returns.iter() {
+            assert_eq!(vec![ Insn::Op(Instruction::DUP), Insn::Op(Instruction::BIPUSH(7)), helper_call(HELPER_CLASS, "returnInt", INT_RETURN_DESCRIPTOR) ],
+                       code.instructions[index - 3..index].to_vec());
+
+            // A return that throws after its probe has run mustn't be recorded as thrown too
+            assert!((index - 3..=index).all(|probe| !is_protected(code, probe)));
+            assert!(is_protected(code, index - 5));
+        }
+
+        let tail = code.instructions[code.instructions.len() - 4..].to_vec();
+        assert_eq!(vec![ Insn::Op(Instruction::DUP), Insn::Op(Instruction::BIPUSH(7)), helper_call(HELPER_CLASS, "thrown", THROWN_DESCRIPTOR),
+                         Insn::Op(Instruction::ATHROW) ], tail);
+        assert_eq!(2, code.try_catch_blocks.len());
+        assert_eq!(4, code.max_stack);
+
+        round_trip(&class);
+    }
+
+    #[test]
+    fn calls_are_recorded_per_thread() {
+        let registry = CaptureRegistry::new();
+        registry.configure(settings());
+        let login = registry.register(CaptureSite::from_method(&users_class(), 0, &registry.settings()));
+        let touch = registry.register(CaptureSite::new("com/acme/Users", "touch", &MethodDescriptor::parse("(CS)V").unwrap(),
+                                                       true, &[], &registry.settings()));
+
+        registry.enter(login);
+        registry.argument(0, text("java.lang.String", "alice"));
+        registry.argument(1, text("java.lang.String", "secret"));
+
+        // A nested call that never completes is discarded with its caller
+        registry.enter(touch);
+        registry.argument(0, CapturedValue::Int(65));
+        registry.enter(touch);
+        registry.argument(0, CapturedValue::Int(66));
+        registry.argument(1, CapturedValue::Int(-1));
+        assert!(registry.exit(touch, CallOutcome::Returned(None)).is_some());
+
+        let call = registry.exit(login, CallOutcome::Returned(Some(CapturedValue::Int(1)))).unwrap();
+        assert_eq!(vec![ text("java.lang.String", "alice"), CapturedValue::Redacted, CapturedValue::Unavailable ], call.arguments);
+        assert_eq!(CallOutcome::Returned(Some(CapturedValue::Boolean(true))), call.outcome);
+
+        let calls = registry.drain();
+        assert_eq!(2, calls.len());
+        assert_eq!("com.acme.Users.touch(arg0='B', arg1=-1) returned", registry.describe(&calls[0]));
+        assert_eq!("com.acme.Users.login(user=\"alice\", password=<redacted>, attempts=<unavailable>) returned true",
+                   registry.describe(&calls[1]));
+        assert!(registry.calls().is_empty());
+
+        // Unknown sites and exits without a matching entry are ignored
+        registry.enter(9);
+        registry.argument(0, CapturedValue::Int(1));
+        assert_eq!(None, registry.exit(9, CallOutcome::Returned(None)));
+        assert_eq!(None, registry.exit(login, CallOutcome::Threw(CapturedValue::Unavailable)));
+    }
+
+    #[test]
+    fn only_the_latest_calls_are_kept() {
+        let registry = CaptureRegistry::new();
+        let mut settings = settings();
+        settings.max_calls = 2;
+        registry.configure(settings);
+
+        let site = registry.register(CaptureSite::new("com/acme/Clock", "tick", &MethodDescriptor::parse("(I)V").unwrap(),
+                                                      true, &[], &registry.settings()));
+
+        for tick in 0..3 {
+            registry.enter(site);
+            registry.argument(0, CapturedValue::Int(tick));
+            registry.exit(site, CallOutcome::Threw(text("java.lang.IllegalStateException", "java.lang.IllegalStateException: stopped")));
+        }
+
+        let calls = registry.calls();
+        assert_eq!(vec![ vec![ CapturedValue::Int(1) ], vec![ CapturedValue::Int(2) ] ],
+                   calls.iter().map(|call| call.arguments.clone()).collect::<Vec<Vec<CapturedValue>>>());
+        assert_eq!("com.acme.Clock.tick(arg0=2) threw java.lang.IllegalStateException: stopped", registry.describe(&calls[1]));
+    }
+
+    #[test]
+    fn values_are_size_limited() {
+        let value = CapturedValue::text("java.lang.String", "päßwörd", 4);
+
+        assert_eq!(CapturedValue::Object { class_name: "java.lang.String".to_string(), text: "päßw".to_string(), truncated: true }, value);
+        assert_eq!("\"päßw...\"", value.to_string());
+        assert_eq!("[1, 2]", CapturedValue::text("java.util.List", "[1, 2]", 6).to_string());
+        assert_eq!(CapturedValue::Char('\u{e9}'), CapturedValue::from_int(&JavaType::Char, 0xe9));
+        assert_eq!(CapturedValue::Byte(-1), CapturedValue::from_int(&JavaType::Byte, 255));
+    }
+
+    #[test]
+    fn arguments_are_read_from_locals() {
+        let mut jvmti = JVMEmulator::new();
+        jvmti.locals.insert(0, JavaValue::from(ptr::null_mut()));
+        jvmti.locals.insert(2, JavaValue::from(5i64));
+
+        let site = CaptureSite::from_method(&users_class(), 0, &settings());
+        let arguments = read_arguments(&jvmti, ptr::null_mut(), 0, &site, &|object| {
+            if object.is_null() { CapturedValue::Null } else { CapturedValue::Unavailable }
+        });
+
+        assert_eq!(vec![ CapturedValue::Null, CapturedValue::Redacted, CapturedValue::Long(5) ], arguments);
+
+        let returned = JavaValue::from(1i32);
+        assert_eq!(CallOutcome::Returned(Some(CapturedValue::Boolean(true))),
+                   read_return_value(&site, Some(&returned), &|_| CapturedValue::Unavailable));
+        assert_eq!(CallOutcome::Threw(CapturedValue::Unavailable), read_return_value(&site, None, &|_| CapturedValue::Unavailable));
+    }
+
+    #[test]
+    fn capture_is_read_from_config() {
+        let config = config(r#"
+            [pointcuts]
+            users = "class(com.acme.*)"
+
+            [capture]
+            pointcuts = [ "users" ]
+            max_length = 40
+            redact = [ "Password" ]
+
+            [capture.extractors]
+            "com.acme.User" = "login"
+            "com.acme.Order" = "id:J"
+        "#);
+
+        let registry = Arc::new(CaptureRegistry::new());
+        let probes = CaptureProbes::from_config(&config, registry.clone()).unwrap();
+        let settings = registry.settings();
+
+        assert_eq!(40, settings.max_length);
+        assert_eq!(1000, settings.max_calls);
+        assert!(settings.is_redacted("password"));
+        assert_eq!(Some(&FieldExtractor { field: "login".to_string(), descriptor: "Ljava/lang/String;".to_string() }),
+                   settings.extractors.get("com.acme.User"));
+        assert_eq!(Some(&FieldExtractor { field: "id".to_string(), descriptor: "J".to_string() }),
+                   settings.extractors.get("com.acme.Order"));
+
+        // Nothing is woven before the helper class is installed
+        assert!(!is_installed());
+        assert!(!probes.accepts("com/acme/Users"));
+
+        let mut missing = config;
+        missing.capture.as_mut().unwrap().pointcuts.push("missing".to_string());
+        assert_unknown_pointcut(EventCapture::from_config(&missing, Arc::new(CaptureRegistry::new())), "missing");
+    }
+}
@@ -0,0 +1,217 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ ClassReader, ClassWriter, Instruction };
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::asm::ClassBuilder;
+    use jvmti::instrumentation::chain::*;
+    use jvmti::instrumentation::analysis;
+    use jvmti::instrumentation::code::{ FieldOp, Jump };
+    use std::io::Cursor;
+    use std::sync::{ Arc, Mutex };
+
+    fn class_data() -> Vec<u8> {
+        let classfile = ClassBuilder::new("com/acme/Target")
+            .public()
+            .default_constructor()
+            .method("run", "()I", |m| m.public().static_().iconst(1).ret())
+            .to_classfile()
+            .unwrap();
+
+        let mut data = vec![];
+        ClassWriter::new(&mut data).write_class(&classfile).unwrap();
+        data
+    }
+
+    fn read(data: &[u8]) -> JavaClass {
+        JavaClass::from_classfile(&ClassReader::read_class(&mut Cursor::new(data)).unwrap()).unwrap()
+    }
+
+    fn add_field(name: &'static str) -> impl Fn(&mut JavaClass) -> Result<bool, TransformError> {
+        move |class: &mut JavaClass| {
+            class.add_field(Field::new(name.to_string(), JavaType::Int));
+            Ok(true)
+        }
+    }
+
+    struct OnlyPackage(&'static str);
+
+    impl ClassTransformer for OnlyPackage {
+        fn accepts(&self, class_name: &str) -> bool {
+            class_name.starts_with(self.0)
+        }
+
+        fn transform(&self, class: &mut JavaClass) -> Result<bool, TransformError> {
+            class.add_interface("java/io/Serializable");
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn transformers_run_in_priority_order() {
+        let order = Arc::new(Mutex::new(vec![]));
+        let mut chain = TransformerChain::new();
+
+        for &(name, priority) in [ ("low", -5), ("first", 10), ("default", 0), ("second", 10) ].iter() {
+            let order = order.clone();
+            chain.add(name, priority, move |_: &mut JavaClass| {
+                order.lock().unwrap().push(name);
+                Ok(false)
+            });
+        }
+
+        assert_eq!(vec![ "first", "second", "default", "low" ], chain.names());
+        assert_eq!(None, chain.transform("com/acme/Target", &class_data()));
+        assert_eq!(vec![ "first", "second", "default", "low" ], *order.lock().unwrap());
+
+        assert!(chain.remove("default"));
+        assert!(!chain.remove("default"));
+        chain.add("first", -10, |_: &mut JavaClass| Ok(false));
+        assert_eq!(vec![ "second", "low", "first" ], chain.names());
+    }
+
+    #[test]
+    fn failing_transformers_are_isolated() {
+        let mut chain = TransformerChain::new();
+
+        chain.add("counter", 0, add_field("counter"));
+        chain.add("panics", 0, |class: &mut JavaClass| -> Result<bool, TransformError> {
+            class.add_field(Field::new("partial".to_string(), JavaType::Int));
+            panic!("boom")
+        });
+        chain.add("fails", 0, |class: &mut JavaClass| {
+            class.add_field(Field::new("partial".to_string(), JavaType::Int));
+            Err(TransformError::Failed("unsupported".to_string()))
+        });
+        chain.add("unverifiable", 0, |class: &mut JavaClass| {
+            let code = class.method_mut("run", "()I").unwrap().code.as_mut().unwrap();
+            code.instructions.insert(0, Insn::Op(Instruction::POP));
+            Ok(true)
+        });
+        chain.add("enabled", 0, add_field("enabled"));
+
+        let transformed = read(&chain.transform("com/acme/Target", &class_data()).unwrap());
+        let fields: Vec<&str> = transformed.fields.iter().map(|f| f.name.as_str()).collect();
+
+        assert_eq!(vec![ "counter", "enabled" ], fields);
+        assert_eq!(read(&class_data()).method("run", "()I"), transformed.method("run", "()I"));
+
+        let panics = chain.stats("panics").unwrap();
+        assert_eq!((1, 0, 1, 1), (panics.invocations, panics.modified, panics.failures, panics.panics));
+        assert_eq!(Some(("com/acme/Target".to_string(), TransformError::Panicked("boom".to_string()))),
+                   panics.last_failure);
+
+        let fails = chain.stats("fails").unwrap();
+        assert_eq!((1, 0, 1, 0), (fails.invocations, fails.modified, fails.failures, fails.panics));
+
+        let unverifiable = chain.stats("unverifiable").unwrap();
+        assert_eq!(Some(("com/acme/Target".to_string(), TransformError::Model(ModelError::StackUnderflow(0)))),
+                   unverifiable.last_failure);
+
+        let counter = chain.stats("counter").unwrap();
+        assert_eq!((1, 1, 0), (counter.invocations, counter.modified, counter.failures));
+        assert!(counter.max_time <= counter.total_time);
+
+        assert_eq!(ChainStats { classes_parsed: 1, classes_transformed: 1, read_failures: 0, write_failures: 0 },
+                   chain.chain_stats());
+    }
+
+    #[test]
+    fn modified_methods_get_new_maxs() {
+        let mut chain = TransformerChain::new();
+
+        chain.add("store", 0, |class: &mut JavaClass| {
+            let code = class.method_mut("run", "()I").unwrap().code.as_mut().unwrap();
+            code.instructions.insert(0, Insn::Op(Instruction::LCONST_0));
+            code.instructions.insert(1, Insn::Op(Instruction::LSTORE_2));
+            Ok(true)
+        });
+
+        let transformed = read(&chain.transform("com/acme/Target", &class_data()).unwrap());
+        let code = transformed.method("run", "()I").unwrap().code.as_ref().unwrap();
+
+        assert_eq!((2, 4), (code.max_stack, code.max_locals));
+    }
+
+    /// Prepends a branch to `run`, with frames computed and then replaced by the given one
+    fn branch(frame: Option<Frame>) -> impl Fn(&mut JavaClass) -> Result<bool, TransformError> {
+        move |class: &mut JavaClass| {
+            let method = class.method_mut("run", "()I").unwrap();
+            let label = method.code.as_mut().unwrap().new_label();
+
+            method.code.as_mut().unwrap().instructions.splice(0..0, vec![ Insn::Op(Instruction::ICONST_0),
+                                                                          Insn::Jump(Jump::IfEq, label),
+                                                                          Insn::Label(label) ]);
+
+            if let Some(ref frame) = frame {
+                analysis::compute_frames("com/acme/Target", method)?;
+
+                for insn in method.code.as_mut().unwrap().instructions.iter_mut() {
+                    if let Insn::Frame(computed) = insn {
+                        *computed = frame.clone();
+                    }
+                }
+            }
+
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn modified_methods_need_matching_frames() {
+        let mut chain = TransformerChain::new();
+
+        chain.add("missing", 0, branch(None));
+        assert_eq!(None, chain.transform("com/acme/Target", &class_data()));
+        assert_eq!(Some(TransformError::Model(ModelError::InvalidFrame(3))),
+                   chain.stats("missing").unwrap().last_failure.map(|failure| failure.1));
+
+        chain.add("missing", 0, branch(Some(Frame::new(vec![], vec![ FrameItem::Integer ]))));
+        assert_eq!(None, chain.transform("com/acme/Target", &class_data()));
+        assert_eq!(Some(TransformError::Model(ModelError::InvalidFrame(4))),
+                   chain.stats("missing").unwrap().last_failure.map(|failure| failure.1));
+
+        chain.add("computed", 0, branch(Some(Frame::new(vec![], vec![]))));
+        let transformed = read(&chain.transform("com/acme/Target", &class_data()).unwrap());
+        assert!(transformed.method("run", "()I").unwrap().code.as_ref().unwrap().instructions.contains(&Insn::Frame(Frame::new(vec![], vec![]))));
+    }
+
+    #[test]
+    fn uninteresting_classes_are_not_parsed() {
+        let mut chain = TransformerChain::new();
+        chain.add("acme", 0, OnlyPackage("com/acme/"));
+
+        assert_eq!(None, chain.transform("org/other/Target", b"not a class"));
+        assert_eq!(0, chain.chain_stats().classes_parsed);
+        assert_eq!(0, chain.stats("acme").unwrap().invocations);
+
+        assert_eq!(None, chain.transform("com/acme/Broken", b"not a class"));
+        assert_eq!(1, chain.chain_stats().read_failures);
+
+        let transformed = read(&chain.transform("com/acme/Target", &class_data()).unwrap());
+        assert_eq!(vec![ "java/io/Serializable".to_string() ], transformed.interfaces);
+    }
+
+    #[test]
+    fn unverifiable_new_methods_are_discarded() {
+        let mut chain = TransformerChain::new();
+
+        chain.add("dangling", 0, |class: &mut JavaClass| {
+            let mut method = Method::new("get".to_string(), MethodDescriptor::parse("()I").unwrap());
+            let mut code = Code::new();
+            let label = code.new_label();
+            code.instructions = vec![ Insn::Field(FieldOp::GetStatic, MemberRef::new("com/acme/Target", "x", "I")),
+                                      Insn::Jump(Jump::Goto, label) ];
+            method.code = Some(code);
+            class.add_method(method);
+            Ok(true)
+        });
+
+        assert_eq!(None, chain.transform("com/acme/Target", &class_data()));
+        match chain.stats("dangling").unwrap().last_failure {
+            Some((_, TransformError::Model(ModelError::UnplacedLabel(_)))) => (),
+            other => panic!("unexpected failure {:?}", other),
+        }
+    }
+}
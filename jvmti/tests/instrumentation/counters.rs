@@ -0,0 +1,200 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ AccessFlags, ClassAccessFlags, Instruction, MethodAccessFlags };
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::code::*;
+    use jvmti::instrumentation::counters::*;
+    use std::sync::Arc;
+    use super::super::{ assert_unknown_pointcut, config, round_trip };
+
+    /// class Orders { static int total; static { total = 1; } int count(List l) { l.size(); return l.hashCode(); } void clear() { } }
+    fn orders_class() -> JavaClass {
+        let mut class = JavaClass::new();
+        class.name = "com/acme/Orders".to_string();
+
+        let mut clinit = Code::new();
+        clinit.instructions = vec![
+            Insn::Op(Instruction::ICONST_1),
+            Insn::Field(FieldOp::PutStatic, MemberRef::new("com/acme/Orders", "total", "I")),
+            Insn::Op(Instruction::RETURN),
+        ];
+
+        let mut count = Code::new();
+        count.max_locals = 2;
+        count.instructions = vec![
+            Insn::LineNumber(10),
+            Insn::Op(Instruction::ALOAD_1),
+            Insn::Invoke(Invoke::Interface, MemberRef::interface("java/util/List", "size", "()I")),
+            Insn::Op(Instruction::POP),
+            Insn::LineNumber(11),
+            Insn::Op(Instruction::ALOAD_1),
+            Insn::Invoke(Invoke::Virtual, MemberRef::new("java/lang/Object", "hashCode", "()I")),
+            Insn::Op(Instruction::IRETURN),
+        ];
+
+        let mut clear = Code::new();
+        clear.max_locals = 1;
+        clear.instructions = vec![ Insn::Op(Instruction::RETURN) ];
+
+        for &(name, descriptor, access, ref code) in [ ("<clinit>", "()V", MethodAccessFlags::Static as u16, clinit),
+                                                       ("count", "(Ljava/util/List;)I", 0, count),
+                                                       ("clear", "()V", 0, clear) ].iter() {
+            let mut method = Method::new(name.to_string(), MethodDescriptor::parse(descriptor).unwrap());
+            method.access_flags = AccessFlags::of(access);
+            method.code = Some(code.clone());
+            class.add_method(method);
+        }
+
+        class
+    }
+
+    fn increment(slot: Instruction) -> Vec<Insn> {
+        vec![ Insn::Field(FieldOp::GetStatic, MemberRef::new("com/acme/Orders", COUNTER_FIELD, COUNTER_DESCRIPTOR)),
+              Insn::Op(slot),
+              Insn::Op(Instruction::DUP2),
+              Insn::Op(Instruction::LALOAD),
+              Insn::Op(Instruction::LCONST_1),
+              Insn::Op(Instruction::LADD),
+              Insn::Op(Instruction::LASTORE) ]
+    }
+
+    fn site(method_name: &str, callee: Option<&str>) -> CounterSite {
+        CounterSite {
+            class_name: "com/acme/Orders".to_string(),
+            method_name: method_name.to_string(),
+            descriptor: "()V".to_string(),
+            callee: callee.map(|c| c.to_string()),
+            line: None,
+        }
+    }
+
+    #[test]
+    fn methods_count_their_invocations() {
+        let mut class = orders_class();
+        let sites = weave(&mut class, &[ 1, 2 ], CounterMode::Method).unwrap();
+
+        assert_eq!(vec![ ("count", None), ("clear", None) ],
+                   sites.iter().map(|s| (s.method_name.as_str(), s.callee.clone())).collect::<Vec<(&str, Option<String>)>>());
+
+        assert_eq!(increment(Instruction::ICONST_0), class.methods[1].code.as_ref().unwrap().instructions[0..7].to_vec());
+        assert_eq!(increment(Instruction::ICONST_1), class.methods[2].code.as_ref().unwrap().instructions[0..7].to_vec());
+        assert_eq!(6, class.methods[2].code.as_ref().unwrap().max_stack);
+
+        // The existing static initialiser creates the array first
+        let clinit = class.method("<clinit>", "()V").unwrap().code.as_ref().unwrap();
+        assert_eq!(vec![ Insn::Op(Instruction::ICONST_2),
+                         Insn::Op(Instruction::NEWARRAY(11)),
+                         Insn::Field(FieldOp::PutStatic, MemberRef::new("com/acme/Orders", COUNTER_FIELD, COUNTER_DESCRIPTOR)),
+                         Insn::Op(Instruction::ICONST_1) ],
+                   clinit.instructions[0..4].to_vec());
+
+        let field = class.field(COUNTER_FIELD).unwrap();
+        assert!(field.is_static());
+        assert_eq!("[J", field.field_type.descriptor());
+
+        let read = round_trip(&class);
+        assert!(read.field(COUNTER_FIELD).is_some());
+        assert_eq!(3, read.methods.len());
+    }
+
+    #[test]
+    fn call_sites_count_the_calls_made() {
+        let mut class = orders_class();
+        class.methods.remove(0);
+
+        let sites = weave(&mut class, &[ 0, 1 ], CounterMode::CallSite).unwrap();
+
+        assert_eq!(vec![ (Some("java/util/List.size()I".to_string()), Some(10)),
+                         (Some("java/lang/Object.hashCode()I".to_string()), Some(11)) ],
+                   sites.iter().map(|s| (s.callee.clone(), s.line)).collect::<Vec<(Option<String>, Option<u16>)>>());
+
+        let count = class.methods[0].code.as_ref().unwrap();
+        assert_eq!(increment(Instruction::ICONST_0), count.instructions[2..9].to_vec());
+        assert_eq!(increment(Instruction::ICONST_1), count.instructions[13..20].to_vec());
+
+        // A method without calls is left alone, and a static initialiser is created
+        assert_eq!(vec![ Insn::Op(Instruction::RETURN) ], class.methods[1].code.as_ref().unwrap().instructions);
+
+        let clinit = class.method("<clinit>", "()V").unwrap();
+        assert!(clinit.is_static());
+        assert_eq!(vec![ Insn::Op(Instruction::ICONST_2),
+                         Insn::Op(Instruction::NEWARRAY(11)),
+                         Insn::Field(FieldOp::PutStatic, MemberRef::new("com/acme/Orders", COUNTER_FIELD, COUNTER_DESCRIPTOR)),
+                         Insn::Op(Instruction::RETURN) ],
+                   clinit.code.as_ref().unwrap().instructions);
+
+        round_trip(&class);
+    }
+
+    #[test]
+    fn classes_without_sites_are_unchanged() {
+        let mut class = orders_class();
+        let original = class.clone();
+
+        assert!(weave(&mut class, &[ 0, 2 ], CounterMode::CallSite).unwrap().is_empty());
+        assert_eq!(original, class);
+    }
+
+    #[test]
+    fn registry_keeps_the_last_read_counts() {
+        let registry = CounterRegistry::new();
+
+        registry.register("com/acme/Orders", vec![ site("count", None), site("clear", None) ]);
+
+        assert_eq!(Some(2), registry.slots("com/acme/Orders"));
+        assert_eq!(None, registry.slots("com/acme/Users"));
+
+        registry.update("com/acme/Orders", &[ 5, 7, 9 ]);
+        assert_eq!(Some(7), registry.count("com/acme/Orders", 1));
+
+        // Registering the same slots again, eg. from another class loader, keeps the counts
+        registry.register("com/acme/Orders", vec![ site("count", None), site("clear", None) ]);
+        assert_eq!(vec![ 5, 7 ], registry.snapshot().into_iter().map(|(_, count)| count).collect::<Vec<u64>>());
+
+        registry.register("com/acme/Orders", vec![ site("count", Some("java/util/List.size()I")) ]);
+        assert_eq!(Some(0), registry.count("com/acme/Orders", 0));
+        assert_eq!(1, registry.len());
+
+        registry.update("com/acme/Orders", &[]);
+        assert_eq!(Some(0), registry.count("com/acme/Orders", 0));
+    }
+
+    #[test]
+    fn counters_are_read_from_config() {
+        let config = config(r#"
+            [pointcuts]
+            orders = "class(com.acme.*)"
+
+            [counters]
+            pointcuts = [ "orders" ]
+            mode = "call_site"
+        "#);
+
+        let counters = config.counters.as_ref().unwrap();
+        assert_eq!(CounterMode::CallSite, counters.mode);
+        assert_eq!(1000, counters.interval);
+
+        let probes = CounterProbes::from_config(&config, Arc::new(CounterRegistry::new())).unwrap();
+
+        // No helper class is needed, so the weaving starts right away
+        assert!(probes.accepts("com/acme/Orders"));
+        assert!(!probes.accepts("jvmti/probe/Orders"));
+
+        let mut class = orders_class();
+        assert!(probes.transform(&mut class).unwrap());
+        assert_eq!(2, probes.registry().len());
+
+        // Woven classes aren't woven again, and interfaces aren't woven at all
+        assert!(!probes.transform(&mut class).unwrap());
+
+        let mut interface = orders_class();
+        interface.access_flags = AccessFlags::of(ClassAccessFlags::Interface as u16 | ClassAccessFlags::Abstract as u16);
+        assert!(!probes.transform(&mut interface).unwrap());
+
+        let mut missing = config;
+        missing.counters.as_mut().unwrap().pointcuts.push("missing".to_string());
+        assert_unknown_pointcut(CounterProbes::from_config(&missing, probes.registry()), "missing");
+    }
+}
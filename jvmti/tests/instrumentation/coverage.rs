@@ -0,0 +1,300 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ AccessFlags, Instruction, MethodAccessFlags };
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::analysis;
+    use jvmti::instrumentation::asm::ClassBuilder;
+    use jvmti::instrumentation::code::*;
+    use jvmti::instrumentation::coverage::*;
+    use std::sync::Arc;
+    use super::super::{ assert_unknown_pointcut, config, round_trip };
+
+    /// static int sign(int x) {
+    ///     if (x < 0)        // line 10
+    ///         return -1;    // line 11
+    ///     return 1;         // line 12
+    /// }
+    fn sign_class() -> JavaClass {
+        let mut class = JavaClass::new();
+        class.version.major_version = 61;
+        class.name = "com/acme/Sign".to_string();
+        class.source_file = Some("Sign.java".to_string());
+
+        let mut code = Code::new();
+        let positive = code.new_label();
+
+        code.max_stack = 1;
+        code.max_locals = 1;
+        code.instructions = vec![
+            Insn::LineNumber(10),
+            Insn::Op(Instruction::ILOAD_0),
+            Insn::Jump(Jump::IfGe, positive),
+            Insn::LineNumber(11),
+            Insn::Op(Instruction::ICONST_M1),
+            Insn::Op(Instruction::IRETURN),
+            Insn::Label(positive),
+            Insn::Frame(Frame::new(vec![ FrameItem::Integer ], vec![])),
+            Insn::LineNumber(12),
+            Insn::Op(Instruction::ICONST_1),
+            Insn::Op(Instruction::IRETURN),
+        ];
+
+        let mut method = Method::new("sign".to_string(), MethodDescriptor::parse("(I)I").unwrap());
+        method.access_flags = AccessFlags::of(MethodAccessFlags::Static as u16);
+        method.code = Some(code);
+        class.add_method(method);
+
+        class
+    }
+
+    fn expected_coverage() -> ClassCoverage {
+        ClassCoverage {
+            class_name: "com/acme/Sign".to_string(),
+            source_file: Some("Sign.java".to_string()),
+            methods: vec![ MethodCoverage {
+                name: "sign".to_string(),
+                descriptor: "(I)I".to_string(),
+                entry: 0,
+                lines: vec![ LineProbe { line: 10, probe: 0 }, LineProbe { line: 11, probe: 3 }, LineProbe { line: 12, probe: 4 } ],
+                branches: vec![ BranchProbe { line: 10, block: 0, branch: 0, probe: 1 },
+                                BranchProbe { line: 10, block: 0, branch: 1, probe: 2 } ],
+            } ],
+            probes: 5,
+        }
+    }
+
+    fn hit_insns(class_id: i32, probe: i32) -> Vec<Insn> {
+        let push = |value: i32| Insn::Op(match value {
+            0 => Instruction::ICONST_0,
+            1 => Instruction::ICONST_1,
+            2 => Instruction::ICONST_2,
+            3 => Instruction::ICONST_3,
+            4 => Instruction::ICONST_4,
+            _ => Instruction::BIPUSH(value as u8),
+        });
+
+        vec![ push(class_id), push(probe), Insn::Invoke(Invoke::Static, MemberRef::new(HELPER_CLASS, "hit", HIT_DESCRIPTOR)) ]
+    }
+
+    /// The sign method after x = 5 was passed twice and x = -1 once
+    fn registry() -> CoverageRegistry {
+        let registry = CoverageRegistry::new();
+        let id = registry.register("com/acme/Sign");
+
+        registry.update(id, expected_coverage());
+        for &probe in [ 0, 2, 4, 0, 2, 4, 0, 1, 3 ].iter() {
+            registry.hit(id, probe);
+        }
+
+        registry
+    }
+
+    #[test]
+    fn probes_are_woven_into_blocks_and_branches() {
+        let mut class = sign_class();
+        let coverage = weave(&mut class, &[ 0 ], 0).unwrap();
+
+        assert_eq!(expected_coverage(), coverage);
+
+        let code = class.methods[0].code.as_ref().unwrap();
+        let trampoline = match code.instructions[5] {
+            Insn::Jump(Jump::IfGe, label) => label,
+            ref other => panic!("unexpected instruction {:?}", other),
+        };
+
+        assert_eq!(hit_insns(0, 0), code.instructions[1..4].to_vec());
+        assert_eq!(hit_insns(0, 1), code.instructions[6..9].to_vec());
+        assert_eq!(Insn::LineNumber(11), code.instructions[9]);
+
+        // The trampoline of the jump has the frame of the original target
+        let tail = code.instructions[code.instructions.len() - 6..].to_vec();
+        let mut expected = vec![ Insn::Label(trampoline), Insn::Frame(Frame::new(vec![ FrameItem::Integer ], vec![])) ];
+        expected.extend(hit_insns(0, 2));
+        expected.push(Insn::Jump(Jump::Goto, match sign_class().methods[0].code.as_ref().unwrap().instructions[2] {
+            Insn::Jump(_, target) => target,
+            _ => unreachable!(),
+        }));
+        assert_eq!(expected, tail);
+        assert_eq!(2, code.max_stack);
+
+        let read = round_trip(&class);
+        assert_eq!(5, read.methods[0].code.as_ref().unwrap().instructions.iter()
+            .filter(|insn| match **insn { Insn::Invoke(Invoke::Static, ref member) => member.owner == HELPER_CLASS, _ => false })
+            .count());
+    }
+
+    /// static Object pick(int y, int x) {
+    ///     return y != 0 ? new StringBuilder(x != 0 ? 1 : 2) : null;
+    /// }
+    fn allocating_class() -> JavaClass {
+        ClassBuilder::new("com/acme/Pick")
+            .method("pick", "(II)Ljava/lang/Object;", |mut m| {
+                let none = m.new_label();
+                let end = m.new_label();
+                let two = m.new_label();
+                let init = m.new_label();
+
+                m.static_().line(5).iload(0).ifeq(none)
+                    .new_("java/lang/StringBuilder").dup().iload(1).ifeq(two)
+                    .iconst(1).goto(init)
+                    .mark(two).iconst(2)
+                    .mark(init).invokespecial("java/lang/StringBuilder", "<init>", "(I)V").goto(end)
+                    .mark(none).aconst_null()
+                    .mark(end).ret()
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn uninitialized_values_still_refer_to_their_allocation() {
+        let mut class = allocating_class();
+        weave(&mut class, &[ 0 ], 0).unwrap();
+
+        let read = round_trip(&class);
+        let instructions = &read.methods[0].code.as_ref().unwrap().instructions;
+        let mut uninitialized = vec![];
+
+        for insn in instructions.iter() {
+            if let Insn::Frame(frame) = insn {
+                uninitialized.extend(frame.locals.iter().chain(frame.stack.iter()).filter_map(|item| match item {
+                    FrameItem::Uninitialized(label) => Some(*label),
+                    _ => None,
+                }));
+            }
+        }
+
+        assert_eq!(6, uninitialized.len());
+
+        for label in uninitialized {
+            let position = instructions.iter().position(|insn| *insn == Insn::Label(label)).unwrap();
+            let allocation = instructions[position..].iter().find(|insn| insn.is_instruction());
+
+            assert_eq!(Some(&Insn::Type(TypeOp::New, "java/lang/StringBuilder".to_string())), allocation);
+        }
+
+        // The probe of the block runs before the allocation
+        let new = instructions.iter().position(|insn| matches!(insn, Insn::Type(TypeOp::New, _))).unwrap();
+        assert_eq!(hit_insns(0, 3), instructions[new - 4..new - 1].to_vec());
+        assert_eq!(Ok(()), analysis::verify_frames("com/acme/Pick", &read.methods[0]));
+    }
+
+    #[test]
+    fn switches_get_a_probe_per_target() {
+        let mut class = sign_class();
+        {
+            let code = class.methods[0].code.as_mut().unwrap();
+            let target = match code.instructions[2] { Insn::Jump(_, target) => target, _ => unreachable!() };
+
+            code.instructions[2] = Insn::LookupSwitch { default: target, pairs: vec![ (1, target), (2, target) ] };
+        }
+
+        let coverage = weave(&mut class, &[ 0 ], 3).unwrap();
+
+        // All keys jump to the same target, so there's a single outcome
+        assert_eq!(vec![ BranchProbe { line: 10, block: 0, branch: 0, probe: 1 } ], coverage.methods[0].branches);
+        class.to_classfile().unwrap();
+    }
+
+    #[test]
+    fn hits_are_kept_for_unchanged_probes() {
+        let registry = registry();
+
+        assert_eq!(0, registry.register("com/acme/Sign"));
+        registry.update(0, expected_coverage());
+        assert_eq!(vec![ 3, 1, 2, 1, 2 ], registry.snapshot()[0].1);
+
+        let mut changed = expected_coverage();
+        changed.probes = 6;
+        registry.update(0, changed);
+        assert_eq!(vec![ 0; 6 ], registry.snapshot()[0].1);
+
+        // Unknown classes and probes are ignored
+        registry.hit(7, 0);
+        registry.hit(0, 9);
+        assert_eq!(1, registry.len());
+    }
+
+    #[test]
+    fn lcov_report() {
+        assert_eq!("TN:\n\
+                    SF:com/acme/Sign.java\n\
+                    FN:10,com.acme.Sign.sign(I)I\n\
+                    FNDA:3,com.acme.Sign.sign(I)I\n\
+                    FNF:1\n\
+                    FNH:1\n\
+                    BRDA:10,0,0,1\n\
+                    BRDA:10,0,1,2\n\
+                    BRF:2\n\
+                    BRH:2\n\
+                    DA:10,3\n\
+                    DA:11,1\n\
+                    DA:12,2\n\
+                    LF:3\n\
+                    LH:3\n\
+                    end_of_record\n", lcov(&registry().snapshot()));
+
+        let mut unreached = registry().snapshot();
+        unreached[0].1 = vec![ 0; 5 ];
+        let report = lcov(&unreached);
+
+        assert!(report.contains("BRDA:10,0,0,-\n"));
+        assert!(report.contains("FNH:0\n"));
+        assert!(report.contains("LH:0\n"));
+    }
+
+    #[test]
+    fn cobertura_report() {
+        let report = cobertura(&registry().snapshot(), 1234);
+
+        assert!(report.contains("<coverage line-rate=\"1.0000\" branch-rate=\"1.0000\" lines-covered=\"3\" lines-valid=\"3\" \
+                                 branches-covered=\"2\" branches-valid=\"2\" complexity=\"0\" version=\"0\" timestamp=\"1234\">"));
+        assert!(report.contains("<package name=\"com.acme\""));
+        assert!(report.contains("<class name=\"com.acme.Sign\" filename=\"com/acme/Sign.java\""));
+        assert!(report.contains("<method name=\"sign\" signature=\"(I)I\""));
+        assert!(report.contains("<line number=\"10\" hits=\"3\" branch=\"true\" condition-coverage=\"100% (2/2)\"/>"));
+        assert!(report.contains("<line number=\"12\" hits=\"2\" branch=\"false\"/>"));
+
+        let mut constructor = registry().snapshot();
+        constructor[0].0.methods[0].name = "<init>".to_string();
+        assert!(cobertura(&constructor, 0).contains("<method name=\"&lt;init&gt;\""));
+    }
+
+    #[test]
+    fn source_paths() {
+        let mut class = ClassCoverage::new("com/acme/Service$Worker");
+        assert_eq!("com/acme/Service.java", class.source_path());
+
+        class.source_file = Some("Services.kt".to_string());
+        assert_eq!("com/acme/Services.kt", class.source_path());
+        assert_eq!("Main.java", ClassCoverage::new("Main").source_path());
+    }
+
+    #[test]
+    fn coverage_is_read_from_config() {
+        let config = config(r#"
+            [pointcuts]
+            services = "class(com.acme.*)"
+
+            [coverage]
+            pointcuts = [ "services" ]
+            lcov = "coverage.info"
+        "#);
+
+        let coverage = config.coverage.clone().unwrap();
+        assert_eq!(Some("coverage.info".to_string()), coverage.lcov);
+        assert_eq!(None, coverage.cobertura);
+
+        let probes = CoverageProbes::from_config(&config, Arc::new(CoverageRegistry::new())).unwrap();
+
+        // Nothing is woven before the helper class is installed
+        assert!(!is_installed());
+        assert!(!probes.accepts("com/acme/Service"));
+
+        let mut missing = config;
+        missing.coverage.as_mut().unwrap().pointcuts.push("missing".to_string());
+        assert_unknown_pointcut(CoverageProbes::from_config(&missing, Arc::new(CoverageRegistry::new())), "missing");
+    }
+}
@@ -0,0 +1,181 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::Instruction;
+    use jvmti::emulator::JVMEmulator;
+    use jvmti::error::RedefineError;
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::asm::ClassBuilder;
+    use jvmti::native::jvmti_native::jclass;
+
+    /// com.acme.Service with `static int pick()` and `static void load()`
+    fn service_class() -> JavaClass {
+        ClassBuilder::new("com/acme/Service")
+            .public()
+            .method("pick", "()I", |m| m.public().static_().iconst(1).ret())
+            .method("load", "()V", |m| m.public().static_().ret())
+            .build()
+            .unwrap()
+    }
+
+    /// A weaver that puts a BIPUSH/POP pair with the given marker in front of the method body
+    fn marker(value: i32) -> impl Fn(&mut JavaClass, usize) -> Result<(), TransformError> + Send + Sync {
+        move |class: &mut JavaClass, index: usize| {
+            let code = class.methods[index].code.as_mut().unwrap();
+            code.instructions.insert(0, Insn::Op(Instruction::POP));
+            code.instructions.insert(0, Insn::Op(Instruction::BIPUSH(value as u8)));
+            Ok(())
+        }
+    }
+
+    fn markers(class: &JavaClass, name: &str, descriptor: &str) -> Vec<u8> {
+        class.method(name, descriptor).unwrap().code.as_ref().unwrap().instructions.iter().filter_map(|insn| match *insn {
+            Insn::Op(Instruction::BIPUSH(value)) => Some(value),
+            _ => None,
+        }).collect()
+    }
+
+    fn emulator(classes: &[(usize, &str)]) -> JVMEmulator {
+        let mut emu = JVMEmulator::new();
+
+        for &(id, signature) in classes {
+            emu.loaded_classes.push(id as jclass);
+            emu.class_signatures.insert(id as jclass, signature.to_string());
+        }
+
+        emu
+    }
+
+    #[test]
+    fn probes_are_woven_into_the_selected_methods() {
+        let manager = ProbeManager::new();
+        manager.add("b", Pointcut::parse("class(com.acme.*) && method(pick)").unwrap(), marker(2));
+        manager.add("a", Pointcut::parse("class(com.acme.*)").unwrap(), marker(1));
+
+        assert!(manager.accepts("com/acme/Service"));
+        assert!(!manager.accepts("org/other/Service"));
+
+        let mut class = service_class();
+        assert!(manager.transform(&mut class).unwrap());
+
+        // Probes are woven in the order of their names
+        assert_eq!(vec![ 2, 1 ], markers(&class, "pick", "()I"));
+        assert_eq!(vec![ 1 ], markers(&class, "load", "()V"));
+        assert_eq!(vec![ "com/acme/Service".to_string() ], manager.woven_classes("b"));
+        assert_eq!(vec![ "a".to_string(), "b".to_string() ], manager.probes());
+    }
+
+    #[test]
+    fn probes_still_in_the_class_are_not_woven_twice() {
+        let manager = ProbeManager::new();
+        manager.add("pick", Pointcut::parse("method(pick)").unwrap(), marker(1));
+
+        let mut class = service_class();
+        assert!(manager.transform(&mut class).unwrap());
+        assert!(manager.original("com/acme/Service").is_some());
+
+        // Transforming the woven class again doesn't weave the probe twice
+        assert!(manager.transform(&mut class).unwrap());
+        assert_eq!(vec![ 1 ], markers(&class, "pick", "()I"));
+
+        assert_eq!(Some(vec![ "com/acme/Service".to_string() ]), manager.remove("pick"));
+        assert_eq!(None, manager.remove("pick"));
+
+        // Once the last probe is gone, the class is restored and the cache entry dropped
+        assert!(manager.accepts("com/acme/Service"));
+        assert!(manager.transform(&mut class).unwrap());
+        assert_eq!(Vec::<u8>::new(), markers(&class, "pick", "()I"));
+        assert_eq!(service_class().method("pick", "()I").unwrap().code, class.method("pick", "()I").unwrap().code);
+        assert_eq!(None, manager.original("com/acme/Service"));
+        assert!(!manager.accepts("com/acme/Service"));
+    }
+
+    #[test]
+    fn changes_of_earlier_transformers_are_kept() {
+        let manager = ProbeManager::new();
+        manager.add("pick", Pointcut::parse("method(pick)").unwrap(), marker(1));
+
+        let mut class = service_class();
+        assert!(manager.transform(&mut class).unwrap());
+
+        // Transformed again with the probe still in place, after another transformer has
+        // changed the class
+        let load = class.methods.iter().position(|m| m.name == "load").unwrap();
+        marker(7)(&mut class, load).unwrap();
+        class.add_field(Field::new("count".to_string(), JavaType::Int));
+
+        assert!(manager.transform(&mut class).unwrap());
+        assert_eq!(vec![ 1 ], markers(&class, "pick", "()I"));
+        assert_eq!(vec![ 7 ], markers(&class, "load", "()V"));
+        assert!(class.field("count").is_some());
+
+        // Classes that arrive without the probes, eg. on retransformation, are woven as they are
+        let mut retransformed = service_class();
+        retransformed.add_field(Field::new("count".to_string(), JavaType::Int));
+
+        assert!(manager.transform(&mut retransformed).unwrap());
+        assert_eq!(vec![ 1 ], markers(&retransformed, "pick", "()I"));
+        assert!(retransformed.field("count").is_some());
+    }
+
+    #[test]
+    fn failing_weavers_leave_the_state_unchanged() {
+        let manager = ProbeManager::new();
+        manager.add("broken", Pointcut::parse("class(com.acme.*)").unwrap(),
+                    |_: &mut JavaClass, _: usize| Err(TransformError::Failed("broken".to_string())));
+
+        let mut class = service_class();
+        assert_eq!(Err(TransformError::Failed("broken".to_string())), manager.transform(&mut class));
+        assert!(manager.woven_classes("broken").is_empty());
+        assert_eq!(None, manager.original("com/acme/Service"));
+    }
+
+    #[test]
+    fn only_affected_classes_are_retransformed() {
+        let emu = emulator(&[ (1, "Lcom/acme/Service;"), (2, "Lorg/other/Main;"), (3, "[Lcom/acme/Service;"),
+                              (4, "Lcom/acme/Cart;") ]);
+        let manager = ProbeManager::new();
+
+        assert_eq!(vec![ "com/acme/Service".to_string() ],
+                   manager.enable("pick", Pointcut::parse("execution(com.acme.Service.pick)").unwrap(), marker(1), &emu).unwrap());
+        assert_eq!(vec![ 1 as jclass ], *emu.retransformed.lock().unwrap());
+
+        // The emulator doesn't run the transformers, so the class is woven here
+        manager.transform(&mut service_class()).unwrap();
+        emu.retransformed.lock().unwrap().clear();
+
+        // Replacing a probe retransforms the classes the old one is woven into as well
+        assert_eq!(vec![ "com/acme/Service".to_string(), "com/acme/Cart".to_string() ],
+                   manager.enable("pick", Pointcut::parse("execution(com.acme.Cart.pick)").unwrap(), marker(1), &emu).unwrap());
+        emu.retransformed.lock().unwrap().clear();
+
+        assert_eq!(vec![ "com/acme/Service".to_string() ], manager.disable("pick", &emu).unwrap());
+        assert_eq!(vec![ 1 as jclass ], *emu.retransformed.lock().unwrap());
+        assert!(manager.disable("pick", &emu).unwrap().is_empty());
+
+        // Classes that can't be retransformed fail the whole batch
+        let mut emu = emulator(&[ (1, "Lcom/acme/Service;") ]);
+        emu.loaded_classes.push(0 as jclass);
+        emu.class_signatures.insert(0 as jclass, "Lcom/acme/Hidden;".to_string());
+
+        match manager.enable("all", Pointcut::parse("class(com.acme.*)").unwrap(), marker(1), &emu) {
+            Err(RedefineError::Unmodifiable(positions)) => assert_eq!(vec![ 1 ], positions),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(emu.retransformed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn enabling_requires_the_retransform_capability() {
+        let mut emu = emulator(&[ (1, "Lcom/acme/Service;") ]);
+        emu.capabilities.can_retransform_classes = false;
+
+        let manager = ProbeManager::new();
+
+        match manager.enable("pick", Pointcut::parse("method(pick)").unwrap(), marker(1), &emu) {
+            Err(RedefineError::MissingCapability) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,9 +1,161 @@
 extern crate jvmti;
 
+mod allocation;
+mod bridge;
+mod capture;
+mod chain;
+mod counters;
+mod coverage;
+mod dynamic;
+mod pointcut;
+mod replacement;
+mod timing;
+mod trace;
+mod transaction;
+
+use jvmti::bytecode::{ ClassReader, ClassWriter };
+use jvmti::config::Config;
+use jvmti::instrumentation::{ Insn, JavaClass, MemberRef };
+use jvmti::instrumentation::code::{ Code, Invoke, Label };
+use jvmti::instrumentation::timing::ProbeError;
+use std::io::Cursor;
+
+/// Writes the class and reads it back, panicking if it can't be serialised or parsed
+pub fn round_trip(class: &JavaClass) -> JavaClass {
+    let mut data = vec![];
+    ClassWriter::new(&mut data).write_class(&class.to_classfile().unwrap()).unwrap();
+    JavaClass::from_classfile(&ClassReader::read_class(&mut Cursor::new(data)).unwrap()).unwrap()
+}
+
+/// A call of a static method of a probe helper class
+pub fn helper_call(helper: &str, name: &str, descriptor: &str) -> Insn {
+    Insn::Invoke(Invoke::Static, MemberRef::new(helper, name, descriptor))
+}
+
+/// Whether an exception thrown by the instruction at the index reaches one of the handlers
+pub fn is_protected(code: &Code, index: usize) -> bool {
+    let position = |label: Label| code.instructions.iter().position(|insn| *insn == Insn::Label(label)).unwrap();
+
+    code.try_catch_blocks.iter().any(|block| position(block.start) < index && index < position(block.end))
+}
+
+/// Parses a configuration with the given settings following the required ones
+pub fn config(settings: &str) -> Config {
+    format!("agent_name = \"test\"\nentry_points = []\nactive_classes = []\n{}", settings).parse().unwrap()
+}
+
+/// Checks that the configuration was rejected for referring to the given unknown pointcut
+pub fn assert_unknown_pointcut<T>(result: Result<T, ProbeError>, name: &str) {
+    match result {
+        Err(ProbeError::UnknownPointcut(ref unknown)) if unknown == name => (),
+        _ => panic!("the unknown pointcut {} wasn't reported", name),
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use jvmti::instrumentation::JavaClass;
+    use jvmti::bytecode::{ AccessFlags, Attribute, ClassReader, ClassWriter, Classfile, FieldAccessFlags, Instruction,
+                           MethodAccessFlags };
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::asm::{ ClassBuilder, ClassfileVersion };
+    use jvmti::instrumentation::code::*;
+    use jvmti::instrumentation::constants::{ decode_utf8, encode_utf8 };
+    use std::io::Cursor;
+
+    fn roundtrip(classfile: &Classfile) -> Classfile {
+        let mut target: Vec<u8> = vec![];
+        {
+            let mut writer = ClassWriter::new(&mut target);
+            assert!(writer.write_class(classfile).is_ok());
+        }
+
+        ClassReader::read_class(&mut Cursor::new(&mut target)).unwrap()
+    }
+
+    /// static long run(int x) {
+    ///     try {
+    ///         if (x == 0) return 123456789L;
+    ///         switch (x) {
+    ///             case 1: return "hello".length();
+    ///             case 2: return 1L;
+    ///             default: x = 1 / x;
+    ///         }
+    ///         return 0L;
+    ///     } catch (ArithmeticException e) {
+    ///         return -1L;
+    ///     }
+    /// }
+    fn sample_class() -> JavaClass {
+        let mut class = JavaClass::new();
+        class.name = "com/acme/Sample".to_string();
+        class.source_file = Some("Sample.java".to_string());
+        class.add_interface("java/lang/Runnable");
+
+        let mut code = Code::new();
+        let start = code.new_label();
+        let not_zero = code.new_label();
+        let case_one = code.new_label();
+        let case_two = code.new_label();
+        let default = code.new_label();
+        let end = code.new_label();
+
+        let locals = vec![ FrameItem::Integer ];
+        let frame = Frame::new(locals.clone(), vec![]);
+
+        code.max_stack = 4;
+        code.max_locals = 1;
+        code.instructions = vec![
+            Insn::Label(start),
+            Insn::LineNumber(10),
+            Insn::Op(Instruction::ILOAD_0),
+            Insn::Jump(Jump::IfNe, not_zero),
+            Insn::Ldc(Value::Long(123456789)),
+            Insn::Op(Instruction::LRETURN),
+            Insn::Label(not_zero),
+            Insn::LineNumber(11),
+            Insn::Frame(frame.clone()),
+            Insn::Op(Instruction::ILOAD_0),
+            Insn::TableSwitch { default, low: 1, high: 2, targets: vec![ case_one, case_two ] },
+            Insn::Label(case_one),
+            Insn::Frame(frame.clone()),
+            Insn::Ldc(Value::String("hello".to_string())),
+            Insn::Invoke(Invoke::Virtual, MemberRef::new("java/lang/String", "length", "()I")),
+            Insn::Op(Instruction::I2L),
+            Insn::Op(Instruction::LRETURN),
+            Insn::Label(case_two),
+            Insn::Frame(frame.clone()),
+            Insn::Op(Instruction::LCONST_1),
+            Insn::Op(Instruction::LRETURN),
+            Insn::Label(default),
+            Insn::Frame(frame.clone()),
+            Insn::Op(Instruction::ICONST_1),
+            Insn::Op(Instruction::ILOAD_0),
+            Insn::Op(Instruction::IDIV),
+            Insn::Op(Instruction::ISTORE_0),
+            Insn::Op(Instruction::LCONST_0),
+            Insn::Op(Instruction::LRETURN),
+            Insn::Label(end),
+            Insn::Frame(Frame::new(locals, vec![ FrameItem::Object("java/lang/ArithmeticException".to_string()) ])),
+            Insn::Op(Instruction::POP),
+            Insn::Ldc(Value::Long(-1)),
+            Insn::Op(Instruction::LRETURN),
+        ];
+        code.try_catch_blocks = vec![
+            TryCatchBlock { start, end, handler: end, catch_type: Some("java/lang/ArithmeticException".to_string()) }
+        ];
+
+        let mut method = Method::new("run".to_string(), MethodDescriptor::parse("(I)J").unwrap());
+        method.access_flags = AccessFlags::of(MethodAccessFlags::Public as u16 | MethodAccessFlags::Static as u16);
+        method.code = Some(code);
+        class.add_method(method);
+
+        let mut field = Field::new("LIMIT".to_string(), JavaType::Long);
+        field.access_flags = AccessFlags::of(FieldAccessFlags::Static as u16 | FieldAccessFlags::Final as u16);
+        field.constant_value = Some(Value::Long(42));
+        class.add_field(field);
+
+        class
+    }
 
     #[test]
     fn can_create_empty_class() {
@@ -13,4 +165,251 @@ mod tests {
 
         assert_eq!(classfile, classfile);
     }
+
+    #[test]
+    fn empty_class_has_names_in_constant_pool() {
+        let mut class = JavaClass::new();
+        class.name = "com/acme/Empty".to_string();
+
+        let classfile = class.to_classfile().unwrap();
+        let read = JavaClass::from_classfile(&roundtrip(&classfile)).unwrap();
+
+        assert_eq!("com/acme/Empty", read.name);
+        assert_eq!(Some("java/lang/Object".to_string()), read.super_name);
+    }
+
+    #[test]
+    fn model_survives_roundtrip() {
+        let class = sample_class();
+
+        let classfile = class.to_classfile().unwrap();
+        let written = roundtrip(&classfile);
+        assert_eq!(classfile, written);
+
+        let read = JavaClass::from_classfile(&written).unwrap();
+
+        assert_eq!(class.name, read.name);
+        assert_eq!(class.interfaces, read.interfaces);
+        assert_eq!(class.source_file, read.source_file);
+        assert_eq!(class.fields, read.fields);
+        assert_eq!(class.methods, read.methods);
+
+        // Converting a class that has been read reuses its constant pool
+        assert_eq!(written, read.to_classfile().unwrap());
+    }
+
+    #[test]
+    fn edits_extend_the_constant_pool() {
+        let read = JavaClass::from_classfile(&sample_class().to_classfile().unwrap()).unwrap();
+        let original = read.to_classfile().unwrap();
+
+        let mut edited = read.clone();
+        edited.method_mut("run", "(I)J").unwrap().code.as_mut().unwrap().instructions.insert(2,
+            Insn::Ldc(Value::String("probe".to_string())));
+        edited.method_mut("run", "(I)J").unwrap().code.as_mut().unwrap().instructions.insert(3,
+            Insn::Op(Instruction::POP));
+
+        let classfile = edited.to_classfile().unwrap();
+
+        assert!(classfile.constant_pool.cp_len() > original.constant_pool.cp_len());
+        assert_eq!(original.constant_pool.constants[..], classfile.constant_pool.constants[..original.constant_pool.constants.len()]);
+
+        let reread = JavaClass::from_classfile(&roundtrip(&classfile)).unwrap();
+        let code = reread.method("run", "(I)J").unwrap().code.as_ref().unwrap();
+
+        assert_eq!(Insn::Ldc(Value::String("probe".to_string())), code.instructions[2]);
+    }
+
+    #[test]
+    fn far_gotos_are_widened() {
+        let mut code = Code::new();
+        let target = code.new_label();
+
+        code.max_stack = 1;
+        code.instructions.push(Insn::Jump(Jump::Goto, target));
+        for _ in 0..40000 {
+            code.instructions.push(Insn::Op(Instruction::NOP));
+        }
+        code.instructions.push(Insn::Label(target));
+        code.instructions.push(Insn::Frame(Frame::default()));
+        code.instructions.push(Insn::Op(Instruction::RETURN));
+
+        let mut method = Method::new("far".to_string(), MethodDescriptor::parse("()V").unwrap());
+        method.access_flags = AccessFlags::of(MethodAccessFlags::Static as u16);
+        method.code = Some(code.clone());
+
+        let mut class = JavaClass::new();
+        class.name = "Far".to_string();
+        class.add_method(method);
+
+        let classfile = class.to_classfile().unwrap();
+
+        match classfile.methods[0].attributes[0] {
+            Attribute::Code { code: ref instructions, .. } => assert_eq!(Instruction::GOTO_W(40005), instructions[0]),
+            ref other => panic!("unexpected attribute {:?}", other),
+        }
+
+        code.instructions[0] = Insn::Jump(Jump::IfNull, target);
+        class.methods[0].code = Some(code);
+
+        assert_eq!(Err(ModelError::BranchOutOfRange(target)), class.to_classfile());
+    }
+
+    #[test]
+    fn unplaced_labels_are_reported() {
+        let mut code = Code::new();
+        let label = code.new_label();
+        code.instructions.push(Insn::Jump(Jump::Goto, label));
+
+        let mut method = Method::new("broken".to_string(), MethodDescriptor::parse("()V").unwrap());
+        method.code = Some(code);
+
+        let mut class = JavaClass::new();
+        class.add_method(method);
+
+        assert_eq!(Err(ModelError::UnplacedLabel(label)), class.to_classfile());
+    }
+
+    #[test]
+    fn descriptors_are_parsed() {
+        let descriptor = MethodDescriptor::parse("(IJ[Ljava/lang/String;[[D)V").unwrap();
+
+        assert_eq!(vec![ JavaType::Int, JavaType::Long,
+                         JavaType::Array(Box::new(JavaType::Class("java/lang/String".to_string()))),
+                         JavaType::Array(Box::new(JavaType::Array(Box::new(JavaType::Double)))) ], descriptor.parameters);
+        assert_eq!(JavaType::Void, descriptor.return_type);
+        assert_eq!(5, descriptor.parameter_size());
+        assert_eq!("(IJ[Ljava/lang/String;[[D)V", descriptor.descriptor());
+
+        assert_eq!(None, MethodDescriptor::parse("(V)V"));
+        assert_eq!(None, MethodDescriptor::parse("(I"));
+        assert_eq!(None, JavaType::parse("Ljava/lang/String"));
+        assert_eq!("java.lang.String[]", format!("{}", JavaType::parse("[Ljava/lang/String;").unwrap()));
+    }
+
+    #[test]
+    fn modified_utf8_roundtrip() {
+        let text = "a\u{0}b\u{e9}\u{1F600}";
+        let encoded = encode_utf8(text);
+
+        // NUL is encoded in two bytes, supplementary characters as surrogate pairs
+        assert_eq!(vec![ 0x61, 0xC0, 0x80, 0x62, 0xC3, 0xA9, 0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80 ], encoded);
+        assert_eq!(Ok(text.to_string()), decode_utf8(&encoded));
+        assert!(decode_utf8(&[ 0xC3 ]).is_err());
+    }
+
+    #[test]
+    fn builder_creates_probe_class() {
+        let class = ClassBuilder::new("com/acme/Probe")
+            .public()
+            .default_constructor()
+            .method("enter", "(J)V", |m| m.public().static_()
+                .lload(0)
+                .invokestatic("com/acme/Agent", "enter", "(J)V")
+                .ret())
+            .build()
+            .unwrap();
+
+        let enter = class.method("enter", "(J)V").unwrap();
+        let code = enter.code.as_ref().unwrap();
+        assert_eq!(2, code.max_stack);
+        assert_eq!(2, code.max_locals);
+        assert_eq!(Insn::Op(Instruction::LLOAD_0), code.instructions[0]);
+        assert_eq!(Insn::Op(Instruction::RETURN), code.instructions[2]);
+
+        let classfile = roundtrip(&class.to_classfile().unwrap());
+        assert_eq!(52, classfile.version.major_version);
+
+        let read = JavaClass::from_classfile(&classfile).unwrap();
+        assert_eq!("com/acme/Probe", read.name);
+        assert!(read.method("<init>", "()V").is_some());
+        assert_eq!(enter.code, read.method("enter", "(J)V").unwrap().code);
+    }
+
+    #[test]
+    fn builder_computes_frames() {
+        // static int sum(int n) { int s = 0; for (int i = 0; i < n; i++) s += i; return s; }
+        let class = ClassBuilder::new("com/acme/Loop")
+            .version(ClassfileVersion::Java21)
+            .method("sum", "(I)I", |mut m| {
+                let check = m.new_label();
+                let end = m.new_label();
+
+                m.static_()
+                    .iconst(0).istore(1)
+                    .iconst(0).istore(2)
+                    .mark(check)
+                    .iload(2).iload(0).if_icmpge(end)
+                    .iload(1).iload(2).iadd().istore(1)
+                    .iinc(2, 1)
+                    .goto(check)
+                    .mark(end)
+                    .iload(1)
+                    .ret()
+            })
+            .build()
+            .unwrap();
+
+        let code = class.methods[0].code.as_ref().unwrap();
+        let frames: Vec<&Insn> = code.instructions.iter().filter(|i| matches!(**i, Insn::Frame(_))).collect();
+
+        assert_eq!(2, frames.len());
+        assert_eq!(&Insn::Frame(Frame::new(vec![ FrameItem::Integer, FrameItem::Integer, FrameItem::Integer ], vec![])),
+                   frames[0]);
+        assert_eq!((2, 3), (code.max_stack, code.max_locals));
+        assert_eq!(65, class.to_classfile().unwrap().version.major_version);
+    }
+
+    #[test]
+    fn builder_reports_errors() {
+        let result = ClassBuilder::new("com/acme/Broken")
+            .method("run", "(V)V", |m| m.ret())
+            .build();
+        assert_eq!(Err(ModelError::InvalidDescriptor("(V)V".to_string())), result.map(|_| ()));
+
+        let result = ClassBuilder::new("com/acme/Broken")
+            .method("run", "()V", |m| m.op(Instruction::GOTO(3)))
+            .build();
+        assert_eq!(Err(ModelError::InvalidInstruction(Instruction::GOTO(3))), result.map(|_| ()));
+
+        let result = ClassBuilder::new("com/acme/Broken")
+            .method("run", "()I", |m| m.iconst(1))
+            .build();
+        assert_eq!(Err(ModelError::FallOffEnd), result.map(|_| ()));
+    }
+
+    #[test]
+    fn builder_picks_compact_instructions() {
+        let mut method = asm::MethodBuilder::new("run", MethodDescriptor::parse("()V").unwrap())
+            .iconst(-1).iconst(100).iconst(1000).iconst(100000)
+            .aload(3).aload(300)
+            .iinc(1, 1).iinc(1, 1000)
+            .build()
+            .unwrap();
+
+        assert_eq!(vec![ Insn::Op(Instruction::ICONST_M1),
+                         Insn::Op(Instruction::BIPUSH(100)),
+                         Insn::Op(Instruction::SIPUSH(1000)),
+                         Insn::Ldc(Value::Int(100000)),
+                         Insn::Op(Instruction::ALOAD_3),
+                         Insn::Op(Instruction::ALOAD_W(300)),
+                         Insn::Op(Instruction::IINC(1, 1)),
+                         Insn::Op(Instruction::IINC_W(1, 1000)) ],
+                   method.code.take().unwrap().instructions);
+    }
+
+    #[test]
+    fn constant_pool_deduplicates_utf8() {
+        let mut class = asm::Class::new();
+        class.set_version(ClassfileVersion::Java11);
+
+        let first = class.constant_pool().add_utf8_constant("hello".to_string());
+        let second = class.constant_pool().add_utf8_constant("hello".to_string());
+        let string = class.constant_pool().add_string_constant("hello".to_string());
+
+        assert_eq!(first, second);
+        assert!(string != first);
+        assert_eq!(3, class.constant_pool().len());
+        assert_eq!(55, class.to_classfile().version.major_version);
+    }
 }
@@ -0,0 +1,198 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ Annotation, Attribute, Constant, ConstantPoolIndex };
+    use jvmti::config::Config;
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::asm::ClassBuilder;
+    use jvmti::instrumentation::pattern::*;
+    use jvmti::instrumentation::pointcut::*;
+    use super::super::assert_unknown_pointcut;
+
+    fn glob(source: &str) -> Pattern {
+        Pattern::parse(source, Some('.')).unwrap()
+    }
+
+    fn regex(source: &str) -> Pattern {
+        Pattern::parse(&format!("/{}/", source), None).unwrap()
+    }
+
+    /// @Service public class com.acme.OrderService extends com.acme.Base implements java.lang.Runnable
+    /// with public void run() and @Timed private static long total(int)
+    fn service_class() -> JavaClass {
+        let mut classfile = ClassBuilder::new("com/acme/OrderService")
+            .public()
+            .extends("com/acme/Base")
+            .implements("java/lang/Runnable")
+            .method("run", "()V", |m| m.public().ret())
+            .method("total", "(I)J", |m| m.private().static_().lconst(0).ret())
+            .to_classfile()
+            .unwrap();
+
+        let mut annotate = |descriptor: &str| {
+            classfile.constant_pool.constants.push(Constant::Utf8(descriptor.as_bytes().to_vec()));
+            let index = ConstantPoolIndex::new(classfile.constant_pool.constants.len() - 1);
+
+            Attribute::RuntimeVisibleAnnotations(vec![ Annotation { type_index: index, element_value_pairs: vec![] } ])
+        };
+
+        let class_annotation = annotate("Lcom/acme/Service;");
+        let method_annotation = annotate("Lcom/acme/Timed;");
+
+        classfile.attributes.push(class_annotation);
+        classfile.methods[1].attributes.push(method_annotation);
+
+        JavaClass::from_classfile(&classfile).unwrap()
+    }
+
+    fn selects(expression: &str, class: &JavaClass, method: &str) -> bool {
+        let pointcut = Pointcut::parse(expression).unwrap();
+        let method = class.methods.iter().find(|m| m.name == method).unwrap();
+
+        pointcut.matches_method(&ClassInfo::from_class(class), &MethodInfo::from_method(class, method))
+    }
+
+    #[test]
+    fn globs_respect_package_boundaries() {
+        assert!(glob("com.acme.*").matches("com.acme.Service"));
+        assert!(!glob("com.acme.*").matches("com.acme.impl.Service"));
+        assert!(glob("com.acme.**").matches("com.acme.impl.Service"));
+        assert!(glob("com.**.*Service").matches("com.acme.impl.OrderService"));
+        assert!(glob("Order?ervice").matches("OrderService"));
+        assert!(!glob("Order?ervice").matches("Order.ervice"));
+        assert!(glob("a\\*b").matches("a*b"));
+        assert!(!glob("a\\*b").matches("axb"));
+        assert!(glob("").matches(""));
+        assert!(Pattern::parse("(*)V", None).unwrap().matches("(Ljava/lang/String;I)V"));
+    }
+
+    #[test]
+    fn regexes_match_whole_names() {
+        assert!(regex("com\\.acme\\.(Order|User)Service").matches("com.acme.UserService"));
+        assert!(!regex("com\\.acme\\.(Order|User)Service").matches("com.acme.UserServiceImpl"));
+        assert!(regex(".*Service.*").matches("com.acme.UserServiceImpl"));
+        assert!(regex("[a-z]+\\d{2,3}").matches("abc123"));
+        assert!(!regex("[a-z]+\\d{2,3}").matches("abc1234"));
+        assert!(regex("[^.]+").matches("Service$1"));
+        assert!(!regex("[^.]+").matches("acme.Service"));
+        assert!(regex("^get[A-Z]\\w*$").matches("getName"));
+        assert!(regex("(?:ab)*c?").matches("abab"));
+        assert!(regex("a{2,}").matches("aaaa"));
+        assert!(!regex("a{2,}").matches("a"));
+        assert!(regex("(a|)+b").matches("aab"));
+        assert!(regex("(a*)*b").matches(&format!("{}b", "a".repeat(100))));
+    }
+
+    #[test]
+    fn invalid_regexes_are_reported() {
+        assert_eq!(Err(PatternError::UnbalancedParenthesis(0)), Regex::parse("(ab"));
+        assert_eq!(Err(PatternError::UnbalancedParenthesis(2)), Regex::parse("ab)"));
+        assert_eq!(Err(PatternError::UnbalancedBracket(1)), Regex::parse("a[bc"));
+        assert_eq!(Err(PatternError::MissingOperand(0)), Regex::parse("*a"));
+        assert_eq!(Err(PatternError::InvalidRepetition(1)), Regex::parse("a{3,1}"));
+        assert_eq!(Err(PatternError::InvalidEscape(0)), Regex::parse("\\q"));
+        assert_eq!(Err(PatternError::InvalidRange(1)), Regex::parse("[z-a]"));
+        assert_eq!(Err(PatternError::TrailingBackslash), Regex::parse("a\\"));
+    }
+
+    #[test]
+    fn pointcuts_are_parsed() {
+        let pointcut = Pointcut::parse("class(com.acme.*) && !method(<init>) || access(public static)").unwrap();
+
+        assert_eq!(Pointcut::Class(glob("com.acme.*"))
+                       .and(Pointcut::Not(Box::new(Pointcut::Method(Pattern::parse("<init>", None).unwrap()))))
+                       .or(Pointcut::Access(0x0009)),
+                   pointcut);
+        assert_eq!("class(com.acme.*) && !method(<init>) || access(public static)", pointcut.to_string());
+
+        let nested = Pointcut::parse(" ( class(/a(b|c)/) || package(x) ) && descriptor((J)V) ").unwrap();
+        assert_eq!("(class(/a(b|c)/) || package(x)) && descriptor((J)V)", nested.to_string());
+
+        assert_eq!(Err(PointcutError::UnknownPredicate("klass".to_string())), Pointcut::parse("klass(a)"));
+        assert_eq!(Err(PointcutError::UnknownModifier("open".to_string())), Pointcut::parse("access(public open)"));
+        assert_eq!(Err(PointcutError::UnexpectedEnd), Pointcut::parse("class(a) &&"));
+        assert_eq!(Err(PointcutError::UnexpectedCharacter(9, 'x')), Pointcut::parse("class(a) x"));
+        assert_eq!(Err(PointcutError::InvalidExecution("run".to_string())), Pointcut::parse("execution(run)"));
+        assert!(Pointcut::parse("class(/a(/)").is_err());
+    }
+
+    #[test]
+    fn pointcuts_select_methods_by_declaration() {
+        let class = service_class();
+
+        assert!(selects("execution(com.acme.*Service.run)", &class, "run"));
+        assert!(selects("execution(com.acme.**.total(I)J) && access(private static)", &class, "total"));
+        assert!(!selects("execution(com.acme.**.total(J)*)", &class, "total"));
+        assert!(selects("extends(com.acme.Base) && implements(java.lang.Runnable)", &class, "run"));
+        assert!(selects("annotated(com.acme.Service) && class_access(public)", &class, "run"));
+        assert!(!selects("class_access(public final)", &class, "run"));
+        assert!(selects("method_annotated(/.*Timed/)", &class, "total"));
+        assert!(!selects("method_annotated(/.*Timed/)", &class, "run"));
+        assert!(selects("package(com.acme) && !access(static)", &class, "run"));
+    }
+
+    #[test]
+    fn unknown_declarations_are_undecided() {
+        let pointcut = Pointcut::parse("class(com.acme.*) && !annotated(com.acme.Internal) && method(run)").unwrap();
+        let class = ClassInfo::new("com/acme/OrderService");
+        let method = MethodInfo::new("run", "()V");
+
+        assert_eq!("com.acme", class.package());
+        // The class may contain matching methods, but without its annotations nothing is selected
+        assert!(pointcut.matches_class(&class));
+        assert!(!pointcut.matches_method(&class, &method));
+        assert!(!pointcut.matches_class(&ClassInfo::new("org/other/Service")));
+
+        let by_name = Pointcut::parse("execution(com.acme.OrderService.run)").unwrap();
+        assert!(by_name.matches_method(&class, &method));
+    }
+
+    #[test]
+    fn pointcuts_are_read_from_config() {
+        let config: Config = r#"
+            agent_name = "test"
+            entry_points = []
+            active_classes = []
+
+            [pointcuts]
+            services = "execution(com.acme.*Service.*) && access(public)"
+        "#.parse().unwrap();
+
+        assert_eq!(Some(&Pointcut::parse("execution(com.acme.*Service.*) && access(public)").unwrap()),
+                   config.pointcut("services"));
+        assert!(config.pointcut("missing").is_none());
+
+        let invalid = r#"
+            agent_name = "test"
+            entry_points = []
+            active_classes = []
+
+            [pointcuts]
+            broken = "class(com.acme.*"
+        "#.parse::<Config>();
+        assert!(invalid.is_err());
+
+        let without = "agent_name = \"test\"\nentry_points = []\nactive_classes = []".parse::<Config>().unwrap();
+        assert!(without.pointcuts.is_empty());
+    }
+
+    #[test]
+    fn entry_points_name_pointcuts() {
+        let config: Config = r#"
+            agent_name = "test"
+            entry_points = [ "hello", "missing" ]
+            active_classes = []
+
+            [pointcuts]
+            hello = "execution(Hello.main)"
+            other = "execution(Other.main)"
+        "#.parse().unwrap();
+
+        assert_unknown_pointcut(config.entry_points(), "missing");
+
+        let mut defined = config;
+        defined.entry_points.pop();
+        assert_eq!(vec![ &Pointcut::parse("execution(Hello.main)").unwrap() ], defined.entry_points().unwrap());
+    }
+}
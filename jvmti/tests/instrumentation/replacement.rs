@@ -0,0 +1,250 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::Instruction;
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::asm::{ ClassBuilder, MethodBuilder };
+    use jvmti::instrumentation::code::*;
+    use jvmti::instrumentation::replacement::*;
+    use jvmti::instrumentation::timing::ProbeError;
+    use super::super::{ assert_unknown_pointcut, config, round_trip };
+
+    /// class PaymentClient { int fee; boolean charge(int amount) { return amount > fee; } String name() {...} List items() {...} }
+    fn payment_client() -> JavaClass {
+        ClassBuilder::new("com/acme/PaymentClient")
+            .public()
+            .field("fee", "I", |f| f.private())
+            .default_constructor()
+            .method("charge", "(I)Z", |mut m| {
+                let high = m.new_label();
+                m.iload(1).aload(0).getfield("com/acme/PaymentClient", "fee", "I").if_icmpgt(high).iconst(0).ret()
+                 .mark(high).iconst(1).ret()
+            })
+            .method("name", "()Ljava/lang/String;", |m| m.ldc_string("client").ret())
+            .method("items", "()Ljava/util/List;", |m| m.aconst_null().ret())
+            .method("total", "()J", |m| m.lconst(7).ret())
+            .build()
+            .unwrap()
+    }
+
+    /// class Faults { int fee; boolean charge(int amount) { if (amount > 100) throw new IllegalStateException(); return fee == 0; } }
+    fn faults() -> JavaClass {
+        ClassBuilder::new("com/acme/Faults")
+            .public()
+            .field("fee", "I", |f| f.private())
+            .default_constructor()
+            .method("charge", "(I)Z", |mut m| {
+                let small = m.new_label();
+                m.iload(1).iconst(100).if_icmple(small)
+                 .new_("java/lang/IllegalStateException").dup()
+                 .invokespecial("java/lang/IllegalStateException", "<init>", "()V").athrow()
+                 .mark(small).aload(0).getfield("com/acme/Faults", "fee", "I").ret()
+            })
+            .method("charge", "(J)Z", |m| m.iconst(1).ret())
+            .method("helper", "()V", |m| m.ret())
+            .build()
+            .unwrap()
+    }
+
+    fn index(class: &JavaClass, name: &str) -> usize {
+        class.methods.iter().position(|m| m.name == name).unwrap()
+    }
+
+    fn instructions(class: &JavaClass, name: &str) -> Vec<Insn> {
+        class.methods[index(class, name)].code.as_ref().unwrap().instructions.iter()
+            .filter(|insn| insn.is_instruction())
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn donor_bodies_refer_to_the_target_class() {
+        let mut client = payment_client();
+        let donor = faults();
+        let charge = index(&client, "charge");
+
+        replace_body(&mut client, charge, &donor, donor.method("charge", "(I)Z").unwrap()).unwrap();
+
+        let code = client.methods[charge].code.as_ref().unwrap();
+        assert!(code.instructions.contains(&Insn::Field(FieldOp::GetField, MemberRef::new("com/acme/PaymentClient", "fee", "I"))));
+        assert!(code.instructions.contains(&Insn::Type(TypeOp::New, "java/lang/IllegalStateException".to_string())));
+        assert!(code.instructions.iter().any(|insn| match *insn {
+            Insn::Frame(ref frame) => frame.locals == vec![ FrameItem::Object("com/acme/PaymentClient".to_string()), FrameItem::Integer ],
+            _ => false,
+        }));
+        assert_eq!(2, code.max_stack);
+
+        round_trip(&client);
+    }
+
+    #[test]
+    fn incompatible_donors_are_rejected() {
+        let mut client = payment_client();
+        let donor = faults();
+        let charge = index(&client, "charge");
+
+        // The donor uses a method the target doesn't declare
+        let mut calling = donor.clone();
+        calling.method_mut("charge", "(J)Z").unwrap().code.as_mut().unwrap().instructions.insert(
+            0, Insn::Invoke(Invoke::Virtual, MemberRef::new("com/acme/Faults", "helper", "()V")));
+        calling.method_mut("charge", "(J)Z").unwrap().code.as_mut().unwrap().instructions.insert(0, Insn::Op(Instruction::ALOAD_0));
+
+        let mut total = client.clone();
+        let mut method = calling.method("charge", "(J)Z").unwrap().clone();
+        method.descriptor = MethodDescriptor::parse("()J").unwrap();
+        method.code.as_mut().unwrap().instructions = vec![ Insn::Op(Instruction::ALOAD_0),
+                                                           Insn::Invoke(Invoke::Virtual, MemberRef::new("com/acme/Faults", "helper", "()V")),
+                                                           Insn::Op(Instruction::LCONST_0),
+                                                           Insn::Op(Instruction::LRETURN) ];
+        let total_index = index(&total, "total");
+
+        match replace_body(&mut total, total_index, &calling, &method) {
+            Err(ReplaceError::MissingMember(ref member)) if member.owner == "com/acme/PaymentClient" && member.name == "helper" => (),
+            other => panic!("the missing member wasn't reported: {:?}", other),
+        }
+
+        // Inherited members are left to the verifier
+        method.code.as_mut().unwrap().instructions[1] = Insn::Invoke(Invoke::Virtual, MemberRef::new("com/acme/Faults", "hashCode", "()I"));
+        method.code.as_mut().unwrap().instructions.insert(2, Insn::Op(Instruction::POP));
+        assert_eq!(Ok(()), replace_body(&mut total, total_index, &calling, &method));
+
+        // The descriptors must match
+        assert_eq!(Err(ReplaceError::Incompatible("charge".to_string(), "(I)Z".to_string())),
+                   replace_body(&mut client, charge, &donor, donor.method("charge", "(J)Z").unwrap()));
+
+        let transformer = MethodReplacer::new()
+            .donor(Pointcut::parse("method(name)").unwrap(), donor.clone(), "charge");
+
+        match transformer.transform(&mut client) {
+            Err(TransformError::Failed(ref message)) => assert_eq!("The donor method isn't compatible with charge()Ljava/lang/String;", message),
+            other => panic!("the incompatible donor wasn't reported: {:?}", other),
+        }
+
+        let transformer = MethodReplacer::new()
+            .donor(Pointcut::parse("method(charge)").unwrap(), donor, "debit");
+
+        match transformer.transform(&mut client) {
+            Err(TransformError::Failed(ref message)) => assert_eq!("The donor class has no method debit", message),
+            other => panic!("the missing donor method wasn't reported: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stubs_return_constants() {
+        let mut client = payment_client();
+
+        for &(name, value) in [ ("charge", "true"), ("name", "stubbed"), ("items", "null"), ("total", "123456789012") ].iter() {
+            let method = index(&client, name);
+            apply_stub(&mut client, method, &Stub::Return(value.to_string())).unwrap();
+        }
+
+        assert_eq!(vec![ Insn::Op(Instruction::ICONST_1), Insn::Op(Instruction::IRETURN) ], instructions(&client, "charge"));
+        assert_eq!(vec![ Insn::Ldc(Value::String("stubbed".to_string())), Insn::Op(Instruction::ARETURN) ], instructions(&client, "name"));
+        assert_eq!(vec![ Insn::Op(Instruction::ACONST_NULL), Insn::Op(Instruction::ARETURN) ], instructions(&client, "items"));
+        assert_eq!(vec![ Insn::Ldc(Value::Long(123456789012)), Insn::Op(Instruction::LRETURN) ], instructions(&client, "total"));
+        assert!(client.methods[index(&client, "charge")].code.as_ref().unwrap().try_catch_blocks.is_empty());
+
+        let items = index(&client, "items");
+        assert_eq!(Err(ReplaceError::InvalidConstant("[]".to_string(), "Ljava/util/List;".to_string())),
+                   apply_stub(&mut client, items, &Stub::Return("[]".to_string())));
+        let total = index(&client, "total");
+        assert!(apply_stub(&mut client, total, &Stub::Return("many".to_string())).is_err());
+
+        round_trip(&client);
+    }
+
+    #[test]
+    fn stubs_throw_and_delay() {
+        let mut client = payment_client();
+        let charge = index(&client, "charge");
+        let name = index(&client, "name");
+
+        apply_stub(&mut client, charge, &Stub::Throw { exception: "java/io/UncheckedIOException".to_string(), message: None }).unwrap();
+        apply_stub(&mut client, name, &Stub::Throw { exception: "java/lang/IllegalStateException".to_string(),
+                                                    message: Some("injected".to_string()) }).unwrap();
+
+        assert_eq!(vec![ Insn::Type(TypeOp::New, "java/io/UncheckedIOException".to_string()),
+                         Insn::Op(Instruction::DUP),
+                         Insn::Invoke(Invoke::Special, MemberRef::new("java/io/UncheckedIOException", "<init>", "()V")),
+                         Insn::Op(Instruction::ATHROW) ],
+                   instructions(&client, "charge"));
+        assert_eq!(Insn::Ldc(Value::String("injected".to_string())), instructions(&client, "name")[2]);
+
+        let total = index(&client, "total");
+        apply_stub(&mut client, total, &Stub::Delay(250)).unwrap();
+
+        assert_eq!(vec![ Insn::Ldc(Value::Long(250_000_000)),
+                         Insn::Invoke(Invoke::Static, MemberRef::new("java/util/concurrent/locks/LockSupport", "parkNanos", "(J)V")),
+                         Insn::Ldc(Value::Long(7)),
+                         Insn::Op(Instruction::LRETURN) ],
+                   instructions(&client, "total"));
+
+        // Constructors keep their bodies
+        let constructor = index(&client, "<init>");
+        assert_eq!(Err(ReplaceError::Unsupported("<init>".to_string())), apply_stub(&mut client, constructor, &Stub::Delay(1)));
+
+        round_trip(&client);
+    }
+
+    #[test]
+    fn assembled_methods_get_frames() {
+        let mut client = payment_client();
+
+        let mut builder = MethodBuilder::new("charge", MethodDescriptor::parse("(I)Z").unwrap());
+        let negative = builder.new_label();
+        let method = builder.iload(1).ifge(negative).iconst(0).ret().mark(negative).iconst(1).ret().build().unwrap();
+
+        let transformer = MethodReplacer::new().method(Pointcut::parse("method(charge)").unwrap(), method);
+        assert!(transformer.accepts("com/acme/PaymentClient"));
+        assert!(transformer.transform(&mut client).unwrap());
+
+        let code = client.methods[index(&client, "charge")].code.as_ref().unwrap();
+        assert_eq!(1, code.instructions.iter().filter(|insn| matches!(*insn, Insn::Frame(_))).count());
+        assert!(!code.instructions.contains(&Insn::Field(FieldOp::GetField, MemberRef::new("com/acme/PaymentClient", "fee", "I"))));
+
+        round_trip(&client);
+    }
+
+    #[test]
+    fn stubs_are_read_from_config() {
+        let config = config(r#"
+            [pointcuts]
+            payments = "execution(com.acme.PaymentClient.charge)"
+            names = "execution(com.acme.PaymentClient.name)"
+
+            [[stubs]]
+            pointcut = "payments"
+            action = "throw"
+            exception = "java.lang.IllegalStateException"
+            message = "injected failure"
+
+            [[stubs]]
+            pointcut = "names"
+            action = "delay"
+            millis = 20
+        "#);
+
+        assert_eq!(Stub::Throw { exception: "java/lang/IllegalStateException".to_string(), message: Some("injected failure".to_string()) },
+                   Stub::from_config(&config.stubs[0]).unwrap());
+
+        let replacer = MethodReplacer::from_config(&config).unwrap();
+        assert_eq!(2, replacer.len());
+
+        let mut client = payment_client();
+        assert!(replacer.transform(&mut client).unwrap());
+        assert_eq!(Insn::Op(Instruction::ATHROW), *instructions(&client, "charge").last().unwrap());
+        assert_eq!(Insn::Ldc(Value::Long(20_000_000)), instructions(&client, "name")[0]);
+
+        let mut invalid = config;
+        invalid.stubs[1].millis = None;
+
+        match MethodReplacer::from_config(&invalid) {
+            Err(ProbeError::InvalidStub(ref name, ref reason)) if name == "names" && reason == "missing millis" => (),
+            _ => panic!("the invalid stub wasn't reported"),
+        }
+
+        invalid.stubs[1].pointcut = "missing".to_string();
+        assert_unknown_pointcut(MethodReplacer::from_config(&invalid), "missing");
+    }
+}
@@ -0,0 +1,184 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::Instruction;
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::asm::{ ClassBuilder, ClassfileVersion };
+    use jvmti::instrumentation::code::*;
+    use jvmti::instrumentation::timing::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use super::super::{ assert_unknown_pointcut, config, is_protected, round_trip };
+
+    /// public class com.acme.Service with a constructor, `static int pick(int)` returning from two
+    /// branches and a native method
+    fn service_class(version: ClassfileVersion) -> JavaClass {
+        ClassBuilder::new("com/acme/Service")
+            .version(version)
+            .public()
+            .default_constructor()
+            .method("pick", "(I)I", |mut m| {
+                let second = m.new_label();
+                m.public().static_().iload(0).jump(Jump::IfEq, second).iconst(1).ret()
+                 .mark(second).iconst(2).ret()
+            })
+            .method("load", "()V", |m| m.public().native())
+            .build()
+            .unwrap()
+    }
+
+    fn probes(expression: &str) -> (TimingProbes, Arc<ProbeRegistry>) {
+        let registry = Arc::new(ProbeRegistry::new());
+        (TimingProbes::new(vec![ Pointcut::parse(expression).unwrap() ], registry.clone()), registry)
+    }
+
+    fn helper_calls(code: &Code, name: &str) -> usize {
+        code.instructions.iter().filter(|insn| match **insn {
+            Insn::Invoke(Invoke::Static, ref member) => member.owner == HELPER_CLASS && member.name == name,
+            _ => false,
+        }).count()
+    }
+
+    #[test]
+    fn probes_are_woven_around_selected_methods() {
+        let (probes, registry) = probes("class(com.acme.*)");
+        let mut class = service_class(ClassfileVersion::Java17);
+
+        assert!(probes.transform(&mut class).unwrap());
+        assert_eq!(vec![ ProbeSite { class_name: "com/acme/Service".to_string(),
+                                     method_name: "pick".to_string(),
+                                     descriptor: "(I)I".to_string() } ],
+                   registry.snapshot().into_iter().map(|(site, _)| site).collect::<Vec<ProbeSite>>());
+
+        let code = class.method("pick", "(I)I").unwrap().code.as_ref().unwrap().clone();
+
+        assert_eq!(1, helper_calls(&code, "enter"));
+        // Both returns and the exception handler
+        assert_eq!(3, helper_calls(&code, "exit"));
+        assert_eq!(Insn::Op(Instruction::ICONST_0), code.instructions[0]);
+        assert_eq!(Some(&Insn::Op(Instruction::ATHROW)), code.instructions.last());
+        assert_eq!(None, code.try_catch_blocks.last().unwrap().catch_type);
+        assert_eq!(Some(&Insn::Frame(Frame::new(vec![], vec![ FrameItem::Object("java/lang/Throwable".to_string()) ]))),
+                   code.instructions.iter().rfind(|insn| matches!(**insn, Insn::Frame(_))));
+        assert_eq!(4, code.max_stack);
+
+        let constructor = class.method("<init>", "()V").unwrap().code.as_ref().unwrap();
+        assert_eq!(0, helper_calls(constructor, "enter"));
+
+        let woven = round_trip(&class);
+        assert_eq!(3, helper_calls(woven.method("pick", "(I)I").unwrap().code.as_ref().unwrap(), "exit"));
+    }
+
+    #[test]
+    fn exit_probes_run_once_when_the_return_throws() {
+        // The return of a synchronized method throws if its monitor was released in between
+        let mut class = ClassBuilder::new("com/acme/Service")
+            .version(ClassfileVersion::Java17)
+            .public()
+            .method("update", "(I)I", |mut m| {
+                let second = m.new_label();
+                m.public().synchronized().iload(1).jump(Jump::IfEq, second).iconst(1).ret()
+                 .mark(second).iconst(2).ret()
+            })
+            .build()
+            .unwrap();
+
+        weave(&mut class, 0, 0).unwrap();
+
+        let code = class.methods[0].code.as_ref().unwrap();
+        let returns: Vec<usize> = code.instructions.iter().enumerate()
+            .filter(|&(_, insn)| *insn == Insn::Op(Instruction::IRETURN))
+            .map(|(index, _)| index)
+            .collect();
+
+        assert_eq!(2, returns.len());
+        assert_eq!(2, code.try_catch_blocks.len());
+
+        for &index in returns.iter() {
+            // The exit probe right before the return is the only one that runs
+            assert_eq!(Insn::Invoke(Invoke::Static, MemberRef::new(HELPER_CLASS, "exit", EXIT_DESCRIPTOR)), code.instructions[index - 1]);
+            assert!(!is_protected(code, index));
+            assert!(!is_protected(code, index - 1));
+        }
+
+        // The code of the method itself still runs the exit probe of the handler, the range ends
+        // with a label before the three instructions of the probe
+        assert_eq!(Insn::Op(Instruction::ICONST_1), code.instructions[returns[0] - 5]);
+        assert!(is_protected(code, returns[0] - 5));
+        assert_eq!(Insn::Op(Instruction::ICONST_2), code.instructions[returns[1] - 5]);
+        assert!(is_protected(code, returns[1] - 5));
+
+        round_trip(&class);
+    }
+
+    #[test]
+    fn classes_without_frames_get_no_frames() {
+        let (probes, _) = probes("method(pick)");
+        let mut class = service_class(ClassfileVersion::Java1_5);
+
+        assert!(probes.transform(&mut class).unwrap());
+        assert!(!class.method("pick", "(I)I").unwrap().code.as_ref().unwrap().has_frames());
+        round_trip(&class);
+    }
+
+    #[test]
+    fn unselected_classes_are_left_alone() {
+        let (probes, registry) = probes("execution(com.acme.Service.pick(J)I)");
+        let mut class = service_class(ClassfileVersion::Java17);
+        let original = class.clone();
+
+        assert!(!probes.transform(&mut class).unwrap());
+        assert_eq!(original, class);
+        assert!(registry.is_empty());
+
+        // Nothing is woven before the helper class is installed
+        assert!(!is_installed());
+        assert!(!probes.accepts("com/acme/Service"));
+    }
+
+    #[test]
+    fn sites_are_registered_once() {
+        let registry = ProbeRegistry::new();
+        let site = ProbeSite { class_name: "a/B".to_string(), method_name: "c".to_string(), descriptor: "()V".to_string() };
+
+        assert_eq!(0, registry.register(site.clone()));
+        assert_eq!(1, registry.register(ProbeSite { method_name: "d".to_string(), ..site.clone() }));
+        assert_eq!(0, registry.register(site.clone()));
+
+        registry.record(0, Duration::from_millis(3));
+        registry.record(0, Duration::from_millis(5));
+        registry.record(7, Duration::from_millis(5));
+
+        assert_eq!(Some(ProbeStats { calls: 2, total_time: Duration::from_millis(8), max_time: Duration::from_millis(5) }),
+                   registry.stats(0));
+        assert_eq!(Some(site), registry.site(0));
+        assert_eq!(None, registry.stats(7));
+    }
+
+    #[test]
+    fn helper_class_declares_native_methods() {
+        let helper = helper_class().unwrap();
+
+        assert_eq!(HELPER_CLASS, helper.name);
+        assert!(helper.method("enter", ENTER_DESCRIPTOR).unwrap().is_native());
+        assert!(helper.method("exit", EXIT_DESCRIPTOR).unwrap().is_static());
+    }
+
+    #[test]
+    fn nano_times_keep_their_differences() {
+        assert_eq!(Some(2_000_000_500), (nano_time(1_000_000_000) - nano_time(-1_000_000_500)).num_nanoseconds());
+    }
+
+    #[test]
+    fn timed_pointcuts_are_read_from_config() {
+        let config = config(r#"
+            timed = [ "services", "missing" ]
+
+            [pointcuts]
+            services = "class(com.acme.*)"
+        "#);
+
+        assert_unknown_pointcut(TimingProbes::from_config(&config, Arc::new(ProbeRegistry::new())), "missing");
+    }
+}
@@ -0,0 +1,212 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ AccessFlags, ClassReader, ClassWriter, Instruction, MethodAccessFlags };
+    use jvmti::config::Config;
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::code::*;
+    use jvmti::instrumentation::timing::ProbeError;
+    use jvmti::instrumentation::trace::*;
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// static void submit(Executor e, Runnable r, ScheduledExecutorService s, Callable c) {
+    ///     e.execute(r);
+    ///     s.schedule(c, 1L, TimeUnit.SECONDS);
+    ///     CompletableFuture.runAsync(r);
+    ///     s.shutdown();
+    /// }
+    fn submitter_class() -> JavaClass {
+        let mut class = JavaClass::new();
+        class.name = "com/acme/Submitter".to_string();
+
+        let mut code = Code::new();
+        code.max_locals = 4;
+        code.instructions = vec![
+            Insn::Op(Instruction::ALOAD_0),
+            Insn::Op(Instruction::ALOAD_1),
+            Insn::Invoke(Invoke::Interface, MemberRef::interface("java/util/concurrent/Executor", "execute", "(Ljava/lang/Runnable;)V")),
+            Insn::Op(Instruction::ALOAD_2),
+            Insn::Op(Instruction::ALOAD_3),
+            Insn::Op(Instruction::LCONST_1),
+            Insn::Field(FieldOp::GetStatic, MemberRef::new("java/util/concurrent/TimeUnit", "SECONDS", "Ljava/util/concurrent/TimeUnit;")),
+            Insn::Invoke(Invoke::Interface, MemberRef::interface("java/util/concurrent/ScheduledExecutorService", "schedule",
+                                                                 "(Ljava/util/concurrent/Callable;JLjava/util/concurrent/TimeUnit;)Ljava/util/concurrent/ScheduledFuture;")),
+            Insn::Op(Instruction::POP),
+            Insn::Op(Instruction::ALOAD_1),
+            Insn::Invoke(Invoke::Static, MemberRef::new("java/util/concurrent/CompletableFuture", "runAsync",
+                                                        "(Ljava/lang/Runnable;)Ljava/util/concurrent/CompletableFuture;")),
+            Insn::Op(Instruction::POP),
+            Insn::Op(Instruction::ALOAD_2),
+            Insn::Invoke(Invoke::Interface, MemberRef::interface("java/util/concurrent/ExecutorService", "shutdown", "()V")),
+            Insn::Op(Instruction::RETURN),
+        ];
+
+        let descriptor = "(Ljava/util/concurrent/Executor;Ljava/lang/Runnable;Ljava/util/concurrent/ScheduledExecutorService;Ljava/util/concurrent/Callable;)V";
+        let mut method = Method::new("submit".to_string(), MethodDescriptor::parse(descriptor).unwrap());
+        method.access_flags = AccessFlags::of(MethodAccessFlags::Static as u16);
+        method.code = Some(code);
+        class.add_method(method);
+
+        class
+    }
+
+    fn wrap_call(wrapper: &str, interface: &str) -> Insn {
+        Insn::Invoke(Invoke::Static, MemberRef::new(wrapper, "wrap", &format!("(L{};)L{};", interface, interface)))
+    }
+
+    #[test]
+    fn spans_are_nested_on_a_thread() {
+        let registry = TraceRegistry::new();
+
+        assert_eq!(None, registry.current());
+
+        let outer = registry.start_span("outer");
+        let inner = registry.start_span("inner");
+
+        assert_eq!(outer.trace_id, inner.trace_id);
+        assert_eq!(Some(inner), registry.current());
+
+        let finished = registry.end_span().unwrap();
+        assert_eq!(inner, finished.context());
+        assert_eq!(Some(outer.span_id), finished.parent_id);
+        assert_eq!("inner", finished.name);
+
+        assert_eq!(None, registry.end_span().unwrap().parent_id);
+        assert_eq!(None, registry.end_span());
+        assert_eq!(vec![ "inner", "outer" ], registry.drain().into_iter().map(|span| span.name).collect::<Vec<String>>());
+
+        // Another span starts a new trace
+        assert!(registry.start_span("next").trace_id != outer.trace_id);
+        registry.end_span();
+    }
+
+    #[test]
+    fn attached_contexts_link_spans_across_threads() {
+        let registry = Arc::new(TraceRegistry::new());
+        let request = registry.start_span("request");
+
+        let worker = registry.clone();
+        let (task, current) = thread::spawn(move || {
+            assert_eq!(None, worker.current());
+
+            worker.attach(request);
+            worker.start_span("task");
+            let task = worker.end_span();
+            // Spans can't end the attached context, and detaching discards the unfinished ones
+            assert_eq!(None, worker.end_span());
+            worker.start_span("unfinished");
+            assert!(worker.detach());
+            assert!(!worker.detach());
+
+            (task, worker.current())
+        }).join().unwrap();
+
+        let task = task.unwrap();
+        assert_eq!(request.trace_id, task.trace_id);
+        assert_eq!(Some(request.span_id), task.parent_id);
+        assert_eq!(None, current);
+
+        // The spawned thread hasn't changed the context of this one
+        assert_eq!(Some(request), registry.current());
+        assert_eq!(Some(request), registry.end_span().map(|span| span.context()));
+        assert_eq!(2, registry.spans().len());
+    }
+
+    #[test]
+    fn handovers_are_recognised() {
+        assert!(is_handover(&MemberRef::interface("java/util/concurrent/Executor", "execute", "(Ljava/lang/Runnable;)V")));
+        assert!(is_handover(&MemberRef::new("java/util/concurrent/ThreadPoolExecutor", "submit",
+                                            "(Ljava/util/concurrent/Callable;)Ljava/util/concurrent/Future;")));
+        assert!(is_handover(&MemberRef::new("java/util/concurrent/ForkJoinPool", "execute", "(Ljava/lang/Runnable;)V")));
+        assert!(is_handover(&MemberRef::new("java/util/concurrent/CompletableFuture", "thenApply",
+                                            "(Ljava/util/function/Function;)Ljava/util/concurrent/CompletableFuture;")));
+        assert!(!is_handover(&MemberRef::new("java/util/concurrent/ThreadPoolExecutor", "remove", "(Ljava/lang/Runnable;)Z")));
+        assert!(!is_handover(&MemberRef::new("com/acme/Executor", "execute", "(Ljava/lang/Runnable;)V")));
+
+        assert_eq!(Some("java/util/function/Supplier"),
+                   task_type(&JavaType::from_internal_name("java/util/function/Supplier")).map(|task| task.interface));
+        assert_eq!(None, task_type(&JavaType::from_internal_name("java/lang/Object")));
+        assert_eq!("jvmti/probe/TracedBiFunction", TASK_TYPES[5].wrapper());
+    }
+
+    #[test]
+    fn tasks_are_wrapped_at_call_sites() {
+        let mut class = submitter_class();
+
+        assert_eq!(3, weave(&mut class, 0).unwrap());
+
+        let code = class.methods[0].code.as_ref().unwrap();
+
+        // A task passed last is wrapped in place
+        assert_eq!(vec![ Insn::Op(Instruction::ALOAD_1),
+                         wrap_call("jvmti/probe/TracedRunnable", "java/lang/Runnable") ],
+                   code.instructions[1..3].to_vec());
+
+        // Otherwise the arguments are spilled and reloaded
+        assert_eq!(vec![ Insn::Op(Instruction::ASTORE(7)),
+                         Insn::Op(Instruction::LSTORE(5)),
+                         Insn::Op(Instruction::ASTORE(4)),
+                         Insn::Op(Instruction::ALOAD(4)),
+                         wrap_call("jvmti/probe/TracedCallable", "java/util/concurrent/Callable"),
+                         Insn::Op(Instruction::LLOAD(5)),
+                         Insn::Op(Instruction::ALOAD(7)) ],
+                   code.instructions[8..15].to_vec());
+        assert_eq!(wrap_call("jvmti/probe/TracedRunnable", "java/lang/Runnable"), code.instructions[18]);
+        assert_eq!(24, code.instructions.len());
+        assert_eq!(5, code.max_stack);
+        assert_eq!(8, code.max_locals);
+
+        let mut data = vec![];
+        ClassWriter::new(&mut data).write_class(&class.to_classfile().unwrap()).unwrap();
+        JavaClass::from_classfile(&ClassReader::read_class(&mut Cursor::new(data)).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn wrapper_classes_delegate_to_the_task() {
+        for task in TASK_TYPES.iter() {
+            let class = wrapper_class(task).unwrap();
+
+            assert_eq!(task.wrapper(), class.name);
+            assert_eq!(vec![ task.interface.to_string() ], class.interfaces);
+            assert_eq!(vec![ "<init>", "wrap", task.method ], class.methods.iter().map(|m| m.name.as_str()).collect::<Vec<&str>>());
+
+            let code = class.methods[2].code.as_ref().unwrap();
+            assert_eq!(1, code.try_catch_blocks.len());
+            assert!(code.instructions.contains(&Insn::Invoke(Invoke::Interface,
+                                                             MemberRef::interface(task.interface, task.method, task.descriptor))));
+
+            let mut data = vec![];
+            ClassWriter::new(&mut data).write_class(&class.to_classfile().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn propagation_is_read_from_config() {
+        let config: Config = r#"
+            agent_name = "test"
+            entry_points = []
+            active_classes = []
+            propagated = [ "services" ]
+
+            [pointcuts]
+            services = "class(com.acme.*)"
+        "#.parse().unwrap();
+
+        let propagation = ContextPropagation::from_config(&config).unwrap();
+
+        // Nothing is woven before the helper classes are installed
+        assert!(!is_installed());
+        assert!(!propagation.accepts("com/acme/Submitter"));
+
+        let mut missing = config;
+        missing.propagated.push("missing".to_string());
+
+        match ContextPropagation::from_config(&missing) {
+            Err(ProbeError::UnknownPointcut(ref name)) if name == "missing" => (),
+            _ => panic!("the unknown pointcut wasn't reported"),
+        }
+    }
+}
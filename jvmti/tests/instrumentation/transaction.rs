@@ -0,0 +1,211 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+    use jvmti::bytecode::{ AccessFlags, Instruction, MethodAccessFlags };
+    use jvmti::instrumentation::*;
+    use jvmti::instrumentation::pointcut::{ ClassDeclaration, MethodDeclaration };
+    use jvmti::instrumentation::timing::ProbeError;
+    use jvmti::instrumentation::trace::TraceRegistry;
+    use jvmti::instrumentation::transaction::*;
+    use std::sync::Arc;
+    use std::thread;
+    use super::super::{ config, helper_call, is_protected, round_trip };
+
+    fn class_info(name: &str, super_name: &str, interfaces: &[&str]) -> ClassInfo {
+        ClassInfo {
+            name: name.to_string(),
+            declaration: Some(ClassDeclaration {
+                access_flags: 0,
+                super_name: Some(super_name.to_string()),
+                interfaces: interfaces.iter().map(|i| i.to_string()).collect(),
+                annotations: vec![],
+            }),
+        }
+    }
+
+    fn public_method(name: &str, descriptor: &str) -> MethodInfo {
+        MethodInfo {
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+            declaration: Some(MethodDeclaration { access_flags: MethodAccessFlags::Public as u16, annotations: vec![] }),
+        }
+    }
+
+    /// class Statement { public boolean execute(String sql) { return true; } public int executeUpdate() { return 0; } }
+    fn statement_class() -> JavaClass {
+        let mut class = JavaClass::new();
+        class.name = "org/acme/jdbc/Statement".to_string();
+        class.add_interface("java/sql/PreparedStatement");
+
+        for &(name, descriptor, ref value, ref ret) in [ ("execute", "(Ljava/lang/String;)Z", Instruction::ICONST_1, Instruction::IRETURN),
+                                                 ("executeUpdate", "()I", Instruction::ICONST_0, Instruction::IRETURN) ].iter() {
+            let mut code = Code::new();
+            code.instructions = vec![ Insn::Op(value.clone()), Insn::Op(ret.clone()) ];
+
+            let mut method = Method::new(name.to_string(), MethodDescriptor::parse(descriptor).unwrap());
+            method.access_flags = AccessFlags::of(MethodAccessFlags::Public as u16);
+            method.code = Some(code);
+            class.add_method(method);
+        }
+
+        class
+    }
+
+    #[test]
+    fn packs_select_framework_methods() {
+        let servlet = &pack("servlet").unwrap().pointcut;
+        let javax = "(Ljavax/servlet/http/HttpServletRequest;Ljavax/servlet/http/HttpServletResponse;)V";
+        let jakarta = "(Ljakarta/servlet/http/HttpServletRequest;Ljakarta/servlet/http/HttpServletResponse;)V";
+
+        assert_eq!(EntryKind::Request, pack("servlet").unwrap().kind);
+        assert!(servlet.matches_method(&class_info("javax.servlet.http.HttpServlet", "javax.servlet.GenericServlet", &[]),
+                                       &public_method("service", javax)));
+        assert!(servlet.matches_method(&class_info("com.acme.Users", "jakarta.servlet.http.HttpServlet", &[]),
+                                       &public_method("service", jakarta)));
+        assert!(!servlet.matches_method(&class_info("com.acme.Users", "javax.servlet.http.HttpServlet", &[]),
+                                        &public_method("doGet", javax)));
+        assert!(!servlet.matches_method(&class_info("com.acme.Users", "javax.servlet.http.HttpServlet", &[]),
+                                        &public_method("service", jakarta)));
+
+        let jdbc = &pack("jdbc").unwrap().pointcut;
+        let statement = class_info("org.h2.jdbc.JdbcStatement", "java.lang.Object", &[ "java.sql.Statement" ]);
+
+        assert_eq!(EntryKind::Database, pack("jdbc").unwrap().kind);
+        assert!(jdbc.matches_method(&statement, &public_method("executeQuery", "(Ljava/lang/String;)Ljava/sql/ResultSet;")));
+        assert!(!jdbc.matches_method(&statement, &public_method("close", "()V")));
+        assert!(!jdbc.matches_method(&class_info("com.acme.Jobs", "java.lang.Object", &[]), &public_method("execute", "()V")));
+        // The class name alone can't rule out a statement
+        assert!(jdbc.matches_class(&ClassInfo::new("org/h2/jdbc/JdbcStatement")));
+
+        assert_eq!(vec![ "servlet", "jdbc" ], packs().iter().map(|pack| pack.name).collect::<Vec<&str>>());
+        assert_eq!(None, pack("struts"));
+    }
+
+    #[test]
+    fn database_calls_nest_under_requests() {
+        let registry = TransactionRegistry::new(Arc::new(TraceRegistry::new()));
+
+        assert!(registry.enter_request(|| ("GET".to_string(), "http://localhost/users?id=1".to_string())));
+        assert!(registry.enter_database(|| "select * from users".to_string()));
+        assert!(!registry.enter_database(|| panic!("nested calls aren't described")));
+        assert_eq!(None, registry.exit(false));
+
+        let call = match registry.exit(true) {
+            Some(Completed::DatabaseCall(call)) => call,
+            other => panic!("expected a database call, got {:?}", other),
+        };
+        assert_eq!("select * from users", call.sql);
+        assert!(call.failed);
+
+        let transaction = match registry.exit(false) {
+            Some(Completed::Transaction(transaction)) => transaction,
+            other => panic!("expected a transaction, got {:?}", other),
+        };
+        assert_eq!(("GET", "http://localhost/users?id=1"), (transaction.method.as_str(), transaction.url.as_str()));
+        assert_eq!(Some(transaction.context.span_id), call.parent_id);
+        assert_eq!(transaction.context.trace_id, call.context.trace_id);
+        assert_eq!(vec![ call ], transaction.database_calls);
+        assert!(!transaction.failed);
+
+        // Calls outside of requests are kept on their own
+        registry.enter_database(|| "delete from sessions".to_string());
+        registry.exit(false);
+        assert_eq!(None, registry.exit(false));
+
+        assert_eq!(1, registry.transactions().len());
+        assert_eq!(vec![ "delete from sessions" ], registry.database_calls().into_iter().map(|c| c.sql).collect::<Vec<String>>());
+        assert_eq!(1, registry.drain().len());
+        assert!(registry.transactions().is_empty());
+    }
+
+    #[test]
+    fn database_calls_on_other_threads_join_the_request() {
+        let traces = Arc::new(TraceRegistry::new());
+        let registry = Arc::new(TransactionRegistry::new(traces.clone()));
+
+        registry.enter_request(|| ("POST".to_string(), "http://localhost/orders".to_string()));
+        let request = traces.current().unwrap();
+
+        let worker = (traces.clone(), registry.clone());
+        thread::spawn(move || {
+            let (traces, registry) = worker;
+
+            traces.attach(request);
+            registry.enter_database(|| "insert into orders values (?)".to_string());
+            registry.exit(false);
+            traces.detach();
+        }).join().unwrap();
+
+        match registry.exit(false) {
+            Some(Completed::Transaction(transaction)) => {
+                assert_eq!(1, transaction.database_calls.len());
+                assert_eq!(Some(request.span_id), transaction.database_calls[0].parent_id);
+            }
+            other => panic!("expected a transaction, got {:?}", other),
+        }
+        assert!(registry.database_calls().is_empty());
+    }
+
+    #[test]
+    fn probes_pass_the_request_and_sql() {
+        let mut class = statement_class();
+
+        weave(&mut class, 0, EntryKind::Database).unwrap();
+        weave(&mut class, 1, EntryKind::Database).unwrap();
+
+        let execute = class.methods[0].code.as_ref().unwrap();
+        assert_eq!(vec![ Insn::Op(Instruction::ALOAD_0),
+                         Insn::Op(Instruction::ALOAD_1),
+                         helper_call(HELPER_CLASS, "database", DATABASE_DESCRIPTOR) ],
+                   execute.instructions[0..3].to_vec());
+        assert_eq!(vec![ Insn::Op(Instruction::ICONST_0),
+                         helper_call(HELPER_CLASS, "exit", EXIT_DESCRIPTOR),
+                         Insn::Op(Instruction::IRETURN) ],
+                   execute.instructions[6..9].to_vec());
+        // Only the code of the method itself is covered by the handler, not the exit and the return
+        assert!(is_protected(execute, 4));
+        assert!((6..9).all(|index| !is_protected(execute, index)));
+        assert_eq!(vec![ Insn::Op(Instruction::ICONST_1),
+                         helper_call(HELPER_CLASS, "exit", EXIT_DESCRIPTOR),
+                         Insn::Op(Instruction::ATHROW) ],
+                   execute.instructions[execute.instructions.len() - 3..].to_vec());
+        assert_eq!(1, execute.try_catch_blocks.len());
+        assert_eq!(2, execute.max_stack);
+
+        // Prepared statements don't get the SQL passed
+        assert_eq!(Insn::Op(Instruction::ACONST_NULL), class.methods[1].code.as_ref().unwrap().instructions[1]);
+
+        round_trip(&class);
+    }
+
+    #[test]
+    fn packs_are_read_from_config() {
+        let config = config(r#"
+            packs = [ "servlet", "jdbc" ]
+            timed = [ "jdbc" ]
+
+            [pointcuts]
+            servlet = "class(com.acme.*)"
+        "#);
+
+        // Packs are available as pointcuts, unless the configuration defines one of the same name
+        assert_eq!(Some(&pack("jdbc").unwrap().pointcut), config.pointcut("jdbc"));
+        assert_eq!(Some(&Pointcut::parse("class(com.acme.*)").unwrap()), config.pointcut("servlet"));
+        assert!(TimingProbes::from_config(&config, Arc::new(ProbeRegistry::new())).is_ok());
+
+        let probes = TransactionProbes::from_config(&config, Arc::new(TransactionRegistry::new(Arc::new(TraceRegistry::new())))).unwrap();
+
+        // Nothing is woven before the helper class is installed
+        assert!(!is_installed());
+        assert!(!probes.accepts("org/h2/jdbc/JdbcStatement"));
+
+        let mut missing = config;
+        missing.packs.push("struts".to_string());
+
+        match TransactionProbes::from_config(&missing, probes.registry()) {
+            Err(ProbeError::UnknownPack(ref name)) if name == "struts" => (),
+            _ => panic!("the unknown pack wasn't reported"),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+
+    use jvmti::class::JavaType;
+    use jvmti::emulator::JVMEmulator;
+    use jvmti::environment::jvmti::JVMTI;
+    use jvmti::error::NativeError;
+    use jvmti::method::{ LocalValue, LocalVariableEntry };
+    use jvmti::native::JavaValue;
+    use jvmti::native::jvmti_native::{ jmethodID, jobject, jvmtiFrameInfo };
+    use std::ptr;
+
+    fn entry(name: &str, signature: &str, slot: i32, start_location: i64, length: i32) -> LocalVariableEntry {
+        LocalVariableEntry {
+            start_location,
+            length,
+            name: name.to_string(),
+            signature: signature.to_string(),
+            generic_signature: None,
+            slot,
+        }
+    }
+
+    /// `long total(String label, int count)` stopped at location 8, `sum` isn't assigned yet
+    fn emulator() -> JVMEmulator {
+        let mut emu = JVMEmulator::new();
+        let method = 10 as jmethodID;
+
+        emu.capabilities.can_access_local_variables = true;
+        emu.frames = vec![ jvmtiFrameInfo { method, location: 8 },
+                           jvmtiFrameInfo { method: 20 as jmethodID, location: -1 } ];
+
+        emu.local_variable_tables.insert(method, vec![ entry("sum", "J", 4, 12, 6),
+                                                       entry("count", "I", 2, 0, 18),
+                                                       entry("label", "Ljava/lang/String;", 1, 0, 18),
+                                                       entry("this", "Lcom/acme/Orders;", 0, 0, 18),
+                                                       entry("ratio", "D", 3, 2, 10) ]);
+
+        emu.locals.insert(0, JavaValue::from(7 as jobject));
+        emu.locals.insert(1, JavaValue::from(ptr::null_mut()));
+        emu.locals.insert(2, JavaValue::from(3));
+        emu.instance = Some(7 as jobject);
+        emu
+    }
+
+    #[test]
+    fn frame_locals_are_listed_by_name_and_type() {
+        let emu = emulator();
+        let locals = emu.get_frame_locals(ptr::null_mut(), 0).unwrap();
+
+        assert_eq!(vec![ "this", "label", "count", "ratio" ], locals.iter().map(|l| l.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!(Some(LocalValue::Object(7 as jobject)), locals[0].value);
+        assert_eq!(Some(LocalValue::Object(ptr::null_mut())), locals[1].value);
+        assert_eq!("I", locals[2].signature);
+        assert_eq!(Some(LocalValue::Int(3)), locals[2].value);
+
+        // Variables the VM can't read are listed without a value
+        assert_eq!(3, locals[3].slot);
+        assert_eq!(None, locals[3].value);
+
+        assert_eq!(7 as jobject, emu.get_local_instance(ptr::null_mut(), 0).unwrap());
+    }
+
+    #[test]
+    fn native_frames_have_no_locals() {
+        let emu = emulator();
+
+        match emu.get_frame_locals(ptr::null_mut(), 1) {
+            Err(NativeError::OpaqueFrame) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        match emu.get_frame_locals(ptr::null_mut(), 2) {
+            Err(NativeError::NoMoreFrames) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn locals_are_accessed_by_type() {
+        let mut emu = emulator();
+        emu.locals.insert(4, JavaValue::from(1234i64));
+
+        assert_eq!(LocalValue::Long(1234), emu.get_local(ptr::null_mut(), 0, 4, &JavaType::Long).unwrap());
+        assert_eq!(LocalValue::Int(3), emu.get_local(ptr::null_mut(), 0, 2, &JavaType::Boolean).unwrap());
+        assert!(emu.get_local(ptr::null_mut(), 0, 9, &JavaType::Int).is_err());
+
+        emu.set_local(ptr::null_mut(), 0, 2, &LocalValue::Int(5)).unwrap();
+        emu.set_local(ptr::null_mut(), 0, 3, &LocalValue::Double(0.5)).unwrap();
+        emu.set_local(ptr::null_mut(), 0, 1, &LocalValue::Object(ptr::null_mut())).unwrap();
+
+        assert_eq!(vec![ (2, LocalValue::Int(5)), (3, LocalValue::Double(0.5)), (1, LocalValue::Object(ptr::null_mut())) ],
+                   *emu.assigned_locals.lock().unwrap());
+    }
+
+    #[test]
+    fn local_access_requires_the_capability() {
+        let mut emu = emulator();
+        emu.capabilities.can_access_local_variables = false;
+
+        match emu.get_local(ptr::null_mut(), 0, 2, &JavaType::Int) {
+            Err(NativeError::MustPossessCapability) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(emu.set_local(ptr::null_mut(), 0, 2, &LocalValue::Int(5)).is_err());
+        assert!(emu.get_frame_locals(ptr::null_mut(), 0).is_err());
+        assert!(emu.assigned_locals.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn variables_are_live_within_their_code_range() {
+        let sum = entry("sum", "J", 4, 12, 6);
+
+        assert!(!sum.is_live_at(11));
+        assert!(sum.is_live_at(12));
+        assert!(sum.is_live_at(17));
+        assert!(!sum.is_live_at(18));
+    }
+}
@@ -0,0 +1,209 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+
+    use jvmti::emulator::JVMEmulator;
+    use jvmti::environment::jvmti::JVMTI;
+    use jvmti::error::NativeError;
+    use jvmti::event::VMEvent;
+    use jvmti::method::LineNumberEntry;
+    use jvmti::native::jvmti_native::{ jclass, jmethodID, jvmtiAddrLocationMap, jvmtiFrameInfo };
+    use jvmti::perf::*;
+    use jvmti::runtime::{ CompiledMethodLoadEvent, DynamicCodeGeneratedEvent };
+
+    fn count() -> jmethodID {
+        10 as jmethodID
+    }
+
+    fn size() -> jmethodID {
+        20 as jmethodID
+    }
+
+    /// `Orders.count` calling `ArrayList.size`, which is inlined
+    fn emulator() -> JVMEmulator {
+        let mut emu = JVMEmulator::new();
+
+        emu.class_signatures.insert(1 as jclass, "Lcom/acme/Orders;".to_string());
+        emu.class_signatures.insert(2 as jclass, "Ljava/util/ArrayList;".to_string());
+        emu.source_files.insert(1 as jclass, "Orders.java".to_string());
+        emu.source_files.insert(2 as jclass, "ArrayList.java".to_string());
+
+        emu.methods.insert(count(), (1 as jclass, "count".to_string(), "(Ljava/util/List;)I".to_string()));
+        emu.methods.insert(size(), (2 as jclass, "size".to_string(), "()I".to_string()));
+
+        emu.line_number_tables.insert(count(), vec![ LineNumberEntry { start_location: 0, line_number: 10 },
+                                                     LineNumberEntry { start_location: 4, line_number: 11 },
+                                                     LineNumberEntry { start_location: 9, line_number: 12 } ]);
+        emu.line_number_tables.insert(size(), vec![ LineNumberEntry { start_location: 0, line_number: 440 } ]);
+        emu
+    }
+
+    fn map() -> Vec<jvmtiAddrLocationMap> {
+        vec![ jvmtiAddrLocationMap { start_address: 0x1010 as *const _, location: 4 },
+              jvmtiAddrLocationMap { start_address: 0x1000 as *const _, location: 0 },
+              jvmtiAddrLocationMap { start_address: 0x1040 as *const _, location: 9 } ]
+    }
+
+    fn inlining() -> Vec<(usize, Vec<jvmtiFrameInfo>)> {
+        vec![ (0x1020, vec![ jvmtiFrameInfo { method: size(), location: 0 }, jvmtiFrameInfo { method: count(), location: 5 } ]),
+              (0x1030, vec![ jvmtiFrameInfo { method: size(), location: 1 }, jvmtiFrameInfo { method: count(), location: 5 } ]),
+              (0x1050, vec![ jvmtiFrameInfo { method: count(), location: 9 } ]) ]
+    }
+
+    fn compiled(inlining: &[(usize, Vec<jvmtiFrameInfo>)]) -> CompiledMethodLoadEvent {
+        compiled_method_load_event(&emulator(), count(), 0x1000, 0x100, &map(), inlining).unwrap()
+    }
+
+    #[test]
+    fn compiled_methods_are_resolved_with_lines_and_inlined_methods() {
+        let event = compiled(&inlining());
+
+        assert_eq!("com.acme.Orders.count(Ljava/util/List;)I", event.symbol());
+        assert_eq!(Some("Orders.java".to_string()), event.source_file);
+
+        assert_eq!(vec![ 0x1000, 0x1010, 0x1040 ], event.locations.iter().map(|location| location.address).collect::<Vec<usize>>());
+        assert_eq!(vec![ Some(10), Some(11), Some(12) ], event.locations.iter().map(|location| location.line).collect::<Vec<Option<u32>>>());
+
+        let inlined = &event.inlining[0];
+        assert_eq!(0x1020, inlined.address);
+        assert_eq!("java.util.ArrayList", inlined.frames[0].class_name);
+        assert_eq!(Some(440), inlined.frames[0].line);
+        assert_eq!("count", inlined.frames[1].method_name);
+        assert_eq!(Some(11), inlined.frames[1].line);
+    }
+
+    #[test]
+    fn unknown_compiled_methods_are_not_resolved() {
+        match compiled_method_load_event(&emulator(), 99 as jmethodID, 0x1000, 0x100, &[], &[]) {
+            Err(NativeError::NotImplemented) => (),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("resolved an unknown method"),
+        }
+    }
+
+    #[test]
+    fn perf_maps_list_the_generated_code() {
+        let mut perf_map = PerfMap::new(vec![], false);
+
+        perf_map.compiled_method(&compiled(&inlining())).unwrap();
+        perf_map.dynamic_code(&DynamicCodeGeneratedEvent { name: "Interpreter".to_string(), code_address: 0x2000, code_size: 0x40 }).unwrap();
+
+        assert_eq!("1000 100 com.acme.Orders.count(Ljava/util/List;)I\n2000 40 Interpreter\n",
+                   String::from_utf8(perf_map.get_ref().clone()).unwrap());
+    }
+
+    #[test]
+    fn perf_maps_unfold_the_inlined_methods() {
+        let mut perf_map = PerfMap::new(vec![], true);
+
+        perf_map.compiled_method(&compiled(&inlining())).unwrap();
+        perf_map.compiled_method(&compiled(&[])).unwrap();
+
+        assert_eq!("1000 30 com.acme.Orders.count(Ljava/util/List;)I->java.util.ArrayList.size()I\n\
+                    1030 d0 com.acme.Orders.count(Ljava/util/List;)I\n\
+                    1000 100 com.acme.Orders.count(Ljava/util/List;)I\n",
+                   String::from_utf8(perf_map.get_ref().clone()).unwrap());
+    }
+
+    /// The records of a jitdump as their ids and bodies
+    fn records(dump: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut records = vec![];
+        let mut offset = 40;
+
+        while offset < dump.len() {
+            let size = u32_at(dump, offset + 4) as usize;
+            records.push((u32_at(dump, offset), dump[offset + 16..offset + size].to_vec()));
+            offset += size;
+        }
+
+        records
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes([ bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3] ])
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        let mut value = [0; 8];
+        value.copy_from_slice(&bytes[offset..offset + 8]);
+        u64::from_ne_bytes(value)
+    }
+
+    #[test]
+    fn jitdumps_record_the_code_and_lines_of_the_innermost_methods() {
+        let mut dump = vec![];
+        let mut jitdump = JitDump::new(&mut dump, 42).unwrap();
+
+        jitdump.compiled_method(&compiled(&inlining()), &[ 0x90, 0xc3 ]).unwrap();
+        jitdump.close().unwrap();
+
+        assert_eq!(0x4A695444, u32_at(&dump, 0));
+        assert_eq!(1, u32_at(&dump, 4));
+        assert_eq!(40, u32_at(&dump, 8));
+        assert_eq!(42, u32_at(&dump, 20));
+
+        let records = records(&dump);
+        assert_eq!(vec![ 2, 0, 3 ], records.iter().map(|record| record.0).collect::<Vec<u32>>());
+
+        let debug_info = &records[0].1;
+        assert_eq!(0, (debug_info.len() + 16) % 8);
+        assert_eq!(0x1000, u64_at(debug_info, 0));
+        assert_eq!(3, u64_at(debug_info, 8));
+        assert_eq!(0x1020, u64_at(debug_info, 16));
+        assert_eq!(440, u32_at(debug_info, 24));
+        assert!(debug_info[32..].starts_with(b"java/util/ArrayList.java\0"));
+
+        let code_load = &records[1].1;
+        assert_eq!(42, u32_at(code_load, 0));
+        assert_eq!(0x1000, u64_at(code_load, 8));
+        assert_eq!(2, u64_at(code_load, 24));
+        assert_eq!(0, u64_at(code_load, 32));
+        assert_eq!(&b"com.acme.Orders.count(Ljava/util/List;)I\0\x90\xc3"[..], &code_load[40..]);
+
+        assert!(records[2].1.is_empty());
+    }
+
+    #[test]
+    fn jitdumps_record_the_lines_of_compiled_methods_without_inlining() {
+        let mut dump = vec![];
+        {
+            let mut jitdump = JitDump::new(&mut dump, 42).unwrap();
+
+            jitdump.compiled_method(&compiled(&[]), &[]).unwrap();
+            jitdump.dynamic_code(&DynamicCodeGeneratedEvent { name: "Interpreter".to_string(), code_address: 0x2000, code_size: 0 }, &[]).unwrap();
+        }
+
+        let records = records(&dump);
+        assert_eq!(vec![ 2, 0, 0 ], records.iter().map(|record| record.0).collect::<Vec<u32>>());
+
+        let debug_info = &records[0].1;
+        assert_eq!(3, u64_at(debug_info, 8));
+        assert_eq!(0x1000, u64_at(debug_info, 16));
+        assert_eq!(10, u32_at(debug_info, 24));
+        assert!(debug_info[32..].starts_with(b"com/acme/Orders.java\0"));
+
+        // Code indices count the loaded code
+        assert_eq!(1, u64_at(&records[2].1, 32));
+        assert_eq!(&b"Interpreter\0"[..], &records[2].1[40..]);
+    }
+
+    #[test]
+    fn code_events_are_generated_with_the_capability() {
+        let mut emu = JVMEmulator::new();
+
+        match emu.generate_events(VMEvent::CompiledMethodLoad) {
+            Err(NativeError::MustPossessCapability) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match emu.generate_events(VMEvent::ClassLoad) {
+            Err(NativeError::IllegalArgument) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(emu.generate_events(VMEvent::DynamicCodeGenerated).is_ok());
+
+        emu.capabilities.can_generate_compiled_method_load_events = true;
+        assert!(emu.generate_events(VMEvent::CompiledMethodLoad).is_ok());
+        assert_eq!(vec![ VMEvent::DynamicCodeGenerated, VMEvent::CompiledMethodLoad ], *emu.generated_events.lock().unwrap());
+    }
+}
@@ -0,0 +1,115 @@
+extern crate jvmti;
+
+#[cfg(test)]
+mod tests {
+
+    use jvmti::emulator::JVMEmulator;
+    use jvmti::method::LineNumberEntry;
+    use jvmti::native::jvmti_native::{ jclass, jmethodID, jvmtiFrameInfo };
+    use jvmti::stack::*;
+    use std::ptr;
+
+    fn method(id: usize) -> jmethodID {
+        id as jmethodID
+    }
+
+    fn class(id: usize) -> jclass {
+        id as jclass
+    }
+
+    /// Orders.count (Orders.java:11) <- Orders.main (Orders.java) <- Thread.sleep (native) <- Runner.run (no source)
+    fn emulator() -> JVMEmulator {
+        let mut emu = JVMEmulator::new();
+
+        emu.class_signatures.insert(class(1), "Lcom/acme/Orders;".to_string());
+        emu.class_signatures.insert(class(2), "Ljava/lang/Thread;".to_string());
+        emu.class_signatures.insert(class(3), "LRunner;".to_string());
+        emu.source_files.insert(class(1), "Orders.java".to_string());
+        emu.source_files.insert(class(2), "Thread.java".to_string());
+
+        emu.methods.insert(method(10), (class(1), "count".to_string(), "(Ljava/util/List;)I".to_string()));
+        emu.methods.insert(method(11), (class(1), "main".to_string(), "([Ljava/lang/String;)V".to_string()));
+        emu.methods.insert(method(20), (class(2), "sleep".to_string(), "(J)V".to_string()));
+        emu.methods.insert(method(30), (class(3), "run".to_string(), "()V".to_string()));
+
+        // Tables aren't necessarily ordered by location
+        emu.line_number_tables.insert(method(10), vec![ LineNumberEntry { start_location: 4, line_number: 11 },
+                                                        LineNumberEntry { start_location: 0, line_number: 10 },
+                                                        LineNumberEntry { start_location: 9, line_number: 12 } ]);
+        emu.line_number_tables.insert(method(30), vec![ LineNumberEntry { start_location: 0, line_number: 3 } ]);
+
+        emu.frames = vec![ jvmtiFrameInfo { method: method(10), location: 7 },
+                           jvmtiFrameInfo { method: method(20), location: -1 },
+                           jvmtiFrameInfo { method: method(11), location: 2 },
+                           jvmtiFrameInfo { method: method(30), location: 5 } ];
+        emu
+    }
+
+    #[test]
+    fn frames_are_resolved_to_classes_methods_and_lines() {
+        let emu = emulator();
+        let trace = StackTrace::of(&emu, ptr::null_mut()).unwrap();
+
+        assert_eq!(4, trace.len());
+        assert!(!trace.truncated);
+
+        let top = trace.top().unwrap();
+        assert_eq!("com.acme.Orders", top.class_name);
+        assert_eq!("count", top.method_name);
+        assert_eq!("(Ljava/util/List;)I", top.signature);
+        assert_eq!(Some("Orders.java".to_string()), top.source_file);
+        assert_eq!(Some(11), top.line);
+
+        assert!(trace.frames[1].is_native());
+        assert_eq!(None, trace.frames[1].line);
+        assert_eq!(None, trace.frames[2].line);
+        assert_eq!(None, trace.frames[3].source_file);
+        assert_eq!(Some(3), trace.frames[3].line);
+
+        assert_eq!("\tat com.acme.Orders.count(Orders.java:11)\n\
+                    \tat java.lang.Thread.sleep(Native Method)\n\
+                    \tat com.acme.Orders.main(Orders.java)\n\
+                    \tat Runner.run(Unknown Source)\n",
+                   trace.to_string());
+    }
+
+    #[test]
+    fn depth_limits_the_captured_frames() {
+        let emu = emulator();
+
+        let trace = StackTrace::capture(&emu, ptr::null_mut(), 1, 2).unwrap();
+        assert_eq!(vec![ "sleep", "main" ], trace.frames.iter().map(|f| f.method_name.as_str()).collect::<Vec<&str>>());
+        assert!(trace.truncated);
+        assert!(trace.to_string().ends_with("\tat com.acme.Orders.main(Orders.java)\n\t...\n"));
+
+        // Negative start depths count from the bottom of the stack
+        let bottom = StackTrace::capture(&emu, ptr::null_mut(), -1, 5).unwrap();
+        assert_eq!(1, bottom.len());
+        assert_eq!("run", bottom.top().unwrap().method_name);
+        assert!(!bottom.truncated);
+
+        assert!(StackTrace::capture(&emu, ptr::null_mut(), 0, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unresolvable_methods_fail_the_capture() {
+        let mut emu = emulator();
+        emu.frames.push(jvmtiFrameInfo { method: method(99), location: 0 });
+
+        assert!(StackTrace::capture(&emu, ptr::null_mut(), 0, 3).is_ok());
+        assert!(StackTrace::of(&emu, ptr::null_mut()).is_err());
+    }
+
+    #[test]
+    fn locations_map_to_the_closest_preceding_line() {
+        let table = vec![ LineNumberEntry { start_location: 12, line_number: 40 },
+                          LineNumberEntry { start_location: 0, line_number: 38 },
+                          LineNumberEntry { start_location: 5, line_number: 39 } ];
+
+        assert_eq!(Some(38), line_number(&table, 0));
+        assert_eq!(Some(39), line_number(&table, 11));
+        assert_eq!(Some(40), line_number(&table, 120));
+        assert_eq!(None, line_number(&[], 3));
+        assert_eq!(None, line_number(&table[..1], 3));
+    }
+}
@@ -8,19 +8,22 @@ use jvmti::{
     bytecode::{printer::ClassfilePrinter, Constant},
     context::static_context,
     instrumentation::asm::transformer::Transformer,
+    instrumentation::{ClassInfo, MethodInfo},
     runtime::{ClassFileLoadEvent, MethodInvocationEvent, ObjectAllocationEvent},
     thread::Thread,
 };
 
 pub fn on_method_entry(event: MethodInvocationEvent) {
     let shall_record = match static_context().config.read() {
-        Ok(cfg) => (*cfg).entry_points.iter().any(|item| {
-            *item
-                == format!(
-                    "{}.{}.{}",
-                    event.class_sig.package, event.class_sig.name, event.method_sig.name
-                )
-        }), //event.class_name.as_str() == item),
+        Ok(cfg) => {
+            let class = ClassInfo::from_signature(&event.class_sig);
+            let method = MethodInfo::from_signature(&event.method_sig);
+
+            (*cfg)
+                .entry_points()
+                .map(|pointcuts| pointcuts.iter().any(|pointcut| pointcut.matches_method(&class, &method)))
+                .unwrap_or(false)
+        }
         _ => false,
     };
 
@@ -82,10 +85,14 @@ pub fn on_monitor_contended_entered(thread: Thread) {
 
 pub fn on_class_file_load(mut event: ClassFileLoadEvent) -> Option<Vec<u8>> {
     let shall_transform = match static_context().config.read() {
-        Ok(cfg) => (*cfg)
-            .entry_points
-            .iter()
-            .any(|item| item.starts_with(event.class_name.as_str())), //event.class_name.as_str() == item),
+        Ok(cfg) => {
+            let class = ClassInfo::new(&event.class_name);
+
+            (*cfg)
+                .entry_points()
+                .map(|pointcuts| pointcuts.iter().any(|pointcut| pointcut.matches_class(&class)))
+                .unwrap_or(false)
+        }
         _ => false,
     };
 
@@ -8,6 +8,8 @@ use jvmti::{
 };
 
 use jvmti::util::stringify;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
 
 mod agent;
 
@@ -17,6 +19,9 @@ use crate::agent::{
     on_monitor_waited, on_object_alloc, on_object_free, on_thread_end, on_thread_start,
 };
 
+/// The agent set up by `Agent_OnLoad`, kept to shut it down when it's unloaded
+static AGENT: AtomicPtr<Agent> = AtomicPtr::new(ptr::null_mut());
+
 ///
 /// `Agent_OnLoad` is the actual entry point of the agent code and it is called by the
 /// Java Virtual Machine directly.
@@ -32,6 +37,11 @@ pub extern "C" fn Agent_OnLoad(
     println!("Starting up as {}", options.agent_id);
 
     if let Some(config) = Config::read_config() {
+        if let Err(err) = config.entry_points() {
+            println!("Invalid configuration: {}", err);
+            return 1;
+        }
+
         println!("Setting configuration");
         static_context().set_config(config);
     }
@@ -54,6 +64,7 @@ pub extern "C" fn Agent_OnLoad(
     agent.on_class_file_load(Some(on_class_file_load));
 
     agent.update();
+    AGENT.store(Box::into_raw(Box::new(agent)), Ordering::SeqCst);
 
     return 0;
 }
@@ -65,4 +76,10 @@ pub extern "C" fn Agent_OnLoad(
 ///
 #[no_mangle]
 #[allow(non_snake_case, unused_variables)]
-pub extern "C" fn Agent_OnUnload(vm: JavaVMPtr) {}
+pub extern "C" fn Agent_OnUnload(vm: JavaVMPtr) {
+    let agent = AGENT.swap(ptr::null_mut(), Ordering::SeqCst);
+
+    if !agent.is_null() {
+        unsafe { Box::from_raw(agent) }.shutdown();
+    }
+}